@@ -0,0 +1,62 @@
+//! CPU instruction throughput
+//!
+//! Runs small loops on the M-cycle CPU with nothing else attached and
+//! reports instructions per second. Run with `cargo bench --bench cpu`.
+
+use gb3000::cpu::Cpu;
+use gb3000::memory::Memory;
+use gb3000::GbModel;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Instructions executed per program
+const INSTRUCTIONS: u32 = 20_000_000;
+
+/// Programs at 0x0100, each looping forever
+const PROGRAMS: [(&str, &[u8]); 3] = [
+    (
+        // INC A; ADD A, B; XOR C; LD D, A; SRL D; DEC B; JR NZ, -9; JR -11
+        "register ALU",
+        &[0x3C, 0x80, 0xA9, 0x57, 0xCB, 0x3A, 0x05, 0x20, 0xF7, 0x18, 0xF5],
+    ),
+    (
+        // LD HL, $C000; LD B, 0; loop: LD (HL+), A; LD A, (HL); INC A;
+        // ADD A, B; SWAP A; PUSH BC; POP BC; DEC B; JR NZ, loop; JP $0100
+        "memory and stack",
+        &[
+            0x21, 0x00, 0xC0, 0x06, 0x00, 0x22, 0x7E, 0x3C, 0x80, 0xCB, 0x37, 0xC5, 0xC1, 0x05, 0x20, 0xF5, 0xC3,
+            0x00, 0x01,
+        ],
+    ),
+    (
+        // LD SP, $DFFE; loop: CALL sub; JR loop; sub: LD A, (HL); ADD A, $11; RET
+        "calls and jumps",
+        &[0x31, 0xFE, 0xDF, 0xCD, 0x08, 0x01, 0x18, 0xFB, 0x7E, 0xC6, 0x11, 0xC9],
+    ),
+];
+
+fn main() {
+    for (name, program) in PROGRAMS {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        let mut memory = Memory::new();
+        memory.load_rom(&rom);
+        let mut cpu = Cpu::new();
+        cpu.reset_for_model(GbModel::DmgABC);
+
+        let start = Instant::now();
+        let mut cycles = 0u64;
+        for _ in 0..INSTRUCTIONS {
+            cycles += cpu.step_mcycle(&mut memory, |memory, cycles| {
+                black_box((memory, cycles));
+            }) as u64;
+        }
+        let secs = start.elapsed().as_secs_f64();
+        println!(
+            "{:<18} {:7.1} M instructions/s ({:.1}x real time)",
+            name,
+            INSTRUCTIONS as f64 / secs / 1e6,
+            cycles as f64 / 4_194_304.0 / secs
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! Picks the DAT file the `romdb` feature embeds: the one the
+//! `GB3000_ROMDB` environment variable names, relative to the crate root
+//! unless absolute, or else `data/romdb.dat`
+
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GB3000_ROMDB");
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set"));
+    let dat = match env::var_os("GB3000_ROMDB") {
+        Some(path) => root.join(path),
+        None => root.join("data/romdb.dat"),
+    };
+    if env::var_os("CARGO_FEATURE_ROMDB").is_some() && !dat.is_file() {
+        panic!("ROM database {} not found (check GB3000_ROMDB)", dat.display());
+    }
+    println!("cargo:rerun-if-changed={}", dat.display());
+    println!("cargo:rustc-env=GB3000_ROMDB_DAT={}", dat.display());
+}
@@ -0,0 +1,81 @@
+/*
+ * GB3000 C API
+ *
+ * Build the library with:
+ *   cargo build --release --lib --no-default-features --features capi
+ *
+ * and link against libgb3000.so / libgb3000.a (gb3000.dll / gb3000.lib on Windows).
+ * Keep this header in sync with src/ffi.rs.
+ */
+
+#ifndef GB3000_H
+#define GB3000_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque emulator handle */
+typedef struct Gb3000Emulator Gb3000Emulator;
+
+/* Button codes for gb3000_set_button */
+#define GB3000_BUTTON_RIGHT  0
+#define GB3000_BUTTON_LEFT   1
+#define GB3000_BUTTON_UP     2
+#define GB3000_BUTTON_DOWN   3
+#define GB3000_BUTTON_A      4
+#define GB3000_BUTTON_B      5
+#define GB3000_BUTTON_SELECT 6
+#define GB3000_BUTTON_START  7
+
+/* Create an emulator instance. Release it with gb3000_destroy. */
+Gb3000Emulator *gb3000_create(void);
+
+/* Destroy an emulator instance. NULL is ignored. */
+void gb3000_destroy(Gb3000Emulator *emu);
+
+/* Copy a ROM image into the emulator and reset it. Returns 1 on success. */
+int gb3000_load_rom(Gb3000Emulator *emu, const uint8_t *data, size_t len);
+
+/* Reset the emulator while keeping the ROM loaded. */
+void gb3000_reset(Gb3000Emulator *emu);
+
+/* Power cycle the emulator, keeping the ROM and battery-backed cartridge RAM. */
+void gb3000_soft_reset(Gb3000Emulator *emu);
+
+/* Run emulation for one frame. */
+void gb3000_run_frame(Gb3000Emulator *emu);
+
+/*
+ * Pointer to the framebuffer: width * height bytes, one 2-bit color index
+ * (0-3) per pixel, row major. Valid until the next call that runs emulation.
+ */
+const uint8_t *gb3000_framebuffer(const Gb3000Emulator *emu);
+
+/* Framebuffer dimensions (160x144). */
+int gb3000_screen_width(void);
+int gb3000_screen_height(void);
+
+/*
+ * Pull up to `capacity` stereo interleaved float samples into `out`.
+ * Returns the number of floats written; the rest stay queued.
+ */
+size_t gb3000_audio_pull(Gb3000Emulator *emu, float *out, size_t capacity);
+
+/* Audio sample rate in Hz. */
+uint32_t gb3000_audio_sample_rate(const Gb3000Emulator *emu);
+
+/* Press (pressed != 0) or release a button. */
+void gb3000_set_button(Gb3000Emulator *emu, int button, int pressed);
+
+/* 1 while an MBC5 rumble cart has its motor on, else 0. */
+int gb3000_rumble_active(const Gb3000Emulator *emu);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GB3000_H */
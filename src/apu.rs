@@ -1,28 +1,66 @@
-/// Audio Processing Unit (APU) for the Game Boy emulator.
-///
-/// The Game Boy has 4 sound channels:
-/// - Channel 1: Pulse with sweep
-/// - Channel 2: Pulse
-/// - Channel 3: Wave
-/// - Channel 4: Noise
-///
-/// This is a basic implementation that generates audio samples.
-
+//! Audio Processing Unit (APU) for the Game Boy emulator.
+//!
+//! The Game Boy has 4 sound channels:
+//! - Channel 1: Pulse with sweep
+//! - Channel 2: Pulse
+//! - Channel 3: Wave
+//! - Channel 4: Noise
+//!
+//! Register writes are flagged by the memory bus (like the timer's write
+//! flags) and applied at the start of each tick. Between writes the APU
+//! skips ahead from one event to the next: a channel timer expiring, a
+//! frame sequencer step, or an output sample.
+
+use crate::cpu::GbModel;
 use crate::memory::{io, Memory};
+use crate::options::AudioOptions;
+use crate::state::{StateError, StateReader, StateWriter};
 
 /// Audio sample rate
 pub const SAMPLE_RATE: u32 = 44100;
 
-/// CPU cycles per audio sample
-const CYCLES_PER_SAMPLE: u32 = 4194304 / SAMPLE_RATE;
+/// CPU clock rate in Hz
+const CPU_CLOCK: f64 = 4_194_304.0;
+
+/// Fractional bits in the fixed-point sample clock
+const SAMPLE_FRACTION_BITS: u32 = 16;
+
+/// Largest resampling adjustment accepted by [`Apu::set_rate_adjust`]
+pub const MAX_RATE_ADJUST: f64 = 0.05;
+
+/// Number of per-channel samples kept for visualization (~46 ms)
+pub const CHANNEL_HISTORY_LEN: usize = 2048;
 
 /// Frame sequencer step period (in CPU cycles)
 const FRAME_SEQUENCER_PERIOD: u32 = 8192;
 
-#[derive(Debug)]
+/// Extra delay before channel 3 fetches its first sample after a trigger
+/// (2 APU cycles at 2 MHz)
+const CH3_TRIGGER_DELAY: u16 = 4;
+
+/// Window after a wave fetch in which the DMG CPU can reach wave RAM
+const CH3_ACCESS_WINDOW: u32 = 2;
+
+/// Snapshot of a single sound channel, for visualizations
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelOutput {
+    /// Latest output level (0.0-1.0), before panning and master volume
+    pub amplitude: f32,
+    /// Current tone frequency in Hz (LFSR clock rate for the noise channel)
+    pub frequency: f32,
+    /// Whether the channel is playing (enabled with its DAC on)
+    pub active: bool,
+    /// Envelope volume (0-15); for the wave channel, its output level
+    /// as a volume (15, 7, 3 or 0 for 100%, 50%, 25% and muted)
+    pub volume: u8,
+}
+
+#[derive(Debug, Clone)]
 pub struct Apu {
-    /// Cycle counter for sample generation
+    /// Cycles since the last output sample (16.16 fixed point)
     sample_counter: u32,
+    /// Cycles per output sample (16.16 fixed point)
+    sample_period: u32,
     /// Cycle counter for frame sequencer
     frame_counter: u32,
     /// Frame sequencer step (0-7)
@@ -31,6 +69,22 @@ pub struct Apu {
     pub buffer: Vec<f32>,
     /// Audio enabled flag
     enabled: bool,
+    /// Whether output samples are generated into `buffer`
+    output_enabled: bool,
+    /// Whether channel state has been loaded from the sound registers
+    registers_synced: bool,
+    /// Channels left out of the mix, bit n for channel n; a host setting,
+    /// so it survives resets and isn't saved
+    muted: u8,
+    /// Latest output level of each channel
+    last_outputs: [f32; 4],
+    /// Ring buffer of recent per-channel levels, one entry per output sample
+    history: Box<[[f32; CHANNEL_HISTORY_LEN]; 4]>,
+    /// Next write position in `history`
+    history_pos: usize,
+    /// Mixing settings from the host; like `muted`, they survive resets
+    /// and aren't saved
+    output_options: AudioOptions,
     /// High-pass filter state for left/right channels (removes DC offset and reduces pops)
     hpf_left: f32,
     hpf_right: f32,
@@ -38,7 +92,7 @@ pub struct Apu {
     // Channel 1 (Pulse with sweep)
     ch1_enabled: bool,
     ch1_dac_enabled: bool,
-    ch1_length_counter: u8,
+    ch1_length_counter: u16,
     ch1_length_enabled: bool,
     ch1_frequency: u16,
     ch1_timer: u16,
@@ -48,17 +102,21 @@ pub struct Apu {
     ch1_envelope_timer: u8,
     ch1_envelope_period: u8,
     ch1_envelope_add: bool,
+    /// Last value written to NR12, for zombie mode
+    ch1_envelope_reg: u8,
     ch1_sweep_period: u8,
     ch1_sweep_shift: u8,
     ch1_sweep_negate: bool,
     ch1_sweep_timer: u8,
     ch1_sweep_enabled: bool,
     ch1_sweep_shadow: u16,
+    /// A sweep calculation in negate mode happened since the last trigger
+    ch1_sweep_negate_used: bool,
 
     // Channel 2 (Pulse)
     ch2_enabled: bool,
     ch2_dac_enabled: bool,
-    ch2_length_counter: u8,
+    ch2_length_counter: u16,
     ch2_length_enabled: bool,
     ch2_frequency: u16,
     ch2_timer: u16,
@@ -68,6 +126,8 @@ pub struct Apu {
     ch2_envelope_timer: u8,
     ch2_envelope_period: u8,
     ch2_envelope_add: bool,
+    /// Last value written to NR22, for zombie mode
+    ch2_envelope_reg: u8,
 
     // Channel 3 (Wave)
     ch3_enabled: bool,
@@ -79,22 +139,29 @@ pub struct Apu {
     ch3_position: u8,
     ch3_volume_code: u8,
     ch3_sample_buffer: u8,
+    /// Cycles since channel 3 last fetched from wave RAM
+    ch3_fetch_age: u32,
 
     // Channel 4 (Noise)
     ch4_enabled: bool,
     ch4_dac_enabled: bool,
-    ch4_length_counter: u8,
+    ch4_length_counter: u16,
     ch4_length_enabled: bool,
     ch4_volume: u8,
     ch4_volume_initial: u8,
     ch4_envelope_timer: u8,
     ch4_envelope_period: u8,
     ch4_envelope_add: bool,
+    /// Last value written to NR42, for zombie mode
+    ch4_envelope_reg: u8,
     ch4_timer: u32,
     ch4_lfsr: u16,
     ch4_width_mode: bool,
     ch4_clock_shift: u8,
     ch4_divisor_code: u8,
+
+    /// Hardware model, for DMG/CGB differences
+    model: GbModel,
 }
 
 /// Duty cycle patterns for pulse channels
@@ -109,10 +176,18 @@ impl Apu {
     pub fn new() -> Self {
         Self {
             sample_counter: 0,
+            sample_period: sample_period(1.0),
             frame_counter: 0,
             frame_step: 0,
             buffer: Vec::with_capacity(1024),
             enabled: false,
+            output_enabled: true,
+            registers_synced: false,
+            muted: 0,
+            last_outputs: [0.0; 4],
+            history: Box::new([[0.0; CHANNEL_HISTORY_LEN]; 4]),
+            history_pos: 0,
+            output_options: AudioOptions::default(),
             hpf_left: 0.0,
             hpf_right: 0.0,
 
@@ -128,12 +203,14 @@ impl Apu {
             ch1_envelope_timer: 0,
             ch1_envelope_period: 0,
             ch1_envelope_add: false,
+            ch1_envelope_reg: 0,
             ch1_sweep_period: 0,
             ch1_sweep_shift: 0,
             ch1_sweep_negate: false,
             ch1_sweep_timer: 0,
             ch1_sweep_enabled: false,
             ch1_sweep_shadow: 0,
+            ch1_sweep_negate_used: false,
 
             ch2_enabled: false,
             ch2_dac_enabled: false,
@@ -147,6 +224,7 @@ impl Apu {
             ch2_envelope_timer: 0,
             ch2_envelope_period: 0,
             ch2_envelope_add: false,
+            ch2_envelope_reg: 0,
 
             ch3_enabled: false,
             ch3_dac_enabled: false,
@@ -157,6 +235,7 @@ impl Apu {
             ch3_position: 0,
             ch3_volume_code: 0,
             ch3_sample_buffer: 0,
+            ch3_fetch_age: u32::MAX,
 
             ch4_enabled: false,
             ch4_dac_enabled: false,
@@ -167,223 +246,646 @@ impl Apu {
             ch4_envelope_timer: 0,
             ch4_envelope_period: 0,
             ch4_envelope_add: false,
+            ch4_envelope_reg: 0,
             ch4_timer: 0,
             ch4_lfsr: 0x7FFF,
             ch4_width_mode: false,
             ch4_clock_shift: 0,
             ch4_divisor_code: 0,
+
+            model: GbModel::DmgABC,
         }
     }
 
     pub fn reset(&mut self) {
+        let output_enabled = self.output_enabled;
+        let sample_period = self.sample_period;
+        let model = self.model;
+        let muted = self.muted;
+        let output_options = self.output_options;
         *self = Self::new();
+        self.muted = muted;
+        self.output_options = output_options;
+        self.output_enabled = output_enabled;
+        self.sample_period = sample_period;
+        self.model = model;
+    }
+
+    /// Set the hardware model, for DMG/CGB differences
+    pub fn set_model(&mut self, model: GbModel) {
+        self.model = model;
+    }
+
+    /// Change how the channels are mixed for output
+    pub fn set_output_options(&mut self, options: AudioOptions) {
+        self.output_options = options;
+    }
+
+    /// Resample output by `ratio` (1.0 = exactly [`SAMPLE_RATE`])
+    ///
+    /// Frontends nudge this slightly to keep their audio buffer at a steady
+    /// fill level. The ratio is clamped to 1 ± [`MAX_RATE_ADJUST`].
+    pub fn set_rate_adjust(&mut self, ratio: f64) {
+        let ratio = ratio.clamp(1.0 - MAX_RATE_ADJUST, 1.0 + MAX_RATE_ADJUST);
+        self.sample_period = sample_period(ratio);
+        // Keep the current sample phase valid for a shorter period
+        self.sample_counter = self.sample_counter.min(self.sample_period);
+    }
+
+    /// Enable or disable output sample generation
+    ///
+    /// Channels keep running so emulation is unaffected; only the mixing
+    /// and buffering of samples is skipped.
+    pub fn set_output_enabled(&mut self, enabled: bool) {
+        self.output_enabled = enabled;
+        if !enabled {
+            self.buffer.clear();
+        }
+    }
+
+    /// Leave `channel` (0-3) out of the mix, or put it back
+    ///
+    /// The channel keeps running and still shows up in
+    /// [`channel_outputs`](Self::channel_outputs) and the history.
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        let bit = 1 << (channel & 3);
+        if muted {
+            self.muted |= bit;
+        } else {
+            self.muted &= !bit;
+        }
+    }
+
+    /// Whether `channel` (0-3) is left out of the mix
+    pub fn channel_muted(&self, channel: usize) -> bool {
+        self.muted & (1 << (channel & 3)) != 0
+    }
+
+    /// Latest amplitude and frequency of each channel (1-4)
+    pub fn channel_outputs(&self) -> [ChannelOutput; 4] {
+        let pulse = |freq: u16| 131072.0 / (2048 - (freq & 0x7FF) as u32) as f32;
+        let noise_divisor = match self.ch4_divisor_code {
+            0 => 0.5,
+            d => d as f32,
+        };
+        let frequencies = [
+            pulse(self.ch1_frequency),
+            pulse(self.ch2_frequency),
+            65536.0 / (2048 - (self.ch3_frequency & 0x7FF) as u32) as f32,
+            262144.0 / (noise_divisor * (1u32 << (self.ch4_clock_shift & 0x0F)) as f32),
+        ];
+        let active = [
+            self.ch1_enabled && self.ch1_dac_enabled,
+            self.ch2_enabled && self.ch2_dac_enabled,
+            self.ch3_enabled && self.ch3_dac_enabled,
+            self.ch4_enabled && self.ch4_dac_enabled,
+        ];
+
+        let volumes = [self.ch1_volume, self.ch2_volume, 15 >> ch3_shift(self.ch3_volume_code), self.ch4_volume];
+
+        std::array::from_fn(|i| ChannelOutput {
+            amplitude: self.last_outputs[i],
+            frequency: if active[i] { frequencies[i] } else { 0.0 },
+            active: active[i],
+            volume: volumes[i],
+        })
+    }
+
+    /// Copy the most recent levels of `channel` (0-3) into `out`, oldest first
+    ///
+    /// At most [`CHANNEL_HISTORY_LEN`] samples are available; they are
+    /// recorded at the output sample rate while output is enabled. Any
+    /// other `channel` leaves `out` untouched.
+    pub fn channel_history(&self, channel: usize, out: &mut [f32]) {
+        let Some(history) = self.history.get(channel) else {
+            return;
+        };
+        let len = out.len().min(CHANNEL_HISTORY_LEN);
+        let start = (self.history_pos + CHANNEL_HISTORY_LEN - len) % CHANNEL_HISTORY_LEN;
+        for (i, dst) in out[..len].iter_mut().enumerate() {
+            *dst = history[(start + i) % CHANNEL_HISTORY_LEN];
+        }
+    }
+
+    /// Serialize frame sequencer, filter, and channel state
+    ///
+    /// Pending output samples are not saved.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.sample_counter);
+        w.u32(self.frame_counter);
+        w.u8(self.frame_step);
+        w.bool(self.enabled);
+        w.bool(self.registers_synced);
+        w.f32(self.hpf_left);
+        w.f32(self.hpf_right);
+        w.bool(self.ch1_enabled);
+        w.bool(self.ch1_dac_enabled);
+        w.u16(self.ch1_length_counter);
+        w.bool(self.ch1_length_enabled);
+        w.u16(self.ch1_frequency);
+        w.u16(self.ch1_timer);
+        w.u8(self.ch1_duty_position);
+        w.u8(self.ch1_volume);
+        w.u8(self.ch1_volume_initial);
+        w.u8(self.ch1_envelope_timer);
+        w.u8(self.ch1_envelope_period);
+        w.bool(self.ch1_envelope_add);
+        w.u8(self.ch1_envelope_reg);
+        w.u8(self.ch1_sweep_period);
+        w.u8(self.ch1_sweep_shift);
+        w.bool(self.ch1_sweep_negate);
+        w.u8(self.ch1_sweep_timer);
+        w.bool(self.ch1_sweep_enabled);
+        w.u16(self.ch1_sweep_shadow);
+        w.bool(self.ch1_sweep_negate_used);
+        w.bool(self.ch2_enabled);
+        w.bool(self.ch2_dac_enabled);
+        w.u16(self.ch2_length_counter);
+        w.bool(self.ch2_length_enabled);
+        w.u16(self.ch2_frequency);
+        w.u16(self.ch2_timer);
+        w.u8(self.ch2_duty_position);
+        w.u8(self.ch2_volume);
+        w.u8(self.ch2_volume_initial);
+        w.u8(self.ch2_envelope_timer);
+        w.u8(self.ch2_envelope_period);
+        w.bool(self.ch2_envelope_add);
+        w.u8(self.ch2_envelope_reg);
+        w.bool(self.ch3_enabled);
+        w.bool(self.ch3_dac_enabled);
+        w.u16(self.ch3_length_counter);
+        w.bool(self.ch3_length_enabled);
+        w.u16(self.ch3_frequency);
+        w.u16(self.ch3_timer);
+        w.u8(self.ch3_position);
+        w.u8(self.ch3_volume_code);
+        w.u8(self.ch3_sample_buffer);
+        w.u32(self.ch3_fetch_age);
+        w.bool(self.ch4_enabled);
+        w.bool(self.ch4_dac_enabled);
+        w.u16(self.ch4_length_counter);
+        w.bool(self.ch4_length_enabled);
+        w.u8(self.ch4_volume);
+        w.u8(self.ch4_volume_initial);
+        w.u8(self.ch4_envelope_timer);
+        w.u8(self.ch4_envelope_period);
+        w.bool(self.ch4_envelope_add);
+        w.u8(self.ch4_envelope_reg);
+        w.u32(self.ch4_timer);
+        w.u16(self.ch4_lfsr);
+        w.bool(self.ch4_width_mode);
+        w.u8(self.ch4_clock_shift);
+        w.u8(self.ch4_divisor_code);
+    }
+
+    /// Restore frame sequencer, filter, and channel state
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.sample_counter = r.u32()?.min(self.sample_period);
+        self.frame_counter = r.u32()?;
+        self.frame_step = r.u8()?;
+        self.enabled = r.bool()?;
+        self.registers_synced = r.bool()?;
+        self.hpf_left = r.f32()?;
+        self.hpf_right = r.f32()?;
+        self.ch1_enabled = r.bool()?;
+        self.ch1_dac_enabled = r.bool()?;
+        self.ch1_length_counter = r.u16()?;
+        self.ch1_length_enabled = r.bool()?;
+        self.ch1_frequency = r.u16()?;
+        self.ch1_timer = r.u16()?;
+        self.ch1_duty_position = r.u8()?;
+        self.ch1_volume = r.u8()?;
+        self.ch1_volume_initial = r.u8()?;
+        self.ch1_envelope_timer = r.u8()?;
+        self.ch1_envelope_period = r.u8()?;
+        self.ch1_envelope_add = r.bool()?;
+        self.ch1_envelope_reg = r.u8()?;
+        self.ch1_sweep_period = r.u8()?;
+        self.ch1_sweep_shift = r.u8()?;
+        self.ch1_sweep_negate = r.bool()?;
+        self.ch1_sweep_timer = r.u8()?;
+        self.ch1_sweep_enabled = r.bool()?;
+        self.ch1_sweep_shadow = r.u16()?;
+        self.ch1_sweep_negate_used = r.bool()?;
+        self.ch2_enabled = r.bool()?;
+        self.ch2_dac_enabled = r.bool()?;
+        self.ch2_length_counter = r.u16()?;
+        self.ch2_length_enabled = r.bool()?;
+        self.ch2_frequency = r.u16()?;
+        self.ch2_timer = r.u16()?;
+        self.ch2_duty_position = r.u8()?;
+        self.ch2_volume = r.u8()?;
+        self.ch2_volume_initial = r.u8()?;
+        self.ch2_envelope_timer = r.u8()?;
+        self.ch2_envelope_period = r.u8()?;
+        self.ch2_envelope_add = r.bool()?;
+        self.ch2_envelope_reg = r.u8()?;
+        self.ch3_enabled = r.bool()?;
+        self.ch3_dac_enabled = r.bool()?;
+        self.ch3_length_counter = r.u16()?;
+        self.ch3_length_enabled = r.bool()?;
+        self.ch3_frequency = r.u16()?;
+        self.ch3_timer = r.u16()?;
+        self.ch3_position = r.u8()?;
+        self.ch3_volume_code = r.u8()?;
+        self.ch3_sample_buffer = r.u8()?;
+        self.ch3_fetch_age = r.u32()?;
+        self.ch4_enabled = r.bool()?;
+        self.ch4_dac_enabled = r.bool()?;
+        self.ch4_length_counter = r.u16()?;
+        self.ch4_length_enabled = r.bool()?;
+        self.ch4_volume = r.u8()?;
+        self.ch4_volume_initial = r.u8()?;
+        self.ch4_envelope_timer = r.u8()?;
+        self.ch4_envelope_period = r.u8()?;
+        self.ch4_envelope_add = r.bool()?;
+        self.ch4_envelope_reg = r.u8()?;
+        self.ch4_timer = r.u32()?;
+        self.ch4_lfsr = r.u16()?;
+        self.ch4_width_mode = r.bool()?;
+        self.ch4_clock_shift = r.u8()?;
+        self.ch4_divisor_code = r.u8()?;
+        Ok(())
     }
 
     /// Tick the APU by the given number of T-cycles
+    ///
+    /// Register writes made since the last tick are applied first. The APU
+    /// then jumps from event to event (channel timer expiry, frame sequencer
+    /// step, output sample) instead of stepping every cycle.
     pub fn tick(&mut self, memory: &mut Memory, cycles: u32) {
-        // Update enabled state from NR52
-        self.enabled = memory.data[io::NR52 as usize] & 0x80 != 0;
+        self.process_writes(memory);
 
-        if !self.enabled {
-            return;
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let step = remaining.min(self.cycles_until_event());
+            self.advance(memory, step);
+            remaining -= step;
+        }
+
+        self.update_status(memory);
+    }
+
+    /// Mirror channel state the memory bus needs: the enable flags in the
+    /// low bits of NR52 and which wave RAM byte channel 3 is playing
+    fn update_status(&self, memory: &mut Memory) {
+        let status = self.ch1_enabled as u8
+            | (self.ch2_enabled as u8) << 1
+            | (self.ch3_enabled as u8) << 2
+            | (self.ch4_enabled as u8) << 3;
+        let nr52 = &mut memory.io[io::NR52 as usize];
+        *nr52 = (*nr52 & 0xF0) | status;
+
+        memory.wave_playing_byte = self.ch3_enabled.then_some(self.ch3_position / 2);
+        memory.wave_fetch_now = self.ch3_fetch_age < CH3_ACCESS_WINDOW;
+
+        if self.model.is_cgb() {
+            let [ch1, ch2, ch3, ch4] = self.digital_outputs(memory);
+            memory.io[io::PCM12 as usize] = ch1 | ch2 << 4;
+            memory.io[io::PCM34 as usize] = ch3 | ch4 << 4;
+        }
+    }
+
+    /// Power the APU off: clear the sound registers and silence all channels
+    fn power_off(&mut self, memory: &mut Memory) {
+        for addr in io::NR10..io::NR52 {
+            memory.io[addr as usize] = 0;
         }
+        self.ch1_enabled = false;
+        self.ch2_enabled = false;
+        self.ch3_enabled = false;
+        self.ch4_enabled = false;
+        self.ch1_duty_position = 0;
+        self.ch2_duty_position = 0;
+        self.ch3_sample_buffer = 0;
+        // Length counters survive power-off on the DMG, not the CGB
+        let keep_lengths = !self.model.is_cgb();
+        for addr in io::NR10..io::NR52 {
+            if !(keep_lengths && matches!(addr, io::NR11 | io::NR21 | io::NR31 | io::NR41)) {
+                self.write_register(memory, addr);
+            }
+        }
+    }
 
-        // Read channel parameters from memory (and handle triggers)
-        self.read_channel_registers(memory);
+    /// Cycles until the next channel, frame sequencer, or sample event
+    fn cycles_until_event(&self) -> u32 {
+        let sample = self
+            .sample_period
+            .saturating_sub(self.sample_counter)
+            .div_ceil(1 << SAMPLE_FRACTION_BITS)
+            .max(1);
+        if !self.enabled {
+            // Powered off: only the output sample clock runs
+            return sample;
+        }
+        let frame = FRAME_SEQUENCER_PERIOD.saturating_sub(self.frame_counter).max(1);
+        // A channel timer of 0 expires on the very next cycle
+        (self.ch1_timer.max(1) as u32)
+            .min(self.ch2_timer.max(1) as u32)
+            .min(self.ch3_timer.max(1) as u32)
+            .min(self.ch4_timer.max(1))
+            .min(frame)
+            .min(sample)
+    }
 
-        for _ in 0..cycles {
-            // Tick channels
-            self.tick_channel1();
-            self.tick_channel2();
-            self.tick_channel3(memory);
-            self.tick_channel4();
+    /// Advance all counters by `step` cycles, which must not pass an event
+    fn advance(&mut self, memory: &Memory, step: u32) {
+        if self.enabled {
+            if advance_timer(&mut self.ch1_timer, step) {
+                self.clock_channel1();
+            }
+            if advance_timer(&mut self.ch2_timer, step) {
+                self.clock_channel2();
+            }
+            if advance_timer(&mut self.ch3_timer, step) {
+                self.clock_channel3(memory);
+                self.ch3_fetch_age = 0;
+            } else {
+                self.ch3_fetch_age = self.ch3_fetch_age.saturating_add(step);
+            }
+            let ch4_left = self.ch4_timer.max(1);
+            if step >= ch4_left {
+                self.clock_channel4();
+            } else {
+                self.ch4_timer = ch4_left - step;
+            }
 
-            // Frame sequencer
-            self.frame_counter += 1;
+            self.frame_counter += step;
             if self.frame_counter >= FRAME_SEQUENCER_PERIOD {
                 self.frame_counter = 0;
                 self.tick_frame_sequencer();
             }
+        }
 
-            // Generate sample
-            self.sample_counter += 1;
-            if self.sample_counter >= CYCLES_PER_SAMPLE {
-                self.sample_counter = 0;
+        self.sample_counter += step << SAMPLE_FRACTION_BITS;
+        if self.sample_counter >= self.sample_period {
+            self.sample_counter -= self.sample_period;
+            if self.output_enabled {
                 self.generate_sample_output(memory);
             }
         }
     }
 
-    fn read_channel_registers(&mut self, memory: &mut Memory) {
-        // Channel 1
-        let nr10 = memory.data[io::NR10 as usize];
-        self.ch1_sweep_period = (nr10 >> 4) & 0x07;
-        self.ch1_sweep_negate = nr10 & 0x08 != 0;
-        self.ch1_sweep_shift = nr10 & 0x07;
-
-        let nr11 = memory.data[io::NR11 as usize];
-        let nr12 = memory.data[io::NR12 as usize];
-        self.ch1_dac_enabled = nr12 & 0xF8 != 0;
-
-        let nr13 = memory.data[io::NR13 as usize];
-        let nr14 = memory.data[io::NR14 as usize];
-        self.ch1_frequency = (nr13 as u16) | (((nr14 & 0x07) as u16) << 8);
-        self.ch1_length_enabled = nr14 & 0x40 != 0;
-        
-        // Check for channel 1 trigger
-        if nr14 & 0x80 != 0 {
-            memory.data[io::NR14 as usize] &= 0x7F; // Clear trigger bit
-            if self.ch1_dac_enabled {
-                self.ch1_enabled = true;
-                self.ch1_length_counter = 64 - (nr11 & 0x3F);
-                self.ch1_timer = (2048 - self.ch1_frequency) * 4;
-                self.ch1_volume = nr12 >> 4;
-                self.ch1_envelope_timer = nr12 & 0x07;
-                self.ch1_envelope_period = nr12 & 0x07;
-                self.ch1_envelope_add = nr12 & 0x08 != 0;
-                self.ch1_sweep_shadow = self.ch1_frequency;
-                self.ch1_sweep_timer = if self.ch1_sweep_period > 0 { self.ch1_sweep_period } else { 8 };
-                self.ch1_sweep_enabled = self.ch1_sweep_period > 0 || self.ch1_sweep_shift > 0;
-            }
+    /// Apply sound register writes recorded by the memory bus
+    fn process_writes(&mut self, memory: &mut Memory) {
+        let mut written = std::mem::take(&mut memory.apu_written);
+        if !self.registers_synced {
+            // After power-on or reset, pick up whatever the registers hold
+            // without treating NR52 as a power transition
+            self.registers_synced = true;
+            self.enabled = memory.io[io::NR52 as usize] & 0x80 != 0;
+            written = u32::MAX;
         }
 
-        // Channel 2
-        let nr21 = memory.data[io::NR21 as usize];
-        let nr22 = memory.data[io::NR22 as usize];
-        self.ch2_dac_enabled = nr22 & 0xF8 != 0;
-
-        let nr23 = memory.data[io::NR23 as usize];
-        let nr24 = memory.data[io::NR24 as usize];
-        self.ch2_frequency = (nr23 as u16) | (((nr24 & 0x07) as u16) << 8);
-        self.ch2_length_enabled = nr24 & 0x40 != 0;
-        
-        // Check for channel 2 trigger
-        if nr24 & 0x80 != 0 {
-            memory.data[io::NR24 as usize] &= 0x7F; // Clear trigger bit
-            if self.ch2_dac_enabled {
-                self.ch2_enabled = true;
-                self.ch2_length_counter = 64 - (nr21 & 0x3F);
-                self.ch2_timer = (2048 - self.ch2_frequency) * 4;
-                self.ch2_volume = nr22 >> 4;
-                self.ch2_envelope_timer = nr22 & 0x07;
-                self.ch2_envelope_period = nr22 & 0x07;
-                self.ch2_envelope_add = nr22 & 0x08 != 0;
-            }
+        while written != 0 {
+            let reg = written.trailing_zeros() as u16;
+            written &= written - 1;
+            self.write_register(memory, 0xFF10 + reg);
         }
+    }
 
-        // Channel 3
-        let nr30 = memory.data[io::NR30 as usize];
-        self.ch3_dac_enabled = nr30 & 0x80 != 0;
+    /// Update channel state after a write to a sound register
+    fn write_register(&mut self, memory: &mut Memory, addr: u16) {
+        let value = memory.io[addr as usize];
+        // Length is clocked on even steps; if the next step is odd, enabling
+        // length now clocks it once immediately
+        let extra_length_clock = self.frame_step.is_multiple_of(2);
+        match addr {
+            // Channel 1
+            io::NR10 => {
+                self.ch1_sweep_period = (value >> 4) & 0x07;
+                let negate = value & 0x08 != 0;
+                if self.ch1_sweep_negate && !negate && self.ch1_sweep_negate_used {
+                    // Leaving negate mode after using it disables the channel
+                    self.ch1_enabled = false;
+                }
+                self.ch1_sweep_negate = negate;
+                self.ch1_sweep_shift = value & 0x07;
+            }
+            io::NR12 => {
+                if self.ch1_enabled {
+                    self.ch1_volume = zombie_volume(self.ch1_volume, self.ch1_envelope_reg, value);
+                }
+                self.ch1_envelope_reg = value;
+                self.ch1_dac_enabled = value & 0xF8 != 0;
+                self.ch1_enabled &= self.ch1_dac_enabled;
+            }
+            io::NR11 => self.ch1_length_counter = 64 - (value & 0x3F) as u16,
+            io::NR13 => self.ch1_frequency = (self.ch1_frequency & 0x700) | value as u16,
+            io::NR14 => {
+                self.ch1_frequency = (self.ch1_frequency & 0xFF) | (((value & 0x07) as u16) << 8);
+                write_length_control(
+                    value,
+                    extra_length_clock,
+                    64,
+                    &mut self.ch1_length_enabled,
+                    &mut self.ch1_length_counter,
+                    &mut self.ch1_enabled,
+                );
+                if value & 0x80 != 0 {
+                    memory.io[addr as usize] &= 0x7F; // Clear trigger bit
+                    if self.ch1_dac_enabled {
+                        let nr12 = memory.io[io::NR12 as usize];
+                        self.ch1_enabled = true;
+                        self.ch1_timer = (2048 - self.ch1_frequency) * 4;
+                        self.ch1_volume = nr12 >> 4;
+                        self.ch1_envelope_timer = nr12 & 0x07;
+                        self.ch1_envelope_period = nr12 & 0x07;
+                        self.ch1_envelope_add = nr12 & 0x08 != 0;
+                        self.ch1_sweep_shadow = self.ch1_frequency;
+                        self.ch1_sweep_timer = if self.ch1_sweep_period > 0 { self.ch1_sweep_period } else { 8 };
+                        self.ch1_sweep_enabled = self.ch1_sweep_period > 0 || self.ch1_sweep_shift > 0;
+                        self.ch1_sweep_negate_used = false;
+                        if self.ch1_sweep_shift > 0 {
+                            // Overflow check against the new frequency
+                            self.calculate_sweep_frequency();
+                        }
+                    }
+                }
+            }
 
-        let nr31 = memory.data[io::NR31 as usize];
-        let nr32 = memory.data[io::NR32 as usize];
-        self.ch3_volume_code = (nr32 >> 5) & 0x03;
+            // Channel 2
+            io::NR21 => self.ch2_length_counter = 64 - (value & 0x3F) as u16,
+            io::NR22 => {
+                if self.ch2_enabled {
+                    self.ch2_volume = zombie_volume(self.ch2_volume, self.ch2_envelope_reg, value);
+                }
+                self.ch2_envelope_reg = value;
+                self.ch2_dac_enabled = value & 0xF8 != 0;
+                self.ch2_enabled &= self.ch2_dac_enabled;
+            }
+            io::NR23 => self.ch2_frequency = (self.ch2_frequency & 0x700) | value as u16,
+            io::NR24 => {
+                self.ch2_frequency = (self.ch2_frequency & 0xFF) | (((value & 0x07) as u16) << 8);
+                write_length_control(
+                    value,
+                    extra_length_clock,
+                    64,
+                    &mut self.ch2_length_enabled,
+                    &mut self.ch2_length_counter,
+                    &mut self.ch2_enabled,
+                );
+                if value & 0x80 != 0 {
+                    memory.io[addr as usize] &= 0x7F; // Clear trigger bit
+                    if self.ch2_dac_enabled {
+                        let nr22 = memory.io[io::NR22 as usize];
+                        self.ch2_enabled = true;
+                        self.ch2_timer = (2048 - self.ch2_frequency) * 4;
+                        self.ch2_volume = nr22 >> 4;
+                        self.ch2_envelope_timer = nr22 & 0x07;
+                        self.ch2_envelope_period = nr22 & 0x07;
+                        self.ch2_envelope_add = nr22 & 0x08 != 0;
+                    }
+                }
+            }
 
-        let nr33 = memory.data[io::NR33 as usize];
-        let nr34 = memory.data[io::NR34 as usize];
-        self.ch3_frequency = (nr33 as u16) | (((nr34 & 0x07) as u16) << 8);
-        self.ch3_length_enabled = nr34 & 0x40 != 0;
-        
-        // Check for channel 3 trigger
-        if nr34 & 0x80 != 0 {
-            memory.data[io::NR34 as usize] &= 0x7F; // Clear trigger bit
-            if self.ch3_dac_enabled {
-                self.ch3_enabled = true;
-                self.ch3_length_counter = 256 - (nr31 as u16);
-                self.ch3_timer = (2048 - self.ch3_frequency) * 2;
-                self.ch3_position = 0;
+            // Channel 3
+            io::NR30 => {
+                self.ch3_dac_enabled = value & 0x80 != 0;
+                self.ch3_enabled &= self.ch3_dac_enabled;
+            }
+            io::NR31 => self.ch3_length_counter = 256 - value as u16,
+            io::NR32 => self.ch3_volume_code = (value >> 5) & 0x03,
+            io::NR33 => self.ch3_frequency = (self.ch3_frequency & 0x700) | value as u16,
+            io::NR34 => {
+                self.ch3_frequency = (self.ch3_frequency & 0xFF) | (((value & 0x07) as u16) << 8);
+                write_length_control(
+                    value,
+                    extra_length_clock,
+                    256,
+                    &mut self.ch3_length_enabled,
+                    &mut self.ch3_length_counter,
+                    &mut self.ch3_enabled,
+                );
+                if value & 0x80 != 0 {
+                    memory.io[addr as usize] &= 0x7F; // Clear trigger bit
+                    if self.ch3_enabled && self.ch3_timer <= 2 && !self.model.is_cgb() {
+                        self.corrupt_wave_ram(memory);
+                    }
+                    if self.ch3_dac_enabled {
+                        self.ch3_enabled = true;
+                        self.ch3_timer = (2048 - self.ch3_frequency) * 2 + CH3_TRIGGER_DELAY;
+                        self.ch3_position = 0;
+                    }
+                }
             }
-        }
 
-        // Channel 4
-        let nr41 = memory.data[io::NR41 as usize];
-        let nr42 = memory.data[io::NR42 as usize];
-        self.ch4_dac_enabled = nr42 & 0xF8 != 0;
+            // Channel 4
+            io::NR41 => self.ch4_length_counter = 64 - (value & 0x3F) as u16,
+            io::NR42 => {
+                if self.ch4_enabled {
+                    self.ch4_volume = zombie_volume(self.ch4_volume, self.ch4_envelope_reg, value);
+                }
+                self.ch4_envelope_reg = value;
+                self.ch4_dac_enabled = value & 0xF8 != 0;
+                self.ch4_enabled &= self.ch4_dac_enabled;
+            }
+            io::NR43 => {
+                self.ch4_clock_shift = value >> 4;
+                self.ch4_width_mode = value & 0x08 != 0;
+                self.ch4_divisor_code = value & 0x07;
+            }
+            io::NR44 => {
+                write_length_control(
+                    value,
+                    extra_length_clock,
+                    64,
+                    &mut self.ch4_length_enabled,
+                    &mut self.ch4_length_counter,
+                    &mut self.ch4_enabled,
+                );
+                if value & 0x80 != 0 {
+                    memory.io[addr as usize] &= 0x7F; // Clear trigger bit
+                    if self.ch4_dac_enabled {
+                        let nr42 = memory.io[io::NR42 as usize];
+                        self.ch4_enabled = true;
+                        self.ch4_lfsr = 0x7FFF;
+                        self.ch4_volume = nr42 >> 4;
+                        self.ch4_envelope_timer = nr42 & 0x07;
+                        self.ch4_envelope_period = nr42 & 0x07;
+                        self.ch4_envelope_add = nr42 & 0x08 != 0;
+                        self.ch4_timer = self.ch4_period();
+                    }
+                }
+            }
 
-        let nr43 = memory.data[io::NR43 as usize];
-        self.ch4_clock_shift = nr43 >> 4;
-        self.ch4_width_mode = nr43 & 0x08 != 0;
-        
-        let nr44 = memory.data[io::NR44 as usize];
-        self.ch4_length_enabled = nr44 & 0x40 != 0;
-        
-        // Check for channel 4 trigger
-        if nr44 & 0x80 != 0 {
-            memory.data[io::NR44 as usize] &= 0x7F; // Clear trigger bit
-            if self.ch4_dac_enabled {
-                self.ch4_enabled = true;
-                self.ch4_length_counter = 64 - (nr41 & 0x3F);
-                self.ch4_lfsr = 0x7FFF;
-                self.ch4_volume = nr42 >> 4;
-                self.ch4_envelope_timer = nr42 & 0x07;
-                self.ch4_envelope_period = nr42 & 0x07;
-                self.ch4_envelope_add = nr42 & 0x08 != 0;
-                let divisor: u32 = if nr43 & 0x07 == 0 { 8 } else { (nr43 & 0x07) as u32 * 16 };
-                self.ch4_timer = divisor << self.ch4_clock_shift;
+            // Control
+            io::NR52 => {
+                let enabled = value & 0x80 != 0;
+                if self.enabled && !enabled {
+                    self.power_off(memory);
+                } else if !self.enabled && enabled {
+                    // The frame sequencer restarts so its next step is step 0
+                    self.frame_counter = 0;
+                    self.frame_step = 7;
+                }
+                self.enabled = enabled;
             }
+
+            _ => {}
         }
-        self.ch4_divisor_code = nr43 & 0x07;
     }
 
-    fn tick_channel1(&mut self) {
-        if self.ch1_timer > 0 {
-            self.ch1_timer -= 1;
-        }
-        if self.ch1_timer == 0 {
-            self.ch1_timer = (2048 - self.ch1_frequency) * 4;
-            self.ch1_duty_position = (self.ch1_duty_position + 1) % 8;
-        }
+    /// Noise channel timer period in T-cycles
+    fn ch4_period(&self) -> u32 {
+        let divisor = if self.ch4_divisor_code == 0 {
+            8
+        } else {
+            (self.ch4_divisor_code as u32) * 16
+        };
+        divisor << self.ch4_clock_shift
     }
 
-    fn tick_channel2(&mut self) {
-        if self.ch2_timer > 0 {
-            self.ch2_timer -= 1;
-        }
-        if self.ch2_timer == 0 {
-            self.ch2_timer = (2048 - self.ch2_frequency) * 4;
-            self.ch2_duty_position = (self.ch2_duty_position + 1) % 8;
-        }
+    /// Channel 1 timer expired: advance the duty step
+    fn clock_channel1(&mut self) {
+        self.ch1_timer = (2048 - self.ch1_frequency) * 4;
+        self.ch1_duty_position = (self.ch1_duty_position + 1) % 8;
     }
 
-    fn tick_channel3(&mut self, memory: &Memory) {
-        if self.ch3_timer > 0 {
-            self.ch3_timer -= 1;
-        }
-        if self.ch3_timer == 0 {
-            self.ch3_timer = (2048 - self.ch3_frequency) * 2;
-            self.ch3_position = (self.ch3_position + 1) % 32;
+    /// Channel 2 timer expired: advance the duty step
+    fn clock_channel2(&mut self) {
+        self.ch2_timer = (2048 - self.ch2_frequency) * 4;
+        self.ch2_duty_position = (self.ch2_duty_position + 1) % 8;
+    }
 
-            // Read sample from wave RAM
-            let addr = 0xFF30 + (self.ch3_position / 2) as u16;
-            let byte = memory.data[addr as usize];
-            self.ch3_sample_buffer = if self.ch3_position % 2 == 0 {
-                byte >> 4
-            } else {
-                byte & 0x0F
-            };
-        }
+    /// Channel 3 timer expired: fetch the next wave sample
+    fn clock_channel3(&mut self, memory: &Memory) {
+        self.ch3_timer = (2048 - self.ch3_frequency) * 2;
+        self.ch3_position = (self.ch3_position + 1) % 32;
+
+        // Read sample from wave RAM
+        let addr = 0xFF30 + (self.ch3_position / 2) as u16;
+        let byte = memory.io[addr as usize];
+        self.ch3_sample_buffer = if self.ch3_position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
     }
 
-    fn tick_channel4(&mut self) {
-        if self.ch4_timer > 0 {
-            self.ch4_timer -= 1;
+    /// DMG quirk: retriggering channel 3 just as it fetches a sample
+    /// overwrites the start of wave RAM with the bytes being read
+    fn corrupt_wave_ram(&self, memory: &mut Memory) {
+        let index = ((self.ch3_position + 1) % 32 / 2) as usize;
+        let wave = &mut memory.io[0xFF30..0xFF40];
+        if index < 4 {
+            wave[0] = wave[index];
+        } else {
+            let block = index & !3;
+            wave.copy_within(block..block + 4, 0);
         }
-        if self.ch4_timer == 0 {
-            let divisor = if self.ch4_divisor_code == 0 {
-                8
-            } else {
-                (self.ch4_divisor_code as u32) * 16
-            };
-            self.ch4_timer = divisor << self.ch4_clock_shift;
+    }
 
-            // LFSR tick
-            let xor_result = (self.ch4_lfsr & 0x01) ^ ((self.ch4_lfsr >> 1) & 0x01);
-            self.ch4_lfsr = (self.ch4_lfsr >> 1) | (xor_result << 14);
+    /// Channel 4 timer expired: clock the LFSR
+    fn clock_channel4(&mut self) {
+        self.ch4_timer = self.ch4_period();
 
-            if self.ch4_width_mode {
-                self.ch4_lfsr &= !(1 << 6);
-                self.ch4_lfsr |= xor_result << 6;
-            }
+        let xor_result = (self.ch4_lfsr & 0x01) ^ ((self.ch4_lfsr >> 1) & 0x01);
+        self.ch4_lfsr = (self.ch4_lfsr >> 1) | (xor_result << 14);
+
+        if self.ch4_width_mode {
+            self.ch4_lfsr &= !(1 << 6);
+            self.ch4_lfsr |= xor_result << 6;
         }
     }
 
@@ -391,7 +893,7 @@ impl Apu {
         self.frame_step = (self.frame_step + 1) % 8;
 
         // Length counter (steps 0, 2, 4, 6)
-        if self.frame_step % 2 == 0 {
+        if self.frame_step.is_multiple_of(2) {
             self.tick_length_counters();
         }
 
@@ -501,16 +1003,19 @@ impl Apu {
                     self.ch1_frequency = new_freq;
                     self.ch1_sweep_shadow = new_freq;
 
-                    // Overflow check
-                    let _ = self.calculate_sweep_frequency();
+                    // Second overflow check with the updated shadow
+                    // frequency; its result is not written back
+                    self.calculate_sweep_frequency();
                 }
             }
         }
     }
 
+    /// Compute the next sweep frequency, disabling channel 1 on overflow
     fn calculate_sweep_frequency(&mut self) -> u16 {
         let delta = self.ch1_sweep_shadow >> self.ch1_sweep_shift;
         let new_freq = if self.ch1_sweep_negate {
+            self.ch1_sweep_negate_used = true;
             self.ch1_sweep_shadow.wrapping_sub(delta)
         } else {
             self.ch1_sweep_shadow.wrapping_add(delta)
@@ -523,72 +1028,70 @@ impl Apu {
         new_freq
     }
 
-    fn generate_sample_output(&mut self, memory: &Memory) {
-        let nr50 = memory.data[io::NR50 as usize];
-        let nr51 = memory.data[io::NR51 as usize];
-
-        let left_volume = ((nr50 >> 4) & 0x07) as f32 / 7.0;
-        let right_volume = (nr50 & 0x07) as f32 / 7.0;
+    /// Current output level of each channel (0.0-1.0, before panning)
+    fn channel_levels(&self, memory: &Memory) -> [f32; 4] {
+        self.digital_outputs(memory).map(|level| level as f32 / 15.0)
+    }
 
-        let mut left = 0.0f32;
-        let mut right = 0.0f32;
+    /// Each channel's 4-bit digital output (0-15), as fed to its DAC and
+    /// read back through PCM12/PCM34 on the CGB
+    pub fn digital_outputs(&self, memory: &Memory) -> [u8; 4] {
+        let mut levels = [0u8; 4];
+        if !self.enabled {
+            return levels;
+        }
 
         // Channel 1
         if self.ch1_enabled && self.ch1_dac_enabled {
-            let duty = (memory.data[io::NR11 as usize] >> 6) as usize;
-            let sample = DUTY_TABLE[duty][self.ch1_duty_position as usize] as f32;
-            let output = sample * (self.ch1_volume as f32 / 15.0);
-
-            if nr51 & 0x10 != 0 {
-                left += output;
-            }
-            if nr51 & 0x01 != 0 {
-                right += output;
-            }
+            let duty = (memory.io[io::NR11 as usize] >> 6) as usize;
+            levels[0] = DUTY_TABLE[duty][self.ch1_duty_position as usize] * self.ch1_volume;
         }
 
         // Channel 2
         if self.ch2_enabled && self.ch2_dac_enabled {
-            let duty = (memory.data[io::NR21 as usize] >> 6) as usize;
-            let sample = DUTY_TABLE[duty][self.ch2_duty_position as usize] as f32;
-            let output = sample * (self.ch2_volume as f32 / 15.0);
-
-            if nr51 & 0x20 != 0 {
-                left += output;
-            }
-            if nr51 & 0x02 != 0 {
-                right += output;
-            }
+            let duty = (memory.io[io::NR21 as usize] >> 6) as usize;
+            levels[1] = DUTY_TABLE[duty][self.ch2_duty_position as usize] * self.ch2_volume;
         }
 
         // Channel 3
         if self.ch3_enabled && self.ch3_dac_enabled {
-            let shift = match self.ch3_volume_code {
-                0 => 4, // Mute
-                1 => 0, // 100%
-                2 => 1, // 50%
-                3 => 2, // 25%
-                _ => 4,
-            };
-            let output = ((self.ch3_sample_buffer >> shift) as f32) / 15.0;
-
-            if nr51 & 0x40 != 0 {
-                left += output;
-            }
-            if nr51 & 0x04 != 0 {
-                right += output;
-            }
+            levels[2] = self.ch3_sample_buffer >> ch3_shift(self.ch3_volume_code);
         }
 
         // Channel 4
         if self.ch4_enabled && self.ch4_dac_enabled {
-            let sample = if self.ch4_lfsr & 0x01 == 0 { 1.0 } else { 0.0 };
-            let output = sample * (self.ch4_volume as f32 / 15.0);
+            let sample = (self.ch4_lfsr & 0x01 == 0) as u8;
+            levels[3] = sample * self.ch4_volume;
+        }
 
-            if nr51 & 0x80 != 0 {
+        levels
+    }
+
+    fn generate_sample_output(&mut self, memory: &Memory) {
+        let nr50 = memory.io[io::NR50 as usize];
+        let nr51 = memory.io[io::NR51 as usize];
+
+        let left_volume = ((nr50 >> 4) & 0x07) as f32 / 7.0;
+        let right_volume = (nr50 & 0x07) as f32 / 7.0;
+
+        let outputs = self.channel_levels(memory);
+        self.last_outputs = outputs;
+        for (history, &output) in self.history.iter_mut().zip(outputs.iter()) {
+            history[self.history_pos] = output;
+        }
+        self.history_pos = (self.history_pos + 1) % CHANNEL_HISTORY_LEN;
+
+        // Pan each channel: NR51 bits 4-7 enable left, bits 0-3 enable right
+        let mut left = 0.0f32;
+        let mut right = 0.0f32;
+        for (i, &output) in outputs.iter().enumerate() {
+            if self.muted & (1 << i) != 0 {
+                continue;
+            }
+            if nr51 & (0x10 << i) != 0 {
                 left += output;
             }
-            if nr51 & 0x08 != 0 {
+            if nr51 & (0x01 << i) != 0 {
                 right += output;
             }
         }
@@ -597,28 +1100,34 @@ impl Apu {
         left = (left / 4.0) * left_volume;
         right = (right / 4.0) * right_volume;
 
+        // Narrow the stereo image toward the middle of both sides
+        let separation = self.output_options.stereo_separation.min(100) as f32 / 100.0;
+        let middle = (left + right) / 2.0;
+        left = middle + (left - middle) * separation;
+        right = middle + (right - middle) * separation;
+
         // Apply high-pass filter to remove DC offset and reduce pops
         // This simulates the capacitor in the Game Boy's audio output
-        const HPF_FACTOR: f32 = 0.999;
-        self.hpf_left = self.hpf_left * HPF_FACTOR + left;
-        self.hpf_right = self.hpf_right * HPF_FACTOR + right;
-        let left_out = left - self.hpf_left * (1.0 - HPF_FACTOR);
-        let right_out = right - self.hpf_right * (1.0 - HPF_FACTOR);
+        if self.output_options.high_pass {
+            let charge = capacitor_charge(self.model);
+            left = high_pass(&mut self.hpf_left, left, charge);
+            right = high_pass(&mut self.hpf_right, right, charge);
+        }
 
         // Output stereo sample (interleaved) with slight volume reduction
-        self.buffer.push(left_out * 0.5);
-        self.buffer.push(right_out * 0.5);
+        self.buffer.push(left * 0.5);
+        self.buffer.push(right * 0.5);
     }
 
     /// Trigger channel 1
     pub fn trigger_ch1(&mut self, memory: &Memory) {
-        let nr11 = memory.data[io::NR11 as usize];
-        let nr12 = memory.data[io::NR12 as usize];
-        let nr13 = memory.data[io::NR13 as usize];
-        let nr14 = memory.data[io::NR14 as usize];
+        let nr11 = memory.io[io::NR11 as usize];
+        let nr12 = memory.io[io::NR12 as usize];
+        let nr13 = memory.io[io::NR13 as usize];
+        let nr14 = memory.io[io::NR14 as usize];
 
         self.ch1_enabled = self.ch1_dac_enabled;
-        self.ch1_length_counter = 64 - (nr11 & 0x3F);
+        self.ch1_length_counter = 64 - (nr11 & 0x3F) as u16;
         self.ch1_frequency = (nr13 as u16) | (((nr14 & 0x07) as u16) << 8);
         self.ch1_timer = (2048 - self.ch1_frequency) * 4;
         self.ch1_volume = nr12 >> 4;
@@ -628,7 +1137,6 @@ impl Apu {
         self.ch1_envelope_add = nr12 & 0x08 != 0;
 
         // Sweep
-        let nr10 = memory.data[io::NR10 as usize];
         self.ch1_sweep_shadow = self.ch1_frequency;
         self.ch1_sweep_timer = if self.ch1_sweep_period > 0 {
             self.ch1_sweep_period
@@ -644,13 +1152,13 @@ impl Apu {
 
     /// Trigger channel 2
     pub fn trigger_ch2(&mut self, memory: &Memory) {
-        let nr21 = memory.data[io::NR21 as usize];
-        let nr22 = memory.data[io::NR22 as usize];
-        let nr23 = memory.data[io::NR23 as usize];
-        let nr24 = memory.data[io::NR24 as usize];
+        let nr21 = memory.io[io::NR21 as usize];
+        let nr22 = memory.io[io::NR22 as usize];
+        let nr23 = memory.io[io::NR23 as usize];
+        let nr24 = memory.io[io::NR24 as usize];
 
         self.ch2_enabled = self.ch2_dac_enabled;
-        self.ch2_length_counter = 64 - (nr21 & 0x3F);
+        self.ch2_length_counter = 64 - (nr21 & 0x3F) as u16;
         self.ch2_frequency = (nr23 as u16) | (((nr24 & 0x07) as u16) << 8);
         self.ch2_timer = (2048 - self.ch2_frequency) * 4;
         self.ch2_volume = nr22 >> 4;
@@ -662,9 +1170,9 @@ impl Apu {
 
     /// Trigger channel 3
     pub fn trigger_ch3(&mut self, memory: &Memory) {
-        let nr31 = memory.data[io::NR31 as usize];
-        let nr33 = memory.data[io::NR33 as usize];
-        let nr34 = memory.data[io::NR34 as usize];
+        let nr31 = memory.io[io::NR31 as usize];
+        let nr33 = memory.io[io::NR33 as usize];
+        let nr34 = memory.io[io::NR34 as usize];
 
         self.ch3_enabled = self.ch3_dac_enabled;
         self.ch3_length_counter = 256 - (nr31 as u16);
@@ -675,11 +1183,11 @@ impl Apu {
 
     /// Trigger channel 4
     pub fn trigger_ch4(&mut self, memory: &Memory) {
-        let nr41 = memory.data[io::NR41 as usize];
-        let nr42 = memory.data[io::NR42 as usize];
+        let nr41 = memory.io[io::NR41 as usize];
+        let nr42 = memory.io[io::NR42 as usize];
 
         self.ch4_enabled = self.ch4_dac_enabled;
-        self.ch4_length_counter = 64 - (nr41 & 0x3F);
+        self.ch4_length_counter = 64 - (nr41 & 0x3F) as u16;
         self.ch4_lfsr = 0x7FFF;
         self.ch4_volume = nr42 >> 4;
         self.ch4_volume_initial = self.ch4_volume;
@@ -704,6 +1212,115 @@ impl Apu {
     pub fn take_samples(&mut self) -> Vec<f32> {
         std::mem::take(&mut self.buffer)
     }
+
+    /// Move all samples to the end of `out`, keeping the buffer's capacity
+    pub fn append_samples(&mut self, out: &mut Vec<f32>) {
+        out.extend_from_slice(&self.buffer);
+        self.buffer.clear();
+    }
+
+    /// Move up to `out.len()` samples from the front of the buffer into `out`
+    /// Returns the number of samples written; the rest stay buffered.
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.buffer.len());
+        for (dst, src) in out.iter_mut().zip(self.buffer.drain(..count)) {
+            *dst = src;
+        }
+        count
+    }
+}
+
+/// Right shift channel 3 applies to its samples for an NR32 output level
+fn ch3_shift(volume_code: u8) -> u8 {
+    match volume_code {
+        0 => 4, // Mute
+        1 => 0, // 100%
+        2 => 1, // 50%
+        3 => 2, // 25%
+        _ => 4,
+    }
+}
+
+/// Fixed-point cycles per output sample when resampling by `ratio`
+fn sample_period(ratio: f64) -> u32 {
+    (CPU_CLOCK / (SAMPLE_RATE as f64 * ratio) * (1 << SAMPLE_FRACTION_BITS) as f64).round() as u32
+}
+
+/// Count a channel timer down by `step` cycles
+///
+/// Returns true if the timer expired; a timer of 0 expires on the next cycle.
+fn advance_timer(timer: &mut u16, step: u32) -> bool {
+    let left = (*timer).max(1) as u32;
+    if step >= left {
+        true
+    } else {
+        *timer = (left - step) as u16;
+        false
+    }
+}
+
+/// Apply the length enable bit of an NRx4 write
+///
+/// Enabling length while the next frame sequencer step will not clock it
+/// clocks the counter once straight away, which can disable the channel.
+/// A trigger reloads an expired counter with its full length.
+fn write_length_control(
+    value: u8,
+    extra_clock: bool,
+    full_length: u16,
+    length_enabled: &mut bool,
+    counter: &mut u16,
+    channel_enabled: &mut bool,
+) {
+    let was_enabled = *length_enabled;
+    *length_enabled = value & 0x40 != 0;
+    let trigger = value & 0x80 != 0;
+
+    if extra_clock && !was_enabled && *length_enabled && *counter > 0 {
+        *counter -= 1;
+        if *counter == 0 && !trigger {
+            *channel_enabled = false;
+        }
+    }
+    if trigger && *counter == 0 {
+        *counter = full_length;
+        if extra_clock && *length_enabled {
+            *counter -= 1;
+        }
+    }
+}
+
+/// DMG "zombie mode": writing NRx2 while a channel plays nudges its volume
+fn zombie_volume(volume: u8, old: u8, new: u8) -> u8 {
+    let mut volume = volume;
+    if old & 0x07 == 0 {
+        volume += 1;
+    } else if old & 0x08 == 0 {
+        volume += 2;
+    }
+    if (old ^ new) & 0x08 != 0 {
+        volume = 16 - volume;
+    }
+    volume & 0x0F
+}
+
+/// How much of the output capacitor's charge is left after one output
+/// sample: 0.999958 per T-cycle on the DMG, 0.998943 on the CGB
+fn capacitor_charge(model: GbModel) -> f32 {
+    // powf(CPU_CLOCK / SAMPLE_RATE) of the per-cycle factors
+    if model.is_cgb() {
+        0.904_31
+    } else {
+        0.996_013
+    }
+}
+
+/// One step of the capacitor filter: pass the input's changes, let its
+/// steady level drain away
+fn high_pass(capacitor: &mut f32, input: f32, charge: f32) -> f32 {
+    let out = input - *capacitor;
+    *capacitor = input - out * charge;
+    out
 }
 
 impl Default for Apu {
@@ -712,3 +1329,409 @@ impl Default for Apu {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Trigger channel 2 at full volume with a 50% duty cycle
+    ///
+    /// Channel 1's DAC is turned off, silencing the post-boot beep.
+    fn start_channel2(memory: &mut Memory, frequency: u16) {
+        memory.io[io::NR52 as usize] = 0x80;
+        memory.io[io::NR12 as usize] = 0x00;
+        memory.io[io::NR51 as usize] = 0xFF;
+        memory.io[io::NR50 as usize] = 0x77;
+        memory.io[io::NR21 as usize] = 0x80;
+        memory.io[io::NR22 as usize] = 0xF0;
+        memory.io[io::NR23 as usize] = frequency as u8;
+        memory.io[io::NR24 as usize] = 0x80 | (frequency >> 8) as u8;
+    }
+
+    #[test]
+    fn channel_outputs_report_frequency() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel2(&mut memory, 1750);
+        apu.tick(&mut memory, 4096);
+
+        let outputs = apu.channel_outputs();
+        assert!(!outputs[0].active);
+        assert!(outputs[1].active);
+        assert!((outputs[1].frequency - 131072.0 / 298.0).abs() < 0.01);
+        assert_eq!(outputs[2].frequency, 0.0);
+    }
+
+    #[test]
+    fn nr52_reports_channel_status() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel2(&mut memory, 1750);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(io::NR52), 0xF2);
+
+        // Status bits are read-only
+        memory.write_byte(io::NR52, 0x8F);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(io::NR52), 0xF2);
+
+        // Turning the DAC off disables the channel
+        memory.write_byte(io::NR22, 0x00);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(io::NR52), 0xF0);
+    }
+
+    #[test]
+    fn power_off_clears_and_locks_registers() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel2(&mut memory, 1750);
+        apu.tick(&mut memory, 4);
+
+        memory.write_byte(io::NR52, 0x00);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(io::NR52), 0x70);
+        assert_eq!(memory.read_byte(io::NR22), 0x00);
+        assert_eq!(memory.read_byte(io::NR50), 0x00);
+        assert!(apu.channel_outputs().iter().all(|c| !c.active));
+
+        // Writes are ignored while off, apart from DMG length counters
+        memory.write_byte(io::NR22, 0xF0);
+        memory.write_byte(io::NR21, 0xFF);
+        assert_eq!(memory.read_byte(io::NR22), 0x00);
+        assert_eq!(memory.read_byte(io::NR21), 0x3F);
+
+        // Wave RAM stays accessible
+        memory.write_byte(0xFF30, 0x5A);
+        assert_eq!(memory.read_byte(0xFF30), 0x5A);
+
+        memory.write_byte(io::NR52, 0x80);
+        memory.write_byte(io::NR22, 0xF0);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(io::NR22), 0xF0);
+        assert_eq!(memory.read_byte(io::NR52), 0xF0);
+    }
+
+    /// Fill wave RAM with 0x00, 0x11, ... and trigger channel 3
+    fn start_channel3(memory: &mut Memory, frequency: u16) {
+        for i in 0..16 {
+            memory.io[0xFF30 + i] = (i as u8) * 0x11;
+        }
+        memory.io[io::NR52 as usize] = 0x80;
+        memory.io[io::NR12 as usize] = 0x00;
+        memory.io[io::NR30 as usize] = 0x80;
+        memory.io[io::NR32 as usize] = 0x20;
+        memory.io[io::NR33 as usize] = frequency as u8;
+        memory.io[io::NR34 as usize] = 0x80 | (frequency >> 8) as u8;
+    }
+
+    #[test]
+    fn pcm_registers_read_digital_outputs() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        apu.set_model(GbModel::Cgb);
+        memory.set_model(GbModel::Cgb);
+        start_channel2(&mut memory, 1750);
+
+        // Channel 2 alternates between silence and full volume
+        let mut seen = Vec::new();
+        for _ in 0..8 {
+            apu.tick(&mut memory, 1192);
+            seen.push(memory.read_byte(io::PCM12));
+        }
+        assert!(seen.contains(&0x00) && seen.contains(&0xF0));
+        assert!(seen.iter().all(|&pcm| pcm & 0x0F == 0));
+        assert_eq!(memory.read_byte(io::PCM34), 0x00);
+
+        memory.write_byte(io::PCM12, 0x12);
+        assert_ne!(memory.read_byte(io::PCM12), 0x12);
+
+        memory.set_model(GbModel::DmgABC);
+        assert_eq!(memory.read_byte(io::PCM12), 0xFF);
+    }
+
+    #[test]
+    fn wave_ram_is_locked_while_playing() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        // 96-cycle sample period; the first fetch follows the trigger delay
+        start_channel3(&mut memory, 2000);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(0xFF35), 0xFF);
+        memory.write_byte(0xFF35, 0xAB);
+        assert_eq!(memory.io[0xFF35], 0x55);
+
+        // During a fetch the CPU sees the byte being played, whatever the address
+        apu.tick(&mut memory, 96);
+        assert_eq!(memory.read_byte(0xFF35), 0x00);
+        memory.write_byte(0xFF35, 0xAB);
+        assert_eq!(memory.io[0xFF30], 0xAB);
+
+        memory.write_byte(io::NR30, 0x00);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.read_byte(0xFF35), 0x55);
+    }
+
+    #[test]
+    fn retrigger_during_fetch_corrupts_wave_ram() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel3(&mut memory, 2000);
+        // Nine fetches in, then stop two cycles short of the tenth
+        apu.tick(&mut memory, CH3_TRIGGER_DELAY as u32 + 96 * 9 + 94);
+
+        memory.write_byte(io::NR34, 0x87);
+        apu.tick(&mut memory, 4);
+        assert_eq!(memory.io[0xFF30..0xFF34], [0x44, 0x55, 0x66, 0x77]);
+        assert_eq!(memory.io[0xFF34], 0x44);
+    }
+
+    #[test]
+    fn sweep_frequency_persists_between_ticks() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        apu.tick(&mut memory, 4);
+
+        // Sweep up by freq/4 every sweep step (period 1), starting at 1024
+        memory.write_byte(io::NR10, 0x12);
+        memory.write_byte(io::NR12, 0xF0);
+        memory.write_byte(io::NR13, 0x00);
+        memory.write_byte(io::NR14, 0x84);
+        apu.tick(&mut memory, 4);
+        let start = apu.channel_outputs()[0].frequency;
+
+        // Sweep is clocked on frame sequencer step 2
+        apu.tick(&mut memory, FRAME_SEQUENCER_PERIOD * 2);
+        apu.tick(&mut memory, 4);
+        let swept = apu.channel_outputs()[0].frequency;
+        assert_eq!(start, 131072.0 / 1024.0);
+        assert_eq!(swept, 131072.0 / (2048.0 - 1280.0));
+    }
+
+    #[test]
+    fn trigger_checks_sweep_overflow() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        apu.tick(&mut memory, 4);
+
+        // 1500 + 1500/2 overflows before the first sweep step
+        memory.write_byte(io::NR10, 0x01);
+        memory.write_byte(io::NR12, 0xF0);
+        memory.write_byte(io::NR13, 0xDC);
+        memory.write_byte(io::NR14, 0x85);
+        apu.tick(&mut memory, 4);
+        assert!(!apu.channel_outputs()[0].active);
+    }
+
+    #[test]
+    fn leaving_negate_mode_after_sweep_disables_channel1() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        apu.tick(&mut memory, 4);
+
+        memory.write_byte(io::NR10, 0x18);
+        memory.write_byte(io::NR12, 0xF0);
+        memory.write_byte(io::NR13, 0x00);
+        memory.write_byte(io::NR14, 0x84);
+        apu.tick(&mut memory, 4);
+
+        // Clearing negate before any calculation is harmless (with a shift
+        // of 0 the trigger skips its overflow check)
+        memory.write_byte(io::NR10, 0x10);
+        apu.tick(&mut memory, 4);
+        assert!(apu.channel_outputs()[0].active);
+
+        // Once a negate calculation ran, clearing the bit kills the channel
+        memory.write_byte(io::NR10, 0x18);
+        apu.tick(&mut memory, FRAME_SEQUENCER_PERIOD * 3);
+        assert!(apu.channel_outputs()[0].active);
+        memory.write_byte(io::NR10, 0x10);
+        apu.tick(&mut memory, 4);
+        assert!(!apu.channel_outputs()[0].active);
+    }
+
+    #[test]
+    fn zombie_mode_adjusts_volume() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel2(&mut memory, 1750);
+        memory.io[io::NR22 as usize] = 0x80;
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_volume, 8);
+
+        // Old period 0: +1; then a direction change mirrors the volume
+        memory.write_byte(io::NR22, 0x80);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_volume, 9);
+        memory.write_byte(io::NR22, 0x88);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_volume, 6);
+
+        // Back to subtract mode (16 - 7), then old period non-zero: +2
+        memory.write_byte(io::NR22, 0x81);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_volume, 9);
+        memory.write_byte(io::NR22, 0x81);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_volume, 11);
+    }
+
+    #[test]
+    fn enabling_length_clocks_it_on_odd_step() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel2(&mut memory, 1750);
+        memory.io[io::NR21 as usize] = 0x3E;
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_length_counter, 2);
+
+        // The next step (1) does not clock length, so enabling it does
+        memory.write_byte(io::NR24, 0x40);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_length_counter, 1);
+        assert!(apu.ch2_enabled);
+
+        // After step 1 the next step clocks length: no extra clock
+        apu.tick(&mut memory, FRAME_SEQUENCER_PERIOD);
+        memory.write_byte(io::NR24, 0x00);
+        apu.tick(&mut memory, 4);
+        assert!(!apu.ch2_length_enabled);
+        memory.write_byte(io::NR24, 0x40);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_length_counter, 1);
+
+        // Back on an even step, the extra clock expires the counter
+        apu.tick(&mut memory, FRAME_SEQUENCER_PERIOD);
+        assert!(!apu.ch2_enabled);
+
+        // Triggering with an expired counter reloads it, minus the extra clock
+        memory.write_byte(io::NR24, 0xC0);
+        apu.tick(&mut memory, 4);
+        assert_eq!(apu.ch2_length_counter, 63);
+        assert!(apu.ch2_enabled);
+    }
+
+    #[test]
+    fn samples_continue_while_powered_off() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        memory.write_byte(io::NR52, 0x00);
+        apu.tick(&mut memory, 952);
+        assert_eq!(apu.buffer.len(), 20);
+        assert!(apu.channel_outputs().iter().all(|c| c.amplitude == 0.0));
+    }
+
+    #[test]
+    fn rate_adjust_resamples_output() {
+        let mut memory = Memory::new();
+        let mut counts = Vec::new();
+        for ratio in [1.0, 1.005, 2.0] {
+            let mut apu = Apu::new();
+            apu.set_rate_adjust(ratio);
+            // One second of emulation, in instruction-sized ticks
+            for _ in 0..4_194_304 / 16 {
+                apu.tick(&mut memory, 16);
+            }
+            counts.push(apu.buffer.len() / 2);
+        }
+        assert_eq!(counts[0], SAMPLE_RATE as usize);
+        assert_eq!(counts[1], 44_320);
+        // Clamped to +5%
+        assert_eq!(counts[2], 46_304);
+    }
+
+    #[test]
+    fn channel_history_tracks_waveform() {
+        let mut apu = Apu::new();
+        let mut memory = Memory::new();
+        start_channel2(&mut memory, 1750);
+        apu.tick(&mut memory, 256 * 96);
+
+        let mut history = [0.0f32; 256];
+        apu.channel_history(1, &mut history);
+        // A 440 Hz square wave at full volume alternates between 0 and 1
+        assert!(history.contains(&1.0));
+        assert!(history.contains(&0.0));
+
+        apu.channel_history(0, &mut history);
+        assert!(history.iter().all(|&s| s == 0.0));
+
+        history.fill(0.5);
+        apu.channel_history(4, &mut history);
+        assert!(history.iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn muted_channels_leave_the_mix_only() {
+        let mut playing = Apu::new();
+        let mut muted = Apu::new();
+        muted.set_channel_muted(1, true);
+        for apu in [&mut playing, &mut muted] {
+            let mut memory = Memory::new();
+            start_channel2(&mut memory, 1750);
+            apu.tick(&mut memory, 256 * 96);
+        }
+        assert!(playing.take_samples().iter().any(|&s| s != 0.0));
+        assert!(muted.take_samples().iter().all(|&s| s == 0.0));
+
+        // The visualization still sees it play
+        assert_eq!(muted.channel_outputs()[1].volume, 15);
+        assert!(muted.channel_outputs()[1].active);
+        let mut history = [0.0f32; 256];
+        muted.channel_history(1, &mut history);
+        assert!(history.contains(&1.0));
+
+        muted.reset();
+        assert!(muted.channel_muted(1));
+        muted.set_channel_muted(1, false);
+        assert!(!muted.channel_muted(1));
+    }
+
+    #[test]
+    fn output_options_shape_the_mix() {
+        // Channel 2 panned hard left; the filter off to compare raw levels
+        let run = |stereo_separation| {
+            let mut apu = Apu::new();
+            apu.set_output_options(AudioOptions { stereo_separation, high_pass: false });
+            let mut memory = Memory::new();
+            start_channel2(&mut memory, 1750);
+            memory.io[io::NR51 as usize] = 0x20;
+            apu.tick(&mut memory, 256 * 96);
+            apu.take_samples()
+        };
+        let peak = |samples: &[f32], side: usize| {
+            samples.iter().skip(side).step_by(2).fold(0.0f32, |a, &b| a.max(b))
+        };
+
+        let hard = run(100);
+        assert!(peak(&hard, 0) > 0.0);
+        assert_eq!(peak(&hard, 1), 0.0);
+
+        let mono = run(0);
+        assert!(mono.chunks(2).all(|lr| lr[0] == lr[1]));
+        assert_eq!(peak(&mono, 0), peak(&hard, 0) / 2.0);
+
+        let narrow = run(50);
+        assert_eq!(peak(&narrow, 0), peak(&hard, 0) * 0.75);
+        assert_eq!(peak(&narrow, 1), peak(&hard, 0) * 0.25);
+    }
+
+    #[test]
+    fn high_pass_drains_steady_levels() {
+        let mut capacitor = 0.0;
+        let first = high_pass(&mut capacitor, 1.0, capacitor_charge(GbModel::Cgb));
+        assert_eq!(first, 1.0);
+        let mut last = first;
+        for _ in 0..1000 {
+            last = high_pass(&mut capacitor, 1.0, capacitor_charge(GbModel::Cgb));
+        }
+        assert!(last.abs() < 1e-3);
+
+        // The DMG's capacitor holds its charge far longer
+        let mut capacitor = 0.0;
+        for _ in 0..1000 {
+            last = high_pass(&mut capacitor, 1.0, capacitor_charge(GbModel::DmgABC));
+        }
+        assert!(last > 0.01);
+    }
+}
@@ -0,0 +1,166 @@
+//! Audio output for the desktop UI
+//!
+//! Plays the shared [`AudioBuffer`] on the device picked in the settings,
+//! or the system default. Output devices come and go (a USB DAC being
+//! unplugged, headphones switching over), so the stream's error callback
+//! only raises a flag; [`AudioOutput::poll`] on the window thread then
+//! drops the dead stream and keeps trying to open a new one, on the picked
+//! device when it's back and the default until then.
+
+use crate::emu_thread::AudioBuffer;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often a missing or replaced device is looked for again
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Names of the output devices on the default host
+pub fn device_names() -> Vec<String> {
+    let host = cpal::default_host();
+    let Ok(devices) = host.output_devices() else { return Vec::new() };
+    devices.filter_map(|d| d.name().ok()).collect()
+}
+
+/// The output stream and what it should be playing on
+pub struct AudioOutput {
+    buffer: AudioBuffer,
+    sample_rate: u32,
+    /// Device picked in the settings, None for the system default
+    device: Option<String>,
+    /// Frames per device callback, 0 for the device's default
+    buffer_size: u32,
+    stream: Option<cpal::Stream>,
+    /// Name of the device the stream plays on
+    playing_on: Option<String>,
+    /// Set by the current stream when it fails
+    failed: Arc<AtomicBool>,
+    last_attempt: Instant,
+}
+
+impl AudioOutput {
+    /// Start playing `buffer`, if any device will take it
+    pub fn open(buffer: AudioBuffer, sample_rate: u32, device: Option<String>, buffer_size: u32) -> Self {
+        let mut output = Self {
+            buffer,
+            sample_rate,
+            device,
+            buffer_size,
+            stream: None,
+            playing_on: None,
+            failed: Arc::new(AtomicBool::new(false)),
+            last_attempt: Instant::now(),
+        };
+        if let Err(e) = output.reopen() {
+            eprintln!("{}", e);
+        }
+        output
+    }
+
+    /// Whether a stream is draining the buffer
+    pub fn is_live(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Switch device or buffer size, restarting the stream
+    pub fn configure(&mut self, device: Option<String>, buffer_size: u32) {
+        self.device = device;
+        self.buffer_size = buffer_size;
+        if let Err(e) = self.reopen() {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Recover from a failed stream and move back to the picked device
+    /// when it reappears; call once per host frame
+    ///
+    /// Returns a message for the player when the output changed.
+    pub fn poll(&mut self) -> Option<String> {
+        if self.failed.swap(false, Ordering::Relaxed) && self.stream.take().is_some() {
+            eprintln!("Audio device lost: {}", self.playing_on.as_deref().unwrap_or("unknown"));
+            self.playing_on = None;
+            self.last_attempt = Instant::now();
+            return Some("Audio device lost".to_string());
+        }
+
+        let on_fallback = self.device.is_some() && self.playing_on != self.device;
+        if (self.stream.is_some() && !on_fallback) || self.last_attempt.elapsed() < RETRY_INTERVAL {
+            return None;
+        }
+        if self.stream.is_some() && !device_names().iter().any(|n| Some(n) == self.device.as_ref()) {
+            self.last_attempt = Instant::now();
+            return None;
+        }
+        // Failures are expected while nothing is plugged in; keep quiet
+        let before = self.playing_on.clone();
+        let _ = self.reopen();
+        if self.playing_on == before {
+            return None;
+        }
+        self.playing_on.as_ref().map(|name| format!("Audio: {}", name))
+    }
+
+    /// Replace the stream with one on the picked device, falling back to
+    /// the default
+    fn reopen(&mut self) -> Result<(), String> {
+        self.last_attempt = Instant::now();
+        self.stream = None;
+        self.playing_on = None;
+        // A fresh flag, so the old stream's last errors don't count
+        self.failed = Arc::new(AtomicBool::new(false));
+
+        let host = cpal::default_host();
+        let picked = self.device.as_ref().and_then(|name| {
+            host.output_devices().ok()?.find(|d| d.name().ok().as_ref() == Some(name))
+        });
+        let device = picked
+            .or_else(|| host.default_output_device())
+            .ok_or("No audio output device")?;
+        let stream = self
+            .build_stream(&device)
+            .map_err(|e| format!("Failed to open audio device: {}", e))?;
+        self.playing_on = device.name().ok();
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn build_stream(&self, device: &cpal::Device) -> Result<cpal::Stream, String> {
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(self.sample_rate),
+            buffer_size: match self.buffer_size {
+                0 => cpal::BufferSize::Default,
+                frames => cpal::BufferSize::Fixed(frames),
+            },
+        };
+
+        let buffer = Arc::clone(&self.buffer);
+        let failed = Arc::clone(&self.failed);
+        let mut last_sample = 0.0f32;
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let mut buffer = buffer.lock().unwrap();
+                    for sample in data.iter_mut() {
+                        if let Some(s) = buffer.pop_front() {
+                            *sample = s;
+                            last_sample = s;
+                        } else {
+                            last_sample *= 0.9;
+                            *sample = last_sample;
+                        }
+                    }
+                },
+                move |err| {
+                    eprintln!("Audio error: {}", err);
+                    failed.store(true, Ordering::Relaxed);
+                },
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        Ok(stream)
+    }
+}
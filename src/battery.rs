@@ -0,0 +1,124 @@
+//! Battery save persistence for the desktop UI
+//!
+//! Saves live next to the ROM (`game.sav`) in the layout BGB and VBA-M
+//! use, with the RTC footer for clock cartridges. The file is loaded with
+//! the ROM, rewritten shortly after the game changes its save RAM (see
+//! [`Emulator::save_ram_dirty`]), and written a final time when the ROM
+//! is closed.
+
+use gb3000::Emulator;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Minimum time between writes, so a game saving over several frames
+/// causes a single write
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Path of the battery save for a ROM
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// Path of the battery save for a second copy of a ROM played alongside
+/// the first (`game.2.sav`), so trading between them keeps both saves
+pub fn second_save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("2.sav")
+}
+
+/// Seconds since the UNIX epoch, for RTC footers
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Keeps a ROM's `.sav` file in step with the emulator
+#[derive(Debug)]
+pub struct BatterySaver {
+    path: PathBuf,
+    last_write: Instant,
+}
+
+impl BatterySaver {
+    /// Load the save for `rom_path` into the emulator, if there is one
+    pub fn load(emulator: &mut Emulator, rom_path: &Path) -> Self {
+        Self::load_from(emulator, save_path(rom_path))
+    }
+
+    /// Load the save at `path` into the emulator, if there is one, and
+    /// keep writing it there
+    pub fn load_from(emulator: &mut Emulator, path: PathBuf) -> Self {
+        if emulator.has_battery() {
+            if let Ok(data) = fs::read(&path) {
+                emulator.load_save_file(&data, unix_time());
+                println!("Loaded save: {}", path.display());
+            }
+        }
+        Self {
+            path,
+            last_write: Instant::now(),
+        }
+    }
+
+    /// Write the save if the game changed its RAM since the last write
+    ///
+    /// Cheap to call every frame.
+    pub fn flush_if_changed(&mut self, emulator: &mut Emulator) {
+        if emulator.save_ram_dirty() && self.last_write.elapsed() >= FLUSH_INTERVAL {
+            self.save(emulator);
+        }
+    }
+
+    /// Write the save unconditionally (on quit, reset and ROM change)
+    pub fn save(&mut self, emulator: &mut Emulator) {
+        let Some(data) = emulator.save_file(unix_time()) else { return };
+        // Write a temporary file first so a crash can't truncate the save
+        let tmp = self.path.with_extension("sav.tmp");
+        match fs::write(&tmp, &data).and_then(|()| fs::rename(&tmp, &self.path)) {
+            Ok(()) => {
+                emulator.mark_saved();
+                self.last_write = Instant::now();
+                println!("Saved game: {}", self.path.display());
+            }
+            Err(e) => eprintln!("Failed to save {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_only_changed_ram() {
+        let rom_path = std::env::temp_dir().join("gb3000-battery-test.gb");
+        let _ = fs::remove_file(save_path(&rom_path));
+
+        // MBC3+TIMER+RAM+BATTERY with 8 KB of RAM
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&rom);
+
+        let mut saver = BatterySaver::load(&mut emulator, &rom_path);
+        saver.last_write -= FLUSH_INTERVAL;
+        saver.flush_if_changed(&mut emulator);
+        assert!(!save_path(&rom_path).exists());
+
+        emulator.poke(0xA000, 0x42);
+        saver.flush_if_changed(&mut emulator);
+        assert!(save_path(&rom_path).exists());
+        assert!(!emulator.save_ram_dirty());
+        let data = fs::read(save_path(&rom_path)).unwrap();
+        assert_eq!(data.len(), 0x2000 + gb3000::rtc::FOOTER_SIZE);
+
+        let mut reloaded = Emulator::new();
+        reloaded.load_rom(&rom);
+        BatterySaver::load(&mut reloaded, &rom_path);
+        fs::remove_file(save_path(&rom_path)).unwrap();
+        assert_eq!(reloaded.save_ram().unwrap()[0], 0x42);
+    }
+}
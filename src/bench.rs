@@ -0,0 +1,179 @@
+//! Benchmark mode
+//!
+//! `gb3000-ui bench game.gb --seconds 10` runs the game as fast as it
+//! goes, with no window, scaling or audio output, then reports the
+//! emulated speed and how the time split between the core's subsystems,
+//! so performance work on the core can be measured consistently. With
+//! `--opcodes N` it also lists the N instructions executed most, to see
+//! which paths of the CPU a game leans on.
+
+use gb3000::disasm::disassemble;
+use gb3000::{Emulator, GbModel, OpcodeHistogram, Profile};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub const USAGE: &str = "Usage: gb3000-ui bench <rom> [--seconds N] [--model MODEL] [--opcodes N]";
+
+/// CPU cycles per second at normal speed
+const CLOCK_HZ: f64 = 4_194_304.0;
+
+/// What a benchmark run does
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    pub rom: PathBuf,
+    pub duration: Duration,
+    pub model: Option<GbModel>,
+    /// How many of the most executed opcodes to list
+    pub opcodes: usize,
+}
+
+impl BenchOptions {
+    /// Parse the arguments after `bench`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut rom = None;
+        let mut duration = Duration::from_secs(10);
+        let mut model = None;
+        let mut opcodes = 0;
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            let mut value = || rest.next().ok_or_else(|| format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--seconds" => {
+                    let n = value()?;
+                    let seconds: f64 = n.parse().map_err(|_| format!("Bad duration: {}", n))?;
+                    duration = Duration::try_from_secs_f64(seconds).map_err(|_| format!("Bad duration: {}", n))?;
+                }
+                "--model" => {
+                    let name = value()?;
+                    let found = GbModel::ALL.into_iter().find(|m| m.to_string().eq_ignore_ascii_case(name));
+                    model = Some(found.ok_or_else(|| format!("Unknown model: {}", name))?);
+                }
+                "--opcodes" => {
+                    let n = value()?;
+                    opcodes = n.parse().map_err(|_| format!("Bad opcode count: {}", n))?;
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+                path if rom.is_none() => rom = Some(PathBuf::from(path)),
+                extra => return Err(format!("Unexpected argument: {}", extra)),
+            }
+        }
+        Ok(BenchOptions { rom: rom.ok_or("No ROM given")?, duration, model, opcodes })
+    }
+}
+
+/// Run the benchmark and return its report
+pub fn run(options: &BenchOptions) -> Result<String, String> {
+    let rom = fs::read(&options.rom).map_err(|e| format!("Failed to read ROM: {}", e))?;
+    let mut emulator = Emulator::new();
+    emulator.set_audio_enabled(false);
+    emulator.load_rom(&rom);
+    emulator.reset_for_model(options.model.unwrap_or_default());
+    emulator.set_profiling(true);
+    emulator.set_opcode_counting(options.opcodes > 0);
+
+    let start = Instant::now();
+    let mut frames = 0u64;
+    while start.elapsed() < options.duration {
+        emulator.run_frame();
+        frames += 1;
+    }
+    let elapsed = start.elapsed();
+    let profile = emulator.profile().cloned().unwrap_or_default();
+    let mut report = format_report(frames, emulator.total_cycles(), elapsed, &profile);
+    if let Some(histogram) = emulator.opcode_histogram() {
+        report.push_str(&format_opcodes(histogram, options.opcodes));
+    }
+    Ok(report)
+}
+
+/// Describe a finished run: speed first, then the share of each subsystem
+pub fn format_report(frames: u64, cycles: u64, elapsed: Duration, profile: &Profile) -> String {
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    let mut out = format!(
+        "{} frames in {:.2}s: {:.1} frames/s, {:.2}x real time\n",
+        frames,
+        secs,
+        frames as f64 / secs,
+        cycles as f64 / CLOCK_HZ / secs
+    );
+
+    let total = profile.total().as_secs_f64();
+    if total > 0.0 {
+        out.push_str(&format!("\nTime per subsystem ({} sampled steps):\n", profile.samples));
+        for (name, time) in [
+            ("CPU", profile.cpu),
+            ("PPU", profile.ppu),
+            ("APU", profile.apu),
+            ("Timer", profile.timer),
+            ("Other", profile.other),
+        ] {
+            out.push_str(&format!("  {:<6} {:5.1}%\n", name, time.as_secs_f64() / total * 100.0));
+        }
+    }
+    out
+}
+
+/// List the `n` opcodes executed most with their share of instructions;
+/// operands show as zero
+pub fn format_opcodes(histogram: &OpcodeHistogram, n: usize) -> String {
+    let total = histogram.total().max(1) as f64;
+    let mut out = format!("\nMost executed opcodes ({} instructions):\n", histogram.total());
+    for (opcode, count) in histogram.most_executed(n) {
+        let bytes = opcode.to_be_bytes();
+        let (hex, bytes) = if opcode > 0xFF {
+            (format!("CB {:02X}", bytes[1]), [bytes[0], bytes[1], 0])
+        } else {
+            (format!("{:02X}", bytes[1]), [bytes[1], 0, 0])
+        };
+        let text = disassemble(|addr| bytes.get(addr as usize).copied().unwrap_or(0), 0).text;
+        out.push_str(&format!("  {:<5} {:<14} {:5.1}%\n", hex, text, count as f64 / total * 100.0));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options_and_formats_report() {
+        let args: Vec<String> = ["game.gb", "--seconds", "2.5"].iter().map(|s| s.to_string()).collect();
+        let options = BenchOptions::parse(&args).unwrap();
+        assert_eq!(options.rom, PathBuf::from("game.gb"));
+        assert_eq!(options.duration, Duration::from_millis(2500));
+        assert!(BenchOptions::parse(&["game.gb".to_string(), "--seconds".to_string(), "-1".to_string()]).is_err());
+        assert!(BenchOptions::parse(&[]).is_err());
+
+        let profile = Profile {
+            cpu: Duration::from_millis(500),
+            ppu: Duration::from_millis(300),
+            apu: Duration::from_millis(100),
+            timer: Duration::from_millis(50),
+            other: Duration::from_millis(50),
+            samples: 1000,
+        };
+        let report = format_report(1200, 1200 * 70224, Duration::from_secs(2), &profile);
+        assert!(report.starts_with("1200 frames in 2.00s: 600.0 frames/s, 10.05x real time\n"), "{}", report);
+        assert!(report.contains("  CPU     50.0%\n"), "{}", report);
+        assert!(report.contains("  Timer    5.0%\n"), "{}", report);
+
+        // Nothing timed, nothing to split
+        assert!(!format_report(0, 0, Duration::ZERO, &Profile::default()).contains('%'));
+    }
+
+    #[test]
+    fn lists_most_executed_opcodes() {
+        let args: Vec<String> = ["game.gb", "--opcodes", "2"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(BenchOptions::parse(&args).unwrap().opcodes, 2);
+
+        let mut histogram = OpcodeHistogram::default();
+        histogram.base[0x3C] = 1;
+        histogram.base[0xCB] = 3;
+        histogram.cb[0x37] = 3;
+        let listing = format_opcodes(&histogram, 1);
+        assert!(listing.contains("(4 instructions)"), "{}", listing);
+        assert!(listing.contains("  CB 37 SWAP A          75.0%\n"), "{}", listing);
+        assert!(!listing.contains("INC A"), "{}", listing);
+    }
+}
@@ -0,0 +1,343 @@
+//! Screenshot and gameplay recording for the desktop UI
+//!
+//! Screenshots are written as PNG files and recordings as animated GIF or
+//! APNG files into a `screenshots` directory in the working directory,
+//! named after the ROM and the capture time.
+
+use gb3000::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Game Boy frame duration in milliseconds (70224 cycles at 4194304 Hz)
+const FRAME_MS: f64 = 70_224.0 * 1000.0 / 4_194_304.0;
+
+/// Directory screenshots are written to
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+/// Write an RGBA frame (160x144) as a PNG into [`SCREENSHOT_DIR`]
+///
+/// Returns the path of the written file.
+pub fn save_screenshot(rgba: &[u8], rom_name: &str) -> Result<PathBuf, String> {
+    let path = output_path(rom_name, "png")?;
+    write_png(&path, rgba, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)?;
+    Ok(path)
+}
+
+/// Encode an RGBA image to a PNG file
+pub fn write_png(path: &Path, rgba: &[u8], width: u32, height: u32) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("PNG error: {}", e))?;
+    writer.write_image_data(rgba).map_err(|e| format!("PNG error: {}", e))
+}
+
+/// Decode a PNG file to RGBA, returning the pixels, width and height
+///
+/// Grayscale, RGB and indexed images are converted.
+pub fn read_png(path: &Path) -> Result<(Vec<u8>, u32, u32), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| format!("PNG error: {}", e))?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| format!("PNG error: {}", e))?;
+    data.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => data,
+        png::ColorType::Rgb => data.chunks(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+        png::ColorType::Indexed => return Err("PNG error: palette not expanded".to_string()),
+    };
+    Ok((rgba, info.width, info.height))
+}
+
+/// Animated output format for [`Recorder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Gif,
+    Apng,
+}
+
+impl RecordFormat {
+    /// File extension for this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Gif => "gif",
+            RecordFormat::Apng => "png",
+        }
+    }
+}
+
+/// A unique frame and how many emulated frames it stayed on screen
+struct RecordedFrame {
+    pixels: Box<[u8]>,
+    repeats: u32,
+}
+
+/// Rolling capture of the last N seconds of gameplay
+///
+/// Identical consecutive frames are stored once with a repeat count, so
+/// static screens and the 59.7 Hz frame rate cost almost nothing.
+pub struct Recorder {
+    frames: VecDeque<RecordedFrame>,
+    /// Emulated frames currently held
+    total_frames: u32,
+    /// Maximum emulated frames to keep
+    max_frames: u32,
+}
+
+impl Recorder {
+    /// Create a recorder that keeps the last `seconds` of video
+    pub fn new(seconds: f64) -> Self {
+        Self {
+            frames: VecDeque::new(),
+            total_frames: 0,
+            max_frames: ((seconds * 1000.0 / FRAME_MS) as u32).max(1),
+        }
+    }
+
+    /// Add a frame of 2-bit color indices, dropping the oldest if full
+    pub fn push_frame(&mut self, framebuffer: &[u8]) {
+        match self.frames.back_mut() {
+            Some(last) if *last.pixels == *framebuffer => last.repeats += 1,
+            _ => self.frames.push_back(RecordedFrame {
+                pixels: framebuffer.into(),
+                repeats: 1,
+            }),
+        }
+        self.total_frames += 1;
+
+        while self.total_frames > self.max_frames {
+            let Some(front) = self.frames.front_mut() else { break };
+            front.repeats -= 1;
+            self.total_frames -= 1;
+            if front.repeats == 0 {
+                self.frames.pop_front();
+            }
+        }
+    }
+
+    /// Number of unique frames held
+    pub fn unique_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Recorded duration in seconds
+    pub fn duration_secs(&self) -> f64 {
+        self.total_frames as f64 * FRAME_MS / 1000.0
+    }
+
+    /// Frame delays in units of `1/unit` seconds
+    ///
+    /// Rounding error is carried over between frames so the total length
+    /// stays accurate even though GIF delays are whole centiseconds.
+    fn delays(&self, unit: f64) -> Vec<u16> {
+        let mut elapsed = 0.0;
+        let mut emitted = 0u64;
+        self.frames
+            .iter()
+            .map(|f| {
+                elapsed += f.repeats as f64 * FRAME_MS * unit / 1000.0;
+                let target = (elapsed.round() as u64).max(emitted + 1);
+                let delay = (target - emitted).min(u16::MAX as u64);
+                emitted += delay;
+                delay as u16
+            })
+            .collect()
+    }
+
+    /// Write the recording to [`SCREENSHOT_DIR`] as a timestamped file
+    pub fn save_to_dir(
+        &self,
+        rom_name: &str,
+        format: RecordFormat,
+        palette: &[u32; 4],
+    ) -> Result<PathBuf, String> {
+        let path = output_path(rom_name, format.extension())?;
+        self.save(&path, format, palette)?;
+        Ok(path)
+    }
+
+    /// Write the recording to `path`
+    pub fn save(&self, path: &Path, format: RecordFormat, palette: &[u32; 4]) -> Result<(), String> {
+        if self.frames.is_empty() {
+            return Err("Nothing recorded".to_string());
+        }
+        let rgb: Vec<u8> = palette
+            .iter()
+            .flat_map(|&c| [(c >> 16) as u8, (c >> 8) as u8, c as u8])
+            .collect();
+        let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let out = BufWriter::new(file);
+
+        match format {
+            RecordFormat::Gif => self.write_gif(out, &rgb),
+            RecordFormat::Apng => self.write_apng(out, &rgb),
+        }
+    }
+
+    fn write_gif(&self, out: BufWriter<File>, rgb: &[u8]) -> Result<(), String> {
+        let err = |e: gif::EncodingError| format!("GIF error: {}", e);
+        let mut encoder =
+            gif::Encoder::new(out, SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, rgb).map_err(err)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(err)?;
+
+        for (frame, delay) in self.frames.iter().zip(self.delays(100.0)) {
+            let gif_frame = gif::Frame {
+                width: SCREEN_WIDTH as u16,
+                height: SCREEN_HEIGHT as u16,
+                delay,
+                buffer: Cow::Borrowed(&frame.pixels),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&gif_frame).map_err(err)?;
+        }
+        Ok(())
+    }
+
+    fn write_apng(&self, out: BufWriter<File>, rgb: &[u8]) -> Result<(), String> {
+        let err = |e: png::EncodingError| format!("PNG error: {}", e);
+        let mut encoder = png::Encoder::new(out, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(rgb.to_vec());
+        encoder.set_animated(self.frames.len() as u32, 0).map_err(err)?;
+        let mut writer = encoder.write_header().map_err(err)?;
+
+        for (frame, delay) in self.frames.iter().zip(self.delays(1000.0)) {
+            writer.set_frame_delay(delay, 1000).map_err(err)?;
+            writer.write_image_data(&frame.pixels).map_err(err)?;
+        }
+        writer.finish().map_err(err)
+    }
+}
+
+/// Create [`SCREENSHOT_DIR`] and pick a timestamped file name in it
+fn output_path(rom_name: &str, ext: &str) -> Result<PathBuf, String> {
+    let dir = Path::new(SCREENSHOT_DIR);
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(unique_path(dir, &format!("{}-{}", rom_name, timestamp()), ext))
+}
+
+/// Pick `dir/stem.ext`, adding a numeric suffix if that file already exists
+pub fn unique_path(dir: &Path, stem: &str, ext: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", stem, ext));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", stem, n, ext));
+        n += 1;
+    }
+    path
+}
+
+/// Current UTC time formatted as `YYYYMMDD-HHMMSS`
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format_timestamp(secs)
+}
+
+/// Format seconds since the Unix epoch as `YYYYMMDD-HHMMSS` (UTC)
+fn format_timestamp(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = civil_from_secs(secs);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Format seconds since the Unix epoch as `YYYY-MM-DD HH:MM` (UTC) for display
+pub fn format_datetime(secs: u64) -> String {
+    let (year, month, day, hour, minute, _) = civil_from_secs(secs);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, hour, minute)
+}
+
+/// Split seconds since the Unix epoch into UTC (year, month, day, hour, minute, second)
+fn civil_from_secs(secs: u64) -> (i64, i64, i64, u64, u64, u64) {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, rem / 3600, (rem / 60) % 60, rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorder_deduplicates_and_trims() {
+        let a = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let b = vec![1u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        // One second holds 59 whole frames
+        let mut rec = Recorder::new(1.0);
+        for _ in 0..30 {
+            rec.push_frame(&a);
+        }
+        for _ in 0..40 {
+            rec.push_frame(&b);
+        }
+        assert_eq!(rec.unique_frames(), 2);
+        assert_eq!(rec.total_frames, rec.max_frames);
+        assert!((rec.duration_secs() - 1.0).abs() < 0.02);
+
+        // Centisecond delays add up to the recorded length
+        let total: u32 = rec.delays(100.0).iter().map(|&d| d as u32).sum();
+        assert_eq!(total, (rec.duration_secs() * 100.0).round() as u32);
+    }
+
+    #[test]
+    fn recorder_writes_gif_and_apng() {
+        let mut rec = Recorder::new(1.0);
+        for i in 0..10u8 {
+            rec.push_frame(&vec![i % 4; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        }
+        let palette = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+        let dir = std::env::temp_dir();
+        for (format, magic) in [(RecordFormat::Gif, &b"GIF89a"[..]), (RecordFormat::Apng, &b"\x89PNG"[..])] {
+            let path = dir.join(format!("gb3000-recorder-test.{}", format.extension()));
+            rec.save(&path, format, &palette).unwrap();
+            let data = fs::read(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert!(data.starts_with(magic));
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let rgba: Vec<u8> = (0..6 * 4).map(|i| (i * 10) as u8).collect();
+        let path = std::env::temp_dir().join("gb3000-png-test.png");
+        write_png(&path, &rgba, 3, 2).unwrap();
+        let decoded = read_png(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(decoded, Ok((rgba, 3, 2)));
+    }
+
+    #[test]
+    fn timestamp_formatting() {
+        assert_eq!(format_timestamp(0), "19700101-000000");
+        assert_eq!(format_timestamp(951_782_400), "20000229-000000");
+        assert_eq!(format_timestamp(1_700_000_000), "20231114-221320");
+        assert_eq!(format_datetime(1_700_000_000), "2023-11-14 22:13");
+    }
+}
@@ -0,0 +1,793 @@
+//! Frontend settings file
+//!
+//! Settings live in `config.toml` under the user's config directory
+//! (`~/.config/gb3000` on Linux). Only the subset of TOML the settings
+//! need is supported: `[table]` and `[[array]]` headers, `#` comments and
+//! `key = value` lines with string, integer, float and boolean values.
+
+use crate::ui::RecentRom;
+use gb3000::{palettes, AudioOptions, Button, GbModel};
+use std::collections::BTreeMap;
+use minifb::Key;
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of ROMs kept in the recent list
+pub const MAX_RECENT_ROMS: usize = 5;
+
+/// Selectable palettes, in menu order
+pub const PALETTES: [(&str, [u32; 4]); 5] = [
+    ("Grayscale", palettes::GRAYSCALE),
+    ("DMG Green", palettes::DMG_GREEN),
+    ("Pocket", palettes::POCKET),
+    ("Light", palettes::LIGHT),
+    ("SGB", palettes::SGB),
+];
+
+/// Selectable window scales (multiples of the filtered 640x576 output)
+pub const WINDOW_SCALES: [u8; 2] = [1, 2];
+
+/// Selectable audio buffer sizes in frames per device callback, 0 being
+/// the device's default; larger buffers trade latency for fewer dropouts
+pub const AUDIO_BUFFER_SIZES: [u32; 5] = [0, 256, 512, 1024, 2048];
+
+/// Selectable stereo separations in percent, hardware panning first and
+/// mono last
+pub const STEREO_SEPARATIONS: [u8; 5] = [100, 75, 50, 25, 0];
+
+/// Fullscreen window size when the settings file doesn't give one
+pub const DEFAULT_FULLSCREEN_SIZE: (usize, usize) = (1920, 1080);
+
+/// Models a game can be forced to, in menu order
+pub const MODELS: [GbModel; 6] = [
+    GbModel::Dmg0,
+    GbModel::DmgABC,
+    GbModel::Mgb,
+    GbModel::Sgb,
+    GbModel::Sgb2,
+    GbModel::Cgb,
+];
+
+/// Game Boy buttons in settings order, with their config names
+pub const BUTTONS: [(Button, &str); 8] = [
+    (Button::Up, "up"),
+    (Button::Down, "down"),
+    (Button::Left, "left"),
+    (Button::Right, "right"),
+    (Button::A, "a"),
+    (Button::B, "b"),
+    (Button::Start, "start"),
+    (Button::Select, "select"),
+];
+
+/// Buttons with a turbo key, which repeatedly presses them while held
+pub const TURBO_BUTTONS: [(Button, &str); 2] = [(Button::A, "turbo_a"), (Button::B, "turbo_b")];
+
+/// Selectable turbo rates in presses per second, each dividing 60 frames
+/// into equal on and off halves
+pub const TURBO_RATES: [u8; 5] = [30, 15, 10, 6, 5];
+
+/// Keyboard key for each entry in [`BUTTONS`], then for each entry in
+/// [`TURBO_BUTTONS`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBindings(pub [Key; BUTTONS.len() + TURBO_BUTTONS.len()]);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings([
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Z,
+            Key::X,
+            Key::Enter,
+            Key::Space,
+            Key::A,
+            Key::S,
+        ])
+    }
+}
+
+impl KeyBindings {
+    /// Player 2's defaults, clear of player 1's keys and the hotkeys
+    pub fn player_2() -> Self {
+        KeyBindings([
+            Key::I,
+            Key::K,
+            Key::J,
+            Key::L,
+            Key::G,
+            Key::F,
+            Key::T,
+            Key::R,
+            Key::V,
+            Key::C,
+        ])
+    }
+
+    fn parse(&mut self, table: &Table) {
+        for (i, (_, name)) in BUTTONS.iter().chain(&TURBO_BUTTONS).enumerate() {
+            if let Some(key) = table.string(name).and_then(parse_key) {
+                self.0[i] = key;
+            }
+        }
+    }
+
+    fn to_toml(self) -> String {
+        let mut out = String::new();
+        for (i, (_, name)) in BUTTONS.iter().chain(&TURBO_BUTTONS).enumerate() {
+            out += &format!("{} = {}\n", name, quote(&key_name(self.0[i])));
+        }
+        out
+    }
+}
+
+/// Level and stereo position of one console's sound when two are played
+/// side by side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsoleMix {
+    /// 0.0 to 1.0, under the master volume
+    pub volume: f32,
+    /// -1.0 (left only) to 1.0 (right only)
+    pub pan: f32,
+}
+
+/// Player 1 leaning left and player 2 right, quiet enough that both
+/// together don't clip
+pub const DEFAULT_CONSOLE_MIX: [ConsoleMix; 2] =
+    [ConsoleMix { volume: 0.7, pan: -0.5 }, ConsoleMix { volume: 0.7, pan: 0.5 }];
+
+/// What sits in the link port when no second console does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MobileSetting {
+    /// Nothing
+    #[default]
+    Off,
+    /// A Mobile Adapter GB on the offline mock network
+    Mock,
+    /// A Mobile Adapter GB reaching servers over the host's network
+    Network,
+}
+
+impl MobileSetting {
+    const NAMES: [(MobileSetting, &'static str); 3] =
+        [(MobileSetting::Off, "off"), (MobileSetting::Mock, "mock"), (MobileSetting::Network, "network")];
+
+    fn name(self) -> &'static str {
+        Self::NAMES.iter().find(|(m, _)| *m == self).map_or("off", |(_, name)| name)
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::NAMES.iter().find(|(_, n)| *n == name).map(|&(m, _)| m)
+    }
+}
+
+/// Settings remembered for one game
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameSettings {
+    /// Title from the ROM header, to make the file readable
+    pub title: String,
+    /// Last selected save state slot
+    pub state_slot: u8,
+    /// Palette picked while playing, overriding the global one
+    pub palette: Option<usize>,
+    /// Hardware model to reset into instead of the default
+    pub model: Option<GbModel>,
+}
+
+/// Persistent frontend settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub keys: KeyBindings,
+    /// Keys for the second console when two are played side by side
+    pub keys2: KeyBindings,
+    /// Index into [`PALETTES`]
+    pub palette: usize,
+    /// Master volume, 0.0 to 1.0
+    pub volume: f32,
+    /// Silence output without forgetting the volume (M key)
+    pub muted: bool,
+    /// Name of the output device, None for the system default
+    pub audio_device: Option<String>,
+    /// Entry of [`AUDIO_BUFFER_SIZES`]
+    pub audio_buffer: u32,
+    /// Entry of [`STEREO_SEPARATIONS`]
+    pub stereo_separation: u8,
+    /// Whether the output capacitor filter is on
+    pub high_pass: bool,
+    /// Player 1's and player 2's sound with two consoles side by side
+    pub console_mix: [ConsoleMix; 2],
+    /// Entry of [`TURBO_RATES`], how fast turbo keys press their button
+    pub turbo_rate: u8,
+    /// Soft reset when A, B, Start and Select are held together
+    pub reset_combo: bool,
+    /// Mobile Adapter GB in the link port
+    pub mobile_adapter: MobileSetting,
+    /// Host names the network adapter resolves to fixed addresses
+    pub mobile_hosts: BTreeMap<String, [u8; 4]>,
+    /// Entry of [`WINDOW_SCALES`], the window size at startup
+    pub window_scale: u8,
+    /// Size of the borderless fullscreen window; minifb can't ask the
+    /// monitor, so this has to match the display
+    pub fullscreen_size: (usize, usize),
+    /// Most recently loaded ROMs, newest first
+    pub recent_roms: Vec<RecentRom>,
+    /// Per-game settings keyed by [`header_hash`]
+    pub games: BTreeMap<u32, GameSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keys: KeyBindings::default(),
+            keys2: KeyBindings::player_2(),
+            palette: 0,
+            volume: 1.0,
+            muted: false,
+            audio_device: None,
+            audio_buffer: 0,
+            stereo_separation: 100,
+            high_pass: true,
+            console_mix: DEFAULT_CONSOLE_MIX,
+            turbo_rate: 15,
+            reset_combo: false,
+            mobile_adapter: MobileSetting::Off,
+            mobile_hosts: BTreeMap::new(),
+            window_scale: 1,
+            fullscreen_size: DEFAULT_FULLSCREEN_SIZE,
+            recent_roms: Vec::new(),
+            games: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Volume samples are scaled by, zero while muted
+    pub fn output_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.volume
+        }
+    }
+
+    /// Mixing options for the emulator
+    pub fn audio_options(&self) -> AudioOptions {
+        AudioOptions { stereo_separation: self.stereo_separation, high_pass: self.high_pass }
+    }
+
+    /// Location of the settings file, if a config directory can be found
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("gb3000").join("config.toml"))
+    }
+
+    /// Load the settings file, falling back to defaults
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else { return Self::default() };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write the settings file, creating its directory if needed
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No config directory found")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, self.to_toml()).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Parse settings, keeping the default for anything missing or invalid
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        for table in parse_tables(text) {
+            match table.name.as_str() {
+                "video" => {
+                    if let Some(name) = table.string("palette") {
+                        if let Some(i) = PALETTES.iter().position(|(n, _)| *n == name) {
+                            config.palette = i;
+                        }
+                    }
+                    if let Some(scale) = table.integer("window_scale") {
+                        if let Some(&s) = WINDOW_SCALES.iter().find(|&&s| s as i64 == scale) {
+                            config.window_scale = s;
+                        }
+                    }
+                    if let Some(size) = table.string("fullscreen_size").and_then(parse_size) {
+                        config.fullscreen_size = size;
+                    }
+                }
+                "audio" => {
+                    if let Some(volume) = table.float("volume") {
+                        config.volume = (volume as f32).clamp(0.0, 1.0);
+                    }
+                    if let Some(muted) = table.bool("muted") {
+                        config.muted = muted;
+                    }
+                    config.audio_device = table.string("device").map(str::to_string);
+                    if let Some(size) = table.integer("buffer_size") {
+                        if let Some(&s) = AUDIO_BUFFER_SIZES.iter().find(|&&s| s as i64 == size) {
+                            config.audio_buffer = s;
+                        }
+                    }
+                    if let Some(separation) = table.integer("stereo_separation") {
+                        if let Some(&s) = STEREO_SEPARATIONS.iter().find(|&&s| s as i64 == separation) {
+                            config.stereo_separation = s;
+                        }
+                    }
+                    if let Some(high_pass) = table.bool("high_pass") {
+                        config.high_pass = high_pass;
+                    }
+                    for (player, mix) in config.console_mix.iter_mut().enumerate() {
+                        if let Some(volume) = table.float(&format!("player{}_volume", player + 1)) {
+                            mix.volume = (volume as f32).clamp(0.0, 1.0);
+                        }
+                        if let Some(pan) = table.float(&format!("player{}_pan", player + 1)) {
+                            mix.pan = (pan as f32).clamp(-1.0, 1.0);
+                        }
+                    }
+                }
+                "keys" => {
+                    config.keys.parse(&table);
+                    if let Some(rate) = table.integer("turbo_rate") {
+                        if let Some(&r) = TURBO_RATES.iter().find(|&&r| r as i64 == rate) {
+                            config.turbo_rate = r;
+                        }
+                    }
+                    if let Some(reset_combo) = table.bool("reset_combo") {
+                        config.reset_combo = reset_combo;
+                    }
+                }
+                "player2_keys" => config.keys2.parse(&table),
+                "link" => {
+                    if let Some(setting) = table.string("mobile_adapter").and_then(MobileSetting::parse) {
+                        config.mobile_adapter = setting;
+                    }
+                }
+                "mobile_hosts" => {
+                    for (host, value) in &table.entries {
+                        if let Value::String(address) = value {
+                            if let Ok(ip) = address.parse::<std::net::Ipv4Addr>() {
+                                config.mobile_hosts.insert(host.clone(), ip.octets());
+                            }
+                        }
+                    }
+                }
+                "recent_rom" if config.recent_roms.len() < MAX_RECENT_ROMS => {
+                    if let Some(path) = table.string("path") {
+                        config.recent_roms.push(RecentRom {
+                            path: PathBuf::from(path),
+                            title: table.string("title").unwrap_or_default().to_string(),
+                        });
+                    }
+                }
+                "game" => {
+                    let Some(hash) = table.string("hash").and_then(|h| u32::from_str_radix(h, 16).ok()) else {
+                        continue;
+                    };
+                    let game = GameSettings {
+                        title: table.string("title").unwrap_or_default().to_string(),
+                        state_slot: table.integer("state_slot").and_then(|s| u8::try_from(s).ok()).unwrap_or(0),
+                        palette: table
+                            .string("palette")
+                            .and_then(|name| PALETTES.iter().position(|(n, _)| *n == name)),
+                        model: table
+                            .string("model")
+                            .and_then(|name| MODELS.iter().copied().find(|m| m.to_string() == name)),
+                    };
+                    config.games.insert(hash, game);
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+
+    /// Serialize the settings as TOML
+    pub fn to_toml(&self) -> String {
+        let mut out = String::from("# GB3000 settings\n\n[video]\n");
+        out += &format!("palette = {}\n", quote(self.palette_name()));
+        out += &format!("window_scale = {}\n", self.window_scale);
+        let (width, height) = self.fullscreen_size;
+        out += &format!("fullscreen_size = \"{}x{}\"\n", width, height);
+        out += &format!("\n[audio]\nvolume = {:?}\nmuted = {}\n", self.volume, self.muted);
+        if let Some(device) = &self.audio_device {
+            out += &format!("device = {}\n", quote(device));
+        }
+        out += &format!("buffer_size = {}\n", self.audio_buffer);
+        out += &format!("stereo_separation = {}\n", self.stereo_separation);
+        out += &format!("high_pass = {}\n", self.high_pass);
+        for (player, mix) in self.console_mix.iter().enumerate() {
+            out += &format!("player{}_volume = {:?}\n", player + 1, mix.volume);
+            out += &format!("player{}_pan = {:?}\n", player + 1, mix.pan);
+        }
+        out += "\n[keys]\n";
+        out += &self.keys.to_toml();
+        out += &format!("turbo_rate = {}\n", self.turbo_rate);
+        out += &format!("reset_combo = {}\n", self.reset_combo);
+        out += "\n[player2_keys]\n";
+        out += &self.keys2.to_toml();
+        out += &format!("\n[link]\nmobile_adapter = {}\n", quote(self.mobile_adapter.name()));
+        if !self.mobile_hosts.is_empty() {
+            out += "\n[mobile_hosts]\n";
+            for (host, [a, b, c, d]) in &self.mobile_hosts {
+                out += &format!("{} = \"{}.{}.{}.{}\"\n", quote(host), a, b, c, d);
+            }
+        }
+        for rom in &self.recent_roms {
+            out += "\n[[recent_rom]]\n";
+            out += &format!("path = {}\n", quote(&rom.path.to_string_lossy()));
+            out += &format!("title = {}\n", quote(&rom.title));
+        }
+        for (hash, game) in &self.games {
+            out += &format!("\n[[game]]\nhash = \"{:08X}\"\n", hash);
+            out += &format!("title = {}\n", quote(&game.title));
+            out += &format!("state_slot = {}\n", game.state_slot);
+            if let Some(palette) = game.palette {
+                out += &format!("palette = {}\n", quote(PALETTES[palette % PALETTES.len()].0));
+            }
+            if let Some(model) = game.model {
+                out += &format!("model = {}\n", quote(&model.to_string()));
+            }
+        }
+        out
+    }
+
+    /// Settings for a game, creating a default entry if needed
+    pub fn game_mut(&mut self, hash: u32) -> &mut GameSettings {
+        self.games.entry(hash).or_default()
+    }
+
+    /// Name of the selected palette
+    pub fn palette_name(&self) -> &'static str {
+        PALETTES[self.palette % PALETTES.len()].0
+    }
+}
+
+/// Identify a game by its cartridge header (title, licensee, type and
+/// checksums), so settings follow it across renamed or moved files
+pub fn header_hash(rom: &[u8]) -> u32 {
+    let header = rom.get(0x134..0x150).unwrap_or(rom);
+    header.iter().fold(0x811C9DC5u32, |hash, &b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+/// Display name of a hardware model
+pub fn model_name(model: GbModel) -> &'static str {
+    match model {
+        GbModel::Dmg0 => "DMG-0",
+        GbModel::DmgABC => "DMG",
+        GbModel::Mgb => "Pocket",
+        GbModel::Sgb => "SGB",
+        GbModel::Sgb2 => "SGB2",
+        GbModel::Cgb => "CGB",
+    }
+}
+
+/// Base config directory: `%APPDATA%` on Windows, otherwise
+/// `$XDG_CONFIG_HOME` or `~/.config`
+fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return std::env::var_os("APPDATA").map(PathBuf::from);
+    }
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Display and config name of a key
+pub fn key_name(key: Key) -> String {
+    format!("{:?}", key)
+}
+
+/// Whether a key can be bound to a button (hotkeys like Tab and the
+/// function keys are reserved)
+pub fn is_bindable(key: Key) -> bool {
+    BINDABLE_KEYS.contains(&key)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    BINDABLE_KEYS.iter().copied().find(|&k| key_name(k) == name)
+}
+
+/// Parse a `WIDTHxHEIGHT` window size
+fn parse_size(text: &str) -> Option<(usize, usize)> {
+    let (width, height) = text.split_once('x')?;
+    let size = (width.trim().parse().ok()?, height.trim().parse().ok()?);
+    (size.0 > 0 && size.1 > 0).then_some(size)
+}
+
+/// Keys that can be bound to buttons
+const BINDABLE_KEYS: [Key; 70] = [
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::Up, Key::Down, Key::Left, Key::Right,
+    Key::Space, Key::Enter,
+    Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl,
+    Key::LeftAlt, Key::RightAlt,
+    Key::Comma, Key::Period, Key::Slash, Key::Semicolon, Key::Apostrophe,
+    Key::LeftBracket, Key::RightBracket, Key::Backslash, Key::Minus, Key::Equal,
+    Key::NumPadEnter, Key::Backquote,
+];
+
+// ============================================================================
+// TOML subset
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+/// One `[table]` or `[[array]]` entry and its keys
+#[derive(Debug, Default)]
+struct Table {
+    name: String,
+    entries: Vec<(String, Value)>,
+}
+
+impl Table {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn string(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn integer(&self, key: &str) -> Option<i64> {
+        match *self.get(key)? {
+            Value::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    fn float(&self, key: &str) -> Option<f64> {
+        match *self.get(key)? {
+            Value::Float(f) => Some(f),
+            Value::Integer(i) => Some(i as f64),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, key: &str) -> Option<bool> {
+        match *self.get(key)? {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+/// Split a document into tables; keys before any header go in a table
+/// named "". Lines that don't parse are skipped.
+fn parse_tables(text: &str) -> Vec<Table> {
+    let mut tables = vec![Table::default()];
+    for line in text.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]")) {
+            tables.push(Table { name: name.trim().to_string(), entries: Vec::new() });
+        } else if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            tables.push(Table { name: name.trim().to_string(), entries: Vec::new() });
+        } else if let Some((key, value)) = line.split_once('=') {
+            if let Some(value) = parse_value(value.trim()) {
+                let table = tables.last_mut().expect("tables is never empty");
+                table.entries.push((key.trim().trim_matches('"').to_string(), value));
+            }
+        }
+    }
+    tables
+}
+
+/// Remove a trailing `#` comment that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(s) = text.strip_prefix('\'') {
+        return s.strip_suffix('\'').map(|s| Value::String(s.to_string()));
+    }
+    if let Some(s) = text.strip_prefix('"') {
+        return unescape(s.strip_suffix('"')?).map(Value::String);
+    }
+    match text {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    let digits = text.replace('_', "");
+    if let Ok(i) = digits.parse() {
+        return Some(Value::Integer(i));
+    }
+    digits.parse().ok().map(Value::Float)
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            'r' => out.push('\r'),
+            '"' => out.push('"'),
+            '\\' => out.push('\\'),
+            'u' => {
+                let hex: String = chars.by_ref().take(4).collect();
+                out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Quote a string as a TOML basic string
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_roundtrips_through_toml() {
+        let config = Config {
+            keys: KeyBindings([
+                Key::W, Key::S, Key::A, Key::D, Key::K, Key::J, Key::Enter, Key::RightShift,
+                Key::L, Key::H,
+            ]),
+            keys2: KeyBindings([
+                Key::NumPad8, Key::NumPad2, Key::NumPad4, Key::NumPad6, Key::NumPad1, Key::NumPad0,
+                Key::NumPadEnter, Key::NumPad5, Key::NumPad3, Key::NumPad7,
+            ]),
+            palette: 2,
+            volume: 0.35,
+            muted: true,
+            audio_device: Some("USB Audio DAC".to_string()),
+            audio_buffer: 512,
+            stereo_separation: 25,
+            high_pass: false,
+            console_mix: [ConsoleMix { volume: 0.5, pan: 0.0 }, ConsoleMix { volume: 1.0, pan: 1.0 }],
+            turbo_rate: 6,
+            reset_combo: true,
+            mobile_adapter: MobileSetting::Network,
+            mobile_hosts: BTreeMap::from([("gameboy.datacenter.ne.jp".to_string(), [192, 0, 2, 7])]),
+            window_scale: 2,
+            fullscreen_size: (2560, 1440),
+            recent_roms: vec![RecentRom {
+                path: PathBuf::from("C:\\Games\\\"Zelda\".gb"),
+                title: "ZELDA".to_string(),
+            }],
+            games: BTreeMap::from([(
+                0x00C0FFEE,
+                GameSettings {
+                    title: "ZELDA".to_string(),
+                    state_slot: 7,
+                    palette: Some(3),
+                    model: Some(GbModel::Mgb),
+                },
+            )]),
+        };
+        let parsed = Config::parse(&config.to_toml());
+        assert_eq!(parsed.keys, config.keys);
+        assert_eq!(parsed.keys2, config.keys2);
+        assert_eq!(parsed.palette, 2);
+        assert_eq!(parsed.volume, 0.35);
+        assert!(parsed.muted);
+        assert_eq!(parsed.audio_device.as_deref(), Some("USB Audio DAC"));
+        assert_eq!(parsed.audio_buffer, 512);
+        assert_eq!(parsed.audio_options(), AudioOptions { stereo_separation: 25, high_pass: false });
+        assert_eq!(parsed.console_mix, config.console_mix);
+        assert_eq!(parsed.turbo_rate, 6);
+        assert!(parsed.reset_combo);
+        assert_eq!(parsed.mobile_adapter, MobileSetting::Network);
+        assert_eq!(parsed.mobile_hosts, config.mobile_hosts);
+        assert_eq!(parsed.window_scale, 2);
+        assert_eq!(parsed.fullscreen_size, (2560, 1440));
+        assert_eq!(parsed.recent_roms.len(), 1);
+        assert_eq!(parsed.recent_roms[0].path, config.recent_roms[0].path);
+        assert_eq!(parsed.recent_roms[0].title, "ZELDA");
+        assert_eq!(parsed.games, config.games);
+    }
+
+    #[test]
+    fn invalid_entries_keep_defaults() {
+        let text = "
+            # hand-edited
+            [video]
+            palette = 'Pocket'  # comment after a value
+            window_scale = 7
+            fullscreen_size = \"1920x0\"
+
+            [audio]
+            volume = 3
+            muted = 1
+            buffer_size = 300
+            stereo_separation = 30
+            player2_pan = -4
+
+            [keys]
+            a = \"NotAKey\"
+            b = \"Q\"
+            turbo_rate = 12
+            reset_combo = \"yes\"
+
+            [player2_keys]
+            up = \"Escape\"
+
+            [link]
+            mobile_adapter = \"bluetooth\"
+
+            [mobile_hosts]
+            example.com = \"300.1.2.3\"
+        ";
+        let config = Config::parse(text);
+        assert_eq!(config.palette_name(), "Pocket");
+        assert_eq!(config.window_scale, 1);
+        assert_eq!(config.fullscreen_size, DEFAULT_FULLSCREEN_SIZE);
+        assert_eq!(config.volume, 1.0);
+        assert!(!config.muted);
+        assert_eq!(config.audio_device, None);
+        assert_eq!(config.audio_buffer, 0);
+        assert_eq!(config.stereo_separation, 100);
+        assert_eq!(config.keys.0[4], Key::Z);
+        assert_eq!(config.keys.0[5], Key::Q);
+        assert_eq!(config.turbo_rate, 15);
+        assert!(!config.reset_combo);
+        assert_eq!(config.console_mix[1], ConsoleMix { volume: 0.7, pan: -1.0 });
+        assert_eq!(config.keys2, KeyBindings::player_2());
+        assert_eq!(config.mobile_adapter, MobileSetting::Off);
+        assert!(config.mobile_hosts.is_empty());
+    }
+}
@@ -1,11 +1,14 @@
-/// CPU core for the Game Boy emulator.
-///
-/// This implements the Sharp LR35902 processor with all opcodes,
-/// proper flag handling, and interrupt support.
-/// 
-/// This version supports M-cycle accurate execution for precise timing.
+//! CPU core for the Game Boy emulator.
+//!
+//! This implements the Sharp LR35902 processor with all opcodes,
+//! proper flag handling, and interrupt support.
+//! 
+//! This version supports M-cycle accurate execution for precise timing.
+
+use crate::memory::{io, Memory};
+use crate::state::{StateError, StateReader, StateWriter};
 
-use crate::memory::Memory;
+mod ops;
 
 // Flag bit positions in the F register
 const FLAG_Z: u8 = 0b1000_0000; // Zero flag
@@ -45,6 +48,26 @@ impl std::fmt::Display for GbModel {
 }
 
 impl GbModel {
+    /// Every model, in the order used by save states
+    pub const ALL: [GbModel; 6] = [
+        GbModel::Dmg0,
+        GbModel::DmgABC,
+        GbModel::Mgb,
+        GbModel::Sgb,
+        GbModel::Sgb2,
+        GbModel::Cgb,
+    ];
+
+    /// Whether this is a Game Boy Color
+    pub fn is_cgb(self) -> bool {
+        self == GbModel::Cgb
+    }
+
+    /// Whether this is a Super Game Boy
+    pub fn is_sgb(self) -> bool {
+        matches!(self, GbModel::Sgb | GbModel::Sgb2)
+    }
+
     /// Detect hardware model from ROM filename/path
     pub fn from_filename(filename: &str) -> Self {
         let lower = filename.to_lowercase();
@@ -76,7 +99,50 @@ impl GbModel {
     }
 }
 
-#[derive(Debug)]
+/// Snapshot of the CPU registers, for debuggers and test harnesses
+///
+/// Returned by [`Emulator::cpu_state`](crate::Emulator::cpu_state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuState {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable
+    pub ime: bool,
+    /// Waiting in HALT for an interrupt
+    pub halted: bool,
+    /// Waiting in STOP for a button press
+    pub stopped: bool,
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub rom_bank: u16,
+}
+
+impl CpuState {
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Cpu {
     // 8-bit registers
     pub a: u8,
@@ -92,7 +158,9 @@ pub struct Cpu {
     pub pc: u16,
     // Interrupt master enable
     pub ime: bool,
-    // Pending IME enable (for EI delay)
+    // Pending IME enable (for EI delay): set by EI, turned into IME as the
+    // next instruction starts, so interrupts are taken after it at the
+    // earliest. DI and RETI cancel it.
     pub ime_pending: bool,
     // CPU halted state
     pub halted: bool,
@@ -213,6 +281,40 @@ impl Cpu {
         self.halt_bug = false;
     }
 
+    /// Serialize CPU registers and execution state
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for reg in [self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l] {
+            w.u8(reg);
+        }
+        w.u16(self.sp);
+        w.u16(self.pc);
+        w.bool(self.ime);
+        w.bool(self.ime_pending);
+        w.bool(self.halted);
+        w.bool(self.stopped);
+        w.bool(self.halt_bug);
+    }
+
+    /// Restore CPU registers and execution state
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.a = r.u8()?;
+        self.f = r.u8()? & 0xF0;
+        self.b = r.u8()?;
+        self.c = r.u8()?;
+        self.d = r.u8()?;
+        self.e = r.u8()?;
+        self.h = r.u8()?;
+        self.l = r.u8()?;
+        self.sp = r.u16()?;
+        self.pc = r.u16()?;
+        self.ime = r.bool()?;
+        self.ime_pending = r.bool()?;
+        self.halted = r.bool()?;
+        self.stopped = r.bool()?;
+        self.halt_bug = r.bool()?;
+        Ok(())
+    }
+
     // ========== Flag helpers ==========
 
     #[inline]
@@ -324,30 +426,81 @@ impl Cpu {
         val
     }
 
-    #[inline]
-    fn fetch_word(&mut self, memory: &Memory) -> u16 {
-        let lo = self.fetch_byte(memory) as u16;
-        let hi = self.fetch_byte(memory) as u16;
-        (hi << 8) | lo
-    }
+    // ========== Interrupts ==========
 
-    // ========== Stack operations ==========
+    /// Wake from HALT and dispatch a pending interrupt, ticking each
+    /// M-cycle it takes
+    ///
+    /// Leaving HALT takes one M-cycle once an enabled interrupt is
+    /// requested, whatever IME is. Dispatch then takes five: two internal,
+    /// two pushing PC and one jumping to the vector. The vector is picked
+    /// between the two pushes, so a push that overwrites IE can change it,
+    /// or cancel the dispatch and jump to 0x0000 instead.
+    /// Returns the T-cycles taken, 0 if nothing was pending.
+    pub fn service_interrupts<F>(&mut self, memory: &mut Memory, tick: &mut F) -> u32
+    where
+        F: FnMut(&mut Memory, u32),
+    {
+        if memory.pending_interrupts() == 0 {
+            return 0;
+        }
+        let mut cycles = 0;
+        if self.halted {
+            self.halted = false;
+            tick(memory, 4);
+            cycles += 4;
+        }
+        if !self.ime {
+            return cycles;
+        }
 
-    #[inline]
-    fn push(&mut self, memory: &mut Memory, val: u16) {
+        self.ime = false;
+        tick(memory, 4);
+        tick(memory, 4);
         self.sp = self.sp.wrapping_sub(1);
-        memory.write_byte(self.sp, (val >> 8) as u8);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        let pending = memory.pending_interrupts();
         self.sp = self.sp.wrapping_sub(1);
-        memory.write_byte(self.sp, val as u8);
-    }
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = if pending == 0 {
+            0x0000
+        } else {
+            let bit = pending.trailing_zeros();
+            memory.clear_interrupt(1 << bit);
+            0x0040 + 8 * bit as u16
+        };
+        tick(memory, 4);
+        cycles + 20
+    }
+
+    // ========== STOP ==========
+
+    /// Execute STOP, whose effect depends on the joypad, pending
+    /// interrupts and an armed CGB speed switch
+    ///
+    /// - With a selected button held, STOP doesn't stop: it acts as HALT,
+    ///   or as a NOP when an interrupt is already pending. DIV keeps running.
+    /// - Otherwise DIV is reset, and the armed speed switch takes place or
+    ///   the CPU enters STOP mode until a selected button is pressed.
+    ///
+    /// The byte after STOP is skipped unless an interrupt is pending.
+    fn stop(&mut self, memory: &mut Memory) {
+        let pending = memory.pending_interrupts() != 0;
+        if !pending {
+            self.pc = self.pc.wrapping_add(1);
+        }
 
-    #[inline]
-    fn pop(&mut self, memory: &Memory) -> u16 {
-        let lo = memory.read_byte(self.sp) as u16;
-        self.sp = self.sp.wrapping_add(1);
-        let hi = memory.read_byte(self.sp) as u16;
-        self.sp = self.sp.wrapping_add(1);
-        (hi << 8) | lo
+        if memory.joypad_lines() != 0x0F {
+            self.halted = !pending;
+            return;
+        }
+
+        memory.write_byte(io::DIV, 0);
+        if !memory.switch_speed() {
+            self.stopped = true;
+        }
     }
 
     // ========== ALU operations ==========
@@ -512,7 +665,7 @@ impl Cpu {
     }
 
     fn alu_swap(&mut self, val: u8) -> u8 {
-        let result = (val >> 4) | (val << 4);
+        let result = val.rotate_left(4);
         self.set_flags(result == 0, false, false, false);
         result
     }
@@ -565,2461 +718,236 @@ impl Cpu {
 
     // ========== Main execution ==========
 
-    /// Executes a single CPU step (fetch/decode/execute cycle).
+    /// Executes a single instruction with nothing to tick between its
+    /// M-cycles, for running the CPU on its own.
     /// Returns the number of T-cycles consumed.
     pub fn step(&mut self, memory: &mut Memory) -> u32 {
+        self.step_mcycle(memory, |_, _| {})
+    }
+
+    /// Executes a single CPU step with M-cycle accurate timing.
+    /// The callback is called after each M-cycle (4 T-cycles) allowing
+    /// other components to be updated between memory accesses.
+    /// Returns the total number of T-cycles consumed.
+    pub fn step_mcycle<F>(&mut self, memory: &mut Memory, mut tick: F) -> u32
+    where
+        F: FnMut(&mut Memory, u32),
+    {
         // Handle pending IME enable (EI has a one-instruction delay)
         if self.ime_pending {
             self.ime = true;
             self.ime_pending = false;
         }
 
-        // If halted, just return 4 cycles
+        // If halted, tick for one M-cycle and return
         if self.halted {
+            tick(memory, 4);
             return 4;
         }
 
+        // Fetch opcode (1 M-cycle)
         let opcode = self.fetch_byte(memory);
+        tick(memory, 4);
 
-        match opcode {
-            // ==================== 0x0X ====================
-            0x00 => 4, // NOP
-
-            0x01 => { // LD BC, d16
-                let val = self.fetch_word(memory);
-                self.set_bc(val);
-                12
-            }
-
-            0x02 => { // LD (BC), A
-                self.write_byte(memory, self.bc(), self.a);
-                8
-            }
-
-            0x03 => { // INC BC
-                self.set_bc(self.bc().wrapping_add(1));
-                8
-            }
-
-            0x04 => { // INC B
-                self.b = self.alu_inc(self.b);
-                4
-            }
-
-            0x05 => { // DEC B
-                self.b = self.alu_dec(self.b);
-                4
-            }
-
-            0x06 => { // LD B, d8
-                self.b = self.fetch_byte(memory);
-                8
-            }
-
-            0x07 => { // RLCA
-                let carry = self.a >> 7;
-                self.a = (self.a << 1) | carry;
-                self.set_flags(false, false, false, carry != 0);
-                4
-            }
-
-            0x08 => { // LD (a16), SP
-                let addr = self.fetch_word(memory);
-                memory.write_byte(addr, self.sp as u8);
-                memory.write_byte(addr.wrapping_add(1), (self.sp >> 8) as u8);
-                20
-            }
-
-            0x09 => { // ADD HL, BC
-                self.alu_add_hl(self.bc());
-                8
-            }
-
-            0x0A => { // LD A, (BC)
-                self.a = self.read_byte(memory, self.bc());
-                8
-            }
-
-            0x0B => { // DEC BC
-                self.set_bc(self.bc().wrapping_sub(1));
-                8
-            }
-
-            0x0C => { // INC C
-                self.c = self.alu_inc(self.c);
-                4
-            }
-
-            0x0D => { // DEC C
-                self.c = self.alu_dec(self.c);
-                4
-            }
-
-            0x0E => { // LD C, d8
-                self.c = self.fetch_byte(memory);
-                8
-            }
-
-            0x0F => { // RRCA
-                let carry = self.a & 1;
-                self.a = (self.a >> 1) | (carry << 7);
-                self.set_flags(false, false, false, carry != 0);
-                4
-            }
-
-            // ==================== 0x1X ====================
-            0x10 => { // STOP
-                self.pc = self.pc.wrapping_add(1);
-                self.stopped = true;
-                4
-            }
-
-            0x11 => { // LD DE, d16
-                let val = self.fetch_word(memory);
-                self.set_de(val);
-                12
-            }
-
-            0x12 => { // LD (DE), A
-                self.write_byte(memory, self.de(), self.a);
-                8
-            }
-
-            0x13 => { // INC DE
-                self.set_de(self.de().wrapping_add(1));
-                8
-            }
-
-            0x14 => { // INC D
-                self.d = self.alu_inc(self.d);
-                4
-            }
-
-            0x15 => { // DEC D
-                self.d = self.alu_dec(self.d);
-                4
-            }
-
-            0x16 => { // LD D, d8
-                self.d = self.fetch_byte(memory);
-                8
-            }
-
-            0x17 => { // RLA
-                let old_carry = if self.flag_c() { 1 } else { 0 };
-                let new_carry = self.a >> 7;
-                self.a = (self.a << 1) | old_carry;
-                self.set_flags(false, false, false, new_carry != 0);
-                4
-            }
-
-            0x18 => { // JR r8
-                let offset = self.fetch_byte(memory) as i8;
-                self.pc = self.pc.wrapping_add(offset as u16);
-                12
-            }
-
-            0x19 => { // ADD HL, DE
-                self.alu_add_hl(self.de());
-                8
-            }
-
-            0x1A => { // LD A, (DE)
-                self.a = self.read_byte(memory, self.de());
-                8
-            }
-
-            0x1B => { // DEC DE
-                self.set_de(self.de().wrapping_sub(1));
-                8
-            }
-
-            0x1C => { // INC E
-                self.e = self.alu_inc(self.e);
-                4
-            }
-
-            0x1D => { // DEC E
-                self.e = self.alu_dec(self.e);
-                4
-            }
-
-            0x1E => { // LD E, d8
-                self.e = self.fetch_byte(memory);
-                8
-            }
-
-            0x1F => { // RRA
-                let old_carry = if self.flag_c() { 1 } else { 0 };
-                let new_carry = self.a & 1;
-                self.a = (self.a >> 1) | (old_carry << 7);
-                self.set_flags(false, false, false, new_carry != 0);
-                4
-            }
-
-            // ==================== 0x2X ====================
-            0x20 => { // JR NZ, r8
-                let offset = self.fetch_byte(memory) as i8;
-                if !self.flag_z() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    12
-                } else {
-                    8
-                }
-            }
-
-            0x21 => { // LD HL, d16
-                let val = self.fetch_word(memory);
-                self.set_hl(val);
-                12
-            }
-
-            0x22 => { // LD (HL+), A
-                self.write_byte(memory, self.hl(), self.a);
-                self.set_hl(self.hl().wrapping_add(1));
-                8
-            }
-
-            0x23 => { // INC HL
-                self.set_hl(self.hl().wrapping_add(1));
-                8
-            }
-
-            0x24 => { // INC H
-                self.h = self.alu_inc(self.h);
-                4
-            }
-
-            0x25 => { // DEC H
-                self.h = self.alu_dec(self.h);
-                4
-            }
-
-            0x26 => { // LD H, d8
-                self.h = self.fetch_byte(memory);
-                8
-            }
-
-            0x27 => { // DAA
-                self.alu_daa();
-                4
-            }
-
-            0x28 => { // JR Z, r8
-                let offset = self.fetch_byte(memory) as i8;
-                if self.flag_z() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    12
-                } else {
-                    8
-                }
-            }
-
-            0x29 => { // ADD HL, HL
-                let hl = self.hl();
-                self.alu_add_hl(hl);
-                8
-            }
-
-            0x2A => { // LD A, (HL+)
-                self.a = self.read_byte(memory, self.hl());
-                self.set_hl(self.hl().wrapping_add(1));
-                8
-            }
-
-            0x2B => { // DEC HL
-                self.set_hl(self.hl().wrapping_sub(1));
-                8
-            }
-
-            0x2C => { // INC L
-                self.l = self.alu_inc(self.l);
-                4
-            }
-
-            0x2D => { // DEC L
-                self.l = self.alu_dec(self.l);
-                4
-            }
-
-            0x2E => { // LD L, d8
-                self.l = self.fetch_byte(memory);
-                8
-            }
-
-            0x2F => { // CPL
-                self.a = !self.a;
-                self.set_flag(FLAG_N, true);
-                self.set_flag(FLAG_H, true);
-                4
-            }
+        // Execute the instruction with M-cycle ticking
+        self.execute_mcycle(opcode, memory, &mut tick)
+    }
 
-            // ==================== 0x3X ====================
-            0x30 => { // JR NC, r8
-                let offset = self.fetch_byte(memory) as i8;
-                if !self.flag_c() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    12
-                } else {
-                    8
-                }
-            }
+    /// Execute an instruction with M-cycle accurate timing
+    fn execute_mcycle<F>(&mut self, opcode: u8, memory: &mut Memory, tick: &mut F) -> u32
+    where
+        F: FnMut(&mut Memory, u32),
+    {
+        ops::Table::<F>::OPS[opcode as usize](self, memory, tick)
+    }
 
-            0x31 => { // LD SP, d16
-                self.sp = self.fetch_word(memory);
-                12
-            }
+}
 
-            0x32 => { // LD (HL-), A
-                self.write_byte(memory, self.hl(), self.a);
-                self.set_hl(self.hl().wrapping_sub(1));
-                8
-            }
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            0x33 => { // INC SP
-                self.sp = self.sp.wrapping_add(1);
-                8
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            0x34 => { // INC (HL)
-                let addr = self.hl();
-                let val = self.read_byte(memory, addr);
-                let result = self.alu_inc(val);
-                self.write_byte(memory, addr, result);
-                12
-            }
+    #[test]
+    fn reset_sets_initial_values() {
+        let mut cpu = Cpu::new();
+        cpu.reset();
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(cpu.f, 0xB0);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.pc, 0x0100);
+    }
 
-            0x35 => { // DEC (HL)
-                let addr = self.hl();
-                let val = self.read_byte(memory, addr);
-                let result = self.alu_dec(val);
-                self.write_byte(memory, addr, result);
-                12
-            }
+    #[test]
+    fn step_executes_nop() {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        
+        // Create a minimal ROM with NOP at 0x0100
+        let mut rom = vec![0u8; 0x8000]; // 32KB ROM
+        rom[0x0100] = 0x00; // NOP
+        mem.load_rom(&rom);
+        
+        cpu.reset();
+        cpu.step(&mut mem);
+        assert_eq!(cpu.pc, 0x0101);
+    }
 
-            0x36 => { // LD (HL), d8
-                let val = self.fetch_byte(memory);
-                self.write_byte(memory, self.hl(), val);
-                12
-            }
+    /// CPU and memory running `code` at 0x0100, with `handler` at the
+    /// VBlank vector and RETI at the STAT vector
+    fn setup(code: &[u8], handler: &[u8]) -> (Cpu, Memory) {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0040..0x0040 + handler.len()].copy_from_slice(handler);
+        rom[0x0048] = 0xD9;
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        let mut cpu = Cpu::new();
+        cpu.reset();
+        cpu.ime = false;
+        (cpu, mem)
+    }
 
-            0x37 => { // SCF
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, false);
-                self.set_flag(FLAG_C, true);
-                4
-            }
+    /// Service interrupts and execute an instruction, like `Emulator::step`
+    fn run(cpu: &mut Cpu, mem: &mut Memory) -> u32 {
+        cpu.service_interrupts(mem, &mut |_, _| {}) + cpu.step_mcycle(mem, |_, _| {})
+    }
 
-            0x38 => { // JR C, r8
-                let offset = self.fetch_byte(memory) as i8;
-                if self.flag_c() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    12
-                } else {
-                    8
-                }
-            }
+    #[test]
+    fn ei_then_di_never_enables_interrupts() {
+        // EI; DI; NOP
+        let (mut cpu, mut mem) = setup(&[0xFB, 0xF3, 0x00], &[]);
+        mem.ie = 0x01;
+        mem.write_byte(io::IF, 0x01);
+        for pc in [0x0101, 0x0102, 0x0103] {
+            run(&mut cpu, &mut mem);
+            assert_eq!(cpu.pc, pc);
+        }
+        assert!(!cpu.ime && !cpu.ime_pending);
+    }
 
-            0x39 => { // ADD HL, SP
-                self.alu_add_hl(self.sp);
-                8
-            }
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI; NOP; NOP with VBlank already requested
+        let (mut cpu, mut mem) = setup(&[0xFB, 0x00, 0x00], &[]);
+        mem.ie = 0x01;
+        mem.write_byte(io::IF, 0x01);
+        run(&mut cpu, &mut mem);
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.sp, 0xFFFE, "the NOP after EI runs first");
+        run(&mut cpu, &mut mem);
+        assert_eq!(mem.read_byte(0xFFFC), 0x02, "returns to the second NOP");
+    }
 
-            0x3A => { // LD A, (HL-)
-                self.a = self.read_byte(memory, self.hl());
-                self.set_hl(self.hl().wrapping_sub(1));
-                8
-            }
+    #[test]
+    fn ei_before_halt_waits_for_vblank() {
+        // EI; HALT; NOP
+        let (mut cpu, mut mem) = setup(&[0xFB, 0x76, 0x00], &[0xD9]);
+        mem.ie = 0x01;
+        run(&mut cpu, &mut mem);
+        run(&mut cpu, &mut mem);
+        assert!(cpu.halted && cpu.ime);
+        assert_eq!(run(&mut cpu, &mut mem), 4, "halted until VBlank");
 
-            0x3B => { // DEC SP
-                self.sp = self.sp.wrapping_sub(1);
-                8
-            }
+        mem.write_byte(io::IF, 0x01);
+        assert_eq!(run(&mut cpu, &mut mem), 24 + 16, "wake, dispatch and RETI");
+        assert_eq!(cpu.pc, 0x0102);
+        assert!(cpu.ime && !cpu.halted);
+    }
 
-            0x3C => { // INC A
-                self.a = self.alu_inc(self.a);
-                4
-            }
+    #[test]
+    fn reti_and_ei_in_a_handler() {
+        // The VBlank handler returns with RETI: a STAT interrupt requested
+        // meanwhile is taken straight away, before the code returned to
+        let (mut cpu, mut mem) = setup(&[0x00, 0x00], &[0xD9]);
+        cpu.ime = true;
+        mem.ie = 0x03;
+        mem.write_byte(io::IF, 0x03);
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.pc, 0x0100, "dispatched, then RETI ran");
+        assert_eq!(cpu.service_interrupts(&mut mem, &mut |_, _| {}), 20);
+        assert_eq!(cpu.pc, 0x0048);
+
+        // With EI; RET instead, the RET still runs first
+        let (mut cpu, mut mem) = setup(&[0x00, 0x00], &[0xFB, 0xC9]);
+        cpu.ime = true;
+        mem.ie = 0x03;
+        mem.write_byte(io::IF, 0x03);
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.pc, 0x0041);
+        run(&mut cpu, &mut mem);
+        assert_eq!(cpu.pc, 0x0100, "RET ran before the STAT interrupt");
+        assert_eq!(cpu.service_interrupts(&mut mem, &mut |_, _| {}), 20);
+        assert_eq!(cpu.pc, 0x0048);
+    }
 
-            0x3D => { // DEC A
-                self.a = self.alu_dec(self.a);
-                4
-            }
+    #[test]
+    fn every_m_cycle_is_ticked() {
+        // LD HL, $C000; LD (HL), $42; INC (HL); CALL $0200, then a NOP there
+        let (mut cpu, mut mem) = setup(&[0x21, 0x00, 0xC0, 0x36, 0x42, 0x34, 0xCD, 0x00, 0x02], &[]);
+        let mut ticks = Vec::new();
+        for expected in [12, 12, 12, 24, 4] {
+            ticks.clear();
+            let cycles = cpu.step_mcycle(&mut mem, |_, cycles| ticks.push(cycles));
+            assert_eq!(cycles, expected);
+            assert_eq!(ticks, vec![4; expected as usize / 4]);
+        }
+        assert_eq!(mem.read_byte(0xC000), 0x43);
+    }
 
-            0x3E => { // LD A, d8
-                self.a = self.fetch_byte(memory);
-                8
-            }
+    #[test]
+    fn halt_exit_and_dispatch_timing() {
+        let mut cpu = Cpu::new();
+        let mut mem = Memory::new();
+        mem.load_rom(&vec![0u8; 0x8000]);
+        cpu.reset();
+        let mut ticks = 0;
+        let mut tick = |_: &mut Memory, cycles| ticks += cycles;
+
+        // Nothing pending: still halted, no time taken
+        cpu.halted = true;
+        mem.ie = 0x04;
+        assert_eq!(cpu.service_interrupts(&mut mem, &mut tick), 0);
+        assert!(cpu.halted);
+
+        // IME off: waking takes an M-cycle and execution carries on
+        mem.write_byte(io::IF, 0x04);
+        assert_eq!(cpu.service_interrupts(&mut mem, &mut tick), 4);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0100);
 
-            0x3F => { // CCF
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, false);
-                self.set_flag(FLAG_C, !self.flag_c());
-                4
-            }
-
-            // ==================== 0x4X - LD B/C, r ====================
-            0x40 => 4,
-            0x41 => { self.b = self.c; 4 }
-            0x42 => { self.b = self.d; 4 }
-            0x43 => { self.b = self.e; 4 }
-            0x44 => { self.b = self.h; 4 }
-            0x45 => { self.b = self.l; 4 }
-            0x46 => { self.b = self.read_byte(memory, self.hl()); 8 }
-            0x47 => { self.b = self.a; 4 }
-            0x48 => { self.c = self.b; 4 }
-            0x49 => 4,
-            0x4A => { self.c = self.d; 4 }
-            0x4B => { self.c = self.e; 4 }
-            0x4C => { self.c = self.h; 4 }
-            0x4D => { self.c = self.l; 4 }
-            0x4E => { self.c = self.read_byte(memory, self.hl()); 8 }
-            0x4F => { self.c = self.a; 4 }
-
-            // ==================== 0x5X - LD D/E, r ====================
-            0x50 => { self.d = self.b; 4 }
-            0x51 => { self.d = self.c; 4 }
-            0x52 => 4,
-            0x53 => { self.d = self.e; 4 }
-            0x54 => { self.d = self.h; 4 }
-            0x55 => { self.d = self.l; 4 }
-            0x56 => { self.d = self.read_byte(memory, self.hl()); 8 }
-            0x57 => { self.d = self.a; 4 }
-            0x58 => { self.e = self.b; 4 }
-            0x59 => { self.e = self.c; 4 }
-            0x5A => { self.e = self.d; 4 }
-            0x5B => 4,
-            0x5C => { self.e = self.h; 4 }
-            0x5D => { self.e = self.l; 4 }
-            0x5E => { self.e = self.read_byte(memory, self.hl()); 8 }
-            0x5F => { self.e = self.a; 4 }
-
-            // ==================== 0x6X - LD H/L, r ====================
-            0x60 => { self.h = self.b; 4 }
-            0x61 => { self.h = self.c; 4 }
-            0x62 => { self.h = self.d; 4 }
-            0x63 => { self.h = self.e; 4 }
-            0x64 => 4,
-            0x65 => { self.h = self.l; 4 }
-            0x66 => { self.h = self.read_byte(memory, self.hl()); 8 }
-            0x67 => { self.h = self.a; 4 }
-            0x68 => { self.l = self.b; 4 }
-            0x69 => { self.l = self.c; 4 }
-            0x6A => { self.l = self.d; 4 }
-            0x6B => { self.l = self.e; 4 }
-            0x6C => { self.l = self.h; 4 }
-            0x6D => 4,
-            0x6E => { self.l = self.read_byte(memory, self.hl()); 8 }
-            0x6F => { self.l = self.a; 4 }
-
-            // ==================== 0x7X - LD (HL)/A, r ====================
-            0x70 => { self.write_byte(memory, self.hl(), self.b); 8 }
-            0x71 => { self.write_byte(memory, self.hl(), self.c); 8 }
-            0x72 => { self.write_byte(memory, self.hl(), self.d); 8 }
-            0x73 => { self.write_byte(memory, self.hl(), self.e); 8 }
-            0x74 => { self.write_byte(memory, self.hl(), self.h); 8 }
-            0x75 => { self.write_byte(memory, self.hl(), self.l); 8 }
-            0x76 => { // HALT
-                // Check for HALT bug: if IME=0 and an interrupt is pending
-                let pending = memory.pending_interrupts();
-                if !self.ime && pending != 0 {
-                    // HALT bug: don't halt, but next instruction's opcode is read twice
-                    self.halt_bug = true;
-                } else {
-                    self.halted = true;
-                }
-                4
-            }
-            0x77 => { self.write_byte(memory, self.hl(), self.a); 8 }
-            0x78 => { self.a = self.b; 4 }
-            0x79 => { self.a = self.c; 4 }
-            0x7A => { self.a = self.d; 4 }
-            0x7B => { self.a = self.e; 4 }
-            0x7C => { self.a = self.h; 4 }
-            0x7D => { self.a = self.l; 4 }
-            0x7E => { self.a = self.read_byte(memory, self.hl()); 8 }
-            0x7F => 4,
-
-            // ==================== 0x8X - ADD/ADC A, r ====================
-            0x80 => { self.alu_add(self.b); 4 }
-            0x81 => { self.alu_add(self.c); 4 }
-            0x82 => { self.alu_add(self.d); 4 }
-            0x83 => { self.alu_add(self.e); 4 }
-            0x84 => { self.alu_add(self.h); 4 }
-            0x85 => { self.alu_add(self.l); 4 }
-            0x86 => { let v = self.read_byte(memory, self.hl()); self.alu_add(v); 8 }
-            0x87 => { self.alu_add(self.a); 4 }
-            0x88 => { self.alu_adc(self.b); 4 }
-            0x89 => { self.alu_adc(self.c); 4 }
-            0x8A => { self.alu_adc(self.d); 4 }
-            0x8B => { self.alu_adc(self.e); 4 }
-            0x8C => { self.alu_adc(self.h); 4 }
-            0x8D => { self.alu_adc(self.l); 4 }
-            0x8E => { let v = self.read_byte(memory, self.hl()); self.alu_adc(v); 8 }
-            0x8F => { self.alu_adc(self.a); 4 }
-
-            // ==================== 0x9X - SUB/SBC A, r ====================
-            0x90 => { self.alu_sub(self.b); 4 }
-            0x91 => { self.alu_sub(self.c); 4 }
-            0x92 => { self.alu_sub(self.d); 4 }
-            0x93 => { self.alu_sub(self.e); 4 }
-            0x94 => { self.alu_sub(self.h); 4 }
-            0x95 => { self.alu_sub(self.l); 4 }
-            0x96 => { let v = self.read_byte(memory, self.hl()); self.alu_sub(v); 8 }
-            0x97 => { self.alu_sub(self.a); 4 }
-            0x98 => { self.alu_sbc(self.b); 4 }
-            0x99 => { self.alu_sbc(self.c); 4 }
-            0x9A => { self.alu_sbc(self.d); 4 }
-            0x9B => { self.alu_sbc(self.e); 4 }
-            0x9C => { self.alu_sbc(self.h); 4 }
-            0x9D => { self.alu_sbc(self.l); 4 }
-            0x9E => { let v = self.read_byte(memory, self.hl()); self.alu_sbc(v); 8 }
-            0x9F => { self.alu_sbc(self.a); 4 }
-
-            // ==================== 0xAX - AND/XOR A, r ====================
-            0xA0 => { self.alu_and(self.b); 4 }
-            0xA1 => { self.alu_and(self.c); 4 }
-            0xA2 => { self.alu_and(self.d); 4 }
-            0xA3 => { self.alu_and(self.e); 4 }
-            0xA4 => { self.alu_and(self.h); 4 }
-            0xA5 => { self.alu_and(self.l); 4 }
-            0xA6 => { let v = self.read_byte(memory, self.hl()); self.alu_and(v); 8 }
-            0xA7 => { self.alu_and(self.a); 4 }
-            0xA8 => { self.alu_xor(self.b); 4 }
-            0xA9 => { self.alu_xor(self.c); 4 }
-            0xAA => { self.alu_xor(self.d); 4 }
-            0xAB => { self.alu_xor(self.e); 4 }
-            0xAC => { self.alu_xor(self.h); 4 }
-            0xAD => { self.alu_xor(self.l); 4 }
-            0xAE => { let v = self.read_byte(memory, self.hl()); self.alu_xor(v); 8 }
-            0xAF => { self.alu_xor(self.a); 4 }
-
-            // ==================== 0xBX - OR/CP A, r ====================
-            0xB0 => { self.alu_or(self.b); 4 }
-            0xB1 => { self.alu_or(self.c); 4 }
-            0xB2 => { self.alu_or(self.d); 4 }
-            0xB3 => { self.alu_or(self.e); 4 }
-            0xB4 => { self.alu_or(self.h); 4 }
-            0xB5 => { self.alu_or(self.l); 4 }
-            0xB6 => { let v = self.read_byte(memory, self.hl()); self.alu_or(v); 8 }
-            0xB7 => { self.alu_or(self.a); 4 }
-            0xB8 => { self.alu_cp(self.b); 4 }
-            0xB9 => { self.alu_cp(self.c); 4 }
-            0xBA => { self.alu_cp(self.d); 4 }
-            0xBB => { self.alu_cp(self.e); 4 }
-            0xBC => { self.alu_cp(self.h); 4 }
-            0xBD => { self.alu_cp(self.l); 4 }
-            0xBE => { let v = self.read_byte(memory, self.hl()); self.alu_cp(v); 8 }
-            0xBF => { self.alu_cp(self.a); 4 }
-
-            // ==================== 0xCX ====================
-            0xC0 => { // RET NZ
-                if !self.flag_z() {
-                    self.pc = self.pop(memory);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            0xC1 => { // POP BC
-                let val = self.pop(memory);
-                self.set_bc(val);
-                12
-            }
-
-            0xC2 => { // JP NZ, a16
-                let addr = self.fetch_word(memory);
-                if !self.flag_z() {
-                    self.pc = addr;
-                    16
-                } else {
-                    12
-                }
-            }
-
-            0xC3 => { // JP a16
-                self.pc = self.fetch_word(memory);
-                16
-            }
-
-            0xC4 => { // CALL NZ, a16
-                let addr = self.fetch_word(memory);
-                if !self.flag_z() {
-                    self.push(memory, self.pc);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            0xC5 => { // PUSH BC
-                self.push(memory, self.bc());
-                16
-            }
-
-            0xC6 => { // ADD A, d8
-                let val = self.fetch_byte(memory);
-                self.alu_add(val);
-                8
-            }
-
-            0xC7 => { // RST 00H
-                self.push(memory, self.pc);
-                self.pc = 0x0000;
-                16
-            }
-
-            0xC8 => { // RET Z
-                if self.flag_z() {
-                    self.pc = self.pop(memory);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            0xC9 => { // RET
-                self.pc = self.pop(memory);
-                16
-            }
-
-            0xCA => { // JP Z, a16
-                let addr = self.fetch_word(memory);
-                if self.flag_z() {
-                    self.pc = addr;
-                    16
-                } else {
-                    12
-                }
-            }
-
-            0xCB => self.execute_cb(memory),
-
-            0xCC => { // CALL Z, a16
-                let addr = self.fetch_word(memory);
-                if self.flag_z() {
-                    self.push(memory, self.pc);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            0xCD => { // CALL a16
-                let addr = self.fetch_word(memory);
-                self.push(memory, self.pc);
-                self.pc = addr;
-                24
-            }
-
-            0xCE => { // ADC A, d8
-                let val = self.fetch_byte(memory);
-                self.alu_adc(val);
-                8
-            }
-
-            0xCF => { // RST 08H
-                self.push(memory, self.pc);
-                self.pc = 0x0008;
-                16
-            }
-
-            // ==================== 0xDX ====================
-            0xD0 => { // RET NC
-                if !self.flag_c() {
-                    self.pc = self.pop(memory);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            0xD1 => { // POP DE
-                let val = self.pop(memory);
-                self.set_de(val);
-                12
-            }
-
-            0xD2 => { // JP NC, a16
-                let addr = self.fetch_word(memory);
-                if !self.flag_c() {
-                    self.pc = addr;
-                    16
-                } else {
-                    12
-                }
-            }
-
-            0xD3 => panic!("Illegal opcode 0xD3"),
-
-            0xD4 => { // CALL NC, a16
-                let addr = self.fetch_word(memory);
-                if !self.flag_c() {
-                    self.push(memory, self.pc);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            0xD5 => { // PUSH DE
-                self.push(memory, self.de());
-                16
-            }
-
-            0xD6 => { // SUB d8
-                let val = self.fetch_byte(memory);
-                self.alu_sub(val);
-                8
-            }
-
-            0xD7 => { // RST 10H
-                self.push(memory, self.pc);
-                self.pc = 0x0010;
-                16
-            }
-
-            0xD8 => { // RET C
-                if self.flag_c() {
-                    self.pc = self.pop(memory);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            0xD9 => { // RETI
-                self.pc = self.pop(memory);
-                self.ime = true;
-                16
-            }
-
-            0xDA => { // JP C, a16
-                let addr = self.fetch_word(memory);
-                if self.flag_c() {
-                    self.pc = addr;
-                    16
-                } else {
-                    12
-                }
-            }
-
-            0xDB => panic!("Illegal opcode 0xDB"),
-
-            0xDC => { // CALL C, a16
-                let addr = self.fetch_word(memory);
-                if self.flag_c() {
-                    self.push(memory, self.pc);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            0xDD => panic!("Illegal opcode 0xDD"),
-
-            0xDE => { // SBC A, d8
-                let val = self.fetch_byte(memory);
-                self.alu_sbc(val);
-                8
-            }
-
-            0xDF => { // RST 18H
-                self.push(memory, self.pc);
-                self.pc = 0x0018;
-                16
-            }
-
-            // ==================== 0xEX ====================
-            0xE0 => { // LDH (a8), A
-                let offset = self.fetch_byte(memory) as u16;
-                self.write_byte(memory, 0xFF00 + offset, self.a);
-                12
-            }
-
-            0xE1 => { // POP HL
-                let val = self.pop(memory);
-                self.set_hl(val);
-                12
-            }
-
-            0xE2 => { // LD (C), A
-                self.write_byte(memory, 0xFF00 + self.c as u16, self.a);
-                8
-            }
-
-            0xE3 => panic!("Illegal opcode 0xE3"),
-            0xE4 => panic!("Illegal opcode 0xE4"),
-
-            0xE5 => { // PUSH HL
-                self.push(memory, self.hl());
-                16
-            }
-
-            0xE6 => { // AND d8
-                let val = self.fetch_byte(memory);
-                self.alu_and(val);
-                8
-            }
-
-            0xE7 => { // RST 20H
-                self.push(memory, self.pc);
-                self.pc = 0x0020;
-                16
-            }
-
-            0xE8 => { // ADD SP, r8
-                let val = self.fetch_byte(memory) as i8;
-                self.sp = self.alu_add_sp(val);
-                16
-            }
-
-            0xE9 => { // JP HL
-                self.pc = self.hl();
-                4
-            }
-
-            0xEA => { // LD (a16), A
-                let addr = self.fetch_word(memory);
-                self.write_byte(memory, addr, self.a);
-                16
-            }
-
-            0xEB => panic!("Illegal opcode 0xEB"),
-            0xEC => panic!("Illegal opcode 0xEC"),
-            0xED => panic!("Illegal opcode 0xED"),
-
-            0xEE => { // XOR d8
-                let val = self.fetch_byte(memory);
-                self.alu_xor(val);
-                8
-            }
-
-            0xEF => { // RST 28H
-                self.push(memory, self.pc);
-                self.pc = 0x0028;
-                16
-            }
-
-            // ==================== 0xFX ====================
-            0xF0 => { // LDH A, (a8)
-                let offset = self.fetch_byte(memory) as u16;
-                self.a = self.read_byte(memory, 0xFF00 + offset);
-                12
-            }
-
-            0xF1 => { // POP AF
-                let val = self.pop(memory);
-                self.set_af(val);
-                12
-            }
-
-            0xF2 => { // LD A, (C)
-                self.a = self.read_byte(memory, 0xFF00 + self.c as u16);
-                8
-            }
-
-            0xF3 => { // DI
-                self.ime = false;
-                4
-            }
-
-            0xF4 => panic!("Illegal opcode 0xF4"),
-
-            0xF5 => { // PUSH AF
-                self.push(memory, self.af());
-                16
-            }
-
-            0xF6 => { // OR d8
-                let val = self.fetch_byte(memory);
-                self.alu_or(val);
-                8
-            }
-
-            0xF7 => { // RST 30H
-                self.push(memory, self.pc);
-                self.pc = 0x0030;
-                16
-            }
-
-            0xF8 => { // LD HL, SP+r8
-                let val = self.fetch_byte(memory) as i8;
-                let result = self.alu_add_sp(val);
-                self.set_hl(result);
-                12
-            }
-
-            0xF9 => { // LD SP, HL
-                self.sp = self.hl();
-                8
-            }
-
-            0xFA => { // LD A, (a16)
-                let addr = self.fetch_word(memory);
-                self.a = self.read_byte(memory, addr);
-                16
-            }
-
-            0xFB => { // EI
-                self.ime_pending = true;
-                4
-            }
-
-            0xFC => panic!("Illegal opcode 0xFC"),
-            0xFD => panic!("Illegal opcode 0xFD"),
-
-            0xFE => { // CP d8
-                let val = self.fetch_byte(memory);
-                self.alu_cp(val);
-                8
-            }
-
-            0xFF => { // RST 38H
-                self.push(memory, self.pc);
-                self.pc = 0x0038;
-                16
-            }
-        }
-    }
-
-    /// Executes a CB-prefixed instruction.
-    fn execute_cb(&mut self, memory: &mut Memory) -> u32 {
-        let opcode = self.fetch_byte(memory);
-
-        let get_reg = |cpu: &Cpu, mem: &Memory, idx: u8| -> u8 {
-            match idx {
-                0 => cpu.b,
-                1 => cpu.c,
-                2 => cpu.d,
-                3 => cpu.e,
-                4 => cpu.h,
-                5 => cpu.l,
-                6 => cpu.read_byte(mem, cpu.hl()),
-                7 => cpu.a,
-                _ => unreachable!(),
-            }
-        };
-
-        let set_reg = |cpu: &mut Cpu, mem: &mut Memory, idx: u8, val: u8| {
-            match idx {
-                0 => cpu.b = val,
-                1 => cpu.c = val,
-                2 => cpu.d = val,
-                3 => cpu.e = val,
-                4 => cpu.h = val,
-                5 => cpu.l = val,
-                6 => cpu.write_byte(mem, cpu.hl(), val),
-                7 => cpu.a = val,
-                _ => unreachable!(),
-            }
-        };
-
-        let reg_idx = opcode & 0x07;
-        let is_hl = reg_idx == 6;
-        let base_cycles = if is_hl { 16 } else { 8 };
-
-        match opcode {
-            0x00..=0x07 => { // RLC r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_rlc(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x08..=0x0F => { // RRC r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_rrc(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x10..=0x17 => { // RL r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_rl(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x18..=0x1F => { // RR r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_rr(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x20..=0x27 => { // SLA r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_sla(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x28..=0x2F => { // SRA r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_sra(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x30..=0x37 => { // SWAP r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_swap(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x38..=0x3F => { // SRL r
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_srl(val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0x40..=0x7F => { // BIT b, r
-                let bit = (opcode >> 3) & 0x07;
-                let val = get_reg(self, memory, reg_idx);
-                self.alu_bit(bit, val);
-                if is_hl { 12 } else { 8 }
-            }
-
-            0x80..=0xBF => { // RES b, r
-                let bit = (opcode >> 3) & 0x07;
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_res(bit, val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-
-            0xC0..=0xFF => { // SET b, r
-                let bit = (opcode >> 3) & 0x07;
-                let val = get_reg(self, memory, reg_idx);
-                let result = self.alu_set(bit, val);
-                set_reg(self, memory, reg_idx, result);
-                base_cycles
-            }
-        }
-    }
-
-    /// Executes a single CPU step with M-cycle accurate timing.
-    /// The callback is called after each M-cycle (4 T-cycles) allowing
-    /// other components to be updated between memory accesses.
-    /// Returns the total number of T-cycles consumed.
-    pub fn step_mcycle<F>(&mut self, memory: &mut Memory, mut tick: F) -> u32
-    where
-        F: FnMut(&mut Memory, u32),
-    {
-        // Handle pending IME enable (EI has a one-instruction delay)
-        if self.ime_pending {
-            self.ime = true;
-            self.ime_pending = false;
-        }
-
-        // If halted, tick for one M-cycle and return
-        if self.halted {
-            tick(memory, 4);
-            return 4;
-        }
-
-        // Fetch opcode (1 M-cycle)
-        let opcode = self.fetch_byte(memory);
-        tick(memory, 4);
-
-        // Execute the instruction with M-cycle ticking
-        self.execute_mcycle(opcode, memory, &mut tick)
-    }
-
-    /// Execute an instruction with M-cycle accurate timing
-    fn execute_mcycle<F>(&mut self, opcode: u8, memory: &mut Memory, tick: &mut F) -> u32
-    where
-        F: FnMut(&mut Memory, u32),
-    {
-        match opcode {
-            // NOP - no additional M-cycles
-            0x00 => 4,
-
-            // LD BC, d16 - 2 more M-cycles for operand fetch
-            0x01 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.set_bc(((hi as u16) << 8) | (lo as u16));
-                12
-            }
-
-            // LD (BC), A - 1 more M-cycle for write
-            0x02 => {
-                self.write_byte(memory, self.bc(), self.a);
-                tick(memory, 4);
-                8
-            }
-
-            // INC BC - 1 internal M-cycle
-            0x03 => {
-                self.set_bc(self.bc().wrapping_add(1));
-                tick(memory, 4);
-                8
-            }
-
-            // INC B - no additional M-cycles
-            0x04 => { self.b = self.alu_inc(self.b); 4 }
-
-            // DEC B - no additional M-cycles
-            0x05 => { self.b = self.alu_dec(self.b); 4 }
-
-            // LD B, d8 - 1 more M-cycle for operand
-            0x06 => {
-                self.b = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // RLCA - no additional M-cycles
-            0x07 => {
-                let carry = self.a >> 7;
-                self.a = (self.a << 1) | carry;
-                self.set_flags(false, false, false, carry != 0);
-                4
-            }
-
-            // LD (a16), SP - 4 more M-cycles
-            0x08 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                memory.write_byte(addr, self.sp as u8);
-                tick(memory, 4);
-                memory.write_byte(addr.wrapping_add(1), (self.sp >> 8) as u8);
-                tick(memory, 4);
-                20
-            }
-
-            // ADD HL, BC - 1 internal M-cycle
-            0x09 => {
-                self.alu_add_hl(self.bc());
-                tick(memory, 4);
-                8
-            }
-
-            // LD A, (BC) - 1 more M-cycle for read
-            0x0A => {
-                self.a = self.read_byte(memory, self.bc());
-                tick(memory, 4);
-                8
-            }
-
-            // DEC BC - 1 internal M-cycle
-            0x0B => {
-                self.set_bc(self.bc().wrapping_sub(1));
-                tick(memory, 4);
-                8
-            }
-
-            // INC C - no additional M-cycles
-            0x0C => { self.c = self.alu_inc(self.c); 4 }
-
-            // DEC C - no additional M-cycles
-            0x0D => { self.c = self.alu_dec(self.c); 4 }
-
-            // LD C, d8 - 1 more M-cycle
-            0x0E => {
-                self.c = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // RRCA - no additional M-cycles
-            0x0F => {
-                let carry = self.a & 1;
-                self.a = (self.a >> 1) | (carry << 7);
-                self.set_flags(false, false, false, carry != 0);
-                4
-            }
-
-            // STOP
-            0x10 => {
-                self.pc = self.pc.wrapping_add(1);
-                self.stopped = true;
-                4
-            }
-
-            // LD DE, d16
-            0x11 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.set_de(((hi as u16) << 8) | (lo as u16));
-                12
-            }
-
-            // LD (DE), A
-            0x12 => {
-                self.write_byte(memory, self.de(), self.a);
-                tick(memory, 4);
-                8
-            }
-
-            // INC DE
-            0x13 => {
-                self.set_de(self.de().wrapping_add(1));
-                tick(memory, 4);
-                8
-            }
-
-            // INC D
-            0x14 => { self.d = self.alu_inc(self.d); 4 }
-
-            // DEC D
-            0x15 => { self.d = self.alu_dec(self.d); 4 }
-
-            // LD D, d8
-            0x16 => {
-                self.d = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // RLA
-            0x17 => {
-                let old_carry = if self.flag_c() { 1 } else { 0 };
-                let new_carry = self.a >> 7;
-                self.a = (self.a << 1) | old_carry;
-                self.set_flags(false, false, false, new_carry != 0);
-                4
-            }
-
-            // JR r8
-            0x18 => {
-                let offset = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                self.pc = self.pc.wrapping_add(offset as u16);
-                tick(memory, 4);
-                12
-            }
-
-            // ADD HL, DE
-            0x19 => {
-                self.alu_add_hl(self.de());
-                tick(memory, 4);
-                8
-            }
-
-            // LD A, (DE)
-            0x1A => {
-                self.a = self.read_byte(memory, self.de());
-                tick(memory, 4);
-                8
-            }
-
-            // DEC DE
-            0x1B => {
-                self.set_de(self.de().wrapping_sub(1));
-                tick(memory, 4);
-                8
-            }
-
-            // INC E
-            0x1C => { self.e = self.alu_inc(self.e); 4 }
-
-            // DEC E
-            0x1D => { self.e = self.alu_dec(self.e); 4 }
-
-            // LD E, d8
-            0x1E => {
-                self.e = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // RRA
-            0x1F => {
-                let old_carry = if self.flag_c() { 1 } else { 0 };
-                let new_carry = self.a & 1;
-                self.a = (self.a >> 1) | (old_carry << 7);
-                self.set_flags(false, false, false, new_carry != 0);
-                4
-            }
-
-            // JR NZ, r8
-            0x20 => {
-                let offset = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                if !self.flag_z() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    tick(memory, 4);
-                    12
-                } else {
-                    8
-                }
-            }
-
-            // LD HL, d16
-            0x21 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.set_hl(((hi as u16) << 8) | (lo as u16));
-                12
-            }
-
-            // LD (HL+), A
-            0x22 => {
-                self.write_byte(memory, self.hl(), self.a);
-                tick(memory, 4);
-                self.set_hl(self.hl().wrapping_add(1));
-                8
-            }
-
-            // INC HL
-            0x23 => {
-                self.set_hl(self.hl().wrapping_add(1));
-                tick(memory, 4);
-                8
-            }
-
-            // INC H
-            0x24 => { self.h = self.alu_inc(self.h); 4 }
-
-            // DEC H
-            0x25 => { self.h = self.alu_dec(self.h); 4 }
-
-            // LD H, d8
-            0x26 => {
-                self.h = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // DAA
-            0x27 => { self.alu_daa(); 4 }
-
-            // JR Z, r8
-            0x28 => {
-                let offset = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                if self.flag_z() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    tick(memory, 4);
-                    12
-                } else {
-                    8
-                }
-            }
-
-            // ADD HL, HL
-            0x29 => {
-                let hl = self.hl();
-                self.alu_add_hl(hl);
-                tick(memory, 4);
-                8
-            }
-
-            // LD A, (HL+)
-            0x2A => {
-                self.a = self.read_byte(memory, self.hl());
-                tick(memory, 4);
-                self.set_hl(self.hl().wrapping_add(1));
-                8
-            }
-
-            // DEC HL
-            0x2B => {
-                self.set_hl(self.hl().wrapping_sub(1));
-                tick(memory, 4);
-                8
-            }
-
-            // INC L
-            0x2C => { self.l = self.alu_inc(self.l); 4 }
-
-            // DEC L
-            0x2D => { self.l = self.alu_dec(self.l); 4 }
-
-            // LD L, d8
-            0x2E => {
-                self.l = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // CPL
-            0x2F => {
-                self.a = !self.a;
-                self.set_flag(FLAG_N, true);
-                self.set_flag(FLAG_H, true);
-                4
-            }
-
-            // JR NC, r8
-            0x30 => {
-                let offset = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                if !self.flag_c() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    tick(memory, 4);
-                    12
-                } else {
-                    8
-                }
-            }
-
-            // LD SP, d16
-            0x31 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.sp = ((hi as u16) << 8) | (lo as u16);
-                12
-            }
-
-            // LD (HL-), A
-            0x32 => {
-                self.write_byte(memory, self.hl(), self.a);
-                tick(memory, 4);
-                self.set_hl(self.hl().wrapping_sub(1));
-                8
-            }
-
-            // INC SP
-            0x33 => {
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                8
-            }
-
-            // INC (HL)
-            0x34 => {
-                let addr = self.hl();
-                let val = self.read_byte(memory, addr);
-                tick(memory, 4);
-                let result = self.alu_inc(val);
-                self.write_byte(memory, addr, result);
-                tick(memory, 4);
-                12
-            }
-
-            // DEC (HL)
-            0x35 => {
-                let addr = self.hl();
-                let val = self.read_byte(memory, addr);
-                tick(memory, 4);
-                let result = self.alu_dec(val);
-                self.write_byte(memory, addr, result);
-                tick(memory, 4);
-                12
-            }
-
-            // LD (HL), d8
-            0x36 => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.write_byte(memory, self.hl(), val);
-                tick(memory, 4);
-                12
-            }
-
-            // SCF
-            0x37 => {
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, false);
-                self.set_flag(FLAG_C, true);
-                4
-            }
-
-            // JR C, r8
-            0x38 => {
-                let offset = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                if self.flag_c() {
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    tick(memory, 4);
-                    12
-                } else {
-                    8
-                }
-            }
-
-            // ADD HL, SP
-            0x39 => {
-                self.alu_add_hl(self.sp);
-                tick(memory, 4);
-                8
-            }
-
-            // LD A, (HL-)
-            0x3A => {
-                self.a = self.read_byte(memory, self.hl());
-                tick(memory, 4);
-                self.set_hl(self.hl().wrapping_sub(1));
-                8
-            }
-
-            // DEC SP
-            0x3B => {
-                self.sp = self.sp.wrapping_sub(1);
-                tick(memory, 4);
-                8
-            }
-
-            // INC A
-            0x3C => { self.a = self.alu_inc(self.a); 4 }
-
-            // DEC A
-            0x3D => { self.a = self.alu_dec(self.a); 4 }
-
-            // LD A, d8
-            0x3E => {
-                self.a = self.fetch_byte(memory);
-                tick(memory, 4);
-                8
-            }
-
-            // CCF
-            0x3F => {
-                self.set_flag(FLAG_N, false);
-                self.set_flag(FLAG_H, false);
-                self.set_flag(FLAG_C, !self.flag_c());
-                4
-            }
-
-            // LD r, r' (0x40-0x7F except 0x76)
-            0x40 => 4,
-            0x41 => { self.b = self.c; 4 }
-            0x42 => { self.b = self.d; 4 }
-            0x43 => { self.b = self.e; 4 }
-            0x44 => { self.b = self.h; 4 }
-            0x45 => { self.b = self.l; 4 }
-            0x46 => { self.b = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x47 => { self.b = self.a; 4 }
-            0x48 => { self.c = self.b; 4 }
-            0x49 => 4,
-            0x4A => { self.c = self.d; 4 }
-            0x4B => { self.c = self.e; 4 }
-            0x4C => { self.c = self.h; 4 }
-            0x4D => { self.c = self.l; 4 }
-            0x4E => { self.c = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x4F => { self.c = self.a; 4 }
-            0x50 => { self.d = self.b; 4 }
-            0x51 => { self.d = self.c; 4 }
-            0x52 => 4,
-            0x53 => { self.d = self.e; 4 }
-            0x54 => { self.d = self.h; 4 }
-            0x55 => { self.d = self.l; 4 }
-            0x56 => { self.d = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x57 => { self.d = self.a; 4 }
-            0x58 => { self.e = self.b; 4 }
-            0x59 => { self.e = self.c; 4 }
-            0x5A => { self.e = self.d; 4 }
-            0x5B => 4,
-            0x5C => { self.e = self.h; 4 }
-            0x5D => { self.e = self.l; 4 }
-            0x5E => { self.e = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x5F => { self.e = self.a; 4 }
-            0x60 => { self.h = self.b; 4 }
-            0x61 => { self.h = self.c; 4 }
-            0x62 => { self.h = self.d; 4 }
-            0x63 => { self.h = self.e; 4 }
-            0x64 => 4,
-            0x65 => { self.h = self.l; 4 }
-            0x66 => { self.h = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x67 => { self.h = self.a; 4 }
-            0x68 => { self.l = self.b; 4 }
-            0x69 => { self.l = self.c; 4 }
-            0x6A => { self.l = self.d; 4 }
-            0x6B => { self.l = self.e; 4 }
-            0x6C => { self.l = self.h; 4 }
-            0x6D => 4,
-            0x6E => { self.l = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x6F => { self.l = self.a; 4 }
-            0x70 => { self.write_byte(memory, self.hl(), self.b); tick(memory, 4); 8 }
-            0x71 => { self.write_byte(memory, self.hl(), self.c); tick(memory, 4); 8 }
-            0x72 => { self.write_byte(memory, self.hl(), self.d); tick(memory, 4); 8 }
-            0x73 => { self.write_byte(memory, self.hl(), self.e); tick(memory, 4); 8 }
-            0x74 => { self.write_byte(memory, self.hl(), self.h); tick(memory, 4); 8 }
-            0x75 => { self.write_byte(memory, self.hl(), self.l); tick(memory, 4); 8 }
-
-            // HALT
-            0x76 => {
-                let pending = memory.pending_interrupts();
-                if !self.ime && pending != 0 {
-                    self.halt_bug = true;
-                } else {
-                    self.halted = true;
-                }
-                4
-            }
-
-            0x77 => { self.write_byte(memory, self.hl(), self.a); tick(memory, 4); 8 }
-            0x78 => { self.a = self.b; 4 }
-            0x79 => { self.a = self.c; 4 }
-            0x7A => { self.a = self.d; 4 }
-            0x7B => { self.a = self.e; 4 }
-            0x7C => { self.a = self.h; 4 }
-            0x7D => { self.a = self.l; 4 }
-            0x7E => { self.a = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
-            0x7F => 4,
-
-            // ALU operations with registers
-            0x80 => { self.alu_add(self.b); 4 }
-            0x81 => { self.alu_add(self.c); 4 }
-            0x82 => { self.alu_add(self.d); 4 }
-            0x83 => { self.alu_add(self.e); 4 }
-            0x84 => { self.alu_add(self.h); 4 }
-            0x85 => { self.alu_add(self.l); 4 }
-            0x86 => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_add(v); 8 }
-            0x87 => { self.alu_add(self.a); 4 }
-            0x88 => { self.alu_adc(self.b); 4 }
-            0x89 => { self.alu_adc(self.c); 4 }
-            0x8A => { self.alu_adc(self.d); 4 }
-            0x8B => { self.alu_adc(self.e); 4 }
-            0x8C => { self.alu_adc(self.h); 4 }
-            0x8D => { self.alu_adc(self.l); 4 }
-            0x8E => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_adc(v); 8 }
-            0x8F => { self.alu_adc(self.a); 4 }
-            0x90 => { self.alu_sub(self.b); 4 }
-            0x91 => { self.alu_sub(self.c); 4 }
-            0x92 => { self.alu_sub(self.d); 4 }
-            0x93 => { self.alu_sub(self.e); 4 }
-            0x94 => { self.alu_sub(self.h); 4 }
-            0x95 => { self.alu_sub(self.l); 4 }
-            0x96 => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_sub(v); 8 }
-            0x97 => { self.alu_sub(self.a); 4 }
-            0x98 => { self.alu_sbc(self.b); 4 }
-            0x99 => { self.alu_sbc(self.c); 4 }
-            0x9A => { self.alu_sbc(self.d); 4 }
-            0x9B => { self.alu_sbc(self.e); 4 }
-            0x9C => { self.alu_sbc(self.h); 4 }
-            0x9D => { self.alu_sbc(self.l); 4 }
-            0x9E => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_sbc(v); 8 }
-            0x9F => { self.alu_sbc(self.a); 4 }
-            0xA0 => { self.alu_and(self.b); 4 }
-            0xA1 => { self.alu_and(self.c); 4 }
-            0xA2 => { self.alu_and(self.d); 4 }
-            0xA3 => { self.alu_and(self.e); 4 }
-            0xA4 => { self.alu_and(self.h); 4 }
-            0xA5 => { self.alu_and(self.l); 4 }
-            0xA6 => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_and(v); 8 }
-            0xA7 => { self.alu_and(self.a); 4 }
-            0xA8 => { self.alu_xor(self.b); 4 }
-            0xA9 => { self.alu_xor(self.c); 4 }
-            0xAA => { self.alu_xor(self.d); 4 }
-            0xAB => { self.alu_xor(self.e); 4 }
-            0xAC => { self.alu_xor(self.h); 4 }
-            0xAD => { self.alu_xor(self.l); 4 }
-            0xAE => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_xor(v); 8 }
-            0xAF => { self.alu_xor(self.a); 4 }
-            0xB0 => { self.alu_or(self.b); 4 }
-            0xB1 => { self.alu_or(self.c); 4 }
-            0xB2 => { self.alu_or(self.d); 4 }
-            0xB3 => { self.alu_or(self.e); 4 }
-            0xB4 => { self.alu_or(self.h); 4 }
-            0xB5 => { self.alu_or(self.l); 4 }
-            0xB6 => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_or(v); 8 }
-            0xB7 => { self.alu_or(self.a); 4 }
-            0xB8 => { self.alu_cp(self.b); 4 }
-            0xB9 => { self.alu_cp(self.c); 4 }
-            0xBA => { self.alu_cp(self.d); 4 }
-            0xBB => { self.alu_cp(self.e); 4 }
-            0xBC => { self.alu_cp(self.h); 4 }
-            0xBD => { self.alu_cp(self.l); 4 }
-            0xBE => { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_cp(v); 8 }
-            0xBF => { self.alu_cp(self.a); 4 }
-
-            // RET NZ
-            0xC0 => {
-                tick(memory, 4); // Internal delay
-                if !self.flag_z() {
-                    let lo = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    let hi = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    self.pc = (hi << 8) | lo;
-                    tick(memory, 4);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            // POP BC
-            0xC1 => {
-                let lo = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                let hi = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                self.set_bc((hi << 8) | lo);
-                12
-            }
-
-            // JP NZ, a16
-            0xC2 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if !self.flag_z() {
-                    self.pc = addr;
-                    tick(memory, 4);
-                    16
-                } else {
-                    12
-                }
-            }
-
-            // JP a16
-            0xC3 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.pc = ((hi as u16) << 8) | (lo as u16);
-                tick(memory, 4);
-                16
-            }
-
-            // CALL NZ, a16
-            0xC4 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if !self.flag_z() {
-                    tick(memory, 4); // Internal
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                    tick(memory, 4);
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, self.pc as u8);
-                    tick(memory, 4);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            // PUSH BC - 16 cycles: fetch(4) + internal(4) + write_hi(4) + write_lo(4)
-            0xC5 => {
-                // M2: Internal delay
-                tick(memory, 4);
-                // M3: Decrement SP, write high byte
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.b);
-                tick(memory, 4);
-                // M4: Decrement SP, write low byte
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.c);
-                tick(memory, 4);
-                16
-            }
-
-            // ADD A, d8
-            0xC6 => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_add(val);
-                8
-            }
-
-            // RST 00H
-            0xC7 => {
-                tick(memory, 4); // Internal
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0000;
-                16
-            }
-
-            // RET Z
-            0xC8 => {
-                tick(memory, 4); // Internal
-                if self.flag_z() {
-                    let lo = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    let hi = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    self.pc = (hi << 8) | lo;
-                    tick(memory, 4);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            // RET
-            0xC9 => {
-                let lo = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                let hi = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                self.pc = (hi << 8) | lo;
-                tick(memory, 4);
-                16
-            }
-
-            // JP Z, a16
-            0xCA => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if self.flag_z() {
-                    self.pc = addr;
-                    tick(memory, 4);
-                    16
-                } else {
-                    12
-                }
-            }
-
-            // CB prefix
-            0xCB => self.execute_cb_mcycle(memory, tick),
-
-            // CALL Z, a16
-            0xCC => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if self.flag_z() {
-                    tick(memory, 4); // Internal
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                    tick(memory, 4);
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, self.pc as u8);
-                    tick(memory, 4);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            // CALL a16
-            0xCD => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                tick(memory, 4); // Internal
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = addr;
-                24
-            }
-
-            // ADC A, d8
-            0xCE => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_adc(val);
-                8
-            }
-
-            // RST 08H
-            0xCF => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0008;
-                16
-            }
-
-            // RET NC
-            0xD0 => {
-                tick(memory, 4);
-                if !self.flag_c() {
-                    let lo = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    let hi = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    self.pc = (hi << 8) | lo;
-                    tick(memory, 4);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            // POP DE
-            0xD1 => {
-                let lo = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                let hi = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                self.set_de((hi << 8) | lo);
-                12
-            }
-
-            // JP NC, a16
-            0xD2 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if !self.flag_c() {
-                    self.pc = addr;
-                    tick(memory, 4);
-                    16
-                } else {
-                    12
-                }
-            }
-
-            0xD3 => panic!("Illegal opcode 0xD3"),
-
-            // CALL NC, a16
-            0xD4 => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if !self.flag_c() {
-                    tick(memory, 4);
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                    tick(memory, 4);
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, self.pc as u8);
-                    tick(memory, 4);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            // PUSH DE
-            0xD5 => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.d);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.e);
-                tick(memory, 4);
-                16
-            }
-
-            // SUB d8
-            0xD6 => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_sub(val);
-                8
-            }
-
-            // RST 10H
-            0xD7 => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0010;
-                16
-            }
-
-            // RET C
-            0xD8 => {
-                tick(memory, 4);
-                if self.flag_c() {
-                    let lo = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    let hi = memory.read_byte(self.sp) as u16;
-                    self.sp = self.sp.wrapping_add(1);
-                    tick(memory, 4);
-                    self.pc = (hi << 8) | lo;
-                    tick(memory, 4);
-                    20
-                } else {
-                    8
-                }
-            }
-
-            // RETI
-            0xD9 => {
-                let lo = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                let hi = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                self.pc = (hi << 8) | lo;
-                tick(memory, 4);
-                self.ime = true;
-                16
-            }
-
-            // JP C, a16
-            0xDA => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if self.flag_c() {
-                    self.pc = addr;
-                    tick(memory, 4);
-                    16
-                } else {
-                    12
-                }
-            }
-
-            0xDB => panic!("Illegal opcode 0xDB"),
-
-            // CALL C, a16
-            0xDC => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                if self.flag_c() {
-                    tick(memory, 4);
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                    tick(memory, 4);
-                    self.sp = self.sp.wrapping_sub(1);
-                    memory.write_byte(self.sp, self.pc as u8);
-                    tick(memory, 4);
-                    self.pc = addr;
-                    24
-                } else {
-                    12
-                }
-            }
-
-            0xDD => panic!("Illegal opcode 0xDD"),
-
-            // SBC A, d8
-            0xDE => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_sbc(val);
-                8
-            }
-
-            // RST 18H
-            0xDF => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0018;
-                16
-            }
-
-            // LDH (a8), A
-            0xE0 => {
-                let offset = self.fetch_byte(memory) as u16;
-                tick(memory, 4);
-                self.write_byte(memory, 0xFF00 + offset, self.a);
-                tick(memory, 4);
-                12
-            }
-
-            // POP HL
-            0xE1 => {
-                let lo = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                let hi = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                self.set_hl((hi << 8) | lo);
-                12
-            }
-
-            // LD (C), A
-            0xE2 => {
-                self.write_byte(memory, 0xFF00 + self.c as u16, self.a);
-                tick(memory, 4);
-                8
-            }
-
-            0xE3 => panic!("Illegal opcode 0xE3"),
-            0xE4 => panic!("Illegal opcode 0xE4"),
-
-            // PUSH HL
-            0xE5 => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.h);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.l);
-                tick(memory, 4);
-                16
-            }
-
-            // AND d8
-            0xE6 => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_and(val);
-                8
-            }
-
-            // RST 20H
-            0xE7 => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0020;
-                16
-            }
-
-            // ADD SP, r8
-            0xE8 => {
-                let val = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                self.sp = self.alu_add_sp(val);
-                tick(memory, 4);
-                tick(memory, 4);
-                16
-            }
-
-            // JP HL
-            0xE9 => {
-                self.pc = self.hl();
-                4
-            }
-
-            // LD (a16), A
-            0xEA => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                self.write_byte(memory, addr, self.a);
-                tick(memory, 4);
-                16
-            }
-
-            0xEB => panic!("Illegal opcode 0xEB"),
-            0xEC => panic!("Illegal opcode 0xEC"),
-            0xED => panic!("Illegal opcode 0xED"),
-
-            // XOR d8
-            0xEE => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_xor(val);
-                8
-            }
-
-            // RST 28H
-            0xEF => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0028;
-                16
-            }
-
-            // LDH A, (a8)
-            0xF0 => {
-                let offset = self.fetch_byte(memory) as u16;
-                tick(memory, 4);
-                self.a = self.read_byte(memory, 0xFF00 + offset);
-                tick(memory, 4);
-                12
-            }
-
-            // POP AF
-            0xF1 => {
-                let lo = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                let hi = memory.read_byte(self.sp) as u16;
-                self.sp = self.sp.wrapping_add(1);
-                tick(memory, 4);
-                self.set_af((hi << 8) | lo);
-                12
-            }
-
-            // LD A, (C)
-            0xF2 => {
-                self.a = self.read_byte(memory, 0xFF00 + self.c as u16);
-                tick(memory, 4);
-                8
-            }
-
-            // DI
-            0xF3 => {
-                self.ime = false;
-                4
-            }
-
-            0xF4 => panic!("Illegal opcode 0xF4"),
-
-            // PUSH AF
-            0xF5 => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.a);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.f);
-                tick(memory, 4);
-                16
-            }
-
-            // OR d8
-            0xF6 => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_or(val);
-                8
-            }
-
-            // RST 30H
-            0xF7 => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0030;
-                16
-            }
-
-            // LD HL, SP+r8
-            0xF8 => {
-                let val = self.fetch_byte(memory) as i8;
-                tick(memory, 4);
-                let result = self.alu_add_sp(val);
-                tick(memory, 4);
-                self.set_hl(result);
-                12
-            }
-
-            // LD SP, HL
-            0xF9 => {
-                self.sp = self.hl();
-                tick(memory, 4);
-                8
-            }
-
-            // LD A, (a16)
-            0xFA => {
-                let lo = self.fetch_byte(memory);
-                tick(memory, 4);
-                let hi = self.fetch_byte(memory);
-                tick(memory, 4);
-                let addr = ((hi as u16) << 8) | (lo as u16);
-                self.a = self.read_byte(memory, addr);
-                tick(memory, 4);
-                16
-            }
-
-            // EI
-            0xFB => {
-                self.ime_pending = true;
-                4
-            }
-
-            0xFC => panic!("Illegal opcode 0xFC"),
-            0xFD => panic!("Illegal opcode 0xFD"),
-
-            // CP d8
-            0xFE => {
-                let val = self.fetch_byte(memory);
-                tick(memory, 4);
-                self.alu_cp(val);
-                8
-            }
-
-            // RST 38H
-            0xFF => {
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, (self.pc >> 8) as u8);
-                tick(memory, 4);
-                self.sp = self.sp.wrapping_sub(1);
-                memory.write_byte(self.sp, self.pc as u8);
-                tick(memory, 4);
-                self.pc = 0x0038;
-                16
-            }
-        }
-    }
-
-    /// Execute a CB-prefixed instruction with M-cycle accurate timing
-    fn execute_cb_mcycle<F>(&mut self, memory: &mut Memory, tick: &mut F) -> u32
-    where
-        F: FnMut(&mut Memory, u32),
-    {
-        let opcode = self.fetch_byte(memory);
-        tick(memory, 4);
-
-        let reg_idx = opcode & 0x07;
-        let is_hl = reg_idx == 6;
-
-        // For (HL) operations, we need additional M-cycles for read and possibly write
-        let get_reg = |cpu: &Cpu, mem: &Memory, idx: u8| -> u8 {
-            match idx {
-                0 => cpu.b,
-                1 => cpu.c,
-                2 => cpu.d,
-                3 => cpu.e,
-                4 => cpu.h,
-                5 => cpu.l,
-                6 => cpu.read_byte(mem, cpu.hl()),
-                7 => cpu.a,
-                _ => unreachable!(),
-            }
-        };
-
-        let val = get_reg(self, memory, reg_idx);
-        if is_hl {
-            tick(memory, 4);
-        }
-
-        let (result, needs_write) = match opcode {
-            0x00..=0x07 => (self.alu_rlc(val), true),
-            0x08..=0x0F => (self.alu_rrc(val), true),
-            0x10..=0x17 => (self.alu_rl(val), true),
-            0x18..=0x1F => (self.alu_rr(val), true),
-            0x20..=0x27 => (self.alu_sla(val), true),
-            0x28..=0x2F => (self.alu_sra(val), true),
-            0x30..=0x37 => (self.alu_swap(val), true),
-            0x38..=0x3F => (self.alu_srl(val), true),
-            0x40..=0x7F => {
-                let bit = (opcode >> 3) & 0x07;
-                self.alu_bit(bit, val);
-                (0, false) // BIT doesn't write back
-            }
-            0x80..=0xBF => {
-                let bit = (opcode >> 3) & 0x07;
-                (self.alu_res(bit, val), true)
-            }
-            0xC0..=0xFF => {
-                let bit = (opcode >> 3) & 0x07;
-                (self.alu_set(bit, val), true)
-            }
-        };
-
-        if needs_write {
-            match reg_idx {
-                0 => self.b = result,
-                1 => self.c = result,
-                2 => self.d = result,
-                3 => self.e = result,
-                4 => self.h = result,
-                5 => self.l = result,
-                6 => {
-                    self.write_byte(memory, self.hl(), result);
-                    tick(memory, 4);
-                }
-                7 => self.a = result,
-                _ => unreachable!(),
-            }
-        }
-
-        // Return cycles: 8 for register, 16 for (HL) with write, 12 for BIT (HL)
-        if is_hl {
-            if needs_write { 16 } else { 12 }
-        } else {
-            8
-        }
-    }
-}
-
-impl Default for Cpu {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn reset_sets_initial_values() {
-        let mut cpu = Cpu::new();
-        cpu.reset();
-        assert_eq!(cpu.a, 0x01);
-        assert_eq!(cpu.f, 0xB0);
-        assert_eq!(cpu.sp, 0xFFFE);
-        assert_eq!(cpu.pc, 0x0100);
-    }
+        // IME on: waking, then five M-cycles of dispatch
+        cpu.halted = true;
+        cpu.ime = true;
+        assert_eq!(cpu.service_interrupts(&mut mem, &mut tick), 24);
+        assert_eq!((cpu.pc, cpu.sp, cpu.ime), (0x0050, 0xFFFC, false));
+        assert_eq!(mem.read_byte(io::IF) & 0x1F, 0x00);
+        assert_eq!([mem.read_byte(0xFFFC), mem.read_byte(0xFFFD)], [0x00, 0x01]);
+        assert_eq!(ticks, 28);
+    }
 
     #[test]
-    fn step_executes_nop() {
+    fn dispatch_cancelled_by_pushing_over_ie() {
         let mut cpu = Cpu::new();
         let mut mem = Memory::new();
-        
-        // Create a minimal ROM with NOP at 0x0100
-        let mut rom = vec![0u8; 0x8000]; // 32KB ROM
-        rom[0x0100] = 0x00; // NOP
-        mem.load_rom(&rom);
-        
+        mem.load_rom(&vec![0u8; 0x8000]);
         cpu.reset();
-        cpu.step(&mut mem);
-        assert_eq!(cpu.pc, 0x0101);
+        cpu.ime = true;
+        cpu.sp = 0x0000;
+        cpu.pc = 0x1234;
+        mem.ie = 0x01;
+        mem.write_byte(io::IF, 0x01);
+
+        // The high byte of PC lands in IE, disabling VBlank before the
+        // vector is picked
+        assert_eq!(cpu.service_interrupts(&mut mem, &mut |_, _| {}), 20);
+        assert_eq!(mem.ie, 0x12);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(mem.read_byte(io::IF) & 0x01, 0x01, "not acknowledged");
     }
 }
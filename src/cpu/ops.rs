@@ -0,0 +1,1485 @@
+//! Opcode handlers for the M-cycle CPU
+//!
+//! Every opcode has its own small handler, found through a 256-entry
+//! table (and a second one for CB-prefixed opcodes) instead of one large
+//! `match`. Each handler runs its instruction after the opcode fetch,
+//! calling `tick` after every further M-cycle, and returns the T-cycles
+//! the whole instruction took. The CB handlers share one body, specialized
+//! per opcode through a const parameter so the register and operation
+//! decoding folds away.
+
+use super::{Cpu, FLAG_C, FLAG_H, FLAG_N};
+use crate::memory::Memory;
+use std::marker::PhantomData;
+
+/// Catches the rest of the system up after each M-cycle
+pub(super) trait Tick: FnMut(&mut Memory, u32) {}
+
+impl<F: FnMut(&mut Memory, u32)> Tick for F {}
+
+/// Runs one instruction, returning its T-cycles
+type Handler<T> = fn(&mut Cpu, &mut Memory, &mut T) -> u32;
+
+/// Handler tables, one pair per tick callback type
+pub(super) struct Table<T>(PhantomData<T>);
+
+impl<T: Tick> Table<T> {
+    pub(super) const OPS: [Handler<T>; 256] = [
+        Cpu::op_00, Cpu::op_01, Cpu::op_02, Cpu::op_03, Cpu::op_04, Cpu::op_05, Cpu::op_06, Cpu::op_07,
+        Cpu::op_08, Cpu::op_09, Cpu::op_0a, Cpu::op_0b, Cpu::op_0c, Cpu::op_0d, Cpu::op_0e, Cpu::op_0f,
+        Cpu::op_10, Cpu::op_11, Cpu::op_12, Cpu::op_13, Cpu::op_14, Cpu::op_15, Cpu::op_16, Cpu::op_17,
+        Cpu::op_18, Cpu::op_19, Cpu::op_1a, Cpu::op_1b, Cpu::op_1c, Cpu::op_1d, Cpu::op_1e, Cpu::op_1f,
+        Cpu::op_20, Cpu::op_21, Cpu::op_22, Cpu::op_23, Cpu::op_24, Cpu::op_25, Cpu::op_26, Cpu::op_27,
+        Cpu::op_28, Cpu::op_29, Cpu::op_2a, Cpu::op_2b, Cpu::op_2c, Cpu::op_2d, Cpu::op_2e, Cpu::op_2f,
+        Cpu::op_30, Cpu::op_31, Cpu::op_32, Cpu::op_33, Cpu::op_34, Cpu::op_35, Cpu::op_36, Cpu::op_37,
+        Cpu::op_38, Cpu::op_39, Cpu::op_3a, Cpu::op_3b, Cpu::op_3c, Cpu::op_3d, Cpu::op_3e, Cpu::op_3f,
+        Cpu::op_40, Cpu::op_41, Cpu::op_42, Cpu::op_43, Cpu::op_44, Cpu::op_45, Cpu::op_46, Cpu::op_47,
+        Cpu::op_48, Cpu::op_49, Cpu::op_4a, Cpu::op_4b, Cpu::op_4c, Cpu::op_4d, Cpu::op_4e, Cpu::op_4f,
+        Cpu::op_50, Cpu::op_51, Cpu::op_52, Cpu::op_53, Cpu::op_54, Cpu::op_55, Cpu::op_56, Cpu::op_57,
+        Cpu::op_58, Cpu::op_59, Cpu::op_5a, Cpu::op_5b, Cpu::op_5c, Cpu::op_5d, Cpu::op_5e, Cpu::op_5f,
+        Cpu::op_60, Cpu::op_61, Cpu::op_62, Cpu::op_63, Cpu::op_64, Cpu::op_65, Cpu::op_66, Cpu::op_67,
+        Cpu::op_68, Cpu::op_69, Cpu::op_6a, Cpu::op_6b, Cpu::op_6c, Cpu::op_6d, Cpu::op_6e, Cpu::op_6f,
+        Cpu::op_70, Cpu::op_71, Cpu::op_72, Cpu::op_73, Cpu::op_74, Cpu::op_75, Cpu::op_76, Cpu::op_77,
+        Cpu::op_78, Cpu::op_79, Cpu::op_7a, Cpu::op_7b, Cpu::op_7c, Cpu::op_7d, Cpu::op_7e, Cpu::op_7f,
+        Cpu::op_80, Cpu::op_81, Cpu::op_82, Cpu::op_83, Cpu::op_84, Cpu::op_85, Cpu::op_86, Cpu::op_87,
+        Cpu::op_88, Cpu::op_89, Cpu::op_8a, Cpu::op_8b, Cpu::op_8c, Cpu::op_8d, Cpu::op_8e, Cpu::op_8f,
+        Cpu::op_90, Cpu::op_91, Cpu::op_92, Cpu::op_93, Cpu::op_94, Cpu::op_95, Cpu::op_96, Cpu::op_97,
+        Cpu::op_98, Cpu::op_99, Cpu::op_9a, Cpu::op_9b, Cpu::op_9c, Cpu::op_9d, Cpu::op_9e, Cpu::op_9f,
+        Cpu::op_a0, Cpu::op_a1, Cpu::op_a2, Cpu::op_a3, Cpu::op_a4, Cpu::op_a5, Cpu::op_a6, Cpu::op_a7,
+        Cpu::op_a8, Cpu::op_a9, Cpu::op_aa, Cpu::op_ab, Cpu::op_ac, Cpu::op_ad, Cpu::op_ae, Cpu::op_af,
+        Cpu::op_b0, Cpu::op_b1, Cpu::op_b2, Cpu::op_b3, Cpu::op_b4, Cpu::op_b5, Cpu::op_b6, Cpu::op_b7,
+        Cpu::op_b8, Cpu::op_b9, Cpu::op_ba, Cpu::op_bb, Cpu::op_bc, Cpu::op_bd, Cpu::op_be, Cpu::op_bf,
+        Cpu::op_c0, Cpu::op_c1, Cpu::op_c2, Cpu::op_c3, Cpu::op_c4, Cpu::op_c5, Cpu::op_c6, Cpu::op_c7,
+        Cpu::op_c8, Cpu::op_c9, Cpu::op_ca, Cpu::op_cb, Cpu::op_cc, Cpu::op_cd, Cpu::op_ce, Cpu::op_cf,
+        Cpu::op_d0, Cpu::op_d1, Cpu::op_d2, Cpu::op_d3, Cpu::op_d4, Cpu::op_d5, Cpu::op_d6, Cpu::op_d7,
+        Cpu::op_d8, Cpu::op_d9, Cpu::op_da, Cpu::op_db, Cpu::op_dc, Cpu::op_dd, Cpu::op_de, Cpu::op_df,
+        Cpu::op_e0, Cpu::op_e1, Cpu::op_e2, Cpu::op_e3, Cpu::op_e4, Cpu::op_e5, Cpu::op_e6, Cpu::op_e7,
+        Cpu::op_e8, Cpu::op_e9, Cpu::op_ea, Cpu::op_eb, Cpu::op_ec, Cpu::op_ed, Cpu::op_ee, Cpu::op_ef,
+        Cpu::op_f0, Cpu::op_f1, Cpu::op_f2, Cpu::op_f3, Cpu::op_f4, Cpu::op_f5, Cpu::op_f6, Cpu::op_f7,
+        Cpu::op_f8, Cpu::op_f9, Cpu::op_fa, Cpu::op_fb, Cpu::op_fc, Cpu::op_fd, Cpu::op_fe, Cpu::op_ff,
+    ];
+
+    const CB: [Handler<T>; 256] = [
+        Cpu::cb_op::<T, 0x00>, Cpu::cb_op::<T, 0x01>, Cpu::cb_op::<T, 0x02>, Cpu::cb_op::<T, 0x03>,
+        Cpu::cb_op::<T, 0x04>, Cpu::cb_op::<T, 0x05>, Cpu::cb_op::<T, 0x06>, Cpu::cb_op::<T, 0x07>,
+        Cpu::cb_op::<T, 0x08>, Cpu::cb_op::<T, 0x09>, Cpu::cb_op::<T, 0x0A>, Cpu::cb_op::<T, 0x0B>,
+        Cpu::cb_op::<T, 0x0C>, Cpu::cb_op::<T, 0x0D>, Cpu::cb_op::<T, 0x0E>, Cpu::cb_op::<T, 0x0F>,
+        Cpu::cb_op::<T, 0x10>, Cpu::cb_op::<T, 0x11>, Cpu::cb_op::<T, 0x12>, Cpu::cb_op::<T, 0x13>,
+        Cpu::cb_op::<T, 0x14>, Cpu::cb_op::<T, 0x15>, Cpu::cb_op::<T, 0x16>, Cpu::cb_op::<T, 0x17>,
+        Cpu::cb_op::<T, 0x18>, Cpu::cb_op::<T, 0x19>, Cpu::cb_op::<T, 0x1A>, Cpu::cb_op::<T, 0x1B>,
+        Cpu::cb_op::<T, 0x1C>, Cpu::cb_op::<T, 0x1D>, Cpu::cb_op::<T, 0x1E>, Cpu::cb_op::<T, 0x1F>,
+        Cpu::cb_op::<T, 0x20>, Cpu::cb_op::<T, 0x21>, Cpu::cb_op::<T, 0x22>, Cpu::cb_op::<T, 0x23>,
+        Cpu::cb_op::<T, 0x24>, Cpu::cb_op::<T, 0x25>, Cpu::cb_op::<T, 0x26>, Cpu::cb_op::<T, 0x27>,
+        Cpu::cb_op::<T, 0x28>, Cpu::cb_op::<T, 0x29>, Cpu::cb_op::<T, 0x2A>, Cpu::cb_op::<T, 0x2B>,
+        Cpu::cb_op::<T, 0x2C>, Cpu::cb_op::<T, 0x2D>, Cpu::cb_op::<T, 0x2E>, Cpu::cb_op::<T, 0x2F>,
+        Cpu::cb_op::<T, 0x30>, Cpu::cb_op::<T, 0x31>, Cpu::cb_op::<T, 0x32>, Cpu::cb_op::<T, 0x33>,
+        Cpu::cb_op::<T, 0x34>, Cpu::cb_op::<T, 0x35>, Cpu::cb_op::<T, 0x36>, Cpu::cb_op::<T, 0x37>,
+        Cpu::cb_op::<T, 0x38>, Cpu::cb_op::<T, 0x39>, Cpu::cb_op::<T, 0x3A>, Cpu::cb_op::<T, 0x3B>,
+        Cpu::cb_op::<T, 0x3C>, Cpu::cb_op::<T, 0x3D>, Cpu::cb_op::<T, 0x3E>, Cpu::cb_op::<T, 0x3F>,
+        Cpu::cb_op::<T, 0x40>, Cpu::cb_op::<T, 0x41>, Cpu::cb_op::<T, 0x42>, Cpu::cb_op::<T, 0x43>,
+        Cpu::cb_op::<T, 0x44>, Cpu::cb_op::<T, 0x45>, Cpu::cb_op::<T, 0x46>, Cpu::cb_op::<T, 0x47>,
+        Cpu::cb_op::<T, 0x48>, Cpu::cb_op::<T, 0x49>, Cpu::cb_op::<T, 0x4A>, Cpu::cb_op::<T, 0x4B>,
+        Cpu::cb_op::<T, 0x4C>, Cpu::cb_op::<T, 0x4D>, Cpu::cb_op::<T, 0x4E>, Cpu::cb_op::<T, 0x4F>,
+        Cpu::cb_op::<T, 0x50>, Cpu::cb_op::<T, 0x51>, Cpu::cb_op::<T, 0x52>, Cpu::cb_op::<T, 0x53>,
+        Cpu::cb_op::<T, 0x54>, Cpu::cb_op::<T, 0x55>, Cpu::cb_op::<T, 0x56>, Cpu::cb_op::<T, 0x57>,
+        Cpu::cb_op::<T, 0x58>, Cpu::cb_op::<T, 0x59>, Cpu::cb_op::<T, 0x5A>, Cpu::cb_op::<T, 0x5B>,
+        Cpu::cb_op::<T, 0x5C>, Cpu::cb_op::<T, 0x5D>, Cpu::cb_op::<T, 0x5E>, Cpu::cb_op::<T, 0x5F>,
+        Cpu::cb_op::<T, 0x60>, Cpu::cb_op::<T, 0x61>, Cpu::cb_op::<T, 0x62>, Cpu::cb_op::<T, 0x63>,
+        Cpu::cb_op::<T, 0x64>, Cpu::cb_op::<T, 0x65>, Cpu::cb_op::<T, 0x66>, Cpu::cb_op::<T, 0x67>,
+        Cpu::cb_op::<T, 0x68>, Cpu::cb_op::<T, 0x69>, Cpu::cb_op::<T, 0x6A>, Cpu::cb_op::<T, 0x6B>,
+        Cpu::cb_op::<T, 0x6C>, Cpu::cb_op::<T, 0x6D>, Cpu::cb_op::<T, 0x6E>, Cpu::cb_op::<T, 0x6F>,
+        Cpu::cb_op::<T, 0x70>, Cpu::cb_op::<T, 0x71>, Cpu::cb_op::<T, 0x72>, Cpu::cb_op::<T, 0x73>,
+        Cpu::cb_op::<T, 0x74>, Cpu::cb_op::<T, 0x75>, Cpu::cb_op::<T, 0x76>, Cpu::cb_op::<T, 0x77>,
+        Cpu::cb_op::<T, 0x78>, Cpu::cb_op::<T, 0x79>, Cpu::cb_op::<T, 0x7A>, Cpu::cb_op::<T, 0x7B>,
+        Cpu::cb_op::<T, 0x7C>, Cpu::cb_op::<T, 0x7D>, Cpu::cb_op::<T, 0x7E>, Cpu::cb_op::<T, 0x7F>,
+        Cpu::cb_op::<T, 0x80>, Cpu::cb_op::<T, 0x81>, Cpu::cb_op::<T, 0x82>, Cpu::cb_op::<T, 0x83>,
+        Cpu::cb_op::<T, 0x84>, Cpu::cb_op::<T, 0x85>, Cpu::cb_op::<T, 0x86>, Cpu::cb_op::<T, 0x87>,
+        Cpu::cb_op::<T, 0x88>, Cpu::cb_op::<T, 0x89>, Cpu::cb_op::<T, 0x8A>, Cpu::cb_op::<T, 0x8B>,
+        Cpu::cb_op::<T, 0x8C>, Cpu::cb_op::<T, 0x8D>, Cpu::cb_op::<T, 0x8E>, Cpu::cb_op::<T, 0x8F>,
+        Cpu::cb_op::<T, 0x90>, Cpu::cb_op::<T, 0x91>, Cpu::cb_op::<T, 0x92>, Cpu::cb_op::<T, 0x93>,
+        Cpu::cb_op::<T, 0x94>, Cpu::cb_op::<T, 0x95>, Cpu::cb_op::<T, 0x96>, Cpu::cb_op::<T, 0x97>,
+        Cpu::cb_op::<T, 0x98>, Cpu::cb_op::<T, 0x99>, Cpu::cb_op::<T, 0x9A>, Cpu::cb_op::<T, 0x9B>,
+        Cpu::cb_op::<T, 0x9C>, Cpu::cb_op::<T, 0x9D>, Cpu::cb_op::<T, 0x9E>, Cpu::cb_op::<T, 0x9F>,
+        Cpu::cb_op::<T, 0xA0>, Cpu::cb_op::<T, 0xA1>, Cpu::cb_op::<T, 0xA2>, Cpu::cb_op::<T, 0xA3>,
+        Cpu::cb_op::<T, 0xA4>, Cpu::cb_op::<T, 0xA5>, Cpu::cb_op::<T, 0xA6>, Cpu::cb_op::<T, 0xA7>,
+        Cpu::cb_op::<T, 0xA8>, Cpu::cb_op::<T, 0xA9>, Cpu::cb_op::<T, 0xAA>, Cpu::cb_op::<T, 0xAB>,
+        Cpu::cb_op::<T, 0xAC>, Cpu::cb_op::<T, 0xAD>, Cpu::cb_op::<T, 0xAE>, Cpu::cb_op::<T, 0xAF>,
+        Cpu::cb_op::<T, 0xB0>, Cpu::cb_op::<T, 0xB1>, Cpu::cb_op::<T, 0xB2>, Cpu::cb_op::<T, 0xB3>,
+        Cpu::cb_op::<T, 0xB4>, Cpu::cb_op::<T, 0xB5>, Cpu::cb_op::<T, 0xB6>, Cpu::cb_op::<T, 0xB7>,
+        Cpu::cb_op::<T, 0xB8>, Cpu::cb_op::<T, 0xB9>, Cpu::cb_op::<T, 0xBA>, Cpu::cb_op::<T, 0xBB>,
+        Cpu::cb_op::<T, 0xBC>, Cpu::cb_op::<T, 0xBD>, Cpu::cb_op::<T, 0xBE>, Cpu::cb_op::<T, 0xBF>,
+        Cpu::cb_op::<T, 0xC0>, Cpu::cb_op::<T, 0xC1>, Cpu::cb_op::<T, 0xC2>, Cpu::cb_op::<T, 0xC3>,
+        Cpu::cb_op::<T, 0xC4>, Cpu::cb_op::<T, 0xC5>, Cpu::cb_op::<T, 0xC6>, Cpu::cb_op::<T, 0xC7>,
+        Cpu::cb_op::<T, 0xC8>, Cpu::cb_op::<T, 0xC9>, Cpu::cb_op::<T, 0xCA>, Cpu::cb_op::<T, 0xCB>,
+        Cpu::cb_op::<T, 0xCC>, Cpu::cb_op::<T, 0xCD>, Cpu::cb_op::<T, 0xCE>, Cpu::cb_op::<T, 0xCF>,
+        Cpu::cb_op::<T, 0xD0>, Cpu::cb_op::<T, 0xD1>, Cpu::cb_op::<T, 0xD2>, Cpu::cb_op::<T, 0xD3>,
+        Cpu::cb_op::<T, 0xD4>, Cpu::cb_op::<T, 0xD5>, Cpu::cb_op::<T, 0xD6>, Cpu::cb_op::<T, 0xD7>,
+        Cpu::cb_op::<T, 0xD8>, Cpu::cb_op::<T, 0xD9>, Cpu::cb_op::<T, 0xDA>, Cpu::cb_op::<T, 0xDB>,
+        Cpu::cb_op::<T, 0xDC>, Cpu::cb_op::<T, 0xDD>, Cpu::cb_op::<T, 0xDE>, Cpu::cb_op::<T, 0xDF>,
+        Cpu::cb_op::<T, 0xE0>, Cpu::cb_op::<T, 0xE1>, Cpu::cb_op::<T, 0xE2>, Cpu::cb_op::<T, 0xE3>,
+        Cpu::cb_op::<T, 0xE4>, Cpu::cb_op::<T, 0xE5>, Cpu::cb_op::<T, 0xE6>, Cpu::cb_op::<T, 0xE7>,
+        Cpu::cb_op::<T, 0xE8>, Cpu::cb_op::<T, 0xE9>, Cpu::cb_op::<T, 0xEA>, Cpu::cb_op::<T, 0xEB>,
+        Cpu::cb_op::<T, 0xEC>, Cpu::cb_op::<T, 0xED>, Cpu::cb_op::<T, 0xEE>, Cpu::cb_op::<T, 0xEF>,
+        Cpu::cb_op::<T, 0xF0>, Cpu::cb_op::<T, 0xF1>, Cpu::cb_op::<T, 0xF2>, Cpu::cb_op::<T, 0xF3>,
+        Cpu::cb_op::<T, 0xF4>, Cpu::cb_op::<T, 0xF5>, Cpu::cb_op::<T, 0xF6>, Cpu::cb_op::<T, 0xF7>,
+        Cpu::cb_op::<T, 0xF8>, Cpu::cb_op::<T, 0xF9>, Cpu::cb_op::<T, 0xFA>, Cpu::cb_op::<T, 0xFB>,
+        Cpu::cb_op::<T, 0xFC>, Cpu::cb_op::<T, 0xFD>, Cpu::cb_op::<T, 0xFE>, Cpu::cb_op::<T, 0xFF>,
+    ];
+}
+
+impl Cpu {
+    /// NOP - no additional M-cycles
+    fn op_00<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+
+    /// LD BC, d16 - 2 more M-cycles for operand fetch
+    fn op_01<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.set_bc(((hi as u16) << 8) | (lo as u16));
+        12
+    }
+
+    /// LD (BC), A - 1 more M-cycle for write
+    fn op_02<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.write_byte(memory, self.bc(), self.a);
+        tick(memory, 4);
+        8
+    }
+
+    /// INC BC - 1 internal M-cycle
+    fn op_03<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.bc());
+        self.set_bc(self.bc().wrapping_add(1));
+        tick(memory, 4);
+        8
+    }
+
+    /// INC B - no additional M-cycles
+    fn op_04<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.alu_inc(self.b); 4 }
+
+    /// DEC B - no additional M-cycles
+    fn op_05<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.alu_dec(self.b); 4 }
+
+    /// LD B, d8 - 1 more M-cycle for operand
+    fn op_06<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.b = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// RLCA - no additional M-cycles
+    fn op_07<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        let carry = self.a >> 7;
+        self.a = (self.a << 1) | carry;
+        self.set_flags(false, false, false, carry != 0);
+        4
+    }
+
+    /// LD (a16), SP - 4 more M-cycles
+    fn op_08<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        memory.write_byte(addr, self.sp as u8);
+        tick(memory, 4);
+        memory.write_byte(addr.wrapping_add(1), (self.sp >> 8) as u8);
+        tick(memory, 4);
+        20
+    }
+
+    /// ADD HL, BC - 1 internal M-cycle
+    fn op_09<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.alu_add_hl(self.bc());
+        tick(memory, 4);
+        8
+    }
+
+    /// LD A, (BC) - 1 more M-cycle for read
+    fn op_0a<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.a = self.read_byte(memory, self.bc());
+        tick(memory, 4);
+        8
+    }
+
+    /// DEC BC - 1 internal M-cycle
+    fn op_0b<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.bc());
+        self.set_bc(self.bc().wrapping_sub(1));
+        tick(memory, 4);
+        8
+    }
+
+    /// INC C - no additional M-cycles
+    fn op_0c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.alu_inc(self.c); 4 }
+
+    /// DEC C - no additional M-cycles
+    fn op_0d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.alu_dec(self.c); 4 }
+
+    /// LD C, d8 - 1 more M-cycle
+    fn op_0e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.c = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// RRCA - no additional M-cycles
+    fn op_0f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        let carry = self.a & 1;
+        self.a = (self.a >> 1) | (carry << 7);
+        self.set_flags(false, false, false, carry != 0);
+        4
+    }
+
+    /// STOP (or CGB speed switch)
+    fn op_10<T: Tick>(&mut self, memory: &mut Memory, _: &mut T) -> u32 {
+        self.stop(memory);
+        4
+    }
+
+    /// LD DE, d16
+    fn op_11<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.set_de(((hi as u16) << 8) | (lo as u16));
+        12
+    }
+
+    /// LD (DE), A
+    fn op_12<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.write_byte(memory, self.de(), self.a);
+        tick(memory, 4);
+        8
+    }
+
+    /// INC DE
+    fn op_13<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.de());
+        self.set_de(self.de().wrapping_add(1));
+        tick(memory, 4);
+        8
+    }
+
+    /// INC D
+    fn op_14<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.alu_inc(self.d); 4 }
+
+    /// DEC D
+    fn op_15<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.alu_dec(self.d); 4 }
+
+    /// LD D, d8
+    fn op_16<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.d = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// RLA
+    fn op_17<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        let old_carry = if self.flag_c() { 1 } else { 0 };
+        let new_carry = self.a >> 7;
+        self.a = (self.a << 1) | old_carry;
+        self.set_flags(false, false, false, new_carry != 0);
+        4
+    }
+
+    /// JR r8
+    fn op_18<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        self.pc = self.pc.wrapping_add(offset as u16);
+        tick(memory, 4);
+        12
+    }
+
+    /// ADD HL, DE
+    fn op_19<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.alu_add_hl(self.de());
+        tick(memory, 4);
+        8
+    }
+
+    /// LD A, (DE)
+    fn op_1a<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.a = self.read_byte(memory, self.de());
+        tick(memory, 4);
+        8
+    }
+
+    /// DEC DE
+    fn op_1b<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.de());
+        self.set_de(self.de().wrapping_sub(1));
+        tick(memory, 4);
+        8
+    }
+
+    /// INC E
+    fn op_1c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.alu_inc(self.e); 4 }
+
+    /// DEC E
+    fn op_1d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.alu_dec(self.e); 4 }
+
+    /// LD E, d8
+    fn op_1e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.e = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// RRA
+    fn op_1f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        let old_carry = if self.flag_c() { 1 } else { 0 };
+        let new_carry = self.a & 1;
+        self.a = (self.a >> 1) | (old_carry << 7);
+        self.set_flags(false, false, false, new_carry != 0);
+        4
+    }
+
+    /// JR NZ, r8
+    fn op_20<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        if !self.flag_z() {
+            self.pc = self.pc.wrapping_add(offset as u16);
+            tick(memory, 4);
+            12
+        } else {
+            8
+        }
+    }
+
+    /// LD HL, d16
+    fn op_21<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.set_hl(((hi as u16) << 8) | (lo as u16));
+        12
+    }
+
+    /// LD (HL+), A
+    fn op_22<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.write_byte(memory, self.hl(), self.a);
+        tick(memory, 4);
+        self.set_hl(self.hl().wrapping_add(1));
+        8
+    }
+
+    /// INC HL
+    fn op_23<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.hl());
+        self.set_hl(self.hl().wrapping_add(1));
+        tick(memory, 4);
+        8
+    }
+
+    /// INC H
+    fn op_24<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.alu_inc(self.h); 4 }
+
+    /// DEC H
+    fn op_25<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.alu_dec(self.h); 4 }
+
+    /// LD H, d8
+    fn op_26<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.h = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// DAA
+    fn op_27<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_daa(); 4 }
+
+    /// JR Z, r8
+    fn op_28<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        if self.flag_z() {
+            self.pc = self.pc.wrapping_add(offset as u16);
+            tick(memory, 4);
+            12
+        } else {
+            8
+        }
+    }
+
+    /// ADD HL, HL
+    fn op_29<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let hl = self.hl();
+        self.alu_add_hl(hl);
+        tick(memory, 4);
+        8
+    }
+
+    /// LD A, (HL+)
+    fn op_2a<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.a = self.read_byte(memory, self.hl());
+        tick(memory, 4);
+        self.set_hl(self.hl().wrapping_add(1));
+        8
+    }
+
+    /// DEC HL
+    fn op_2b<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.hl());
+        self.set_hl(self.hl().wrapping_sub(1));
+        tick(memory, 4);
+        8
+    }
+
+    /// INC L
+    fn op_2c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.alu_inc(self.l); 4 }
+
+    /// DEC L
+    fn op_2d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.alu_dec(self.l); 4 }
+
+    /// LD L, d8
+    fn op_2e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.l = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// CPL
+    fn op_2f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        self.a = !self.a;
+        self.set_flag(FLAG_N, true);
+        self.set_flag(FLAG_H, true);
+        4
+    }
+
+    /// JR NC, r8
+    fn op_30<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        if !self.flag_c() {
+            self.pc = self.pc.wrapping_add(offset as u16);
+            tick(memory, 4);
+            12
+        } else {
+            8
+        }
+    }
+
+    /// LD SP, d16
+    fn op_31<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.sp = ((hi as u16) << 8) | (lo as u16);
+        12
+    }
+
+    /// LD (HL-), A
+    fn op_32<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.write_byte(memory, self.hl(), self.a);
+        tick(memory, 4);
+        self.set_hl(self.hl().wrapping_sub(1));
+        8
+    }
+
+    /// INC SP
+    fn op_33<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        8
+    }
+
+    /// INC (HL)
+    fn op_34<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let addr = self.hl();
+        let val = self.read_byte(memory, addr);
+        tick(memory, 4);
+        let result = self.alu_inc(val);
+        self.write_byte(memory, addr, result);
+        tick(memory, 4);
+        12
+    }
+
+    /// DEC (HL)
+    fn op_35<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let addr = self.hl();
+        let val = self.read_byte(memory, addr);
+        tick(memory, 4);
+        let result = self.alu_dec(val);
+        self.write_byte(memory, addr, result);
+        tick(memory, 4);
+        12
+    }
+
+    /// LD (HL), d8
+    fn op_36<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.write_byte(memory, self.hl(), val);
+        tick(memory, 4);
+        12
+    }
+
+    /// SCF
+    fn op_37<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        self.set_flag(FLAG_N, false);
+        self.set_flag(FLAG_H, false);
+        self.set_flag(FLAG_C, true);
+        4
+    }
+
+    /// JR C, r8
+    fn op_38<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        if self.flag_c() {
+            self.pc = self.pc.wrapping_add(offset as u16);
+            tick(memory, 4);
+            12
+        } else {
+            8
+        }
+    }
+
+    /// ADD HL, SP
+    fn op_39<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.alu_add_hl(self.sp);
+        tick(memory, 4);
+        8
+    }
+
+    /// LD A, (HL-)
+    fn op_3a<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.a = self.read_byte(memory, self.hl());
+        tick(memory, 4);
+        self.set_hl(self.hl().wrapping_sub(1));
+        8
+    }
+
+    /// DEC SP
+    fn op_3b<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        memory.oam_bug(self.sp);
+        self.sp = self.sp.wrapping_sub(1);
+        tick(memory, 4);
+        8
+    }
+
+    /// INC A
+    fn op_3c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.alu_inc(self.a); 4 }
+
+    /// DEC A
+    fn op_3d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.alu_dec(self.a); 4 }
+
+    /// LD A, d8
+    fn op_3e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.a = self.fetch_byte(memory);
+        tick(memory, 4);
+        8
+    }
+
+    /// CCF
+    fn op_3f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        self.set_flag(FLAG_N, false);
+        self.set_flag(FLAG_H, false);
+        self.set_flag(FLAG_C, !self.flag_c());
+        4
+    }
+
+    // LD r, r' (0x40-0x7F except 0x76)
+    fn op_40<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+    fn op_41<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.c; 4 }
+    fn op_42<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.d; 4 }
+    fn op_43<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.e; 4 }
+    fn op_44<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.h; 4 }
+    fn op_45<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.l; 4 }
+    fn op_46<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.b = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_47<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.b = self.a; 4 }
+    fn op_48<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.b; 4 }
+    fn op_49<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+    fn op_4a<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.d; 4 }
+    fn op_4b<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.e; 4 }
+    fn op_4c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.h; 4 }
+    fn op_4d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.l; 4 }
+    fn op_4e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.c = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_4f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.c = self.a; 4 }
+    fn op_50<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.b; 4 }
+    fn op_51<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.c; 4 }
+    fn op_52<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+    fn op_53<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.e; 4 }
+    fn op_54<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.h; 4 }
+    fn op_55<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.l; 4 }
+    fn op_56<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.d = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_57<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.d = self.a; 4 }
+    fn op_58<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.b; 4 }
+    fn op_59<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.c; 4 }
+    fn op_5a<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.d; 4 }
+    fn op_5b<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+    fn op_5c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.h; 4 }
+    fn op_5d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.l; 4 }
+    fn op_5e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.e = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_5f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.e = self.a; 4 }
+    fn op_60<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.b; 4 }
+    fn op_61<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.c; 4 }
+    fn op_62<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.d; 4 }
+    fn op_63<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.e; 4 }
+    fn op_64<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+    fn op_65<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.l; 4 }
+    fn op_66<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.h = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_67<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.h = self.a; 4 }
+    fn op_68<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.b; 4 }
+    fn op_69<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.c; 4 }
+    fn op_6a<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.d; 4 }
+    fn op_6b<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.e; 4 }
+    fn op_6c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.h; 4 }
+    fn op_6d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+    fn op_6e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.l = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_6f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.l = self.a; 4 }
+    fn op_70<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.b); tick(memory, 4); 8 }
+    fn op_71<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.c); tick(memory, 4); 8 }
+    fn op_72<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.d); tick(memory, 4); 8 }
+    fn op_73<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.e); tick(memory, 4); 8 }
+    fn op_74<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.h); tick(memory, 4); 8 }
+    fn op_75<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.l); tick(memory, 4); 8 }
+
+    /// HALT
+    fn op_76<T: Tick>(&mut self, memory: &mut Memory, _: &mut T) -> u32 {
+        let pending = memory.pending_interrupts();
+        if !self.ime && pending != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+        4
+    }
+
+    fn op_77<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.write_byte(memory, self.hl(), self.a); tick(memory, 4); 8 }
+    fn op_78<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.b; 4 }
+    fn op_79<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.c; 4 }
+    fn op_7a<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.d; 4 }
+    fn op_7b<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.e; 4 }
+    fn op_7c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.h; 4 }
+    fn op_7d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.a = self.l; 4 }
+    fn op_7e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { self.a = self.read_byte(memory, self.hl()); tick(memory, 4); 8 }
+    fn op_7f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { 4 }
+
+    // ALU operations with registers
+    fn op_80<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.b); 4 }
+    fn op_81<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.c); 4 }
+    fn op_82<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.d); 4 }
+    fn op_83<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.e); 4 }
+    fn op_84<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.h); 4 }
+    fn op_85<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.l); 4 }
+    fn op_86<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_add(v); 8 }
+    fn op_87<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_add(self.a); 4 }
+    fn op_88<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.b); 4 }
+    fn op_89<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.c); 4 }
+    fn op_8a<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.d); 4 }
+    fn op_8b<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.e); 4 }
+    fn op_8c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.h); 4 }
+    fn op_8d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.l); 4 }
+    fn op_8e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_adc(v); 8 }
+    fn op_8f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_adc(self.a); 4 }
+    fn op_90<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.b); 4 }
+    fn op_91<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.c); 4 }
+    fn op_92<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.d); 4 }
+    fn op_93<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.e); 4 }
+    fn op_94<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.h); 4 }
+    fn op_95<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.l); 4 }
+    fn op_96<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_sub(v); 8 }
+    fn op_97<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sub(self.a); 4 }
+    fn op_98<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.b); 4 }
+    fn op_99<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.c); 4 }
+    fn op_9a<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.d); 4 }
+    fn op_9b<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.e); 4 }
+    fn op_9c<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.h); 4 }
+    fn op_9d<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.l); 4 }
+    fn op_9e<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_sbc(v); 8 }
+    fn op_9f<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_sbc(self.a); 4 }
+    fn op_a0<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.b); 4 }
+    fn op_a1<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.c); 4 }
+    fn op_a2<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.d); 4 }
+    fn op_a3<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.e); 4 }
+    fn op_a4<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.h); 4 }
+    fn op_a5<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.l); 4 }
+    fn op_a6<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_and(v); 8 }
+    fn op_a7<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_and(self.a); 4 }
+    fn op_a8<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.b); 4 }
+    fn op_a9<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.c); 4 }
+    fn op_aa<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.d); 4 }
+    fn op_ab<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.e); 4 }
+    fn op_ac<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.h); 4 }
+    fn op_ad<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.l); 4 }
+    fn op_ae<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_xor(v); 8 }
+    fn op_af<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_xor(self.a); 4 }
+    fn op_b0<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.b); 4 }
+    fn op_b1<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.c); 4 }
+    fn op_b2<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.d); 4 }
+    fn op_b3<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.e); 4 }
+    fn op_b4<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.h); 4 }
+    fn op_b5<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.l); 4 }
+    fn op_b6<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_or(v); 8 }
+    fn op_b7<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_or(self.a); 4 }
+    fn op_b8<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.b); 4 }
+    fn op_b9<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.c); 4 }
+    fn op_ba<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.d); 4 }
+    fn op_bb<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.e); 4 }
+    fn op_bc<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.h); 4 }
+    fn op_bd<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.l); 4 }
+    fn op_be<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 { let v = self.read_byte(memory, self.hl()); tick(memory, 4); self.alu_cp(v); 8 }
+    fn op_bf<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { self.alu_cp(self.a); 4 }
+
+    /// RET NZ
+    fn op_c0<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4); // Internal delay
+        if !self.flag_z() {
+            let lo = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            let hi = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            self.pc = (hi << 8) | lo;
+            tick(memory, 4);
+            20
+        } else {
+            8
+        }
+    }
+
+    /// POP BC
+    fn op_c1<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        let hi = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        self.set_bc((hi << 8) | lo);
+        12
+    }
+
+    /// JP NZ, a16
+    fn op_c2<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if !self.flag_z() {
+            self.pc = addr;
+            tick(memory, 4);
+            16
+        } else {
+            12
+        }
+    }
+
+    /// JP a16
+    fn op_c3<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.pc = ((hi as u16) << 8) | (lo as u16);
+        tick(memory, 4);
+        16
+    }
+
+    /// CALL NZ, a16
+    fn op_c4<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if !self.flag_z() {
+            tick(memory, 4); // Internal
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, (self.pc >> 8) as u8);
+            tick(memory, 4);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, self.pc as u8);
+            tick(memory, 4);
+            self.pc = addr;
+            24
+        } else {
+            12
+        }
+    }
+
+    /// PUSH BC - 16 cycles: fetch(4) + internal(4) + write_hi(4) + write_lo(4)
+    fn op_c5<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        // M2: Internal delay
+        tick(memory, 4);
+        // M3: Decrement SP, write high byte
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.b);
+        tick(memory, 4);
+        // M4: Decrement SP, write low byte
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.c);
+        tick(memory, 4);
+        16
+    }
+
+    /// ADD A, d8
+    fn op_c6<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_add(val);
+        8
+    }
+
+    /// RST 00H
+    fn op_c7<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4); // Internal
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0000;
+        16
+    }
+
+    /// RET Z
+    fn op_c8<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4); // Internal
+        if self.flag_z() {
+            let lo = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            let hi = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            self.pc = (hi << 8) | lo;
+            tick(memory, 4);
+            20
+        } else {
+            8
+        }
+    }
+
+    /// RET
+    fn op_c9<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        let hi = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        self.pc = (hi << 8) | lo;
+        tick(memory, 4);
+        16
+    }
+
+    /// JP Z, a16
+    fn op_ca<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if self.flag_z() {
+            self.pc = addr;
+            tick(memory, 4);
+            16
+        } else {
+            12
+        }
+    }
+
+    /// CB prefix
+    fn op_cb<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let opcode = self.fetch_byte(memory);
+        tick(memory, 4);
+        Table::<T>::CB[opcode as usize](self, memory, tick)
+    }
+
+    /// CALL Z, a16
+    fn op_cc<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if self.flag_z() {
+            tick(memory, 4); // Internal
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, (self.pc >> 8) as u8);
+            tick(memory, 4);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, self.pc as u8);
+            tick(memory, 4);
+            self.pc = addr;
+            24
+        } else {
+            12
+        }
+    }
+
+    /// CALL a16
+    fn op_cd<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        tick(memory, 4); // Internal
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = addr;
+        24
+    }
+
+    /// ADC A, d8
+    fn op_ce<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_adc(val);
+        8
+    }
+
+    /// RST 08H
+    fn op_cf<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0008;
+        16
+    }
+
+    /// RET NC
+    fn op_d0<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        if !self.flag_c() {
+            let lo = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            let hi = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            self.pc = (hi << 8) | lo;
+            tick(memory, 4);
+            20
+        } else {
+            8
+        }
+    }
+
+    /// POP DE
+    fn op_d1<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        let hi = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        self.set_de((hi << 8) | lo);
+        12
+    }
+
+    /// JP NC, a16
+    fn op_d2<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if !self.flag_c() {
+            self.pc = addr;
+            tick(memory, 4);
+            16
+        } else {
+            12
+        }
+    }
+
+    fn op_d3<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xD3") }
+
+    /// CALL NC, a16
+    fn op_d4<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if !self.flag_c() {
+            tick(memory, 4);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, (self.pc >> 8) as u8);
+            tick(memory, 4);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, self.pc as u8);
+            tick(memory, 4);
+            self.pc = addr;
+            24
+        } else {
+            12
+        }
+    }
+
+    /// PUSH DE
+    fn op_d5<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.d);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.e);
+        tick(memory, 4);
+        16
+    }
+
+    /// SUB d8
+    fn op_d6<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_sub(val);
+        8
+    }
+
+    /// RST 10H
+    fn op_d7<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0010;
+        16
+    }
+
+    /// RET C
+    fn op_d8<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        if self.flag_c() {
+            let lo = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            let hi = memory.read_byte(self.sp) as u16;
+            self.sp = self.sp.wrapping_add(1);
+            tick(memory, 4);
+            self.pc = (hi << 8) | lo;
+            tick(memory, 4);
+            20
+        } else {
+            8
+        }
+    }
+
+    /// RETI
+    fn op_d9<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        let hi = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        self.pc = (hi << 8) | lo;
+        tick(memory, 4);
+        // Unlike EI, RETI enables interrupts straight away
+        self.ime = true;
+        self.ime_pending = false;
+        16
+    }
+
+    /// JP C, a16
+    fn op_da<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if self.flag_c() {
+            self.pc = addr;
+            tick(memory, 4);
+            16
+        } else {
+            12
+        }
+    }
+
+    fn op_db<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xDB") }
+
+    /// CALL C, a16
+    fn op_dc<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        if self.flag_c() {
+            tick(memory, 4);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, (self.pc >> 8) as u8);
+            tick(memory, 4);
+            self.sp = self.sp.wrapping_sub(1);
+            memory.write_byte(self.sp, self.pc as u8);
+            tick(memory, 4);
+            self.pc = addr;
+            24
+        } else {
+            12
+        }
+    }
+
+    fn op_dd<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xDD") }
+
+    /// SBC A, d8
+    fn op_de<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_sbc(val);
+        8
+    }
+
+    /// RST 18H
+    fn op_df<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0018;
+        16
+    }
+
+    /// LDH (a8), A
+    fn op_e0<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as u16;
+        tick(memory, 4);
+        self.write_byte(memory, 0xFF00 + offset, self.a);
+        tick(memory, 4);
+        12
+    }
+
+    /// POP HL
+    fn op_e1<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        let hi = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        self.set_hl((hi << 8) | lo);
+        12
+    }
+
+    /// LD (C), A
+    fn op_e2<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.write_byte(memory, 0xFF00 + self.c as u16, self.a);
+        tick(memory, 4);
+        8
+    }
+
+    fn op_e3<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xE3") }
+    fn op_e4<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xE4") }
+
+    /// PUSH HL
+    fn op_e5<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.h);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.l);
+        tick(memory, 4);
+        16
+    }
+
+    /// AND d8
+    fn op_e6<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_and(val);
+        8
+    }
+
+    /// RST 20H
+    fn op_e7<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0020;
+        16
+    }
+
+    /// ADD SP, r8
+    fn op_e8<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        self.sp = self.alu_add_sp(val);
+        tick(memory, 4);
+        tick(memory, 4);
+        16
+    }
+
+    /// JP HL
+    fn op_e9<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        self.pc = self.hl();
+        4
+    }
+
+    /// LD (a16), A
+    fn op_ea<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        self.write_byte(memory, addr, self.a);
+        tick(memory, 4);
+        16
+    }
+
+    fn op_eb<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xEB") }
+    fn op_ec<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xEC") }
+    fn op_ed<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xED") }
+
+    /// XOR d8
+    fn op_ee<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_xor(val);
+        8
+    }
+
+    /// RST 28H
+    fn op_ef<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0028;
+        16
+    }
+
+    /// LDH A, (a8)
+    fn op_f0<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let offset = self.fetch_byte(memory) as u16;
+        tick(memory, 4);
+        self.a = self.read_byte(memory, 0xFF00 + offset);
+        tick(memory, 4);
+        12
+    }
+
+    /// POP AF
+    fn op_f1<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        let hi = memory.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        tick(memory, 4);
+        self.set_af((hi << 8) | lo);
+        12
+    }
+
+    /// LD A, (C)
+    fn op_f2<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.a = self.read_byte(memory, 0xFF00 + self.c as u16);
+        tick(memory, 4);
+        8
+    }
+
+    /// DI
+    fn op_f3<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        // Also cancels an EI still waiting to take effect
+        self.ime = false;
+        self.ime_pending = false;
+        4
+    }
+
+    fn op_f4<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xF4") }
+
+    /// PUSH AF
+    fn op_f5<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.a);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.f);
+        tick(memory, 4);
+        16
+    }
+
+    /// OR d8
+    fn op_f6<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_or(val);
+        8
+    }
+
+    /// RST 30H
+    fn op_f7<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0030;
+        16
+    }
+
+    /// LD HL, SP+r8
+    fn op_f8<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory) as i8;
+        tick(memory, 4);
+        let result = self.alu_add_sp(val);
+        tick(memory, 4);
+        self.set_hl(result);
+        12
+    }
+
+    /// LD SP, HL
+    fn op_f9<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        self.sp = self.hl();
+        tick(memory, 4);
+        8
+    }
+
+    /// LD A, (a16)
+    fn op_fa<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let lo = self.fetch_byte(memory);
+        tick(memory, 4);
+        let hi = self.fetch_byte(memory);
+        tick(memory, 4);
+        let addr = ((hi as u16) << 8) | (lo as u16);
+        self.a = self.read_byte(memory, addr);
+        tick(memory, 4);
+        16
+    }
+
+    /// EI
+    fn op_fb<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 {
+        self.ime_pending = true;
+        4
+    }
+
+    fn op_fc<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xFC") }
+    fn op_fd<T: Tick>(&mut self, _: &mut Memory, _: &mut T) -> u32 { panic!("Illegal opcode 0xFD") }
+
+    /// CP d8
+    fn op_fe<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let val = self.fetch_byte(memory);
+        tick(memory, 4);
+        self.alu_cp(val);
+        8
+    }
+
+    /// RST 38H
+    fn op_ff<T: Tick>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, (self.pc >> 8) as u8);
+        tick(memory, 4);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_byte(self.sp, self.pc as u8);
+        tick(memory, 4);
+        self.pc = 0x0038;
+        16
+    }
+
+    /// CB-prefixed opcode `OPCODE`, after its second fetch
+    #[inline(always)]
+    fn cb_op<T: Tick, const OPCODE: u8>(&mut self, memory: &mut Memory, tick: &mut T) -> u32 {
+        let reg_idx = OPCODE & 0x07;
+        let is_hl = reg_idx == 6;
+
+        // For (HL) operations, we need additional M-cycles for read and possibly write
+        let get_reg = |cpu: &Cpu, mem: &Memory, idx: u8| -> u8 {
+            match idx {
+                0 => cpu.b,
+                1 => cpu.c,
+                2 => cpu.d,
+                3 => cpu.e,
+                4 => cpu.h,
+                5 => cpu.l,
+                6 => cpu.read_byte(mem, cpu.hl()),
+                7 => cpu.a,
+                _ => unreachable!(),
+            }
+        };
+
+        let val = get_reg(self, memory, reg_idx);
+        if is_hl {
+            tick(memory, 4);
+        }
+
+        let (result, needs_write) = match OPCODE {
+            0x00..=0x07 => (self.alu_rlc(val), true),
+            0x08..=0x0F => (self.alu_rrc(val), true),
+            0x10..=0x17 => (self.alu_rl(val), true),
+            0x18..=0x1F => (self.alu_rr(val), true),
+            0x20..=0x27 => (self.alu_sla(val), true),
+            0x28..=0x2F => (self.alu_sra(val), true),
+            0x30..=0x37 => (self.alu_swap(val), true),
+            0x38..=0x3F => (self.alu_srl(val), true),
+            0x40..=0x7F => {
+                let bit = (OPCODE >> 3) & 0x07;
+                self.alu_bit(bit, val);
+                (0, false) // BIT doesn't write back
+            }
+            0x80..=0xBF => {
+                let bit = (OPCODE >> 3) & 0x07;
+                (self.alu_res(bit, val), true)
+            }
+            0xC0..=0xFF => {
+                let bit = (OPCODE >> 3) & 0x07;
+                (self.alu_set(bit, val), true)
+            }
+        };
+
+        if needs_write {
+            match reg_idx {
+                0 => self.b = result,
+                1 => self.c = result,
+                2 => self.d = result,
+                3 => self.e = result,
+                4 => self.h = result,
+                5 => self.l = result,
+                6 => {
+                    self.write_byte(memory, self.hl(), result);
+                    tick(memory, 4);
+                }
+                7 => self.a = result,
+                _ => unreachable!(),
+            }
+        }
+
+        // Return cycles: 8 for register, 16 for (HL) with write, 12 for BIT (HL)
+        if is_hl {
+            if needs_write { 16 } else { 12 }
+        } else {
+            8
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Debugger window for the desktop UI
+//!
+//! F9 opens a second window with the CPU registers, flags and mapped ROM
+//! bank next to a disassembly of the code at PC. Clicking a line toggles
+//! a breakpoint on it. The buttons step one instruction, step over a
+//! call, or break and resume emulation; a running game stops by itself
+//! when it reaches a breakpoint, and closing the window removes them all
+//! so the game can't stop with nothing on screen to say why. Below them,
+//! the last interrupts dispatched show how many cycles each waited since
+//! its request.
+
+use crate::emu_thread::Session;
+use crate::ui::{draw_rect, draw_text, fill_rect};
+use gb3000::{Emulator, Instruction, InterruptEvent};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+
+/// Window buffer size; the window shows it at 2x
+const WIDTH: usize = 400;
+const HEIGHT: usize = 300;
+
+/// Disassembly lines shown
+const LINES: usize = 28;
+const LINE_HEIGHT: usize = 10;
+/// Width of the disassembly column
+const CODE_WIDTH: usize = 268;
+
+/// Interrupt dispatches listed
+const INTERRUPTS: usize = 3;
+
+/// Lines kept below PC before the view jumps to follow it
+const FOLLOW_MARGIN: usize = 4;
+
+const BACKGROUND: u32 = 0xFF1A1A2E;
+const TEXT: u32 = 0xFFE5E7EB;
+const DIM: u32 = 0xFF6B7280;
+const ACCENT: u32 = 0xFF0EA5E9;
+const BREAKPOINT: u32 = 0xFFEF4444;
+
+pub struct Debugger {
+    window: Window,
+    buffer: Vec<u32>,
+    /// First address in the disassembly
+    top: u16,
+    /// Mouse button state last frame, to detect clicks
+    mouse_was_down: bool,
+    /// Whether the emulator's interrupt log was turned on for the window
+    logging_interrupts: bool,
+}
+
+/// What the buttons ask for
+enum Command {
+    Step,
+    StepOver,
+    ToggleRun,
+}
+
+impl Debugger {
+    pub fn open() -> Self {
+        let mut window = Window::new(
+            "GB3000 - Debugger",
+            WIDTH,
+            HEIGHT,
+            WindowOptions { scale: Scale::X2, ..WindowOptions::default() },
+        )
+        .expect("Failed to create debugger window");
+        window.set_target_fps(0);
+        Self { window, buffer: vec![0; WIDTH * HEIGHT], top: 0, mouse_was_down: false, logging_interrupts: false }
+    }
+
+    /// Handle input and redraw
+    ///
+    /// Returns false once the window was closed, with its close button or
+    /// F9.
+    pub fn update(&mut self, session: &mut Session) -> bool {
+        if !self.window.is_open() || self.window.is_key_pressed(Key::F9, KeyRepeat::No) {
+            return false;
+        }
+        let mouse = self.window.get_mouse_pos(MouseMode::Discard).map(|(x, y)| (x as usize, y as usize));
+        let down = self.window.get_mouse_down(MouseButton::Left);
+        let click = mouse.filter(|_| down && !self.mouse_was_down);
+        self.mouse_was_down = down;
+
+        let pc = session.emulator.cpu_state().pc;
+        self.top = follow_pc(&session.emulator, self.top, pc);
+        let lines = disassembly(&session.emulator, self.top, LINES);
+        fill_rect(&mut self.buffer, WIDTH, 0, 0, WIDTH, HEIGHT, BACKGROUND);
+        if let Some(addr) = self.draw_code(&session.emulator, &lines, mouse, click) {
+            if session.emulator.breakpoints().contains(&addr) {
+                session.emulator.remove_breakpoint(addr);
+            } else {
+                session.emulator.add_breakpoint(addr);
+            }
+        }
+        self.draw_registers(session);
+        self.draw_interrupts(&mut session.emulator);
+
+        match self.draw_buttons(session.debug_break, mouse, click) {
+            Some(Command::Step) => {
+                session.debug_break = true;
+                session.emulator.step_instruction();
+            }
+            Some(Command::StepOver) => {
+                session.debug_break = true;
+                session.emulator.step_over();
+            }
+            Some(Command::ToggleRun) => session.debug_break = !session.debug_break,
+            None => {}
+        }
+
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .expect("Failed to update debugger window");
+        true
+    }
+
+    /// Draw the disassembly, returning the address of a clicked line
+    fn draw_code(
+        &mut self,
+        emulator: &Emulator,
+        lines: &[(u16, Instruction)],
+        mouse: Option<(usize, usize)>,
+        click: Option<(usize, usize)>,
+    ) -> Option<u16> {
+        let pc = emulator.cpu_state().pc;
+        let buffer = &mut self.buffer;
+        let mut clicked = None;
+        for (i, (addr, instruction)) in lines.iter().enumerate() {
+            let y = 6 + i * LINE_HEIGHT;
+            let on_line = |(mx, my): (usize, usize)| mx < CODE_WIDTH && (y..y + LINE_HEIGHT).contains(&my);
+            if *addr == pc {
+                fill_rect(buffer, WIDTH, 2, y, CODE_WIDTH, LINE_HEIGHT, 0xFF374151);
+            } else if mouse.is_some_and(on_line) {
+                fill_rect(buffer, WIDTH, 2, y, CODE_WIDTH, LINE_HEIGHT, 0x30FFFFFF);
+            }
+            if click.is_some_and(on_line) {
+                clicked = Some(*addr);
+            }
+            if emulator.breakpoints().contains(addr) {
+                fill_rect(buffer, WIDTH, 5, y + 2, 6, 6, BREAKPOINT);
+            }
+
+            let bytes: Vec<String> = (0..instruction.len as u16)
+                .map(|n| format!("{:02X}", emulator.peek(addr.wrapping_add(n))))
+                .collect();
+            draw_text(buffer, WIDTH, 16, y + 1, &format!("{:04X}", addr), DIM);
+            draw_text(buffer, WIDTH, 56, y + 1, &bytes.join(""), DIM);
+            draw_text(buffer, WIDTH, 112, y + 1, &instruction.text, TEXT);
+        }
+        clicked
+    }
+
+    fn draw_registers(&mut self, session: &Session) {
+        let buffer = &mut self.buffer;
+        let cpu = session.emulator.cpu_state();
+        let x = CODE_WIDTH + 12;
+        let pairs = [
+            ("AF", u16::from_be_bytes([cpu.a, cpu.f])),
+            ("BC", u16::from_be_bytes([cpu.b, cpu.c])),
+            ("DE", u16::from_be_bytes([cpu.d, cpu.e])),
+            ("HL", u16::from_be_bytes([cpu.h, cpu.l])),
+            ("SP", cpu.sp),
+            ("PC", cpu.pc),
+        ];
+        for (i, (name, value)) in pairs.iter().enumerate() {
+            let y = 8 + i * 12;
+            draw_text(buffer, WIDTH, x, y, name, DIM);
+            draw_text(buffer, WIDTH, x + 24, y, &format!("{:04X}", value), TEXT);
+        }
+
+        // Flags, lit when set
+        for (i, flag) in ["Z", "N", "H", "C"].iter().enumerate() {
+            let set = cpu.f & (0x80 >> i) != 0;
+            draw_text(buffer, WIDTH, x + i * 16, 86, flag, if set { ACCENT } else { DIM });
+        }
+        draw_text(buffer, WIDTH, x, 102, "IME", DIM);
+        draw_text(buffer, WIDTH, x + 32, 102, if cpu.ime { "on" } else { "off" }, TEXT);
+        draw_text(buffer, WIDTH, x, 114, "ROM", DIM);
+        draw_text(buffer, WIDTH, x + 32, 114, &format!("{:02X}", cpu.rom_bank), TEXT);
+        if cpu.halted || cpu.stopped {
+            draw_text(buffer, WIDTH, x, 126, if cpu.stopped { "STOP" } else { "HALT" }, TEXT);
+        }
+
+        let (status, color) = match (session.debug_break, session.emulator.breakpoint_hit()) {
+            (false, _) => ("Running".to_string(), 0xFF4ADE80),
+            (true, Some(addr)) if addr == cpu.pc => (format!("Break {:04X}", addr), 0xFFFACC15),
+            (true, _) => ("Break".to_string(), 0xFFFACC15),
+        };
+        draw_text(buffer, WIDTH, x, 146, &status, color);
+    }
+
+    /// List the latest interrupt dispatches and their wait in cycles,
+    /// logging them from the first update on
+    fn draw_interrupts(&mut self, emulator: &mut Emulator) {
+        if !self.logging_interrupts {
+            emulator.set_interrupt_logging(true);
+            self.logging_interrupts = true;
+        }
+        let x = CODE_WIDTH + 12;
+        draw_text(&mut self.buffer, WIDTH, x, 254, "IRQ", DIM);
+        draw_text(&mut self.buffer, WIDTH, x + 56, 254, "Wait", DIM);
+        let serviced = emulator.interrupt_log().rev().filter_map(|event| match *event {
+            InterruptEvent::Serviced { interrupt, latency, .. } => Some((interrupt, latency)),
+            InterruptEvent::Requested { .. } => None,
+        });
+        for (i, (interrupt, latency)) in serviced.take(INTERRUPTS).enumerate() {
+            let y = 266 + i * LINE_HEIGHT;
+            let wait = latency.map_or_else(|| "?".to_string(), |cycles| cycles.to_string());
+            draw_text(&mut self.buffer, WIDTH, x, y, &interrupt.to_string(), TEXT);
+            draw_text(&mut self.buffer, WIDTH, x + 56, y, &wait, TEXT);
+        }
+    }
+
+    /// Draw the step and run buttons, returning the one clicked
+    fn draw_buttons(
+        &mut self,
+        stopped: bool,
+        mouse: Option<(usize, usize)>,
+        click: Option<(usize, usize)>,
+    ) -> Option<Command> {
+        let buttons = [
+            ("Step", Command::Step),
+            ("Step over", Command::StepOver),
+            (if stopped { "Run" } else { "Break" }, Command::ToggleRun),
+        ];
+        let (x, w, h) = (CODE_WIDTH + 12, WIDTH - CODE_WIDTH - 20, 20);
+        let mut clicked = None;
+        for (i, (label, command)) in buttons.into_iter().enumerate() {
+            let y = 170 + i * (h + 6);
+            let inside = |(mx, my): (usize, usize)| (x..x + w).contains(&mx) && (y..y + h).contains(&my);
+            let hover = mouse.is_some_and(inside);
+            fill_rect(&mut self.buffer, WIDTH, x, y, w, h, if hover { 0xFF374151 } else { 0xFF1F2937 });
+            draw_rect(&mut self.buffer, WIDTH, x, y, w, h, 0xFF4B5563);
+            let text_x = x + w.saturating_sub(label.len() * 8) / 2;
+            draw_text(&mut self.buffer, WIDTH, text_x, y + 6, label, TEXT);
+            if click.is_some_and(inside) {
+                clicked = Some(command);
+            }
+        }
+        clicked
+    }
+}
+
+/// Decode `count` instructions starting at `addr`
+fn disassembly(emulator: &Emulator, addr: u16, count: usize) -> Vec<(u16, Instruction)> {
+    let mut addr = addr;
+    (0..count)
+        .map(|_| {
+            let instruction = emulator.disassemble(addr);
+            let line = (addr, instruction);
+            addr = addr.wrapping_add(line.1.len as u16);
+            line
+        })
+        .collect()
+}
+
+/// Where the disassembly should start so that `pc` is on screen
+///
+/// The view stays put while PC moves within it and jumps to put PC at the
+/// top once it gets near the bottom or leaves it. Code can't be decoded
+/// backwards reliably, so earlier instructions aren't shown.
+fn follow_pc(emulator: &Emulator, top: u16, pc: u16) -> u16 {
+    let visible = disassembly(emulator, top, LINES - FOLLOW_MARGIN);
+    if visible.iter().any(|&(addr, _)| addr == pc) {
+        top
+    } else {
+        pc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_follows_pc() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD BC,$1234 then NOPs
+        rom[0x0100..0x0103].copy_from_slice(&[0x01, 0x34, 0x12]);
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&rom);
+
+        let lines = disassembly(&emulator, 0x0100, 3);
+        let addrs: Vec<u16> = lines.iter().map(|&(addr, _)| addr).collect();
+        assert_eq!(addrs, [0x0100, 0x0103, 0x0104]);
+        assert_eq!(lines[0].1.text, "LD BC,$1234");
+
+        // Within the view it stays, past the margin it jumps
+        assert_eq!(follow_pc(&emulator, 0x0100, 0x0110), 0x0100);
+        let last = 0x0103 + (LINES - FOLLOW_MARGIN - 2) as u16;
+        assert_eq!(follow_pc(&emulator, 0x0100, last), 0x0100);
+        assert_eq!(follow_pc(&emulator, 0x0100, last + 1), last + 1);
+        // Mid-instruction addresses aren't in the view either
+        assert_eq!(follow_pc(&emulator, 0x0100, 0x0101), 0x0101);
+    }
+}
@@ -0,0 +1,158 @@
+//! SM83 disassembler
+//!
+//! Decodes one instruction at a time into conventional assembly text,
+//! for debuggers and trace logs. Immediates are written in hex with a `$`
+//! prefix; relative jumps show their target address rather than the
+//! offset, and opcodes the CPU doesn't implement come out as `DB $xx`.
+
+/// One decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Bytes the instruction takes, 1 to 3
+    pub len: u8,
+    /// Assembly text, e.g. `LDH ($FF44),A`
+    pub text: String,
+}
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+/// Decode the instruction at `addr`, reading memory through `read`
+pub fn disassemble(read: impl Fn(u16) -> u8, addr: u16) -> Instruction {
+    let op = read(addr);
+    let d8 = read(addr.wrapping_add(1));
+    let d16 = u16::from_le_bytes([d8, read(addr.wrapping_add(2))]);
+    let e = d8 as i8;
+    let jr_target = addr.wrapping_add(2).wrapping_add(e as u16);
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+    let (p, q) = ((y >> 1) as usize, y & 1);
+    let (y, z) = (y as usize, z as usize);
+
+    let (len, text) = match (x, z) {
+        (0, 0) => match y {
+            0 => (1, "NOP".to_string()),
+            1 => (3, format!("LD (${:04X}),SP", d16)),
+            2 => (2, "STOP".to_string()),
+            3 => (2, format!("JR ${:04X}", jr_target)),
+            _ => (2, format!("JR {},${:04X}", CC[y - 4], jr_target)),
+        },
+        (0, 1) if q == 0 => (3, format!("LD {},${:04X}", RP[p], d16)),
+        (0, 1) => (1, format!("ADD HL,{}", RP[p])),
+        (0, 2) => {
+            let mem = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            match q {
+                0 => (1, format!("LD {},A", mem)),
+                _ => (1, format!("LD A,{}", mem)),
+            }
+        }
+        (0, 3) => (1, format!("{} {}", if q == 0 { "INC" } else { "DEC" }, RP[p])),
+        (0, 4) => (1, format!("INC {}", R[y])),
+        (0, 5) => (1, format!("DEC {}", R[y])),
+        (0, 6) => (2, format!("LD {},${:02X}", R[y], d8)),
+        (0, _) => (1, ACC[y].to_string()),
+        (1, _) if op == 0x76 => (1, "HALT".to_string()),
+        (1, _) => (1, format!("LD {},{}", R[y], R[z])),
+        (2, _) => (1, format!("{}{}", ALU[y], R[z])),
+        (_, 0) => match y {
+            0..=3 => (1, format!("RET {}", CC[y])),
+            4 => (2, format!("LDH ($FF{:02X}),A", d8)),
+            5 => (2, format!("ADD SP,{}", e)),
+            6 => (2, format!("LDH A,($FF{:02X})", d8)),
+            _ => (2, format!("LD HL,SP{:+}", e)),
+        },
+        (_, 1) if q == 0 => (1, format!("POP {}", RP2[p])),
+        (_, 1) => (1, ["RET", "RETI", "JP HL", "LD SP,HL"][p].to_string()),
+        (_, 2) => match y {
+            0..=3 => (3, format!("JP {},${:04X}", CC[y], d16)),
+            4 => (1, "LD (C),A".to_string()),
+            5 => (3, format!("LD (${:04X}),A", d16)),
+            6 => (1, "LD A,(C)".to_string()),
+            _ => (3, format!("LD A,(${:04X})", d16)),
+        },
+        (_, 3) => match y {
+            0 => (3, format!("JP ${:04X}", d16)),
+            1 => (2, prefixed(d8)),
+            6 => (1, "DI".to_string()),
+            7 => (1, "EI".to_string()),
+            _ => (1, format!("DB ${:02X}", op)),
+        },
+        (_, 4) if y < 4 => (3, format!("CALL {},${:04X}", CC[y], d16)),
+        (_, 5) if q == 0 => (1, format!("PUSH {}", RP2[p])),
+        (_, 5) if p == 0 => (3, format!("CALL ${:04X}", d16)),
+        (_, 6) => (2, format!("{}${:02X}", ALU[y], d8)),
+        (_, 7) => (1, format!("RST ${:02X}", y * 8)),
+        _ => (1, format!("DB ${:02X}", op)),
+    };
+    Instruction { len, text }
+}
+
+/// Text of the CB-prefixed instruction `op`
+fn prefixed(op: u8) -> String {
+    let (y, z) = (((op >> 3) & 7) as usize, (op & 7) as usize);
+    match op >> 6 {
+        0 => format!("{} {}", ROT[y], R[z]),
+        1 => format!("BIT {},{}", y, R[z]),
+        2 => format!("RES {},{}", y, R[z]),
+        _ => format!("SET {},{}", y, R[z]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], addr: u16) -> (u8, String) {
+        let i = disassemble(|a| bytes.get(a.wrapping_sub(addr) as usize).copied().unwrap_or(0), addr);
+        (i.len, i.text)
+    }
+
+    #[test]
+    fn decodes_each_instruction_group() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x00], "NOP"),
+            (&[0x01, 0x34, 0x12], "LD BC,$1234"),
+            (&[0x08, 0x00, 0xC0], "LD ($C000),SP"),
+            (&[0x2A], "LD A,(HL+)"),
+            (&[0x32], "LD (HL-),A"),
+            (&[0x36, 0x7F], "LD (HL),$7F"),
+            (&[0x2F], "CPL"),
+            (&[0x76], "HALT"),
+            (&[0x7E], "LD A,(HL)"),
+            (&[0x91], "SUB C"),
+            (&[0x8E], "ADC A,(HL)"),
+            (&[0xE0, 0x44], "LDH ($FF44),A"),
+            (&[0xE8, 0xFE], "ADD SP,-2"),
+            (&[0xF8, 0x05], "LD HL,SP+5"),
+            (&[0xD9], "RETI"),
+            (&[0xE9], "JP HL"),
+            (&[0xF2], "LD A,(C)"),
+            (&[0xFA, 0x00, 0xFF], "LD A,($FF00)"),
+            (&[0xDC, 0x50, 0x01], "CALL C,$0150"),
+            (&[0xCD, 0x00, 0x40], "CALL $4000"),
+            (&[0xF5], "PUSH AF"),
+            (&[0xFE, 0x90], "CP $90"),
+            (&[0xFF], "RST $38"),
+            (&[0xCB, 0x37], "SWAP A"),
+            (&[0xCB, 0x7C], "BIT 7,H"),
+            (&[0xCB, 0xBE], "RES 7,(HL)"),
+            (&[0xCB, 0xC0], "SET 0,B"),
+            (&[0xD3], "DB $D3"),
+            (&[0xFD], "DB $FD"),
+        ];
+        for &(bytes, text) in cases {
+            assert_eq!(decode(bytes, 0x0100), (bytes.len() as u8, text.to_string()), "{:02X?}", bytes);
+        }
+    }
+
+    #[test]
+    fn relative_jumps_show_their_target() {
+        assert_eq!(decode(&[0x18, 0xFE], 0x0150), (2, "JR $0150".to_string()));
+        assert_eq!(decode(&[0x20, 0x10], 0x0150), (2, "JR NZ,$0162".to_string()));
+        assert_eq!(decode(&[0x10, 0x00], 0x0150), (2, "STOP".to_string()));
+    }
+}
@@ -0,0 +1,134 @@
+//! Emulation driving for the desktop UI
+//!
+//! Decides how many emulated frames run per host frame and keeps the
+//! rewind history, without touching the window or audio device. Two
+//! consoles played side by side run here too, in lockstep.
+
+use gb3000::{Emulator, RewindBuffer};
+
+/// Emulated frames between rewind snapshots
+const REWIND_INTERVAL: u32 = 2;
+
+/// Memory budget for rewind history, roughly a minute of gameplay
+const REWIND_BUFFER_BYTES: usize = 32 * 1024 * 1024;
+
+/// T-cycles each linked console runs before the other takes a turn, one
+/// scanline: short enough for games timing each other's IR pulses
+pub const LINK_SLICE: u32 = 456;
+
+/// T-cycles in a frame, the most a linked frame runs with the LCD off
+const FRAME_CYCLES: u64 = 70_224;
+
+pub struct FrameDriver {
+    /// Emulated frames owed to the speed setting (fractional below 1x)
+    frame_budget: f64,
+    /// Rewind history (hold Backspace)
+    rewind: RewindBuffer,
+    snapshot: Vec<u8>,
+    frames_since_snapshot: u32,
+}
+
+impl FrameDriver {
+    pub fn new() -> Self {
+        Self {
+            frame_budget: 0.0,
+            rewind: RewindBuffer::new(REWIND_BUFFER_BYTES),
+            snapshot: Vec::new(),
+            frames_since_snapshot: 0,
+        }
+    }
+
+    /// Forget the rewind history, e.g. after loading a state or a new game
+    pub fn clear_rewind(&mut self) {
+        self.rewind.clear();
+    }
+
+    /// Advance by one host frame
+    ///
+    /// While rewinding this steps back one snapshot; otherwise it runs
+    /// however many emulated frames `speed` makes due, calling `on_frame`
+    /// after each. Above 1x only the last one ends up on screen, so the
+    /// others are not drawn unless `draw_all` asks for every frame.
+    pub fn advance(
+        &mut self,
+        emulator: &mut Emulator,
+        speed: f64,
+        rewinding: bool,
+        draw_all: bool,
+        mut on_frame: impl FnMut(&mut Emulator),
+    ) {
+        if rewinding {
+            if let Some(state) = self.rewind.pop() {
+                if let Err(e) = emulator.load_state(&state) {
+                    eprintln!("Rewind failed: {}", e);
+                }
+            }
+            return;
+        }
+
+        self.frame_budget += speed;
+        while self.frame_budget >= 1.0 {
+            self.frame_budget -= 1.0;
+            emulator.set_video_enabled(draw_all || self.frame_budget < 1.0);
+            emulator.run_frame();
+            if emulator.breakpoint_hit().is_some() {
+                // Stopped mid-frame; the debugger takes over from here
+                self.frame_budget = 0.0;
+                return;
+            }
+            on_frame(emulator);
+
+            self.frames_since_snapshot += 1;
+            if self.frames_since_snapshot >= REWIND_INTERVAL {
+                self.frames_since_snapshot = 0;
+                emulator.save_state_into(&mut self.snapshot);
+                self.rewind.push(&self.snapshot);
+            }
+        }
+    }
+
+    /// Advance two linked consoles by one host frame
+    ///
+    /// Like [`advance`](Self::advance), with frames counted on `emulator`
+    /// and `partner` kept in lockstep by [`run_linked_frame`]. There is no
+    /// rewind: stepping back only one side would break the link.
+    pub fn advance_linked(
+        &mut self,
+        emulator: &mut Emulator,
+        partner: &mut Emulator,
+        speed: f64,
+        draw_all: bool,
+        mut on_frame: impl FnMut(&mut Emulator, &mut Emulator),
+    ) {
+        self.frame_budget += speed;
+        while self.frame_budget >= 1.0 {
+            self.frame_budget -= 1.0;
+            let draw = draw_all || self.frame_budget < 1.0;
+            emulator.set_video_enabled(draw);
+            partner.set_video_enabled(draw);
+            run_linked_frame(emulator, partner);
+            if emulator.breakpoint_hit().is_some() {
+                self.frame_budget = 0.0;
+                return;
+            }
+            on_frame(emulator, partner);
+        }
+    }
+}
+
+/// Run `emulator` to the end of its frame with `partner` alongside,
+/// taking turns a scanline at a time so each sees the other's link cable
+/// and IR traffic promptly
+///
+/// Stops at a breakpoint in `emulator`; the partner's breakpoints are
+/// not watched.
+pub fn run_linked_frame(emulator: &mut Emulator, partner: &mut Emulator) {
+    let (frame, start) = (emulator.frame_count(), emulator.total_cycles());
+    while emulator.frame_count() == frame && emulator.total_cycles() - start < FRAME_CYCLES {
+        emulator.run_cycles(LINK_SLICE);
+        partner.run_cycles(LINK_SLICE);
+        if emulator.breakpoint_hit().is_some() {
+            return;
+        }
+    }
+}
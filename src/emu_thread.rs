@@ -0,0 +1,426 @@
+//! Emulation thread for the desktop UI
+//!
+//! The emulator runs on its own thread and keeps pace with the audio
+//! device (or the frame timer) there, so slow work on the window thread,
+//! such as a file dialog or drawing a menu, can't starve the audio output.
+//!
+//! Both threads share one [`Session`] behind a mutex. The emulation
+//! thread holds it while running a host frame and lets go while waiting;
+//! the window thread locks it to set input and controls, read the latest
+//! frame, and for anything else it needs from the emulator.
+//!
+//! A second console can join the session for link cable play; the two
+//! then run in lockstep, a scanline at a time.
+
+use crate::battery::BatterySaver;
+use crate::capture::Recorder;
+use crate::driver::{self, FrameDriver};
+use crate::input::Input;
+#[cfg(feature = "lua")]
+use gb3000::lua::Script;
+use gb3000::{Emulator, GbsFile};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Target frame time - Game Boy native rate (59.7275 FPS)
+/// CPU: 4,194,304 Hz / 70,224 cycles per frame = 59.7275 FPS
+pub const FRAME_TIME_NS: u64 = 16_742_706; // ~59.7275 FPS
+
+/// Audio buffer size
+pub const AUDIO_BUFFER_SIZE: usize = 4096;
+
+/// Buffer fill (interleaved samples) audio-sync pacing waits for, ~23 ms
+const AUDIO_TARGET_FILL: usize = AUDIO_BUFFER_SIZE / 2;
+
+/// Interleaved samples produced per frame at 44100 Hz
+const AUDIO_FRAME_SAMPLES: usize = 1477;
+
+/// Largest resampling nudge used to hold the buffer at the target
+const AUDIO_RATE_CONTROL: f64 = 0.005;
+
+/// Samples waiting for the audio device
+pub type AudioBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+/// The second console of a session, played side by side with the first
+pub struct Partner {
+    pub emulator: Emulator,
+    /// Battery save of its ROM
+    pub battery: Option<BatterySaver>,
+    /// Player 2's keys, applied before each frame
+    pub input: Input,
+}
+
+/// Everything the emulation thread runs, and how the window wants it run
+pub struct Session {
+    pub emulator: Emulator,
+    /// Frame pacing and rewind history
+    pub driver: FrameDriver,
+    /// Battery save of the loaded ROM
+    pub battery: Option<BatterySaver>,
+    /// Gameplay recording (None when not recording)
+    pub recorder: Option<Recorder>,
+    /// Keys held, applied to the emulator before each frame
+    pub input: Input,
+    /// Second console linked to the first (None when playing alone)
+    pub partner: Option<Partner>,
+    /// GBS file playing in place of a game, and the song it's on
+    pub gbs: Option<(GbsFile, u8)>,
+    /// Lua script run after each frame of the first console
+    #[cfg(feature = "lua")]
+    pub script: Option<Script>,
+    /// Whether frames run at all (not on menus or the start screen)
+    pub running: bool,
+    /// Held by the debugger, at a breakpoint or while stepping
+    pub debug_break: bool,
+    /// Emulation speed, 1.0 being real time
+    pub speed: f64,
+    /// Step back through the rewind history instead of running
+    pub rewinding: bool,
+    /// Follow the audio device rather than the frame timer at 1x
+    pub audio_sync: bool,
+    /// Whether an output stream is draining the audio buffer; pacing
+    /// falls back to the frame timer while the device is gone
+    pub audio_live: bool,
+    /// Emulated frames run since start, for the FPS display
+    pub frames: u64,
+    /// Set to end the thread
+    quit: bool,
+}
+
+impl Session {
+    pub fn new(emulator: Emulator) -> Self {
+        Self {
+            emulator,
+            driver: FrameDriver::new(),
+            battery: None,
+            recorder: None,
+            input: Input::default(),
+            partner: None,
+            gbs: None,
+            #[cfg(feature = "lua")]
+            script: None,
+            running: false,
+            debug_break: false,
+            speed: 1.0,
+            rewinding: false,
+            audio_sync: true,
+            audio_live: false,
+            frames: 0,
+            quit: false,
+        }
+    }
+
+    /// Write the battery save now, e.g. before changing games
+    pub fn save_battery(&mut self) {
+        if let Some(b) = self.battery.as_mut() {
+            b.save(&mut self.emulator);
+        }
+        if let Some(Partner { emulator, battery: Some(b), .. }) = self.partner.as_mut() {
+            b.save(emulator);
+        }
+    }
+
+    /// Run one host frame's worth of emulation
+    fn advance(&mut self) {
+        if self.partner.is_some() {
+            self.advance_linked();
+            return;
+        }
+        let Self { emulator, driver, recorder, input, frames, .. } = self;
+        #[cfg(feature = "lua")]
+        let script = &mut self.script;
+        // Audio is muted away from normal speed
+        emulator.set_audio_enabled(self.speed == 1.0 && !self.rewinding);
+        input.apply(emulator);
+        #[cfg(feature = "lua")]
+        script_input(script, emulator);
+        driver.advance(emulator, self.speed, self.rewinding, recorder.is_some(), |emulator| {
+            if let Some(rec) = recorder.as_mut() {
+                rec.push_frame(emulator.framebuffer());
+            }
+            *frames += 1;
+            #[cfg(feature = "lua")]
+            run_script(script, emulator);
+            // Turbo presses change from frame to frame
+            input.apply(emulator);
+            #[cfg(feature = "lua")]
+            script_input(script, emulator);
+        });
+        if let Some(b) = self.battery.as_mut() {
+            b.flush_if_changed(&mut self.emulator);
+        }
+        if !self.rewinding && self.emulator.breakpoint_hit().is_some() {
+            self.debug_break = true;
+        }
+    }
+
+    /// Run one host frame of both consoles; rewinding is ignored
+    fn advance_linked(&mut self) {
+        let Self { emulator, driver, recorder, input, partner, frames, .. } = self;
+        let Some(Partner { emulator: other, battery: other_battery, input: other_input }) = partner.as_mut() else {
+            return;
+        };
+        #[cfg(feature = "lua")]
+        let script = &mut self.script;
+        emulator.set_audio_enabled(self.speed == 1.0);
+        other.set_audio_enabled(self.speed == 1.0);
+        input.apply(emulator);
+        #[cfg(feature = "lua")]
+        script_input(script, emulator);
+        other_input.apply(other);
+        driver.advance_linked(emulator, other, self.speed, recorder.is_some(), |emulator, other| {
+            if let Some(rec) = recorder.as_mut() {
+                rec.push_frame(emulator.framebuffer());
+            }
+            *frames += 1;
+            #[cfg(feature = "lua")]
+            run_script(script, emulator);
+            input.apply(emulator);
+            #[cfg(feature = "lua")]
+            script_input(script, emulator);
+            other_input.apply(other);
+        });
+        if let Some(b) = self.battery.as_mut() {
+            b.flush_if_changed(&mut self.emulator);
+        }
+        if let Some(b) = other_battery.as_mut() {
+            b.flush_if_changed(other);
+        }
+        if self.emulator.breakpoint_hit().is_some() {
+            self.debug_break = true;
+        }
+    }
+
+    /// Run one frame, or with `scanline` one scanline, by hand
+    ///
+    /// For frame advance while paused: the step is drawn and recorded like
+    /// any other frame, but stays silent.
+    pub fn frame_advance(&mut self, scanline: bool) {
+        let emulator = &mut self.emulator;
+        self.input.apply(emulator);
+        #[cfg(feature = "lua")]
+        script_input(&mut self.script, emulator);
+        emulator.set_video_enabled(true);
+        emulator.set_audio_enabled(false);
+        let frame = emulator.frame_count();
+        if let Some(partner) = self.partner.as_mut() {
+            partner.input.apply(&mut partner.emulator);
+            partner.emulator.set_video_enabled(true);
+            partner.emulator.set_audio_enabled(false);
+            if scanline {
+                emulator.run_scanline();
+                partner.emulator.run_cycles(driver::LINK_SLICE);
+            } else {
+                driver::run_linked_frame(emulator, &mut partner.emulator);
+            }
+        } else if scanline {
+            emulator.run_scanline();
+        } else {
+            emulator.run_frame();
+        }
+        if emulator.frame_count() != frame {
+            if let Some(rec) = self.recorder.as_mut() {
+                rec.push_frame(emulator.framebuffer());
+            }
+            self.frames += 1;
+            #[cfg(feature = "lua")]
+            run_script(&mut self.script, emulator);
+        }
+        if emulator.breakpoint_hit().is_some() {
+            self.debug_break = true;
+        }
+    }
+
+    /// Whether the thread should be running frames
+    fn playing(&self) -> bool {
+        self.running && !self.debug_break
+    }
+}
+
+/// Force the buttons the script holds
+#[cfg(feature = "lua")]
+fn script_input(script: &mut Option<Script>, emulator: &mut Emulator) {
+    if let Some(script) = script {
+        script.apply_input(emulator);
+    }
+}
+
+/// Run the script's end-of-frame callbacks and print what it printed
+///
+/// A script that fails is reported on the console and stopped.
+#[cfg(feature = "lua")]
+fn run_script(script: &mut Option<Script>, emulator: &mut Emulator) {
+    let Some(running) = script.as_mut() else {
+        return;
+    };
+    let result = running.after_frame(emulator);
+    for line in running.take_output() {
+        println!("{}", line);
+    }
+    if let Err(e) = result {
+        eprintln!("Lua script stopped: {}", e);
+        *script = None;
+    }
+}
+
+/// Handle to the running emulation thread
+pub struct EmuThread {
+    session: Arc<Mutex<Session>>,
+    handle: JoinHandle<()>,
+}
+
+impl EmuThread {
+    /// Start running `session`, pacing against `audio` while the session
+    /// says an output device is playing it
+    pub fn spawn(session: Session, audio: Option<AudioBuffer>) -> Self {
+        let session = Arc::new(Mutex::new(session));
+        let shared = Arc::clone(&session);
+        let handle = thread::Builder::new()
+            .name("emulation".to_string())
+            .spawn(move || run(&shared, audio.as_deref()))
+            .expect("Failed to start the emulation thread");
+        Self { session, handle }
+    }
+
+    /// Borrow the session; emulation waits until the guard is dropped
+    pub fn lock(&self) -> MutexGuard<'_, Session> {
+        self.session.lock().expect("Emulation thread panicked")
+    }
+
+    /// Stop the thread and take the session back
+    pub fn stop(self) -> Session {
+        self.lock().quit = true;
+        self.handle.join().expect("Emulation thread panicked");
+        let session = Arc::try_unwrap(self.session).ok().expect("Session still shared");
+        session.into_inner().expect("Emulation thread panicked")
+    }
+}
+
+/// The emulation thread: run a host frame, then wait for the next one
+fn run(session: &Mutex<Session>, audio: Option<&Mutex<VecDeque<f32>>>) {
+    loop {
+        let frame_start = Instant::now();
+        let synced_audio = {
+            let Ok(mut s) = session.lock() else { return };
+            if s.quit {
+                return;
+            }
+            if s.playing() {
+                s.advance();
+            }
+            let synced = audio.filter(|_| {
+                s.playing() && s.audio_live && s.audio_sync && s.speed == 1.0 && !s.rewinding
+            });
+            let adjust = synced.map_or(1.0, audio_rate_adjust);
+            s.emulator.set_audio_rate_adjust(adjust);
+            // Mixed consoles must keep producing samples at the same pace
+            if let Some(partner) = s.partner.as_mut() {
+                partner.emulator.set_audio_rate_adjust(adjust);
+            }
+            synced
+        };
+
+        // Follow the audio device while playing, otherwise sleep to
+        // maintain ~59.7 FPS
+        match synced_audio {
+            Some(buffer) => wait_for_audio(buffer),
+            None => {
+                let target = Duration::from_nanos(FRAME_TIME_NS);
+                let elapsed = frame_start.elapsed();
+                if elapsed < target {
+                    spin_sleep::sleep(target - elapsed);
+                }
+            }
+        }
+    }
+}
+
+fn audio_fill(audio: &Mutex<VecDeque<f32>>) -> usize {
+    audio.lock().map(|b| b.len()).unwrap_or(0)
+}
+
+/// Audio-sync pacing, part one: resample slightly so the buffer settles
+/// at the target fill
+///
+/// Video then runs at whatever rate the audio device consumes samples,
+/// avoiding the underruns a free-running frame timer drifts into.
+fn audio_rate_adjust(audio: &Mutex<VecDeque<f32>>) -> f64 {
+    // Just after a frame was queued the buffer should hold one frame above target
+    let expected = (AUDIO_TARGET_FILL + AUDIO_FRAME_SAMPLES) as f64;
+    let error = ((audio_fill(audio) as f64 - expected) / AUDIO_TARGET_FILL as f64).clamp(-1.0, 1.0);
+    1.0 - error * AUDIO_RATE_CONTROL
+}
+
+/// Audio-sync pacing, part two: wait for the output stream to drain the
+/// buffer to the target fill
+fn wait_for_audio(audio: &Mutex<VecDeque<f32>>) {
+    // Don't stall on a device that stopped pulling samples
+    let deadline = Instant::now() + Duration::from_nanos(FRAME_TIME_NS * 2);
+    while audio_fill(audio) > AUDIO_TARGET_FILL && Instant::now() < deadline {
+        spin_sleep::sleep(Duration::from_micros(500));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_frames_only_while_running() {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&vec![0u8; 0x8000]);
+        emulator.set_audio_enabled(false);
+        let emu = EmuThread::spawn(Session::new(emulator), None);
+        thread::sleep(Duration::from_nanos(FRAME_TIME_NS * 3));
+        assert_eq!(emu.lock().frames, 0);
+
+        emu.lock().running = true;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while emu.lock().frames < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        emu.lock().running = false;
+        let session = emu.stop();
+        assert!(session.frames >= 3);
+        assert!(session.emulator.total_cycles() > 0);
+    }
+
+    #[test]
+    fn frame_advance_steps_one_frame_or_line() {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&vec![0u8; 0x8000]);
+        let mut session = Session::new(emulator);
+        session.emulator.run_frame();
+        let frame = session.emulator.frame_count();
+
+        session.frame_advance(false);
+        assert_eq!(session.emulator.frame_count(), frame + 1);
+        assert_eq!(session.frames, 1);
+
+        let ly = session.emulator.peek(0xFF44);
+        session.frame_advance(true);
+        assert_eq!(session.emulator.peek(0xFF44), (ly + 1) % 154);
+        assert_eq!(session.emulator.frame_count(), frame + 1);
+    }
+
+    #[test]
+    fn partner_runs_in_step() {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&vec![0u8; 0x8000]);
+        let mut partner = Emulator::new();
+        partner.load_rom(&vec![0u8; 0x8000]);
+        let mut session = Session::new(emulator);
+        session.partner = Some(Partner { emulator: partner, battery: None, input: Input::default() });
+        let frame = session.emulator.frame_count();
+
+        for _ in 0..3 {
+            session.frame_advance(false);
+        }
+        let other = &session.partner.as_ref().unwrap().emulator;
+        assert_eq!(session.emulator.frame_count(), frame + 3);
+        assert!(other.frame_count().abs_diff(frame + 3) <= 1);
+        assert!(other.total_cycles().abs_diff(session.emulator.total_cycles()) <= driver::LINK_SLICE as u64 * 2);
+    }
+}
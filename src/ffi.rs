@@ -0,0 +1,258 @@
+//! C API for embedding the emulator core in non-Rust frontends.
+//!
+//! Enabled with the `capi` feature. Build the `cdylib`/`staticlib` with
+//! `cargo build --release --lib --no-default-features --features capi` and
+//! include `include/gb3000.h`.
+//!
+//! All functions take an opaque `Gb3000Emulator*` returned by
+//! [`gb3000_create`]. Passing a null handle is always safe and is treated as
+//! a no-op (or returns a zero/null value).
+
+use crate::{Button, Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::os::raw::c_int;
+
+/// Button codes accepted by [`gb3000_set_button`]
+pub const GB3000_BUTTON_RIGHT: c_int = 0;
+pub const GB3000_BUTTON_LEFT: c_int = 1;
+pub const GB3000_BUTTON_UP: c_int = 2;
+pub const GB3000_BUTTON_DOWN: c_int = 3;
+pub const GB3000_BUTTON_A: c_int = 4;
+pub const GB3000_BUTTON_B: c_int = 5;
+pub const GB3000_BUTTON_SELECT: c_int = 6;
+pub const GB3000_BUTTON_START: c_int = 7;
+
+fn button_from_code(code: c_int) -> Option<Button> {
+    match code {
+        GB3000_BUTTON_RIGHT => Some(Button::Right),
+        GB3000_BUTTON_LEFT => Some(Button::Left),
+        GB3000_BUTTON_UP => Some(Button::Up),
+        GB3000_BUTTON_DOWN => Some(Button::Down),
+        GB3000_BUTTON_A => Some(Button::A),
+        GB3000_BUTTON_B => Some(Button::B),
+        GB3000_BUTTON_SELECT => Some(Button::Select),
+        GB3000_BUTTON_START => Some(Button::Start),
+        _ => None,
+    }
+}
+
+/// Create a new emulator instance
+///
+/// The returned handle must be released with [`gb3000_destroy`].
+#[no_mangle]
+pub extern "C" fn gb3000_create() -> *mut Emulator {
+    Box::into_raw(Box::new(Emulator::new()))
+}
+
+/// Destroy an emulator instance created by [`gb3000_create`]
+///
+/// # Safety
+/// `emu` must be null or a handle returned by [`gb3000_create`] that has not
+/// already been destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_destroy(emu: *mut Emulator) {
+    if !emu.is_null() {
+        drop(Box::from_raw(emu));
+    }
+}
+
+/// Load a ROM image and reset the emulator
+///
+/// The ROM bytes are copied; the caller keeps ownership of `data`.
+/// Returns 1 on success, 0 if the handle or data pointer is null.
+///
+/// # Safety
+/// `emu` must be null or a valid handle, and `data` must point to `len`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_load_rom(emu: *mut Emulator, data: *const u8, len: usize) -> c_int {
+    let Some(emu) = emu.as_mut() else {
+        return 0;
+    };
+    if data.is_null() {
+        return 0;
+    }
+    let rom = std::slice::from_raw_parts(data, len);
+    emu.load_rom(rom);
+    emu.reset();
+    1
+}
+
+/// Reset the emulator while keeping the ROM loaded
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_reset(emu: *mut Emulator) {
+    if let Some(emu) = emu.as_mut() {
+        emu.reset();
+    }
+}
+
+/// Power cycle the emulator, keeping the ROM and cartridge RAM
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_soft_reset(emu: *mut Emulator) {
+    if let Some(emu) = emu.as_mut() {
+        emu.soft_reset();
+    }
+}
+
+/// Run emulation for one frame
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_run_frame(emu: *mut Emulator) {
+    if let Some(emu) = emu.as_mut() {
+        emu.run_frame();
+    }
+}
+
+/// Get a pointer to the 160x144 framebuffer of 2-bit color indices
+///
+/// The pointer stays valid until the next call that runs emulation or
+/// destroys the handle. Returns null for a null handle.
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_framebuffer(emu: *const Emulator) -> *const u8 {
+    match emu.as_ref() {
+        Some(emu) => emu.framebuffer().as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Framebuffer width in pixels
+#[no_mangle]
+pub extern "C" fn gb3000_screen_width() -> c_int {
+    SCREEN_WIDTH as c_int
+}
+
+/// Framebuffer height in pixels
+#[no_mangle]
+pub extern "C" fn gb3000_screen_height() -> c_int {
+    SCREEN_HEIGHT as c_int
+}
+
+/// Pull pending audio samples into `out`
+///
+/// Samples are stereo interleaved f32 at [`gb3000_audio_sample_rate`] Hz.
+/// Writes at most `capacity` floats and returns how many were written;
+/// anything that doesn't fit stays queued for the next call.
+///
+/// # Safety
+/// `emu` must be null or a valid handle, and `out` must point to
+/// `capacity` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_audio_pull(emu: *mut Emulator, out: *mut f32, capacity: usize) -> usize {
+    let Some(emu) = emu.as_mut() else {
+        return 0;
+    };
+    if out.is_null() {
+        return 0;
+    }
+    let out = std::slice::from_raw_parts_mut(out, capacity);
+    emu.read_audio_samples(out)
+}
+
+/// Audio sample rate in Hz
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_audio_sample_rate(emu: *const Emulator) -> u32 {
+    match emu.as_ref() {
+        Some(emu) => emu.audio_sample_rate(),
+        None => 0,
+    }
+}
+
+/// Set the state of a button (one of the `GB3000_BUTTON_*` codes)
+///
+/// Unknown button codes are ignored.
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_set_button(emu: *mut Emulator, button: c_int, pressed: c_int) {
+    let Some(emu) = emu.as_mut() else {
+        return;
+    };
+    if let Some(button) = button_from_code(button) {
+        emu.set_button(button, pressed != 0);
+    }
+}
+
+/// Whether the game has the rumble motor switched on
+///
+/// Returns 1 while an MBC5 rumble cart drives its motor, 0 otherwise or
+/// for a null handle. Poll it once per frame for force feedback.
+///
+/// # Safety
+/// `emu` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn gb3000_rumble_active(emu: *const Emulator) -> c_int {
+    match emu.as_ref() {
+        Some(emu) => emu.rumble_active() as c_int,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_run_destroy() {
+        let rom = vec![0u8; 0x8000];
+        unsafe {
+            let emu = gb3000_create();
+            assert_eq!(gb3000_load_rom(emu, rom.as_ptr(), rom.len()), 1);
+            gb3000_set_button(emu, GB3000_BUTTON_START, 1);
+            gb3000_run_frame(emu);
+            assert!(!gb3000_framebuffer(emu).is_null());
+
+            let mut samples = [0.0f32; 64];
+            let written = gb3000_audio_pull(emu, samples.as_mut_ptr(), samples.len());
+            assert!(written <= samples.len());
+
+            gb3000_destroy(emu);
+        }
+    }
+
+    #[test]
+    fn null_handle_is_ignored() {
+        unsafe {
+            assert_eq!(gb3000_load_rom(std::ptr::null_mut(), std::ptr::null(), 0), 0);
+            assert!(gb3000_framebuffer(std::ptr::null()).is_null());
+            gb3000_run_frame(std::ptr::null_mut());
+            assert_eq!(gb3000_rumble_active(std::ptr::null()), 0);
+            gb3000_destroy(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn header_declares_every_function() {
+        let header = include_str!("../include/gb3000.h");
+        for name in [
+            "gb3000_create",
+            "gb3000_destroy",
+            "gb3000_load_rom",
+            "gb3000_reset",
+            "gb3000_soft_reset",
+            "gb3000_run_frame",
+            "gb3000_framebuffer",
+            "gb3000_screen_width",
+            "gb3000_screen_height",
+            "gb3000_audio_pull",
+            "gb3000_audio_sample_rate",
+            "gb3000_set_button",
+            "gb3000_rumble_active",
+        ] {
+            assert!(header.contains(&format!("{}(", name)), "{} missing from header", name);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Software video filters for the desktop UI
+//!
+//! Every filter upscales the 160x144 Game Boy framebuffer by [`SCALE`]
+//! into a 32-bit ARGB buffer; two consoles played side by side share it
+//! at half that size.
+
+use gb3000::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Output scale factor shared by all filters
+pub const SCALE: usize = 4;
+
+/// Width of the filtered output
+pub const OUT_WIDTH: usize = SCREEN_WIDTH * SCALE;
+
+/// Height of the filtered output
+pub const OUT_HEIGHT: usize = SCREEN_HEIGHT * SCALE;
+
+/// Available video filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// Plain nearest-neighbor scaling
+    #[default]
+    Nearest,
+    /// Scale2x (AdvMAME) applied twice
+    Scale2x,
+    /// Simplified xBR edge smoothing
+    XbrLite,
+    /// Visible gaps between LCD pixels
+    LcdGrid,
+    /// Scanlines and phosphor bleed
+    Crt,
+}
+
+impl Filter {
+    /// All filters, in menu order
+    pub const ALL: [Filter; 5] = [
+        Filter::Nearest,
+        Filter::Scale2x,
+        Filter::XbrLite,
+        Filter::LcdGrid,
+        Filter::Crt,
+    ];
+
+    /// Display name for menus
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::Nearest => "Nearest",
+            Filter::Scale2x => "Scale2x",
+            Filter::XbrLite => "xBR-lite",
+            Filter::LcdGrid => "LCD Grid",
+            Filter::Crt => "CRT",
+        }
+    }
+
+    /// The filter after this one, wrapping around
+    pub fn next(self) -> Filter {
+        let i = Self::ALL.iter().position(|&f| f == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// Buffers the filters reuse from frame to frame
+#[derive(Debug, Default)]
+pub struct Scratch {
+    /// Scale2x's first pass, at twice the screen size
+    mid: Vec<u32>,
+    /// One full-size screen of a side-by-side pair
+    full: Vec<u32>,
+}
+
+/// Render `src` (2-bit color indices) into `dst` (OUT_WIDTH x OUT_HEIGHT)
+pub fn apply(filter: Filter, src: &[u8], palette: &[u32; 4], dst: &mut [u32], scratch: &mut Scratch) {
+    render(filter, src, palette, dst, &mut scratch.mid);
+}
+
+fn render(filter: Filter, src: &[u8], palette: &[u32; 4], dst: &mut [u32], mid: &mut Vec<u32>) {
+    let mut colors = [0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    for (c, &idx) in colors.iter_mut().zip(src.iter()) {
+        *c = palette[(idx & 0x03) as usize];
+    }
+
+    match filter {
+        Filter::Nearest => nearest(&colors, dst),
+        Filter::Scale2x => {
+            mid.resize(SCREEN_WIDTH * 2 * SCREEN_HEIGHT * 2, 0);
+            scale2x(&colors, SCREEN_WIDTH, SCREEN_HEIGHT, mid);
+            scale2x(mid, SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2, dst);
+        }
+        Filter::XbrLite => xbr_lite(&colors, dst),
+        Filter::LcdGrid => lcd_grid(&colors, dst),
+        Filter::Crt => crt(&colors, dst),
+    }
+}
+
+/// Render two screens side by side into `dst` (OUT_WIDTH x OUT_HEIGHT)
+///
+/// Each is filtered at full size, then halved by averaging 2x2 blocks,
+/// and the pair is centered vertically on black.
+pub fn apply_pair(filter: Filter, screens: [&[u8]; 2], palette: &[u32; 4], dst: &mut [u32], scratch: &mut Scratch) {
+    const HALF_WIDTH: usize = OUT_WIDTH / 2;
+    let top = OUT_HEIGHT / 4;
+    dst.fill(0xFF000000);
+    let Scratch { mid, full } = scratch;
+    full.resize(OUT_WIDTH * OUT_HEIGHT, 0);
+    for (i, src) in screens.into_iter().enumerate() {
+        render(filter, src, palette, full, mid);
+        for y in 0..OUT_HEIGHT / 2 {
+            let (upper, lower) = full[y * 2 * OUT_WIDTH..][..OUT_WIDTH * 2].split_at(OUT_WIDTH);
+            let row = &mut dst[(top + y) * OUT_WIDTH + i * HALF_WIDTH..][..HALF_WIDTH];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let top_pair = mix(upper[x * 2], upper[x * 2 + 1], 1, 2);
+                let bottom_pair = mix(lower[x * 2], lower[x * 2 + 1], 1, 2);
+                *pixel = mix(top_pair, bottom_pair, 1, 2);
+            }
+        }
+    }
+}
+
+/// Nearest-neighbor upscale
+fn nearest(src: &[u32], dst: &mut [u32]) {
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let color = src[y * SCREEN_WIDTH + x];
+            for dy in 0..SCALE {
+                let row = (y * SCALE + dy) * OUT_WIDTH + x * SCALE;
+                dst[row..row + SCALE].fill(color);
+            }
+        }
+    }
+}
+
+/// Fetch a pixel with edge clamping
+fn pixel_at(src: &[u32], w: usize, h: usize, x: isize, y: isize) -> u32 {
+    let x = x.clamp(0, w as isize - 1) as usize;
+    let y = y.clamp(0, h as isize - 1) as usize;
+    src[y * w + x]
+}
+
+/// One Scale2x pass: `src` is w x h, `dst` is 2w x 2h
+fn scale2x(src: &[u32], w: usize, h: usize, dst: &mut [u32]) {
+    let out_w = w * 2;
+    for y in 0..h {
+        for x in 0..w {
+            let (xi, yi) = (x as isize, y as isize);
+            let e = src[y * w + x];
+            let b = pixel_at(src, w, h, xi, yi - 1);
+            let d = pixel_at(src, w, h, xi - 1, yi);
+            let f = pixel_at(src, w, h, xi + 1, yi);
+            let hh = pixel_at(src, w, h, xi, yi + 1);
+
+            let (e0, e1, e2, e3) = if b != hh && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == hh { d } else { e },
+                    if hh == f { f } else { e },
+                )
+            } else {
+                (e, e, e, e)
+            };
+
+            let top = (y * 2) * out_w + x * 2;
+            dst[top] = e0;
+            dst[top + 1] = e1;
+            dst[top + out_w] = e2;
+            dst[top + out_w + 1] = e3;
+        }
+    }
+}
+
+/// Edge smoothing in the spirit of xBR: where two orthogonal neighbors
+/// match and form a corner, the corner of the block takes their color and
+/// the pixels along the diagonal are blended.
+fn xbr_lite(src: &[u32], dst: &mut [u32]) {
+    nearest(src, dst);
+
+    let (w, h) = (SCREEN_WIDTH, SCREEN_HEIGHT);
+    for y in 0..h {
+        for x in 0..w {
+            let (xi, yi) = (x as isize, y as isize);
+            let e = src[y * w + x];
+            let b = pixel_at(src, w, h, xi, yi - 1);
+            let d = pixel_at(src, w, h, xi - 1, yi);
+            let f = pixel_at(src, w, h, xi + 1, yi);
+            let hh = pixel_at(src, w, h, xi, yi + 1);
+
+            // (neighbor a, neighbor b, opposite a, opposite b, corner x, corner y)
+            let corners = [
+                (b, d, f, hh, 0, 0),
+                (b, f, d, hh, SCALE - 1, 0),
+                (hh, d, f, b, 0, SCALE - 1),
+                (hh, f, d, b, SCALE - 1, SCALE - 1),
+            ];
+            for (n1, n2, o1, o2, cx, cy) in corners {
+                if n1 != n2 || n1 == e || n1 == o1 || n2 == o2 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        // Distance from the corner along both axes
+                        let dist = dx.abs_diff(cx) + dy.abs_diff(cy);
+                        let color = match dist {
+                            0 => n1,
+                            d if d < SCALE / 2 => mix(n1, e, 3, 4),
+                            d if d == SCALE / 2 => mix(n1, e, 1, 2),
+                            _ => continue,
+                        };
+                        dst[(y * SCALE + dy) * OUT_WIDTH + x * SCALE + dx] = color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// LCD look: the last row and column of each block are darkened
+fn lcd_grid(src: &[u32], dst: &mut [u32]) {
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let color = src[y * SCREEN_WIDTH + x];
+            let gap = scale_color(color, 3, 4);
+            for dy in 0..SCALE {
+                let row = (y * SCALE + dy) * OUT_WIDTH + x * SCALE;
+                for dx in 0..SCALE {
+                    dst[row + dx] = if dx == SCALE - 1 || dy == SCALE - 1 { gap } else { color };
+                }
+            }
+        }
+    }
+}
+
+/// CRT look: horizontal phosphor bleed and dark scanlines
+fn crt(src: &[u32], dst: &mut [u32]) {
+    // Brightness of each row within a block, out of 16
+    const SCANLINE: [u32; SCALE] = [16, 16, 13, 8];
+
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let color = src[y * SCREEN_WIDTH + x];
+            let left = src[y * SCREEN_WIDTH + x.saturating_sub(1)];
+            let right = src[y * SCREEN_WIDTH + (x + 1).min(SCREEN_WIDTH - 1)];
+            for (dy, &level) in SCANLINE.iter().enumerate() {
+                let row = (y * SCALE + dy) * OUT_WIDTH + x * SCALE;
+                for dx in 0..SCALE {
+                    // Bleed the neighbor's color into the outer columns
+                    let c = match dx {
+                        0 => mix(left, color, 1, 4),
+                        d if d == SCALE - 1 => mix(right, color, 1, 4),
+                        _ => color,
+                    };
+                    dst[row + dx] = scale_color(c, level, 16);
+                }
+            }
+        }
+    }
+}
+
+/// Blend `a` into `b`: `num/den` of `a` plus the rest of `b`
+fn mix(a: u32, b: u32, num: u32, den: u32) -> u32 {
+    let channel = |shift: u32| {
+        let ca = (a >> shift) & 0xFF;
+        let cb = (b >> shift) & 0xFF;
+        ((ca * num + cb * (den - num)) / den) << shift
+    };
+    0xFF000000 | channel(16) | channel(8) | channel(0)
+}
+
+/// Multiply a color's brightness by `num/den`
+fn scale_color(color: u32, num: u32, den: u32) -> u32 {
+    mix(color, 0xFF000000, num, den)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: [u32; 4] = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+
+    #[test]
+    fn flat_image_is_unchanged_by_smoothing_filters() {
+        let src = vec![2u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for filter in [Filter::Nearest, Filter::Scale2x, Filter::XbrLite] {
+            let mut dst = vec![0u32; OUT_WIDTH * OUT_HEIGHT];
+            apply(filter, &src, &PALETTE, &mut dst, &mut Scratch::default());
+            assert!(dst.iter().all(|&c| c == PALETTE[2]), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonal_corner() {
+        // A black pixel at (1,1) with black neighbors above and to the left
+        let mut src = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        src[1] = 3;
+        src[SCREEN_WIDTH] = 3;
+        let mut dst = vec![0u32; OUT_WIDTH * OUT_HEIGHT];
+        apply(Filter::Scale2x, &src, &PALETTE, &mut dst, &mut Scratch::default());
+
+        // The top-left corner of the white pixel at (1,1) is filled in
+        let block = SCALE * OUT_WIDTH + SCALE;
+        assert_eq!(dst[block], PALETTE[3]);
+        assert_eq!(dst[block + 3 * OUT_WIDTH + 3], PALETTE[0]);
+    }
+
+    #[test]
+    fn pair_sits_side_by_side_at_half_size() {
+        let left = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut right = vec![3u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        right[0] = 0;
+        let mut dst = vec![0u32; OUT_WIDTH * OUT_HEIGHT];
+        apply_pair(Filter::Nearest, [&left, &right], &PALETTE, &mut dst, &mut Scratch::default());
+
+        let top = OUT_HEIGHT / 4;
+        assert_eq!(dst[0], 0xFF000000);
+        assert_eq!(dst[top * OUT_WIDTH], PALETTE[0]);
+        assert_eq!(dst[top * OUT_WIDTH + OUT_WIDTH / 2 - 1], PALETTE[0]);
+        // Each Game Boy pixel is now 2x2
+        assert_eq!(dst[top * OUT_WIDTH + OUT_WIDTH / 2 + 1], PALETTE[0]);
+        assert_eq!(dst[top * OUT_WIDTH + OUT_WIDTH / 2 + 2], PALETTE[3]);
+        assert_eq!(dst[(top + OUT_HEIGHT / 2 - 1) * OUT_WIDTH + OUT_WIDTH - 1], PALETTE[3]);
+        assert_eq!(dst[(top + OUT_HEIGHT / 2) * OUT_WIDTH], 0xFF000000);
+    }
+
+    #[test]
+    fn filter_cycle_wraps() {
+        let mut f = Filter::default();
+        for _ in 0..Filter::ALL.len() {
+            f = f.next();
+        }
+        assert_eq!(f, Filter::Nearest);
+    }
+}
@@ -0,0 +1,151 @@
+//! Comparing frames
+//!
+//! [`hash`] gives a framebuffer a 64-bit FNV-1a hash that stays the same
+//! across runs, platforms and versions, for golden-image tests and for
+//! spotting netplay desyncs; [`hash_bytes`] applies the same hash to
+//! anything else, such as audio or save states. [`diff`] reports what changed between two
+//! frames: how many pixels, and the 8x8 blocks around them merged into
+//! rectangles, so a frontend can skip uploading a frame that didn't
+//! change or upload only the parts that did.
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// A framebuffer of color indices, as [`Emulator::framebuffer`](crate::Emulator::framebuffer) returns
+pub type Frame = [u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+/// Side of the square blocks changes are tracked in
+const BLOCK: usize = 8;
+
+const BLOCKS_WIDE: usize = SCREEN_WIDTH / BLOCK;
+const BLOCKS_HIGH: usize = SCREEN_HEIGHT / BLOCK;
+
+/// FNV-1a offset basis, the starting value for [`hash_bytes`]
+pub const HASH_START: u64 = 0xCBF2_9CE4_8422_2325;
+
+/// An area of the screen, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// What changed between two frames
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameDiff {
+    /// Pixels whose color index differs
+    pub changed_pixels: usize,
+    /// Rectangles covering every changed pixel, on 8-pixel boundaries,
+    /// top to bottom
+    pub rects: Vec<Rect>,
+}
+
+impl FrameDiff {
+    /// Whether the frames were identical
+    pub fn is_empty(&self) -> bool {
+        self.changed_pixels == 0
+    }
+}
+
+/// Stable 64-bit hash of a frame's color indices
+pub fn hash(frame: &Frame) -> u64 {
+    hash_bytes(HASH_START, frame)
+}
+
+/// Continue a 64-bit FNV-1a hash over `bytes`
+pub fn hash_bytes(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+/// Compare two frames
+pub fn diff(old: &Frame, new: &Frame) -> FrameDiff {
+    let mut changed_pixels = 0;
+    let mut blocks = [[false; BLOCKS_WIDE]; BLOCKS_HIGH];
+    for (y, (old_row, new_row)) in old.chunks_exact(SCREEN_WIDTH).zip(new.chunks_exact(SCREEN_WIDTH)).enumerate() {
+        for (x, (a, b)) in old_row.iter().zip(new_row).enumerate() {
+            if a != b {
+                changed_pixels += 1;
+                blocks[y / BLOCK][x / BLOCK] = true;
+            }
+        }
+    }
+
+    // Runs of changed blocks along each block row, each growing the
+    // rectangle above it when it spans the same columns
+    let mut rects: Vec<Rect> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    for (row, changed) in blocks.iter().enumerate() {
+        let mut still_open = Vec::new();
+        let mut column = 0;
+        while column < BLOCKS_WIDE {
+            if !changed[column] {
+                column += 1;
+                continue;
+            }
+            let start = column;
+            while column < BLOCKS_WIDE && changed[column] {
+                column += 1;
+            }
+            let (x, width) = (start * BLOCK, (column - start) * BLOCK);
+            match open.iter().copied().find(|&i| rects[i].x == x && rects[i].width == width) {
+                Some(i) => {
+                    rects[i].height += BLOCK;
+                    still_open.push(i);
+                }
+                None => {
+                    still_open.push(rects.len());
+                    rects.push(Rect { x, y: row * BLOCK, width, height: BLOCK });
+                }
+            }
+        }
+        open = still_open;
+    }
+    FrameDiff { changed_pixels, rects }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_stable() {
+        let mut frame = [0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let blank = hash(&frame);
+        assert_eq!(blank, hash(&[0u8; SCREEN_WIDTH * SCREEN_HEIGHT]));
+        frame[100] = 3;
+        assert_ne!(hash(&frame), blank);
+        // Stored hashes must stay valid
+        assert_eq!(hash(&frame), 0x7E98_D68A_5376_AE16);
+    }
+
+    #[test]
+    fn diff_merges_changed_blocks() {
+        let old = [0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        assert!(diff(&old, &old).is_empty());
+        assert!(diff(&old, &old).rects.is_empty());
+
+        let mut new = old;
+        // A 16x12 box at (8, 4), spanning two block columns and block
+        // rows 0-1, with the row below only half as wide
+        for y in 4..16 {
+            new[y * SCREEN_WIDTH + 8..y * SCREEN_WIDTH + 24].fill(1);
+        }
+        new[16 * SCREEN_WIDTH + 9] = 2;
+        // and a pixel elsewhere
+        new[100 * SCREEN_WIDTH + 159] = 3;
+
+        let d = diff(&old, &new);
+        assert_eq!(d.changed_pixels, 16 * 12 + 2);
+        assert_eq!(
+            d.rects,
+            vec![
+                Rect { x: 8, y: 0, width: 16, height: 16 },
+                Rect { x: 8, y: 16, width: 8, height: 8 },
+                Rect { x: 152, y: 96, width: 8, height: 8 },
+            ]
+        );
+    }
+}
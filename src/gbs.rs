@@ -0,0 +1,287 @@
+//! GBS sound file playback
+//!
+//! A GBS file holds a game's sound driver and music data without the rest
+//! of the game, plus a header naming its init and play routines. Playing
+//! one builds a small cartridge around it: the data sits at its load
+//! address in an MBC5 ROM, and a stub driver calls init with the song
+//! number, then play from the VBlank or timer interrupt as the header
+//! asks, halting in between.
+//!
+//! Header layout (0x70 bytes, little-endian):
+//! - "GBS" and version 1
+//! - song count, first song (1-based)
+//! - load, init and play addresses, initial stack pointer
+//! - TMA and TAC; TAC bit 2 selects the timer over VBlank, bit 7 CGB
+//!   double speed
+//! - title, author and copyright, 32 bytes each
+
+use crate::{Emulator, GbModel};
+
+/// Size of the GBS header before the data
+const HEADER_SIZE: usize = 0x70;
+
+/// Where the stub driver goes, after the cartridge header
+const STUB: usize = 0x0150;
+
+/// Lowest load address leaving room for the vectors, header and stub
+const MIN_LOAD_ADDRESS: u16 = 0x0200;
+
+/// Errors that can occur while reading a GBS file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GbsError {
+    /// Data does not start with "GBS"
+    BadMagic,
+    /// File is a version other than 1
+    UnsupportedVersion(u8),
+    /// Data ended inside the header
+    Truncated,
+    /// The data would overlap the vectors or stub, or run past 8 MiB
+    BadLoadAddress(u16),
+    /// The header lists no songs
+    NoSongs,
+}
+
+impl std::fmt::Display for GbsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GbsError::BadMagic => write!(f, "not a GBS file"),
+            GbsError::UnsupportedVersion(v) => write!(f, "unsupported GBS version {}", v),
+            GbsError::Truncated => write!(f, "GBS header is truncated"),
+            GbsError::BadLoadAddress(a) => write!(f, "GBS load address {:04X} is out of range", a),
+            GbsError::NoSongs => write!(f, "GBS file has no songs"),
+        }
+    }
+}
+
+impl std::error::Error for GbsError {}
+
+/// A parsed GBS file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GbsFile {
+    song_count: u8,
+    first_song: u8,
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+    stack_pointer: u16,
+    tma: u8,
+    tac: u8,
+    title: String,
+    author: String,
+    copyright: String,
+    data: Vec<u8>,
+}
+
+impl GbsFile {
+    /// Parse a GBS file
+    pub fn parse(bytes: &[u8]) -> Result<Self, GbsError> {
+        if bytes.len() < 4 || &bytes[..3] != b"GBS" {
+            return Err(GbsError::BadMagic);
+        }
+        if bytes[3] != 1 {
+            return Err(GbsError::UnsupportedVersion(bytes[3]));
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(GbsError::Truncated);
+        }
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &bytes[offset..offset + 32];
+            let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).trim().to_string()
+        };
+        let gbs = Self {
+            song_count: bytes[4],
+            first_song: bytes[5].clamp(1, bytes[4].max(1)),
+            load_address: word(0x06),
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            tma: bytes[0x0E],
+            tac: bytes[0x0F],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+            data: bytes[HEADER_SIZE..].to_vec(),
+        };
+        if gbs.song_count == 0 {
+            return Err(GbsError::NoSongs);
+        }
+        if gbs.load_address < MIN_LOAD_ADDRESS
+            || gbs.load_address >= 0x8000
+            || gbs.load_address as usize + gbs.data.len() > 0x80_0000
+        {
+            return Err(GbsError::BadLoadAddress(gbs.load_address));
+        }
+        Ok(gbs)
+    }
+
+    /// Number of songs
+    pub fn song_count(&self) -> u8 {
+        self.song_count
+    }
+
+    /// Song to play first, 0-based
+    pub fn first_song(&self) -> u8 {
+        self.first_song - 1
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn copyright(&self) -> &str {
+        &self.copyright
+    }
+
+    /// Whether play runs from the timer interrupt rather than VBlank
+    pub fn uses_timer(&self) -> bool {
+        self.tac & 0x04 != 0
+    }
+
+    /// How many times a second play is called
+    pub fn play_rate(&self) -> f64 {
+        if !self.uses_timer() {
+            return 4_194_304.0 / 70_224.0;
+        }
+        let input_clock = [4096.0, 262_144.0, 65_536.0, 16_384.0][(self.tac & 0x03) as usize];
+        let speed = if self.double_speed() { 2.0 } else { 1.0 };
+        input_clock * speed / (256.0 - self.tma as f64)
+    }
+
+    fn double_speed(&self) -> bool {
+        self.tac & 0x80 != 0
+    }
+
+    /// Reset `emulator` into song `song` (0-based, wrapping past the last)
+    ///
+    /// The sound data replaces whatever ROM was loaded. Switching songs is
+    /// another call to `play`.
+    pub fn play(&self, emulator: &mut Emulator, song: u8) {
+        emulator.load_rom(&self.rom_image(song % self.song_count));
+        // Double speed is a CGB feature; otherwise match the DMG sound
+        let model = if self.double_speed() { GbModel::Cgb } else { GbModel::DmgABC };
+        emulator.reset_for_model(model);
+    }
+
+    /// A cartridge that plays `song`
+    fn rom_image(&self, song: u8) -> Vec<u8> {
+        let end = self.load_address as usize + self.data.len();
+        let size = end.next_power_of_two().max(0x8000);
+        let mut rom = vec![0xFFu8; size];
+        rom[self.load_address as usize..end].copy_from_slice(&self.data);
+
+        // RST vectors jump to their copies at the load address
+        for rst in (0x00..0x40).step_by(8) {
+            let [lo, hi] = (self.load_address + rst as u16).to_le_bytes();
+            rom[rst..rst + 3].copy_from_slice(&[0xC3, lo, hi]);
+        }
+        // Interrupt vectors: play on VBlank or the timer, return from the rest
+        let [play_lo, play_hi] = self.play_address.to_le_bytes();
+        let play_vector = if self.uses_timer() { 0x50 } else { 0x40 };
+        for vector in (0x40..=0x60).step_by(8) {
+            rom[vector] = 0xD9; // RETI
+        }
+        rom[play_vector..play_vector + 4].copy_from_slice(&[0xCD, play_lo, play_hi, 0xD9]);
+
+        // Cartridge header: entry point, title, MBC5 with 8 KiB of RAM
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, STUB as u8, (STUB >> 8) as u8]);
+        rom[0x104..0x150].fill(0);
+        for (dst, src) in rom[0x134..0x143].iter_mut().zip(self.title.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        if self.double_speed() {
+            rom[0x143] = 0x80; // CGB compatible, else KEY1 is missing
+        }
+        rom[0x147] = 0x1A;
+        rom[0x148] = (size / 0x8000).trailing_zeros() as u8;
+        rom[0x149] = 0x02;
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+
+        // Stub driver: init the song, start the interrupt, halt forever
+        let [sp_lo, sp_hi] = self.stack_pointer.to_le_bytes();
+        let [init_lo, init_hi] = self.init_address.to_le_bytes();
+        let mut stub = vec![0xF3, 0x31, sp_lo, sp_hi]; // DI; LD SP, sp
+        if self.double_speed() {
+            stub.extend_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00]); // switch via KEY1 and STOP
+        }
+        let interrupt = if self.uses_timer() { 0x04 } else { 0x01 };
+        stub.extend_from_slice(&[
+            0x3E, song, 0xCD, init_lo, init_hi, // LD A, song; CALL init
+            0x3E, self.tma, 0xE0, 0x06, // LD A, tma; LDH (TMA), A
+            0x3E, self.tac & 0x07, 0xE0, 0x07, // LD A, tac; LDH (TAC), A
+            0x3E, interrupt, 0xE0, 0xFF, // LD A, interrupt; LDH (IE), A
+            0xAF, 0xE0, 0x0F, // XOR A; LDH (IF), A
+            0xFB, // EI
+            0x76, 0x00, 0x18, 0xFC, // HALT; NOP; JR -4
+        ]);
+        rom[STUB..STUB + stub.len()].copy_from_slice(&stub);
+        rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GBS whose init stores the song number at $C000 and whose play
+    /// counts calls at $C001
+    fn test_gbs(tac: u8) -> Vec<u8> {
+        let mut gbs = vec![0u8; HEADER_SIZE];
+        gbs[..4].copy_from_slice(b"GBS\x01");
+        gbs[4] = 3;
+        gbs[5] = 2;
+        gbs[0x06..0x0E].copy_from_slice(&[0x00, 0x04, 0x00, 0x04, 0x10, 0x04, 0xFE, 0xFF]);
+        gbs[0x0E] = 0x00;
+        gbs[0x0F] = tac;
+        gbs[0x10..0x15].copy_from_slice(b"Tunes");
+        gbs[0x30..0x37].copy_from_slice(b"Someone");
+        gbs.resize(HEADER_SIZE + 0x10, 0);
+        // init at $0400: LD ($C000), A; RET
+        gbs[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&[0xEA, 0x00, 0xC0, 0xC9]);
+        // play at $0410: LD HL, $C001; INC (HL); RET
+        gbs.extend_from_slice(&[0x21, 0x01, 0xC0, 0x34, 0xC9]);
+        gbs
+    }
+
+    #[test]
+    fn parses_header() {
+        let gbs = GbsFile::parse(&test_gbs(0x00)).unwrap();
+        assert_eq!(gbs.song_count(), 3);
+        assert_eq!(gbs.first_song(), 1);
+        assert_eq!(gbs.title(), "Tunes");
+        assert_eq!(gbs.author(), "Someone");
+        assert!(!gbs.uses_timer());
+        assert!((gbs.play_rate() - 59.73).abs() < 0.01);
+        assert_eq!(GbsFile::parse(&test_gbs(0x04)).unwrap().play_rate(), 4096.0 / 256.0);
+
+        assert_eq!(GbsFile::parse(b"GBX\x01"), Err(GbsError::BadMagic));
+        assert_eq!(GbsFile::parse(b"GBS\x02"), Err(GbsError::UnsupportedVersion(2)));
+        assert_eq!(GbsFile::parse(&test_gbs(0)[..0x40]), Err(GbsError::Truncated));
+        let mut low = test_gbs(0);
+        low[0x07] = 0x01;
+        assert_eq!(GbsFile::parse(&low), Err(GbsError::BadLoadAddress(0x0100)));
+    }
+
+    #[test]
+    fn plays_songs_from_vblank_or_timer_at_either_speed() {
+        for (tac, calls_per_second) in [(0x00, 59..=60), (0x04, 15..=16), (0x84, 31..=32)] {
+            let gbs = GbsFile::parse(&test_gbs(tac)).unwrap();
+            let mut emulator = Emulator::new();
+            gbs.play(&mut emulator, 2);
+            emulator.run_cycles(4_194_304);
+            assert_eq!(emulator.peek(0xC000), 2);
+            assert!(calls_per_second.contains(&emulator.peek(0xC001)), "{}", emulator.peek(0xC001));
+
+            // Picking another song starts over
+            gbs.play(&mut emulator, gbs.first_song());
+            emulator.run_cycles(70_224 * 2);
+            assert_eq!(emulator.peek(0xC000), 1);
+            assert!(emulator.peek(0xC001) <= 2);
+        }
+    }
+}
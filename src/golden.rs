@@ -0,0 +1,181 @@
+//! Golden-state regression baselines
+//!
+//! Recording runs each ROM for a number of frames and keeps hashes of the
+//! final framebuffer, all audio produced and the final save state.
+//! Checking later reruns the same ROMs and reports every hash that
+//! changed, catching accuracy regressions between versions.
+//!
+//! Baselines are stored in one binary fixtures file: the magic `GB3G`, a
+//! version byte and an entry count (u32), then per entry the ROM path
+//! below the test directory (u16 length and UTF-8), the frame count (u32)
+//! and the three hashes (u64 each), all little-endian.
+
+use crate::test_runner;
+use gb3000::frame::{hash_bytes, HASH_START};
+use gb3000::{Emulator, GbModel};
+use std::path::Path;
+
+/// Magic bytes at the start of a fixtures file
+const MAGIC: &[u8; 4] = b"GB3G";
+
+/// Fixtures file layout version
+const VERSION: u8 = 1;
+
+/// Hashes of one ROM after a number of frames
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Golden {
+    /// ROM path below the test directory, with `/` separators
+    pub name: String,
+    pub frames: u32,
+    pub frame_hash: u64,
+    pub audio_hash: u64,
+    pub state_hash: u64,
+}
+
+/// Run `rom` for `frames` frames and hash the result
+pub fn record(rom: &Path, name: &str, frames: u32) -> Result<Golden, String> {
+    let data = std::fs::read(rom).map_err(|e| format!("{}: {}", rom.display(), e))?;
+    let mut emu = Emulator::new();
+    emu.load_rom(&data);
+    emu.reset_for_model(GbModel::from_filename(&rom.to_string_lossy()));
+
+    let mut audio_hash = HASH_START;
+    for _ in 0..frames {
+        emu.run_frame();
+        for sample in emu.audio_samples() {
+            audio_hash = hash_bytes(audio_hash, &sample.to_le_bytes());
+        }
+    }
+    Ok(Golden {
+        name: name.to_string(),
+        frames,
+        frame_hash: emu.frame_hash(),
+        audio_hash,
+        state_hash: hash_bytes(HASH_START, &emu.save_state()),
+    })
+}
+
+/// Path of `rom` below `root`, with `/` separators
+fn relative_name(root: &Path, rom: &Path) -> String {
+    let path = if root.is_file() {
+        rom.file_name().map(Path::new).unwrap_or(rom)
+    } else {
+        rom.strip_prefix(root).unwrap_or(rom)
+    };
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Record every ROM below a directory, or a single ROM
+pub fn record_all(test_path: &str, frames: u32) -> Result<Vec<Golden>, String> {
+    let root = Path::new(test_path);
+    test_runner::test_roms(root)
+        .iter()
+        .map(|rom| record(rom, &relative_name(root, rom), frames))
+        .collect()
+}
+
+/// Rerun the ROMs of `baseline` and describe each difference
+pub fn check(test_path: &str, baseline: &[Golden]) -> Vec<String> {
+    let root = Path::new(test_path);
+    let mut problems = Vec::new();
+    for want in baseline {
+        let rom = if root.is_file() { root.to_path_buf() } else { root.join(&want.name) };
+        let got = match record(&rom, &want.name, want.frames) {
+            Ok(got) => got,
+            Err(e) => {
+                problems.push(e);
+                continue;
+            }
+        };
+        for (what, changed) in [
+            ("framebuffer", got.frame_hash != want.frame_hash),
+            ("audio", got.audio_hash != want.audio_hash),
+            ("state", got.state_hash != want.state_hash),
+        ] {
+            if changed {
+                problems.push(format!("{}: {} changed after {} frames", want.name, what, want.frames));
+            }
+        }
+    }
+    problems
+}
+
+/// Serialize baselines into the fixtures file layout
+pub fn encode(goldens: &[Golden]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&(goldens.len() as u32).to_le_bytes());
+    for g in goldens {
+        out.extend_from_slice(&(g.name.len() as u16).to_le_bytes());
+        out.extend_from_slice(g.name.as_bytes());
+        out.extend_from_slice(&g.frames.to_le_bytes());
+        for h in [g.frame_hash, g.audio_hash, g.state_hash] {
+            out.extend_from_slice(&h.to_le_bytes());
+        }
+    }
+    out
+}
+
+/// Parse a fixtures file
+pub fn decode(data: &[u8]) -> Result<Vec<Golden>, String> {
+    let mut pos = 0;
+    let mut take = |len: usize| {
+        let bytes = data.get(pos..pos + len).ok_or("Fixtures file is truncated")?;
+        pos += len;
+        Ok::<_, String>(bytes)
+    };
+    if take(4)? != MAGIC {
+        return Err("Not a fixtures file".to_string());
+    }
+    if take(1)?[0] != VERSION {
+        return Err("Unsupported fixtures file version".to_string());
+    }
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut goldens = Vec::new();
+    for _ in 0..count {
+        let len = u16::from_le_bytes(take(2)?.try_into().unwrap()) as usize;
+        let name = String::from_utf8(take(len)?.to_vec()).map_err(|_| "Bad ROM name in fixtures file")?;
+        let frames = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let mut hashes = [0u64; 3];
+        for h in &mut hashes {
+            *h = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        }
+        let [frame_hash, audio_hash, state_hash] = hashes;
+        goldens.push(Golden { name, frames, frame_hash, audio_hash, state_hash });
+    }
+    Ok(goldens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_and_checks_baselines() {
+        let dir = std::env::temp_dir().join("gb3000-golden-test");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut rom = vec![0u8; 0x8000];
+        // LD A, $E4; LDH (BGP), A; then scroll forever: INC A; LDH (SCY), A; JR -5
+        rom[0x0100..0x0109].copy_from_slice(&[0x3E, 0xE4, 0xE0, 0x47, 0x3C, 0xE0, 0x42, 0x18, 0xFB]);
+        std::fs::write(dir.join("a.gb"), &rom).unwrap();
+        std::fs::write(dir.join("sub/b.gb"), &rom).unwrap();
+
+        let baseline = record_all(dir.to_str().unwrap(), 5).unwrap();
+        let names: Vec<_> = baseline.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["a.gb", "sub/b.gb"]);
+        assert_eq!(decode(&encode(&baseline)), Ok(baseline.clone()));
+        assert!(check(dir.to_str().unwrap(), &baseline).is_empty());
+
+        // A change in behavior shows up
+        rom[0x0101] = 0x1B;
+        std::fs::write(dir.join("sub/b.gb"), &rom).unwrap();
+        let problems = check(dir.to_str().unwrap(), &baseline);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(problems.iter().any(|p| p == "sub/b.gb: state changed after 5 frames"), "{:?}", problems);
+        assert!(problems.iter().all(|p| p.starts_with("sub/b.gb")));
+
+        assert!(decode(b"GB3G\x01\x01\x00\x00\x00").is_err());
+        assert!(decode(b"nope").is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! Windowless runs for scripting, debugging and CI smoke tests
+//!
+//! `gb3000-ui run game.gb --frames 600 --screenshot out.png` runs the game
+//! for a fixed number of frames with no window or audio device and writes
+//! what was asked for: a PNG of the last frame, its raw color indices,
+//! the core's save state or a VGM log of the music.
+//!
+//! A `.gbs` sound file in place of the ROM plays one of its songs, the
+//! file's first unless `--song N` (counting from 1) picks another, which
+//! with `--vgm` turns a GBS rip into a VGM.
+//!
+//! Built with the `lua` feature, `--lua script.lua` runs a script
+//! alongside, printing what it prints; its drawing has nowhere to go.
+
+use crate::capture;
+use crate::config::PALETTES;
+#[cfg(feature = "lua")]
+use gb3000::lua::Script;
+use gb3000::{Emulator, GbModel, GbsFile, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fs;
+#[cfg(feature = "lua")]
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: gb3000-ui run <rom> [--frames N] [--model MODEL] [--load-state FILE] \
+                         [--screenshot FILE.png] [--dump-frame FILE] [--save-state FILE] \
+                         [--vgm FILE.vgm] [--song N] [--lua SCRIPT]";
+
+/// Frames run when `--frames` isn't given, ten seconds of emulation
+const DEFAULT_FRAMES: u32 = 600;
+
+/// What a headless run does
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOptions {
+    pub rom: PathBuf,
+    pub frames: u32,
+    /// Model to reset into instead of the default
+    pub model: Option<GbModel>,
+    /// Save state to start from
+    pub load_state: Option<PathBuf>,
+    /// PNG of the last frame, in the first palette
+    pub screenshot: Option<PathBuf>,
+    /// Last frame as raw 160x144 color indices (0-3), one byte per pixel
+    pub dump_frame: Option<PathBuf>,
+    /// Save state after the last frame
+    pub save_state: Option<PathBuf>,
+    /// VGM log of the sound registers over the whole run
+    pub vgm: Option<PathBuf>,
+    /// Song to play when the ROM is a GBS file, counting from 1
+    pub song: Option<u8>,
+    /// Lua script run after each frame
+    #[cfg(feature = "lua")]
+    pub lua: Option<PathBuf>,
+}
+
+impl RunOptions {
+    /// Parse the arguments after `run`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut rom = None;
+        let mut options = RunOptions {
+            rom: PathBuf::new(),
+            frames: DEFAULT_FRAMES,
+            model: None,
+            load_state: None,
+            screenshot: None,
+            dump_frame: None,
+            save_state: None,
+            vgm: None,
+            song: None,
+            #[cfg(feature = "lua")]
+            lua: None,
+        };
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            let mut value = || rest.next().ok_or_else(|| format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--frames" => {
+                    let n = value()?;
+                    options.frames = n.parse().map_err(|_| format!("Bad frame count: {}", n))?;
+                }
+                "--model" => {
+                    let name = value()?;
+                    let model = GbModel::ALL.into_iter().find(|m| m.to_string().eq_ignore_ascii_case(name));
+                    options.model = Some(model.ok_or_else(|| format!("Unknown model: {}", name))?);
+                }
+                "--load-state" => options.load_state = Some(PathBuf::from(value()?)),
+                "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+                "--dump-frame" => options.dump_frame = Some(PathBuf::from(value()?)),
+                "--save-state" => options.save_state = Some(PathBuf::from(value()?)),
+                "--vgm" => options.vgm = Some(PathBuf::from(value()?)),
+                "--song" => {
+                    let n = value()?;
+                    let song = n.parse().ok().filter(|&song| song > 0);
+                    options.song = Some(song.ok_or_else(|| format!("Bad song number: {}", n))?);
+                }
+                #[cfg(feature = "lua")]
+                "--lua" => options.lua = Some(PathBuf::from(value()?)),
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+                path if rom.is_none() => rom = Some(PathBuf::from(path)),
+                extra => return Err(format!("Unexpected argument: {}", extra)),
+            }
+        }
+        options.rom = rom.ok_or("No ROM given")?;
+        Ok(options)
+    }
+}
+
+/// Run the game as described and write the requested files
+///
+/// Returns a one-line summary of the run.
+pub fn run(options: &RunOptions) -> Result<String, String> {
+    let rom = fs::read(&options.rom).map_err(|e| format!("Failed to read ROM: {}", e))?;
+    let mut emulator = Emulator::new();
+    emulator.set_audio_enabled(false);
+    if options.rom.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gbs")) {
+        let gbs = GbsFile::parse(&rom).map_err(|e| format!("Failed to read GBS: {}", e))?;
+        let song = options.song.map_or(gbs.first_song(), |n| n - 1);
+        if song >= gbs.song_count() {
+            return Err(format!("{} only has {} songs", options.rom.display(), gbs.song_count()));
+        }
+        gbs.play(&mut emulator, song);
+    } else {
+        emulator.load_rom(&rom);
+        emulator.reset_for_model(options.model.unwrap_or_default());
+    }
+    if let Some(path) = &options.load_state {
+        let state = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        emulator
+            .load_state(&state)
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+    }
+
+    #[cfg(feature = "lua")]
+    let mut script = options.lua.as_deref().map(|path| load_script(path, &mut emulator)).transpose()?;
+
+    if options.vgm.is_some() {
+        emulator.start_vgm_log();
+    }
+
+    // Only the last frame can end up in a file
+    for frame in 0..options.frames {
+        emulator.set_video_enabled(frame + 1 == options.frames);
+        #[cfg(feature = "lua")]
+        if let Some(script) = &script {
+            script.apply_input(&mut emulator);
+        }
+        emulator.run_frame();
+        #[cfg(feature = "lua")]
+        if let Some(script) = &mut script {
+            let result = script.after_frame(&mut emulator);
+            for line in script.take_output() {
+                println!("{}", line);
+            }
+            result.map_err(|e| e.to_string())?;
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        let rgba = emulator.screenshot_rgba(&PALETTES[0].1);
+        capture::write_png(path, &rgba, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)?;
+    }
+    if let Some(path) = &options.dump_frame {
+        fs::write(path, emulator.framebuffer()).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &options.save_state {
+        fs::write(path, emulator.save_state()).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    if let (Some(path), Some(vgm)) = (&options.vgm, emulator.stop_vgm_log()) {
+        fs::write(path, vgm).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(format!(
+        "Ran {} frames ({} cycles) of {}",
+        options.frames,
+        emulator.total_cycles(),
+        options.rom.display()
+    ))
+}
+
+/// Read a Lua script and run its top level against `emulator`, printing
+/// anything it prints
+#[cfg(feature = "lua")]
+pub fn load_script(path: &Path, emulator: &mut Emulator) -> Result<Script, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    let mut script = Script::load(&name, &source, emulator).map_err(|e| e.to_string())?;
+    for line in script.take_output() {
+        println!("{}", line);
+    }
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_run_options() {
+        let options =
+            RunOptions::parse(&args("game.gb --frames 60 --model dmg0 --screenshot out.png --vgm song.vgm")).unwrap();
+        assert_eq!(options.rom, PathBuf::from("game.gb"));
+        assert_eq!(options.frames, 60);
+        assert_eq!(options.model, Some(GbModel::Dmg0));
+        assert_eq!(options.screenshot, Some(PathBuf::from("out.png")));
+        assert_eq!(options.vgm, Some(PathBuf::from("song.vgm")));
+        assert_eq!(options.save_state, None);
+
+        assert_eq!(RunOptions::parse(&args("game.gb")).unwrap().frames, DEFAULT_FRAMES);
+        assert!(RunOptions::parse(&args("--frames 60")).is_err());
+        assert!(RunOptions::parse(&args("game.gb --frames")).is_err());
+        assert!(RunOptions::parse(&args("game.gb --frames lots")).is_err());
+        assert!(RunOptions::parse(&args("game.gb --model nes")).is_err());
+        assert!(RunOptions::parse(&args("game.gb other.gb")).is_err());
+        assert_eq!(RunOptions::parse(&args("music.gbs --song 3")).unwrap().song, Some(3));
+        assert!(RunOptions::parse(&args("music.gbs --song 0")).is_err());
+    }
+
+    #[test]
+    fn run_writes_requested_files() {
+        let dir = std::env::temp_dir().join("gb3000-headless-test");
+        fs::create_dir_all(&dir).unwrap();
+        let mut rom = vec![0u8; 0x8000];
+        // JR -2
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+        fs::write(dir.join("game.gb"), &rom).unwrap();
+
+        let dir_arg = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let options = RunOptions::parse(&[
+            dir_arg("game.gb"),
+            "--frames".to_string(),
+            "3".to_string(),
+            "--screenshot".to_string(),
+            dir_arg("out.png"),
+            "--dump-frame".to_string(),
+            dir_arg("out.bin"),
+            "--save-state".to_string(),
+            dir_arg("out.ss"),
+        ])
+        .unwrap();
+        let summary = run(&options).unwrap();
+        assert!(summary.starts_with("Ran 3 frames"), "{}", summary);
+
+        let (_, width, height) = capture::read_png(&dir.join("out.png")).unwrap();
+        assert_eq!((width, height), (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32));
+        assert_eq!(fs::read(dir.join("out.bin")).unwrap().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+
+        // The saved state resumes where the run stopped
+        let mut resumed = options.clone();
+        resumed.frames = 0;
+        resumed.load_state = Some(dir.join("out.ss"));
+        resumed.save_state = Some(dir.join("resumed.ss"));
+        run(&resumed).unwrap();
+        let saved = fs::read(dir.join("out.ss")).unwrap();
+        let resaved = fs::read(dir.join("resumed.ss")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(saved, resaved);
+    }
+
+    #[cfg(feature = "lua")]
+    #[test]
+    fn lua_errors_end_the_run() {
+        let dir = std::env::temp_dir().join("gb3000-headless-lua-test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("game.gb"), vec![0u8; 0x8000]).unwrap();
+        let script = "event.onframeend(function()\n  if emu.framecount() >= 2 then error('stop') end\nend)";
+        fs::write(dir.join("stop.lua"), script).unwrap();
+
+        let dir_arg = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let options =
+            RunOptions::parse(&[dir_arg("game.gb"), "--lua".to_string(), dir_arg("stop.lua")]).unwrap();
+        assert_eq!(options.lua, Some(dir.join("stop.lua")));
+        let result = run(&options);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Err("script error: stop.lua:2: stop".to_string()));
+    }
+}
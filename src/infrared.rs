@@ -0,0 +1,68 @@
+//! CGB infrared port
+//!
+//! The RP register (FF56) drives an IR LED and reads a photodiode. Games
+//! such as Pokémon Crystal (Mystery Gift) and the Pocket Pikachu pedometers
+//! talk by blinking the LED with carefully timed loops, so a device only
+//! has to answer "is light hitting the sensor right now?".
+//!
+//! Attach a device with [`Emulator::set_infrared_device`](crate::Emulator::set_infrared_device).
+//! [`InfraredLink::pair`] connects two emulators; the frontend must then
+//! run them in small interleaved slices (a few hundred cycles with
+//! [`Emulator::run_cycles`](crate::Emulator::run_cycles)), as the games
+//! time each other's pulses.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Something on the other side of the IR port
+pub trait InfraredDevice: Send {
+    /// Called when the game turns its LED on or off
+    fn set_led(&mut self, on: bool);
+    /// Whether the sensor currently sees light
+    fn light(&mut self) -> bool;
+}
+
+/// A mirror in front of the port: the sensor sees the game's own LED
+#[derive(Debug, Clone, Default)]
+pub struct Loopback {
+    on: bool,
+}
+
+impl InfraredDevice for Loopback {
+    fn set_led(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    fn light(&mut self) -> bool {
+        self.on
+    }
+}
+
+/// One end of an IR connection between two emulators
+#[derive(Debug, Clone)]
+pub struct InfraredLink {
+    own: Arc<AtomicBool>,
+    other: Arc<AtomicBool>,
+}
+
+impl InfraredLink {
+    /// Two ends facing each other
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(AtomicBool::new(false));
+        let b = Arc::new(AtomicBool::new(false));
+        (
+            Self { own: a.clone(), other: b.clone() },
+            Self { own: b, other: a },
+        )
+    }
+}
+
+impl InfraredDevice for InfraredLink {
+    fn set_led(&mut self, on: bool) {
+        self.own.store(on, Ordering::Relaxed);
+    }
+
+    fn light(&mut self) -> bool {
+        self.other.load(Ordering::Relaxed)
+    }
+}
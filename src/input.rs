@@ -0,0 +1,103 @@
+//! Keyboard input for the desktop UI
+//!
+//! The window thread polls the keyboard into an [`Input`] once per host
+//! frame; the emulation thread hands it to the emulator before every
+//! emulated frame. Turbo keys press their button on alternate runs of
+//! frames, counted by the emulator's own frame counter so the rate holds
+//! at any speed.
+//!
+//! Holding A, B, Start and Select together is the reset combination many
+//! games check for themselves; [`Input::poll`] also reports when it's
+//! pressed, for the frontend to soft reset games that don't.
+
+use crate::config::{KeyBindings, BUTTONS, TURBO_BUTTONS};
+use gb3000::{Button, Emulator};
+use minifb::Window;
+
+/// Key state of every binding, as last polled
+#[derive(Debug, Clone, Default)]
+pub struct Input {
+    /// Whether each entry in [`BUTTONS`] is held
+    held: [bool; BUTTONS.len()],
+    /// Whether each entry in [`TURBO_BUTTONS`] is held
+    turbo: [bool; TURBO_BUTTONS.len()],
+    /// Turbo presses per second
+    turbo_rate: u8,
+}
+
+/// Buttons held together for a soft reset
+const RESET_COMBO: [Button; 4] = [Button::A, Button::B, Button::Start, Button::Select];
+
+impl Input {
+    /// Read the bound keys from `window`
+    ///
+    /// Returns true when this poll completed the reset combination.
+    pub fn poll(&mut self, window: &Window, keys: &KeyBindings, turbo_rate: u8) -> bool {
+        let combo_was_held = self.combo_held();
+        let (held, turbo) = keys.0.split_at(BUTTONS.len());
+        for (state, &key) in self.held.iter_mut().zip(held) {
+            *state = window.is_key_down(key);
+        }
+        for (state, &key) in self.turbo.iter_mut().zip(turbo) {
+            *state = window.is_key_down(key);
+        }
+        self.turbo_rate = turbo_rate;
+        self.combo_held() && !combo_was_held
+    }
+
+    /// Whether every button of the reset combination is held
+    fn combo_held(&self) -> bool {
+        RESET_COMBO
+            .iter()
+            .all(|&button| BUTTONS.iter().zip(&self.held).any(|(&(b, _), &held)| b == button && held))
+    }
+
+    /// Set the emulator's buttons for its next frame
+    pub fn apply(&self, emulator: &mut Emulator) {
+        let turbo_down = turbo_phase(emulator.frame_count(), self.turbo_rate);
+        for (&(button, _), &held) in BUTTONS.iter().zip(&self.held) {
+            let turbo = TURBO_BUTTONS
+                .iter()
+                .zip(&self.turbo)
+                .any(|(&(b, _), &held)| b == button && held && turbo_down);
+            emulator.set_button(button, held || turbo);
+        }
+    }
+}
+
+/// Whether a held turbo key presses its button on `frame`
+///
+/// The button is down for the first half of each `60 / rate` frame cycle.
+fn turbo_phase(frame: u64, rate: u8) -> bool {
+    let half = (30 / rate.max(1) as u64).max(1);
+    (frame / half).is_multiple_of(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turbo_alternates_at_its_rate() {
+        let pattern = |rate| (0..8).map(|frame| turbo_phase(frame, rate)).collect::<Vec<_>>();
+        assert_eq!(pattern(30), [true, false, true, false, true, false, true, false]);
+        assert_eq!(pattern(15), [true, true, false, false, true, true, false, false]);
+        assert_eq!(pattern(10), [true, true, true, false, false, false, true, true]);
+        // Rates above 30 can't alternate faster than every frame
+        assert_eq!(pattern(60), pattern(30));
+    }
+
+    #[test]
+    fn reset_combo_needs_all_four_buttons() {
+        let mut input = Input::default();
+        let hold = |input: &mut Input, buttons: &[Button]| {
+            for (&(b, _), held) in BUTTONS.iter().zip(input.held.iter_mut()) {
+                *held = buttons.contains(&b);
+            }
+        };
+        hold(&mut input, &RESET_COMBO[..3]);
+        assert!(!input.combo_held());
+        hold(&mut input, &[Button::Up, Button::A, Button::B, Button::Start, Button::Select]);
+        assert!(input.combo_held());
+    }
+}
@@ -0,0 +1,126 @@
+//! Log of interrupt requests and dispatches, for debuggers
+//!
+//! With [`Emulator::set_interrupt_logging`](crate::Emulator::set_interrupt_logging)
+//! on, every interrupt flag that goes up in IF, raised by the hardware or
+//! written by the game, is logged with the cycle it rose on, and every
+//! dispatch with the address it interrupted and how long the interrupt
+//! waited. A jittery raster effect usually shows up here as STAT
+//! interrupts waiting behind a handler that runs with interrupts off.
+//! Only the most recent [`LOG_SIZE`] entries are kept.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Entries kept before the oldest are dropped
+pub const LOG_SIZE: usize = 1024;
+
+/// Interrupt sources, in priority order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 5] =
+        [Interrupt::VBlank, Interrupt::LcdStat, Interrupt::Timer, Interrupt::Serial, Interrupt::Joypad];
+
+    /// Bit in IF and IE
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Address the CPU jumps to
+    pub fn vector(self) -> u16 {
+        0x0040 + 8 * self as u16
+    }
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Interrupt::VBlank => "VBlank",
+            Interrupt::LcdStat => "STAT",
+            Interrupt::Timer => "Timer",
+            Interrupt::Serial => "Serial",
+            Interrupt::Joypad => "Joypad",
+        })
+    }
+}
+
+/// One entry of the interrupt log; cycles count as
+/// [`Emulator::total_cycles`](crate::Emulator::total_cycles) does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptEvent {
+    /// The interrupt's flag went up during the M-cycle ending at `cycle`
+    Requested { interrupt: Interrupt, cycle: u64 },
+    /// The CPU began dispatching the interrupt at `cycle`, leaving the
+    /// code at `pc`; `latency` is the cycles since the request, unknown
+    /// for one requested before logging began
+    Serviced { interrupt: Interrupt, cycle: u64, pc: u16, latency: Option<u64> },
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InterruptLog {
+    events: VecDeque<InterruptEvent>,
+    /// IF as last seen
+    flags: u8,
+    /// When each interrupt was last requested
+    requested: [Option<u64>; 5],
+}
+
+impl InterruptLog {
+    /// Start logging with IF holding `flags`; those interrupts are
+    /// already pending, not newly requested
+    pub fn new(flags: u8) -> Self {
+        Self { flags: flags & 0x1F, ..Self::default() }
+    }
+
+    pub fn events(&self) -> &VecDeque<InterruptEvent> {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Look for flags that went up since the last call
+    pub fn watch(&mut self, flags: u8, cycle: u64) {
+        let flags = flags & 0x1F;
+        let risen = flags & !self.flags;
+        self.flags = flags;
+        if risen == 0 {
+            return;
+        }
+        for (i, interrupt) in Interrupt::ALL.into_iter().enumerate() {
+            if risen & interrupt.mask() != 0 {
+                self.requested[i] = Some(cycle);
+                self.push(InterruptEvent::Requested { interrupt, cycle });
+            }
+        }
+    }
+
+    /// Note a dispatch that began at `cycle`, from `pc` to `vector`
+    ///
+    /// A dispatch cancelled by a push overwriting IE jumps to 0x0000 and
+    /// isn't logged.
+    pub fn serviced(&mut self, vector: u16, pc: u16, cycle: u64) {
+        let Some(i) = Interrupt::ALL.iter().position(|interrupt| interrupt.vector() == vector) else {
+            return;
+        };
+        let interrupt = Interrupt::ALL[i];
+        let latency = self.requested[i].take().map(|at| cycle.saturating_sub(at));
+        self.flags &= !interrupt.mask();
+        self.push(InterruptEvent::Serviced { interrupt, cycle, pc, latency });
+    }
+
+    fn push(&mut self, event: InterruptEvent) {
+        if self.events.len() == LOG_SIZE {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
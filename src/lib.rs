@@ -42,21 +42,72 @@
 //! }
 //! ```
 
+#[macro_use]
+mod logging;
+mod licensee;
+
 pub mod apu;
 pub mod cpu;
+pub mod disasm;
+#[cfg(feature = "capi")]
+pub mod ffi;
+pub mod frame;
+pub mod gbs;
+pub mod infrared;
+pub mod interrupt_log;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "lua")]
+pub mod lua;
 pub mod memory;
+pub mod mobile;
+pub mod movie;
+pub mod netplay;
+pub mod options;
 pub mod ppu;
+pub mod profile;
+pub mod rewind;
+#[cfg(feature = "romdb")]
+pub mod romdb;
+pub mod rtc;
+pub mod savefile;
+pub mod serial;
+pub mod sgb;
+pub mod state;
 pub mod timer;
+pub mod vgm;
 
 use apu::Apu;
 use cpu::Cpu;
-use memory::{interrupts, Memory};
+use memory::Memory;
 use ppu::Ppu;
+use profile::{Part, Profiler};
+use serial::Serial;
+use state::{StateReader, StateWriter};
 use timer::Timer;
 
 // Re-export commonly used types
-pub use cpu::GbModel;
-pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use apu::ChannelOutput;
+pub use cpu::{CpuState, GbModel};
+pub use disasm::Instruction;
+pub use frame::FrameDiff;
+pub use gbs::{GbsError, GbsFile};
+pub use infrared::InfraredDevice;
+pub use interrupt_log::{Interrupt, InterruptEvent};
+pub use movie::{Movie, MovieError};
+pub use netplay::{NetplayConfig, NetplaySession};
+pub use options::{AccuracyOptions, AudioOptions, EmulatorOptions, InitialRam};
+pub use ppu::{OamEntry, PpuWrite, TileMap, SCREEN_HEIGHT, SCREEN_WIDTH, TILE_MAP_SIZE, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH};
+pub use profile::{OpcodeHistogram, Profile};
+pub use rewind::RewindBuffer;
+#[cfg(feature = "romdb")]
+pub use romdb::RomDatabase;
+pub use savefile::SaveFormat;
+pub use serial::SerialDevice;
+pub use sgb::{SGB_HEIGHT, SGB_WIDTH};
+use sgb::MAX_PLAYERS;
+pub use state::StateError;
+pub use vgm::{ApuWrite, VgmLog};
 
 /// Game Boy button enumeration
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -71,6 +122,127 @@ pub enum Button {
     Start,
 }
 
+impl Button {
+    /// All buttons, in joypad register bit order
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Bit for this button in the joypad state byte
+    const fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+/// The set of buttons held during one frame
+///
+/// Each bit is a pressed button, in the same order as [`Button::ALL`].
+/// Used with [`Emulator::run_frames_with_input`] to drive the emulator
+/// from scripts, tests, and bots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct InputFrame(pub u8);
+
+impl InputFrame {
+    /// No buttons pressed
+    pub const NONE: InputFrame = InputFrame(0);
+
+    /// Build a frame from a list of pressed buttons
+    pub fn from_buttons(buttons: &[Button]) -> Self {
+        InputFrame(buttons.iter().fold(0, |acc, b| acc | b.mask()))
+    }
+
+    /// Return a copy with `button` pressed
+    pub fn with(self, button: Button) -> Self {
+        InputFrame(self.0 | button.mask())
+    }
+
+    /// Check if `button` is pressed in this frame
+    pub fn is_pressed(self, button: Button) -> bool {
+        self.0 & button.mask() != 0
+    }
+}
+
+/// Something that happened during emulation
+///
+/// Recorded while enabled with [`Emulator::set_events_enabled`] and
+/// collected with [`Emulator::drain_events`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorEvent {
+    /// VBlank started: the framebuffer holds a complete frame
+    VBlank,
+    /// The PPU finished drawing `line` and entered HBlank
+    HBlank { line: u8 },
+    /// The game started sending `byte` over the link cable
+    SerialByte(u8),
+    /// The CPU has been spinning at `pc` for the cycles given to
+    /// [`Emulator::set_hang_detection`] with no way to take an interrupt
+    /// and no I/O register access: the game has most likely crashed
+    Hang { pc: u16 },
+    /// The instruction at `pc` pushed to or popped from the stack word at
+    /// `addr` among the I/O registers or IE, with
+    /// [`Emulator::set_stack_check`] on: the stack overflowed out of high
+    /// RAM or underflowed past its top
+    StackWarning { pc: u16, addr: u16 },
+}
+
+/// One word on the stack, as returned by [`Emulator::stack_slice`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEntry {
+    /// Where the word is
+    pub addr: u16,
+    /// The word itself
+    pub value: u16,
+    /// The word points just past a CALL or RST, so it's most likely a
+    /// return address
+    pub return_address: bool,
+}
+
+/// What a call to [`Emulator::step_instruction`] or
+/// [`Emulator::run_scanline`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StepInfo {
+    /// T-cycles emulated, counted at normal speed
+    pub cycles: u32,
+    /// Program counter afterwards
+    pub pc: u16,
+    /// A frame was completed (VBlank started)
+    pub frame_completed: bool,
+}
+
+/// What a call to [`Emulator::run_frame`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameReport {
+    /// T-cycles emulated, counted at normal speed
+    pub cycles: u32,
+    /// Scanlines the PPU drew; 0 while the LCD is off
+    pub scanlines_rendered: u32,
+    /// Audio samples generated, left and right counted separately
+    pub audio_samples: usize,
+    /// Events recorded, if [enabled](Emulator::set_events_enabled)
+    pub events: usize,
+    /// A frame was completed: VBlank started, or with the LCD off another
+    /// 70224 cycles passed; false when a breakpoint stopped the run first
+    pub frame_completed: bool,
+    /// The LCD is off and the framebuffer blank white
+    pub lcd_off: bool,
+}
+
 /// ROM information parsed from header
 #[derive(Debug, Clone)]
 pub struct RomInfo {
@@ -88,23 +260,242 @@ pub struct RomInfo {
     pub rom_size_code: u8,
     /// RAM size code
     pub ram_size_code: u8,
+    /// CGB flag (0x0143): 0x80 = CGB enhanced, 0xC0 = CGB only
+    pub cgb_flag: u8,
+    /// SGB flag (0x0146): 0x03 = SGB functions supported
+    pub sgb_flag: u8,
+    /// Old licensee code (0x014B); 0x33 means the new code is used
+    pub licensee_code: u8,
+    /// New licensee code (0x0144-0x0145), two ASCII characters
+    pub new_licensee_code: [u8; 2],
+    /// Destination code (0x014A): 0x00 = Japan, 0x01 = overseas
+    pub destination_code: u8,
+    /// Mask ROM version number (0x014C), usually 0
+    pub version: u8,
+    /// Header checksum stored at 0x014D
+    pub header_checksum: u8,
+    /// Header checksum computed over 0x0134-0x014C
+    pub computed_header_checksum: u8,
+    /// Global checksum stored at 0x014E-0x014F
+    pub global_checksum: u16,
+    /// Global checksum computed over the whole ROM
+    pub computed_global_checksum: u16,
+    /// Whether the Nintendo logo at 0x0104-0x0133 is intact
+    pub logo_valid: bool,
+    /// Length of the ROM image in bytes
+    pub file_size: usize,
+}
+
+impl RomInfo {
+    /// ROM size in bytes declared by the header, if the code is a known one
+    pub fn declared_size(&self) -> Option<usize> {
+        match self.rom_size_code {
+            code @ 0x00..=0x08 => Some(0x8000 << code),
+            0x52 => Some(72 * 0x4000),
+            0x53 => Some(80 * 0x4000),
+            0x54 => Some(96 * 0x4000),
+            _ => None,
+        }
+    }
+
+    /// How the image's length compares with the declared ROM size
+    pub fn size_check(&self) -> RomSizeCheck {
+        match self.declared_size() {
+            None => RomSizeCheck::UnknownSize,
+            Some(size) if self.file_size > size => RomSizeCheck::Overdump,
+            Some(size) if self.file_size < size => RomSizeCheck::Truncated,
+            Some(_) => RomSizeCheck::Matches,
+        }
+    }
+
+    /// What the CGB flag says about Game Boy Color support
+    ///
+    /// The CGB boot ROM only looks at bit 7, so anything with it set
+    /// counts as at least compatible.
+    pub fn cgb_support(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::Incompatible,
+        }
+    }
+
+    /// Whether the game uses Super Game Boy functions
+    ///
+    /// The SGB only enables them for 0x03 in the SGB flag together with
+    /// the old licensee code 0x33.
+    pub fn sgb_support(&self) -> bool {
+        self.sgb_flag == 0x03 && self.licensee_code == licensee::USE_NEW_CODE
+    }
+
+    /// Publisher named by the licensee code, old or new
+    pub fn publisher(&self) -> Option<&'static str> {
+        licensee::publisher(self.licensee_code, self.new_licensee_code)
+    }
+
+    /// Where the game was meant to be sold
+    pub fn destination(&self) -> Destination {
+        match self.destination_code {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            code => Destination::Unknown(code),
+        }
+    }
+}
+
+/// Result of [`RomInfo::cgb_support`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// A DMG game, run by the CGB in compatibility mode
+    Incompatible,
+    /// Enhanced for the CGB but still playable on a DMG
+    Compatible,
+    /// Needs a CGB
+    Only,
+}
+
+/// Result of [`RomInfo::destination`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    /// Anywhere but Japan
+    Overseas,
+    /// A code other than 0x00 or 0x01
+    Unknown(u8),
+}
+
+/// Result of [`RomInfo::size_check`]
+///
+/// The emulator runs all of these: banks are numbered modulo the smaller
+/// of the two sizes, so a truncated image mirrors the banks it has and
+/// the tail of an overdump is never mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomSizeCheck {
+    /// The image is exactly the declared size
+    Matches,
+    /// The image has extra data past the declared size
+    Overdump,
+    /// The image is shorter than the declared size
+    Truncated,
+    /// The header's ROM size code isn't a known one
+    UnknownSize,
+}
+
+/// Result of [`Emulator::verify_rom`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomValidation {
+    /// The ROM is large enough to contain a header
+    pub has_header: bool,
+    /// The boot ROM would accept the Nintendo logo
+    pub logo_ok: bool,
+    /// The boot ROM would accept the header checksum
+    pub header_checksum_ok: bool,
+    /// The global checksum matches; hardware never checks it, but a
+    /// mismatch usually means a bad or modified dump
+    pub global_checksum_ok: bool,
+    /// How the image's length compares with the header's ROM size
+    pub size: RomSizeCheck,
+}
+
+impl RomValidation {
+    /// Whether every check passed
+    pub fn is_valid(&self) -> bool {
+        self.has_header
+            && self.logo_ok
+            && self.header_checksum_ok
+            && self.global_checksum_ok
+            && self.size == RomSizeCheck::Matches
+    }
+
+    /// Whether real hardware would refuse to boot the ROM
+    pub fn would_lock_up(&self) -> bool {
+        !(self.has_header && self.logo_ok && self.header_checksum_ok)
+    }
 }
 
+/// The logo the boot ROM compares against 0x0104-0x0133
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
 /// The main emulator struct
 ///
 /// This is the primary interface for using the emulator. It ties together
 /// all the components (CPU, Memory, PPU, APU, Timer) and provides a simple
 /// API for running games.
+///
+/// It is `Send`, so a frontend can run it on a thread of its own; hooks,
+/// audio sinks and IR and serial devices are required to be `Send` for
+/// that reason.
 pub struct Emulator {
     cpu: Cpu,
     memory: Memory,
     ppu: Ppu,
     apu: Apu,
     timer: Timer,
-    /// Button state (active LOW internally)
-    button_state: u8,
+    serial: Serial,
+    /// Hardware model set by the last reset
+    model: GbModel,
+    /// Options applied at each reset
+    options: EmulatorOptions,
+    /// Button state of each controller (active LOW internally); players
+    /// after the first are only seen by SGB multiplayer games
+    button_states: [u8; MAX_PLAYERS],
+    /// Button states waiting for the next frame boundary, in queued input
+    /// mode
+    queued_inputs: Option<[u8; MAX_PLAYERS]>,
+    /// Receives audio samples at the end of each run call, if set
+    audio_sink: Option<AudioSink>,
+    /// Whatever faces the CGB infrared port, if anything
+    infrared: Option<Box<dyn InfraredDevice>>,
+    /// LED state last reported to the infrared device
+    ir_led: bool,
+    /// Whatever is plugged into the link port, if anything
+    serial_device: Option<Box<dyn SerialDevice>>,
+    /// T-cycles emulated since the last reset, at normal speed
+    total_cycles: u64,
+    /// Scanlines drawn since the emulator was created
+    lines_drawn: u64,
+    /// Whether events are recorded
+    events_enabled: bool,
+    /// Events since the last drain
+    events: Vec<EmulatorEvent>,
+    /// Idle T-cycles after which the CPU counts as hung, if watching
+    hang_limit: Option<u64>,
+    /// T-cycles since the CPU last touched I/O or could take an interrupt
+    idle_cycles: u64,
+    /// The watchdog fired and the CPU is still idle
+    hung: bool,
+    /// Whether stack accesses outside RAM are reported
+    stack_check: bool,
+    /// Callbacks run after the CPU executes their opcode
+    debug_hooks: Vec<(u8, DebugHook)>,
+    /// Receives sound register writes, if set
+    apu_write_hook: Option<ApuWriteHook>,
+    /// Sound register writes being logged for a VGM file
+    vgm_log: Option<VgmLog>,
+    /// Addresses run calls stop at before executing
+    breakpoints: Vec<u16>,
+    /// Breakpoint the last run call stopped at
+    breakpoint_hit: Option<u16>,
+    /// Subsystem timing, while profiling
+    profiler: Option<Profiler>,
+    /// Executions per opcode, while counting
+    opcode_histogram: Option<Box<OpcodeHistogram>>,
 }
 
+/// Callback receiving stereo interleaved f32 samples at 44100 Hz
+pub type AudioSink = Box<dyn FnMut(&[f32]) + Send>;
+
+/// Callback run after a watched opcode executes, with the registers it
+/// left behind
+pub type DebugHook = Box<dyn FnMut(&Emulator, &CpuState) + Send>;
+
+/// Callback receiving each write to a sound register or wave RAM
+pub type ApuWriteHook = Box<dyn FnMut(ApuWrite) + Send>;
+
 impl Emulator {
     /// Create a new emulator instance
     pub fn new() -> Self {
@@ -114,10 +505,52 @@ impl Emulator {
             ppu: Ppu::new(),
             apu: Apu::new(),
             timer: Timer::new(),
-            button_state: 0xFF, // All buttons released
+            serial: Serial::new(),
+            model: GbModel::DmgABC,
+            options: EmulatorOptions::default(),
+            button_states: [0xFF; MAX_PLAYERS], // All buttons released
+            queued_inputs: None,
+            audio_sink: None,
+            infrared: None,
+            ir_led: false,
+            serial_device: None,
+            total_cycles: 0,
+            lines_drawn: 0,
+            events_enabled: false,
+            events: Vec::new(),
+            hang_limit: None,
+            idle_cycles: 0,
+            hung: false,
+            stack_check: false,
+            debug_hooks: Vec::new(),
+            apu_write_hook: None,
+            vgm_log: None,
+            breakpoints: Vec::new(),
+            breakpoint_hit: None,
+            profiler: None,
+            opcode_histogram: None,
         }
     }
 
+    /// Create a new emulator instance with the given options
+    pub fn with_options(options: EmulatorOptions) -> Self {
+        let mut emulator = Self::new();
+        emulator.set_options(options);
+        emulator
+    }
+
+    /// Options applied at each reset
+    pub fn options(&self) -> EmulatorOptions {
+        self.options
+    }
+
+    /// Change the options; they take effect at the next reset, except
+    /// for the audio options, which apply straight away
+    pub fn set_options(&mut self, options: EmulatorOptions) {
+        self.options = options;
+        self.apu.set_output_options(options.audio);
+    }
+
     /// Load a ROM into the emulator
     ///
     /// This parses the ROM header and sets up the appropriate memory bank controller.
@@ -127,30 +560,108 @@ impl Emulator {
 
     /// Reset the emulator to initial state
     ///
-    /// This resets all components while keeping the ROM loaded.
+    /// This resets all components while keeping the ROM loaded, using the
+    /// model of the previous reset (DMG-ABC by default).
     pub fn reset(&mut self) {
-        self.cpu.reset();
-        self.ppu.reset();
-        self.apu.reset();
-        self.timer.reset();
-        self.button_state = 0xFF;
+        self.reset_for_model(self.model);
+    }
+
+    /// Power the console off and on again, keeping the ROM and the
+    /// cartridge's battery-backed RAM and clock
+    ///
+    /// Unlike [`reset`](Self::reset), which only restarts the CPU and
+    /// the console's registers, this also puts the memory bank controller
+    /// back at bank 1 with RAM disabled, as a game finds it at power-up.
+    /// Games that erase saves or return to the title on A+B+Start+Select
+    /// go through this state.
+    pub fn soft_reset(&mut self) {
+        self.memory.power_cycle();
+        self.reset();
     }
 
     /// Reset the emulator for a specific hardware model
+    ///
+    /// Registers, I/O and the divider start out as that model's boot ROM
+    /// leaves them, and model-specific quirks (such as the DMG STAT write
+    /// bug) follow the model until the next reset.
     pub fn reset_for_model(&mut self, model: GbModel) {
+        self.model = model;
         self.cpu.reset_for_model(model);
+        self.memory.set_model(model);
+        self.memory.set_accuracy(self.options.accuracy);
+        self.memory.fill_ram(self.options.initial_ram);
+        self.memory.reset_io();
+        self.ppu.set_model(model);
+        self.ppu.set_accuracy(self.options.accuracy);
         self.ppu.reset();
+        self.apu.set_model(model);
         self.apu.reset();
-        self.timer.reset();
-        self.button_state = 0xFF;
+        self.timer.reset_for_model(model);
+        self.memory.io[memory::io::DIV as usize] = self.timer.div();
+        self.serial.reset();
+        self.button_states = [0xFF; MAX_PLAYERS];
+        if let Some(log) = self.vgm_log.as_mut() {
+            log.clock_jumped(self.total_cycles, 0);
+        }
+        self.total_cycles = 0;
+        self.idle_cycles = 0;
+        self.hung = false;
+    }
+
+    /// Hardware model selected by the last reset
+    pub fn model(&self) -> GbModel {
+        self.model
+    }
+
+    /// T-cycles emulated since the last reset
+    ///
+    /// Counted at normal speed (4194304 per second, whatever the CGB speed
+    /// mode), so it measures emulated time. Saved in save states.
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Frames completed (VBlank entries) since the last reset
+    ///
+    /// Frames with the LCD off don't count. Saved in save states.
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
+
+    /// Snapshot of the CPU registers and the mapped ROM bank
+    pub fn cpu_state(&self) -> CpuState {
+        let cpu = &self.cpu;
+        CpuState {
+            a: cpu.a,
+            f: cpu.f,
+            b: cpu.b,
+            c: cpu.c,
+            d: cpu.d,
+            e: cpu.e,
+            h: cpu.h,
+            l: cpu.l,
+            sp: cpu.sp,
+            pc: cpu.pc,
+            ime: cpu.ime,
+            halted: cpu.halted,
+            stopped: cpu.stopped,
+            rom_bank: self.memory.rom_bank() as u16,
+        }
     }
 
     /// Run emulation for one frame (~70224 cycles, ~16.7ms)
     ///
-    /// This runs the emulator until VBlank is reached (one complete frame).
-    pub fn run_frame(&mut self) {
+    /// This runs the emulator until VBlank is reached (one complete frame),
+    /// or until the CPU reaches a [breakpoint](Self::add_breakpoint). With
+    /// the LCD off there is no VBlank; frames end every 70224 cycles
+    /// instead, counted from when the LCD was turned off, with a blank
+    /// white screen and [`FrameReport::lcd_off`] set.
+    pub fn run_frame(&mut self) -> FrameReport {
         const CYCLES_PER_FRAME: u32 = 70224;
         let mut cycles_this_frame = 0u32;
+        self.breakpoint_hit = None;
+        let (lines, samples, events) = (self.lines_drawn, self.apu.buffer.len(), self.events.len());
+        let mut frame_completed = false;
 
         while cycles_this_frame < CYCLES_PER_FRAME {
             let cycles = self.step();
@@ -158,202 +669,1231 @@ impl Emulator {
 
             if self.ppu.frame_ready {
                 self.ppu.frame_ready = false;
+                frame_completed = true;
+                break;
+            }
+            if self.at_breakpoint() {
                 break;
             }
         }
+        let report = FrameReport {
+            cycles: cycles_this_frame,
+            scanlines_rendered: (self.lines_drawn - lines) as u32,
+            audio_samples: self.apu.buffer.len().saturating_sub(samples),
+            events: self.events.len() - events,
+            frame_completed,
+            lcd_off: self.ppu.lcd_off(),
+        };
+        self.flush_audio();
+        report
     }
 
     /// Run emulation for a specific number of cycles
     ///
-    /// Useful for more fine-grained control over emulation timing.
+    /// Useful for more fine-grained control over emulation timing. Stops
+    /// early at a [breakpoint](Self::add_breakpoint).
     pub fn run_cycles(&mut self, target_cycles: u32) {
         let mut cycles = 0u32;
+        self.breakpoint_hit = None;
         while cycles < target_cycles {
             cycles += self.step();
+            if self.at_breakpoint() {
+                break;
+            }
         }
+        self.flush_audio();
     }
 
-    /// Execute a single CPU instruction and update all subsystems
+    /// Step until `done` returns true, for at most `max_cycles` T-cycles
     ///
-    /// Returns the number of T-cycles consumed.
-    pub fn step(&mut self) -> u32 {
-        // Update joypad state
-        self.memory.set_joypad(self.button_state);
-
-        // Handle interrupts
-        let intr_cycles = self.handle_interrupts();
-        if intr_cycles > 0 {
-            self.timer.tick(&mut self.memory, intr_cycles);
-            self.ppu.tick(&mut self.memory, intr_cycles);
-            self.apu.tick(&mut self.memory, intr_cycles);
-            for _ in 0..intr_cycles {
-                self.memory.tick_dma();
+    /// `done` is checked before every instruction, so a condition that
+    /// already holds runs nothing. Also stops at a
+    /// [breakpoint](Self::add_breakpoint). Returns the cycles run.
+    ///
+    /// ```no_run
+    /// # let mut emulator = gb3000::Emulator::new();
+    /// emulator.run_until(|emu| emu.cpu_state().pc == 0x0150, 10_000_000);
+    /// emulator.run_until(|emu| emu.peek(0xC000) != 0, 10_000_000);
+    /// let start = emulator.frame_count();
+    /// emulator.run_until(|emu| emu.frame_count() >= start + 30, 10_000_000);
+    /// emulator.run_until(|emu| emu.serial_output().ends_with(b"Passed"), 1_000_000_000);
+    /// ```
+    pub fn run_until(&mut self, mut done: impl FnMut(&Emulator) -> bool, max_cycles: u64) -> u64 {
+        let mut cycles = 0u64;
+        self.breakpoint_hit = None;
+        while cycles < max_cycles && !done(self) {
+            cycles += self.step() as u64;
+            if self.at_breakpoint() {
+                break;
+            }
+        }
+        self.flush_audio();
+        cycles
+    }
+
+    /// Run until LY changes, at most one scanline (456 cycles)
+    ///
+    /// Starting mid-line runs to the start of the next one, so repeated
+    /// calls stay aligned to scanlines; with the LCD off a full line's
+    /// worth of cycles runs instead.
+    pub fn run_scanline(&mut self) -> StepInfo {
+        const CYCLES_PER_LINE: u32 = 456;
+        let frame = self.ppu.frame_count();
+        let ly = self.memory.io[memory::io::LY as usize];
+        let mut cycles = 0u32;
+        while cycles < CYCLES_PER_LINE {
+            cycles += self.step();
+            if self.memory.io[memory::io::LY as usize] != ly {
+                break;
             }
         }
+        self.flush_audio();
+        StepInfo {
+            cycles,
+            pc: self.cpu.pc,
+            frame_completed: self.ppu.frame_count() != frame,
+        }
+    }
+
+    /// Execute a single CPU instruction, like [`step`](Self::step), and
+    /// report what happened
+    ///
+    /// Pending interrupts are dispatched as part of the instruction.
+    pub fn step_instruction(&mut self) -> StepInfo {
+        let frame = self.ppu.frame_count();
+        let cycles = self.step();
+        StepInfo {
+            cycles,
+            pc: self.cpu.pc,
+            frame_completed: self.ppu.frame_count() != frame,
+        }
+    }
 
-        // Execute CPU instruction
-        let cycles = self.cpu.step(&mut self.memory);
+    /// Execute one instruction, running any call it makes to completion
+    ///
+    /// CALL and RST run until execution comes back to the next
+    /// instruction, a breakpoint is reached, or a second of emulated time
+    /// has passed, whichever is first; anything else is a plain
+    /// [`step_instruction`](Self::step_instruction).
+    pub fn step_over(&mut self) -> StepInfo {
+        const STEP_OVER_LIMIT: u32 = 4_194_304;
+        let (pc, sp) = (self.cpu.pc, self.cpu.sp);
+        let opcode = self.memory.peek(pc);
+        let is_rst = opcode & 0xC7 == 0xC7;
+        let is_call = matches!(opcode, 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC);
+        if self.cpu.halted || !(is_call || is_rst) {
+            return self.step_instruction();
+        }
 
-        // Check for PPU register writes that need immediate processing
-        if self.memory.stat_written {
-            self.memory.stat_written = false;
-            self.ppu.on_stat_write(&mut self.memory);
+        let next = pc.wrapping_add(if is_rst { 1 } else { 3 });
+        let frame = self.ppu.frame_count();
+        let mut cycles = 0;
+        self.breakpoint_hit = None;
+        loop {
+            cycles += self.step();
+            // A conditional call not taken lands here straight away
+            let returned = self.cpu.pc == next && self.cpu.sp >= sp;
+            if returned || cycles >= STEP_OVER_LIMIT || self.at_breakpoint() {
+                break;
+            }
         }
-        if self.memory.lyc_written {
-            self.memory.lyc_written = false;
-            self.ppu.on_lyc_write(&mut self.memory);
+        self.flush_audio();
+        StepInfo {
+            cycles,
+            pc: self.cpu.pc,
+            frame_completed: self.ppu.frame_count() != frame,
+        }
+    }
+
+    /// Stop [`run_frame`](Self::run_frame), [`run_cycles`](Self::run_cycles)
+    /// and [`step_over`](Self::step_over) when the CPU is about to execute
+    /// the instruction at `addr`
+    ///
+    /// The address is matched against PC alone, whichever bank is mapped.
+    /// A run call always executes at least one instruction, so calling it
+    /// again continues past the breakpoint it stopped at.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
         }
+    }
+
+    /// Remove the breakpoint at `addr`, if any
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|&a| a != addr);
+    }
+
+    /// Remove every breakpoint
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Addresses with a breakpoint, in the order they were added
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
 
-        // Update subsystems
-        self.timer.tick(&mut self.memory, cycles);
-        self.ppu.tick(&mut self.memory, cycles);
-        self.apu.tick(&mut self.memory, cycles);
+    /// The breakpoint the last run call stopped at, if it stopped early
+    pub fn breakpoint_hit(&self) -> Option<u16> {
+        self.breakpoint_hit
+    }
 
-        for _ in 0..cycles {
-            self.memory.tick_dma();
+    /// Whether the CPU is about to execute an instruction with a
+    /// breakpoint, noting it as hit
+    fn at_breakpoint(&mut self) -> bool {
+        let pc = self.cpu.pc;
+        if self.breakpoints.is_empty() || self.cpu.halted || !self.breakpoints.contains(&pc) {
+            return false;
         }
+        self.breakpoint_hit = Some(pc);
+        true
+    }
 
-        cycles + intr_cycles
+    /// Decode the instruction at `addr` in the current memory map
+    pub fn disassemble(&self, addr: u16) -> Instruction {
+        disasm::disassemble(|a| self.memory.peek(a), addr)
     }
 
-    /// Handle pending interrupts
-    fn handle_interrupts(&mut self) -> u32 {
-        if self.memory.pending_interrupts() != 0 {
-            self.cpu.halted = false;
+    /// Execute a single CPU instruction and update all subsystems
+    ///
+    /// Returns the number of T-cycles consumed, counted at normal speed:
+    /// in CGB double speed mode the CPU, timer and OAM DMA run twice as
+    /// fast while the PPU, APU and clock keep their rate, so an instruction
+    /// takes half as long.
+    pub fn step(&mut self) -> u32 {
+        // Update joypad state
+        for (player, &state) in self.button_states.iter().enumerate() {
+            self.memory.set_joypad(player, state);
+        }
+        // A selected button press ends STOP mode
+        if self.cpu.stopped && self.memory.joypad_lines() != 0x0F {
+            self.cpu.stopped = false;
+        }
+        if self.cpu.stopped {
+            // The system clock is stopped; only the cartridge clock, which
+            // has its own crystal, keeps time
+            self.memory.tick_rtc(4);
+            self.total_cycles += 4;
+            return 4;
         }
+        let mut sample = self.profiler.as_mut().and_then(Profiler::begin);
+        self.sync_infrared();
+        let frame = self.ppu.frame_count();
+        let mode = self.ppu.mode();
 
-        if !self.cpu.ime {
-            return 0;
+        // Wake up or dispatch an interrupt, then execute the instruction,
+        // updating the other subsystems after every M-cycle so memory
+        // accesses see them mid-instruction
+        let Self {
+            cpu, memory, ppu, apu, timer, serial, serial_device, debug_hooks, opcode_histogram, total_cycles,
+            stack_check, ..
+        } = self;
+        let mut dots = 0;
+        let mut tick = |memory: &mut Memory, cycles| {
+            profile::lap(&mut sample, Part::Cpu);
+            if !memory.ppu_write_log.is_empty() {
+                ppu.record_writes(memory, *total_cycles + dots as u64);
+            }
+            // PPU register writes need immediate processing
+            if memory.stat_written {
+                memory.stat_written = false;
+                ppu.on_stat_write(memory);
+            }
+            if memory.lyc_written {
+                memory.lyc_written = false;
+                ppu.on_lyc_write(memory);
+            }
+            let cycle_dots = if memory.double_speed() { cycles / 2 } else { cycles };
+            profile::lap(&mut sample, Part::Ppu);
+            timer.tick(memory, cycles);
+            profile::lap(&mut sample, Part::Timer);
+            serial.tick(memory, cycles, serial_device.as_mut().map(|d| &mut **d as _));
+            profile::lap(&mut sample, Part::Other);
+            ppu.tick(memory, cycle_dots);
+            profile::lap(&mut sample, Part::Ppu);
+            apu.tick(memory, cycle_dots);
+            profile::lap(&mut sample, Part::Apu);
+            for _ in 0..cycles {
+                memory.tick_dma();
+            }
+            profile::lap(&mut sample, Part::Other);
+            dots += cycle_dots;
+            if let Some(log) = memory.interrupt_log.as_mut() {
+                log.watch(memory.io[memory::io::IF as usize], *total_cycles + dots as u64);
+            }
+        };
+        let (pc, sp, ime) = (cpu.pc, cpu.sp, cpu.ime);
+        let service_cycles = cpu.service_interrupts(memory, &mut tick);
+        let interrupted = service_cycles > 0;
+        let dispatch_sp = cpu.sp;
+        if let Some(log) = memory.interrupt_log.as_mut().filter(|_| ime && interrupted) {
+            // The dispatch takes the last 20 cycles, after any HALT wake-up
+            log.serviced(cpu.pc, pc, *total_cycles + service_cycles as u64 - 20);
         }
+        let opcode = (!debug_hooks.is_empty() && !cpu.halted).then(|| memory.peek(cpu.pc));
+        if let Some(histogram) = opcode_histogram.as_mut().filter(|_| !cpu.halted) {
+            histogram.count(memory.peek(cpu.pc), memory.peek(cpu.pc.wrapping_add(1)));
+        }
+        let stack_op = (*stack_check && !cpu.halted).then(|| (cpu.pc, cpu.sp, memory.peek(cpu.pc)));
+        cpu.step_mcycle(memory, &mut tick);
+        profile::lap(&mut sample, Part::Cpu);
+        self.memory.tick_rtc(dots);
 
-        let pending = self.memory.pending_interrupts();
-        if pending == 0 {
-            return 0;
+        if self.cpu.stopped {
+            // The LCD driver stops with the clock, leaving a blank screen
+            self.ppu.clear_screen();
+            self.ppu.frame_ready = true;
         }
 
-        self.cpu.ime = false;
+        if self.model.is_sgb() && self.ppu.frame_count() != frame {
+            self.memory.sgb_mut().on_frame(&self.ppu.framebuffer);
+        }
+        self.record_events(mode, frame);
+        if self.ppu.frame_count() != frame {
+            self.latch_inputs();
+        }
+        self.watch_for_hang(dots, interrupted);
+        if self.stack_check {
+            if ime && interrupted {
+                // The dispatch pushed PC like a CALL
+                self.check_stack_access(pc, 0xCD, sp, dispatch_sp);
+            }
+            if let Some((pc, sp, opcode)) = stack_op {
+                self.check_stack_access(pc, opcode, sp, self.cpu.sp);
+            }
+        }
+        if let Some(opcode) = opcode {
+            self.run_debug_hooks(opcode);
+        }
 
-        let pc = self.cpu.pc;
-        self.cpu.sp = self.cpu.sp.wrapping_sub(1);
-        self.memory.data[self.cpu.sp as usize] = (pc >> 8) as u8;
-        self.cpu.sp = self.cpu.sp.wrapping_sub(1);
-        self.memory.data[self.cpu.sp as usize] = pc as u8;
-
-        if pending & interrupts::VBLANK != 0 {
-            self.memory.clear_interrupt(interrupts::VBLANK);
-            self.cpu.pc = 0x0040;
-        } else if pending & interrupts::LCD_STAT != 0 {
-            self.memory.clear_interrupt(interrupts::LCD_STAT);
-            self.cpu.pc = 0x0048;
-        } else if pending & interrupts::TIMER != 0 {
-            self.memory.clear_interrupt(interrupts::TIMER);
-            self.cpu.pc = 0x0050;
-        } else if pending & interrupts::SERIAL != 0 {
-            self.memory.clear_interrupt(interrupts::SERIAL);
-            self.cpu.pc = 0x0058;
-        } else if pending & interrupts::JOYPAD != 0 {
-            self.memory.clear_interrupt(interrupts::JOYPAD);
-            self.cpu.pc = 0x0060;
-        }
-
-        20
+        self.total_cycles += dots as u64;
+        if !self.memory.apu_write_log.is_empty() {
+            self.pass_on_apu_writes();
+        }
+        if let (Some(sample), Some(profiler)) = (sample, self.profiler.as_mut()) {
+            profiler.finish(sample);
+        }
+        dots
     }
 
-    /// Set the state of a button
+    /// Count the lines drawn and queue the events of the step that
+    /// started in `mode` on `frame`
     ///
-    /// # Arguments
-    /// * `button` - The button to set
-    /// * `pressed` - true if pressed, false if released
-    pub fn set_button(&mut self, button: Button, pressed: bool) {
-        let bit = match button {
-            Button::Right => 0x01,
-            Button::Left => 0x02,
-            Button::Up => 0x04,
-            Button::Down => 0x08,
-            Button::A => 0x10,
-            Button::B => 0x20,
-            Button::Select => 0x40,
-            Button::Start => 0x80,
+    /// A step is shorter than any PPU mode apart from OAM scan, so it
+    /// crosses at most one HBlank or VBlank start.
+    fn record_events(&mut self, mode: ppu::Mode, frame: u64) {
+        let serial_started = std::mem::take(&mut self.memory.serial_started);
+        let line_drawn = mode == ppu::Mode::Drawing && self.ppu.mode() == ppu::Mode::HBlank;
+        self.lines_drawn += line_drawn as u64;
+        if !self.events_enabled {
+            return;
+        }
+        let events = &mut self.events;
+        if line_drawn {
+            let line = self.memory.io[memory::io::LY as usize];
+            events.push(EmulatorEvent::HBlank { line });
+        }
+        if self.ppu.frame_count() != frame {
+            events.push(EmulatorEvent::VBlank);
+        }
+        if serial_started {
+            events.push(EmulatorEvent::SerialByte(self.memory.io[memory::io::SB as usize]));
+        }
+    }
+
+    /// Count `cycles` towards the hang watchdog, unless the step
+    /// dispatched an interrupt, touched I/O or left one able to fire
+    fn watch_for_hang(&mut self, cycles: u32, interrupted: bool) {
+        let Some(limit) = self.hang_limit else {
+            return;
         };
+        let io_accessed = self.memory.take_io_accessed();
+        let can_interrupt = self.memory.ie & 0x1F != 0 && (self.cpu.ime || self.cpu.halted);
+        if interrupted || io_accessed || can_interrupt {
+            self.idle_cycles = 0;
+            self.hung = false;
+            return;
+        }
+        self.idle_cycles += cycles as u64;
+        if self.idle_cycles >= limit && !self.hung {
+            self.hung = true;
+            log_debug!("gb3000::cpu", "hang detected at {:04X}", self.cpu.pc);
+            if self.events_enabled {
+                self.events.push(EmulatorEvent::Hang { pc: self.cpu.pc });
+            }
+        }
+    }
 
-        if pressed {
-            self.button_state &= !bit; // Active LOW
-        } else {
-            self.button_state |= bit;
+    /// Watch for the CPU hanging (off by default)
+    ///
+    /// With `Some(cycles)`, spinning that many T-cycles with no interrupt
+    /// able to fire and no I/O register read or written counts as a hang:
+    /// [`hang_detected`](Self::hang_detected) turns true and a
+    /// [`EmulatorEvent::Hang`] is recorded. A game waiting for input or
+    /// VBlank touches I/O or has interrupts on, so it never trips this.
+    pub fn set_hang_detection(&mut self, cycles: Option<u64>) {
+        self.hang_limit = cycles;
+        self.idle_cycles = 0;
+        self.hung = false;
+    }
+
+    /// Whether the CPU is hung, as watched for by
+    /// [`set_hang_detection`](Self::set_hang_detection)
+    pub fn hang_detected(&self) -> bool {
+        self.hung
+    }
+
+    /// Report stack accesses among the I/O registers and IE (off by
+    /// default)
+    ///
+    /// A push that lands on 0xFF00-0xFF7F or 0xFFFF, or a pop that reads
+    /// there, means the stack ran out of high RAM or was popped past its
+    /// top, a common homebrew bug. Each one records an
+    /// [`EmulatorEvent::StackWarning`].
+    pub fn set_stack_check(&mut self, enabled: bool) {
+        self.stack_check = enabled;
+    }
+
+    /// Warn if the instruction `opcode` at `pc`, which moved SP from
+    /// `before` to `after`, pushed or popped a word outside RAM
+    fn check_stack_access(&mut self, pc: u16, opcode: u8, before: u16, after: u16) {
+        let pushed = after == before.wrapping_sub(2);
+        let popped = after == before.wrapping_add(2);
+        let addr = match opcode {
+            // PUSH, CALL and RST
+            0xC5 | 0xD5 | 0xE5 | 0xF5 | 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC if pushed => after,
+            op if op & 0xC7 == 0xC7 && pushed => after,
+            // POP, RET and RETI
+            0xC1 | 0xD1 | 0xE1 | 0xF1 | 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9 if popped => before,
+            _ => return,
+        };
+        let outside_ram = |a: u16| matches!(a, 0xFF00..=0xFF7F | 0xFFFF);
+        if !outside_ram(addr) && !outside_ram(addr.wrapping_add(1)) {
+            return;
+        }
+        log_debug!("gb3000::cpu", "stack access at {:04X} by the instruction at {:04X}", addr, pc);
+        if self.events_enabled {
+            self.events.push(EmulatorEvent::StackWarning { pc, addr });
         }
     }
 
-    /// Get the current framebuffer
+    /// The top `depth` words of the stack, from SP up, stopping at the end
+    /// of the address space
     ///
-    /// Returns a 160x144 array of 2-bit color indices (0-3).
-    /// Use a palette to convert to actual colors.
-    pub fn framebuffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
-        &self.ppu.framebuffer
+    /// Words are read as [`peek`](Self::peek) reads. One counts as a
+    /// likely return address when, in the current memory map, a CALL or
+    /// RST ends just before where it points; RST $38 doesn't count, as
+    /// that is what blank memory decodes to. Interrupt dispatches push
+    /// whatever PC was, so their return addresses aren't recognized.
+    pub fn stack_slice(&self, depth: usize) -> Vec<StackEntry> {
+        let sp = self.cpu.sp as usize;
+        (sp..0xFFFF)
+            .step_by(2)
+            .take(depth)
+            .map(|addr| {
+                let addr = addr as u16;
+                let value = u16::from_le_bytes([self.peek(addr), self.peek(addr + 1)]);
+                let call = value >= 3 && matches!(self.peek(value - 3), 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC);
+                let rst = value >= 1 && matches!(self.peek(value - 1), op if op & 0xC7 == 0xC7 && op != 0xFF);
+                StackEntry { addr, value, return_address: call || rst }
+            })
+            .collect()
     }
 
-    /// Take pending audio samples from the APU
+    /// Record [`EmulatorEvent`]s from now on (off by default)
     ///
-    /// Returns stereo interleaved f32 samples at 44100 Hz.
-    /// The buffer is cleared after calling this.
-    pub fn audio_samples(&mut self) -> Vec<f32> {
-        self.apu.take_samples()
+    /// Disabling drops any events not yet drained.
+    pub fn set_events_enabled(&mut self, enabled: bool) {
+        self.events_enabled = enabled;
+        if !enabled {
+            self.events.clear();
+        }
     }
 
-    /// Get the audio sample rate
-    pub fn audio_sample_rate(&self) -> u32 {
-        apu::SAMPLE_RATE
+    /// Time each subsystem from now on (off by default)
+    ///
+    /// Enabling starts a fresh [`Profile`]; disabling drops it.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler = enabled.then(Profiler::default);
     }
 
-    /// Check if a new frame is ready
-    pub fn frame_ready(&self) -> bool {
-        self.ppu.frame_ready
+    /// Time spent per subsystem since profiling was enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profiler.as_ref().map(Profiler::profile)
     }
 
-    /// Check if the cartridge has battery-backed RAM (saveable)
-    pub fn has_battery(&self) -> bool {
-        self.memory.has_battery()
+    /// Count executions of each opcode from now on (off by default)
+    ///
+    /// Enabling starts a fresh [`OpcodeHistogram`]; disabling drops it.
+    /// Instructions are counted as they start, so one an interrupt
+    /// dispatch preempts isn't.
+    pub fn set_opcode_counting(&mut self, enabled: bool) {
+        self.opcode_histogram = enabled.then(Box::default);
     }
 
-    /// Get the external RAM (save data) for battery-backed cartridges
+    /// Executions per opcode since counting was enabled
+    pub fn opcode_histogram(&self) -> Option<&OpcodeHistogram> {
+        self.opcode_histogram.as_deref()
+    }
+
+    /// Log interrupt requests and dispatches from now on (off by default)
     ///
-    /// Returns None if the cartridge has no RAM or no battery.
-    pub fn save_ram(&self) -> Option<Vec<u8>> {
-        if self.has_battery() {
-            Some(self.memory.get_eram().to_vec())
-        } else {
-            None
+    /// Enabling starts an empty log; disabling drops it. See
+    /// [`interrupt_log`](crate::interrupt_log) for what is recorded.
+    pub fn set_interrupt_logging(&mut self, enabled: bool) {
+        let flags = self.memory.io[memory::io::IF as usize];
+        self.memory.interrupt_log = enabled.then(|| Box::new(interrupt_log::InterruptLog::new(flags)));
+    }
+
+    /// The interrupt log, oldest first; empty while logging is off
+    pub fn interrupt_log(&self) -> impl DoubleEndedIterator<Item = &InterruptEvent> + '_ {
+        self.memory.interrupt_log.iter().flat_map(|log| log.events())
+    }
+
+    /// Empty the interrupt log, keeping logging on
+    pub fn clear_interrupt_log(&mut self) {
+        if let Some(log) = self.memory.interrupt_log.as_mut() {
+            log.clear();
         }
     }
 
-    /// Load external RAM (save data) into the cartridge
+    /// Record where in the frame each LCD register write lands (off by
+    /// default)
     ///
-    /// Use this to restore a saved game.
-    pub fn load_ram(&mut self, data: &[u8]) {
-        self.memory.set_eram(data);
+    /// Enabling starts an empty timeline; disabling drops it.
+    pub fn set_ppu_write_timeline(&mut self, enabled: bool) {
+        self.memory.log_ppu_writes = enabled;
+        self.memory.ppu_write_log.clear();
+        self.ppu.set_write_timeline(enabled);
     }
 
-    /// Parse ROM information from ROM data
-    pub fn parse_rom_info(rom: &[u8]) -> Option<RomInfo> {
-        if rom.len() < 0x150 {
-            return None;
-        }
+    /// Writes to LCDC through WX during the last complete frame, oldest
+    /// first, each with the line and dot it landed on; a frame ends when
+    /// VBlank begins. Empty while the timeline is off.
+    pub fn ppu_write_timeline(&self) -> &[PpuWrite] {
+        self.ppu.write_timeline()
+    }
 
-        let title: String = rom[0x0134..0x0144]
-            .iter()
-            .take_while(|&&b| b != 0)
-            .map(|&b| {
-                if b.is_ascii_graphic() || b == b' ' {
-                    b as char
-                } else {
-                    '?'
-                }
-            })
-            .collect();
+    /// Take the events recorded since the last call, oldest first
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, EmulatorEvent> {
+        self.events.drain(..)
+    }
 
-        let cart_type_code = rom[0x0147];
-        let cart_type = match cart_type_code {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
+    /// Take the bytes the game sent over the link port since the last call
+    ///
+    /// Test ROMs such as Blargg's print their results this way, so a
+    /// headless runner can look for "Passed" or "Failed" in the text.
+    /// Until [`set_serial_device`](Self::set_serial_device) attaches a
+    /// device, each transfer completes on its own and receives 0xFF. Up
+    /// to 64 KB is kept between calls.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.take_output()
+    }
+
+    /// The bytes [`take_serial_output`](Self::take_serial_output) would
+    /// return, left in place
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    /// Call `hook` after every execution of `opcode`
+    ///
+    /// A "magic breakpoint" for the conventions test ROMs and homebrew use
+    /// to talk to emulators: Mooneye's tests execute LD B,B (0x40) when
+    /// done, with the result in the registers, and no$gmb-style debug
+    /// messages follow an LD D,D (0x52). The hook gets the emulator, to
+    /// read memory, and the registers after the instruction. Replaces any
+    /// hook already set for `opcode`.
+    pub fn set_debug_opcode_hook(
+        &mut self,
+        opcode: u8,
+        hook: impl FnMut(&Emulator, &CpuState) + Send + 'static,
+    ) {
+        self.clear_debug_opcode_hook(opcode);
+        self.debug_hooks.push((opcode, Box::new(hook)));
+    }
+
+    /// Remove the hook for `opcode`, if any
+    pub fn clear_debug_opcode_hook(&mut self, opcode: u8) {
+        self.debug_hooks.retain(|(op, _)| *op != opcode);
+    }
+
+    /// Call the hooks watching `opcode`
+    fn run_debug_hooks(&mut self, opcode: u8) {
+        let mut hooks = std::mem::take(&mut self.debug_hooks);
+        let state = self.cpu_state();
+        for (_, hook) in hooks.iter_mut().filter(|(op, _)| *op == opcode) {
+            hook(self, &state);
+        }
+        self.debug_hooks = hooks;
+    }
+
+    /// Call `hook` with every write to a sound register (0xFF10-0xFF26)
+    /// or wave RAM, in order, timed to the end of the instruction making
+    /// it
+    ///
+    /// Writes are reported as the game makes them, including ones the
+    /// APU ignores, so a player of the log sees what the game did.
+    /// Replaces any hook already set.
+    pub fn set_apu_write_hook(&mut self, hook: impl FnMut(ApuWrite) + Send + 'static) {
+        self.apu_write_hook = Some(Box::new(hook));
+        self.memory.log_apu_writes = true;
+    }
+
+    /// Remove the sound register write hook
+    pub fn clear_apu_write_hook(&mut self) {
+        self.apu_write_hook = None;
+        self.memory.log_apu_writes = self.vgm_log.is_some();
+    }
+
+    /// Start logging sound register writes for a VGM file, replacing any
+    /// log in progress
+    pub fn start_vgm_log(&mut self) {
+        self.vgm_log = Some(VgmLog::new(self.total_cycles, &self.memory));
+        self.memory.log_apu_writes = true;
+    }
+
+    /// Stop logging and return the VGM file, if a log was running
+    pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+        let log = self.vgm_log.take()?;
+        self.memory.log_apu_writes = self.apu_write_hook.is_some();
+        Some(log.to_vgm(self.total_cycles))
+    }
+
+    /// Hand the sound register writes of the last step to the hook and
+    /// the VGM log
+    fn pass_on_apu_writes(&mut self) {
+        for (address, value) in self.memory.apu_write_log.drain(..) {
+            let write = ApuWrite { cycle: self.total_cycles, address, value };
+            if let Some(log) = self.vgm_log.as_mut() {
+                log.record(write);
+            }
+            if let Some(hook) = self.apu_write_hook.as_mut() {
+                hook(write);
+            }
+        }
+    }
+
+    /// Exchange LED and sensor state with the infrared device
+    fn sync_infrared(&mut self) {
+        let Some(device) = self.infrared.as_mut() else { return };
+        let led = self.memory.ir_led();
+        if led != self.ir_led {
+            self.ir_led = led;
+            device.set_led(led);
+        }
+        self.memory.set_ir_light(device.light());
+    }
+
+    /// Whether the CPU runs in CGB double speed mode
+    pub fn double_speed(&self) -> bool {
+        self.memory.double_speed()
+    }
+
+    /// Set the state of a button
+    ///
+    /// # Arguments
+    /// * `button` - The button to set
+    /// * `pressed` - true if pressed, false if released
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.set_button_for_player(0, button, pressed);
+    }
+
+    /// Set the state of a button on one of the SGB multiplayer controllers
+    ///
+    /// Player 0 is the controller [`Emulator::set_button`] drives. Games
+    /// see players 1-3 only on an SGB model after enabling them with
+    /// MLT_REQ. Players past [`sgb::MAX_PLAYERS`] are ignored.
+    pub fn set_button_for_player(&mut self, player: usize, button: Button, pressed: bool) {
+        let Some(state) = self.input_target().get_mut(player) else { return };
+        let bit = button.mask();
+
+        if pressed {
+            *state &= !bit; // Active LOW
+        } else {
+            *state |= bit;
+        }
+    }
+
+    /// Set the state of all buttons at once
+    pub fn set_input(&mut self, input: InputFrame) {
+        self.input_target()[0] = !input.0; // Active LOW
+    }
+
+    /// Set every controller's buttons at once, from player 0 up
+    ///
+    /// Players past the end of `inputs` have nothing pressed, so each call
+    /// is a complete snapshot and no earlier presses linger.
+    pub fn set_inputs(&mut self, inputs: &[InputFrame]) {
+        for (player, state) in self.input_target().iter_mut().enumerate() {
+            *state = !inputs.get(player).map_or(0, |input| input.0);
+        }
+    }
+
+    /// Get the currently held buttons
+    ///
+    /// In queued mode these are the buttons latched for the current frame,
+    /// not any set since.
+    pub fn input(&self) -> InputFrame {
+        InputFrame(!self.button_states[0])
+    }
+
+    /// Latch input at frame boundaries only (off by default)
+    ///
+    /// When queued, [`set_button`](Self::set_button) and the other input
+    /// setters take effect when the next frame starts: at VBlank, or every
+    /// 70224 cycles while the LCD is off. The game then sees the same input
+    /// for the whole frame however a frontend interleaves its input
+    /// polling with [`run_cycles`](Self::run_cycles) or
+    /// [`step_instruction`](Self::step_instruction). Turning queued mode
+    /// off applies any waiting input at once.
+    pub fn set_input_queued(&mut self, queued: bool) {
+        self.latch_inputs();
+        self.queued_inputs = queued.then_some(self.button_states);
+    }
+
+    /// Whether input is latched at frame boundaries
+    pub fn input_queued(&self) -> bool {
+        self.queued_inputs.is_some()
+    }
+
+    /// Button states the input setters change
+    fn input_target(&mut self) -> &mut [u8; MAX_PLAYERS] {
+        match &mut self.queued_inputs {
+            Some(queued) => queued,
+            None => &mut self.button_states,
+        }
+    }
+
+    /// Hand queued input to the game
+    fn latch_inputs(&mut self) {
+        if let Some(queued) = self.queued_inputs {
+            self.button_states = queued;
+        }
+    }
+
+    /// Run one frame holding `buttons` (an [`InputFrame`] bitmask)
+    ///
+    /// The input is latched before the frame starts, so the result doesn't
+    /// depend on when a frontend polls its controls.
+    pub fn run_frame_with_input(&mut self, buttons: u8) {
+        self.set_input(InputFrame(buttons));
+        self.latch_inputs();
+        self.run_frame();
+    }
+
+    /// Run one frame per entry in `inputs`, holding that frame's buttons
+    ///
+    /// Emulation is fully deterministic: the same state and inputs always
+    /// produce the same result. Combine with [`Emulator::set_audio_enabled`]
+    /// to skip audio work when running headless.
+    pub fn run_frames_with_input(&mut self, inputs: &[InputFrame]) {
+        for &input in inputs {
+            self.run_frame_with_input(input.0);
+        }
+    }
+
+    /// Hash identifying the loaded ROM (stored in save states and movies)
+    pub fn rom_hash(&self) -> u32 {
+        self.memory.rom_hash()
+    }
+
+    /// Read a byte from memory without side effects
+    ///
+    /// ROM and cartridge RAM are read through the current banks (RAM even
+    /// while disabled), and I/O registers return their stored value rather
+    /// than what a CPU read would see: no read masks, no joypad mixing.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.memory.peek(addr)
+    }
+
+    /// Read a byte from a specific ROM bank (0x0000-0x7FFF) or external
+    /// RAM bank (0xA000-0xBFFF), whatever is currently mapped
+    ///
+    /// Other addresses aren't banked and read as [`Emulator::peek`].
+    pub fn peek_banked(&self, bank: usize, addr: u16) -> u8 {
+        self.memory.peek_banked(bank, addr)
+    }
+
+    /// Write a byte to a specific external RAM bank (0xA000-0xBFFF),
+    /// whatever is currently mapped, like [`Emulator::poke`]
+    ///
+    /// Other addresses aren't banked and write as [`Emulator::poke`].
+    pub fn poke_banked(&mut self, bank: usize, addr: u16, value: u8) {
+        self.memory.poke_banked(bank, addr, value);
+    }
+
+    /// Number of 16 KB ROM banks [`Emulator::peek_banked`] can read
+    pub fn rom_bank_count(&self) -> usize {
+        self.memory.rom_bank_count()
+    }
+
+    /// Number of 8 KB external RAM banks, 0 without cartridge RAM
+    pub fn ram_bank_count(&self) -> usize {
+        self.memory.ram_bank_count()
+    }
+
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub fn current_rom_bank(&self) -> usize {
+        self.memory.rom_bank()
+    }
+
+    /// External RAM bank mapped at 0xA000-0xBFFF
+    pub fn current_ram_bank(&self) -> usize {
+        self.memory.ram_bank()
+    }
+
+    /// Write a byte to memory without side effects
+    ///
+    /// Unlike a CPU write, this doesn't trigger bank switches, DMA, or timer
+    /// resets; ROM addresses are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.memory.poke(addr, value);
+    }
+
+    /// Enable or disable audio sample generation (enabled by default)
+    ///
+    /// When disabled the APU still runs, so emulation behaves identically,
+    /// but no samples are produced. Useful for headless or fast-forward use.
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        self.apu.set_output_enabled(enabled);
+    }
+
+    /// Enable or disable drawing into the framebuffer (enabled by default)
+    ///
+    /// When disabled the PPU keeps its timing, interrupts and STAT
+    /// behavior, but no pixels are drawn and the framebuffer keeps its
+    /// last picture. Useful for headless runs and skipped fast-forward
+    /// frames; enable it again a frame before the picture is needed.
+    pub fn set_video_enabled(&mut self, enabled: bool) {
+        self.ppu.set_video_enabled(enabled);
+    }
+
+    /// Whether the framebuffer is being drawn
+    pub fn video_enabled(&self) -> bool {
+        self.ppu.video_enabled()
+    }
+
+    /// Resample audio output by `ratio` for dynamic rate control
+    ///
+    /// 1.0 produces exactly 44100 samples per emulated second; frontends
+    /// that pace video off their audio buffer nudge this by a fraction of
+    /// a percent. Clamped to ±5%.
+    pub fn set_audio_rate_adjust(&mut self, ratio: f64) {
+        self.apu.set_rate_adjust(ratio);
+    }
+
+    /// Stream audio to a callback instead of polling for samples
+    ///
+    /// The sink is called with all pending samples at the end of every
+    /// [`run_frame`](Self::run_frame) and [`run_cycles`](Self::run_cycles),
+    /// reusing the APU's buffer so no allocation happens per call. While a
+    /// sink is set, [`audio_samples`](Self::audio_samples) returns nothing.
+    pub fn set_audio_sink(&mut self, sink: impl FnMut(&[f32]) + Send + 'static) {
+        self.audio_sink = Some(Box::new(sink));
+    }
+
+    /// Remove the audio sink, going back to polling
+    pub fn clear_audio_sink(&mut self) {
+        self.audio_sink = None;
+    }
+
+    /// Put a device in front of the CGB infrared port
+    ///
+    /// Use [`infrared::Loopback`] to let a game see its own LED, or one end
+    /// of an [`infrared::InfraredLink`] to connect two emulators. Without a
+    /// device the port sees no light.
+    pub fn set_infrared_device(&mut self, device: impl InfraredDevice + 'static) {
+        self.infrared = Some(Box::new(device));
+        self.ir_led = false;
+    }
+
+    /// Remove the infrared device; the sensor goes dark
+    pub fn clear_infrared_device(&mut self) {
+        self.infrared = None;
+        self.memory.set_ir_light(false);
+    }
+
+    /// Plug a device into the link port
+    ///
+    /// Use one end of a [`serial::LinkCable`] to connect two emulators.
+    /// Without a device every transfer receives 0xFF.
+    pub fn set_serial_device(&mut self, device: impl SerialDevice + 'static) {
+        self.serial_device = Some(Box::new(device));
+    }
+
+    /// Unplug the link port device
+    pub fn clear_serial_device(&mut self) {
+        self.serial_device = None;
+    }
+
+    /// Hand pending samples to the audio sink now
+    ///
+    /// Only needed when driving the emulator with [`step`](Self::step).
+    pub fn flush_audio(&mut self) {
+        if let Some(sink) = self.audio_sink.as_mut() {
+            if !self.apu.buffer.is_empty() {
+                sink(&self.apu.buffer);
+                self.apu.clear_buffer();
+            }
+        }
+    }
+
+    /// Get the current framebuffer
+    ///
+    /// Returns a 160x144 array of 2-bit color indices (0-3).
+    /// Use a palette to convert to actual colors.
+    pub fn framebuffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.ppu.framebuffer
+    }
+
+    /// Stable 64-bit hash of the framebuffer, see [`frame::hash`]
+    ///
+    /// Equal frames hash the same on every platform and version, so the
+    /// hash can be stored for golden-image tests or compared between
+    /// netplay peers.
+    pub fn frame_hash(&self) -> u64 {
+        frame::hash(&self.ppu.framebuffer)
+    }
+
+    /// Convert the framebuffer to 32-bit colors using a palette
+    ///
+    /// Writes `SCREEN_WIDTH * SCREEN_HEIGHT` pixels in the palette's
+    /// 0xAARRGGBB format (see [`palettes`]). `out` must be at least that long.
+    pub fn framebuffer_argb(&self, palette: &[u32; 4], out: &mut [u32]) {
+        for (dst, &idx) in out.iter_mut().zip(self.ppu.framebuffer.iter()) {
+            *dst = palette[(idx & 0x03) as usize];
+        }
+    }
+
+    /// Draw every tile in VRAM, for tile viewers
+    ///
+    /// Writes `TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT` raw color indices
+    /// (0-3, no palette applied); tile `n` is at column `n % 16`, row
+    /// `n / 16`.
+    pub fn tile_sheet(&self, out: &mut [u8]) {
+        ppu::draw_tile_sheet(&self.memory, out);
+    }
+
+    /// Draw a whole 256x256 tile map the way the background would show it
+    ///
+    /// Writes `TILE_MAP_SIZE * TILE_MAP_SIZE` shades through BGP, using
+    /// the tile data LCDC currently selects. Which map the background and
+    /// window use is up to LCDC too; see [`TileMap::background`].
+    pub fn tile_map(&self, map: TileMap, out: &mut [u8]) {
+        ppu::draw_tile_map(&self.memory, map, out);
+    }
+
+    /// The 40 sprites in OAM, in OAM order
+    pub fn oam_entries(&self) -> [OamEntry; 40] {
+        ppu::oam_entries(&self.memory)
+    }
+
+    /// Super Game Boy picture: the border with the colorized screen inside
+    ///
+    /// `SGB_WIDTH * SGB_HEIGHT` pixels in 0xAARRGGBB format, updated every
+    /// frame. Returns None unless an SGB model is selected (see
+    /// [`Emulator::reset_for_model`]).
+    pub fn sgb_framebuffer(&self) -> Option<&[u32]> {
+        self.model.is_sgb().then(|| self.memory.sgb().output())
+    }
+
+    /// Convert the framebuffer to 8-bit grayscale
+    ///
+    /// Writes one byte per pixel (255 = white, 0 = black), the layout most
+    /// machine learning tools expect. `out` must be at least
+    /// `SCREEN_WIDTH * SCREEN_HEIGHT` bytes long.
+    pub fn framebuffer_gray8(&self, out: &mut [u8]) {
+        const LEVELS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+        for (dst, &idx) in out.iter_mut().zip(self.ppu.framebuffer.iter()) {
+            *dst = LEVELS[(idx & 0x03) as usize];
+        }
+    }
+
+    /// Capture the current frame as 8-bit RGBA
+    ///
+    /// Returns `SCREEN_WIDTH * SCREEN_HEIGHT * 4` bytes (R, G, B, A per
+    /// pixel, row-major) with `palette` applied, ready for image encoders.
+    pub fn screenshot_rgba(&self, palette: &[u32; 4]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        for &idx in self.ppu.framebuffer.iter() {
+            let argb = palette[(idx & 0x03) as usize];
+            out.extend_from_slice(&[(argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8]);
+        }
+        out
+    }
+
+    /// Take pending audio samples from the APU
+    ///
+    /// Returns stereo interleaved f32 samples at 44100 Hz.
+    /// The buffer is cleared after calling this.
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// Move pending audio samples to the end of `out`
+    ///
+    /// Like [`audio_samples`](Self::audio_samples) without allocating:
+    /// once `out` and the emulator's own buffer have grown to a frame's
+    /// worth, calling this every frame reuses both.
+    pub fn audio_samples_into(&mut self, out: &mut Vec<f32>) {
+        self.apu.append_samples(out);
+    }
+
+    /// Copy pending audio samples into a caller-provided buffer
+    ///
+    /// Writes at most `out.len()` stereo interleaved samples and returns how
+    /// many were written. Samples that don't fit stay queued for the next call.
+    pub fn read_audio_samples(&mut self, out: &mut [f32]) -> usize {
+        self.apu.drain_samples(out)
+    }
+
+    /// Latest amplitude, frequency and envelope volume of each sound channel
+    ///
+    /// Intended for oscilloscope and piano-roll style visualizations.
+    pub fn channel_outputs(&self) -> [ChannelOutput; 4] {
+        self.apu.channel_outputs()
+    }
+
+    /// Copy recent output levels of sound channel `channel` (0-3) into `out`
+    ///
+    /// Samples are oldest first, at the audio sample rate; see
+    /// [`apu::CHANNEL_HISTORY_LEN`] for how many are kept. Any other
+    /// channel leaves `out` untouched.
+    pub fn channel_history(&self, channel: usize, out: &mut [f32]) {
+        self.apu.channel_history(channel, out);
+    }
+
+    /// Leave sound channel `channel` (0-3) out of the audio output, or
+    /// put it back (solo a channel by muting the others)
+    ///
+    /// Muted channels keep running and still show up in
+    /// [`channel_outputs`](Self::channel_outputs). A frontend setting: it
+    /// survives resets and isn't part of save states.
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.apu.set_channel_muted(channel, muted);
+    }
+
+    /// Whether sound channel `channel` (0-3) is muted
+    pub fn channel_muted(&self, channel: usize) -> bool {
+        self.apu.channel_muted(channel)
+    }
+
+    /// Get the audio sample rate
+    pub fn audio_sample_rate(&self) -> u32 {
+        apu::SAMPLE_RATE
+    }
+
+    /// Check if a new frame is ready
+    pub fn frame_ready(&self) -> bool {
+        self.ppu.frame_ready
+    }
+
+    /// Check if the cartridge has battery-backed RAM (saveable)
+    pub fn has_battery(&self) -> bool {
+        self.memory.has_battery()
+    }
+
+    /// Get the external RAM (save data) for battery-backed cartridges
+    ///
+    /// Returns None if the cartridge has no RAM or no battery.
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        self.save_ram_bytes().map(<[u8]>::to_vec)
+    }
+
+    /// Borrow the external RAM (save data) for battery-backed cartridges
+    ///
+    /// Like [`save_ram`](Self::save_ram) without the copy, for writing the
+    /// save out directly.
+    pub fn save_ram_bytes(&self) -> Option<&[u8]> {
+        if self.has_battery() {
+            Some(self.memory.get_eram())
+        } else {
+            None
+        }
+    }
+
+    /// Whether battery-backed RAM (or the cartridge clock) changed since
+    /// the last [`Emulator::mark_saved`] or [`Emulator::load_ram`]
+    ///
+    /// Lets frontends write saves only when the game actually saved.
+    /// Changes made through [`Emulator::save_ram_mut`] are not tracked.
+    pub fn save_ram_dirty(&self) -> bool {
+        self.has_battery() && self.memory.eram_dirty()
+    }
+
+    /// Clear the [`Emulator::save_ram_dirty`] flag once the save has been
+    /// written out
+    pub fn mark_saved(&mut self) {
+        self.memory.clear_eram_dirty();
+    }
+
+    /// Load external RAM (save data) into the cartridge
+    ///
+    /// Use this to restore a saved game.
+    pub fn load_ram(&mut self, data: &[u8]) {
+        self.memory.set_eram(data);
+    }
+
+    /// Whether the cartridge has an MBC3 real-time clock
+    pub fn has_rtc(&self) -> bool {
+        self.memory.has_rtc()
+    }
+
+    /// Whether the cartridge is an MBC5 rumble cart
+    pub fn has_rumble(&self) -> bool {
+        self.memory.has_rumble()
+    }
+
+    /// Whether the game has the rumble motor switched on
+    ///
+    /// Games pulse the motor to vary its strength, so frontends usually
+    /// poll this once per frame and forward it to force feedback.
+    pub fn rumble_active(&self) -> bool {
+        self.memory.rumble_active()
+    }
+
+    /// Battery save in the `.sav` layout other emulators use
+    ///
+    /// This is the external RAM, followed for clock cartridges by the
+    /// 48-byte BGB/VBA-M RTC footer stamped with `unix_time`. Returns None
+    /// if the cartridge has no battery.
+    pub fn save_file(&self, unix_time: u64) -> Option<Vec<u8>> {
+        self.export_save(SaveFormat::default(), unix_time)
+    }
+
+    /// Battery save in a specific emulator's layout
+    ///
+    /// See [`savefile`] for the variants. Returns None if the cartridge has
+    /// no battery.
+    pub fn export_save(&self, format: SaveFormat, unix_time: u64) -> Option<Vec<u8>> {
+        let ram = self.save_ram_bytes()?;
+        let mut data = if self.memory.is_mbc2() {
+            savefile::encode_mbc2(ram, format.mbc2_layout)
+        } else {
+            ram.to_vec()
+        };
+        if self.has_rtc() {
+            data.extend_from_slice(&self.memory.rtc().footer(format.rtc_footer, unix_time));
+        }
+        Some(data)
+    }
+
+    /// Load a battery save written by [`Emulator::save_file`] or another
+    /// emulator
+    ///
+    /// Every layout in [`savefile`] is accepted. An RTC footer, if present,
+    /// sets the clock and advances it by the time elapsed until `unix_time`.
+    pub fn load_save_file(&mut self, data: &[u8], unix_time: u64) {
+        if self.memory.is_mbc2() {
+            self.load_ram(&savefile::decode_mbc2(data));
+            return;
+        }
+        let ram_size = self.memory.get_eram().len();
+        let (ram, footer) = data.split_at(ram_size.min(data.len()));
+        self.load_ram(ram);
+        if self.has_rtc() {
+            self.memory.rtc_mut().load_footer(footer, unix_time);
+        }
+    }
+
+    /// Get mutable access to battery-backed external RAM
+    ///
+    /// Returns None if the cartridge has no RAM or no battery. Frontends
+    /// that own the save buffer (e.g. libretro) can read and write it in place.
+    pub fn save_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if self.has_battery() {
+            Some(self.memory.eram_mut())
+        } else {
+            None
+        }
+    }
+
+    /// Serialize the complete emulator state
+    ///
+    /// The ROM is not included; load the same ROM before calling
+    /// [`Emulator::load_state`].
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.save_state_into(&mut data);
+        data
+    }
+
+    /// Like [`Emulator::save_state`], but reuses `out`'s allocation
+    ///
+    /// Meant for frequent snapshots such as rewind.
+    pub fn save_state_into(&self, out: &mut Vec<u8>) {
+        let mut w = StateWriter::with_buffer(std::mem::take(out));
+        self.memory.save_state(&mut w);
+        self.cpu.save_state(&mut w);
+        self.ppu.save_state(&mut w);
+        self.apu.save_state(&mut w);
+        self.timer.save_state(&mut w);
+        w.bytes(&self.button_states);
+        w.u8(self.model as u8);
+        w.u64(self.total_cycles);
+        self.serial.save_state(&mut w);
+        *out = w.finish();
+    }
+
+    /// Restore emulator state produced by [`Emulator::save_state`]
+    ///
+    /// Header and ROM mismatches are detected before anything is modified.
+    /// If the data is otherwise corrupt, the emulator is left partially
+    /// restored and should be reset.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data)?;
+        self.memory.load_state(&mut r)?;
+        self.cpu.load_state(&mut r)?;
+        self.ppu.load_state(&mut r)?;
+        self.apu.load_state(&mut r)?;
+        self.timer.load_state(&mut r)?;
+        r.bytes_into(&mut self.button_states)?;
+        let model = *GbModel::ALL
+            .get(r.u8()? as usize)
+            .ok_or(StateError::Invalid("hardware model"))?;
+        self.model = model;
+        self.memory.set_model(model);
+        self.ppu.set_model(model);
+        self.apu.set_model(model);
+        self.apu.clear_buffer();
+        let total_cycles = r.u64()?;
+        if let Some(log) = self.vgm_log.as_mut() {
+            log.clock_jumped(self.total_cycles, total_cycles);
+        }
+        self.total_cycles = total_cycles;
+        self.serial.load_state(&mut r)?;
+        self.idle_cycles = 0;
+        self.hung = false;
+        // The sound jumps to the loaded state
+        if let Some(log) = self.vgm_log.as_mut() {
+            log.snapshot(&self.memory);
+        }
+        Ok(())
+    }
+
+    /// Parse ROM information from ROM data
+    pub fn parse_rom_info(rom: &[u8]) -> Option<RomInfo> {
+        if rom.len() < 0x150 {
+            return None;
+        }
+
+        let title: String = rom[0x0134..0x0144]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+
+        let cart_type_code = rom[0x0147];
+        let cart_type = match cart_type_code {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
             0x02 => "MBC1+RAM",
             0x03 => "MBC1+RAM+BATTERY",
             0x05 => "MBC2",
@@ -373,95 +1913,1185 @@ impl Emulator {
             0x1E => "MBC5+RUMBLE+RAM+BATTERY",
             _ => "Unknown",
         }
-        .to_string();
+        .to_string();
+
+        let rom_size_code = rom[0x0148];
+        let rom_size = match rom_size_code {
+            0x00 => "32 KB",
+            0x01 => "64 KB",
+            0x02 => "128 KB",
+            0x03 => "256 KB",
+            0x04 => "512 KB",
+            0x05 => "1 MB",
+            0x06 => "2 MB",
+            0x07 => "4 MB",
+            0x08 => "8 MB",
+            _ => "Unknown",
+        }
+        .to_string();
+
+        let ram_size_code = rom[0x0149];
+        let ram_size = match ram_size_code {
+            0x00 => "None",
+            0x01 => "2 KB",
+            0x02 => "8 KB",
+            0x03 => "32 KB",
+            0x04 => "128 KB",
+            0x05 => "64 KB",
+            _ => "Unknown",
+        }
+        .to_string();
+
+        let computed_header_checksum = rom[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16));
+
+        Some(RomInfo {
+            title: title.trim().to_string(),
+            cart_type,
+            rom_size,
+            ram_size,
+            cart_type_code,
+            rom_size_code,
+            ram_size_code,
+            cgb_flag: rom[0x0143],
+            sgb_flag: rom[0x0146],
+            licensee_code: rom[0x014B],
+            new_licensee_code: [rom[0x0144], rom[0x0145]],
+            destination_code: rom[0x014A],
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            computed_header_checksum,
+            global_checksum: u16::from_be_bytes([rom[0x014E], rom[0x014F]]),
+            computed_global_checksum,
+            logo_valid: rom[0x0104..0x0134] == NINTENDO_LOGO,
+            file_size: rom.len(),
+        })
+    }
+
+    /// Check a ROM's header for signs of a corrupt or truncated dump
+    pub fn verify_rom(rom: &[u8]) -> RomValidation {
+        match Self::parse_rom_info(rom) {
+            Some(info) => RomValidation {
+                has_header: true,
+                logo_ok: info.logo_valid,
+                header_checksum_ok: info.header_checksum == info.computed_header_checksum,
+                global_checksum_ok: info.global_checksum == info.computed_global_checksum,
+                size: info.size_check(),
+            },
+            None => RomValidation {
+                has_header: false,
+                logo_ok: false,
+                header_checksum_ok: false,
+                global_checksum_ok: false,
+                size: RomSizeCheck::UnknownSize,
+            },
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A deep copy that runs on independently of the original, for look-ahead
+/// search, speculative rollback or rewind checkpoints
+///
+/// The ROM is shared rather than copied. What is attached from outside
+/// stays with the original: the clone has no audio sink, infrared or
+/// serial device or debug hooks, and profiling off.
+impl Clone for Emulator {
+    fn clone(&self) -> Self {
+        Self {
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            ppu: self.ppu.clone(),
+            apu: self.apu.clone(),
+            timer: self.timer.clone(),
+            serial: self.serial.clone(),
+            model: self.model,
+            options: self.options,
+            button_states: self.button_states,
+            queued_inputs: self.queued_inputs,
+            audio_sink: None,
+            infrared: None,
+            ir_led: self.ir_led,
+            serial_device: None,
+            total_cycles: self.total_cycles,
+            lines_drawn: self.lines_drawn,
+            events_enabled: self.events_enabled,
+            events: self.events.clone(),
+            hang_limit: self.hang_limit,
+            idle_cycles: self.idle_cycles,
+            hung: self.hung,
+            stack_check: self.stack_check,
+            debug_hooks: Vec::new(),
+            apu_write_hook: None,
+            vgm_log: self.vgm_log.clone(),
+            breakpoints: self.breakpoints.clone(),
+            breakpoint_hit: self.breakpoint_hit,
+            profiler: None,
+            opcode_histogram: self.opcode_histogram.clone(),
+        }
+    }
+}
+
+/// Standard Game Boy palettes
+pub mod palettes {
+    /// Grayscale palette (White, Light Gray, Dark Gray, Black)
+    pub const GRAYSCALE: [u32; 4] = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+
+    /// Classic DMG green palette
+    pub const DMG_GREEN: [u32; 4] = [0xFF9BBC0F, 0xFF8BAC0F, 0xFF306230, 0xFF0F380F];
+
+    /// Game Boy Pocket palette (slightly different green)
+    pub const POCKET: [u32; 4] = [0xFFC4CFA1, 0xFF8B956D, 0xFF4D533C, 0xFF1F1F1F];
+
+    /// Game Boy Light palette
+    pub const LIGHT: [u32; 4] = [0xFF00B581, 0xFF009A71, 0xFF006839, 0xFF004F2B];
+
+    /// SGB Border palette style
+    pub const SGB: [u32; 4] = [0xFFF7E7C6, 0xFFD68E49, 0xFFA63725, 0xFF331820];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::io;
+
+    #[test]
+    fn emulator_creation() {
+        let emu = Emulator::new();
+        assert_eq!(emu.framebuffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    }
+
+    #[test]
+    fn button_state() {
+        let mut emu = Emulator::new();
+
+        // All buttons released
+        assert_eq!(emu.button_states[0], 0xFF);
+
+        // Press A
+        emu.set_button(Button::A, true);
+        assert_eq!(emu.button_states[0] & 0x10, 0x00);
+
+        // Release A
+        emu.set_button(Button::A, false);
+        assert_eq!(emu.button_states[0] & 0x10, 0x10);
+    }
+
+    #[test]
+    fn queued_input_latches_at_vblank() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset();
+        emu.set_button_for_player(1, Button::B, true);
+        emu.set_inputs(&[InputFrame::NONE.with(Button::A)]);
+        assert_eq!(emu.button_states, [0xEF, 0xFF, 0xFF, 0xFF], "a full snapshot");
+
+        emu.set_input_queued(true);
+        emu.run_frame();
+        emu.set_input(InputFrame::NONE.with(Button::Start));
+        emu.run_cycles(10_000);
+        emu.set_button(Button::Select, true);
+        assert_eq!(emu.input(), InputFrame::NONE.with(Button::A), "held until VBlank");
+        emu.run_frame();
+        assert_eq!(emu.input(), InputFrame::from_buttons(&[Button::Start, Button::Select]));
+
+        emu.set_input(InputFrame::NONE);
+        emu.set_input_queued(false);
+        assert_eq!(emu.input(), InputFrame::NONE, "applied when leaving queued mode");
+    }
+
+    #[test]
+    fn emulator_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Emulator>();
+
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        let emu = std::thread::spawn(move || {
+            emu.run_frame();
+            emu
+        })
+        .join()
+        .unwrap();
+        assert!(emu.total_cycles() > 0);
+    }
+
+    #[test]
+    fn clones_run_independently() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD HL, $C000; INC (HL); JR -3
+        rom[0x0100..0x0106].copy_from_slice(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD]);
+        rom[0x0147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x0149] = 0x02;
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.run_frames_with_input(&[InputFrame::NONE; 3]);
+
+        let mut fork = emu.clone();
+        assert_eq!(fork.save_state(), emu.save_state());
+        fork.poke_banked(0, 0xA000, 0x42);
+        fork.run_frame_with_input(InputFrame::NONE.with(Button::A).0);
+        assert_eq!(emu.peek_banked(0, 0xA000), 0x00);
+        assert_eq!(emu.input(), InputFrame::NONE);
+        assert_ne!(fork.frame_count(), emu.frame_count());
+
+        // Fed the same input, a clone stays in step with the original
+        let mut twin = emu.clone();
+        emu.run_frames_with_input(&[InputFrame::NONE; 5]);
+        twin.run_frames_with_input(&[InputFrame::NONE; 5]);
+        assert_eq!(twin.save_state(), emu.save_state());
+    }
+
+    #[test]
+    fn input_frames_are_deterministic() {
+        let rom = vec![0u8; 0x8000];
+        let inputs = [
+            InputFrame::NONE,
+            InputFrame::from_buttons(&[Button::A, Button::Start]),
+            InputFrame::NONE.with(Button::Left),
+        ];
+        assert!(inputs[1].is_pressed(Button::Start));
+
+        let mut a = Emulator::new();
+        let mut b = Emulator::new();
+        for emu in [&mut a, &mut b] {
+            emu.load_rom(&rom);
+            emu.reset();
+            emu.set_audio_enabled(false);
+            emu.run_frames_with_input(&inputs);
+        }
+        assert_eq!(a.input(), inputs[2]);
+        assert_eq!(a.save_state(), b.save_state());
+        assert!(a.audio_samples().is_empty());
+    }
+
+    #[test]
+    fn skipped_video_catches_up_in_one_frame() {
+        let mut rom = vec![0u8; 0x8000];
+        // Set BGP, fill tile 0 with vertical stripes, then scroll forever
+        rom[0x0100..0x0115].copy_from_slice(&[
+            0x3E, 0xE4, 0xE0, 0x47, // LD A, $E4; LDH (BGP), A
+            0x21, 0x00, 0x80, 0x3E, 0x0F, 0x06, 0x10, // LD HL, $8000; LD A, $0F; LD B, 16
+            0x22, 0x05, 0x20, 0xFC, // LD (HL+), A; DEC B; JR NZ, -4
+            0x3C, 0xE0, 0x43, 0x18, 0xFB, 0x00, // INC A; LDH (SCX), A; JR -5
+        ]);
+
+        let mut drawn = Emulator::new();
+        let mut skipped = Emulator::new();
+        for emu in [&mut drawn, &mut skipped] {
+            emu.load_rom(&rom);
+            emu.reset();
+            emu.set_audio_enabled(false);
+        }
+        skipped.set_video_enabled(false);
+        for _ in 0..9 {
+            drawn.run_frame();
+            skipped.run_frame();
+        }
+        assert!(skipped.framebuffer().iter().all(|&c| c == 0));
+        assert_eq!(drawn.total_cycles(), skipped.total_cycles());
+
+        skipped.set_video_enabled(true);
+        drawn.run_frame();
+        skipped.run_frame();
+        assert!(drawn.framebuffer().iter().any(|&c| c != 0));
+        assert_eq!(drawn.framebuffer(), skipped.framebuffer());
+        assert_eq!(drawn.save_state(), skipped.save_state());
+    }
+
+    #[test]
+    fn breakpoints_stop_runs_and_step_over_skips_calls() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0100..0x0108].copy_from_slice(&[
+            0xCD, 0x00, 0x02, // CALL $0200
+            0x3C, // INC A
+            0xC3, 0x03, 0x01, // JP $0103
+            0x00,
+        ]);
+        rom[0x0200..0x0204].copy_from_slice(&[0x04, 0x04, 0x04, 0xC9]); // INC B x3; RET
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.set_audio_enabled(false);
+        assert_eq!(emu.disassemble(0x0100), Instruction { len: 3, text: "CALL $0200".to_string() });
+
+        let b = emu.cpu_state().b;
+        let info = emu.step_over();
+        assert_eq!(info.pc, 0x0103);
+        assert_eq!(info.cycles, 24 + 3 * 4 + 16);
+        assert_eq!(emu.cpu_state().b, b.wrapping_add(3));
+        // Anything but a call is a single step
+        assert_eq!(emu.step_over().pc, 0x0104);
+
+        emu.add_breakpoint(0x0103);
+        emu.add_breakpoint(0x0103);
+        assert_eq!(emu.breakpoints(), [0x0103]);
+        emu.run_frame();
+        assert_eq!(emu.breakpoint_hit(), Some(0x0103));
+        assert_eq!(emu.cpu_state().pc, 0x0103);
+        // Running again goes around the loop once more
+        let cycles = emu.total_cycles();
+        emu.run_cycles(70224);
+        assert_eq!(emu.breakpoint_hit(), Some(0x0103));
+        assert_eq!(emu.total_cycles() - cycles, 4 + 16);
+
+        emu.remove_breakpoint(0x0103);
+        let frames = emu.frame_count();
+        emu.run_frame();
+        assert_eq!(emu.breakpoint_hit(), None);
+        assert_eq!(emu.frame_count(), frames + 1);
+    }
+
+    #[test]
+    fn audio_sink_receives_samples() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received);
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.set_audio_sink(move |samples| {
+            counter.fetch_add(samples.len(), Ordering::Relaxed);
+        });
+        emu.run_frame();
+
+        let count = received.load(Ordering::Relaxed);
+        assert!(count > 0 && count.is_multiple_of(2), "{}", count);
+        assert!(emu.audio_samples().is_empty());
+
+        emu.clear_audio_sink();
+        emu.run_frame();
+        assert!(!emu.audio_samples().is_empty());
+        assert_eq!(received.load(Ordering::Relaxed), count);
+    }
+
+    #[test]
+    fn peek_and_poke() {
+        let mut emu = Emulator::new();
+        emu.poke(0xC123, 0x42);
+        assert_eq!(emu.peek(0xC123), 0x42);
+        assert_eq!(emu.peek(0xE123), 0x42); // Echo RAM
+
+        // Poking DIV stores the value instead of resetting the timer
+        emu.poke(0xFF04, 0x99);
+        assert_eq!(emu.peek(0xFF04), 0x99);
+
+        let mut gray = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        emu.framebuffer_gray8(&mut gray);
+        assert!(gray.iter().all(|&p| p == 0xFF));
+    }
+
+    #[test]
+    fn peek_banked_memory() {
+        // MBC5 with 4 ROM banks and 4 RAM banks, each ROM bank tagged
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x1B;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x03;
+        for bank in 0..4 {
+            rom[bank * 0x4000 + 0x2000] = bank as u8 + 0x10;
+        }
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.memory.write_byte(0x2000, 2);
+        emu.memory.write_byte(0x4000, 1);
+        emu.poke(0xA010, 0x5A);
+
+        assert_eq!((emu.current_rom_bank(), emu.current_ram_bank()), (2, 1));
+        assert_eq!(emu.peek(0x6000), 0x12);
+        assert_eq!(emu.peek_banked(3, 0x6000), 0x13);
+        assert_eq!(emu.peek_banked(3, 0x2000), 0x13);
+        assert_eq!(emu.peek_banked(9, 0x4000), 0xFF);
+        // RAM reads work while the game has it disabled
+        assert_eq!(emu.peek(0xA010), 0x5A);
+        assert_eq!(emu.peek_banked(1, 0xA010), 0x5A);
+        assert_eq!(emu.peek_banked(0, 0xA010), 0x00);
+
+        // Peeking JOYP shows the selection, not the pressed buttons
+        emu.set_button(Button::A, true);
+        emu.step();
+        emu.memory.write_byte(io::JOYP, 0x10);
+        assert_eq!(emu.memory.read_byte(io::JOYP), 0xDE);
+        assert_eq!(emu.peek(io::JOYP), 0xDF);
+    }
+
+    #[test]
+    fn scanline_and_instruction_stepping() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD A, 1 then NOPs
+        rom[0x0100..0x0102].copy_from_slice(&[0x3E, 0x01]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+
+        let info = emu.step_instruction();
+        assert_eq!(info, StepInfo { cycles: 8, pc: 0x0102, frame_completed: false });
+
+        // Align to a line start, then each call advances LY by one
+        emu.run_scanline();
+        let ly = emu.peek(io::LY);
+        let info = emu.run_scanline();
+        assert_eq!(emu.peek(io::LY), (ly + 1) % 154);
+        assert!((453..=460).contains(&info.cycles));
+
+        // A frame's worth of scanlines completes exactly one frame
+        let frames = (0..154).filter(|_| emu.run_scanline().frame_completed).count();
+        assert_eq!(frames, 1);
+    }
+
+    #[test]
+    fn events_report_lines_frames_and_serial() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD A, $42; LDH (SB), A; LD A, $81; LDH (SC), A
+        rom[0x0100..0x0108].copy_from_slice(&[0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.set_events_enabled(true);
+        emu.run_frame();
+        assert!(emu.drain_events().any(|e| e == EmulatorEvent::SerialByte(0x42)));
+
+        // A whole frame: one HBlank per visible line, then VBlank
+        emu.run_frame();
+        let events: Vec<_> = emu.drain_events().collect();
+        let lines: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                EmulatorEvent::HBlank { line } => Some(*line),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lines, (0..144).collect::<Vec<u8>>());
+        assert_eq!(events.last(), Some(&EmulatorEvent::VBlank));
+
+        emu.set_events_enabled(false);
+        emu.run_frame();
+        assert_eq!(emu.drain_events().count(), 0);
+    }
+
+    #[test]
+    fn run_until_stops_on_predicate_or_cycle_limit() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD HL, $C000; INC (HL); JR -3
+        rom[0x0100..0x0106].copy_from_slice(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFD]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        let cycles = emu.run_until(|emu| emu.peek(0xC000) == 5, 1_000_000);
+        assert_eq!(emu.peek(0xC000), 5);
+        assert_eq!(cycles, 12 + 5 * 12 + 4 * 12);
+        assert_eq!(emu.run_until(|emu| emu.peek(0xC000) == 5, 1_000_000), 0);
+
+        let cycles = emu.run_until(|_| false, 1000);
+        assert!((1000..1012).contains(&cycles));
+    }
+
+    #[test]
+    fn apu_write_hook_and_vgm_log() {
+        let mut rom = vec![0u8; 0x8000];
+        // INC A; LDH (NR50), A; LDH ($30), A; JR -6
+        rom[0x0100..0x0107].copy_from_slice(&[0x3C, 0xE0, 0x24, 0xE0, 0x30, 0x18, 0xF9]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        let writes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = std::sync::Arc::clone(&writes);
+        emu.set_apu_write_hook(move |write| log.lock().unwrap().push(write));
+        emu.run_until(|emu| emu.total_cycles() >= 400, 1000);
+
+        let writes = std::mem::take(&mut *writes.lock().unwrap());
+        assert_eq!(writes.len(), 20);
+        for pair in writes.chunks(2) {
+            assert_eq!([pair[0].address, pair[1].address], [0xFF24, 0xFF30]);
+            assert_eq!(pair[0].value, pair[1].value);
+            assert_eq!(pair[1].cycle - pair[0].cycle, 12);
+        }
+        assert_eq!(writes[2].cycle - writes[0].cycle, 40);
+        assert_eq!(writes[2].value, writes[0].value.wrapping_add(1));
 
-        let rom_size_code = rom[0x0148];
-        let rom_size = match rom_size_code {
-            0x00 => "32 KB",
-            0x01 => "64 KB",
-            0x02 => "128 KB",
-            0x03 => "256 KB",
-            0x04 => "512 KB",
-            0x05 => "1 MB",
-            0x06 => "2 MB",
-            0x07 => "4 MB",
-            0x08 => "8 MB",
-            _ => "Unknown",
+        // A second of writes every 40 cycles, each NR50 and wave RAM write
+        // logged as a command
+        emu.clear_apu_write_hook();
+        emu.start_vgm_log();
+        emu.run_cycles(4_194_304);
+        let vgm = emu.stop_vgm_log().unwrap();
+        assert_eq!(&vgm[..4], b"Vgm ");
+        assert_eq!(u32::from_le_bytes(vgm[0x18..0x1C].try_into().unwrap()), 44_100);
+        assert!(vgm.len() > 0x100 + 3 * 2 * 4_194_304 / 40);
+        assert!(emu.stop_vgm_log().is_none());
+        assert!(!emu.memory.log_apu_writes);
+    }
+
+    #[test]
+    fn steady_state_frames_reuse_buffers() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset();
+        let mut audio = Vec::with_capacity(4096);
+        for _ in 0..2 {
+            emu.run_frame();
+            emu.audio_samples_into(&mut audio);
         }
-        .to_string();
+        let (ours, theirs) = (audio.as_ptr(), emu.apu.buffer.as_ptr());
+        for _ in 0..10 {
+            audio.clear();
+            emu.run_frame();
+            emu.audio_samples_into(&mut audio);
+            assert!(!audio.is_empty());
+        }
+        assert_eq!(audio.as_ptr(), ours);
+        assert_eq!(emu.apu.buffer.as_ptr(), theirs);
+    }
 
-        let ram_size_code = rom[0x0149];
-        let ram_size = match ram_size_code {
-            0x00 => "None",
-            0x01 => "2 KB",
-            0x02 => "8 KB",
-            0x03 => "32 KB",
-            0x04 => "128 KB",
-            0x05 => "64 KB",
-            _ => "Unknown",
+    #[test]
+    fn frame_report() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset();
+        emu.set_events_enabled(true);
+        emu.run_frame();
+        let report = emu.run_frame();
+        assert!(report.frame_completed);
+        assert_eq!(report.scanlines_rendered, 144);
+        assert!((70224 - 24..=70224 + 24).contains(&report.cycles));
+        assert_eq!(report.events, 145, "an HBlank per line and a VBlank");
+        assert!(report.audio_samples > 0);
+
+        assert!(!report.lcd_off);
+
+        // With the LCD off, white frames complete on the same cadence
+        emu.poke(io::LCDC, 0x11);
+        for _ in 0..3 {
+            let report = emu.run_frame();
+            assert!(report.frame_completed && report.lcd_off);
+            assert_eq!(report.scanlines_rendered, 0);
+            assert!((70224 - 24..=70224 + 24).contains(&report.cycles));
+            assert!(emu.framebuffer().iter().all(|&c| c == 0));
         }
-        .to_string();
+        emu.poke(io::LCDC, 0x91);
+        assert!(!emu.run_frame().lcd_off);
+    }
 
-        Some(RomInfo {
-            title: title.trim().to_string(),
-            cart_type,
-            rom_size,
-            ram_size,
-            cart_type_code,
-            rom_size_code,
-            ram_size_code,
-        })
+    #[test]
+    fn timer_reads_in_a_loop_see_every_increment() {
+        // LD A, $05; LDH (TAC), A; LD HL, $C000
+        // loop: LDH A, (reg); LD (HL+), A; JR loop (32 cycles a turn)
+        let poll = |reg: u8| {
+            let mut rom = vec![0u8; 0x8000];
+            rom[0x0100..0x010C].copy_from_slice(&[
+                0x3E, 0x05, 0xE0, 0x07, 0x21, 0x00, 0xC0, 0xF0, reg, 0x22, 0x18, 0xFB,
+            ]);
+            let mut emu = Emulator::new();
+            emu.load_rom(&rom);
+            emu.reset();
+            emu.run_cycles(32 * 100);
+            (0xC000..0xC000 + 96).map(|addr| emu.peek(addr)).collect::<Vec<u8>>()
+        };
+
+        // DIV counts every 256 cycles: runs of 8 equal reads, stepping by 1
+        let div = poll(0x04);
+        let changes: Vec<usize> = (1..div.len()).filter(|&i| div[i] != div[i - 1]).collect();
+        assert!(changes.len() >= 10);
+        for pair in changes.windows(2) {
+            assert_eq!(pair[1] - pair[0], 8);
+            assert_eq!(div[pair[1]], div[pair[0]].wrapping_add(1));
+        }
+
+        // TIMA at 16 cycles per count goes up by exactly 2 a turn
+        let tima = poll(0x05);
+        for pair in tima.windows(2) {
+            assert_eq!(pair[1], pair[0] + 2);
+        }
     }
-}
 
-impl Default for Emulator {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn hang_detection() {
+        let mut rom = vec![0u8; 0x8000];
+        // DI; JR -2
+        rom[0x0100..0x0103].copy_from_slice(&[0xF3, 0x18, 0xFE]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.set_events_enabled(true);
+        emu.run_frame();
+        assert!(!emu.hang_detected(), "off by default");
+
+        emu.set_hang_detection(Some(70224));
+        emu.run_frame();
+        emu.run_frame();
+        assert!(emu.hang_detected());
+        let hangs: Vec<_> =
+            emu.drain_events().filter(|e| matches!(e, EmulatorEvent::Hang { .. })).collect();
+        assert_eq!(hangs, [EmulatorEvent::Hang { pc: 0x0101 }]);
+
+        // LD A, 1; LDH (IE), A; EI; HALT; JR -3, with RETI at the VBlank vector
+        rom[0x0040] = 0xD9;
+        rom[0x0100..0x0108].copy_from_slice(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x76, 0x18, 0xFD]);
+        emu.load_rom(&rom);
+        emu.reset();
+        for _ in 0..4 {
+            emu.run_frame();
+        }
+        assert!(!emu.hang_detected(), "waiting for VBlank is not a hang");
     }
-}
 
-/// Standard Game Boy palettes
-pub mod palettes {
-    /// Grayscale palette (White, Light Gray, Dark Gray, Black)
-    pub const GRAYSCALE: [u32; 4] = [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000];
+    #[test]
+    fn debug_opcode_hook_sees_registers() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD B, 3; LD B, B; LD D, D; LD B, B
+        rom[0x0100..0x0105].copy_from_slice(&[0x06, 0x03, 0x40, 0x52, 0x40]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        let (tx, rx) = std::sync::mpsc::channel();
+        emu.set_debug_opcode_hook(0x40, move |emu, cpu| {
+            tx.send((cpu.pc, cpu.b, emu.peek(cpu.pc))).unwrap();
+        });
+        for _ in 0..4 {
+            emu.step();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [(0x0103, 3, 0x52), (0x0105, 3, 0x00)]);
 
-    /// Classic DMG green palette
-    pub const DMG_GREEN: [u32; 4] = [0xFF9BBC0F, 0xFF8BAC0F, 0xFF306230, 0xFF0F380F];
+        emu.clear_debug_opcode_hook(0x40);
+        emu.reset();
+        for _ in 0..4 {
+            emu.step();
+        }
+        assert!(rx.try_recv().is_err());
+    }
 
-    /// Game Boy Pocket palette (slightly different green)
-    pub const POCKET: [u32; 4] = [0xFFC4CFA1, 0xFF8B956D, 0xFF4D533C, 0xFF1F1F1F];
+    #[test]
+    fn profiling_samples_steps() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset();
+        assert!(emu.profile().is_none());
 
-    /// Game Boy Light palette
-    pub const LIGHT: [u32; 4] = [0xFF00B581, 0xFF009A71, 0xFF006839, 0xFF004F2B];
+        emu.set_profiling(true);
+        let steps = 10 * profile::SAMPLE_INTERVAL as u64;
+        for _ in 0..steps {
+            emu.step();
+        }
+        let profile = emu.profile().unwrap();
+        assert_eq!(profile.samples, steps / profile::SAMPLE_INTERVAL as u64);
+        assert!(profile.total() > std::time::Duration::ZERO);
 
-    /// SGB Border palette style
-    pub const SGB: [u32; 4] = [0xFFF7E7C6, 0xFFD68E49, 0xFFA63725, 0xFF331820];
-}
+        emu.set_profiling(false);
+        assert!(emu.profile().is_none());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn opcode_counting() {
+        let mut rom = vec![0u8; 0x8000];
+        // INC A; SWAP A; JR -5
+        rom[0x100..0x105].copy_from_slice(&[0x3C, 0xCB, 0x37, 0x18, 0xFB]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        assert!(emu.opcode_histogram().is_none());
+
+        emu.set_opcode_counting(true);
+        for _ in 0..30 {
+            emu.step();
+        }
+        let histogram = emu.opcode_histogram().unwrap();
+        assert_eq!(histogram.total(), 30);
+        assert_eq!((histogram.base[0x3C], histogram.base[0xCB], histogram.base[0x18]), (10, 10, 10));
+        assert_eq!(histogram.cb[0x37], 10);
+        assert_eq!(histogram.most_executed(2), [(0x18, 10), (0x3C, 10)]);
+        assert_eq!(histogram.unexecuted().len(), 512 - 4);
+
+        emu.set_opcode_counting(false);
+        assert!(emu.opcode_histogram().is_none());
+    }
 
     #[test]
-    fn emulator_creation() {
+    fn interrupt_log_times_dispatches() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x40] = 0xD9; // RETI
+        // LD A,1; LDH (IE),A; EI; HALT; JR -3
+        rom[0x100..0x108].copy_from_slice(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x76, 0x18, 0xFD]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.set_interrupt_logging(true);
+        emu.run_frame();
+        emu.run_frame();
+
+        let log: Vec<_> = emu.interrupt_log().copied().collect();
+        let InterruptEvent::Requested { interrupt: Interrupt::VBlank, cycle: requested } = log[0] else {
+            panic!("{:?}", log);
+        };
+        let InterruptEvent::Serviced { interrupt: Interrupt::VBlank, cycle, pc, latency } = log[1] else {
+            panic!("{:?}", log);
+        };
+        // Waking from HALT takes an M-cycle before the dispatch
+        assert_eq!((pc, latency), (0x0106, Some(4)));
+        assert_eq!(cycle, requested + 4);
+
+        // One pending before logging began waited for an unknown time
+        emu.set_interrupt_logging(false);
+        emu.set_interrupt_logging(true);
+        emu.run_frame();
+        let log: Vec<_> = emu.interrupt_log().copied().collect();
+        assert!(matches!(log[0], InterruptEvent::Serviced { interrupt: Interrupt::VBlank, latency: None, .. }));
+
+        emu.clear_interrupt_log();
+        assert_eq!(emu.interrupt_log().count(), 0);
+        emu.set_interrupt_logging(false);
+        emu.run_frame();
+        assert_eq!(emu.interrupt_log().count(), 0);
+    }
+
+    #[test]
+    fn ppu_write_timeline_places_writes_in_the_frame() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x112].copy_from_slice(&[
+            0xF0, 0x44, 0xFE, 0x48, 0x20, 0xFA, // wait for LY 72
+            0x3E, 0x05, 0xE0, 0x43, // LD A,5; LDH (SCX),A
+            0xF0, 0x44, 0xFE, 0x48, 0x28, 0xFA, // wait for LY to move on
+            0x18, 0xEE, // JR back to the start
+        ]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        assert!(emu.ppu_write_timeline().is_empty());
+        emu.set_ppu_write_timeline(true);
+        emu.run_frame();
+        emu.run_frame();
+
+        let writes = emu.ppu_write_timeline().to_vec();
+        assert_eq!(writes.len(), 1, "{:?}", writes);
+        let write = writes[0];
+        assert_eq!((write.register, write.value, write.line), (0xFF43, 5, 72));
+        assert!(write.dot < 456);
+        assert!(write.cycle < emu.total_cycles());
+
+        // Each frame replaces the last
+        emu.run_frame();
+        assert_eq!(emu.ppu_write_timeline().len(), 1);
+        assert!(emu.ppu_write_timeline()[0].cycle > write.cycle);
+
+        emu.set_ppu_write_timeline(false);
+        emu.run_frame();
+        assert!(emu.ppu_write_timeline().is_empty());
+        assert!(emu.memory.ppu_write_log.is_empty());
+    }
+
+    #[test]
+    fn stack_slice_and_check() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x112].copy_from_slice(&[
+            0x31, 0x81, 0xFF, // LD SP,$FF81
+            0xC5, // PUSH BC, into I/O
+            0x31, 0xFE, 0xFF, // LD SP,$FFFE
+            0xC1, // POP BC, from IE
+            0x31, 0x00, 0xD0, // LD SP,$D000
+            0x21, 0x34, 0x12, // LD HL,$1234
+            0xE5, // PUSH HL
+            0xCD, 0x20, 0x01, // CALL $0120
+        ]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.set_events_enabled(true);
+        emu.set_stack_check(true);
+        for _ in 0..8 {
+            emu.step();
+        }
+        assert_eq!(emu.cpu_state().pc, 0x0120);
+        let warnings: Vec<_> = emu.drain_events().filter(|e| matches!(e, EmulatorEvent::StackWarning { .. })).collect();
+        assert_eq!(
+            warnings,
+            [EmulatorEvent::StackWarning { pc: 0x0103, addr: 0xFF7F }, EmulatorEvent::StackWarning { pc: 0x0107, addr: 0xFFFE }]
+        );
+
+        let stack = emu.stack_slice(2);
+        assert_eq!(
+            stack,
+            [
+                StackEntry { addr: 0xCFFC, value: 0x0112, return_address: true },
+                StackEntry { addr: 0xCFFE, value: 0x1234, return_address: false },
+            ]
+        );
+        emu.cpu.sp = 0xFFFC;
+        assert_eq!(emu.stack_slice(4).len(), 2);
+    }
+
+    #[test]
+    fn cycle_and_frame_counters() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset();
+        emu.run_cycles(1000);
+        let cycles = emu.total_cycles();
+        assert!(cycles >= 1000);
+        emu.run_frame();
+        emu.run_frame();
+        assert_eq!(emu.frame_count(), 2);
+        assert!(emu.total_cycles() > cycles + 70224);
+
+        let state = emu.save_state();
+        let (cycles, frames) = (emu.total_cycles(), emu.frame_count());
+        emu.run_frame();
+        emu.load_state(&state).unwrap();
+        assert_eq!((emu.total_cycles(), emu.frame_count()), (cycles, frames));
+
+        emu.reset();
+        assert_eq!((emu.total_cycles(), emu.frame_count()), (0, 0));
+    }
+
+    #[test]
+    fn screenshot_applies_palette() {
         let emu = Emulator::new();
-        assert_eq!(emu.framebuffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        let rgba = emu.screenshot_rgba(&palettes::DMG_GREEN);
+        assert_eq!(rgba.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        assert_eq!(&rgba[..4], &[0x9B, 0xBC, 0x0F, 0xFF]);
     }
 
     #[test]
-    fn button_state() {
+    fn reset_applies_model_state() {
         let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset();
+        assert_eq!(emu.peek(io::DIV), 0xAB);
+        assert_eq!(emu.peek(io::NR52), 0xF1);
 
-        // All buttons released
-        assert_eq!(emu.button_state, 0xFF);
+        emu.reset_for_model(GbModel::Sgb);
+        assert_eq!(emu.peek(io::DIV), 0xD2);
+        assert_eq!(emu.peek(io::NR52), 0xF0);
+        assert_eq!(emu.cpu.c, 0x14);
 
-        // Press A
+        // The model survives plain resets and save states
+        emu.reset();
+        assert_eq!(emu.model(), GbModel::Sgb);
+        let state = emu.save_state();
+        emu.reset_for_model(GbModel::Mgb);
+        emu.load_state(&state).unwrap();
+        assert_eq!(emu.model(), GbModel::Sgb);
+    }
+
+    #[test]
+    fn soft_reset_keeps_cartridge_ram() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x0148] = 0x01; // 64KB
+        rom[0x0149] = 0x02; // 8KB
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset_for_model(GbModel::Mgb);
+        emu.memory.write_byte(0x0000, 0x0A);
+        emu.memory.write_byte(0xA000, 0x42);
+        emu.memory.write_byte(0x2000, 0x03);
+        emu.memory.write_byte(0xC000, 0x99);
+        emu.run_frame();
+
+        // A plain reset leaves the bank registers as the game set them
+        emu.reset();
+        assert_eq!(emu.memory.rom_bank(), 3);
+
+        emu.soft_reset();
+        assert_eq!(emu.memory.rom_bank(), 1);
+        assert_eq!(emu.memory.read_byte(0xA000), 0xFF, "RAM starts out disabled");
+        assert_eq!(emu.memory.get_eram()[0], 0x42);
+        assert_eq!(emu.peek(0xC000), 0x00);
+        assert_eq!(emu.model(), GbModel::Mgb);
+        assert_eq!((emu.cpu.pc, emu.total_cycles()), (0x0100, 0));
+    }
+
+    #[test]
+    fn sgb_multiplayer_reads_each_controller() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&vec![0u8; 0x8000]);
+        emu.reset_for_model(GbModel::Sgb);
+        emu.set_button_for_player(1, Button::Start, true);
+        emu.set_button_for_player(MAX_PLAYERS, Button::Start, true);
+        emu.step();
+
+        // MLT_REQ packet enabling two controllers
+        let mut packet = [0u8; 16];
+        packet[..2].copy_from_slice(&[0x11 << 3 | 1, 0x01]);
+        emu.memory.write_byte(io::JOYP, 0x00);
+        emu.memory.write_byte(io::JOYP, 0x30);
+        for i in 0..128 {
+            let bit = packet[i / 8] >> (i % 8) & 1;
+            emu.memory.write_byte(io::JOYP, if bit != 0 { 0x10 } else { 0x20 });
+            emu.memory.write_byte(io::JOYP, 0x30);
+        }
+        emu.memory.write_byte(io::JOYP, 0x20);
+        emu.memory.write_byte(io::JOYP, 0x30);
+        assert_eq!(emu.memory.read_byte(io::JOYP) & 0x0F, 0x0F);
+
+        // Player 1 is selected on the next P15 rising edge; with both
+        // lines high the low nibble reads as the controller ID
+        emu.memory.write_byte(io::JOYP, 0x10);
+        assert_eq!(emu.memory.read_byte(io::JOYP) & 0x0F, 0x0F);
+        emu.memory.write_byte(io::JOYP, 0x30);
+        assert_eq!(emu.memory.read_byte(io::JOYP) & 0x0F, 0x0E);
+        emu.memory.write_byte(io::JOYP, 0x10);
+        assert_eq!(emu.memory.read_byte(io::JOYP) & 0x0F, 0x07);
+    }
+
+    #[test]
+    fn stop_switches_cgb_speed() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0143] = 0x80; // CGB support
+        // LD A, 1; LDH (KEY1), A; STOP; NOP
+        rom[0x0100..0x0107].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x00]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset_for_model(GbModel::Cgb);
+        assert_eq!(emu.memory.read_byte(io::KEY1), 0x7E);
+
+        emu.step();
+        emu.step();
+        assert_eq!(emu.memory.read_byte(io::KEY1), 0x7F);
+        emu.step();
+        assert!(emu.double_speed() && !emu.cpu.stopped);
+        assert_eq!(emu.memory.read_byte(io::KEY1), 0xFE);
+        // NOPs now take half as long, but DIV counts CPU cycles
+        let div = emu.peek(io::DIV);
+        let mut dots = 0;
+        while dots < 4096 {
+            assert_eq!(emu.step(), 2);
+            dots += 2;
+        }
+        assert_eq!(emu.timer.div(), div.wrapping_add(32));
+
+        // DMG mode has no KEY1, so STOP stops
+        rom[0x0143] = 0x00;
+        emu.load_rom(&rom);
+        emu.reset_for_model(GbModel::Cgb);
+        assert_eq!(emu.memory.read_byte(io::KEY1), 0xFF);
+        for _ in 0..3 {
+            emu.step();
+        }
+        assert!(!emu.double_speed() && emu.cpu.stopped);
+    }
+
+    #[test]
+    fn infrared_link_connects_emulators() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0143] = 0x80;
+        let mut emus = [Emulator::new(), Emulator::new()];
+        let (a, b) = infrared::InfraredLink::pair();
+        emus[0].set_infrared_device(a);
+        emus[1].set_infrared_device(b);
+        for emu in &mut emus {
+            emu.load_rom(&rom);
+            emu.reset_for_model(GbModel::Cgb);
+            emu.memory.write_byte(io::RP, 0xC0);
+        }
+        assert_eq!(emus[1].memory.read_byte(io::RP), 0xFE);
+
+        // The LED is seen by the other side only, once both have stepped
+        emus[0].memory.write_byte(io::RP, 0xC1);
+        emus[0].step();
+        emus[1].step();
+        assert_eq!(emus[1].memory.read_byte(io::RP), 0xFC);
+        assert_eq!(emus[0].memory.read_byte(io::RP), 0xFF);
+
+        // Disabling the sensor hides the light
+        emus[1].memory.write_byte(io::RP, 0x00);
+        assert_eq!(emus[1].memory.read_byte(io::RP), 0x3E);
+
+        emus[0].set_infrared_device(infrared::Loopback::default());
+        emus[0].step();
+        assert_eq!(emus[0].memory.read_byte(io::RP), 0xFD);
+    }
+
+    #[test]
+    fn link_cable_connects_emulators() {
+        let mut emus = [Emulator::new(), Emulator::new()];
+        let (a, b) = serial::LinkCable::pair();
+        emus[0].set_serial_device(a);
+        emus[1].set_serial_device(b);
+        for (i, sb, sc) in [(1, 0x34, 0x80), (0, 0x56, 0x81)] {
+            let emu = &mut emus[i];
+            emu.load_rom(&[0u8; 0x8000]);
+            emu.reset();
+            emu.memory.write_byte(io::SB, sb);
+            emu.memory.write_byte(io::SC, sc);
+            emu.step();
+        }
+        // Interleaved like a frontend would run them
+        for _ in 0..20 {
+            for emu in &mut emus {
+                emu.run_cycles(456);
+            }
+        }
+        assert_eq!(emus[0].memory.read_byte(io::SB), 0x34);
+        assert_eq!(emus[1].memory.read_byte(io::SB), 0x56);
+        assert!(emus.iter().all(|emu| emu.memory.read_byte(io::SC) & 0x80 == 0));
+    }
+
+    #[test]
+    fn selected_button_press_ends_stop() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD A, $20; LDH (JOYP), A; STOP
+        rom[0x0100..0x0106].copy_from_slice(&[0x3E, 0x20, 0xE0, 0x00, 0x10, 0x00]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        for _ in 0..3 {
+            emu.step();
+        }
+        assert!(emu.cpu.stopped);
+
+        // Only directions are selected
         emu.set_button(Button::A, true);
-        assert_eq!(emu.button_state & 0x10, 0x00);
+        emu.step();
+        assert!(emu.cpu.stopped);
+        emu.set_button(Button::Down, true);
+        emu.step();
+        assert!(!emu.cpu.stopped);
+    }
 
-        // Release A
-        emu.set_button(Button::A, false);
-        assert_eq!(emu.button_state & 0x10, 0x10);
+    #[test]
+    fn stop_mode_freezes_the_system() {
+        let mut rom = vec![0u8; 0x8000];
+        // LD A, $10; LDH (JOYP), A; STOP; NOP
+        rom[0x0100..0x0107].copy_from_slice(&[0x3E, 0x10, 0xE0, 0x00, 0x10, 0x00, 0x00]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.ppu.framebuffer.fill(3);
+        for _ in 0..3 {
+            emu.step();
+        }
+        assert!(emu.cpu.stopped);
+        assert_eq!(emu.cpu.pc, 0x0106);
+        assert!(emu.ppu.framebuffer.iter().all(|&c| c == 0));
+
+        // Nothing runs, DIV included, until a button wakes the CPU
+        let ly = emu.peek(io::LY);
+        emu.run_cycles(70224);
+        assert_eq!((emu.cpu.pc, emu.peek(io::DIV), emu.peek(io::LY)), (0x0106, 0, ly));
+
+        // With a selected button already held, STOP just halts
+        emu.reset();
+        emu.set_button(Button::A, true);
+        for _ in 0..3 {
+            emu.step();
+        }
+        assert!(emu.cpu.halted && !emu.cpu.stopped);
+        assert_eq!(emu.cpu.pc, 0x0106);
+        assert_ne!(emu.peek(io::DIV), 0);
+    }
+
+    #[test]
+    fn cpu_state_snapshot() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x19; // MBC5
+        rom[0x0148] = 0x01;
+        // LD A, 3; LD ($2000), A
+        rom[0x0100..0x0105].copy_from_slice(&[0x3E, 0x03, 0xEA, 0x00, 0x20]);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+
+        let state = emu.cpu_state();
+        assert_eq!((state.pc, state.sp, state.af()), (0x0100, 0xFFFE, 0x01B0));
+        assert_eq!((state.bc(), state.de(), state.hl()), (0x0013, 0x00D8, 0x014D));
+        assert_eq!(state.rom_bank, 1);
+
+        emu.step();
+        emu.step();
+        let state = emu.cpu_state();
+        assert_eq!((state.a, state.pc, state.rom_bank), (3, 0x0105, 3));
+        assert!(!state.ime && !state.halted && !state.stopped);
+    }
+
+    #[test]
+    fn save_state_roundtrip() {
+        let mut rom = vec![0u8; 0x8000];
+        // Tight loop: INC A; JR -3
+        rom[0x0100..0x0103].copy_from_slice(&[0x3C, 0x18, 0xFD]);
+
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.reset();
+        emu.run_frame();
+
+        let state = emu.save_state();
+        let (a, pc) = (emu.cpu.a, emu.cpu.pc);
+        emu.run_frame();
+        emu.run_frame();
+        assert_ne!(emu.save_state(), state);
+
+        emu.load_state(&state).unwrap();
+        assert_eq!((emu.cpu.a, emu.cpu.pc), (a, pc));
+        assert_eq!(emu.save_state(), state);
+    }
+
+    #[test]
+    fn load_state_rejects_other_rom() {
+        let mut emu = Emulator::new();
+        emu.load_rom(&[0u8; 0x8000]);
+        let state = emu.save_state();
+
+        let mut other = Emulator::new();
+        other.load_rom(&[0xFFu8; 0x8000]);
+        assert_eq!(other.load_state(&state), Err(StateError::RomMismatch));
+    }
+
+    #[test]
+    fn save_ram_dirty_tracks_changes() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x1B; // MBC5+RAM+BATTERY
+        rom[0x0149] = 0x02; // 8KB
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        assert!(!emu.save_ram_dirty());
+
+        emu.poke(0xA000, 0x12);
+        assert!(emu.save_ram_dirty());
+        // Reading the RAM out doesn't mean it was saved
+        emu.save_ram().unwrap();
+        emu.export_save(SaveFormat::default(), 0).unwrap();
+        assert!(emu.save_ram_dirty());
+        emu.mark_saved();
+        assert!(!emu.save_ram_dirty());
+
+        // Rewriting the same value isn't a change
+        emu.poke(0xA000, 0x12);
+        assert!(!emu.save_ram_dirty());
+    }
+
+    #[test]
+    fn save_file_imports_vba_footer() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x0149] = 0x02; // 8KB
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.poke(0xA000, 0x5A);
+
+        let format = SaveFormat { rtc_footer: savefile::RtcFooter::Vba, ..Default::default() };
+        let data = emu.export_save(format, 1_000_000).unwrap();
+        assert_eq!(data.len(), 0x2000 + rtc::VBA_FOOTER_SIZE);
+
+        let mut loaded = Emulator::new();
+        loaded.load_rom(&rom);
+        loaded.load_save_file(&data, 1_000_000 + 61);
+        assert_eq!(loaded.save_ram().unwrap()[0], 0x5A);
+        // Re-exported in the default layout with the elapsed minute counted
+        let resaved = loaded.save_file(1_000_061).unwrap();
+        assert_eq!(resaved.len(), 0x2000 + rtc::FOOTER_SIZE);
+        assert_eq!(resaved[0x2000..0x2008], [1, 0, 0, 0, 1, 0, 0, 0]);
     }
 
     #[test]
@@ -477,6 +3107,100 @@ mod tests {
         assert_eq!(info.cart_type, "MBC1");
         assert_eq!(info.rom_size, "32 KB");
         assert_eq!(info.ram_size, "None");
+        assert!(!info.logo_valid);
+        assert_eq!(info.cgb_support(), CgbSupport::Incompatible);
+        assert!(!info.sgb_support());
+        assert_eq!(info.publisher(), Some("None"));
+        assert_eq!(info.destination(), Destination::Japan);
+
+        rom[0x0143] = 0xC0;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x33;
+        rom[0x014C] = 0x02;
+        let info = Emulator::parse_rom_info(&rom).unwrap();
+        assert_eq!(info.cgb_support(), CgbSupport::Only);
+        assert!(info.sgb_support());
+        assert_eq!(info.publisher(), Some("Nintendo R&D1"));
+        assert_eq!(info.destination(), Destination::Overseas);
+        assert_eq!(info.version, 2);
+
+        rom[0x0143] = 0x80;
+        rom[0x014B] = 0x01;
+        let info = Emulator::parse_rom_info(&rom).unwrap();
+        assert_eq!(info.cgb_support(), CgbSupport::Compatible);
+        assert!(!info.sgb_support());
+        assert_eq!(info.publisher(), Some("Nintendo"));
     }
-}
 
+    #[test]
+    fn rom_verification() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x0134..0x0138].copy_from_slice(b"GOOD");
+        rom[0x014D] = Emulator::parse_rom_info(&rom).unwrap().computed_header_checksum;
+        let global = Emulator::parse_rom_info(&rom).unwrap().computed_global_checksum;
+        rom[0x014E..0x0150].copy_from_slice(&global.to_be_bytes());
+        assert!(Emulator::verify_rom(&rom).is_valid());
+
+        // A flipped byte outside the header only breaks the global checksum
+        rom[0x4000] ^= 0xFF;
+        let v = Emulator::verify_rom(&rom);
+        assert!(!v.global_checksum_ok && !v.would_lock_up());
+
+        rom[0x0134] = b'B';
+        assert!(!Emulator::verify_rom(&rom).header_checksum_ok);
+        assert!(!Emulator::verify_rom(&rom[..0x100]).has_header);
+    }
+
+    #[test]
+    fn initial_ram_options() {
+        let wram = |options: EmulatorOptions| {
+            let mut emu = Emulator::with_options(options);
+            emu.load_rom(&[0u8; 0x8000]);
+            emu.reset();
+            (0xC000..0xC100).map(|addr| emu.peek(addr)).collect::<Vec<u8>>()
+        };
+        let options = |initial_ram| EmulatorOptions { initial_ram, ..Default::default() };
+        let zeros = wram(EmulatorOptions::default());
+        assert!(zeros.iter().all(|&b| b == 0));
+
+        let random = |seed| wram(options(InitialRam::Random(seed)));
+        assert_eq!(random(1), random(1));
+        assert_ne!(random(1), random(2));
+        assert!(random(1).iter().any(|&b| b != 0));
+
+        let pattern = wram(options(InitialRam::ModelPattern));
+        assert_eq!(pattern, wram(options(InitialRam::ModelPattern)));
+        assert_ne!(pattern, zeros);
+
+        let mut emu = Emulator::with_options(options(InitialRam::ModelPattern));
+        emu.load_rom(&[0u8; 0x8000]);
+        emu.reset_for_model(GbModel::Cgb);
+        assert_eq!([emu.peek(0xC000), emu.peek(0xC008)], [0x00, 0xFF]);
+    }
+
+    #[test]
+    fn rom_size_check() {
+        let mut rom = vec![0u8; 0x8000];
+        let check = |rom: &[u8]| Emulator::parse_rom_info(rom).unwrap().size_check();
+        assert_eq!(check(&rom), RomSizeCheck::Matches);
+        rom[0x0148] = 0x01;
+        assert_eq!(check(&rom), RomSizeCheck::Truncated);
+        rom.resize(0x20000, 0xFF);
+        assert_eq!(check(&rom), RomSizeCheck::Overdump);
+        assert_eq!(Emulator::verify_rom(&rom).size, RomSizeCheck::Overdump);
+        rom[0x0148] = 0x09;
+        assert_eq!(check(&rom), RomSizeCheck::UnknownSize);
+
+        // Past 8 MB nothing is reachable, but the image still loads
+        rom[0x0147] = 0x19;
+        rom[0x0148] = 0x08;
+        rom.resize(0x900000, 0xFF);
+        assert_eq!(check(&rom), RomSizeCheck::Overdump);
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom);
+        emu.run_frame();
+    }
+}
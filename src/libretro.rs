@@ -0,0 +1,454 @@
+//! libretro core implementation.
+//!
+//! Enabled with the `libretro` feature. Build the core with
+//! `cargo build --release --lib --no-default-features --features libretro`
+//! and load the resulting `libgb3000.so` (or `gb3000.dll`) in RetroArch.
+//!
+//! The libretro API is a set of global C functions, so the emulator and the
+//! frontend callbacks live in a process-wide [`Core`] behind a mutex.
+
+use crate::sgb::MAX_PLAYERS;
+use crate::{palettes, Button, Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::sync::Mutex;
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 2;
+const RETRO_ENVIRONMENT_GET_RUMBLE_INTERFACE: c_uint = 23;
+const RETRO_RUMBLE_STRONG: c_uint = 0;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+
+const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+/// Game Boy frame rate (4194304 Hz / 70224 cycles per frame)
+const FPS: f64 = 4_194_304.0 / 70_224.0;
+
+/// Mapping from libretro joypad IDs to Game Boy buttons
+const BUTTON_MAP: [(c_uint, Button); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_A, Button::A),
+    (RETRO_DEVICE_ID_JOYPAD_B, Button::B),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, Button::Select),
+    (RETRO_DEVICE_ID_JOYPAD_START, Button::Start),
+    (RETRO_DEVICE_ID_JOYPAD_UP, Button::Up),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, Button::Down),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, Button::Left),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, Button::Right),
+];
+
+pub type RetroEnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPollFn = unsafe extern "C" fn();
+pub type RetroInputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+pub type RetroSetRumbleStateFn = unsafe extern "C" fn(port: c_uint, effect: c_uint, strength: u16) -> bool;
+
+#[repr(C)]
+pub struct RetroRumbleInterface {
+    pub set_rumble_state: Option<RetroSetRumbleStateFn>,
+}
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+/// Process-wide core state shared by the libretro entry points
+struct Core {
+    emulator: Emulator,
+    video: Vec<u32>,
+    audio: Vec<f32>,
+    audio_out: Vec<i16>,
+    environment: Option<RetroEnvironmentFn>,
+    video_refresh: Option<RetroVideoRefreshFn>,
+    audio_batch: Option<RetroAudioSampleBatchFn>,
+    input_poll: Option<RetroInputPollFn>,
+    input_state: Option<RetroInputStateFn>,
+    /// Frontend force feedback, if the frontend offers it
+    set_rumble: Option<RetroSetRumbleStateFn>,
+    /// Motor state last sent to the frontend
+    rumble: bool,
+}
+
+impl Core {
+    fn create() -> Self {
+        Self {
+            emulator: Emulator::new(),
+            video: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            audio: Vec::with_capacity(4096),
+            audio_out: Vec::with_capacity(4096),
+            environment: None,
+            video_refresh: None,
+            audio_batch: None,
+            input_poll: None,
+            input_state: None,
+            set_rumble: None,
+            rumble: false,
+        }
+    }
+}
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+/// Run `f` with the core, creating it on first use
+fn with_core<R>(f: impl FnOnce(&mut Core) -> R) -> R {
+    let mut guard = CORE.lock().unwrap_or_else(|e| e.into_inner());
+    f(guard.get_or_insert_with(Core::create))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: RetroEnvironmentFn) {
+    with_core(|core| core.environment = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: RetroVideoRefreshFn) {
+    with_core(|core| core.video_refresh = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: RetroAudioSampleFn) {
+    // Audio is always delivered through the batch callback
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: RetroAudioSampleBatchFn) {
+    with_core(|core| core.audio_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: RetroInputPollFn) {
+    with_core(|core| core.input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: RetroInputStateFn) {
+    with_core(|core| core.input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    with_core(|_| {});
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    let mut guard = CORE.lock().unwrap_or_else(|e| e.into_inner());
+    *guard = None;
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    if let Some(info) = info.as_mut() {
+        info.library_name = c"GB3000".as_ptr();
+        info.library_version = concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char;
+        info.valid_extensions = c"gb|dmg".as_ptr();
+        info.need_fullpath = false;
+        info.block_extract = false;
+    }
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    if let Some(info) = info.as_mut() {
+        info.geometry = RetroGameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+        };
+        info.timing = RetroSystemTiming {
+            fps: FPS,
+            sample_rate: crate::apu::SAMPLE_RATE as f64,
+        };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| core.emulator.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core(|core| {
+        if let Some(poll) = core.input_poll {
+            unsafe { poll() };
+        }
+        if let Some(state) = core.input_state {
+            // Ports past the first only matter to SGB multiplayer games
+            for port in 0..MAX_PLAYERS {
+                for (id, button) in BUTTON_MAP {
+                    let pressed = unsafe { state(port as c_uint, RETRO_DEVICE_JOYPAD, 0, id) } != 0;
+                    core.emulator.set_button_for_player(port, button, pressed);
+                }
+            }
+        }
+
+        core.emulator.run_frame();
+
+        let rumble = core.emulator.rumble_active();
+        if rumble != core.rumble {
+            core.rumble = rumble;
+            if let Some(set_rumble) = core.set_rumble {
+                let strength = if rumble { u16::MAX } else { 0 };
+                unsafe { set_rumble(0, RETRO_RUMBLE_STRONG, strength) };
+            }
+        }
+
+        core.emulator.framebuffer_argb(&palettes::GRAYSCALE, &mut core.video);
+        if let Some(refresh) = core.video_refresh {
+            unsafe {
+                refresh(
+                    core.video.as_ptr() as *const c_void,
+                    SCREEN_WIDTH as c_uint,
+                    SCREEN_HEIGHT as c_uint,
+                    SCREEN_WIDTH * std::mem::size_of::<u32>(),
+                );
+            }
+        }
+
+        core.audio.resize(core.audio.capacity(), 0.0);
+        let count = core.emulator.read_audio_samples(&mut core.audio);
+        core.audio_out.clear();
+        core.audio_out.extend(
+            core.audio[..count]
+                .iter()
+                .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+        );
+        if let Some(batch) = core.audio_batch {
+            let mut frames = &core.audio_out[..];
+            while frames.len() >= 2 {
+                let written = unsafe { batch(frames.as_ptr(), frames.len() / 2) };
+                if written == 0 {
+                    break;
+                }
+                frames = &frames[(written * 2).min(frames.len())..];
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.emulator.save_state().len())
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    with_core(|core| {
+        let state = core.emulator.save_state();
+        if state.len() > size {
+            return false;
+        }
+        std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(|core| core.emulator.load_state(state).is_ok())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a valid `retro_game_info` whose `data`
+/// holds `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() || game.size == 0 {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts(game.data as *const u8, game.size);
+
+    with_core(|core| {
+        if let Some(env) = core.environment {
+            let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+            if !env(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+                return false;
+            }
+            let mut rumble = RetroRumbleInterface { set_rumble_state: None };
+            if env(RETRO_ENVIRONMENT_GET_RUMBLE_INTERFACE, &mut rumble as *mut _ as *mut c_void) {
+                core.set_rumble = rumble.set_rumble_state;
+            }
+        }
+        core.rumble = false;
+        core.emulator = Emulator::new();
+        core.emulator.load_rom(rom);
+        core.emulator.reset();
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| {
+        if let (true, Some(set_rumble)) = (core.rumble, core.set_rumble) {
+            unsafe { set_rumble(0, RETRO_RUMBLE_STRONG, 0) };
+        }
+        core.rumble = false;
+        core.emulator = Emulator::new();
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SAVE_RAM {
+        return std::ptr::null_mut();
+    }
+    // The ERAM buffer lives inside the emulator, which stays at a fixed
+    // address inside CORE until the game is unloaded.
+    with_core(|core| match core.emulator.save_ram_mut() {
+        Some(ram) => ram.as_mut_ptr() as *mut c_void,
+        None => std::ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SAVE_RAM {
+        return 0;
+    }
+    with_core(|core| core.emulator.save_ram_mut().map_or(0, |ram| ram.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn environment(_cmd: c_uint, _data: *mut c_void) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, _pitch: usize) {
+        assert!(!data.is_null());
+        assert_eq!((width, height), (160, 144));
+        FRAMES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn load_run_and_serialize() {
+        let rom = vec![0u8; 0x8000];
+        let game = RetroGameInfo {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+
+        retro_init();
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        unsafe {
+            assert!(retro_load_game(&game));
+        }
+        retro_run();
+        assert_eq!(FRAMES.load(Ordering::SeqCst), 1);
+
+        let size = retro_serialize_size();
+        let mut state = vec![0u8; size];
+        unsafe {
+            assert!(retro_serialize(state.as_mut_ptr() as *mut c_void, size));
+            retro_run();
+            assert!(retro_unserialize(state.as_ptr() as *const c_void, size));
+        }
+        retro_deinit();
+    }
+}
@@ -0,0 +1,39 @@
+//! Diagnostic logging through the `log` crate.
+//!
+//! Enabled with the `log` feature; without it the macros compile to
+//! nothing. Each message goes to a target naming the part of the console
+//! it is about, so a logger can show just one of them (with `env_logger`,
+//! `RUST_LOG=gb3000::mbc=trace`):
+//!
+//! - `gb3000::mbc`: ROM and RAM bank switches, RAM enable and disable
+//! - `gb3000::ppu`: LCDC writes, with the LCD switching on and off at
+//!   debug level
+//! - `gb3000::dma`: OAM DMA transfers starting
+//! - `gb3000::memory`: accesses to addresses nothing answers, such as
+//!   0xFEA0-0xFEFF or disabled cartridge RAM
+//! - `gb3000::serial`: bytes sent over the link port
+//! - `gb3000::cpu`: the hang watchdog firing
+
+/// Log at debug level to a `gb3000::*` target
+macro_rules! log_debug {
+    ($target:literal, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::debug!(target: $target, $($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// Log at trace level to a `gb3000::*` target
+macro_rules! log_trace {
+    ($target:literal, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::trace!(target: $target, $($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
@@ -0,0 +1,219 @@
+//! Lua scripting (the `lua` feature)
+//!
+//! Scripts are run by a small built-in Lua 5.4 interpreter, so the
+//! feature needs no C library or build tools. It covers the language
+//! except `goto` and coroutines, plus the base, `math`, `string` and
+//! `table` libraries, and these emulator bindings:
+//!
+//! - `memory.read_u8(addr)`, `read_s8`, `read_u16_le`, `write_u8(addr,
+//!   value)` and `write_u16_le`, through [`Emulator::peek`] and
+//!   [`Emulator::poke`]
+//! - `joypad.get()`, a table of held buttons by name (`A`, `B`,
+//!   `Select`, `Start`, `Right`, `Left`, `Up`, `Down`), and
+//!   `joypad.set(buttons)` to force the named ones for the next frame
+//! - `emu.framecount()` and `emu.registers()`
+//! - `event.onframeend(f)` to call `f` after every frame
+//! - `gui.pixel(x, y, color)`, `gui.line(x1, y1, x2, y2, color)`,
+//!   `gui.rect(x, y, w, h, color, fill)` and `gui.text(x, y, text,
+//!   color)`, in screen pixels with 0xRRGGBB or 0xAARRGGBB colors
+//!
+//! Each call into a script may run a million statements before it's
+//! stopped with an error, so a stuck loop can't hang the emulator.
+//! `math.random` starts from a fixed seed, keeping scripted runs
+//! repeatable.
+
+mod bindings;
+mod interp;
+mod lexer;
+mod parser;
+mod stdlib;
+mod value;
+
+use crate::{Button, Emulator};
+use bindings::Host;
+use interp::{State, Throw, Vm};
+use value::Value;
+
+/// Errors from loading or running a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LuaError {
+    /// The source isn't valid Lua
+    Syntax(String),
+    /// The script raised an error or ran too long
+    Runtime(String),
+}
+
+impl std::fmt::Display for LuaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LuaError::Syntax(message) => write!(f, "syntax error: {}", message),
+            LuaError::Runtime(message) => write!(f, "script error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for LuaError {}
+
+impl From<Throw> for LuaError {
+    fn from(throw: Throw) -> Self {
+        LuaError::Runtime(match throw {
+            Throw::Error(Value::Str(message)) => String::from_utf8_lossy(&message).into_owned(),
+            Throw::Error(value @ (Value::Nil | Value::Bool(_) | Value::Int(_) | Value::Num(_))) => value.to_string(),
+            Throw::Error(value) => format!("(error object is a {} value)", value.type_name()),
+            Throw::Timeout => "script ran too long without returning".to_string(),
+        })
+    }
+}
+
+/// A shape a script drew over the screen, in Game Boy screen pixels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DrawCommand {
+    Pixel { x: i32, y: i32, color: u32 },
+    Line { x1: i32, y1: i32, x2: i32, y2: i32, color: u32 },
+    /// An outline, or a solid block if `fill`
+    Rect { x: i32, y: i32, width: i32, height: i32, color: u32, fill: bool },
+    Text { x: i32, y: i32, text: String, color: u32 },
+}
+
+/// A loaded script
+///
+/// A frontend calls [`apply_input`](Self::apply_input) after setting the
+/// player's buttons for a frame and [`after_frame`](Self::after_frame)
+/// once it has run, then shows the [`overlay`](Self::overlay) over the
+/// screen and prints the [`output`](Self::take_output).
+pub struct Script {
+    state: State,
+}
+
+impl Script {
+    /// Compile a script and run its top level, which usually registers
+    /// callbacks; `name` appears in error messages
+    pub fn load(name: &str, source: &str, emulator: &mut Emulator) -> Result<Script, LuaError> {
+        let proto = parser::parse(name, source)?;
+        let mut state = State::new(Host::default());
+        bindings::open(&mut state);
+        Vm::new(&mut state, emulator).run(proto)?;
+        Ok(Script { state })
+    }
+
+    /// Force the buttons the script set with `joypad.set`
+    ///
+    /// Call after the frontend sets its own input for the frame; calling
+    /// again before the frame runs is harmless.
+    pub fn apply_input(&self, emulator: &mut Emulator) {
+        for (button, forced) in Button::ALL.into_iter().zip(self.state.host.joypad) {
+            if let Some(pressed) = forced {
+                emulator.set_button(button, pressed);
+            }
+        }
+    }
+
+    /// Run the `event.onframeend` callbacks for the frame just finished
+    ///
+    /// Clears the previous frame's overlay and forced buttons first. On an
+    /// error the remaining callbacks are skipped; frontends usually stop
+    /// the script.
+    pub fn after_frame(&mut self, emulator: &mut Emulator) -> Result<(), LuaError> {
+        let host = &mut self.state.host;
+        host.overlay.clear();
+        host.joypad = [None; 8];
+        let callbacks = host.frame_end.clone();
+        let mut vm = Vm::new(&mut self.state, emulator);
+        for callback in &callbacks {
+            vm.call(callback, Vec::new())?;
+        }
+        Ok(())
+    }
+
+    /// What `gui` drew for the last frame, in drawing order
+    pub fn overlay(&self) -> &[DrawCommand] {
+        &self.state.host.overlay
+    }
+
+    /// Lines the script printed since the last call
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.state.host.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InputFrame;
+
+    fn test_emulator() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&vec![0u8; 0x8000]);
+        emulator.set_audio_enabled(false);
+        emulator
+    }
+
+    #[test]
+    fn scripts_read_and_write_memory() {
+        let mut emulator = test_emulator();
+        emulator.poke(0xC000, 0x34);
+        emulator.poke(0xC001, 0x12);
+        let source = "
+            print(memory.read_u16_le(0xC000), memory.read_s8(0xC001))
+            memory.write_u8(0xC002, 0x1FF)
+            memory.write_u16_le(0xC003, 0xBEEF)
+        ";
+        let mut script = Script::load("test.lua", source, &mut emulator).unwrap();
+        assert_eq!(script.take_output(), ["4660\t18"]);
+        assert_eq!(emulator.peek(0xC002), 0xFF);
+        assert_eq!([emulator.peek(0xC003), emulator.peek(0xC004)], [0xEF, 0xBE]);
+        assert!(script.take_output().is_empty());
+    }
+
+    #[test]
+    fn frame_callbacks_draw_and_press_buttons() {
+        let mut emulator = test_emulator();
+        let source = "
+            event.onframeend(function()
+                local frame = emu.framecount()
+                gui.text(1, 2, 'frame ' .. frame)
+                gui.rect(0, 0, 10, 5, 0x80FF0000, true)
+                joypad.set({A = frame % 2 == 1, Start = true})
+            end)
+        ";
+        let mut script = Script::load("test.lua", source, &mut emulator).unwrap();
+        emulator.set_input(InputFrame::from_buttons(&[Button::B]));
+        script.apply_input(&mut emulator);
+        assert_eq!(emulator.input(), InputFrame::from_buttons(&[Button::B]));
+
+        emulator.run_frame();
+        script.after_frame(&mut emulator).unwrap();
+        let frame = emulator.frame_count();
+        assert_eq!(
+            script.overlay(),
+            [
+                DrawCommand::Text { x: 1, y: 2, text: format!("frame {}", frame), color: 0xFFFFFFFF },
+                DrawCommand::Rect { x: 0, y: 0, width: 10, height: 5, color: 0x80FF0000, fill: true },
+            ]
+        );
+        script.apply_input(&mut emulator);
+        let input = emulator.input();
+        assert_eq!(input.is_pressed(Button::A), frame % 2 == 1);
+        assert!(input.is_pressed(Button::Start) && input.is_pressed(Button::B));
+    }
+
+    #[test]
+    fn errors_report_where_they_happened() {
+        let mut emulator = test_emulator();
+        let error = Script::load("bad.lua", "local x = ", &mut emulator).err().unwrap();
+        assert_eq!(error, LuaError::Syntax("bad.lua:1: unexpected symbol near <eof>".into()));
+
+        let source = "event.onframeend(function()\n  memory.read_u8(0x10000)\nend)";
+        let mut script = Script::load("cb.lua", source, &mut emulator).unwrap();
+        assert_eq!(
+            script.after_frame(&mut emulator),
+            Err(LuaError::Runtime("cb.lua:2: bad argument #1 to 'read_u8' (address out of range)".into()))
+        );
+
+        let mut script = Script::load("loop.lua", "event.onframeend(function() while true do end end)", &mut emulator).unwrap();
+        assert_eq!(
+            script.after_frame(&mut emulator),
+            Err(LuaError::Runtime("script ran too long without returning".into()))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! The `memory`, `joypad`, `emu`, `event` and `gui` tables
+
+use super::interp::{LuaResult, State, Vm};
+use super::stdlib::{arg, bad_argument, check_integer, check_table, library, opt_integer};
+use super::value::{TableRef, Value};
+use super::DrawCommand;
+use crate::Button;
+
+/// What the bindings keep between calls into the script
+#[derive(Default)]
+pub struct Host {
+    /// Lines from `print`, until the frontend takes them
+    pub output: Vec<String>,
+    /// What `gui` drew since the last frame ended
+    pub overlay: Vec<DrawCommand>,
+    /// Functions from `event.onframeend`
+    pub frame_end: Vec<Value>,
+    /// Buttons `joypad.set` forces for the next frame, in [`Button::ALL`]
+    /// order; None leaves a button to the player
+    pub joypad: [Option<bool>; 8],
+}
+
+impl Host {
+    /// Values the host holds on the script's behalf
+    pub fn values(&self) -> Vec<Value> {
+        self.frame_end.clone()
+    }
+}
+
+/// Button names as scripts spell them, in [`Button::ALL`] order
+const BUTTON_NAMES: [&str; 8] = ["Right", "Left", "Up", "Down", "A", "B", "Select", "Start"];
+
+pub fn open(state: &mut State) {
+    let g = &state.globals;
+    let memory = library(&[
+        ("read_u8", read_u8),
+        ("read_s8", read_s8),
+        ("read_u16_le", read_u16_le),
+        ("write_u8", write_u8),
+        ("write_u16_le", write_u16_le),
+    ]);
+    g.set_str("memory", Value::Table(memory));
+    g.set_str("joypad", Value::Table(library(&[("get", joypad_get), ("set", joypad_set)])));
+    g.set_str("emu", Value::Table(library(&[("framecount", framecount), ("registers", registers)])));
+    g.set_str("event", Value::Table(library(&[("onframeend", onframeend)])));
+    let gui = library(&[("pixel", gui_pixel), ("line", gui_line), ("rect", gui_rect), ("text", gui_text)]);
+    g.set_str("gui", Value::Table(gui));
+}
+
+// memory
+
+fn address(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<u16> {
+    let addr = check_integer(vm, args, i, name)?;
+    u16::try_from(addr).map_err(|_| bad_argument(vm, i, name, "address out of range"))
+}
+
+fn read_u8(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let addr = address(vm, &args, 0, "read_u8")?;
+    Ok(vec![Value::Int(vm.emulator.peek(addr) as i64)])
+}
+
+fn read_s8(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let addr = address(vm, &args, 0, "read_s8")?;
+    Ok(vec![Value::Int(vm.emulator.peek(addr) as i8 as i64)])
+}
+
+fn read_u16_le(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let addr = address(vm, &args, 0, "read_u16_le")?;
+    let value = u16::from_le_bytes([vm.emulator.peek(addr), vm.emulator.peek(addr.wrapping_add(1))]);
+    Ok(vec![Value::Int(value as i64)])
+}
+
+/// Writes keep the low bits of the value, as the hardware would
+fn write_u8(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let addr = address(vm, &args, 0, "write_u8")?;
+    let value = check_integer(vm, &args, 1, "write_u8")?;
+    vm.emulator.poke(addr, value as u8);
+    Ok(Vec::new())
+}
+
+fn write_u16_le(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let addr = address(vm, &args, 0, "write_u16_le")?;
+    let [low, high] = (check_integer(vm, &args, 1, "write_u16_le")? as u16).to_le_bytes();
+    vm.emulator.poke(addr, low);
+    vm.emulator.poke(addr.wrapping_add(1), high);
+    Ok(Vec::new())
+}
+
+// joypad
+
+fn joypad_get(vm: &mut Vm, _: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let input = vm.emulator.input();
+    let buttons = TableRef::new();
+    for (button, name) in Button::ALL.into_iter().zip(BUTTON_NAMES) {
+        buttons.set_str(name, Value::Bool(input.is_pressed(button)));
+    }
+    Ok(vec![Value::Table(buttons)])
+}
+
+/// Buttons named in the table are forced for the next frame; the rest
+/// stay under the player's control
+fn joypad_set(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let buttons = check_table(vm, &args, 0, "set")?;
+    for (i, name) in BUTTON_NAMES.iter().enumerate() {
+        match buttons.get_str(name) {
+            Value::Nil => {}
+            pressed => vm.state.host.joypad[i] = Some(pressed.truthy()),
+        }
+    }
+    Ok(Vec::new())
+}
+
+// emu
+
+fn framecount(vm: &mut Vm, _: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![Value::Int(vm.emulator.frame_count() as i64)])
+}
+
+fn registers(vm: &mut Vm, _: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let cpu = vm.emulator.cpu_state();
+    let registers = TableRef::new();
+    let bytes = [("a", cpu.a), ("f", cpu.f), ("b", cpu.b), ("c", cpu.c), ("d", cpu.d), ("e", cpu.e), ("h", cpu.h), ("l", cpu.l)];
+    for (name, value) in bytes {
+        registers.set_str(name, Value::Int(value as i64));
+    }
+    registers.set_str("sp", Value::Int(cpu.sp as i64));
+    registers.set_str("pc", Value::Int(cpu.pc as i64));
+    registers.set_str("ime", Value::Bool(cpu.ime));
+    registers.set_str("halted", Value::Bool(cpu.halted));
+    registers.set_str("rom_bank", Value::Int(cpu.rom_bank as i64));
+    Ok(vec![Value::Table(registers)])
+}
+
+// event
+
+fn onframeend(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let callback = arg(&args, 0);
+    if !matches!(callback, Value::Function(_)) {
+        return Err(bad_argument(vm, 0, "onframeend", format!("function expected, got {}", callback.type_name())));
+    }
+    vm.state.host.frame_end.push(callback);
+    Ok(Vec::new())
+}
+
+// gui
+
+/// A coordinate, clamped so shapes far off screen can't overflow
+fn coordinate(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<i32> {
+    Ok(check_integer(vm, args, i, name)?.clamp(-0x8000, 0x7FFF) as i32)
+}
+
+/// A color, 0xRRGGBB or 0xAARRGGBB, as ARGB; white if absent
+fn color(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<u32> {
+    let color = opt_integer(vm, args, i, name, 0xFFFFFF)? as u32;
+    Ok(if color <= 0xFFFFFF { color | 0xFF000000 } else { color })
+}
+
+fn gui_pixel(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let x = coordinate(vm, &args, 0, "pixel")?;
+    let y = coordinate(vm, &args, 1, "pixel")?;
+    let color = color(vm, &args, 2, "pixel")?;
+    vm.state.host.overlay.push(DrawCommand::Pixel { x, y, color });
+    Ok(Vec::new())
+}
+
+fn gui_line(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let x1 = coordinate(vm, &args, 0, "line")?;
+    let y1 = coordinate(vm, &args, 1, "line")?;
+    let x2 = coordinate(vm, &args, 2, "line")?;
+    let y2 = coordinate(vm, &args, 3, "line")?;
+    let color = color(vm, &args, 4, "line")?;
+    vm.state.host.overlay.push(DrawCommand::Line { x1, y1, x2, y2, color });
+    Ok(Vec::new())
+}
+
+fn gui_rect(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let x = coordinate(vm, &args, 0, "rect")?;
+    let y = coordinate(vm, &args, 1, "rect")?;
+    let width = coordinate(vm, &args, 2, "rect")?;
+    let height = coordinate(vm, &args, 3, "rect")?;
+    let color = color(vm, &args, 4, "rect")?;
+    let fill = arg(&args, 5).truthy();
+    vm.state.host.overlay.push(DrawCommand::Rect { x, y, width, height, color, fill });
+    Ok(Vec::new())
+}
+
+fn gui_text(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let x = coordinate(vm, &args, 0, "text")?;
+    let y = coordinate(vm, &args, 1, "text")?;
+    let text = String::from_utf8_lossy(&vm.tostring(&arg(&args, 2))?.to_bytes()).into_owned();
+    let color = color(vm, &args, 3, "text")?;
+    vm.state.host.overlay.push(DrawCommand::Text { x, y, text, color });
+    Ok(Vec::new())
+}
@@ -0,0 +1,1084 @@
+//! Runs parsed chunks by walking their syntax trees
+
+use super::parser::{BinOp, Block, Expr, Field, FuncProto, StatKind, UnOp, Upvalue};
+use super::value::{Cell, Closure, Function, TableRef, Value};
+use super::bindings::Host;
+use super::stdlib;
+use crate::Emulator;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Lua calls allowed on the stack at once, low enough for a debug build
+/// on a 2 MiB thread stack
+const MAX_DEPTH: usize = 100;
+
+/// `__index` and `__newindex` tables followed before giving up
+const MAX_META_CHAIN: usize = 100;
+
+/// Statements and calls one entry into a script may run, so a runaway
+/// callback can't hang the emulator
+pub const STEP_BUDGET: u64 = 1_000_000;
+
+/// Longest string a script can build
+pub const MAX_STRING: usize = 16 << 20;
+
+pub type LuaResult<T> = Result<T, Throw>;
+
+/// Why a call stopped early
+#[derive(Debug)]
+pub enum Throw {
+    /// An error, which `pcall` can catch
+    Error(Value),
+    /// The step budget ran out, which nothing catches
+    Timeout,
+}
+
+/// Everything a script keeps between calls
+pub struct State {
+    pub globals: TableRef,
+    /// Shared metatable of all strings, for `s:upper()` and friends
+    pub string_meta: TableRef,
+    /// `math.random` state
+    pub rng: u64,
+    pub host: Host,
+}
+
+impl State {
+    /// Globals with the standard library loaded
+    pub fn new(host: Host) -> State {
+        let mut state = State { globals: TableRef::new(), string_meta: TableRef::new(), rng: 0, host };
+        stdlib::open(&mut state);
+        state
+    }
+}
+
+impl Drop for State {
+    /// Clear every reachable table, so reference cycles such as
+    /// `t.__index = t` don't outlive the script
+    fn drop(&mut self) {
+        let mut pending = vec![self.globals.clone(), self.string_meta.clone()];
+        for value in self.host.values() {
+            unlink(&value, &mut pending);
+        }
+        let mut seen = std::collections::HashSet::new();
+        while let Some(table) = pending.pop() {
+            if !seen.insert(table.ptr()) {
+                continue;
+            }
+            let contents = std::mem::take(&mut *table.lock());
+            let mut key = Value::Nil;
+            while let Ok(Some((k, v))) = contents.next(&key) {
+                unlink(&k, &mut pending);
+                unlink(&v, &mut pending);
+                key = k;
+            }
+            pending.extend(contents.metatable.clone());
+        }
+    }
+}
+
+/// Collect the tables a value refers to, emptying the variables of
+/// closures on the way so recursive local functions are freed too
+fn unlink(value: &Value, out: &mut Vec<TableRef>) {
+    match value {
+        Value::Table(t) => out.push(t.clone()),
+        Value::Function(Function::Lua(closure)) => {
+            for cell in &closure.upvalues {
+                let value = std::mem::take(&mut *lock(cell));
+                unlink(&value, out);
+            }
+        }
+        Value::Function(Function::Native(native)) => {
+            for value in &native.bound {
+                unlink(value, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn lock(cell: &Cell) -> std::sync::MutexGuard<'_, Value> {
+    cell.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A local variable, moved into a shared cell once a closure captures it
+enum Slot {
+    Value(Value),
+    Cell(Cell),
+}
+
+/// The locals of one running Lua function
+struct Frame {
+    slots: Vec<Slot>,
+    varargs: Vec<Value>,
+    closure: Arc<Closure>,
+}
+
+impl Frame {
+    fn get(&self, slot: usize) -> Value {
+        match &self.slots[slot] {
+            Slot::Value(value) => value.clone(),
+            Slot::Cell(cell) => lock(cell).clone(),
+        }
+    }
+
+    fn set(&mut self, slot: usize, value: Value) {
+        match &mut self.slots[slot] {
+            Slot::Value(v) => *v = value,
+            Slot::Cell(cell) => *lock(cell) = value,
+        }
+    }
+
+    /// Start a fresh variable in `slot`, leaving any closure that captured
+    /// the previous one with its own copy
+    fn declare(&mut self, slot: usize, value: Value) {
+        self.slots[slot] = Slot::Value(value);
+    }
+
+    fn capture(&mut self, slot: usize) -> Cell {
+        let slot = &mut self.slots[slot];
+        if let Slot::Value(value) = slot {
+            *slot = Slot::Cell(Arc::new(Mutex::new(std::mem::take(value))));
+        }
+        match slot {
+            Slot::Cell(cell) => cell.clone(),
+            Slot::Value(_) => unreachable!(),
+        }
+    }
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// A script running for one entry from the host
+pub struct Vm<'a> {
+    pub state: &'a mut State,
+    /// The emulator the script drives
+    pub emulator: &'a mut Emulator,
+    steps: u64,
+    depth: usize,
+    /// Where the running statement is, for error messages
+    chunk: Arc<str>,
+    line: u32,
+}
+
+/// The first of a list of results
+pub fn first(values: Vec<Value>) -> Value {
+    values.into_iter().next().unwrap_or_default()
+}
+
+/// How an expression is named in error messages, as in "(global 'x')"
+fn describe(expr: &Expr) -> String {
+    let name = |kind: &str, name: &[u8]| format!(" ({} '{}')", kind, String::from_utf8_lossy(name));
+    match expr {
+        Expr::Local(_, n) => name("local", n),
+        Expr::Upvalue(_, n) => name("upvalue", n),
+        Expr::Global(n) => name("global", n),
+        Expr::Index(_, key) => match &**key {
+            Expr::Const(Value::Str(n)) => name("field", n),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Metamethod names of the binary operators
+fn event(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "__add",
+        BinOp::Sub => "__sub",
+        BinOp::Mul => "__mul",
+        BinOp::Div => "__div",
+        BinOp::IntDiv => "__idiv",
+        BinOp::Mod => "__mod",
+        BinOp::Pow => "__pow",
+        BinOp::Concat => "__concat",
+        BinOp::BitAnd => "__band",
+        BinOp::BitOr => "__bor",
+        BinOp::BitXor => "__bxor",
+        BinOp::Shl => "__shl",
+        BinOp::Shr => "__shr",
+        BinOp::Eq | BinOp::Ne => "__eq",
+        BinOp::Lt | BinOp::Gt => "__lt",
+        BinOp::Le | BinOp::Ge => "__le",
+    }
+}
+
+fn is_bitwise(op: BinOp) -> bool {
+    matches!(op, BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr)
+}
+
+/// Arithmetic on numbers, None if an operand isn't one
+fn arith_numbers(op: BinOp, a: &Value, b: &Value) -> Option<Result<Value, &'static str>> {
+    let (a, b) = (a.to_number()?, b.to_number()?);
+    if is_bitwise(op) {
+        let (Some(x), Some(y)) = (a.to_integer(), b.to_integer()) else {
+            return Some(Err("number has no integer representation"));
+        };
+        return Some(Ok(Value::Int(match op {
+            BinOp::BitAnd => x & y,
+            BinOp::BitOr => x | y,
+            BinOp::BitXor => x ^ y,
+            BinOp::Shl => shift_left(x, y),
+            _ => shift_left(x, y.wrapping_neg()),
+        })));
+    }
+    if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+        let (x, y) = (*x, *y);
+        match op {
+            BinOp::Add => return Some(Ok(Value::Int(x.wrapping_add(y)))),
+            BinOp::Sub => return Some(Ok(Value::Int(x.wrapping_sub(y)))),
+            BinOp::Mul => return Some(Ok(Value::Int(x.wrapping_mul(y)))),
+            BinOp::IntDiv if y == 0 => return Some(Err("attempt to perform 'n//0'")),
+            BinOp::Mod if y == 0 => return Some(Err("attempt to perform 'n%0'")),
+            BinOp::IntDiv => {
+                let q = x.wrapping_div(y);
+                // Round toward negative infinity
+                let q = if x.wrapping_rem(y) != 0 && (x < 0) != (y < 0) { q - 1 } else { q };
+                return Some(Ok(Value::Int(q)));
+            }
+            BinOp::Mod => {
+                let r = x.wrapping_rem(y);
+                let r = if r != 0 && (r < 0) != (y < 0) { r + y } else { r };
+                return Some(Ok(Value::Int(r)));
+            }
+            _ => {}
+        }
+    }
+    let (x, y) = (a.to_float()?, b.to_float()?);
+    Some(Ok(Value::Num(match op {
+        BinOp::Add => x + y,
+        BinOp::Sub => x - y,
+        BinOp::Mul => x * y,
+        BinOp::Div => x / y,
+        BinOp::IntDiv => (x / y).floor(),
+        BinOp::Mod => {
+            let r = x % y;
+            if r != 0.0 && (r < 0.0) != (y < 0.0) {
+                r + y
+            } else {
+                r
+            }
+        }
+        _ => x.powf(y),
+    })))
+}
+
+/// Logical shift, left for positive counts and right for negative ones
+pub fn shift_left(x: i64, n: i64) -> i64 {
+    if n <= -64 || n >= 64 {
+        0
+    } else if n >= 0 {
+        ((x as u64) << n) as i64
+    } else {
+        ((x as u64) >> -n) as i64
+    }
+}
+
+const TWO_63: f64 = (1u64 << 63) as f64;
+
+/// `a < b` for numbers, exact even where i64 and f64 disagree
+fn number_less(a: &Value, b: &Value, or_equal: bool) -> bool {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => if or_equal { x <= y } else { x < y },
+        (Value::Num(x), Value::Num(y)) => if or_equal { x <= y } else { x < y },
+        (Value::Int(i), Value::Num(f)) => {
+            if f.is_nan() {
+                false
+            } else if *f >= TWO_63 {
+                true
+            } else if *f < -TWO_63 {
+                false
+            } else if or_equal {
+                *i <= f.floor() as i64
+            } else {
+                *i < f.ceil() as i64
+            }
+        }
+        (Value::Num(f), Value::Int(i)) => {
+            if f.is_nan() || *f >= TWO_63 {
+                false
+            } else if *f < -TWO_63 {
+                true
+            } else if or_equal {
+                (f.ceil() as i64) <= *i
+            } else {
+                (f.floor() as i64) < *i
+            }
+        }
+        _ => false,
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::Num(_))
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(state: &'a mut State, emulator: &'a mut Emulator) -> Vm<'a> {
+        Vm { state, emulator, steps: STEP_BUDGET, depth: 0, chunk: "?".into(), line: 0 }
+    }
+
+    /// An error at the running statement
+    pub fn error(&self, message: impl fmt::Display) -> Throw {
+        Throw::Error(Value::from(format!("{}:{}: {}", self.chunk, self.line, message)))
+    }
+
+    /// Charge one step against the budget
+    pub fn step(&mut self) -> LuaResult<()> {
+        if self.steps == 0 {
+            return Err(Throw::Timeout);
+        }
+        self.steps -= 1;
+        Ok(())
+    }
+
+    /// Run a parsed chunk
+    pub fn run(&mut self, proto: Arc<FuncProto>) -> LuaResult<Vec<Value>> {
+        let main = Value::Function(Function::Lua(Arc::new(Closure { proto, upvalues: Vec::new() })));
+        self.call(&main, Vec::new())
+    }
+
+    pub fn call(&mut self, f: &Value, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        self.call_named(f, args, "")
+    }
+
+    fn call_named(&mut self, f: &Value, mut args: Vec<Value>, name: &str) -> LuaResult<Vec<Value>> {
+        self.step()?;
+        let function = match f {
+            Value::Function(function) => function.clone(),
+            Value::Table(t) => match t.metamethod("__call") {
+                Value::Function(function) => {
+                    args.insert(0, f.clone());
+                    function
+                }
+                _ => return Err(self.error(format!("attempt to call a table value{}", name))),
+            },
+            _ => return Err(self.error(format!("attempt to call a {} value{}", f.type_name(), name))),
+        };
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("stack overflow"));
+        }
+        self.depth += 1;
+        let result = match function {
+            Function::Native(native) => {
+                if !native.bound.is_empty() {
+                    args.splice(0..0, native.bound.iter().cloned());
+                }
+                (native.func)(self, args)
+            }
+            Function::Lua(closure) => self.call_closure(closure, args),
+        };
+        self.depth -= 1;
+        result
+    }
+
+    fn call_closure(&mut self, closure: Arc<Closure>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        let proto = closure.proto.clone();
+        let mut slots = Vec::with_capacity(proto.slots);
+        slots.resize_with(proto.slots, || Slot::Value(Value::Nil));
+        let mut args = args.into_iter();
+        for &slot in &proto.params {
+            slots[slot] = Slot::Value(args.next().unwrap_or_default());
+        }
+        let varargs = if proto.is_vararg { args.collect() } else { Vec::new() };
+        let mut frame = Frame { slots, varargs, closure };
+        let caller = (std::mem::replace(&mut self.chunk, proto.chunk.clone()), self.line);
+        self.line = proto.line;
+        let flow = self.exec_block(&mut frame, &proto.body);
+        (self.chunk, self.line) = caller;
+        match flow? {
+            Flow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // Metamethod-aware operations, shared with the standard library
+
+    pub fn index(&mut self, object: &Value, key: &Value) -> LuaResult<Value> {
+        self.index_named(object, key, "")
+    }
+
+    fn index_named(&mut self, object: &Value, key: &Value, name: &str) -> LuaResult<Value> {
+        let mut object = object.clone();
+        let mut name = name;
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &object {
+                Value::Table(t) => {
+                    let value = t.get(key);
+                    if !value.is_nil() {
+                        return Ok(value);
+                    }
+                    match t.metamethod("__index") {
+                        Value::Nil => return Ok(Value::Nil),
+                        handler => handler,
+                    }
+                }
+                Value::Str(_) => self.state.string_meta.get_str("__index"),
+                _ => Value::Nil,
+            };
+            match handler {
+                Value::Nil => {
+                    return Err(self.error(format!("attempt to index a {} value{}", object.type_name(), name)));
+                }
+                Value::Function(_) => return Ok(first(self.call(&handler, vec![object, key.clone()])?)),
+                handler => object = handler,
+            }
+            name = "";
+        }
+        Err(self.error("'__index' chain too long; possible loop"))
+    }
+
+    pub fn set_index(&mut self, object: &Value, key: Value, value: Value) -> LuaResult<()> {
+        self.set_index_named(object, key, value, "")
+    }
+
+    fn set_index_named(&mut self, object: &Value, key: Value, value: Value, name: &str) -> LuaResult<()> {
+        let mut object = object.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &object {
+                Value::Table(t) => {
+                    let handler = if t.get(&key).is_nil() { t.metamethod("__newindex") } else { Value::Nil };
+                    if handler.is_nil() {
+                        return t.set(key, value).map_err(|e| self.error(e));
+                    }
+                    handler
+                }
+                _ => return Err(self.error(format!("attempt to index a {} value{}", object.type_name(), name))),
+            };
+            match handler {
+                Value::Function(_) => {
+                    self.call(&handler, vec![object, key, value])?;
+                    return Ok(());
+                }
+                handler => object = handler,
+            }
+        }
+        Err(self.error("'__newindex' chain too long; possible loop"))
+    }
+
+    /// `tostring`, honouring `__tostring` and `__name`
+    pub fn tostring(&mut self, value: &Value) -> LuaResult<Value> {
+        if let Value::Table(t) = value {
+            let handler = t.metamethod("__tostring");
+            if !handler.is_nil() {
+                return match first(self.call(&handler, vec![value.clone()])?) {
+                    s @ Value::Str(_) => Ok(s),
+                    _ => Err(self.error("'__tostring' must return a string")),
+                };
+            }
+            if let Value::Str(name) = t.metamethod("__name") {
+                let mut text = name.to_vec();
+                text.extend_from_slice(format!(": {:#010x}", t.ptr()).as_bytes());
+                return Ok(Value::from(text));
+            }
+        }
+        Ok(match value {
+            Value::Str(_) => value.clone(),
+            _ => Value::from(value.to_bytes()),
+        })
+    }
+
+    pub fn equals(&mut self, a: &Value, b: &Value) -> LuaResult<bool> {
+        if a.raw_equals(b) {
+            return Ok(true);
+        }
+        let (Value::Table(x), Value::Table(y)) = (a, b) else { return Ok(false) };
+        let handler = match x.metamethod("__eq") {
+            Value::Nil => y.metamethod("__eq"),
+            handler => handler,
+        };
+        if handler.is_nil() {
+            return Ok(false);
+        }
+        Ok(first(self.call(&handler, vec![a.clone(), b.clone()])?).truthy())
+    }
+
+    /// `a < b`, or `a <= b` if `or_equal`
+    pub fn less(&mut self, a: &Value, b: &Value, or_equal: bool) -> LuaResult<bool> {
+        if is_number(a) && is_number(b) {
+            return Ok(number_less(a, b, or_equal));
+        }
+        if let (Value::Str(x), Value::Str(y)) = (a, b) {
+            return Ok(if or_equal { x <= y } else { x < y });
+        }
+        let event = if or_equal { "__le" } else { "__lt" };
+        let handler = self.metamethod(a, b, event);
+        if handler.is_nil() {
+            let (x, y) = (a.type_name(), b.type_name());
+            return Err(if x == y {
+                self.error(format!("attempt to compare two {} values", x))
+            } else {
+                self.error(format!("attempt to compare {} with {}", x, y))
+            });
+        }
+        Ok(first(self.call(&handler, vec![a.clone(), b.clone()])?).truthy())
+    }
+
+    /// A binary metamethod from either operand
+    fn metamethod(&self, a: &Value, b: &Value, event: &str) -> Value {
+        let of = |value: &Value| match value {
+            Value::Table(t) => t.metamethod(event),
+            Value::Str(_) => self.state.string_meta.get_str(event),
+            _ => Value::Nil,
+        };
+        match of(a) {
+            Value::Nil => of(b),
+            handler => handler,
+        }
+    }
+
+    pub fn arith(&mut self, op: BinOp, a: &Value, b: &Value) -> LuaResult<Value> {
+        self.binary(op, a, b, None)
+    }
+
+    /// A binary operator, with the operand expressions for error messages
+    fn binary(&mut self, op: BinOp, a: &Value, b: &Value, exprs: Option<(&Expr, &Expr)>) -> LuaResult<Value> {
+        match op {
+            BinOp::Eq => return Ok(Value::Bool(self.equals(a, b)?)),
+            BinOp::Ne => return Ok(Value::Bool(!self.equals(a, b)?)),
+            BinOp::Lt => return Ok(Value::Bool(self.less(a, b, false)?)),
+            BinOp::Le => return Ok(Value::Bool(self.less(a, b, true)?)),
+            BinOp::Gt => return Ok(Value::Bool(self.less(b, a, false)?)),
+            BinOp::Ge => return Ok(Value::Bool(self.less(b, a, true)?)),
+            BinOp::Concat => {
+                if let (Some(mut x), Some(y)) = (a.concat_bytes(), b.concat_bytes()) {
+                    if x.len() + y.len() > MAX_STRING {
+                        return Err(self.error("string length overflow"));
+                    }
+                    x.extend_from_slice(&y);
+                    return Ok(Value::from(x));
+                }
+            }
+            _ => {
+                if let Some(result) = arith_numbers(op, a, b) {
+                    return result.map_err(|message| self.error(message));
+                }
+            }
+        }
+        let handler = self.metamethod(a, b, event(op));
+        if !handler.is_nil() {
+            return Ok(first(self.call(&handler, vec![a.clone(), b.clone()])?));
+        }
+        // Blame the operand that isn't a number (or string, for ..)
+        let (culprit, expr) = match op {
+            BinOp::Concat if a.concat_bytes().is_some() => (b, exprs.map(|e| e.1)),
+            BinOp::Concat => (a, exprs.map(|e| e.0)),
+            _ if is_bitwise(op) && a.to_number().is_some() && b.to_number().is_some() => {
+                return Err(self.error("number has no integer representation"));
+            }
+            _ if a.to_number().is_some() => (b, exprs.map(|e| e.1)),
+            _ => (a, exprs.map(|e| e.0)),
+        };
+        let what = match op {
+            BinOp::Concat => "concatenate",
+            _ if is_bitwise(op) => "perform bitwise operation on",
+            _ => "perform arithmetic on",
+        };
+        let name = expr.map(describe).unwrap_or_default();
+        Err(self.error(format!("attempt to {} a {} value{}", what, culprit.type_name(), name)))
+    }
+
+    /// `#value`
+    pub fn len(&mut self, value: &Value) -> LuaResult<Value> {
+        self.len_named(value, "")
+    }
+
+    fn len_named(&mut self, value: &Value, name: &str) -> LuaResult<Value> {
+        match value {
+            Value::Str(s) => Ok(Value::Int(s.len() as i64)),
+            Value::Table(t) => match t.metamethod("__len") {
+                Value::Nil => Ok(Value::Int(t.len() as i64)),
+                handler => Ok(first(self.call(&handler, vec![value.clone()])?)),
+            },
+            _ => Err(self.error(format!("attempt to get length of a {} value{}", value.type_name(), name))),
+        }
+    }
+
+    fn unary(&mut self, op: UnOp, value: &Value, name: &str) -> LuaResult<Value> {
+        match op {
+            UnOp::Not => return Ok(Value::Bool(!value.truthy())),
+            UnOp::Len => return self.len_named(value, name),
+            UnOp::Neg => match value.to_number() {
+                Some(Value::Int(i)) => return Ok(Value::Int(i.wrapping_neg())),
+                Some(Value::Num(n)) => return Ok(Value::Num(-n)),
+                _ => {}
+            },
+            UnOp::BitNot => {
+                if let Some(n) = value.to_number() {
+                    return match n.to_integer() {
+                        Some(i) => Ok(Value::Int(!i)),
+                        None => Err(self.error("number has no integer representation")),
+                    };
+                }
+            }
+        }
+        let event = if op == UnOp::Neg { "__unm" } else { "__bnot" };
+        let handler = self.metamethod(value, value, event);
+        if !handler.is_nil() {
+            return Ok(first(self.call(&handler, vec![value.clone(), value.clone()])?));
+        }
+        let what = if op == UnOp::Neg { "perform arithmetic on" } else { "perform bitwise operation on" };
+        Err(self.error(format!("attempt to {} a {} value{}", what, value.type_name(), name)))
+    }
+
+    // Statements
+
+    fn exec_block(&mut self, frame: &mut Frame, block: &Block) -> LuaResult<Flow> {
+        for stat in block {
+            self.line = stat.line;
+            self.step()?;
+            match self.exec(frame, &stat.kind)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Run a loop body, returning the flow that ends the loop, if any
+    fn loop_body(&mut self, frame: &mut Frame, body: &Block) -> LuaResult<Option<Flow>> {
+        self.step()?;
+        Ok(match self.exec_block(frame, body)? {
+            Flow::Normal => None,
+            Flow::Break => Some(Flow::Normal),
+            flow => Some(flow),
+        })
+    }
+
+    fn exec(&mut self, frame: &mut Frame, stat: &StatKind) -> LuaResult<Flow> {
+        match stat {
+            StatKind::Expr(expr) => {
+                self.eval_values(frame, expr)?;
+            }
+            StatKind::Local(slots, exprs) => {
+                let values = self.eval_list(frame, exprs, slots.len())?;
+                for (&slot, value) in slots.iter().zip(values) {
+                    frame.declare(slot, value);
+                }
+            }
+            StatKind::LocalFunction(slot, proto) => {
+                frame.declare(*slot, Value::Nil);
+                let function = self.closure(frame, proto);
+                frame.set(*slot, function);
+            }
+            StatKind::Assign(targets, exprs) => self.assign(frame, targets, exprs)?,
+            StatKind::Do(body) => return self.exec_block(frame, body),
+            StatKind::While(cond, body) => {
+                while self.eval(frame, cond)?.truthy() {
+                    if let Some(flow) = self.loop_body(frame, body)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StatKind::Repeat(body, cond) => loop {
+                if let Some(flow) = self.loop_body(frame, body)? {
+                    return Ok(flow);
+                }
+                if self.eval(frame, cond)?.truthy() {
+                    break;
+                }
+            },
+            StatKind::If(branches, otherwise) => {
+                for (cond, body) in branches {
+                    if self.eval(frame, cond)?.truthy() {
+                        return self.exec_block(frame, body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(frame, body);
+                }
+            }
+            StatKind::NumericFor { var, start, limit, step, body } => {
+                let start = self.eval(frame, start)?;
+                let limit = self.eval(frame, limit)?;
+                let step = match step {
+                    Some(step) => self.eval(frame, step)?,
+                    None => Value::Int(1),
+                };
+                return self.numeric_for(frame, *var, start, limit, step, body);
+            }
+            StatKind::GenericFor { vars, exprs, body } => {
+                let mut values = self.eval_list(frame, exprs, 3)?.into_iter();
+                let (f, state, mut control) =
+                    (values.next().unwrap_or_default(), values.next().unwrap_or_default(), values.next().unwrap_or_default());
+                loop {
+                    let results = self.call_named(&f, vec![state.clone(), control.clone()], " (for iterator)")?;
+                    let mut results = results.into_iter();
+                    control = results.next().unwrap_or_default();
+                    if control.is_nil() {
+                        break;
+                    }
+                    frame.declare(vars[0], control.clone());
+                    for &var in &vars[1..] {
+                        frame.declare(var, results.next().unwrap_or_default());
+                    }
+                    if let Some(flow) = self.loop_body(frame, body)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StatKind::Return(exprs) => return Ok(Flow::Return(self.eval_multi(frame, exprs)?)),
+            StatKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn numeric_for(
+        &mut self,
+        frame: &mut Frame,
+        var: usize,
+        start: Value,
+        limit: Value,
+        step: Value,
+        body: &Block,
+    ) -> LuaResult<Flow> {
+        for (value, what) in [(&start, "initial"), (&limit, "limit"), (&step, "step")] {
+            if !is_number(value) {
+                return Err(self.error(format!("'for' {} value must be a number", what)));
+            }
+        }
+        if let (Value::Int(start), Value::Int(step)) = (&start, &step) {
+            let (start, step) = (*start, *step);
+            if step == 0 {
+                return Err(self.error("'for' step is zero"));
+            }
+            let Some(limit) = for_limit(&limit, step) else { return Ok(Flow::Normal) };
+            if (step > 0 && start > limit) || (step < 0 && start < limit) {
+                return Ok(Flow::Normal);
+            }
+            // Count iterations up front so the counter can't overflow
+            let mut remaining = if step > 0 {
+                (limit as u64).wrapping_sub(start as u64) / step as u64
+            } else {
+                (start as u64).wrapping_sub(limit as u64) / step.unsigned_abs()
+            };
+            let mut i = start;
+            loop {
+                frame.declare(var, Value::Int(i));
+                if let Some(flow) = self.loop_body(frame, body)? {
+                    return Ok(flow);
+                }
+                if remaining == 0 {
+                    return Ok(Flow::Normal);
+                }
+                remaining -= 1;
+                i = i.wrapping_add(step);
+            }
+        }
+        let (start, limit, step) =
+            (start.to_float().unwrap_or(0.0), limit.to_float().unwrap_or(0.0), step.to_float().unwrap_or(0.0));
+        if step == 0.0 {
+            return Err(self.error("'for' step is zero"));
+        }
+        let mut i = start;
+        while (step > 0.0 && i <= limit) || (step < 0.0 && i >= limit) {
+            frame.declare(var, Value::Num(i));
+            if let Some(flow) = self.loop_body(frame, body)? {
+                return Ok(flow);
+            }
+            i += step;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn assign(&mut self, frame: &mut Frame, targets: &[Expr], exprs: &[Expr]) -> LuaResult<()> {
+        if let ([target], [expr]) = (targets, exprs) {
+            let value = self.eval(frame, expr)?;
+            return match target {
+                Expr::Index(object, key) => {
+                    let (o, k) = (self.eval(frame, object)?, self.eval(frame, key)?);
+                    self.set_index_named(&o, k, value, &describe(object))
+                }
+                _ => self.assign_to(frame, target, value),
+            };
+        }
+        // Tables and keys are evaluated before any value
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Expr::Index(object, key) => Some((self.eval(frame, object)?, self.eval(frame, key)?)),
+                _ => None,
+            });
+        }
+        let values = self.eval_list(frame, exprs, targets.len())?;
+        for ((target, place), value) in targets.iter().zip(places).zip(values) {
+            match (target, place) {
+                (Expr::Index(object, _), Some((o, k))) => self.set_index_named(&o, k, value, &describe(object))?,
+                _ => self.assign_to(frame, target, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn assign_to(&mut self, frame: &mut Frame, target: &Expr, value: Value) -> LuaResult<()> {
+        match target {
+            Expr::Local(slot, _) => frame.set(*slot, value),
+            Expr::Upvalue(index, _) => *lock(&frame.closure.upvalues[*index]) = value,
+            Expr::Global(name) => {
+                let globals = Value::Table(self.state.globals.clone());
+                self.set_index(&globals, Value::Str(name.clone()), value)?;
+            }
+            _ => unreachable!("the parser only accepts assignable targets"),
+        }
+        Ok(())
+    }
+
+    fn closure(&mut self, frame: &mut Frame, proto: &Arc<FuncProto>) -> Value {
+        let upvalues = proto
+            .upvalues
+            .iter()
+            .map(|upvalue| match *upvalue {
+                Upvalue::Local(slot) => frame.capture(slot),
+                Upvalue::Upvalue(index) => frame.closure.upvalues[index].clone(),
+            })
+            .collect();
+        Value::Function(Function::Lua(Arc::new(Closure { proto: proto.clone(), upvalues })))
+    }
+
+    // Expressions
+
+    /// Exactly `count` values from an expression list
+    fn eval_list(&mut self, frame: &mut Frame, exprs: &[Expr], count: usize) -> LuaResult<Vec<Value>> {
+        let mut values = self.eval_multi(frame, exprs)?;
+        values.resize(count, Value::Nil);
+        Ok(values)
+    }
+
+    /// An expression list, with the last expression's values expanded
+    fn eval_multi(&mut self, frame: &mut Frame, exprs: &[Expr]) -> LuaResult<Vec<Value>> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            if i + 1 == exprs.len() && expr.is_multi() {
+                values.extend(self.eval_values(frame, expr)?);
+            } else {
+                values.push(self.eval(frame, expr)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// All the values an expression produces
+    fn eval_values(&mut self, frame: &mut Frame, expr: &Expr) -> LuaResult<Vec<Value>> {
+        match expr {
+            Expr::Call(function, args) => {
+                let f = self.eval(frame, function)?;
+                let args = self.eval_multi(frame, args)?;
+                self.call_named(&f, args, &describe(function))
+            }
+            Expr::Method(object_expr, name, args) => {
+                let object = self.eval(frame, object_expr)?;
+                let f = self.index_named(&object, &Value::Str(name.clone()), &describe(object_expr))?;
+                let mut values = Vec::with_capacity(args.len() + 1);
+                values.push(object);
+                values.extend(self.eval_multi(frame, args)?);
+                let name = format!(" (method '{}')", String::from_utf8_lossy(name));
+                self.call_named(&f, values, &name)
+            }
+            Expr::Vararg => Ok(frame.varargs.clone()),
+            _ => Ok(vec![self.eval(frame, expr)?]),
+        }
+    }
+
+    fn eval(&mut self, frame: &mut Frame, expr: &Expr) -> LuaResult<Value> {
+        Ok(match expr {
+            Expr::Const(value) => value.clone(),
+            Expr::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expr::Function(proto) => self.closure(frame, proto),
+            Expr::Local(slot, _) => frame.get(*slot),
+            Expr::Upvalue(index, _) => lock(&frame.closure.upvalues[*index]).clone(),
+            Expr::Global(name) => {
+                let globals = Value::Table(self.state.globals.clone());
+                self.index(&globals, &Value::Str(name.clone()))?
+            }
+            Expr::Index(object, key) => {
+                let o = self.eval(frame, object)?;
+                let k = self.eval(frame, key)?;
+                self.index_named(&o, &k, &describe(object))?
+            }
+            Expr::Call(..) | Expr::Method(..) => first(self.eval_values(frame, expr)?),
+            Expr::Binary(op, a, b) => {
+                let x = self.eval(frame, a)?;
+                let y = self.eval(frame, b)?;
+                self.binary(*op, &x, &y, Some((a, b)))?
+            }
+            Expr::And(a, b) => {
+                let x = self.eval(frame, a)?;
+                if x.truthy() {
+                    self.eval(frame, b)?
+                } else {
+                    x
+                }
+            }
+            Expr::Or(a, b) => {
+                let x = self.eval(frame, a)?;
+                if x.truthy() {
+                    x
+                } else {
+                    self.eval(frame, b)?
+                }
+            }
+            Expr::Unary(op, operand) => {
+                let value = self.eval(frame, operand)?;
+                self.unary(*op, &value, &describe(operand))?
+            }
+            Expr::Table(fields) => self.table(frame, fields)?,
+            Expr::Paren(inner) => self.eval(frame, inner)?,
+        })
+    }
+
+    fn table(&mut self, frame: &mut Frame, fields: &[Field]) -> LuaResult<Value> {
+        let table = TableRef::new();
+        let mut n = 1;
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expr) if i + 1 == fields.len() && expr.is_multi() => {
+                    for value in self.eval_values(frame, expr)? {
+                        let _ = table.set(Value::Int(n), value);
+                        n += 1;
+                    }
+                }
+                Field::Positional(expr) => {
+                    let value = self.eval(frame, expr)?;
+                    let _ = table.set(Value::Int(n), value);
+                    n += 1;
+                }
+                Field::Named(key, value) => {
+                    let key = self.eval(frame, key)?;
+                    let value = self.eval(frame, value)?;
+                    table.set(key, value).map_err(|e| self.error(e))?;
+                }
+            }
+        }
+        Ok(Value::Table(table))
+    }
+}
+
+/// The integer limit of an integer loop, None if the loop can't run
+fn for_limit(limit: &Value, step: i64) -> Option<i64> {
+    match limit {
+        Value::Int(i) => Some(*i),
+        Value::Num(f) if f.is_nan() => None,
+        Value::Num(f) => {
+            let f = if step > 0 { f.floor() } else { f.ceil() };
+            if f >= TWO_63 {
+                (step > 0).then_some(i64::MAX)
+            } else if f < -TWO_63 {
+                (step < 0).then_some(i64::MIN)
+            } else {
+                Some(f as i64)
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::parser::parse;
+
+    fn run(source: &str) -> Result<Vec<Value>, String> {
+        let mut state = State::new(Host::default());
+        let proto = parse("test", source).map_err(|e| e.to_string())?;
+        let mut emulator = Emulator::new();
+        let mut vm = Vm::new(&mut state, &mut emulator);
+        vm.run(proto).map_err(|throw| match throw {
+            Throw::Error(value) => value.to_string(),
+            Throw::Timeout => "timeout".to_string(),
+        })
+    }
+
+    fn eval(source: &str) -> Value {
+        first(run(source).unwrap())
+    }
+
+    #[test]
+    fn arithmetic_follows_lua_rules() {
+        assert_eq!(eval("return 7 // 2, 7 % -3"), Value::Int(3));
+        assert_eq!(run("return 7 // 2, 7 % -3, -7 // 2, 7.5 % 2").unwrap(), [3.into(), (-2).into(), (-4).into(), 1.5.into()]);
+        assert_eq!(eval("return 1 / 2"), Value::Num(0.5));
+        assert_eq!(eval("return math.maxinteger + 1 == math.mininteger"), Value::Bool(true));
+        assert_eq!(eval("return '10' + 5"), Value::Int(15));
+        assert_eq!(eval("return 1 << 63 >> 63"), Value::Int(1));
+        assert_eq!(eval("return 2^10"), Value::Num(1024.0));
+        assert_eq!(eval("return 1 < 1.5 and 2 <= 2.0 and 'a' < 'b'"), Value::Bool(true));
+        assert_eq!(eval("return 1 .. 2"), Value::from("12"));
+        assert_eq!(run("return 1 // 0").unwrap_err(), "test:1: attempt to perform 'n//0'");
+    }
+
+    #[test]
+    fn closures_capture_fresh_variables_per_iteration() {
+        let source = "
+            local fs = {}
+            for i = 1, 3 do fs[i] = function() return i end end
+            local function counter()
+                local n = 0
+                return function() n = n + 1; return n end
+            end
+            local c = counter()
+            c(); c()
+            return fs[1]() + fs[2]() * 10 + fs[3]() * 100, c()
+        ";
+        assert_eq!(run(source).unwrap(), [Value::Int(321), Value::Int(3)]);
+    }
+
+    #[test]
+    fn metatables_drive_indexing_calls_and_operators() {
+        let source = "
+            local Point = {}
+            Point.__index = Point
+            Point.__add = function(a, b) return setmetatable({x = a.x + b.x}, Point) end
+            Point.__call = function(self, k) return self.x * k end
+            function Point.new(x) return setmetatable({x = x}, Point) end
+            function Point:double() return self.x * 2 end
+            local p = Point.new(2) + Point.new(3)
+            return p:double(), p(10), tostring(nil)
+        ";
+        assert_eq!(run(source).unwrap(), [Value::Int(10), Value::Int(50), Value::from("nil")]);
+    }
+
+    #[test]
+    fn loops_and_varargs() {
+        let source = "
+            local function sum(...)
+                local total = 0
+                for _, v in ipairs({...}) do total = total + v end
+                return total, select('#', ...)
+            end
+            local n = 0
+            for i = 10, 1, -3 do n = n + i end
+            local w = 0
+            while true do w = w + 1; if w == 5 then break end end
+            repeat local r = w; w = w - 1 until r <= 3
+            return sum(1, 2, 3), n, w
+        ";
+        assert_eq!(run(source).unwrap(), [Value::Int(6), Value::Int(22), Value::Int(2)]);
+    }
+
+    #[test]
+    fn errors_name_the_culprit_and_can_be_caught() {
+        assert_eq!(run("local t = nil\nreturn t.x").unwrap_err(), "test:2: attempt to index a nil value (local 't')");
+        assert_eq!(run("missing()").unwrap_err(), "test:1: attempt to call a nil value (global 'missing')");
+        assert_eq!(run("local t = {}\nreturn t.a.b").unwrap_err(), "test:2: attempt to index a nil value (field 'a')");
+        assert_eq!(run("return {} .. 'x'").unwrap_err(), "test:1: attempt to concatenate a table value");
+        assert_eq!(run("return 1 < 'x'").unwrap_err(), "test:1: attempt to compare number with string");
+        let source = "
+            local ok, err = pcall(error, {code = 7})
+            local ok2, err2 = pcall(function() error('boom') end)
+            return ok, err.code, ok2, err2
+        ";
+        assert_eq!(run(source).unwrap(), [false.into(), 7.into(), false.into(), "test:3: boom".into()]);
+    }
+
+    #[test]
+    fn runaway_scripts_are_stopped() {
+        assert_eq!(run("while true do end").unwrap_err(), "timeout");
+        assert_eq!(run("local function f() return f() + 1 end\nreturn f()").unwrap_err(), "test:1: stack overflow");
+        // The budget can't be caught
+        assert_eq!(run("pcall(function() while true do end end)").unwrap_err(), "timeout");
+    }
+}
@@ -0,0 +1,483 @@
+//! Lua source to tokens
+
+use super::value::{parse_number, Value};
+use super::LuaError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    Number(Value),
+    Str(Vec<u8>),
+    // Keywords
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    Goto,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    // Symbols
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    DoubleSlash,
+    Percent,
+    Caret,
+    Hash,
+    Ampersand,
+    Tilde,
+    Pipe,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    LessEqual,
+    GreaterEqual,
+    Less,
+    Greater,
+    Assign,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    DoubleColon,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Ellipsis,
+    Eof,
+}
+
+impl Token {
+    /// How the token reads in error messages
+    pub fn describe(&self) -> String {
+        let text = match self {
+            Token::Eof => return "<eof>".to_string(),
+            Token::Name(name) => name,
+            Token::Number(_) => "<number>",
+            Token::Str(_) => "<string>",
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::DoubleSlash => "//",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Hash => "#",
+            Token::Ampersand => "&",
+            Token::Tilde => "~",
+            Token::Pipe => "|",
+            Token::ShiftLeft => "<<",
+            Token::ShiftRight => ">>",
+            Token::Equal => "==",
+            Token::NotEqual => "~=",
+            Token::LessEqual => "<=",
+            Token::GreaterEqual => ">=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::Assign => "=",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::DoubleColon => "::",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Ellipsis => "...",
+        };
+        format!("'{}'", text)
+    }
+}
+
+fn keyword(name: &str) -> Option<Token> {
+    Some(match name {
+        "and" => Token::And,
+        "break" => Token::Break,
+        "do" => Token::Do,
+        "else" => Token::Else,
+        "elseif" => Token::Elseif,
+        "end" => Token::End,
+        "false" => Token::False,
+        "for" => Token::For,
+        "function" => Token::Function,
+        "goto" => Token::Goto,
+        "if" => Token::If,
+        "in" => Token::In,
+        "local" => Token::Local,
+        "nil" => Token::Nil,
+        "not" => Token::Not,
+        "or" => Token::Or,
+        "repeat" => Token::Repeat,
+        "return" => Token::Return,
+        "then" => Token::Then,
+        "true" => Token::True,
+        "until" => Token::Until,
+        "while" => Token::While,
+        _ => return None,
+    })
+}
+
+/// Split `source` into tokens, each with the line it starts on
+pub fn tokenize(chunk: &str, source: &str) -> Result<Vec<(Token, u32)>, LuaError> {
+    Lexer { chunk, src: source.as_bytes(), pos: 0, line: 1 }.run()
+}
+
+struct Lexer<'a> {
+    chunk: &'a str,
+    src: &'a [u8],
+    pos: usize,
+    line: u32,
+}
+
+impl Lexer<'_> {
+    fn error(&self, message: &str) -> LuaError {
+        LuaError::Syntax(format!("{}:{}: {}", self.chunk, self.line, message))
+    }
+
+    fn peek(&self) -> u8 {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> u8 {
+        self.src.get(self.pos + offset).copied().unwrap_or(0)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.src.len()
+    }
+
+    fn run(mut self) -> Result<Vec<(Token, u32)>, LuaError> {
+        let mut tokens = Vec::new();
+        // A first line starting with # is a shebang
+        if self.src.starts_with(b"#") {
+            while !self.at_end() && self.peek() != b'\n' {
+                self.pos += 1;
+            }
+        }
+        loop {
+            self.skip_space_and_comments()?;
+            let line = self.line;
+            if self.at_end() {
+                tokens.push((Token::Eof, line));
+                return Ok(tokens);
+            }
+            let token = self.token()?;
+            tokens.push((token, line));
+        }
+    }
+
+    fn skip_space_and_comments(&mut self) -> Result<(), LuaError> {
+        while !self.at_end() {
+            match self.peek() {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' | 0x0B | 0x0C => self.pos += 1,
+                b'-' if self.peek_at(1) == b'-' => {
+                    self.pos += 2;
+                    if self.peek() == b'[' {
+                        if let Some(level) = self.long_bracket_level() {
+                            self.long_string(level)?;
+                            continue;
+                        }
+                    }
+                    while !self.at_end() && self.peek() != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// The level of a `[==[` opening at the current position, if it is one
+    fn long_bracket_level(&self) -> Option<usize> {
+        let mut level = 0;
+        while self.peek_at(1 + level) == b'=' {
+            level += 1;
+        }
+        (self.peek_at(1 + level) == b'[').then_some(level)
+    }
+
+    /// Read a long string or comment whose opening bracket starts here
+    fn long_string(&mut self, level: usize) -> Result<Vec<u8>, LuaError> {
+        self.pos += level + 2;
+        // A newline right after the opening bracket is skipped
+        if self.peek() == b'\r' {
+            self.pos += 1;
+        }
+        if self.peek() == b'\n' {
+            self.line += 1;
+            self.pos += 1;
+        }
+        let mut text = Vec::new();
+        loop {
+            if self.at_end() {
+                return Err(self.error("unfinished long string or comment"));
+            }
+            let c = self.peek();
+            if c == b']' && (1..=level).all(|i| self.peek_at(i) == b'=') && self.peek_at(level + 1) == b']' {
+                self.pos += level + 2;
+                return Ok(text);
+            }
+            if c == b'\n' {
+                self.line += 1;
+            }
+            text.push(c);
+            self.pos += 1;
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, LuaError> {
+        let c = self.peek();
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let start = self.pos;
+            while self.peek().is_ascii_alphanumeric() || self.peek() == b'_' {
+                self.pos += 1;
+            }
+            let name = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+            return Ok(keyword(&name).unwrap_or(Token::Name(name)));
+        }
+        if c.is_ascii_digit() || (c == b'.' && self.peek_at(1).is_ascii_digit()) {
+            return self.number();
+        }
+        if c == b'"' || c == b'\'' {
+            return self.string(c);
+        }
+        if c == b'[' {
+            if let Some(level) = self.long_bracket_level() {
+                return Ok(Token::Str(self.long_string(level)?));
+            }
+        }
+
+        let (token, len) = match (c, self.peek_at(1), self.peek_at(2)) {
+            (b'.', b'.', b'.') => (Token::Ellipsis, 3),
+            (b'.', b'.', _) => (Token::Concat, 2),
+            (b'/', b'/', _) => (Token::DoubleSlash, 2),
+            (b'<', b'<', _) => (Token::ShiftLeft, 2),
+            (b'>', b'>', _) => (Token::ShiftRight, 2),
+            (b'=', b'=', _) => (Token::Equal, 2),
+            (b'~', b'=', _) => (Token::NotEqual, 2),
+            (b'<', b'=', _) => (Token::LessEqual, 2),
+            (b'>', b'=', _) => (Token::GreaterEqual, 2),
+            (b':', b':', _) => (Token::DoubleColon, 2),
+            (b'+', _, _) => (Token::Plus, 1),
+            (b'-', _, _) => (Token::Minus, 1),
+            (b'*', _, _) => (Token::Star, 1),
+            (b'/', _, _) => (Token::Slash, 1),
+            (b'%', _, _) => (Token::Percent, 1),
+            (b'^', _, _) => (Token::Caret, 1),
+            (b'#', _, _) => (Token::Hash, 1),
+            (b'&', _, _) => (Token::Ampersand, 1),
+            (b'~', _, _) => (Token::Tilde, 1),
+            (b'|', _, _) => (Token::Pipe, 1),
+            (b'<', _, _) => (Token::Less, 1),
+            (b'>', _, _) => (Token::Greater, 1),
+            (b'=', _, _) => (Token::Assign, 1),
+            (b'(', _, _) => (Token::LeftParen, 1),
+            (b')', _, _) => (Token::RightParen, 1),
+            (b'{', _, _) => (Token::LeftBrace, 1),
+            (b'}', _, _) => (Token::RightBrace, 1),
+            (b'[', _, _) => (Token::LeftBracket, 1),
+            (b']', _, _) => (Token::RightBracket, 1),
+            (b';', _, _) => (Token::Semicolon, 1),
+            (b':', _, _) => (Token::Colon, 1),
+            (b',', _, _) => (Token::Comma, 1),
+            (b'.', _, _) => (Token::Dot, 1),
+            _ => return Err(self.error(&format!("unexpected symbol '{}'", c as char))),
+        };
+        self.pos += len;
+        Ok(token)
+    }
+
+    fn number(&mut self) -> Result<Token, LuaError> {
+        let start = self.pos;
+        let hex = self.peek() == b'0' && matches!(self.peek_at(1), b'x' | b'X');
+        if hex {
+            self.pos += 2;
+        }
+        loop {
+            let c = self.peek();
+            let exponent = if hex { b"pP" } else { b"eE" };
+            if exponent.contains(&c) && matches!(self.peek_at(1), b'+' | b'-') {
+                self.pos += 2;
+            } else if c.is_ascii_alphanumeric() || c == b'.' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let text = String::from_utf8_lossy(&self.src[start..self.pos]);
+        match parse_number(&text) {
+            Some(value) => Ok(Token::Number(value)),
+            None => Err(self.error(&format!("malformed number near '{}'", text))),
+        }
+    }
+
+    fn string(&mut self, quote: u8) -> Result<Token, LuaError> {
+        self.pos += 1;
+        let mut text = Vec::new();
+        loop {
+            if self.at_end() {
+                return Err(self.error("unfinished string"));
+            }
+            let c = self.peek();
+            self.pos += 1;
+            match c {
+                c if c == quote => return Ok(Token::Str(text)),
+                b'\n' => return Err(self.error("unfinished string")),
+                b'\\' => self.escape(&mut text)?,
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self, text: &mut Vec<u8>) -> Result<(), LuaError> {
+        let c = self.peek();
+        self.pos += 1;
+        match c {
+            b'n' => text.push(b'\n'),
+            b't' => text.push(b'\t'),
+            b'r' => text.push(b'\r'),
+            b'a' => text.push(0x07),
+            b'b' => text.push(0x08),
+            b'f' => text.push(0x0C),
+            b'v' => text.push(0x0B),
+            b'\\' | b'"' | b'\'' => text.push(c),
+            b'\n' => {
+                self.line += 1;
+                text.push(b'\n');
+            }
+            b'x' => {
+                let digits = [self.peek(), self.peek_at(1)];
+                let value = std::str::from_utf8(&digits)
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok())
+                    .ok_or_else(|| self.error("hexadecimal digit expected"))?;
+                self.pos += 2;
+                text.push(value);
+            }
+            b'z' => {
+                while self.peek().is_ascii_whitespace() {
+                    if self.peek() == b'\n' {
+                        self.line += 1;
+                    }
+                    self.pos += 1;
+                }
+            }
+            b'u' => {
+                if self.peek() != b'{' {
+                    return Err(self.error("missing '{' in \\u{xxxx}"));
+                }
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_ascii_hexdigit() {
+                    self.pos += 1;
+                }
+                let code = std::str::from_utf8(&self.src[start..self.pos])
+                    .ok()
+                    .and_then(|s| u32::from_str_radix(s, 16).ok())
+                    .and_then(char::from_u32);
+                if self.peek() != b'}' {
+                    return Err(self.error("missing '}' in \\u{xxxx}"));
+                }
+                self.pos += 1;
+                let code = code.ok_or_else(|| self.error("UTF-8 value too large"))?;
+                text.extend_from_slice(code.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            b'0'..=b'9' => {
+                let mut value = (c - b'0') as u32;
+                for _ in 0..2 {
+                    if !self.peek().is_ascii_digit() {
+                        break;
+                    }
+                    value = value * 10 + (self.peek() - b'0') as u32;
+                    self.pos += 1;
+                }
+                let byte = u8::try_from(value).map_err(|_| self.error("decimal escape too large"))?;
+                text.push(byte);
+            }
+            _ => return Err(self.error("invalid escape sequence")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize("test", source).unwrap().into_iter().map(|(t, _)| t).collect()
+    }
+
+    #[test]
+    fn reads_numbers_strings_and_comments() {
+        assert_eq!(
+            tokens("x = 0x1F + 2.5e1 -- note\n..'a\\tb\\65' [==[long]]]==]"),
+            [
+                Token::Name("x".into()),
+                Token::Assign,
+                Token::Number(Value::Int(31)),
+                Token::Plus,
+                Token::Number(Value::Num(25.0)),
+                Token::Concat,
+                Token::Str(b"a\tbA".to_vec()),
+                Token::Str(b"long]]".to_vec()),
+                Token::Eof,
+            ]
+        );
+        let lines: Vec<u32> = tokenize("test", "a\n--[[ two\nlines ]] b").unwrap().iter().map(|t| t.1).collect();
+        assert_eq!(lines, [1, 3, 3]);
+        assert!(tokenize("test", "'open").is_err());
+    }
+}
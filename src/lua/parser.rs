@@ -0,0 +1,870 @@
+//! Tokens to a syntax tree, with every variable resolved to a local slot,
+//! a captured upvalue or a global as it's parsed
+
+use super::lexer::{tokenize, Token};
+use super::value::{LuaStr, Value};
+use super::LuaError;
+use std::sync::Arc;
+
+/// Nesting allowed in expressions and blocks, so deeply nested source
+/// can't overflow the parser's stack
+const MAX_DEPTH: usize = 200;
+
+/// A parsed function body
+#[derive(Debug)]
+pub struct FuncProto {
+    /// Chunk name, for error messages
+    pub chunk: Arc<str>,
+    pub line: u32,
+    /// Slots the parameters are stored in
+    pub params: Vec<usize>,
+    pub is_vararg: bool,
+    /// Local variable slots used by the body
+    pub slots: usize,
+    /// Where each upvalue comes from in the enclosing function
+    pub upvalues: Vec<Upvalue>,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Upvalue {
+    /// A local of the enclosing function
+    Local(usize),
+    /// One of the enclosing function's own upvalues
+    Upvalue(usize),
+}
+
+pub type Block = Vec<Stat>;
+
+#[derive(Debug)]
+pub struct Stat {
+    pub kind: StatKind,
+    pub line: u32,
+}
+
+#[derive(Debug)]
+pub enum StatKind {
+    Expr(Expr),
+    Local(Vec<usize>, Vec<Expr>),
+    LocalFunction(usize, Arc<FuncProto>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Do(Block),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    NumericFor { var: usize, start: Expr, limit: Expr, step: Option<Expr>, body: Block },
+    GenericFor { vars: Vec<usize>, exprs: Vec<Expr>, body: Block },
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    IntDiv,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+    BitNot,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Const(Value),
+    Vararg,
+    Function(Arc<FuncProto>),
+    Local(usize, LuaStr),
+    Upvalue(usize, LuaStr),
+    Global(LuaStr),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, LuaStr, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Table(Vec<Field>),
+    /// Parentheses, which cut a call or `...` down to one value
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    /// Whether the expression can produce several values
+    pub fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::Vararg)
+    }
+}
+
+#[derive(Debug)]
+pub enum Field {
+    Positional(Expr),
+    Named(Expr, Expr),
+}
+
+/// Parse a whole chunk into the function that runs it
+pub fn parse(chunk: &str, source: &str) -> Result<Arc<FuncProto>, LuaError> {
+    let tokens = tokenize(chunk, source)?;
+    let mut parser = Parser { chunk: chunk.into(), tokens, pos: 0, functions: Vec::new(), depth: 0 };
+    parser.functions.push(FunctionScope { is_vararg: true, ..Default::default() });
+    parser.open_scope();
+    let body = parser.block()?;
+    if parser.peek() != &Token::Eof {
+        return Err(parser.unexpected());
+    }
+    let scope = parser.functions.pop().unwrap_or_default();
+    Ok(Arc::new(FuncProto {
+        chunk: parser.chunk,
+        line: 0,
+        params: Vec::new(),
+        is_vararg: true,
+        slots: scope.slots,
+        upvalues: scope.upvalues,
+        body,
+    }))
+}
+
+/// Names visible in one function, innermost block last
+#[derive(Default)]
+struct FunctionScope {
+    blocks: Vec<Vec<(LuaStr, usize)>>,
+    slots: usize,
+    upvalues: Vec<Upvalue>,
+    upvalue_names: Vec<LuaStr>,
+    is_vararg: bool,
+    loops: usize,
+}
+
+struct Parser {
+    chunk: Arc<str>,
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+    functions: Vec<FunctionScope>,
+    depth: usize,
+}
+
+/// How tightly binary operators bind, as (left, right) priorities
+fn binary_op(token: &Token) -> Option<(BinOp, u8, u8)> {
+    Some(match token {
+        Token::Or => return None,
+        Token::And => return None,
+        Token::Equal => (BinOp::Eq, 3, 3),
+        Token::NotEqual => (BinOp::Ne, 3, 3),
+        Token::Less => (BinOp::Lt, 3, 3),
+        Token::LessEqual => (BinOp::Le, 3, 3),
+        Token::Greater => (BinOp::Gt, 3, 3),
+        Token::GreaterEqual => (BinOp::Ge, 3, 3),
+        Token::Pipe => (BinOp::BitOr, 4, 4),
+        Token::Tilde => (BinOp::BitXor, 5, 5),
+        Token::Ampersand => (BinOp::BitAnd, 6, 6),
+        Token::ShiftLeft => (BinOp::Shl, 7, 7),
+        Token::ShiftRight => (BinOp::Shr, 7, 7),
+        Token::Concat => (BinOp::Concat, 9, 8),
+        Token::Plus => (BinOp::Add, 10, 10),
+        Token::Minus => (BinOp::Sub, 10, 10),
+        Token::Star => (BinOp::Mul, 11, 11),
+        Token::Slash => (BinOp::Div, 11, 11),
+        Token::DoubleSlash => (BinOp::IntDiv, 11, 11),
+        Token::Percent => (BinOp::Mod, 11, 11),
+        Token::Caret => (BinOp::Pow, 14, 13),
+        _ => return None,
+    })
+}
+
+/// Priority of unary operators, between `%` and `^`
+const UNARY_PRIORITY: u8 = 12;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        &self.tokens[(self.pos + offset).min(self.tokens.len() - 1)].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, message: &str) -> LuaError {
+        LuaError::Syntax(format!("{}:{}: {}", self.chunk, self.line(), message))
+    }
+
+    fn unexpected(&self) -> LuaError {
+        self.error(&format!("unexpected symbol near {}", self.peek().describe()))
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), LuaError> {
+        if self.check(&token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("{} expected near {}", what, self.peek().describe())))
+        }
+    }
+
+    /// Expect the token closing one opened on `line`
+    fn expect_closing(&mut self, token: Token, opening: &str, line: u32) -> Result<(), LuaError> {
+        if self.check(&token) {
+            Ok(())
+        } else if line == self.line() {
+            Err(self.error(&format!("{} expected near {}", token.describe(), self.peek().describe())))
+        } else {
+            Err(self.error(&format!(
+                "{} expected (to close '{}' at line {}) near {}",
+                token.describe(),
+                opening,
+                line,
+                self.peek().describe()
+            )))
+        }
+    }
+
+    fn name(&mut self) -> Result<LuaStr, LuaError> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Ok(name.as_bytes().into())
+            }
+            _ => Err(self.error(&format!("<name> expected near {}", self.peek().describe()))),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), LuaError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("chunk has too many syntax levels"));
+        }
+        Ok(())
+    }
+
+    // Scopes
+
+    fn function(&mut self) -> &mut FunctionScope {
+        self.functions.last_mut().expect("inside a function")
+    }
+
+    fn open_scope(&mut self) {
+        self.function().blocks.push(Vec::new());
+    }
+
+    fn close_scope(&mut self) {
+        self.function().blocks.pop();
+    }
+
+    /// Give a new local its slot; it becomes visible once `activate`d
+    fn new_local(&mut self) -> usize {
+        let function = self.function();
+        function.slots += 1;
+        function.slots - 1
+    }
+
+    fn activate(&mut self, name: LuaStr, slot: usize) {
+        if let Some(block) = self.function().blocks.last_mut() {
+            block.push((name, slot));
+        }
+    }
+
+    fn resolve(&mut self, name: LuaStr) -> Expr {
+        match self.resolve_in(self.functions.len() - 1, &name) {
+            Some(Resolved::Local(slot)) => Expr::Local(slot, name),
+            Some(Resolved::Upvalue(index)) => Expr::Upvalue(index, name),
+            None => Expr::Global(name),
+        }
+    }
+
+    fn resolve_in(&mut self, level: usize, name: &LuaStr) -> Option<Resolved> {
+        let function = &self.functions[level];
+        for block in function.blocks.iter().rev() {
+            if let Some(&(_, slot)) = block.iter().rev().find(|(n, _)| n == name) {
+                return Some(Resolved::Local(slot));
+            }
+        }
+        if let Some(index) = function.upvalue_names.iter().position(|n| n == name) {
+            return Some(Resolved::Upvalue(index));
+        }
+        if level == 0 {
+            return None;
+        }
+        let upvalue = match self.resolve_in(level - 1, name)? {
+            Resolved::Local(slot) => Upvalue::Local(slot),
+            Resolved::Upvalue(index) => Upvalue::Upvalue(index),
+        };
+        let function = &mut self.functions[level];
+        function.upvalues.push(upvalue);
+        function.upvalue_names.push(name.clone());
+        Some(Resolved::Upvalue(function.upvalues.len() - 1))
+    }
+
+    // Statements
+
+    fn block_ends(&self) -> bool {
+        matches!(self.peek(), Token::Eof | Token::End | Token::Else | Token::Elseif | Token::Until)
+    }
+
+    /// Statements up to the end of a block, in the current scope
+    fn block(&mut self) -> Result<Block, LuaError> {
+        let mut block = Vec::new();
+        while !self.block_ends() {
+            if self.peek() == &Token::Return {
+                block.push(self.return_stat()?);
+                break;
+            }
+            if let Some(stat) = self.statement()? {
+                block.push(stat);
+            }
+        }
+        Ok(block)
+    }
+
+    /// A block in a scope of its own
+    fn scoped_block(&mut self) -> Result<Block, LuaError> {
+        self.open_scope();
+        let block = self.block();
+        self.close_scope();
+        block
+    }
+
+    fn return_stat(&mut self) -> Result<Stat, LuaError> {
+        let line = self.line();
+        self.advance();
+        let exprs = if self.block_ends() || self.peek() == &Token::Semicolon {
+            Vec::new()
+        } else {
+            self.expr_list()?
+        };
+        self.check(&Token::Semicolon);
+        if !self.block_ends() {
+            return Err(self.error(&format!("'<eof>' expected near {}", self.peek().describe())));
+        }
+        Ok(Stat { kind: StatKind::Return(exprs), line })
+    }
+
+    fn statement(&mut self) -> Result<Option<Stat>, LuaError> {
+        let line = self.line();
+        self.enter()?;
+        let kind = match self.peek() {
+            Token::Semicolon => {
+                self.advance();
+                self.depth -= 1;
+                return Ok(None);
+            }
+            Token::If => self.if_stat(line)?,
+            Token::While => {
+                self.advance();
+                let cond = self.expr()?;
+                self.expect(Token::Do, "'do'")?;
+                let body = self.loop_body()?;
+                self.expect_closing(Token::End, "while", line)?;
+                StatKind::While(cond, body)
+            }
+            Token::Do => {
+                self.advance();
+                let body = self.scoped_block()?;
+                self.expect_closing(Token::End, "do", line)?;
+                StatKind::Do(body)
+            }
+            Token::For => self.for_stat(line)?,
+            Token::Repeat => {
+                self.advance();
+                // The condition can see the body's locals
+                self.open_scope();
+                self.function().loops += 1;
+                let body = self.block();
+                self.function().loops -= 1;
+                let body = body?;
+                self.expect_closing(Token::Until, "repeat", line)?;
+                let cond = self.expr();
+                self.close_scope();
+                StatKind::Repeat(body, cond?)
+            }
+            Token::Function => self.function_stat()?,
+            Token::Local => {
+                self.advance();
+                if self.check(&Token::Function) {
+                    let name = self.name()?;
+                    let slot = self.new_local();
+                    // Visible inside its own body, for recursion
+                    self.activate(name, slot);
+                    let proto = self.function_body(line, false)?;
+                    StatKind::LocalFunction(slot, proto)
+                } else {
+                    self.local_stat()?
+                }
+            }
+            Token::DoubleColon | Token::Goto => return Err(self.error("goto and labels are not supported")),
+            Token::Break => {
+                self.advance();
+                if self.function().loops == 0 {
+                    return Err(self.error("break outside a loop"));
+                }
+                StatKind::Break
+            }
+            _ => self.expr_stat()?,
+        };
+        self.depth -= 1;
+        Ok(Some(Stat { kind, line }))
+    }
+
+    fn loop_body(&mut self) -> Result<Block, LuaError> {
+        self.function().loops += 1;
+        let body = self.scoped_block();
+        self.function().loops -= 1;
+        body
+    }
+
+    fn if_stat(&mut self, line: u32) -> Result<StatKind, LuaError> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        self.advance();
+        loop {
+            let cond = self.expr()?;
+            self.expect(Token::Then, "'then'")?;
+            branches.push((cond, self.scoped_block()?));
+            match self.peek() {
+                Token::Elseif => {
+                    self.advance();
+                }
+                Token::Else => {
+                    self.advance();
+                    otherwise = Some(self.scoped_block()?);
+                    self.expect_closing(Token::End, "if", line)?;
+                    break;
+                }
+                _ => {
+                    self.expect_closing(Token::End, "if", line)?;
+                    break;
+                }
+            }
+        }
+        Ok(StatKind::If(branches, otherwise))
+    }
+
+    fn for_stat(&mut self, line: u32) -> Result<StatKind, LuaError> {
+        self.advance();
+        let first = self.name()?;
+        if self.check(&Token::Assign) {
+            let start = self.expr()?;
+            self.expect(Token::Comma, "','")?;
+            let limit = self.expr()?;
+            let step = if self.check(&Token::Comma) { Some(self.expr()?) } else { None };
+            self.expect(Token::Do, "'do'")?;
+            self.open_scope();
+            let var = self.new_local();
+            self.activate(first, var);
+            let body = self.loop_body();
+            self.close_scope();
+            self.expect_closing(Token::End, "for", line)?;
+            return Ok(StatKind::NumericFor { var, start, limit, step, body: body? });
+        }
+        let mut names = vec![first];
+        while self.check(&Token::Comma) {
+            names.push(self.name()?);
+        }
+        self.expect(Token::In, "'=' or 'in'")?;
+        let exprs = self.expr_list()?;
+        self.expect(Token::Do, "'do'")?;
+        self.open_scope();
+        let vars: Vec<usize> = names
+            .into_iter()
+            .map(|name| {
+                let slot = self.new_local();
+                self.activate(name, slot);
+                slot
+            })
+            .collect();
+        let body = self.loop_body();
+        self.close_scope();
+        self.expect_closing(Token::End, "for", line)?;
+        Ok(StatKind::GenericFor { vars, exprs, body: body? })
+    }
+
+    fn function_stat(&mut self) -> Result<StatKind, LuaError> {
+        let line = self.line();
+        self.advance();
+        let name = self.name()?;
+        let mut target = self.resolve(name);
+        let mut is_method = false;
+        loop {
+            match self.peek() {
+                Token::Dot => {
+                    self.advance();
+                    let key = self.name()?;
+                    target = Expr::Index(Box::new(target), Box::new(Expr::Const(Value::Str(key))));
+                }
+                Token::Colon => {
+                    self.advance();
+                    let key = self.name()?;
+                    target = Expr::Index(Box::new(target), Box::new(Expr::Const(Value::Str(key))));
+                    is_method = true;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        let proto = self.function_body(line, is_method)?;
+        Ok(StatKind::Assign(vec![target], vec![Expr::Function(proto)]))
+    }
+
+    fn local_stat(&mut self) -> Result<StatKind, LuaError> {
+        let mut names = Vec::new();
+        loop {
+            names.push(self.name()?);
+            if self.check(&Token::Less) {
+                match self.name()?.as_ref() {
+                    b"const" => {}
+                    b"close" => return Err(self.error("to-be-closed variables are not supported")),
+                    other => {
+                        let attribute = String::from_utf8_lossy(other).into_owned();
+                        return Err(self.error(&format!("unknown attribute '{}'", attribute)));
+                    }
+                }
+                self.expect(Token::Greater, "'>'")?;
+            }
+            if !self.check(&Token::Comma) {
+                break;
+            }
+        }
+        let exprs = if self.check(&Token::Assign) { self.expr_list()? } else { Vec::new() };
+        // The new locals come into scope after their initializers
+        let slots = names
+            .into_iter()
+            .map(|name| {
+                let slot = self.new_local();
+                self.activate(name, slot);
+                slot
+            })
+            .collect();
+        Ok(StatKind::Local(slots, exprs))
+    }
+
+    fn expr_stat(&mut self) -> Result<StatKind, LuaError> {
+        let first = self.suffixed_expr()?;
+        if matches!(self.peek(), Token::Assign | Token::Comma) {
+            let mut targets = vec![first];
+            while self.check(&Token::Comma) {
+                targets.push(self.suffixed_expr()?);
+            }
+            if targets.iter().any(|t| !matches!(t, Expr::Local(..) | Expr::Upvalue(..) | Expr::Global(_) | Expr::Index(..))) {
+                return Err(self.error("syntax error near '='"));
+            }
+            self.expect(Token::Assign, "'='")?;
+            let exprs = self.expr_list()?;
+            return Ok(StatKind::Assign(targets, exprs));
+        }
+        if !matches!(first, Expr::Call(..) | Expr::Method(..)) {
+            return Err(self.error(&format!("syntax error near {}", self.peek().describe())));
+        }
+        Ok(StatKind::Expr(first))
+    }
+
+    /// Parameters and body of a function, `self` first for methods
+    fn function_body(&mut self, line: u32, is_method: bool) -> Result<Arc<FuncProto>, LuaError> {
+        self.functions.push(FunctionScope::default());
+        self.open_scope();
+        let result = self.function_contents(line, is_method);
+        let scope = self.functions.pop().unwrap_or_default();
+        let (params, body) = result?;
+        Ok(Arc::new(FuncProto {
+            chunk: self.chunk.clone(),
+            line,
+            params,
+            is_vararg: scope.is_vararg,
+            slots: scope.slots,
+            upvalues: scope.upvalues,
+            body,
+        }))
+    }
+
+    fn function_contents(&mut self, line: u32, is_method: bool) -> Result<(Vec<usize>, Block), LuaError> {
+        let mut params = Vec::new();
+        if is_method {
+            let slot = self.new_local();
+            self.activate(b"self"[..].into(), slot);
+            params.push(slot);
+        }
+        self.expect(Token::LeftParen, "'('")?;
+        if !self.check(&Token::RightParen) {
+            loop {
+                if self.check(&Token::Ellipsis) {
+                    self.function().is_vararg = true;
+                    break;
+                }
+                let name = self.name()?;
+                let slot = self.new_local();
+                self.activate(name, slot);
+                params.push(slot);
+                if !self.check(&Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::RightParen, "')'")?;
+        }
+        let body = self.block()?;
+        self.expect_closing(Token::End, "function", line)?;
+        Ok((params, body))
+    }
+
+    // Expressions
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, LuaError> {
+        let mut exprs = vec![self.expr()?];
+        while self.check(&Token::Comma) {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, LuaError> {
+        self.binary_expr(0)
+    }
+
+    /// Operators binding tighter than `limit`, by precedence climbing
+    fn binary_expr(&mut self, limit: u8) -> Result<Expr, LuaError> {
+        self.enter()?;
+        let mut left = match self.peek() {
+            Token::Not | Token::Minus | Token::Hash | Token::Tilde => {
+                let op = match self.advance() {
+                    Token::Not => UnOp::Not,
+                    Token::Minus => UnOp::Neg,
+                    Token::Hash => UnOp::Len,
+                    _ => UnOp::BitNot,
+                };
+                let operand = self.binary_expr(UNARY_PRIORITY)?;
+                fold_unary(op, operand)
+            }
+            _ => self.simple_expr()?,
+        };
+        loop {
+            let (left_priority, right_priority, op) = match self.peek() {
+                Token::Or => (1, 1, None),
+                Token::And => (2, 2, None),
+                token => match binary_op(token) {
+                    Some((op, l, r)) => (l, r, Some(op)),
+                    None => break,
+                },
+            };
+            if left_priority <= limit {
+                break;
+            }
+            let token = self.advance();
+            let right = self.binary_expr(right_priority)?;
+            left = match (op, token) {
+                (Some(op), _) => Expr::Binary(op, Box::new(left), Box::new(right)),
+                (None, Token::Or) => Expr::Or(Box::new(left), Box::new(right)),
+                (None, _) => Expr::And(Box::new(left), Box::new(right)),
+            };
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr, LuaError> {
+        let line = self.line();
+        let expr = match self.peek().clone() {
+            Token::Nil => Expr::Const(Value::Nil),
+            Token::True => Expr::Const(Value::Bool(true)),
+            Token::False => Expr::Const(Value::Bool(false)),
+            Token::Number(n) => Expr::Const(n),
+            Token::Str(s) => Expr::Const(Value::from(s)),
+            Token::Ellipsis => {
+                if !self.function().is_vararg {
+                    return Err(self.error("cannot use '...' outside a vararg function"));
+                }
+                Expr::Vararg
+            }
+            Token::Function => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body(line, false)?));
+            }
+            Token::LeftBrace => return self.table(),
+            _ => return self.suffixed_expr(),
+        };
+        self.advance();
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, LuaError> {
+        match self.peek() {
+            Token::Name(_) => {
+                let name = self.name()?;
+                Ok(self.resolve(name))
+            }
+            Token::LeftParen => {
+                let line = self.line();
+                self.advance();
+                let expr = self.expr()?;
+                self.expect_closing(Token::RightParen, "(", line)?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr, LuaError> {
+        let mut expr = self.primary_expr()?;
+        loop {
+            expr = match self.peek() {
+                Token::Dot => {
+                    self.advance();
+                    let key = self.name()?;
+                    Expr::Index(Box::new(expr), Box::new(Expr::Const(Value::Str(key))))
+                }
+                Token::LeftBracket => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect(Token::RightBracket, "']'")?;
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::Colon => {
+                    self.advance();
+                    let name = self.name()?;
+                    let args = self.call_args()?;
+                    Expr::Method(Box::new(expr), name, args)
+                }
+                Token::LeftParen | Token::Str(_) | Token::LeftBrace => {
+                    let args = self.call_args()?;
+                    Expr::Call(Box::new(expr), args)
+                }
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    fn call_args(&mut self) -> Result<Vec<Expr>, LuaError> {
+        match self.peek().clone() {
+            Token::Str(s) => {
+                self.advance();
+                Ok(vec![Expr::Const(Value::from(s))])
+            }
+            Token::LeftBrace => Ok(vec![self.table()?]),
+            Token::LeftParen => {
+                let line = self.line();
+                self.advance();
+                if self.check(&Token::RightParen) {
+                    return Ok(Vec::new());
+                }
+                let args = self.expr_list()?;
+                self.expect_closing(Token::RightParen, "(", line)?;
+                Ok(args)
+            }
+            _ => Err(self.error("function arguments expected")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, LuaError> {
+        let line = self.line();
+        self.expect(Token::LeftBrace, "'{'")?;
+        let mut fields = Vec::new();
+        while self.peek() != &Token::RightBrace {
+            let field = match (self.peek(), self.peek_at(1)) {
+                (Token::LeftBracket, _) => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect(Token::RightBracket, "']'")?;
+                    self.expect(Token::Assign, "'='")?;
+                    Field::Named(key, self.expr()?)
+                }
+                (Token::Name(_), Token::Assign) => {
+                    let key = self.name()?;
+                    self.advance();
+                    Field::Named(Expr::Const(Value::Str(key)), self.expr()?)
+                }
+                _ => Field::Positional(self.expr()?),
+            };
+            fields.push(field);
+            if !self.check(&Token::Comma) && !self.check(&Token::Semicolon) {
+                break;
+            }
+        }
+        self.expect_closing(Token::RightBrace, "{", line)?;
+        Ok(Expr::Table(fields))
+    }
+}
+
+enum Resolved {
+    Local(usize),
+    Upvalue(usize),
+}
+
+/// Fold negated numeric literals into constants
+fn fold_unary(op: UnOp, operand: Expr) -> Expr {
+    match (op, &operand) {
+        (UnOp::Neg, Expr::Const(Value::Int(i))) => Expr::Const(Value::Int(i.wrapping_neg())),
+        (UnOp::Neg, Expr::Const(Value::Num(n))) => Expr::Const(Value::Num(-n)),
+        _ => Expr::Unary(op, Box::new(operand)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        match parse("test", source) {
+            Err(LuaError::Syntax(message)) => message,
+            other => panic!("parsed: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn resolves_locals_upvalues_and_globals() {
+        let proto = parse("test", "local a = 1\nfunction f() return a + b end").unwrap();
+        assert_eq!(proto.slots, 1);
+        let StatKind::Assign(targets, values) = &proto.body[1].kind else { panic!() };
+        assert!(matches!(&targets[0], Expr::Global(name) if &name[..] == b"f"));
+        let Expr::Function(f) = &values[0] else { panic!() };
+        assert!(matches!(f.upvalues[..], [Upvalue::Local(0)]));
+        let StatKind::Return(exprs) = &f.body[0].kind else { panic!() };
+        let Expr::Binary(BinOp::Add, left, right) = &exprs[0] else { panic!() };
+        assert!(matches!(**left, Expr::Upvalue(0, _)));
+        assert!(matches!(&**right, Expr::Global(name) if &name[..] == b"b"));
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_lines() {
+        assert_eq!(error("x = = 1"), "test:1: unexpected symbol near '='");
+        assert_eq!(error("if x then\n\ny()"), "test:3: 'end' expected (to close 'if' at line 1) near <eof>");
+        assert_eq!(error("break"), "test:1: break outside a loop");
+        assert_eq!(error("x"), "test:1: syntax error near <eof>");
+        assert_eq!(error("function f() return ... end"), "test:1: cannot use '...' outside a vararg function");
+        assert!(parse("test", &"(".repeat(1000)).is_err());
+    }
+}
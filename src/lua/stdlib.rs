@@ -0,0 +1,1563 @@
+//! The base, math, string and table libraries
+//!
+//! Follows Lua 5.4 closely enough for the usual script idioms. Missing:
+//! coroutines, `io`, `os`, `utf8`, `string.pack` and `%a` formatting.
+
+use super::interp::{first, LuaResult, State, Throw, Vm, MAX_STRING};
+use super::parser::parse;
+use super::value::{float_to_integer, format_g, Closure, Function, LuaStr, NativeFn, TableRef, Value};
+use super::LuaError;
+use std::sync::Arc;
+
+/// Values `table.unpack` and `string.byte` may return at once
+const MAX_RESULTS: i64 = 1 << 20;
+
+/// Nesting of pattern items before matching gives up
+const MAX_PATTERN_DEPTH: usize = 200;
+
+/// Install the libraries into a fresh state
+pub fn open(state: &mut State) {
+    let g = &state.globals;
+    let base: &[(&'static str, NativeFn)] = &[
+        ("assert", assert),
+        ("error", error),
+        ("getmetatable", getmetatable),
+        ("ipairs", ipairs),
+        ("load", load),
+        ("next", next),
+        ("pairs", pairs),
+        ("pcall", pcall),
+        ("print", print),
+        ("rawequal", rawequal),
+        ("rawget", rawget),
+        ("rawlen", rawlen),
+        ("rawset", rawset),
+        ("select", select),
+        ("setmetatable", setmetatable),
+        ("tonumber", tonumber),
+        ("tostring", tostring),
+        ("type", type_of),
+        ("xpcall", xpcall),
+    ];
+    register(g, base);
+    g.set_str("_G", Value::Table(g.clone()));
+    g.set_str("_VERSION", Value::from("Lua 5.4"));
+
+    let math = library(&[
+        ("abs", math_abs),
+        ("acos", |vm, args| math_float(vm, args, "acos", f64::acos)),
+        ("asin", |vm, args| math_float(vm, args, "asin", f64::asin)),
+        ("atan", math_atan),
+        ("ceil", math_ceil),
+        ("cos", |vm, args| math_float(vm, args, "cos", f64::cos)),
+        ("exp", |vm, args| math_float(vm, args, "exp", f64::exp)),
+        ("floor", math_floor),
+        ("fmod", math_fmod),
+        ("log", math_log),
+        ("max", math_max),
+        ("min", math_min),
+        ("modf", math_modf),
+        ("random", math_random),
+        ("randomseed", math_randomseed),
+        ("sin", |vm, args| math_float(vm, args, "sin", f64::sin)),
+        ("sqrt", |vm, args| math_float(vm, args, "sqrt", f64::sqrt)),
+        ("tan", |vm, args| math_float(vm, args, "tan", f64::tan)),
+        ("tointeger", math_tointeger),
+        ("type", math_type),
+        ("ult", math_ult),
+    ]);
+    math.set_str("huge", Value::Num(f64::INFINITY));
+    math.set_str("pi", Value::Num(std::f64::consts::PI));
+    math.set_str("maxinteger", Value::Int(i64::MAX));
+    math.set_str("mininteger", Value::Int(i64::MIN));
+    g.set_str("math", Value::Table(math));
+
+    let string = library(&[
+        ("byte", str_byte),
+        ("char", str_char),
+        ("find", str_find),
+        ("format", str_format),
+        ("gmatch", str_gmatch),
+        ("gsub", str_gsub),
+        ("len", str_len),
+        ("lower", str_lower),
+        ("match", str_match),
+        ("rep", str_rep),
+        ("reverse", str_reverse),
+        ("sub", str_sub),
+        ("upper", str_upper),
+    ]);
+    state.string_meta.set_str("__index", Value::Table(string.clone()));
+    g.set_str("string", Value::Table(string));
+
+    let table = library(&[
+        ("concat", tbl_concat),
+        ("insert", tbl_insert),
+        ("pack", tbl_pack),
+        ("remove", tbl_remove),
+        ("sort", tbl_sort),
+        ("unpack", tbl_unpack),
+    ]);
+    g.set_str("table", Value::Table(table));
+
+    // A fixed seed, so scripted runs repeat exactly
+    state.rng = 0x2545_F491_4F6C_DD1D;
+}
+
+/// Set each function as a field of `table`
+pub fn register(table: &TableRef, functions: &[(&'static str, NativeFn)]) {
+    for &(name, func) in functions {
+        table.set_str(name, Value::native(name, func));
+    }
+}
+
+/// A table of functions
+pub fn library(functions: &[(&'static str, NativeFn)]) -> TableRef {
+    let table = TableRef::new();
+    register(&table, functions);
+    table
+}
+
+// Argument checking
+
+pub fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or_default()
+}
+
+/// An error about argument `i` (from 0) of `name`
+pub fn bad_argument(vm: &Vm, i: usize, name: &str, message: impl std::fmt::Display) -> Throw {
+    vm.error(format!("bad argument #{} to '{}' ({})", i + 1, name, message))
+}
+
+fn type_error(vm: &Vm, args: &[Value], i: usize, name: &str, expected: &str) -> Throw {
+    let got = args.get(i).map_or("no value", Value::type_name);
+    bad_argument(vm, i, name, format!("{} expected, got {}", expected, got))
+}
+
+pub fn check_integer(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<i64> {
+    match args.get(i).and_then(Value::to_number) {
+        Some(n) => n.to_integer().ok_or_else(|| bad_argument(vm, i, name, "number has no integer representation")),
+        None => Err(type_error(vm, args, i, name, "number")),
+    }
+}
+
+pub fn opt_integer(vm: &Vm, args: &[Value], i: usize, name: &str, default: i64) -> LuaResult<i64> {
+    if arg(args, i).is_nil() {
+        Ok(default)
+    } else {
+        check_integer(vm, args, i, name)
+    }
+}
+
+pub fn check_number(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<Value> {
+    args.get(i).and_then(Value::to_number).ok_or_else(|| type_error(vm, args, i, name, "number"))
+}
+
+pub fn check_float(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<f64> {
+    args.get(i).and_then(Value::to_float).ok_or_else(|| type_error(vm, args, i, name, "number"))
+}
+
+/// A string argument, converting numbers
+pub fn check_string(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<LuaStr> {
+    match args.get(i) {
+        Some(Value::Str(s)) => Ok(s.clone()),
+        Some(n @ (Value::Int(_) | Value::Num(_))) => Ok(n.to_bytes().into()),
+        _ => Err(type_error(vm, args, i, name, "string")),
+    }
+}
+
+pub fn check_table(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<TableRef> {
+    match args.get(i) {
+        Some(Value::Table(t)) => Ok(t.clone()),
+        _ => Err(type_error(vm, args, i, name, "table")),
+    }
+}
+
+fn check_any(vm: &Vm, args: &[Value], i: usize, name: &str) -> LuaResult<Value> {
+    args.get(i).cloned().ok_or_else(|| bad_argument(vm, i, name, "value expected"))
+}
+
+// Base library
+
+fn assert(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let value = check_any(vm, &args, 0, "assert")?;
+    if value.truthy() {
+        return Ok(args);
+    }
+    Err(match args.into_iter().nth(1) {
+        Some(message) => Throw::Error(message),
+        None => vm.error("assertion failed!"),
+    })
+}
+
+fn error(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let level = opt_integer(vm, &args, 1, "error", 1)?;
+    Err(match arg(&args, 0) {
+        Value::Str(message) if level > 0 => vm.error(String::from_utf8_lossy(&message)),
+        value => Throw::Error(value),
+    })
+}
+
+fn getmetatable(_: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let Value::Table(t) = arg(&args, 0) else { return Ok(vec![Value::Nil]) };
+    Ok(vec![match t.metatable() {
+        Some(mt) => match mt.get_str("__metatable") {
+            Value::Nil => Value::Table(mt),
+            protected => protected,
+        },
+        None => Value::Nil,
+    }])
+}
+
+fn setmetatable(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "setmetatable")?;
+    let mt = match arg(&args, 1) {
+        Value::Table(mt) => Some(mt),
+        Value::Nil => None,
+        _ => return Err(type_error(vm, &args, 1, "setmetatable", "nil or table")),
+    };
+    if !t.metamethod("__metatable").is_nil() {
+        return Err(vm.error("cannot change a protected metatable"));
+    }
+    t.lock().metatable = mt;
+    Ok(vec![Value::Table(t)])
+}
+
+fn ipairs(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_any(vm, &args, 0, "ipairs")?;
+    Ok(vec![Value::native("ipairs_iterator", ipairs_iterator), t, Value::Int(0)])
+}
+
+fn ipairs_iterator(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let i = check_integer(vm, &args, 1, "ipairs_iterator")?.wrapping_add(1);
+    let value = vm.index(&arg(&args, 0), &Value::Int(i))?;
+    Ok(if value.is_nil() { vec![Value::Nil] } else { vec![Value::Int(i), value] })
+}
+
+fn pairs(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "pairs")?;
+    let handler = t.metamethod("__pairs");
+    if !handler.is_nil() {
+        let mut results = vm.call(&handler, vec![Value::Table(t)])?;
+        results.resize(3, Value::Nil);
+        return Ok(results);
+    }
+    Ok(vec![Value::native("next", next), Value::Table(t), Value::Nil])
+}
+
+fn next(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "next")?;
+    let entry = t.lock().next(&arg(&args, 1));
+    match entry {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(()) => Err(vm.error("invalid key to 'next'")),
+    }
+}
+
+fn load(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let Value::Str(chunk) = arg(&args, 0) else {
+        return Err(type_error(vm, &args, 0, "load", "string"));
+    };
+    let name = match arg(&args, 1) {
+        Value::Str(name) => String::from_utf8_lossy(&name).into_owned(),
+        _ => String::from_utf8_lossy(&chunk).lines().next().unwrap_or_default().chars().take(40).collect(),
+    };
+    let source = String::from_utf8_lossy(&chunk);
+    match parse(&name, &source) {
+        Ok(proto) => {
+            let closure = Closure { proto, upvalues: Vec::new() };
+            Ok(vec![Value::Function(Function::Lua(Arc::new(closure)))])
+        }
+        Err(LuaError::Syntax(message) | LuaError::Runtime(message)) => Ok(vec![Value::Nil, Value::from(message)]),
+    }
+}
+
+fn pcall(vm: &mut Vm, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let f = check_any(vm, &args, 0, "pcall")?;
+    args.remove(0);
+    match vm.call(&f, args) {
+        Ok(mut results) => {
+            results.insert(0, Value::Bool(true));
+            Ok(results)
+        }
+        Err(Throw::Error(e)) => Ok(vec![Value::Bool(false), e]),
+        Err(timeout) => Err(timeout),
+    }
+}
+
+fn xpcall(vm: &mut Vm, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let f = check_any(vm, &args, 0, "xpcall")?;
+    let handler = arg(&args, 1);
+    let args = args.split_off(2.min(args.len()));
+    match vm.call(&f, args) {
+        Ok(mut results) => {
+            results.insert(0, Value::Bool(true));
+            Ok(results)
+        }
+        Err(Throw::Error(e)) => {
+            let mut results = vm.call(&handler, vec![e])?;
+            results.insert(0, Value::Bool(false));
+            Ok(results)
+        }
+        Err(timeout) => Err(timeout),
+    }
+}
+
+fn print(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut line = Vec::new();
+    for (i, value) in args.iter().enumerate() {
+        if i > 0 {
+            line.push(b'\t');
+        }
+        line.extend(vm.tostring(value)?.to_bytes());
+    }
+    vm.state.host.output.push(String::from_utf8_lossy(&line).into_owned());
+    Ok(Vec::new())
+}
+
+fn rawequal(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let a = check_any(vm, &args, 0, "rawequal")?;
+    let b = check_any(vm, &args, 1, "rawequal")?;
+    Ok(vec![Value::Bool(a.raw_equals(&b))])
+}
+
+fn rawget(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "rawget")?;
+    Ok(vec![t.get(&arg(&args, 1))])
+}
+
+fn rawlen(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    match arg(&args, 0) {
+        Value::Table(t) => Ok(vec![Value::Int(t.len() as i64)]),
+        Value::Str(s) => Ok(vec![Value::Int(s.len() as i64)]),
+        _ => Err(bad_argument(vm, 0, "rawlen", "table or string expected")),
+    }
+}
+
+fn rawset(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "rawset")?;
+    t.set(arg(&args, 1), arg(&args, 2)).map_err(|e| vm.error(e))?;
+    Ok(vec![Value::Table(t)])
+}
+
+fn select(vm: &mut Vm, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let count = args.len() as i64 - 1;
+    if let Value::Str(s) = arg(&args, 0) {
+        if &s[..] == b"#" {
+            return Ok(vec![Value::Int(count)]);
+        }
+    }
+    let n = check_integer(vm, &args, 0, "select")?;
+    let start = if n < 0 { count + n } else { n - 1 };
+    if n == 0 || start < 0 {
+        return Err(bad_argument(vm, 0, "select", "index out of range"));
+    }
+    Ok(args.split_off((start + 1).min(count + 1) as usize))
+}
+
+fn tonumber(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let value = check_any(vm, &args, 0, "tonumber")?;
+    if arg(&args, 1).is_nil() {
+        return Ok(vec![value.to_number().unwrap_or_default()]);
+    }
+    let base = check_integer(vm, &args, 1, "tonumber")?;
+    if !(2..=36).contains(&base) {
+        return Err(bad_argument(vm, 1, "tonumber", "base out of range"));
+    }
+    let Value::Str(s) = value else {
+        return Err(type_error(vm, &args, 0, "tonumber", "string"));
+    };
+    let text = String::from_utf8_lossy(&s);
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let mut n: i64 = 0;
+    for c in digits.chars() {
+        match c.to_digit(base as u32) {
+            Some(d) => n = n.wrapping_mul(base).wrapping_add(d as i64),
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+    Ok(vec![Value::Int(if negative { n.wrapping_neg() } else { n })])
+}
+
+fn tostring(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let value = check_any(vm, &args, 0, "tostring")?;
+    Ok(vec![vm.tostring(&value)?])
+}
+
+fn type_of(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let value = check_any(vm, &args, 0, "type")?;
+    Ok(vec![Value::from(value.type_name())])
+}
+
+// Math library
+
+/// A float as an integer when it has an integer value
+fn integral(n: f64) -> Value {
+    float_to_integer(n).map_or(Value::Num(n), Value::Int)
+}
+
+fn math_float(vm: &mut Vm, args: Vec<Value>, name: &str, f: fn(f64) -> f64) -> LuaResult<Vec<Value>> {
+    Ok(vec![Value::Num(f(check_float(vm, &args, 0, name)?))])
+}
+
+fn math_abs(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![match check_number(vm, &args, 0, "abs")? {
+        Value::Int(i) => Value::Int(i.wrapping_abs()),
+        n => Value::Num(n.to_float().unwrap_or_default().abs()),
+    }])
+}
+
+fn math_atan(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let y = check_float(vm, &args, 0, "atan")?;
+    let x = if arg(&args, 1).is_nil() { 1.0 } else { check_float(vm, &args, 1, "atan")? };
+    Ok(vec![Value::Num(y.atan2(x))])
+}
+
+fn math_ceil(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![match check_number(vm, &args, 0, "ceil")? {
+        Value::Num(n) => integral(n.ceil()),
+        i => i,
+    }])
+}
+
+fn math_floor(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![match check_number(vm, &args, 0, "floor")? {
+        Value::Num(n) => integral(n.floor()),
+        i => i,
+    }])
+}
+
+fn math_fmod(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let a = check_number(vm, &args, 0, "fmod")?;
+    let b = check_number(vm, &args, 1, "fmod")?;
+    if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+        if *y == 0 {
+            return Err(bad_argument(vm, 1, "fmod", "zero"));
+        }
+        return Ok(vec![Value::Int(x.wrapping_rem(*y))]);
+    }
+    Ok(vec![Value::Num(a.to_float().unwrap_or_default() % b.to_float().unwrap_or_default())])
+}
+
+fn math_log(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let x = check_float(vm, &args, 0, "log")?;
+    Ok(vec![Value::Num(match arg(&args, 1) {
+        Value::Nil => x.ln(),
+        _ => match check_float(vm, &args, 1, "log")? {
+            2.0 => x.log2(),
+            10.0 => x.log10(),
+            base => x.ln() / base.ln(),
+        },
+    })])
+}
+
+fn math_extreme(vm: &mut Vm, args: Vec<Value>, name: &str, want_max: bool) -> LuaResult<Vec<Value>> {
+    let mut best = check_number(vm, &args, 0, name)?;
+    for i in 1..args.len() {
+        let n = check_number(vm, &args, i, name)?;
+        let better = if want_max { vm.less(&best, &n, false)? } else { vm.less(&n, &best, false)? };
+        if better {
+            best = n;
+        }
+    }
+    Ok(vec![best])
+}
+
+fn math_max(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_extreme(vm, args, "max", true)
+}
+
+fn math_min(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_extreme(vm, args, "min", false)
+}
+
+fn math_modf(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let n = check_float(vm, &args, 0, "modf")?;
+    let whole = n.trunc();
+    let fraction = if n.is_infinite() { 0.0 } else { n - whole };
+    Ok(vec![Value::Num(whole), Value::Num(fraction)])
+}
+
+/// The next number from the xorshift64* generator
+fn next_random(state: &mut State) -> u64 {
+    let mut x = state.rng;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.rng = x;
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn math_random(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let r = next_random(vm.state);
+    let (low, high) = match args.len() {
+        0 => return Ok(vec![Value::Num((r >> 11) as f64 / (1u64 << 53) as f64)]),
+        1 => (1, check_integer(vm, &args, 0, "random")?),
+        _ => (check_integer(vm, &args, 0, "random")?, check_integer(vm, &args, 1, "random")?),
+    };
+    if low > high {
+        return Err(bad_argument(vm, args.len() - 1, "random", "interval is empty"));
+    }
+    let span = (high as u64).wrapping_sub(low as u64).wrapping_add(1);
+    let offset = if span == 0 { r } else { r % span };
+    Ok(vec![Value::Int(low.wrapping_add(offset as i64))])
+}
+
+fn math_randomseed(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let seed = match check_number(vm, &args, 0, "randomseed")? {
+        Value::Int(i) => i as u64,
+        n => n.to_float().unwrap_or_default().to_bits(),
+    };
+    // Zero would stick; mix the seed so nearby seeds diverge
+    vm.state.rng = (seed ^ 0x9E37_79B9_7F4A_7C15).max(1);
+    for _ in 0..4 {
+        next_random(vm.state);
+    }
+    Ok(Vec::new())
+}
+
+fn math_tointeger(_: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![match arg(&args, 0) {
+        Value::Int(i) => Value::Int(i),
+        Value::Num(n) => float_to_integer(n).map_or(Value::Nil, Value::Int),
+        _ => Value::Nil,
+    }])
+}
+
+fn math_type(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![match check_any(vm, &args, 0, "type")? {
+        Value::Int(_) => Value::from("integer"),
+        Value::Num(_) => Value::from("float"),
+        _ => Value::Nil,
+    }])
+}
+
+fn math_ult(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let a = check_integer(vm, &args, 0, "ult")?;
+    let b = check_integer(vm, &args, 1, "ult")?;
+    Ok(vec![Value::Bool((a as u64) < (b as u64))])
+}
+
+// String library
+
+/// A 1-based, possibly negative, string position as a 0-based offset
+/// clamped to `0..=len`
+fn start_offset(i: i64, len: usize) -> usize {
+    if i > 0 {
+        (i as u64 - 1).min(len as u64) as usize
+    } else if i == 0 {
+        0
+    } else {
+        len.saturating_sub(i.unsigned_abs().min(len as u64) as usize)
+    }
+}
+
+/// The end of a range, as an exclusive 0-based offset
+fn end_offset(j: i64, len: usize) -> usize {
+    if j >= 0 {
+        (j as u64).min(len as u64) as usize
+    } else {
+        (len as i64 + j + 1).max(0) as usize
+    }
+}
+
+fn str_byte(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(vm, &args, 0, "byte")?;
+    let i = opt_integer(vm, &args, 1, "byte", 1)?;
+    let j = opt_integer(vm, &args, 2, "byte", i)?;
+    let (start, end) = (start_offset(i, s.len()), end_offset(j, s.len()));
+    if end.saturating_sub(start) as i64 > MAX_RESULTS {
+        return Err(vm.error("string slice too long"));
+    }
+    Ok(s.get(start..end).unwrap_or_default().iter().map(|&b| Value::Int(b as i64)).collect())
+}
+
+fn str_char(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for i in 0..args.len() {
+        let c = check_integer(vm, &args, i, "char")?;
+        let byte = u8::try_from(c).map_err(|_| bad_argument(vm, i, "char", "value out of range"))?;
+        bytes.push(byte);
+    }
+    Ok(vec![Value::from(bytes)])
+}
+
+fn str_len(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![Value::Int(check_string(vm, &args, 0, "len")?.len() as i64)])
+}
+
+fn str_lower(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![Value::from(check_string(vm, &args, 0, "lower")?.to_ascii_lowercase())])
+}
+
+fn str_upper(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![Value::from(check_string(vm, &args, 0, "upper")?.to_ascii_uppercase())])
+}
+
+fn str_rep(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(vm, &args, 0, "rep")?;
+    let n = check_integer(vm, &args, 1, "rep")?;
+    let sep = match arg(&args, 2) {
+        Value::Nil => LuaStr::from(&b""[..]),
+        _ => check_string(vm, &args, 2, "rep")?,
+    };
+    if n <= 0 {
+        return Ok(vec![Value::str(b"")]);
+    }
+    let total = (s.len() + sep.len()) as u128 * n as u128;
+    if total > MAX_STRING as u128 {
+        return Err(vm.error("resulting string too large"));
+    }
+    let mut out = Vec::with_capacity(total as usize);
+    for i in 0..n {
+        if i > 0 {
+            out.extend_from_slice(&sep);
+        }
+        out.extend_from_slice(&s);
+    }
+    Ok(vec![Value::from(out)])
+}
+
+fn str_reverse(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut s = check_string(vm, &args, 0, "reverse")?.to_vec();
+    s.reverse();
+    Ok(vec![Value::from(s)])
+}
+
+fn str_sub(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(vm, &args, 0, "sub")?;
+    let i = opt_integer(vm, &args, 1, "sub", 1)?;
+    let j = opt_integer(vm, &args, 2, "sub", -1)?;
+    let (start, end) = (start_offset(i, s.len()), end_offset(j, s.len()));
+    Ok(vec![Value::str(s.get(start..end).unwrap_or_default())])
+}
+
+// Patterns
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+const MAX_CAPTURES: usize = 32;
+
+struct Matcher<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    level: usize,
+    /// Start and length of each capture
+    captures: [(usize, isize); MAX_CAPTURES],
+    depth: usize,
+}
+
+type MatchResult = Result<Option<usize>, String>;
+
+fn class_matches(c: u8, class: u8) -> bool {
+    let result = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c == b' ' || (b'\t'..=b'\r').contains(&c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !result
+    } else {
+        result
+    }
+}
+
+impl<'a> Matcher<'a> {
+    fn new(src: &'a [u8], pat: &'a [u8]) -> Matcher<'a> {
+        Matcher { src, pat, level: 0, captures: [(0, 0); MAX_CAPTURES], depth: 0 }
+    }
+
+    fn pat_at(&self, p: usize) -> u8 {
+        self.pat.get(p).copied().unwrap_or(0)
+    }
+
+    /// The end of the single-character class starting at `p`
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pat[p];
+        p += 1;
+        if c == b'%' {
+            if p >= self.pat.len() {
+                return Err("malformed pattern (ends with '%')".into());
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if self.pat_at(p) == b'^' {
+                p += 1;
+            }
+            // The first character is literal, even ']'
+            loop {
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (missing ']')".into());
+                }
+                let c = self.pat[p];
+                p += 1;
+                if c == b'%' && p < self.pat.len() {
+                    p += 1;
+                }
+                if self.pat_at(p) == b']' {
+                    return Ok(p + 1);
+                }
+            }
+        }
+        Ok(p)
+    }
+
+    /// Whether `c` is in the set from `[` at `p` to `]` at `end`
+    fn set_matches(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut found = true;
+        p += 1;
+        if self.pat[p] == b'^' {
+            found = false;
+            p += 1;
+        }
+        while p < end {
+            if self.pat[p] == b'%' {
+                p += 1;
+                if class_matches(c, self.pat[p]) {
+                    return found;
+                }
+            } else if self.pat_at(p + 1) == b'-' && p + 2 < end {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return found;
+            }
+            p += 1;
+        }
+        !found
+    }
+
+    fn single_matches(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else { return false };
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => class_matches(c, self.pat[p + 1]),
+            b'[' => self.set_matches(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn do_match(&mut self, s: usize, p: usize) -> MatchResult {
+        self.depth += 1;
+        if self.depth > MAX_PATTERN_DEPTH {
+            return Err("pattern too complex".into());
+        }
+        let result = self.match_here(s, p);
+        self.depth -= 1;
+        result
+    }
+
+    fn match_here(&mut self, mut s: usize, mut p: usize) -> MatchResult {
+        loop {
+            if p >= self.pat.len() {
+                return Ok(Some(s));
+            }
+            match self.pat[p] {
+                b'(' => {
+                    return if self.pat_at(p + 1) == b')' {
+                        self.start_capture(s, p + 2, CAP_POSITION)
+                    } else {
+                        self.start_capture(s, p + 1, CAP_UNFINISHED)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => return Ok((s == self.src.len()).then_some(s)),
+                b'%' if self.pat_at(p + 1) == b'b' => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                b'%' if self.pat_at(p + 1) == b'f' => {
+                    p += 2;
+                    if self.pat_at(p) != b'[' {
+                        return Err("missing '[' after '%f' in pattern".into());
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.set_matches(previous, p, ep - 1) && self.set_matches(current, p, ep - 1) {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                b'%' if self.pat_at(p + 1).is_ascii_digit() => match self.match_capture(s, self.pat[p + 1])? {
+                    Some(end) => {
+                        s = end;
+                        p += 2;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                _ => {}
+            }
+            let ep = self.class_end(p)?;
+            let suffix = self.pat_at(ep);
+            if !self.single_matches(s, p, ep) {
+                if matches!(suffix, b'*' | b'?' | b'-') {
+                    p = ep + 1;
+                    continue;
+                }
+                return Ok(None);
+            }
+            match suffix {
+                b'?' => {
+                    if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                        return Ok(Some(end));
+                    }
+                    p = ep + 1;
+                }
+                b'+' => return self.max_expand(s + 1, p, ep),
+                b'*' => return self.max_expand(s, p, ep),
+                b'-' => return self.min_expand(s, p, ep),
+                _ => {
+                    s += 1;
+                    p = ep;
+                }
+            }
+        }
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> MatchResult {
+        let mut count = 0;
+        while self.single_matches(s + count, p, ep) {
+            count += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> MatchResult {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if !self.single_matches(s, p, ep) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> MatchResult {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".into());
+        }
+        self.captures[self.level] = (s, what);
+        self.level += 1;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> MatchResult {
+        let Some(l) = (0..self.level).rev().find(|&l| self.captures[l].1 == CAP_UNFINISHED) else {
+            return Err("invalid pattern capture".into());
+        };
+        self.captures[l].1 = (s - self.captures[l].0) as isize;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[l].1 = CAP_UNFINISHED;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> MatchResult {
+        if p + 1 >= self.pat.len() {
+            return Err("malformed pattern (missing arguments to '%b')".into());
+        }
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn match_capture(&self, s: usize, digit: u8) -> MatchResult {
+        let l = (digit - b'1') as usize;
+        if digit == b'0' || l >= self.level || self.captures[l].1 == CAP_UNFINISHED {
+            return Err(format!("invalid capture index %{}", digit - b'0'));
+        }
+        let (start, len) = self.captures[l];
+        let captured = &self.src[start..start + len.max(0) as usize];
+        Ok(self.src[s..].starts_with(captured).then_some(s + captured.len()))
+    }
+
+    /// Capture `i`, or the whole match `s..e` if the pattern has none
+    fn capture(&self, i: usize, s: usize, e: usize) -> Result<Value, String> {
+        if i >= self.level {
+            if i == 0 {
+                return Ok(Value::str(&self.src[s..e]));
+            }
+            return Err(format!("invalid capture index %{}", i + 1));
+        }
+        let (start, len) = self.captures[i];
+        match len {
+            CAP_UNFINISHED => Err("unfinished capture".into()),
+            CAP_POSITION => Ok(Value::Int(start as i64 + 1)),
+            len => Ok(Value::str(&self.src[start..start + len as usize])),
+        }
+    }
+
+    fn captures(&self, s: usize, e: usize, whole_if_none: bool) -> Result<Vec<Value>, String> {
+        let count = if self.level == 0 && whole_if_none { 1 } else { self.level };
+        (0..count).map(|i| self.capture(i, s, e)).collect()
+    }
+}
+
+fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
+
+fn find_aux(vm: &mut Vm, args: Vec<Value>, find: bool) -> LuaResult<Vec<Value>> {
+    let name = if find { "find" } else { "match" };
+    let s = check_string(vm, &args, 0, name)?;
+    let pattern = check_string(vm, &args, 1, name)?;
+    let init = opt_integer(vm, &args, 2, name, 1)?;
+    if init > s.len() as i64 + 1 {
+        return Ok(vec![Value::Nil]);
+    }
+    let init = start_offset(init, s.len());
+    if find && (arg(&args, 3).truthy() || !has_specials(&pattern)) {
+        let position = if pattern.is_empty() {
+            Some(init)
+        } else {
+            s[init..].windows(pattern.len()).position(|w| w == &pattern[..]).map(|i| i + init)
+        };
+        return Ok(match position {
+            Some(i) => vec![Value::Int(i as i64 + 1), Value::Int((i + pattern.len()) as i64)],
+            None => vec![Value::Nil],
+        });
+    }
+    let (anchor, start) = match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut m = Matcher::new(&s, &pattern);
+    let mut position = init;
+    loop {
+        m.level = 0;
+        m.depth = 0;
+        if let Some(end) = m.do_match(position, start).map_err(|e| vm.error(e))? {
+            let captures = m.captures(position, end, !find).map_err(|e| vm.error(e))?;
+            if !find {
+                return Ok(captures);
+            }
+            let mut results = vec![Value::Int(position as i64 + 1), Value::Int(end as i64)];
+            results.extend(captures);
+            return Ok(results);
+        }
+        position += 1;
+        if anchor || position > s.len() {
+            return Ok(vec![Value::Nil]);
+        }
+        vm.step()?;
+    }
+}
+
+fn str_find(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    find_aux(vm, args, true)
+}
+
+fn str_match(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    find_aux(vm, args, false)
+}
+
+fn str_gmatch(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(vm, &args, 0, "gmatch")?;
+    let pattern = check_string(vm, &args, 1, "gmatch")?;
+    let init = start_offset(opt_integer(vm, &args, 2, "gmatch", 1)?, s.len());
+    // The iterator's position lives in a table bound to it
+    let state = TableRef::from_values([Value::Str(s), Value::Str(pattern), Value::Int(init as i64), Value::Int(-1)]);
+    Ok(vec![Value::bound("gmatch_iterator", gmatch_iterator, vec![Value::Table(state)])])
+}
+
+fn gmatch_iterator(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let state = check_table(vm, &args, 0, "gmatch_iterator")?;
+    let (Value::Str(s), Value::Str(pattern), Value::Int(mut position), Value::Int(last)) =
+        (state.get(&Value::Int(1)), state.get(&Value::Int(2)), state.get(&Value::Int(3)), state.get(&Value::Int(4)))
+    else {
+        return Ok(vec![Value::Nil]);
+    };
+    let mut m = Matcher::new(&s, &pattern);
+    while position as usize <= s.len() {
+        m.level = 0;
+        m.depth = 0;
+        match m.do_match(position as usize, 0).map_err(|e| vm.error(e))? {
+            Some(end) if end as i64 != last => {
+                let captures = m.captures(position as usize, end, true).map_err(|e| vm.error(e))?;
+                let _ = state.set(Value::Int(3), Value::Int(end as i64));
+                let _ = state.set(Value::Int(4), Value::Int(end as i64));
+                return Ok(captures);
+            }
+            _ => position += 1,
+        }
+        vm.step()?;
+    }
+    let _ = state.set(Value::Int(3), Value::Int(position));
+    Ok(vec![Value::Nil])
+}
+
+/// Append a `gsub` replacement string, with `%1` and friends filled in
+fn expand_template(m: &Matcher, template: &[u8], s: usize, e: usize, out: &mut Vec<u8>) -> Result<(), String> {
+    let mut i = 0;
+    while i < template.len() {
+        let c = template[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        match template.get(i) {
+            Some(b'%') => out.push(b'%'),
+            Some(b'0') => out.extend_from_slice(&m.src[s..e]),
+            Some(d @ b'1'..=b'9') => out.extend(m.capture((d - b'1') as usize, s, e)?.to_bytes()),
+            _ => return Err("invalid use of '%' in replacement string".into()),
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+fn str_gsub(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(vm, &args, 0, "gsub")?;
+    let pattern = check_string(vm, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+    if !matches!(replacement, Value::Int(_) | Value::Num(_) | Value::Str(_) | Value::Table(_) | Value::Function(_)) {
+        return Err(type_error(vm, &args, 2, "gsub", "string/function/table"));
+    }
+    let max = opt_integer(vm, &args, 3, "gsub", i64::MAX)?;
+    let (anchor, start) = match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut m = Matcher::new(&s, &pattern);
+    let mut out = Vec::with_capacity(s.len());
+    let mut position = 0;
+    let mut last = None;
+    let mut count = 0;
+    while count < max {
+        m.level = 0;
+        m.depth = 0;
+        match m.do_match(position, start).map_err(|e| vm.error(e))? {
+            Some(end) if last != Some(end) => {
+                count += 1;
+                let whole = &s[position..end];
+                let value = match &replacement {
+                    Value::Table(t) => Some(t.get(&m.capture(0, position, end).map_err(|e| vm.error(e))?)),
+                    Value::Function(_) => {
+                        let captures = m.captures(position, end, true).map_err(|e| vm.error(e))?;
+                        Some(first(vm.call(&replacement, captures)?))
+                    }
+                    _ => {
+                        expand_template(&m, &replacement.to_bytes(), position, end, &mut out).map_err(|e| vm.error(e))?;
+                        None
+                    }
+                };
+                match value {
+                    None => {}
+                    Some(Value::Nil | Value::Bool(false)) => out.extend_from_slice(whole),
+                    Some(value @ (Value::Str(_) | Value::Int(_) | Value::Num(_))) => out.extend(value.to_bytes()),
+                    Some(other) => return Err(vm.error(format!("invalid replacement value (a {})", other.type_name()))),
+                }
+                position = end;
+                last = Some(end);
+            }
+            _ if position < s.len() => {
+                out.push(s[position]);
+                position += 1;
+            }
+            _ => break,
+        }
+        if out.len() > MAX_STRING {
+            return Err(vm.error("resulting string too large"));
+        }
+        if anchor {
+            break;
+        }
+        vm.step()?;
+    }
+    out.extend_from_slice(&s[position.min(s.len())..]);
+    Ok(vec![Value::from(out), Value::Int(count)])
+}
+
+// string.format
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad to the field width; `sign_len` leading bytes stay ahead of zeros
+    fn pad(&self, body: String, sign_len: usize, numeric: bool) -> Vec<u8> {
+        let len = body.len();
+        if len >= self.width {
+            return body.into_bytes();
+        }
+        let fill = self.width - len;
+        if self.left {
+            return format!("{}{}", body, " ".repeat(fill)).into_bytes();
+        }
+        if self.zero && numeric {
+            let (sign, digits) = body.split_at(sign_len);
+            return format!("{}{}{}", sign, "0".repeat(fill), digits).into_bytes();
+        }
+        format!("{}{}", " ".repeat(fill), body).into_bytes()
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+/// C's `%e`
+fn format_exp(n: f64, precision: usize, upper: bool) -> String {
+    let text = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let text = format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs());
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+fn format_float(n: f64, conversion: u8, spec: &Spec) -> Vec<u8> {
+    let precision = spec.precision.unwrap_or(6);
+    let upper = conversion.is_ascii_uppercase();
+    let magnitude = n.abs();
+    let body = if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        "inf".to_string()
+    } else {
+        match conversion {
+            b'e' | b'E' => format_exp(magnitude, precision, upper),
+            b'f' | b'F' => format!("{:.*}", precision, magnitude),
+            _ => format_g(magnitude, if precision == 0 { 1 } else { precision }, spec.alternate),
+        }
+    };
+    let body = if upper { body.to_uppercase() } else { body };
+    let sign = spec.sign(n.is_sign_negative() && !n.is_nan());
+    spec.pad(format!("{}{}", sign, body), sign.len(), n.is_finite())
+}
+
+fn format_integer(i: i64, conversion: u8, spec: &Spec) -> Vec<u8> {
+    let (sign, digits) = match conversion {
+        b'd' | b'i' => (spec.sign(i < 0), i.unsigned_abs().to_string()),
+        b'o' => ("", format!("{:o}", i as u64)),
+        b'x' => (if spec.alternate && i != 0 { "0x" } else { "" }, format!("{:x}", i as u64)),
+        _ => (if spec.alternate && i != 0 { "0X" } else { "" }, format!("{:X}", i as u64)),
+    };
+    let digits = match spec.precision {
+        Some(0) if i == 0 => String::new(),
+        Some(p) if digits.len() < p => format!("{}{}", "0".repeat(p - digits.len()), digits),
+        _ => digits,
+    };
+    let mut spec_zero = Spec { zero: spec.zero && spec.precision.is_none(), ..Default::default() };
+    spec_zero.left = spec.left;
+    spec_zero.width = spec.width;
+    spec_zero.pad(format!("{}{}", sign, digits), sign.len(), true)
+}
+
+/// `%q`: a value as Lua source that reads back the same
+fn quote(vm: &Vm, value: &Value) -> LuaResult<Vec<u8>> {
+    Ok(match value {
+        Value::Str(s) => {
+            let mut out = vec![b'"'];
+            for (i, &c) in s.iter().enumerate() {
+                match c {
+                    b'"' | b'\\' => out.extend_from_slice(&[b'\\', c]),
+                    b'\n' => out.extend_from_slice(b"\\n"),
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    0 => out.extend_from_slice(if s.get(i + 1).is_some_and(u8::is_ascii_digit) { b"\\000" } else { b"\\0" }),
+                    c if c.is_ascii_control() => {
+                        let escape = if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                            format!("\\{:03}", c)
+                        } else {
+                            format!("\\{}", c)
+                        };
+                        out.extend_from_slice(escape.as_bytes());
+                    }
+                    c => out.push(c),
+                }
+            }
+            out.push(b'"');
+            out
+        }
+        Value::Int(i64::MIN) => b"0x8000000000000000".to_vec(),
+        Value::Num(n) if n.is_nan() => b"(0/0)".to_vec(),
+        Value::Num(n) if n.is_infinite() => if *n > 0.0 { &b"1e9999"[..] } else { b"-1e9999" }.to_vec(),
+        Value::Num(n) => {
+            let text = format!("{:?}", n);
+            text.into_bytes()
+        }
+        Value::Nil | Value::Bool(_) | Value::Int(_) => value.to_bytes(),
+        _ => return Err(vm.error("bad argument to 'format' (value has no literal form)")),
+    })
+}
+
+fn str_format(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let format = check_string(vm, &args, 0, "format")?;
+    let mut out = Vec::with_capacity(format.len());
+    let mut next_arg = 1;
+    let mut i = 0;
+    while i < format.len() {
+        let c = format[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        if format.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+        let mut spec = Spec::default();
+        while let Some(&flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let digits = |i: &mut usize| {
+            let start = *i;
+            while *i < format.len() && format[*i].is_ascii_digit() && *i - start < 2 {
+                *i += 1;
+            }
+            std::str::from_utf8(&format[start..*i]).ok().and_then(|d| d.parse().ok())
+        };
+        spec.width = digits(&mut i).unwrap_or(0);
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(digits(&mut i).unwrap_or(0));
+        }
+        let Some(&conversion) = format.get(i) else {
+            return Err(vm.error("invalid conversion '%' to 'format'"));
+        };
+        i += 1;
+        let n = next_arg;
+        next_arg += 1;
+        if n >= args.len() && conversion != b'%' {
+            return Err(bad_argument(vm, n, "format", "no value"));
+        }
+        let piece = match conversion {
+            b'c' => vec![check_integer(vm, &args, n, "format")? as u8],
+            b'd' | b'i' | b'o' | b'x' | b'X' => {
+                let value = check_number(vm, &args, n, "format")?;
+                let Some(i) = value.to_integer() else {
+                    return Err(bad_argument(vm, n, "format", "number has no integer representation"));
+                };
+                format_integer(i, conversion, &spec)
+            }
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => format_float(check_float(vm, &args, n, "format")?, conversion, &spec),
+            b'q' => quote(vm, &args[n])?,
+            b's' => {
+                let mut text = vm.tostring(&args[n])?.to_bytes();
+                if let Some(p) = spec.precision {
+                    text.truncate(p);
+                }
+                spec.pad(String::from_utf8_lossy(&text).into_owned(), 0, false)
+            }
+            other => {
+                let text = format!("invalid conversion '%{}' to 'format'", other as char);
+                return Err(vm.error(text));
+            }
+        };
+        out.extend(piece);
+        if out.len() > MAX_STRING {
+            return Err(vm.error("resulting string too large"));
+        }
+    }
+    Ok(vec![Value::from(out)])
+}
+
+// Table library
+
+fn tbl_concat(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "concat")?;
+    let sep = match arg(&args, 1) {
+        Value::Nil => LuaStr::from(&b""[..]),
+        _ => check_string(vm, &args, 1, "concat")?,
+    };
+    let i = opt_integer(vm, &args, 2, "concat", 1)?;
+    let j = opt_integer(vm, &args, 3, "concat", t.len() as i64)?;
+    let mut out = Vec::new();
+    let mut k = i;
+    while k <= j {
+        let Some(bytes) = t.get(&Value::Int(k)).concat_bytes() else {
+            return Err(vm.error(format!("invalid value (at index {}) in table for 'concat'", k)));
+        };
+        out.extend(bytes);
+        if k < j {
+            out.extend_from_slice(&sep);
+        }
+        if out.len() > MAX_STRING {
+            return Err(vm.error("resulting string too large"));
+        }
+        k += 1;
+    }
+    Ok(vec![Value::from(out)])
+}
+
+fn tbl_insert(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "insert")?;
+    let end = t.len() as i64 + 1;
+    let (position, value) = match args.len() {
+        2 => (end, arg(&args, 1)),
+        3 => {
+            let position = check_integer(vm, &args, 1, "insert")?;
+            if !(1..=end).contains(&position) {
+                return Err(bad_argument(vm, 1, "insert", "position out of bounds"));
+            }
+            (position, arg(&args, 2))
+        }
+        _ => return Err(vm.error("wrong number of arguments to 'insert'")),
+    };
+    let mut table = t.lock();
+    for k in (position + 1..=end).rev() {
+        let moved = table.get(&Value::Int(k - 1));
+        let _ = table.set(Value::Int(k), moved);
+    }
+    let _ = table.set(Value::Int(position), value);
+    Ok(Vec::new())
+}
+
+fn tbl_pack(_: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let n = args.len() as i64;
+    let t = TableRef::from_values(args);
+    t.set_str("n", Value::Int(n));
+    Ok(vec![Value::Table(t)])
+}
+
+fn tbl_remove(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "remove")?;
+    let size = t.len() as i64;
+    let position = opt_integer(vm, &args, 1, "remove", size)?;
+    if args.len() > 1 && size + 1 != position && !(1..=size.max(1)).contains(&position) && !(size == 0 && position == 0) {
+        return Err(bad_argument(vm, 1, "remove", "position out of bounds"));
+    }
+    let mut table = t.lock();
+    let removed = table.get(&Value::Int(position));
+    for k in position..size {
+        let moved = table.get(&Value::Int(k + 1));
+        let _ = table.set(Value::Int(k), moved);
+    }
+    if position <= size {
+        let _ = table.set(Value::Int(size), Value::Nil);
+    }
+    Ok(vec![removed])
+}
+
+/// A stable merge sort with a comparison that can fail
+fn merge_sort(vm: &mut Vm, mut items: Vec<Value>, compare: &Value) -> LuaResult<Vec<Value>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(vm, items, compare)?;
+    let right = merge_sort(vm, right, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        let right_first = if compare.is_nil() {
+            vm.less(b, a, false)?
+        } else {
+            first(vm.call(compare, vec![b.clone(), a.clone()])?).truthy()
+        };
+        merged.extend(if right_first { right.next() } else { left.next() });
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn tbl_sort(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "sort")?;
+    let compare = arg(&args, 1);
+    if !matches!(compare, Value::Nil | Value::Function(_)) {
+        return Err(type_error(vm, &args, 1, "sort", "function"));
+    }
+    let n = t.len() as i64;
+    let items = (1..=n).map(|i| t.get(&Value::Int(i))).collect();
+    let sorted = merge_sort(vm, items, &compare)?;
+    let mut table = t.lock();
+    for (i, value) in sorted.into_iter().enumerate() {
+        let _ = table.set(Value::Int(i as i64 + 1), value);
+    }
+    Ok(Vec::new())
+}
+
+fn tbl_unpack(vm: &mut Vm, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(vm, &args, 0, "unpack")?;
+    let i = opt_integer(vm, &args, 1, "unpack", 1)?;
+    let j = match arg(&args, 2) {
+        Value::Nil => t.len() as i64,
+        _ => check_integer(vm, &args, 2, "unpack")?,
+    };
+    if i > j {
+        return Ok(Vec::new());
+    }
+    if j.wrapping_sub(i) >= MAX_RESULTS || j.checked_sub(i).is_none() {
+        return Err(vm.error("too many results to unpack"));
+    }
+    Ok((i..=j).map(|k| t.get(&Value::Int(k))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::bindings::Host;
+    use crate::Emulator;
+
+    fn run(source: &str) -> Vec<Value> {
+        let mut state = State::new(Host::default());
+        let proto = parse("test", source).unwrap();
+        let mut emulator = Emulator::new();
+        let mut vm = Vm::new(&mut state, &mut emulator);
+        match vm.run(proto) {
+            Ok(values) => values,
+            Err(Throw::Error(e)) => panic!("{}", e),
+            Err(Throw::Timeout) => panic!("timeout"),
+        }
+    }
+
+    fn strings(source: &str) -> Vec<String> {
+        run(source).iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn string_functions() {
+        assert_eq!(
+            strings("return ('hello'):upper(), #'abc', ('abc'):sub(-2), ('x'):rep(3, ','), ('abc'):byte(1, -1)"),
+            ["HELLO", "3", "bc", "x,x,x", "97", "98", "99"]
+        );
+        assert_eq!(
+            strings("return string.format('%5.2f|%-4d|%03x|%s|%q|%g', 3.14159, 7, 255, nil, 'a\\nb', 1e20)"),
+            [" 3.14|7   |0ff|nil|\"a\\nb\"|1e+20"]
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(strings("return ('key = value'):match('(%w+)%s*=%s*(%w+)')"), ["key", "value"]);
+        assert_eq!(strings("return ('hello world'):find('o w')"), ["5", "7"]);
+        assert_eq!(strings("return ('f(a(b)c)d'):match('%b()')"), ["(a(b)c)"]);
+        assert_eq!(strings("return ('THE (quick) fox'):find('%f[%a]%a+', 5)"), ["6", "10"]);
+        assert_eq!(strings("return ('abc'):gsub('%w', '%0%0')"), ["aabbcc", "3"]);
+        assert_eq!(strings("return ('hello world'):gsub('o', {o = '0'})"), ["hell0 w0rld", "2"]);
+        assert_eq!(strings("return ('abc'):gsub('', '-')"), ["-a-b-c-", "4"]);
+        let source = "
+            local words = {}
+            for word in ('one two  three'):gmatch('%a+') do words[#words + 1] = word end
+            return table.concat(words, ',')
+        ";
+        assert_eq!(strings(source), ["one,two,three"]);
+    }
+
+    #[test]
+    fn table_functions() {
+        let source = "
+            local t = {5, 2, 8, 1}
+            table.sort(t)
+            table.insert(t, 1, 0)
+            local last = table.remove(t)
+            table.sort(t, function(a, b) return a > b end)
+            return table.concat(t, ' '), last, select('#', table.unpack({1, 2, 3}))
+        ";
+        assert_eq!(strings(source), ["5 2 1 0", "8", "3"]);
+    }
+
+    #[test]
+    fn math_functions() {
+        assert_eq!(
+            strings("return math.floor(3.7), math.max(1, 5, 3), math.type(1), math.type(1.0), math.tointeger(3.0), math.abs(-4)"),
+            ["3", "5", "integer", "float", "3", "4"]
+        );
+        let source = "
+            math.randomseed(42)
+            local a = math.random(1, 6)
+            math.randomseed(42)
+            local ok = true
+            for _ = 1, 100 do local r = math.random(3) if r < 1 or r > 3 then ok = false end end
+            math.randomseed(42)
+            return a == math.random(1, 6), ok
+        ";
+        assert_eq!(strings(source), ["true", "true"]);
+    }
+
+    #[test]
+    fn base_functions() {
+        assert_eq!(
+            strings("return tonumber('0x10'), tonumber('z', 36), tonumber('8', 8), tostring(1.5), type(print)"),
+            ["16", "35", "nil", "1.5", "function"]
+        );
+        assert_eq!(strings("local f = load('return 1 + ...') return f(2)"), ["3"]);
+        assert_eq!(strings("return load('return +')"), ["nil", "return +:1: unexpected symbol near '+'"]);
+        assert_eq!(strings("local n = 0 for k, v in pairs({a = 1, 2, 3}) do n = n + v end return n"), ["6"]);
+    }
+}
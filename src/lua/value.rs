@@ -0,0 +1,609 @@
+//! Lua values, tables and functions
+//!
+//! Tables and captured variables sit behind `Arc<Mutex>` so a script can
+//! move between threads with the emulator it drives. Only one thread
+//! runs a script at a time, so the locks are never contended; they are
+//! held just long enough to read or write one slot, never across a call.
+
+use super::interp::{LuaResult, Vm};
+use super::parser::FuncProto;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Lua strings are byte strings
+pub type LuaStr = Arc<[u8]>;
+
+/// A local variable captured by a closure
+pub type Cell = Arc<Mutex<Value>>;
+
+/// A function implemented in Rust
+pub type NativeFn = fn(&mut Vm, Vec<Value>) -> LuaResult<Vec<Value>>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Num(f64),
+    Str(LuaStr),
+    Table(TableRef),
+    Function(Function),
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Arc<Closure>),
+    Native(Arc<Native>),
+}
+
+pub struct Closure {
+    pub proto: Arc<FuncProto>,
+    pub upvalues: Vec<Cell>,
+}
+
+pub struct Native {
+    pub name: &'static str,
+    pub func: NativeFn,
+    /// Arguments passed ahead of the caller's, like a closure's upvalues
+    pub bound: Vec<Value>,
+}
+
+impl Function {
+    fn ptr(&self) -> usize {
+        match self {
+            Function::Lua(f) => Arc::as_ptr(f) as usize,
+            Function::Native(f) => Arc::as_ptr(f) as usize,
+        }
+    }
+}
+
+impl Value {
+    pub fn native(name: &'static str, func: NativeFn) -> Value {
+        Value::bound(name, func, Vec::new())
+    }
+
+    /// A native function that receives `bound` before its arguments
+    pub fn bound(name: &'static str, func: NativeFn, bound: Vec<Value>) -> Value {
+        Value::Function(Function::Native(Arc::new(Native { name, func, bound })))
+    }
+
+    pub fn str(bytes: &[u8]) -> Value {
+        Value::Str(bytes.into())
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Int(_) | Value::Num(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// The value as a number, converting numeric strings
+    pub fn to_number(&self) -> Option<Value> {
+        match self {
+            Value::Int(_) | Value::Num(_) => Some(self.clone()),
+            Value::Str(s) => std::str::from_utf8(s).ok().and_then(parse_number),
+            _ => None,
+        }
+    }
+
+    pub fn to_float(&self) -> Option<f64> {
+        match self.to_number()? {
+            Value::Int(i) => Some(i as f64),
+            Value::Num(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// The value as an integer, if it has an exact integer representation
+    pub fn to_integer(&self) -> Option<i64> {
+        match self.to_number()? {
+            Value::Int(i) => Some(i),
+            Value::Num(n) => float_to_integer(n),
+            _ => None,
+        }
+    }
+
+    /// Primitive equality, without metamethods
+    pub fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Int(a), Value::Num(b)) | (Value::Num(b), Value::Int(a)) => float_to_integer(*b) == Some(*a),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => a.ptr_eq(b),
+            (Value::Function(a), Value::Function(b)) => a.ptr() == b.ptr(),
+            _ => false,
+        }
+    }
+
+    /// The text `tostring` gives, before any `__tostring` metamethod
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Str(s) => s.to_vec(),
+            _ => self.to_string().into_bytes(),
+        }
+    }
+
+    /// Strings and numbers as the bytes concatenation uses
+    pub fn concat_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Value::Str(s) => Some(s.to_vec()),
+            Value::Int(_) | Value::Num(_) => Some(self.to_string().into_bytes()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Num(n) => {
+                let text = format_g(*n, 14, false);
+                // Floats with integral values still read as floats
+                if text.bytes().all(|c| c == b'-' || c.is_ascii_digit()) {
+                    write!(f, "{}.0", text)
+                } else {
+                    write!(f, "{}", text)
+                }
+            }
+            Value::Str(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+            Value::Table(t) => write!(f, "table: {:#010x}", t.ptr()),
+            Value::Function(func) => match func {
+                Function::Native(n) => write!(f, "function: builtin: {}", n.name),
+                Function::Lua(_) => write!(f, "function: {:#010x}", func.ptr()),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            Value::Num(n) => write!(f, "{:?}", n),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.raw_equals(other)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Num(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::str(s.as_bytes())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::str(s.as_bytes())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Value {
+        Value::Str(bytes.into())
+    }
+}
+
+impl From<TableRef> for Value {
+    fn from(t: TableRef) -> Value {
+        Value::Table(t)
+    }
+}
+
+/// The integer a float holds, if it's whole and in range
+pub fn float_to_integer(n: f64) -> Option<i64> {
+    // 2^63 itself is out of range, -2^63 is not
+    let two_63 = (1u64 << 63) as f64;
+    (n.fract() == 0.0 && (-two_63..two_63).contains(&n)).then_some(n as i64)
+}
+
+/// Parse a numeral the way Lua reads them from source and `tonumber`
+///
+/// Decimal integers too large for 64 bits become floats; hexadecimal
+/// ones wrap around.
+pub fn parse_number(text: &str) -> Option<Value> {
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        if hex.is_empty() || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Value::Int(hex.bytes().fold(0i64, |acc, c| {
+            acc.wrapping_mul(16).wrapping_add((c as char).to_digit(16).unwrap_or(0) as i64)
+        }))
+    } else {
+        if !digits.bytes().any(|c| c.is_ascii_digit())
+            || !digits.bytes().all(|c| c.is_ascii_digit() || b".eE+-".contains(&c))
+        {
+            return None;
+        }
+        if digits.bytes().all(|c| c.is_ascii_digit()) {
+            match digits.parse::<i64>() {
+                Ok(i) => Value::Int(i),
+                Err(_) => Value::Num(digits.parse().ok()?),
+            }
+        } else {
+            Value::Num(digits.parse().ok()?)
+        }
+    };
+    Some(match (negative, value) {
+        (true, Value::Int(i)) => Value::Int(i.wrapping_neg()),
+        (true, Value::Num(n)) => Value::Num(-n),
+        (_, value) => value,
+    })
+}
+
+/// C's `%.{precision}g`, with `alternate` as the `#` flag
+pub fn format_g(n: f64, precision: usize, alternate: bool) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let precision = precision.max(1);
+    let scientific = format!("{:.*e}", precision - 1, n);
+    let exponent: i32 = scientific.split('e').nth(1).and_then(|e| e.parse().ok()).unwrap_or(0);
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        let (mantissa, _) = scientific.split_once('e').unwrap_or((&scientific, ""));
+        let mantissa = if alternate { mantissa.to_string() } else { trim_fraction(mantissa) };
+        format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        let fixed = format!("{:.*}", (precision as i32 - 1 - exponent) as usize, n);
+        if alternate {
+            fixed
+        } else {
+            trim_fraction(&fixed)
+        }
+    };
+    text
+}
+
+/// Drop trailing zeros after a decimal point, and the point if bare
+fn trim_fraction(text: &str) -> String {
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// A table key, normalized so 1 and 1.0 are the same key
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Bool(bool),
+    Int(i64),
+    Num(u64),
+    Str(LuaStr),
+    Ref(usize),
+}
+
+impl Key {
+    fn of(value: &Value) -> Option<Key> {
+        Some(match value {
+            Value::Nil => return None,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Int(i) => Key::Int(*i),
+            Value::Num(n) => match float_to_integer(*n) {
+                Some(i) => Key::Int(i),
+                None if n.is_nan() => return None,
+                None => Key::Num(n.to_bits()),
+            },
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Table(t) => Key::Ref(t.ptr()),
+            Value::Function(f) => Key::Ref(f.ptr()),
+        })
+    }
+}
+
+/// Why a key can't be stored in a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyError {
+    Nil,
+    NaN,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Nil => write!(f, "index is nil"),
+            KeyError::NaN => write!(f, "index is NaN"),
+        }
+    }
+}
+
+/// A Lua table: an array part for keys 1..n and insertion-ordered
+/// entries for the rest, so `next` can walk it while it changes
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    /// Entries whose value was set to nil
+    removed: usize,
+    pub metatable: Option<TableRef>,
+}
+
+impl Table {
+    pub fn get(&self, key: &Value) -> Value {
+        let Some(key) = Key::of(key) else { return Value::Nil };
+        if let Key::Int(i) = key {
+            if i >= 1 && (i as u64) <= self.array.len() as u64 {
+                return self.array[i as usize - 1].clone();
+            }
+        }
+        self.index.get(&key).map_or(Value::Nil, |&i| self.entries[i].1.clone())
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), KeyError> {
+        let Some(hashed) = Key::of(&key) else {
+            return Err(if key.is_nil() { KeyError::Nil } else { KeyError::NaN });
+        };
+        if let Key::Int(i) = hashed {
+            let len = self.array.len() as u64;
+            if i >= 1 && (i as u64) <= len {
+                // Cleared slots stay in place so `next` can continue past them
+                self.array[i as usize - 1] = value;
+                return Ok(());
+            }
+            if i >= 1 && i as u64 == len + 1 && !value.is_nil() {
+                self.remove_entry(&hashed);
+                self.array.push(value);
+                self.migrate();
+                return Ok(());
+            }
+        }
+        match self.index.get(&hashed) {
+            Some(&i) => {
+                let slot = &mut self.entries[i].1;
+                match (slot.is_nil(), value.is_nil()) {
+                    (false, true) => self.removed += 1,
+                    (true, false) => self.removed -= 1,
+                    _ => {}
+                }
+                *slot = value;
+            }
+            None if value.is_nil() => {}
+            None => {
+                if self.removed > 8 && self.removed * 2 > self.entries.len() {
+                    self.compact();
+                }
+                // Whole floats are stored as the integer keys they equal
+                let key = match hashed {
+                    Key::Int(i) => Value::Int(i),
+                    _ => key,
+                };
+                self.index.insert(hashed, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, key: &Key) {
+        if let Some(&i) = self.index.get(key) {
+            if !self.entries[i].1.is_nil() {
+                self.entries[i].1 = Value::Nil;
+                self.removed += 1;
+            }
+        }
+    }
+
+    /// Move entries that continue the array part into it
+    fn migrate(&mut self) {
+        loop {
+            let next = Key::Int(self.array.len() as i64 + 1);
+            let Some(&i) = self.index.get(&next) else { return };
+            if self.entries[i].1.is_nil() {
+                return;
+            }
+            let value = std::mem::take(&mut self.entries[i].1);
+            self.removed += 1;
+            self.array.push(value);
+        }
+    }
+
+    /// Drop removed entries
+    fn compact(&mut self) {
+        self.entries.retain(|(_, value)| !value.is_nil());
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (key, _))| Some((Key::of(key)?, i)))
+            .collect();
+        self.removed = 0;
+    }
+
+    /// The border `#` reports
+    pub fn len(&self) -> usize {
+        self.array.iter().rposition(|value| !value.is_nil()).map_or(0, |i| i + 1)
+    }
+
+    /// The entry after `key` in traversal order, None at the end, or Err
+    /// if `key` isn't in the table
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, ()> {
+        let mut position = match key {
+            Value::Nil => 0,
+            key => match Key::of(key) {
+                Some(Key::Int(i)) if i >= 1 && (i as u64) <= self.array.len() as u64 => i as usize,
+                Some(hashed) => self.array.len() + self.index.get(&hashed).ok_or(())? + 1,
+                None => return Err(()),
+            },
+        };
+        while position < self.array.len() {
+            if !self.array[position].is_nil() {
+                return Ok(Some((Value::Int(position as i64 + 1), self.array[position].clone())));
+            }
+            position += 1;
+        }
+        Ok(self.entries[position - self.array.len()..]
+            .iter()
+            .find(|(_, value)| !value.is_nil())
+            .cloned())
+    }
+
+    /// Append to the array part
+    pub fn push(&mut self, value: Value) {
+        let key = Value::Int(self.len() as i64 + 1);
+        let _ = self.set(key, value);
+    }
+}
+
+/// A shared reference to a table
+#[derive(Clone, Default)]
+pub struct TableRef(Arc<Mutex<Table>>);
+
+impl TableRef {
+    pub fn new() -> TableRef {
+        TableRef::default()
+    }
+
+    /// A table holding `values` at 1..n
+    pub fn from_values(values: impl IntoIterator<Item = Value>) -> TableRef {
+        let table = TableRef::new();
+        {
+            let mut t = table.lock();
+            for value in values {
+                t.push(value);
+            }
+        }
+        table
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Table> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn ptr(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+
+    pub fn ptr_eq(&self, other: &TableRef) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        self.lock().get(key)
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::from(key))
+    }
+
+    pub fn set(&self, key: Value, value: Value) -> Result<(), KeyError> {
+        self.lock().set(key, value)
+    }
+
+    /// Set a string key, which can't fail
+    pub fn set_str(&self, key: &str, value: Value) {
+        let _ = self.set(Value::from(key), value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn metatable(&self) -> Option<TableRef> {
+        self.lock().metatable.clone()
+    }
+
+    /// A field of the metatable, such as `__index`
+    pub fn metamethod(&self, name: &str) -> Value {
+        self.metatable().map_or(Value::Nil, |mt| mt.get_str(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_parse_and_print_like_lua() {
+        assert_eq!(parse_number("42"), Some(Value::Int(42)));
+        assert_eq!(parse_number(" -0x10 "), Some(Value::Int(-16)));
+        assert_eq!(parse_number("1e2"), Some(Value::Num(100.0)));
+        assert_eq!(parse_number("9223372036854775808"), Some(Value::Num(2f64.powi(63))));
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("0x"), None);
+
+        assert_eq!(Value::Num(3.0).to_string(), "3.0");
+        assert_eq!(Value::Num(0.1).to_string(), "0.1");
+        assert_eq!(Value::Num(1e15).to_string(), "1e+15");
+        assert_eq!(Value::Num(-1.5e-7).to_string(), "-1.5e-07");
+        assert_eq!(format_g(100.0, 6, false), "100");
+        assert_eq!(format_g(0.0001234, 3, true), "0.000123");
+    }
+
+    #[test]
+    fn tables_keep_an_array_part_and_ordered_entries() {
+        let mut t = Table::default();
+        t.set(Value::Int(2), "b".into()).unwrap();
+        t.set("x".into(), Value::Int(1)).unwrap();
+        t.set(Value::Num(1.0), "a".into()).unwrap();
+        assert_eq!(t.len(), 2);
+        assert_eq!(t.get(&Value::Int(1)), Value::from("a"));
+        assert_eq!(t.set(Value::Nil, Value::Int(1)), Err(KeyError::Nil));
+        assert_eq!(t.set(Value::Num(f64::NAN), Value::Int(1)), Err(KeyError::NaN));
+
+        let mut keys = Vec::new();
+        let mut key = Value::Nil;
+        while let Some((k, _)) = t.next(&key).unwrap() {
+            keys.push(k.clone());
+            key = k;
+        }
+        assert_eq!(keys, [Value::Int(1), Value::Int(2), Value::from("x")]);
+
+        // Clearing the last element shrinks the border
+        t.set(Value::Int(2), Value::Nil).unwrap();
+        assert_eq!(t.len(), 1);
+        assert!(t.next(&Value::from("missing")).is_err());
+    }
+}
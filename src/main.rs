@@ -2,135 +2,254 @@
 //!
 //! A graphical frontend for the GB3000 Game Boy emulator.
 
+mod audio;
+mod battery;
+mod bench;
+mod capture;
+mod config;
+mod debugger;
+mod driver;
+mod emu_thread;
+mod filters;
+mod golden;
+mod headless;
+mod input;
+mod memory_viewer;
+mod mixer;
+mod mobile_net;
+mod present;
+mod savestates;
+mod single_step;
+mod sound_viewer;
 mod test_runner;
 mod ui;
+mod vram_viewer;
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use gb3000::{palettes, Button, Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
-use minifb::{Key, Window, WindowOptions};
+use gb3000::infrared::InfraredLink;
+use gb3000::mobile::{MobileAdapter, MockBackend};
+use gb3000::serial::LinkCable;
+use gb3000::{Emulator, EmulatorOptions, GbsFile};
+use minifb::{Key, ScaleMode, Window, WindowOptions};
 use std::collections::VecDeque;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use audio::AudioOutput;
+use battery::BatterySaver;
+use capture::{RecordFormat, Recorder};
+use config::{Config, MobileSetting, AUDIO_BUFFER_SIZES, MODELS, PALETTES, STEREO_SEPARATIONS, TURBO_RATES, WINDOW_SCALES};
+use debugger::Debugger;
+use memory_viewer::MemoryViewer;
+use emu_thread::{AudioBuffer, EmuThread, Partner, Session, AUDIO_BUFFER_SIZE, FRAME_TIME_NS};
+use input::Input;
+use mixer::Mixer;
+use mobile_net::NetBackend;
+use present::{present, Viewport};
+use sound_viewer::SoundViewer;
 use ui::{EmulatorState, RomInfo, Ui, UiAction};
+use vram_viewer::VramViewer;
 
-/// Target frame time - Game Boy native rate (59.7275 FPS)
-/// CPU: 4,194,304 Hz / 70,224 cycles per frame = 59.7275 FPS
-const FRAME_TIME_NS: u64 = 16_742_706; // ~59.7275 FPS
+/// UI window dimensions (the filtered Game Boy screen fills the window)
+const UI_WIDTH: usize = filters::OUT_WIDTH;
+const UI_HEIGHT: usize = filters::OUT_HEIGHT;
 
-/// UI window dimensions
-const UI_WIDTH: usize = 640;
-const UI_HEIGHT: usize = 576;
+/// Length of the rolling gameplay recording (F10)
+const RECORD_SECONDS: f64 = 20.0;
 
-/// Audio buffer size
-const AUDIO_BUFFER_SIZE: usize = 4096;
+/// Idle cycles after which the game counts as crashed
+const HANG_CYCLES: u64 = 4_194_304 * 5; // 5 seconds of emulated time
 
-fn setup_audio(
-    audio_buffer: Arc<Mutex<VecDeque<f32>>>,
-    sample_rate: u32,
-) -> Option<cpal::Stream> {
-    let host = cpal::default_host();
-    let device = host.default_output_device()?;
-
-    let config = cpal::StreamConfig {
-        channels: 2,
-        sample_rate: cpal::SampleRate(sample_rate),
-        buffer_size: cpal::BufferSize::Default,
-    };
-
-    let mut last_sample = 0.0f32;
-
-    let stream = device
-        .build_output_stream(
-            &config,
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let mut buffer = audio_buffer.lock().unwrap();
-                for sample in data.iter_mut() {
-                    if let Some(s) = buffer.pop_front() {
-                        *sample = s;
-                        last_sample = s;
-                    } else {
-                        last_sample *= 0.9;
-                        *sample = last_sample;
-                    }
-                }
-            },
-            |err| eprintln!("Audio error: {}", err),
-            None,
-        )
-        .ok()?;
-
-    stream.play().ok()?;
-    Some(stream)
-}
-
-/// Scale Game Boy framebuffer to UI size (optimized)
-fn scale_framebuffer(src: &[u8], dst: &mut [u32], palette: &[u32; 4]) {
-    const SCALE: usize = UI_WIDTH / SCREEN_WIDTH; // 4x scale
-    
-    // Pre-convert palette lookups for each source pixel
-    for src_y in 0..SCREEN_HEIGHT {
-        let src_row_start = src_y * SCREEN_WIDTH;
-        let dst_row_start = src_y * SCALE * UI_WIDTH;
-        
-        for src_x in 0..SCREEN_WIDTH {
-            let color = palette[(src[src_row_start + src_x] & 0x03) as usize];
-            let dst_x_start = src_x * SCALE;
-            
-            // Fill the scaled block
-            for dy in 0..SCALE {
-                let row_offset = (dy * UI_WIDTH) + dst_x_start;
-                for dx in 0..SCALE {
-                    dst[dst_row_start + row_offset + dx] = color;
-                }
-            }
+/// Audio sink that feeds the output stream's buffer, dropping the oldest
+/// samples if emulation runs ahead
+///
+/// `volume` holds the master volume as `f32` bits.
+fn audio_sink(
+    audio_buffer: &AudioBuffer,
+    volume: &Arc<AtomicU32>,
+) -> impl FnMut(&[f32]) + Send + 'static {
+    let audio_buffer = Arc::clone(audio_buffer);
+    let volume = Arc::clone(volume);
+    move |samples| {
+        if let Ok(mut ab) = audio_buffer.lock() {
+            let volume = f32::from_bits(volume.load(Ordering::Relaxed));
+            ab.extend(samples.iter().map(|s| s * volume));
+            let excess = ab.len().saturating_sub(AUDIO_BUFFER_SIZE);
+            ab.drain(..excess);
         }
     }
 }
 
-fn load_rom_file(path: &PathBuf) -> Result<Vec<u8>, String> {
+fn load_rom_file(path: &Path) -> Result<Vec<u8>, String> {
     fs::read(path).map_err(|e| format!("Failed to read ROM: {}", e))
 }
 
-/// Get the save file path for a ROM (same name with .sav extension)
-fn get_save_path(rom_path: &PathBuf) -> PathBuf {
-    rom_path.with_extension("sav")
+/// Base name for captures of the current ROM
+fn capture_name(rom_path: Option<&Path>) -> String {
+    rom_path
+        .and_then(|p| p.file_stem())
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "screenshot".to_string())
 }
 
-/// Load save data if it exists
-fn load_save(emulator: &mut Emulator, rom_path: &PathBuf) {
-    let save_path = get_save_path(rom_path);
-    if save_path.exists() {
-        if let Ok(data) = fs::read(&save_path) {
-            emulator.load_ram(&data);
-            println!("Loaded save: {}", save_path.display());
+/// Save the current frame as a PNG in the screenshots directory
+fn take_screenshot(emulator: &Emulator, palette: &[u32; 4], rom_path: Option<&Path>) {
+    match capture::save_screenshot(&emulator.screenshot_rgba(palette), &capture_name(rom_path)) {
+        Ok(path) => println!("Saved screenshot: {}", path.display()),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Save to the selected slot, reporting the result on screen
+fn save_state_slot(emulator: &Emulator, ui: &mut Ui) {
+    let Some(path) = ui.current_rom.clone() else { return };
+    match savestates::save_slot(emulator, &path, ui.state_slot) {
+        Ok(()) => ui.show_message(format!("Saved state {}", ui.state_slot)),
+        Err(e) => ui.show_message(e),
+    }
+}
+
+/// Load the selected slot, reporting the result on screen
+///
+/// Returns true if a state was loaded.
+fn load_state_slot(emulator: &mut Emulator, ui: &mut Ui) -> bool {
+    let Some(path) = ui.current_rom.clone() else { return false };
+    match savestates::load_slot(emulator, &path, ui.state_slot) {
+        Ok(()) => {
+            ui.show_message(format!("Loaded state {}", ui.state_slot));
+            true
         }
+        Err(e) => {
+            ui.show_message(e);
+            false
+        }
+    }
+}
+
+/// Apply the audio settings to the emulator
+fn apply_audio_options(emulator: &mut Emulator, ui: &Ui) {
+    emulator.set_options(EmulatorOptions { audio: ui.config.audio_options(), ..emulator.options() });
+}
+
+/// Reset into the loaded game's forced model, if it has one
+fn reset_emulator(emulator: &mut Emulator, ui: &Ui) {
+    let model = ui
+        .current_game
+        .and_then(|hash| ui.config.games.get(&hash))
+        .and_then(|game| game.model);
+    emulator.reset_for_model(model.unwrap_or_default());
+}
+
+/// Start tracking settings for a newly loaded ROM and restore its save
+/// state slot (the palette and model are looked up when used)
+fn restore_game_settings(ui: &mut Ui, rom: &[u8]) {
+    let hash = config::header_hash(rom);
+    ui.current_game = Some(hash);
+    let title = ui.rom_info.as_ref().map(|info| info.title.clone()).unwrap_or_default();
+    let game = ui.config.game_mut(hash);
+    game.title = title;
+    ui.state_slot = game.state_slot;
+}
+
+/// Whether `path` names a GBS sound file rather than a ROM
+fn is_gbs(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gbs"))
+}
+
+/// Header information for the ROM info panel, with the database name as
+/// the title when the `romdb` feature knows the game
+fn describe_rom(rom: &[u8], path: &Path) -> Option<RomInfo> {
+    if is_gbs(path) {
+        let gbs = GbsFile::parse(rom).ok()?;
+        let details = vec![
+            ("Title", gbs.title().to_string()),
+            ("Author", gbs.author().to_string()),
+            ("Copyright", gbs.copyright().to_string()),
+            ("Songs", gbs.song_count().to_string()),
+            ("Play rate", format!("{:.2} Hz", gbs.play_rate())),
+        ];
+        return Some(RomInfo {
+            title: gbs.title().to_string(),
+            details,
+        });
     }
+    let info = Emulator::parse_rom_info(rom)?;
+    #[cfg(feature = "romdb")]
+    let title = gb3000::RomInfo::lookup(rom).map_or(info.title.clone(), |m| m.entry.name.clone());
+    #[cfg(not(feature = "romdb"))]
+    let title = info.title.clone();
+    let yes_no = |ok: bool| if ok { "Yes" } else { "No" };
+    let details = vec![
+        ("Header title", info.title.clone()),
+        ("Cartridge", format!("{} ({:02X})", info.cart_type, info.cart_type_code)),
+        ("ROM size", format!("{} (file {} KB)", info.rom_size, info.file_size / 1024)),
+        ("RAM size", info.ram_size.clone()),
+        ("Publisher", info.publisher().unwrap_or("Unknown").to_string()),
+        (
+            "Color",
+            match info.cgb_support() {
+                gb3000::CgbSupport::Incompatible => "No",
+                gb3000::CgbSupport::Compatible => "Enhanced",
+                gb3000::CgbSupport::Only => "Required",
+            }
+            .to_string(),
+        ),
+        ("Super GB", yes_no(info.sgb_support()).to_string()),
+        (
+            "Region",
+            match info.destination() {
+                gb3000::Destination::Japan => "Japan".to_string(),
+                gb3000::Destination::Overseas => "Overseas".to_string(),
+                gb3000::Destination::Unknown(code) => format!("Unknown ({:02X})", code),
+            },
+        ),
+        ("Version", info.version.to_string()),
+        ("Header check", yes_no(info.header_checksum == info.computed_header_checksum).to_string()),
+        ("Global check", yes_no(info.global_checksum == info.computed_global_checksum).to_string()),
+        ("Logo", yes_no(info.logo_valid).to_string()),
+    ];
+    Some(RomInfo { title, details })
 }
 
-/// Save game data to file
-fn save_game(emulator: &Emulator, rom_path: &PathBuf) {
-    if let Some(data) = emulator.save_ram() {
-        let save_path = get_save_path(rom_path);
-        if let Err(e) = fs::write(&save_path, &data) {
-            eprintln!("Failed to save: {}", e);
-        } else {
-            println!("Saved game: {}", save_path.display());
+/// Tell the user if the ROM's header (or the ROM database) says it's a
+/// bad dump
+fn warn_about_bad_dump(ui: &mut Ui, rom: &[u8]) {
+    #[cfg(feature = "romdb")]
+    if let Some(m) = gb3000::RomInfo::lookup(rom) {
+        if m.entry.bad_dump {
+            ui.show_message("Known bad dump");
+        } else if m.overdump {
+            ui.show_message("Overdumped ROM (extra data at the end)");
         }
+        return;
+    }
+    let validation = Emulator::verify_rom(rom);
+    if validation.would_lock_up() {
+        ui.show_message("Bad ROM header (corrupt dump?)");
+    } else if validation.size == gb3000::RomSizeCheck::Overdump {
+        ui.show_message("Overdumped ROM (extra data at the end)");
+    } else if validation.size == gb3000::RomSizeCheck::Truncated {
+        ui.show_message("Truncated ROM (smaller than its header says)");
+    } else if !validation.global_checksum_ok {
+        ui.show_message("ROM checksum mismatch");
+    }
+}
+
+/// Remember the current game's save state slot
+fn remember_game_settings(ui: &mut Ui) {
+    if let Some(hash) = ui.current_game {
+        let slot = ui.state_slot;
+        ui.config.game_mut(hash).state_slot = slot;
     }
 }
 
-fn update_input(emulator: &mut Emulator, window: &Window) {
-    emulator.set_button(Button::Right, window.is_key_down(Key::Right));
-    emulator.set_button(Button::Left, window.is_key_down(Key::Left));
-    emulator.set_button(Button::Up, window.is_key_down(Key::Up));
-    emulator.set_button(Button::Down, window.is_key_down(Key::Down));
-    emulator.set_button(Button::A, window.is_key_down(Key::Z));
-    emulator.set_button(Button::B, window.is_key_down(Key::X));
-    emulator.set_button(Button::Select, window.is_key_down(Key::Space));
-    emulator.set_button(Button::Start, window.is_key_down(Key::Enter));
+/// Write the settings file, reporting failures on the console
+fn save_config(config: &Config) {
+    if let Err(e) = config.save() {
+        eprintln!("{}", e);
+    }
 }
 
 fn main() {
@@ -141,69 +260,126 @@ fn main() {
         run_test_mode(&args);
         return;
     }
+    if args.len() > 1 && args[1] == "--test-screenshot" {
+        run_screenshot_test_mode(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "--golden" {
+        run_golden_mode(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "run" {
+        run_headless_mode(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "bench" {
+        run_bench_mode(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "--test-sm83" {
+        run_sm83_test_mode(&args);
+        return;
+    }
 
-    // Initial ROM from command line
+    #[cfg(feature = "lua")]
+    let (args, lua_script) = take_lua_arg(args);
+
+    // Initial ROM from command line, and optionally one for player 2
     let initial_rom: Option<PathBuf> = if args.len() > 1 {
         Some(PathBuf::from(&args[1]))
     } else {
         None
     };
+    let partner_rom = args.get(2).map(PathBuf::from);
 
-    // Create window
-    let mut window = Window::new(
-        "GB3000 - Game Boy Emulator",
-        UI_WIDTH,
-        UI_HEIGHT,
-        WindowOptions::default(),
-    )
-    .expect("Failed to create window");
+    let config = Config::load();
 
-    // Don't use minifb's rate limiting - we do our own
-    window.set_target_fps(0);
+    // Create window
+    let scale = config.window_scale as usize;
+    let mut windowed_size = (UI_WIDTH * scale, UI_HEIGHT * scale);
+    let mut window = create_window(windowed_size, false);
+    let mut fullscreen = false;
+    let mut debugger: Option<Debugger> = None;
+    let mut memory_viewer: Option<MemoryViewer> = None;
+    let mut vram_viewer: Option<VramViewer> = None;
+    let mut sound_viewer: Option<SoundViewer> = None;
+    let mut hang_warned = false;
 
     // Create UI and emulator
-    let mut ui = Ui::new();
-    let mut emulator = Emulator::new();
-    let palette = palettes::GRAYSCALE;
+    let mut ui = Ui::new(config);
+    let mut session = Session::new(Emulator::new());
 
     // Audio setup
-    let audio_buffer: Arc<Mutex<VecDeque<f32>>> =
-        Arc::new(Mutex::new(VecDeque::with_capacity(AUDIO_BUFFER_SIZE)));
-    let _audio_stream = setup_audio(Arc::clone(&audio_buffer), emulator.audio_sample_rate());
+    let audio_buffer: AudioBuffer = Arc::new(Mutex::new(VecDeque::with_capacity(AUDIO_BUFFER_SIZE)));
+    let mut audio = AudioOutput::open(
+        Arc::clone(&audio_buffer),
+        session.emulator.audio_sample_rate(),
+        ui.config.audio_device.clone(),
+        ui.config.audio_buffer,
+    );
+    let volume = Arc::new(AtomicU32::new(ui.config.output_volume().to_bits()));
+    session.emulator.set_audio_sink(audio_sink(&audio_buffer, &volume));
 
-    // Framebuffer
+    // Framebuffer, the same scaled to the window, and the filters' buffers
     let mut buffer = vec![0u32; UI_WIDTH * UI_HEIGHT];
+    let mut presented = Vec::new();
+    let mut scratch = filters::Scratch::default();
 
-    // FPS tracking - use frames in last second for accurate current FPS
-    let mut frames_this_second = 0u32;
+    // FPS tracking - emulated frames over the last second
+    let mut frames_at_last_fps = 0u64;
     let mut last_fps_time = Instant::now();
 
     // Load initial ROM if provided
     if let Some(path) = initial_rom {
         if let Ok(rom) = load_rom_file(&path) {
-            if let Some(info) = Emulator::parse_rom_info(&rom) {
+            if let Some(info) = describe_rom(&rom, &path) {
                 ui.add_recent_rom(path.clone(), info.title.clone());
-                ui.rom_info = Some(RomInfo {
-                    title: info.title,
-                    cart_type: info.cart_type,
-                    rom_size: info.rom_size,
-                    ram_size: info.ram_size,
-                });
+                ui.rom_info = Some(info);
             }
-            emulator.load_rom(&rom);
-            emulator.reset();
-            load_save(&mut emulator, &path); // Load existing save
-            ui.current_rom = Some(path);
-            ui.state = EmulatorState::Running;
+            start_game(&mut session, &mut ui, &rom, path);
+        }
+    }
+    if let (Some(path), Some(_)) = (partner_rom, &ui.current_rom) {
+        match load_rom_file(&path) {
+            Ok(rom) => start_partner(&mut session, &ui, &rom, &path, &audio_buffer, &volume),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    #[cfg(feature = "lua")]
+    if let (Some(path), Some(_)) = (lua_script, &ui.current_rom) {
+        match headless::load_script(&path, &mut session.emulator) {
+            Ok(script) => session.script = Some(script),
+            Err(e) => eprintln!("{}", e),
         }
     }
 
+    // Emulation runs on its own thread from here on, paced by the audio
+    // device while there is one
+    session.audio_live = audio.is_live();
+    let emu = EmuThread::spawn(session, Some(Arc::clone(&audio_buffer)));
+
     // Main loop
     while window.is_open() {
         let frame_start = Instant::now();
+        let palette = PALETTES[ui.palette_index()].1;
+
+        // Fullscreen toggle: F11 or Alt+Enter
+        let alt = window.is_key_down(Key::LeftAlt) || window.is_key_down(Key::RightAlt);
+        if window.is_key_pressed(Key::F11, minifb::KeyRepeat::No)
+            || (alt && window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No))
+        {
+            if !fullscreen {
+                windowed_size = window.get_size();
+            }
+            fullscreen = !fullscreen;
+            let size = if fullscreen { ui.config.fullscreen_size } else { windowed_size };
+            window = create_window(size, fullscreen);
+        }
 
-        // Update mouse state
+        // Update mouse state, in UI buffer coordinates
         if let Some((mx, my)) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
+            let (width, height) = window.get_size();
+            let (mx, my) = Viewport::fit(width, height).to_ui(mx, my, UI_WIDTH, UI_HEIGHT);
             let mouse_down = window.get_mouse_down(minifb::MouseButton::Left);
             ui.update_mouse(mx, my, mouse_down);
         }
@@ -214,6 +390,182 @@ fn main() {
                 EmulatorState::StartScreen => break,
                 EmulatorState::Running => ui.state = EmulatorState::Paused,
                 EmulatorState::Paused => ui.state = EmulatorState::Running,
+                EmulatorState::StateBrowser | EmulatorState::RomInfo => ui.state = EmulatorState::Paused,
+                EmulatorState::Settings if ui.rebinding.is_some() => ui.rebinding = None,
+                EmulatorState::Settings => close_settings(&mut ui),
+            }
+        }
+
+        // Key capture for rebinding on the settings screen
+        if let (EmulatorState::Settings, Some(i)) = (&ui.state, ui.rebinding) {
+            let pressed = window.get_keys_pressed(minifb::KeyRepeat::No);
+            if let Some(key) = pressed.into_iter().find(|&k| config::is_bindable(k)) {
+                ui.config.keys.0[i] = key;
+                ui.rebinding = None;
+            }
+        }
+
+        // Reconnect audio after the device went away
+        if let Some(message) = audio.poll() {
+            ui.show_message(message);
+        }
+
+        let mut session = emu.lock();
+
+        // Save state hotkeys: 0-9 pick a slot, F5 saves to it, F8 loads it
+        if !matches!(ui.state, EmulatorState::StartScreen | EmulatorState::Settings) {
+            const SLOT_KEYS: [Key; 10] = [
+                Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+                Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+            ];
+            for (slot, &key) in SLOT_KEYS.iter().enumerate() {
+                if window.is_key_pressed(key, minifb::KeyRepeat::No) {
+                    ui.state_slot = slot as u8;
+                    ui.show_message(format!("Slot {}", slot));
+                }
+            }
+            if window.is_key_pressed(Key::F5, minifb::KeyRepeat::No) {
+                save_state_slot(&session.emulator, &mut ui);
+            }
+            if window.is_key_pressed(Key::F8, minifb::KeyRepeat::No) {
+                load_state_slot(&mut session.emulator, &mut ui);
+                session.driver.clear_rewind();
+            }
+        }
+
+        // Hotkeys that double as bindable keys only work while unbound
+        let linked = session.partner.is_some();
+        let is_free = |key: Key| {
+            let bound = ui.config.keys.0.contains(&key) || (linked && ui.config.keys2.0.contains(&key));
+            !bound && ui.rebinding.is_none()
+        };
+        let (period_is_free, m_is_free) = (is_free(Key::Period), is_free(Key::M));
+
+        // Frame advance: the period key pauses, then runs one frame per
+        // press (one scanline with Shift), unless it's bound to a button
+        if period_is_free && window.is_key_pressed(Key::Period, minifb::KeyRepeat::Yes) {
+            match ui.state {
+                EmulatorState::Running => {
+                    ui.state = EmulatorState::Paused;
+                    ui.frame_advance = true;
+                    ui.show_message("Frame advance (Esc to resume)");
+                }
+                EmulatorState::Paused => {
+                    ui.frame_advance = true;
+                    let scanline = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                    session.input.poll(&window, &ui.config.keys, ui.config.turbo_rate);
+                    if let Some(partner) = session.partner.as_mut() {
+                        partner.input.poll(&window, &ui.config.keys2, ui.config.turbo_rate);
+                    }
+                    session.frame_advance(scanline);
+                    let emulator = &session.emulator;
+                    ui.show_message(if scanline {
+                        format!("Frame {} line {}", emulator.frame_count(), emulator.peek(0xFF44))
+                    } else {
+                        format!("Frame {}", emulator.frame_count())
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Mute hotkey, unless M is bound to a button
+        if m_is_free && window.is_key_pressed(Key::M, minifb::KeyRepeat::No) {
+            ui.config.muted = !ui.config.muted;
+            ui.show_message(if ui.config.muted { "Muted" } else { "Sound on" });
+            volume.store(ui.config.output_volume().to_bits(), Ordering::Relaxed);
+        }
+
+        // Screenshot hotkey
+        if window.is_key_pressed(Key::F12, minifb::KeyRepeat::No) && ui.state != EmulatorState::StartScreen {
+            take_screenshot(&session.emulator, &palette, ui.current_rom.as_deref());
+        }
+
+        // Recording hotkey: F10 starts, F10 again saves a GIF (Shift+F10 saves an APNG)
+        if window.is_key_pressed(Key::F10, minifb::KeyRepeat::No) && ui.state != EmulatorState::StartScreen {
+            match session.recorder.take() {
+                Some(rec) => {
+                    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                    let format = if shift { RecordFormat::Apng } else { RecordFormat::Gif };
+                    match rec.save_to_dir(&capture_name(ui.current_rom.as_deref()), format, &palette) {
+                        Ok(path) => println!(
+                            "Saved recording: {} ({:.1}s, {} unique frames)",
+                            path.display(),
+                            rec.duration_secs(),
+                            rec.unique_frames()
+                        ),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                None => {
+                    session.recorder = Some(Recorder::new(RECORD_SECONDS));
+                    println!("Recording started (F10 to save)");
+                }
+            }
+        }
+
+        // Player 2: F3 opens a game on a second console beside this one,
+        // linked by cable and infrared; F3 again puts it away
+        if window.is_key_pressed(Key::F3, minifb::KeyRepeat::No) && ui.current_rom.is_some() && session.gbs.is_none() {
+            if session.partner.is_some() {
+                stop_partner(&mut session, &ui, &audio_buffer, &volume);
+                ui.show_message("Player 2 left");
+            } else {
+                drop(session);
+                let picked = Ui::open_file_dialog();
+                session = emu.lock();
+                if let Some(path) = picked {
+                    match load_rom_file(&path) {
+                        Ok(rom) => {
+                            start_partner(&mut session, &ui, &rom, &path, &audio_buffer, &volume);
+                            ui.show_message("Player 2 joined (F3 to leave)");
+                        }
+                        Err(e) => ui.show_message(e),
+                    }
+                }
+            }
+        }
+
+        // Debug windows: their key opens them, the same key or the close
+        // button closes them
+        if ui.state != EmulatorState::StartScreen {
+            if window.is_key_pressed(Key::F9, minifb::KeyRepeat::No) {
+                toggle(&mut debugger, Debugger::open);
+            }
+            if window.is_key_pressed(Key::F6, minifb::KeyRepeat::No) {
+                toggle(&mut memory_viewer, MemoryViewer::open);
+            }
+            if window.is_key_pressed(Key::F7, minifb::KeyRepeat::No) {
+                toggle(&mut vram_viewer, VramViewer::open);
+            }
+            if window.is_key_pressed(Key::F4, minifb::KeyRepeat::No) {
+                toggle(&mut sound_viewer, SoundViewer::open);
+            }
+        }
+        if debugger.as_mut().is_some_and(|d| !d.update(&mut session)) {
+            debugger = None;
+        }
+        if memory_viewer.as_mut().is_some_and(|m| !m.update(&mut session.emulator)) {
+            memory_viewer = None;
+        }
+        if vram_viewer.as_mut().is_some_and(|v| !v.update(&session.emulator, &palette)) {
+            vram_viewer = None;
+        }
+        if sound_viewer.as_mut().is_some_and(|s| !s.update(&mut session.emulator)) {
+            sound_viewer = None;
+        }
+        if debugger.is_none() {
+            // Nothing left to resume from, or to show interrupts in; a
+            // breakpoint left behind would stop frames with no window
+            // explaining why
+            session.debug_break = false;
+            session.emulator.clear_breakpoints();
+            session.emulator.set_interrupt_logging(false);
+        }
+        if sound_viewer.is_none() {
+            // Muting is only for listening in the viewer
+            for channel in 0..4 {
+                session.emulator.set_channel_muted(channel, false);
             }
         }
 
@@ -224,58 +576,96 @@ fn main() {
             }
 
             EmulatorState::Running => {
-                update_input(&mut emulator, &window);
-                emulator.run_frame();
-                scale_framebuffer(emulator.framebuffer(), &mut buffer, &palette);
-                
-                // Audio
-                let samples = emulator.audio_samples();
-                if !samples.is_empty() {
-                    if let Ok(mut ab) = audio_buffer.lock() {
-                        ab.extend(samples);
-                        while ab.len() > AUDIO_BUFFER_SIZE {
-                            ab.pop_front();
-                        }
+                let combo = session.input.poll(&window, &ui.config.keys, ui.config.turbo_rate);
+                if combo && ui.config.reset_combo {
+                    // Cartridge RAM stays in place, so the battery saver carries on
+                    session.emulator.soft_reset();
+                    session.driver.clear_rewind();
+                    ui.show_message("Soft reset");
+                }
+                if let Some(partner) = session.partner.as_mut() {
+                    if partner.input.poll(&window, &ui.config.keys2, ui.config.turbo_rate) && ui.config.reset_combo {
+                        partner.emulator.soft_reset();
+                        ui.show_message("Player 2 soft reset");
                     }
                 }
+                // Left and Right change songs while a GBS file plays
+                if let Some((gbs, song)) = session.gbs.take() {
+                    let count = gbs.song_count() as u16;
+                    let step = if window.is_key_pressed(Key::Right, minifb::KeyRepeat::Yes) {
+                        1
+                    } else if window.is_key_pressed(Key::Left, minifb::KeyRepeat::Yes) {
+                        count - 1
+                    } else {
+                        0
+                    };
+                    let song = ((song as u16 + step) % count) as u8;
+                    if step != 0 {
+                        gbs.play(&mut session.emulator, song);
+                        session.driver.clear_rewind();
+                        ui.show_message(song_label(&gbs, song));
+                    }
+                    session.gbs = Some((gbs, song));
+                }
+                ui.fast_forward = window.is_key_down(Key::Tab);
+                ui.rewinding = window.is_key_down(Key::Backspace);
+                draw_screens(&session, &ui, &palette, &mut buffer, &mut scratch);
+
+                // Tell the player once when the game stops responding
+                let hung = session.emulator.hang_detected();
+                if hung && !hang_warned {
+                    ui.show_message("The game seems to have crashed");
+                }
+                hang_warned = hung;
 
                 // FPS overlay
                 ui.render_fps(&mut buffer, UI_WIDTH);
+                if session.recorder.is_some() {
+                    ui.render_recording(&mut buffer, UI_WIDTH);
+                }
                 
                 UiAction::None
             }
 
             EmulatorState::Paused => {
-                scale_framebuffer(emulator.framebuffer(), &mut buffer, &palette);
-                ui.render_pause_menu(&mut buffer, UI_WIDTH, UI_HEIGHT)
+                draw_screens(&session, &ui, &palette, &mut buffer, &mut scratch);
+                if ui.frame_advance {
+                    UiAction::None
+                } else {
+                    ui.render_pause_menu(&mut buffer, UI_WIDTH, UI_HEIGHT)
+                }
+            }
+
+            EmulatorState::StateBrowser => {
+                ui.render_state_browser(&mut buffer, UI_WIDTH, UI_HEIGHT, &palette)
             }
+
+            EmulatorState::Settings => ui.render_settings(&mut buffer, UI_WIDTH, UI_HEIGHT),
+
+            EmulatorState::RomInfo => ui.render_rom_info(&mut buffer, UI_WIDTH, UI_HEIGHT),
         };
 
         // Handle UI actions
         match action {
             UiAction::OpenFile => {
-                if let Some(new_path) = Ui::open_file_dialog() {
+                // Let emulation carry on while the dialog is open
+                drop(session);
+                let picked = Ui::open_file_dialog();
+                session = emu.lock();
+                if let Some(new_path) = picked {
                     // Save current game before loading new one
-                    if let Some(ref old_path) = ui.current_rom {
-                        save_game(&emulator, old_path);
-                    }
+                    session.save_battery();
+                    stop_partner(&mut session, &ui, &audio_buffer, &volume);
+                    remember_game_settings(&mut ui);
                     
                     if let Ok(rom) = load_rom_file(&new_path) {
-                        if let Some(info) = Emulator::parse_rom_info(&rom) {
+                        if let Some(info) = describe_rom(&rom, &new_path) {
                             ui.add_recent_rom(new_path.clone(), info.title.clone());
-                            ui.rom_info = Some(RomInfo {
-                                title: info.title,
-                                cart_type: info.cart_type,
-                                rom_size: info.rom_size,
-                                ram_size: info.ram_size,
-                            });
+                            ui.rom_info = Some(info);
                         }
-                        emulator = Emulator::new();
-                        emulator.load_rom(&rom);
-                        emulator.reset();
-                        load_save(&mut emulator, &new_path);
-                        ui.current_rom = Some(new_path);
-                        ui.state = EmulatorState::Running;
+                        session.emulator = Emulator::new();
+                        session.emulator.set_audio_sink(audio_sink(&audio_buffer, &volume));
+                        start_game(&mut session, &mut ui, &rom, new_path);
                         ui.error_message = None;
                     } else {
                         ui.error_message = Some("Failed to load ROM".to_string());
@@ -284,38 +674,136 @@ fn main() {
             }
             UiAction::LoadRom(new_path) => {
                 // Save current game before loading new one
-                if let Some(ref old_path) = ui.current_rom {
-                    save_game(&emulator, old_path);
-                }
+                session.save_battery();
+                stop_partner(&mut session, &ui, &audio_buffer, &volume);
+                remember_game_settings(&mut ui);
                 
                 if let Ok(rom) = load_rom_file(&new_path) {
-                    if let Some(info) = Emulator::parse_rom_info(&rom) {
-                        ui.rom_info = Some(RomInfo {
-                            title: info.title,
-                            cart_type: info.cart_type,
-                            rom_size: info.rom_size,
-                            ram_size: info.ram_size,
-                        });
+                    if let Some(info) = describe_rom(&rom, &new_path) {
+                        ui.rom_info = Some(info);
                     }
-                    emulator = Emulator::new();
-                    emulator.load_rom(&rom);
-                    emulator.reset();
-                    load_save(&mut emulator, &new_path);
-                    ui.current_rom = Some(new_path);
-                    ui.state = EmulatorState::Running;
+                    session.emulator = Emulator::new();
+                    session.emulator.set_audio_sink(audio_sink(&audio_buffer, &volume));
+                    start_game(&mut session, &mut ui, &rom, new_path);
                     ui.error_message = None;
                 }
             }
             UiAction::Resume => ui.state = EmulatorState::Running,
+            UiAction::CycleFilter => ui.video_filter = ui.video_filter.next(),
+            UiAction::ToggleAudioSync => ui.audio_sync = !ui.audio_sync,
+            UiAction::SetSpeed(speed) => ui.speed = speed,
+            UiAction::OpenStates => {
+                if let Some(ref path) = ui.current_rom {
+                    ui.slots = savestates::list_slots(path);
+                }
+                ui.state = EmulatorState::StateBrowser;
+            }
+            UiAction::SelectSlot(slot) => ui.state_slot = slot,
+            UiAction::SaveState => {
+                save_state_slot(&session.emulator, &mut ui);
+                if let Some(ref path) = ui.current_rom {
+                    ui.slots = savestates::list_slots(path);
+                }
+            }
+            UiAction::LoadState => {
+                if load_state_slot(&mut session.emulator, &mut ui) {
+                    session.driver.clear_rewind();
+                    ui.state = EmulatorState::Running;
+                }
+            }
+            UiAction::CloseStates => ui.state = EmulatorState::Paused,
+            UiAction::OpenRomInfo => ui.state = EmulatorState::RomInfo,
+            UiAction::CloseRomInfo => ui.state = EmulatorState::Paused,
+            UiAction::OpenSettings => {
+                drop(session);
+                ui.audio_devices = audio::device_names();
+                session = emu.lock();
+                ui.state = EmulatorState::Settings;
+            }
+            UiAction::CyclePalette => {
+                // With a game loaded the choice is remembered for that game
+                let next = (ui.palette_index() + 1) % PALETTES.len();
+                match ui.current_game {
+                    Some(hash) => ui.config.game_mut(hash).palette = Some(next),
+                    None => ui.config.palette = next,
+                }
+            }
+            UiAction::ChangeVolume(delta) => {
+                // Snap to 10% steps so repeated clicks land on round values
+                ui.config.volume = ((ui.config.volume + delta) * 10.0).round().clamp(0.0, 10.0) / 10.0;
+                ui.config.muted = false;
+                volume.store(ui.config.output_volume().to_bits(), Ordering::Relaxed);
+
+            }
+            UiAction::CycleAudioDevice => {
+                // Default, then each device found when settings opened
+                let devices = &ui.audio_devices;
+                let next = match ui.config.audio_device.as_ref().and_then(|d| devices.iter().position(|n| n == d)) {
+                    None => devices.first(),
+                    Some(i) => devices.get(i + 1),
+                };
+                ui.config.audio_device = next.cloned();
+                // Opening the device can take a while; let emulation run
+                drop(session);
+                audio.configure(ui.config.audio_device.clone(), ui.config.audio_buffer);
+                session = emu.lock();
+            }
+            UiAction::CycleAudioBuffer => {
+                let i = AUDIO_BUFFER_SIZES.iter().position(|&s| s == ui.config.audio_buffer).unwrap_or(0);
+                ui.config.audio_buffer = AUDIO_BUFFER_SIZES[(i + 1) % AUDIO_BUFFER_SIZES.len()];
+                drop(session);
+                audio.configure(ui.config.audio_device.clone(), ui.config.audio_buffer);
+                session = emu.lock();
+            }
+            UiAction::CycleStereoWidth => {
+                let i = STEREO_SEPARATIONS.iter().position(|&s| s == ui.config.stereo_separation).unwrap_or(0);
+                ui.config.stereo_separation = STEREO_SEPARATIONS[(i + 1) % STEREO_SEPARATIONS.len()];
+                apply_audio_options(&mut session.emulator, &ui);
+                if let Some(partner) = session.partner.as_mut() {
+                    apply_audio_options(&mut partner.emulator, &ui);
+                }
+            }
+            UiAction::ToggleHighPass => {
+                ui.config.high_pass = !ui.config.high_pass;
+                apply_audio_options(&mut session.emulator, &ui);
+                if let Some(partner) = session.partner.as_mut() {
+                    apply_audio_options(&mut partner.emulator, &ui);
+                }
+            }
+            UiAction::CycleWindowScale => {
+                let i = WINDOW_SCALES.iter().position(|&s| s == ui.config.window_scale).unwrap_or(0);
+                ui.config.window_scale = WINDOW_SCALES[(i + 1) % WINDOW_SCALES.len()];
+            }
+            UiAction::CycleTurboRate => {
+                let i = TURBO_RATES.iter().position(|&r| r == ui.config.turbo_rate).unwrap_or(0);
+                ui.config.turbo_rate = TURBO_RATES[(i + 1) % TURBO_RATES.len()];
+            }
+            UiAction::ToggleResetCombo => ui.config.reset_combo = !ui.config.reset_combo,
+            UiAction::CycleModel => {
+                if let Some(hash) = ui.current_game {
+                    // Auto, then each model in turn
+                    let game = ui.config.game_mut(hash);
+                    game.model = match game.model.and_then(|m| MODELS.iter().position(|&x| x == m)) {
+                        None => Some(MODELS[0]),
+                        Some(i) => MODELS.get(i + 1).copied(),
+                    };
+                }
+            }
+            UiAction::RebindButton(i) => ui.rebinding = Some(i),
+            UiAction::CloseSettings => close_settings(&mut ui),
             UiAction::Reset => {
                 // Save before reset (keeps the save file)
-                if let Some(ref path) = ui.current_rom {
-                    save_game(&emulator, path);
+                session.save_battery();
+                let Session { emulator, gbs, .. } = &mut *session;
+                match gbs {
+                    Some((gbs, song)) => gbs.play(emulator, *song),
+                    None => reset_emulator(emulator, &ui),
                 }
-                emulator.reset();
+                session.driver.clear_rewind();
                 // Reload the save after reset
                 if let Some(ref path) = ui.current_rom {
-                    load_save(&mut emulator, path);
+                    let battery = BatterySaver::load(&mut session.emulator, path);
+                    session.battery = Some(battery);
                 }
                 ui.state = EmulatorState::Running;
             }
@@ -323,21 +811,37 @@ fn main() {
             UiAction::None => {}
         }
 
-        // Update window
+        if ui.state != EmulatorState::Paused {
+            ui.frame_advance = false;
+        }
+
+        // Tell the emulation thread how to run until the next host frame
+        session.running = ui.state == EmulatorState::Running;
+        session.speed = ui.effective_speed();
+        session.rewinding = ui.rewinding;
+        session.audio_sync = ui.audio_sync;
+        session.audio_live = audio.is_live();
+        let frames = session.frames;
+        drop(session);
+
+        ui.render_message(&mut buffer, UI_WIDTH);
+
+        // Update window, scaled to its current size
+        let (width, height) = window.get_size();
+        present(&buffer, UI_WIDTH, UI_HEIGHT, &mut presented, width, height);
         window
-            .update_with_buffer(&buffer, UI_WIDTH, UI_HEIGHT)
+            .update_with_buffer(&presented, width, height)
             .expect("Failed to update window");
 
-        // FPS tracking - count frames per second
-        frames_this_second += 1;
+        // FPS tracking - count emulated frames per second
         let fps_elapsed = last_fps_time.elapsed();
         if fps_elapsed >= Duration::from_secs(1) {
-            ui.fps = frames_this_second as f64 / fps_elapsed.as_secs_f64();
-            frames_this_second = 0;
+            ui.fps = (frames - frames_at_last_fps) as f64 / fps_elapsed.as_secs_f64();
+            frames_at_last_fps = frames;
             last_fps_time = Instant::now();
         }
 
-        // Frame timing - sleep to maintain ~59.7 FPS
+        // Redraw at ~59.7 FPS; the emulation thread keeps its own pace
         let elapsed = frame_start.elapsed();
         let target = Duration::from_nanos(FRAME_TIME_NS);
         if elapsed < target {
@@ -345,44 +849,431 @@ fn main() {
         }
     }
 
-    // Save game on exit
-    if let Some(ref path) = ui.current_rom {
-        save_game(&emulator, path);
+    // Save game and settings on exit
+    emu.stop().save_battery();
+    remember_game_settings(&mut ui);
+    save_config(&ui.config);
+}
+
+/// Open the main window: resizable, or borderless in the top-left corner
+/// when fullscreen
+fn create_window((width, height): (usize, usize), fullscreen: bool) -> Window {
+    let mut window = Window::new(
+        "GB3000 - Game Boy Emulator",
+        width,
+        height,
+        WindowOptions {
+            borderless: fullscreen,
+            title: !fullscreen,
+            resize: !fullscreen,
+            topmost: fullscreen,
+            scale_mode: ScaleMode::UpperLeft,
+            ..WindowOptions::default()
+        },
+    )
+    .expect("Failed to create window");
+    if fullscreen {
+        window.set_position(0, 0);
+    }
+
+    // Don't use minifb's rate limiting - we do our own
+    window.set_target_fps(0);
+    window
+}
+
+/// Open a debug window if it's closed, or close it
+fn toggle<T>(window: &mut Option<T>, open: impl FnOnce() -> T) {
+    *window = match window.take() {
+        Some(_) => None,
+        None => Some(open()),
+    };
+}
+
+/// Start a freshly loaded ROM with its settings and battery save
+fn start_game(session: &mut Session, ui: &mut Ui, rom: &[u8], path: PathBuf) {
+    if is_gbs(&path) {
+        start_gbs(session, ui, rom, path);
+        return;
     }
+    session.gbs = None;
+    session.emulator.load_rom(rom);
+    session.emulator.set_hang_detection(Some(HANG_CYCLES));
+    apply_audio_options(&mut session.emulator, ui);
+    plug_mobile_adapter(&mut session.emulator, &ui.config);
+    restore_game_settings(ui, rom);
+    warn_about_bad_dump(ui, rom);
+    reset_emulator(&mut session.emulator, ui);
+    session.driver.clear_rewind();
+    session.debug_break = false;
+    session.battery = Some(BatterySaver::load(&mut session.emulator, &path));
+    ui.current_rom = Some(path);
+    ui.state = EmulatorState::Running;
 }
 
-fn run_test_mode(args: &[String]) {
-    let test_dir = args
-        .get(2)
-        .map(|s| s.as_str())
-        .unwrap_or("test_roms/blargg/cpu_instrs/individual");
+/// Play a GBS sound file's first song in place of a game; Left and Right
+/// change songs
+fn start_gbs(session: &mut Session, ui: &mut Ui, data: &[u8], path: PathBuf) {
+    let gbs = match GbsFile::parse(data) {
+        Ok(gbs) => gbs,
+        Err(e) => {
+            ui.show_message(format!("Failed to read GBS: {}", e));
+            ui.current_rom = None;
+            ui.state = EmulatorState::StartScreen;
+            return;
+        }
+    };
+    apply_audio_options(&mut session.emulator, ui);
+    let song = gbs.first_song();
+    gbs.play(&mut session.emulator, song);
+    ui.show_message(song_label(&gbs, song));
+    session.gbs = Some((gbs, song));
+    session.driver.clear_rewind();
+    session.debug_break = false;
+    session.battery = None;
+    ui.current_game = None;
+    ui.current_rom = Some(path);
+    ui.state = EmulatorState::Running;
+}
+
+/// On-screen name of a GBS song
+fn song_label(gbs: &GbsFile, song: u8) -> String {
+    format!("Song {}/{}: {}", song as u32 + 1, gbs.song_count(), gbs.title())
+}
+
+/// Start `rom` on a second console beside the running game, linked to it
+/// by cable and infrared, with the two mixed into the audio output
+fn start_partner(
+    session: &mut Session,
+    ui: &Ui,
+    rom: &[u8],
+    path: &Path,
+    audio_buffer: &AudioBuffer,
+    volume: &Arc<AtomicU32>,
+) {
+    let mut emulator = Emulator::new();
+    emulator.load_rom(rom);
+    apply_audio_options(&mut emulator, ui);
+    let model = ui.config.games.get(&config::header_hash(rom)).and_then(|game| game.model);
+    emulator.reset_for_model(model.unwrap_or_default());
+    // A second copy of the running game keeps a save of its own
+    let save = if ui.current_rom.as_deref() == Some(path) {
+        battery::second_save_path(path)
+    } else {
+        battery::save_path(path)
+    };
+    let battery = BatterySaver::load_from(&mut emulator, save);
 
-    println!("╔══════════════════════════════════════╗");
-    println!("║      GB3000 Test Runner              ║");
-    println!("╚══════════════════════════════════════╝");
-    println!("\nRunning tests from: {}\n", test_dir);
+    let (cable, other_cable) = LinkCable::pair();
+    let (ir, other_ir) = InfraredLink::pair();
+    session.emulator.set_serial_device(cable);
+    session.emulator.set_infrared_device(ir);
+    emulator.set_serial_device(other_cable);
+    emulator.set_infrared_device(other_ir);
 
-    let results = test_runner::run_all_tests(test_dir);
+    let mixer = Mixer::new(audio_buffer, volume, ui.config.console_mix);
+    session.emulator.set_audio_sink(mixer::sink(&mixer, 0));
+    emulator.set_audio_sink(mixer::sink(&mixer, 1));
+    session.driver.clear_rewind();
+    session.partner = Some(Partner { emulator, battery: Some(battery), input: Input::default() });
+}
 
-    println!("\n════════════════════════════════════════");
-    println!("                SUMMARY                 ");
-    println!("════════════════════════════════════════\n");
+/// Put player 2's console away, saving its game, and give the link port
+/// back to the Mobile Adapter setting
+fn stop_partner(session: &mut Session, ui: &Ui, audio_buffer: &AudioBuffer, volume: &Arc<AtomicU32>) {
+    let Some(mut partner) = session.partner.take() else { return };
+    if let Some(b) = partner.battery.as_mut() {
+        b.save(&mut partner.emulator);
+    }
+    plug_mobile_adapter(&mut session.emulator, &ui.config);
+    session.emulator.clear_infrared_device();
+    session.emulator.set_audio_sink(audio_sink(audio_buffer, volume));
+}
 
+/// Put a Mobile Adapter GB in the link port if the settings ask for one,
+/// otherwise leave the port empty
+fn plug_mobile_adapter(emulator: &mut Emulator, config: &Config) {
+    match config.mobile_adapter {
+        MobileSetting::Off => emulator.clear_serial_device(),
+        MobileSetting::Mock => emulator.set_serial_device(MobileAdapter::new(MockBackend::new())),
+        MobileSetting::Network => {
+            emulator.set_serial_device(MobileAdapter::new(NetBackend::new(config.mobile_hosts.clone())))
+        }
+    }
+}
+
+/// Filter the game, or both games side by side, into the UI buffer
+fn draw_screens(session: &Session, ui: &Ui, palette: &[u32; 4], buffer: &mut [u32], scratch: &mut filters::Scratch) {
+    match &session.partner {
+        Some(partner) => {
+            let screens = [&session.emulator.framebuffer()[..], &partner.emulator.framebuffer()[..]];
+            filters::apply_pair(ui.video_filter, screens, palette, buffer, scratch);
+        }
+        None => filters::apply(ui.video_filter, session.emulator.framebuffer(), palette, buffer, scratch),
+    }
+    // Player 1's screen is the left half when paired
+    #[cfg(feature = "lua")]
+    if let Some(script) = &session.script {
+        let (origin, scale) = match session.partner {
+            Some(_) => ((0, filters::OUT_HEIGHT / 4), filters::SCALE / 2),
+            None => ((0, 0), filters::SCALE),
+        };
+        ui::draw_overlay(buffer, UI_WIDTH, script.overlay(), origin, scale);
+    }
+}
+
+/// Split `--lua <script>` off the command line, leaving the ROMs
+#[cfg(feature = "lua")]
+fn take_lua_arg(mut args: Vec<String>) -> (Vec<String>, Option<PathBuf>) {
+    let Some(i) = args.iter().position(|arg| arg == "--lua") else {
+        return (args, None);
+    };
+    args.remove(i);
+    if i == args.len() {
+        eprintln!("Missing value for --lua");
+        return (args, None);
+    }
+    let path = PathBuf::from(args.remove(i));
+    (args, Some(path))
+}
+
+/// Leave the settings screen, saving any changes
+fn close_settings(ui: &mut Ui) {
+    ui.rebinding = None;
+    ui.state = if ui.current_rom.is_some() {
+        EmulatorState::Paused
+    } else {
+        EmulatorState::StartScreen
+    };
+    save_config(&ui.config);
+}
+
+fn run_screenshot_test_mode(args: &[String]) {
+    let Some(test_path) = args.get(2) else {
+        eprintln!("Usage: gb3000-ui --test-screenshot <rom or directory> [reference directory]");
+        std::process::exit(2);
+    };
+    println!("Running screenshot tests from: {}\n", test_path);
+
+    let results = test_runner::run_all_screenshot_tests(test_path, args.get(3).map(|s| s.as_str()));
     let passed = results.iter().filter(|r| r.passed).count();
-    let failed = results.iter().filter(|r| !r.passed).count();
+    println!("\nPassed: {}/{}", passed, results.len());
+    if passed < results.len() || results.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn run_headless_mode(args: &[String]) {
+    let options = headless::RunOptions::parse(&args[2..]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("{}", headless::USAGE);
+        std::process::exit(2);
+    });
+    match headless::run(&options) {
+        Ok(summary) => println!("{}", summary),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_bench_mode(args: &[String]) {
+    let options = bench::BenchOptions::parse(&args[2..]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("{}", bench::USAGE);
+        std::process::exit(2);
+    });
+    match bench::run(&options) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_golden_mode(args: &[String]) {
+    let usage = "Usage: gb3000-ui --golden record|check <rom or directory> <fixtures file> [--frames N]";
+    let (Some(action), Some(test_path), Some(fixtures)) = (args.get(2), args.get(3), args.get(4)) else {
+        eprintln!("{}", usage);
+        std::process::exit(2);
+    };
+    let frames = match args.get(5).map(|s| s.as_str()) {
+        None => 600,
+        Some("--frames") => match args.get(6).and_then(|n| n.parse().ok()) {
+            Some(n) => n,
+            None => {
+                eprintln!("{}", usage);
+                std::process::exit(2);
+            }
+        },
+        Some(_) => {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    };
+
+    match action.as_str() {
+        "record" => {
+            let goldens = golden::record_all(test_path, frames).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            if let Err(e) = fs::write(fixtures, golden::encode(&goldens)) {
+                eprintln!("Failed to write {}: {}", fixtures, e);
+                std::process::exit(1);
+            }
+            println!("Recorded {} ROMs after {} frames into {}", goldens.len(), frames, fixtures);
+        }
+        "check" => {
+            let baseline = fs::read(fixtures)
+                .map_err(|e| format!("Failed to read {}: {}", fixtures, e))
+                .and_then(|data| golden::decode(&data))
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            let problems = golden::check(test_path, &baseline);
+            for problem in &problems {
+                println!("✗ {}", problem);
+            }
+            println!("{} ROMs checked, {} differences", baseline.len(), problems.len());
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    }
+}
 
+fn run_sm83_test_mode(args: &[String]) {
+    let test_dir = args.get(2).map(|s| s.as_str()).unwrap_or("test_roms/sm83/v1");
+    println!("Running SingleStepTests from: {}\n", test_dir);
+
+    let results = single_step::run_all(test_dir);
+    let mut failed = 0;
     for result in &results {
-        let status = if result.passed { "✓ PASS" } else { "✗ FAIL" };
-        println!("{} {} ({} cycles)", status, result.name, result.cycles);
-        if !result.passed {
-            if let Some(ref err) = result.error {
-                println!("  Error: {}", err);
+        match result {
+            Ok(result) if result.failures.is_empty() => {
+                println!("✓ PASS {} ({} tests)", result.name, result.passed);
+            }
+            Ok(result) => {
+                failed += 1;
+                println!(
+                    "✗ FAIL {} ({}/{} tests)",
+                    result.name,
+                    result.passed,
+                    result.passed + result.failures.len()
+                );
+                println!("  {}", result.failures[0]);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("✗ ERROR {}", e);
             }
         }
     }
 
-    println!("\nPassed: {}/{}", passed, results.len());
-    println!("Failed: {}/{}", failed, results.len());
+    println!("\nPassed: {}/{} opcodes", results.len() - failed, results.len());
+    if failed > 0 || results.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn run_test_mode(args: &[String]) {
+    let usage = "Usage: gb3000-ui --test [path] [--format text|json|junit] [--jobs N] \
+                 [--include GLOB] [--exclude GLOB] [--model MODEL]";
+    let bad_usage = || -> ! {
+        eprintln!("{}", usage);
+        std::process::exit(2);
+    };
+    let mut test_dir = "test_roms/blargg/cpu_instrs/individual";
+    let mut filter = test_runner::TestFilter::default();
+    let mut format = test_runner::OutputFormat::Text;
+    let mut jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--format" => match rest.next().and_then(|f| test_runner::OutputFormat::parse(f)) {
+                Some(f) => format = f,
+                None => bad_usage(),
+            },
+            "--jobs" => match rest.next().and_then(|n| n.parse().ok()) {
+                Some(n) => jobs = n,
+                None => bad_usage(),
+            },
+            "--include" => filter.include.push(rest.next().unwrap_or_else(|| bad_usage()).clone()),
+            "--exclude" => filter.exclude.push(rest.next().unwrap_or_else(|| bad_usage()).clone()),
+            "--model" => {
+                let name = rest.next().unwrap_or_else(|| bad_usage());
+                match gb3000::GbModel::ALL.into_iter().find(|m| m.to_string().eq_ignore_ascii_case(name)) {
+                    Some(model) => filter.model = Some(model),
+                    None => bad_usage(),
+                }
+            }
+            path => test_dir = path,
+        }
+    }
+
+    let text = format == test_runner::OutputFormat::Text;
+    if text {
+        println!("╔══════════════════════════════════════╗");
+        println!("║      GB3000 Test Runner              ║");
+        println!("╚══════════════════════════════════════╝");
+        println!("\nRunning tests from: {} ({} threads)\n", test_dir, jobs);
+    }
+
+    let run = test_runner::run_all_tests(test_dir, &filter, jobs, |result| {
+        if text {
+            test_runner::print_result(result);
+        }
+    });
+    let results = &run.results;
+    let passed = results.iter().filter(|r| r.passed).count();
+    let failed = results.len() - passed;
+
+    match format {
+        test_runner::OutputFormat::Json => print!("{}", test_runner::format_json(results)),
+        test_runner::OutputFormat::Junit => print!("{}", test_runner::format_junit(results)),
+        test_runner::OutputFormat::Text => {
+            println!("\n════════════════════════════════════════");
+            println!("                SUMMARY                 ");
+            println!("════════════════════════════════════════\n");
+
+            for result in results {
+                let status = if result.passed { "✓ PASS" } else { "✗ FAIL" };
+                println!(
+                    "{} {} ({} cycles, {:.2}s)",
+                    status,
+                    result.name,
+                    result.cycles,
+                    result.duration.as_secs_f64()
+                );
+                if !result.passed {
+                    if let Some(ref err) = result.error {
+                        println!("  Error: {}", err);
+                    }
+                }
+            }
+
+            if run.suites().len() > 1 {
+                println!();
+                for (suite, suite_passed, total) in run.suites() {
+                    let suite = if suite.is_empty() { "." } else { suite };
+                    println!("{:<40} {}/{}", suite, suite_passed, total);
+                }
+            }
+
+            println!("\nPassed: {}/{}", passed, results.len());
+            println!("Failed: {}/{}", failed, results.len());
+            if run.skipped > 0 {
+                println!("Skipped: {}", run.skipped);
+            }
+        }
+    }
 
     if failed > 0 {
         std::process::exit(1);
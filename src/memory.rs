@@ -1,17 +1,31 @@
-/// Memory subsystem for the Game Boy emulator.
-///
-/// The Game Boy has a 16-bit address space (64KB) with the following layout:
-/// - 0x0000-0x3FFF: ROM Bank 0 (16KB)
-/// - 0x4000-0x7FFF: ROM Bank 1-N (switchable, 16KB)
-/// - 0x8000-0x9FFF: Video RAM (8KB)
-/// - 0xA000-0xBFFF: External RAM (8KB, switchable)
-/// - 0xC000-0xDFFF: Work RAM (8KB)
-/// - 0xE000-0xFDFF: Echo RAM (mirror of C000-DDFF)
-/// - 0xFE00-0xFE9F: OAM (Sprite Attribute Table)
-/// - 0xFEA0-0xFEFF: Not usable
-/// - 0xFF00-0xFF7F: I/O Registers
-/// - 0xFF80-0xFFFE: High RAM (HRAM)
-/// - 0xFFFF: Interrupt Enable Register
+//! Memory subsystem for the Game Boy emulator.
+//!
+//! The Game Boy has a 16-bit address space (64KB) with the following layout:
+//! - 0x0000-0x3FFF: ROM Bank 0 (16KB)
+//! - 0x4000-0x7FFF: ROM Bank 1-N (switchable, 16KB)
+//! - 0x8000-0x9FFF: Video RAM (8KB)
+//! - 0xA000-0xBFFF: External RAM (8KB, switchable)
+//! - 0xC000-0xDFFF: Work RAM (8KB)
+//! - 0xE000-0xFDFF: Echo RAM (mirror of C000-DDFF)
+//! - 0xFE00-0xFE9F: OAM (Sprite Attribute Table)
+//! - 0xFEA0-0xFEFF: Not usable
+//! - 0xFF00-0xFF7F: I/O Registers
+//! - 0xFF80-0xFFFE: High RAM (HRAM)
+//! - 0xFFFF: Interrupt Enable Register
+//!
+//! Each RAM region has its own buffer; ROM and external RAM are only
+//! reached through the cartridge banks, so nothing is stored twice.
+
+use crate::cpu::GbModel;
+use crate::interrupt_log::InterruptLog;
+use crate::options::{AccuracyOptions, InitialRam};
+use crate::rtc::Rtc;
+use crate::savefile::MBC2_RAM_SIZE;
+use crate::sgb::{Sgb, MAX_PLAYERS};
+use std::cell::{Cell, RefCell};
+use std::ops::{Index, IndexMut, Range, RangeInclusive};
+use std::sync::Arc;
+use crate::state::{StateError, StateReader, StateWriter};
 
 /// Hardware register addresses
 pub mod io {
@@ -68,8 +82,50 @@ pub mod io {
     pub const OBP1: u16 = 0xFF49;
     pub const WY: u16 = 0xFF4A;
     pub const WX: u16 = 0xFF4B;
+    
+    // CGB
+    pub const KEY1: u16 = 0xFF4D;
+    pub const RP: u16 = 0xFF56;
+    pub const PCM12: u16 = 0xFF76;
+    pub const PCM34: u16 = 0xFF77;
 }
 
+/// Bits of each I/O register (0xFF00-0xFF7F) that read as 1 whatever was
+/// written: unused and write-only bits, and all of an unmapped address.
+/// These are the DMG values; registers whose reads depend on the model or
+/// on other state are handled in `Memory::read_io` before the table.
+#[rustfmt::skip]
+const IO_READ_MASK: [u8; 0x80] = [
+    // FF00: JOYP SB    SC    -     DIV   TIMA  TMA   TAC
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8,
+    // FF08: -                                        IF
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // FF10: NR10 NR11 NR12  NR13  NR14  -     NR21  NR22
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00,
+    // FF18: NR23 NR24 NR30  NR31  NR32  NR33  NR34  -
+    0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // FF20: NR41 NR42 NR43  NR44  NR50  NR51  NR52  -
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF,
+    // FF28: -
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // FF30: wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // FF40: LCDC STAT SCY   SCX   LY    LYC   DMA   BGP
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // FF48: OBP0 OBP1 WY    WX    -     KEY1  -     VBK
+    0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    // FF50: boot ROM, HDMA
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // FF60: CGB palettes
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // FF70: SVBK and the undocumented registers
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
 /// Interrupt flag bits
 pub mod interrupts {
     pub const VBLANK: u8 = 0b0000_0001;
@@ -79,14 +135,118 @@ pub mod interrupts {
     pub const JOYPAD: u8 = 0b0001_0000;
 }
 
-#[derive(Debug)]
+/// One CPU access, recorded in flat mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+/// A block of RAM, indexed by bus address
+///
+/// `Region<0xC000, 0x2000>` holds 0xC000-0xDFFF, so `wram[0xC123]` is the
+/// byte the CPU sees at 0xC123. Indexing outside the block panics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region<const START: usize, const LEN: usize>([u8; LEN]);
+
+impl<const START: usize, const LEN: usize> Region<START, LEN> {
+    fn new() -> Self {
+        Self([0; LEN])
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl<const START: usize, const LEN: usize> Index<usize> for Region<START, LEN> {
+    type Output = u8;
+
+    #[inline]
+    fn index(&self, addr: usize) -> &u8 {
+        &self.0[addr - START]
+    }
+}
+
+impl<const START: usize, const LEN: usize> IndexMut<usize> for Region<START, LEN> {
+    #[inline]
+    fn index_mut(&mut self, addr: usize) -> &mut u8 {
+        &mut self.0[addr - START]
+    }
+}
+
+impl<const START: usize, const LEN: usize> Index<Range<usize>> for Region<START, LEN> {
+    type Output = [u8];
+
+    fn index(&self, addrs: Range<usize>) -> &[u8] {
+        &self.0[addrs.start - START..addrs.end - START]
+    }
+}
+
+impl<const START: usize, const LEN: usize> IndexMut<Range<usize>> for Region<START, LEN> {
+    fn index_mut(&mut self, addrs: Range<usize>) -> &mut [u8] {
+        &mut self.0[addrs.start - START..addrs.end - START]
+    }
+}
+
+impl<const START: usize, const LEN: usize> Index<RangeInclusive<usize>> for Region<START, LEN> {
+    type Output = [u8];
+
+    fn index(&self, addrs: RangeInclusive<usize>) -> &[u8] {
+        &self.0[addrs.start() - START..=addrs.end() - START]
+    }
+}
+
+impl<const START: usize, const LEN: usize> IndexMut<RangeInclusive<usize>> for Region<START, LEN> {
+    fn index_mut(&mut self, addrs: RangeInclusive<usize>) -> &mut [u8] {
+        &mut self.0[addrs.start() - START..=addrs.end() - START]
+    }
+}
+
+/// Flat mode: 64KB of plain RAM instead of the Game Boy's memory map
+#[derive(Debug, Clone)]
+struct FlatBus {
+    ram: Box<[u8; 0x10000]>,
+    /// Accesses since the log was last taken
+    log: RefCell<Vec<BusAccess>>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Memory {
-    /// Raw memory array (64KB)
-    pub data: [u8; 0x10000],
-    /// ROM data (can be larger than 32KB for banked ROMs)
-    rom: Vec<u8>,
+    /// Video RAM (0x8000-0x9FFF); writing here directly instead of
+    /// through [`Memory::poke`] leaves the PPU's line cache stale
+    pub vram: Region<0x8000, 0x2000>,
+    /// Bumped on every VRAM write through the bus, so the PPU can tell
+    /// when a rendered line may have changed
+    vram_version: u32,
+    /// Work RAM (0xC000-0xDFFF, mirrored at 0xE000-0xFDFF)
+    pub wram: Region<0xC000, 0x2000>,
+    /// Sprite attribute table (0xFE00-0xFE9F)
+    pub oam: Region<0xFE00, 0xA0>,
+    /// I/O registers as stored, before read masks (0xFF00-0xFF7F)
+    pub io: Region<0xFF00, 0x80>,
+    /// High RAM (0xFF80-0xFFFE)
+    pub hram: Region<0xFF80, 0x7F>,
+    /// Interrupt enable register (0xFFFF)
+    pub ie: u8,
+    /// ROM data (can be larger than 32KB for banked ROMs), shared
+    /// between clones
+    rom: Arc<[u8]>,
+    /// FNV-1a hash of the loaded ROM (identifies the ROM in save states)
+    rom_hash: u32,
     /// External RAM
     eram: Vec<u8>,
+    /// Set when the game changes external RAM or the clock; cleared once
+    /// the frontend has written the save
+    eram_dirty: bool,
+    /// Set when the CPU reads or writes an I/O register; cleared through a
+    /// shared reference by the hang watchdog
+    io_accessed: Cell<bool>,
     /// Current ROM bank lower 5 bits (for MBC1)
     rom_bank_low: u8,
     /// Current ROM bank upper 2 bits / RAM bank (for MBC1)
@@ -107,14 +267,31 @@ pub struct Memory {
     ram_bank_count: u8,
     /// MBC1 multicart mode (different banking for multicarts)
     mbc1_multicart: bool,
-    /// Joypad state (directly accessible for input handling)
-    pub joypad_state: u8,
+    /// Whether the cartridge has an MBC3 real-time clock
+    has_rtc: bool,
+    /// MBC3 real-time clock
+    rtc: Rtc,
+    /// Whether the cartridge is an MBC5 rumble cart
+    has_rumble: bool,
+    /// Rumble motor state (bit 3 of the MBC5 RAM bank register)
+    rumble: bool,
+    /// Super Game Boy, listening on the joypad register
+    sgb: Sgb,
+    /// Joypad state of each controller (directly accessible for input
+    /// handling); only the SGB reads beyond the first
+    pub joypad_states: [u8; MAX_PLAYERS],
+    /// Last level of the P10-P13 input lines (low nibble of JOYP); the
+    /// joypad interrupt fires when one of them falls
+    joypad_lines: u8,
     /// DMA transfer in progress
     dma_active: bool,
     dma_source: u16,
     dma_offset: u8,
     /// DMA cycle counter (counts up to 4 for each byte transfer)
     dma_cycles: u8,
+    /// OAM row (8 bytes) the PPU is reading, during OAM scan (set by the
+    /// PPU, for the OAM bug)
+    pub oam_scan_row: Option<u8>,
     /// Timer register write flags (for timer to process)
     pub timer_div_written: bool,
     pub timer_tac_written: bool,
@@ -125,6 +302,41 @@ pub struct Memory {
     /// PPU register write flags (for STAT interrupt handling)
     pub stat_written: bool,
     pub lyc_written: bool,
+    /// Set when the game starts a serial transfer on the internal clock
+    pub serial_started: bool,
+    /// APU register write flags (bit n set = register 0xFF10 + n written)
+    pub apu_written: u32,
+    /// Sound register and wave RAM writes as the CPU made them, while
+    /// `log_apu_writes` is set; drained by the emulator after each step
+    pub apu_write_log: Vec<(u16, u8)>,
+    /// Whether to fill `apu_write_log`; set while an APU write hook or a
+    /// VGM log is active
+    pub log_apu_writes: bool,
+    /// LCD register writes, 0xFF40-0xFF4B, while `log_ppu_writes` is set;
+    /// handed to the PPU's write timeline after each M-cycle
+    pub ppu_write_log: Vec<(u16, u8)>,
+    /// Whether to fill `ppu_write_log`; set while the PPU write timeline
+    /// is enabled
+    pub log_ppu_writes: bool,
+    /// Interrupt requests and dispatches, while logging them; kept here
+    /// so the emulator can reach it mid-instruction
+    pub(crate) interrupt_log: Option<Box<InterruptLog>>,
+    /// Wave RAM byte channel 3 is reading, while it plays (set by the APU)
+    pub wave_playing_byte: Option<u8>,
+    /// Whether channel 3 is fetching that byte right now; the DMG only
+    /// lets the CPU reach wave RAM in that window, the CGB at any time
+    pub wave_fetch_now: bool,
+    /// Hardware model, for model-dependent quirks
+    model: GbModel,
+    /// Which bus quirks are modeled
+    accuracy: AccuracyOptions,
+    /// CGB double speed mode (KEY1 bit 7); the armed switch request lives
+    /// in bit 0 of `io[KEY1]`
+    double_speed: bool,
+    /// Whether light reaches the IR sensor (set from the infrared device)
+    ir_light: bool,
+    /// Flat RAM and its access log, in flat mode
+    flat: Option<FlatBus>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -140,9 +352,18 @@ impl Memory {
     /// Creates new memory initialized to zero.
     pub fn new() -> Self {
         let mut mem = Self {
-            data: [0; 0x10000],
-            rom: Vec::new(),
+            vram: Region::new(),
+            vram_version: 0,
+            wram: Region::new(),
+            oam: Region::new(),
+            io: Region::new(),
+            hram: Region::new(),
+            ie: 0,
+            rom: Arc::from([]),
+            rom_hash: 0,
             eram: vec![0; 0x8000], // 32KB max external RAM
+            eram_dirty: false,
+            io_accessed: Cell::new(false),
             rom_bank_low: 1,
             rom_bank_high: 0,
             rom_bank: 1,
@@ -153,11 +374,18 @@ impl Memory {
             rom_bank_count: 2, // Default 32KB = 2 banks
             ram_bank_count: 0,
             mbc1_multicart: false,
-            joypad_state: 0xFF, // All buttons released
+            has_rtc: false,
+            rtc: Rtc::new(),
+            has_rumble: false,
+            rumble: false,
+            sgb: Sgb::new(),
+            joypad_states: [0xFF; MAX_PLAYERS], // All buttons released
+            joypad_lines: 0x0F,
             dma_active: false,
             dma_source: 0,
             dma_offset: 0,
             dma_cycles: 0,
+            oam_scan_row: None,
             timer_div_written: false,
             timer_tac_written: false,
             timer_tac_old_value: 0,
@@ -166,66 +394,169 @@ impl Memory {
             timer_tma_written: false,
             stat_written: false,
             lyc_written: false,
+            serial_started: false,
+            apu_written: 0,
+            apu_write_log: Vec::new(),
+            log_apu_writes: false,
+            ppu_write_log: Vec::new(),
+            log_ppu_writes: false,
+            interrupt_log: None,
+            wave_playing_byte: None,
+            wave_fetch_now: false,
+            model: GbModel::DmgABC,
+            accuracy: AccuracyOptions::default(),
+            double_speed: false,
+            ir_light: false,
+            flat: None,
         };
         // Initialize registers to post-boot ROM values (DMG)
-        // These are the values after the boot ROM has finished executing
-        
+        mem.reset_io();
+        mem
+    }
+
+    /// Set the hardware model, for model-dependent bus behavior
+    ///
+    /// Call [`Memory::reset_io`] afterwards to apply its post-boot register
+    /// values.
+    pub fn set_model(&mut self, model: GbModel) {
+        self.model = model;
+    }
+
+    pub fn model(&self) -> GbModel {
+        self.model
+    }
+
+    /// Choose which bus quirks are modeled
+    pub fn set_accuracy(&mut self, accuracy: AccuracyOptions) {
+        self.accuracy = accuracy;
+    }
+
+    /// Whether a CGB is running a cartridge with CGB support, which is
+    /// what unlocks the CGB-only registers
+    pub(crate) fn cgb_mode(&self) -> bool {
+        self.model.is_cgb() && self.rom.get(0x0143).is_some_and(|&flag| flag & 0x80 != 0)
+    }
+
+    /// Whether the CPU runs at double speed (CGB only)
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Whether the game has the IR LED on
+    pub fn ir_led(&self) -> bool {
+        self.io[io::RP as usize] & 0x01 != 0
+    }
+
+    /// Tell the IR sensor whether it sees light
+    pub fn set_ir_light(&mut self, light: bool) {
+        self.ir_light = light;
+    }
+
+    /// Perform the speed switch armed through KEY1, as STOP does
+    ///
+    /// Returns false when no switch was armed, in which case STOP really
+    /// stops the CPU.
+    pub fn switch_speed(&mut self) -> bool {
+        let key1 = &mut self.io[io::KEY1 as usize];
+        if !self.model.is_cgb() || *key1 & 0x01 == 0 {
+            return false;
+        }
+        *key1 = 0;
+        self.double_speed = !self.double_speed;
+        true
+    }
+
+    /// Fill work RAM and high RAM with their power-up contents
+    pub fn fill_ram(&mut self, initial: InitialRam) {
+        initial.fill(self.model, self.wram.as_mut_slice());
+        initial.fill(self.model, self.hram.as_mut_slice());
+    }
+
+    /// Clear what a power cycle clears beyond the I/O registers: the
+    /// cartridge's bank registers, an OAM DMA in flight and the SGB's
+    /// state. External RAM and the clock run on the cartridge battery, so
+    /// they keep their contents.
+    pub fn power_cycle(&mut self) {
+        self.rom_bank_low = 1;
+        self.rom_bank_high = 0;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ram_enabled = false;
+        self.banking_mode = 0;
+        self.rumble = false;
+        self.dma_active = false;
+        self.sgb = Sgb::new();
+    }
+
+    /// Set the I/O registers to the values the model's boot ROM leaves
+    pub fn reset_io(&mut self) {
         // Joypad
-        mem.data[io::JOYP as usize] = 0xCF;
+        self.io[io::JOYP as usize] = 0xCF;
+        self.joypad_lines = 0x0F;
         
         // Timer - DIV is handled separately by Timer::reset_for_model
-        mem.data[io::TIMA as usize] = 0x00;
-        mem.data[io::TMA as usize] = 0x00;
-        mem.data[io::TAC as usize] = 0x00;
+        self.io[io::TIMA as usize] = 0x00;
+        self.io[io::TMA as usize] = 0x00;
+        self.io[io::TAC as usize] = 0x00;
+        // Writes the timer hasn't seen yet belong to the old run
+        self.timer_div_written = false;
+        self.timer_tac_written = false;
+        self.timer_tima_written = false;
+        self.timer_tma_written = false;
         
         // Sound registers
-        mem.data[io::NR10 as usize] = 0x80;
-        mem.data[io::NR11 as usize] = 0xBF;
-        mem.data[io::NR12 as usize] = 0xF3;
-        mem.data[io::NR14 as usize] = 0xBF;
-        mem.data[io::NR21 as usize] = 0x3F;
-        mem.data[io::NR22 as usize] = 0x00;
-        mem.data[io::NR24 as usize] = 0xBF;
-        mem.data[io::NR30 as usize] = 0x7F;
-        mem.data[io::NR31 as usize] = 0xFF;
-        mem.data[io::NR32 as usize] = 0x9F;
-        mem.data[io::NR34 as usize] = 0xBF;
-        mem.data[io::NR41 as usize] = 0xFF;
-        mem.data[io::NR42 as usize] = 0x00;
-        mem.data[io::NR43 as usize] = 0x00;
-        mem.data[io::NR44 as usize] = 0xBF;
-        mem.data[io::NR50 as usize] = 0x77;
-        mem.data[io::NR51 as usize] = 0xF3;
-        mem.data[io::NR52 as usize] = 0xF1; // DMG: 0xF1, SGB: 0xF0
+        self.io[io::NR10 as usize] = 0x80;
+        self.io[io::NR11 as usize] = 0xBF;
+        self.io[io::NR12 as usize] = 0xF3;
+        self.io[io::NR14 as usize] = 0xBF;
+        self.io[io::NR21 as usize] = 0x3F;
+        self.io[io::NR22 as usize] = 0x00;
+        self.io[io::NR24 as usize] = 0xBF;
+        self.io[io::NR30 as usize] = 0x7F;
+        self.io[io::NR31 as usize] = 0xFF;
+        self.io[io::NR32 as usize] = 0x9F;
+        self.io[io::NR34 as usize] = 0xBF;
+        self.io[io::NR41 as usize] = 0xFF;
+        self.io[io::NR42 as usize] = 0x00;
+        self.io[io::NR43 as usize] = 0x00;
+        self.io[io::NR44 as usize] = 0xBF;
+        self.io[io::NR50 as usize] = 0x77;
+        self.io[io::NR51 as usize] = 0xF3;
+        self.io[io::NR52 as usize] = if self.model.is_sgb() { 0xF0 } else { 0xF1 };
         
         // PPU registers
-        mem.data[io::LCDC as usize] = 0x91;
-        mem.data[io::STAT as usize] = 0x85; // Mode 1, coincidence flag set
-        mem.data[io::SCY as usize] = 0x00;
-        mem.data[io::SCX as usize] = 0x00;
-        mem.data[io::LY as usize] = 0x00; // Will be updated by PPU
-        mem.data[io::LYC as usize] = 0x00;
-        mem.data[io::BGP as usize] = 0xFC;
-        mem.data[io::OBP0 as usize] = 0xFF;
-        mem.data[io::OBP1 as usize] = 0xFF;
-        mem.data[io::WY as usize] = 0x00;
-        mem.data[io::WX as usize] = 0x00;
+        self.io[io::LCDC as usize] = 0x91;
+        self.io[io::STAT as usize] = 0x85; // Mode 1, coincidence flag set
+        self.io[io::SCY as usize] = 0x00;
+        self.io[io::SCX as usize] = 0x00;
+        self.io[io::LY as usize] = 0x00; // Will be updated by PPU
+        self.io[io::LYC as usize] = 0x00;
+        self.io[io::BGP as usize] = 0xFC;
+        self.io[io::OBP0 as usize] = 0xFF;
+        self.io[io::OBP1 as usize] = 0xFF;
+        self.io[io::WY as usize] = 0x00;
+        self.io[io::WX as usize] = 0x00;
+        
+        // CGB speed switch - the boot ROM leaves the CPU at normal speed
+        self.io[io::KEY1 as usize] = 0x00;
+        self.double_speed = false;
+        self.io[io::RP as usize] = 0x00;
+        
+        // Undocumented CGB registers
+        self.io[0xFF72..=0xFF75].fill(0x00);
         
         // Interrupt registers
         // After boot, no interrupts are pending initially (the boot ROM clears them)
-        mem.data[io::IF as usize] = 0xE0; // Unused bits 5-7 always read as 1
-        mem.data[io::IE as usize] = 0x00;
-        
-        mem
+        self.io[io::IF as usize] = 0xE0; // Unused bits 5-7 always read as 1
+        self.ie = 0x00;
     }
 
     /// Loads the given ROM bytes and detects cartridge type.
     pub fn load_rom(&mut self, rom: &[u8]) {
-        self.rom = rom.to_vec();
-        
-        // Copy first 32KB to memory
-        let len = rom.len().min(0x8000);
-        self.data[..len].copy_from_slice(&rom[..len]);
+        self.rom = Arc::from(rom);
+        self.rom_hash = rom.iter().fold(0x811C9DC5u32, |hash, &b| {
+            (hash ^ b as u32).wrapping_mul(0x01000193)
+        });
         
         // Detect MBC type from cartridge header (0x0147)
         if rom.len() > 0x0147 {
@@ -237,28 +568,32 @@ impl Memory {
                 0x19..=0x1E => MbcType::Mbc5,
                 _ => MbcType::None,
             };
+            // MBC3+TIMER+BATTERY and MBC3+TIMER+RAM+BATTERY
+            self.has_rtc = matches!(rom[0x0147], 0x0F | 0x10);
+            // MBC5+RUMBLE, MBC5+RUMBLE+RAM and MBC5+RUMBLE+RAM+BATTERY
+            self.has_rumble = matches!(rom[0x0147], 0x1C..=0x1E);
         }
         
-        // Calculate number of ROM banks from header (0x0148)
-        if rom.len() > 0x0148 {
-            self.rom_bank_count = match rom[0x0148] {
-                0x00 => 2,    // 32KB = 2 banks
-                0x01 => 4,    // 64KB = 4 banks
-                0x02 => 8,    // 128KB = 8 banks
-                0x03 => 16,   // 256KB = 16 banks
-                0x04 => 32,   // 512KB = 32 banks
-                0x05 => 64,   // 1MB = 64 banks
-                0x06 => 128,  // 2MB = 128 banks
-                0x07 => 256,  // 4MB = 256 banks
-                0x08 => 512,  // 8MB = 512 banks
-                0x52 => 72,   // 1.1MB
-                0x53 => 80,   // 1.2MB
-                0x54 => 96,   // 1.5MB
-                _ => (rom.len() / 0x4000) as u16,
-            };
-        } else {
-            self.rom_bank_count = (rom.len() / 0x4000).max(2) as u16;
-        }
+        // Number of ROM banks from the header (0x0148), capped at what the
+        // image holds: a truncated dump wraps instead of reading 0xFF, and
+        // data past the declared size (an overdump) is never mapped
+        let actual_banks = rom.len().div_ceil(0x4000).clamp(1, u16::MAX as usize) as u16;
+        let declared_banks = match rom.get(0x0148) {
+            Some(0x00) => 2,    // 32KB = 2 banks
+            Some(0x01) => 4,    // 64KB = 4 banks
+            Some(0x02) => 8,    // 128KB = 8 banks
+            Some(0x03) => 16,   // 256KB = 16 banks
+            Some(0x04) => 32,   // 512KB = 32 banks
+            Some(0x05) => 64,   // 1MB = 64 banks
+            Some(0x06) => 128,  // 2MB = 128 banks
+            Some(0x07) => 256,  // 4MB = 256 banks
+            Some(0x08) => 512,  // 8MB = 512 banks
+            Some(0x52) => 72,   // 1.1MB
+            Some(0x53) => 80,   // 1.2MB
+            Some(0x54) => 96,   // 1.5MB
+            _ => actual_banks,
+        };
+        self.rom_bank_count = declared_banks.min(actual_banks);
         
         // Determine RAM size from header (0x0149)
         if rom.len() > 0x0149 {
@@ -272,9 +607,12 @@ impl Memory {
                 _ => (0x8000, 4),
             };
             self.ram_bank_count = ram_banks;
-            if ram_size > 0 {
-                self.eram = vec![0; ram_size];
-            }
+            self.eram = vec![0; ram_size];
+        }
+        if self.mbc_type == MbcType::Mbc2 {
+            // Built into the controller, whatever the header says
+            self.ram_bank_count = 1;
+            self.eram = vec![0; MBC2_RAM_SIZE];
         }
         
         // Detect MBC1 multicart mode
@@ -301,6 +639,42 @@ impl Memory {
         }
     }
     
+    /// ROM bank mapped at 0x4000-0x7FFF
+    pub fn rom_bank(&self) -> usize {
+        match self.mbc_type {
+            MbcType::None => 1,
+            MbcType::Mbc1 => self.mbc1_rom_bank(),
+            MbcType::Mbc2 => {
+                let b = self.rom_bank as usize;
+                if b == 0 { 1 } else { b % (self.rom_bank_count as usize) }
+            }
+            MbcType::Mbc3 | MbcType::Mbc5 => {
+                (self.rom_bank as usize) % (self.rom_bank_count as usize)
+            }
+        }
+    }
+
+    /// External RAM bank mapped at 0xA000-0xBFFF (on MBC3 clock
+    /// cartridges, values 0x08-0x0C select a clock register instead)
+    ///
+    /// Bank numbers wrap at the RAM size, as the chip ignores address lines
+    /// it doesn't have.
+    pub fn ram_bank(&self) -> usize {
+        match self.mbc_type {
+            MbcType::Mbc1 => self.mbc1_ram_bank(),
+            _ => self.ram_bank as usize % self.ram_bank_count().max(1),
+        }
+    }
+
+    /// Offset into external RAM of `addr` (0xA000-0xBFFF) in `bank`
+    ///
+    /// RAM smaller than a bank (the 2 KB chips and MBC2's 512 half-bytes)
+    /// repeats across the window.
+    fn eram_offset(&self, bank: usize, addr: u16) -> usize {
+        let bank_size = self.eram.len().clamp(1, 0x2000);
+        bank * 0x2000 + (addr as usize - 0xA000) % bank_size
+    }
+
     /// Get effective MBC1 ROM bank for 0x4000-0x7FFF region
     fn mbc1_rom_bank(&self) -> usize {
         if self.mbc1_multicart {
@@ -358,15 +732,46 @@ impl Memory {
     fn mbc1_ram_bank(&self) -> usize {
         if self.banking_mode == 1 {
             // Mode 1: Use upper 2 bits for RAM bank (supports up to 4 banks)
-            (self.rom_bank_high as usize) % (self.ram_bank_count.max(1) as usize)
+            (self.rom_bank_high as usize) % self.ram_bank_count().max(1)
         } else {
             // Mode 0: Only bank 0 accessible
             0
         }
     }
 
+    /// Treat the address space as 64 KB of plain RAM in `data`, without
+    /// cartridge, I/O or DMA behaviour, and log every CPU access
+    ///
+    /// For running CPU test vectors that define the whole bus.
+    pub fn set_flat(&mut self, flat: bool) {
+        self.flat = flat.then(|| FlatBus {
+            ram: Box::new([0; 0x10000]),
+            log: RefCell::new(Vec::new()),
+        });
+    }
+
+    /// Take the accesses logged in flat mode since the last call
+    pub fn take_bus_log(&self) -> Vec<BusAccess> {
+        self.flat.as_ref().map_or_else(Vec::new, |flat| flat.log.take())
+    }
+
     /// Reads a byte from the given address.
     pub fn read_byte(&self, addr: u16) -> u8 {
+        if let Some(flat) = &self.flat {
+            let value = flat.ram[addr as usize];
+            flat.log.borrow_mut().push(BusAccess { addr, value, write: false });
+            return value;
+        }
+        if self.dma_active && self.accuracy.dma_bus_conflicts {
+            if let Some(value) = self.dma_conflict(addr) {
+                return value;
+            }
+        }
+        self.read_mapped(addr)
+    }
+
+    /// Reads whatever is mapped at `addr`, whoever has the bus
+    fn read_mapped(&self, addr: u16) -> u8 {
         match addr {
             // ROM Bank 0
             0x0000..=0x3FFF => {
@@ -380,195 +785,208 @@ impl Memory {
             
             // ROM Bank 1-N (switchable)
             0x4000..=0x7FFF => {
-                let bank = match self.mbc_type {
-                    MbcType::None => 1,
-                    MbcType::Mbc1 => self.mbc1_rom_bank(),
-                    MbcType::Mbc2 => {
-                        let b = self.rom_bank as usize;
-                        if b == 0 { 1 } else { b % (self.rom_bank_count as usize) }
-                    }
-                    MbcType::Mbc3 | MbcType::Mbc5 => {
-                        (self.rom_bank as usize) % (self.rom_bank_count as usize)
-                    }
-                };
-                let offset = (bank * 0x4000) + ((addr as usize) - 0x4000);
+                let offset = (self.rom_bank() * 0x4000) + ((addr as usize) - 0x4000);
                 self.rom.get(offset).copied().unwrap_or(0xFF)
             }
             
             // External RAM
             0xA000..=0xBFFF => {
-                if self.ram_enabled {
-                    let bank = match self.mbc_type {
-                        MbcType::Mbc1 => self.mbc1_ram_bank(),
-                        _ => self.ram_bank as usize,
-                    };
-                    let offset = (bank * 0x2000) + ((addr as usize) - 0xA000);
-                    self.eram.get(offset).copied().unwrap_or(0xFF)
-                } else {
+                if !self.ram_enabled {
+                    log_trace!("gb3000::memory", "read from disabled cartridge RAM at {:04X}", addr);
                     0xFF
+                } else if self.rtc_selected() {
+                    self.rtc.read(self.ram_bank)
+                } else {
+                    let offset = self.eram_offset(self.ram_bank(), addr);
+                    let value = self.eram.get(offset).copied().unwrap_or(0xFF);
+                    // MBC2 RAM is 4 bits wide; the upper lines float high
+                    if self.mbc_type == MbcType::Mbc2 { value | 0xF0 } else { value }
                 }
             }
             
             // Echo RAM
-            0xE000..=0xFDFF => self.data[(addr - 0x2000) as usize],
-            
-            // Joypad register
-            0xFF00 => self.read_joypad(),
-            
-            // IF register - bits 5-7 always read as 1
-            0xFF0F => self.data[addr as usize] | 0xE0,
-            
-            // APU registers have specific read masks
-            // Many bits are write-only and read back as 1
-            0xFF10 => self.data[addr as usize] | 0x80, // NR10: bit 7 unused
-            0xFF11 => self.data[addr as usize] | 0x3F, // NR11: bits 0-5 write-only
-            0xFF12 => self.data[addr as usize],         // NR12: fully readable
-            0xFF13 => 0xFF,                             // NR13: write-only
-            0xFF14 => self.data[addr as usize] | 0xBF, // NR14: bits 0-5,7 write-only
-            0xFF15 => 0xFF,                             // Unused
-            0xFF16 => self.data[addr as usize] | 0x3F, // NR21: bits 0-5 write-only
-            0xFF17 => self.data[addr as usize],         // NR22: fully readable
-            0xFF18 => 0xFF,                             // NR23: write-only
-            0xFF19 => self.data[addr as usize] | 0xBF, // NR24: bits 0-5,7 write-only
-            0xFF1A => self.data[addr as usize] | 0x7F, // NR30: bit 7 only readable
-            0xFF1B => 0xFF,                             // NR31: write-only
-            0xFF1C => self.data[addr as usize] | 0x9F, // NR32: bits 5-6 readable
-            0xFF1D => 0xFF,                             // NR33: write-only
-            0xFF1E => self.data[addr as usize] | 0xBF, // NR34: bit 6 readable
-            0xFF1F => 0xFF,                             // Unused
-            0xFF20 => 0xFF,                             // NR41: write-only
-            0xFF21 => self.data[addr as usize],         // NR42: fully readable
-            0xFF22 => self.data[addr as usize],         // NR43: fully readable
-            0xFF23 => self.data[addr as usize] | 0xBF, // NR44: bit 6 readable
-            0xFF24 => self.data[addr as usize],         // NR50: fully readable
-            0xFF25 => self.data[addr as usize],         // NR51: fully readable
-            0xFF26 => {
-                // NR52: bits 0-3 are read-only channel enable status, bit 7 is R/W
-                // Bits 4-6 are unused and read as 1
-                let status = self.data[addr as usize] & 0x8F; // Keep bit 7 and channel status
-                status | 0x70 // Set unused bits 4-6
-            }
-            0xFF27..=0xFF2F => 0xFF,                   // Unused APU registers
-            0xFF30..=0xFF3F => self.data[addr as usize], // Wave RAM
+            0xE000..=0xFDFF => self.wram[(addr - 0x2000) as usize],
             
             // Not usable area
-            0xFEA0..=0xFEFF => 0xFF,
+            0xFEA0..=0xFEFF => self.read_unusable(addr),
             
-            // Everything else reads from data array
-            _ => self.data[addr as usize],
+            0x8000..=0x9FFF => self.vram[addr as usize],
+            0xC000..=0xDFFF => self.wram[addr as usize],
+            0xFE00..=0xFE9F => self.oam[addr as usize],
+            0xFF80..=0xFFFE => self.hram[addr as usize],
+            0xFFFF => self.ie,
+            0xFF00..=0xFF7F => self.read_io(addr),
         }
     }
 
-    /// Writes a byte to the given address.
-    pub fn write_byte(&mut self, addr: u16, value: u8) {
+    /// Handles I/O register reads
+    ///
+    /// Unused and write-only bits read as 1, through [`IO_READ_MASK`] for
+    /// registers that read the same on every model.
+    fn read_io(&self, addr: u16) -> u8 {
+        self.io_accessed.set(true);
         match addr {
-            // ROM area - MBC register writes
-            0x0000..=0x1FFF => {
-                // RAM enable
-                match self.mbc_type {
-                    MbcType::Mbc1 | MbcType::Mbc3 | MbcType::Mbc5 => {
-                        self.ram_enabled = (value & 0x0F) == 0x0A;
-                    }
-                    MbcType::Mbc2 => {
-                        if addr & 0x0100 == 0 {
-                            self.ram_enabled = (value & 0x0F) == 0x0A;
-                        }
-                    }
-                    MbcType::None => {}
-                }
-            }
-            
-            0x2000..=0x3FFF => {
-                // ROM bank select
-                match self.mbc_type {
-                    MbcType::Mbc1 => {
-                        // MBC1: 5-bit bank register (0→1 handled in mbc1_rom_bank)
-                        self.rom_bank_low = value & 0x1F;
-                    }
-                    MbcType::Mbc2 => {
-                        if addr & 0x0100 != 0 {
-                            self.rom_bank = (value & 0x0F) as u16;
-                            if self.rom_bank == 0 {
-                                self.rom_bank = 1;
-                            }
-                        }
-                    }
-                    MbcType::Mbc3 => {
-                        let bank = value & 0x7F;
-                        self.rom_bank = if bank == 0 { 1 } else { bank as u16 };
-                    }
-                    MbcType::Mbc5 => {
-                        if addr < 0x3000 {
-                            self.rom_bank = (self.rom_bank & 0x100) | (value as u16);
-                        } else {
-                            self.rom_bank = (self.rom_bank & 0xFF) | (((value & 1) as u16) << 8);
-                        }
-                    }
-                    MbcType::None => {}
-                }
+            io::JOYP => self.read_joypad(),
+
+            // SC: bit 1 picks the fast clock, in CGB mode only
+            io::SC if self.cgb_mode() => self.io[addr as usize] | 0x7C,
+
+            // KEY1: bit 7 current speed, bit 0 switch armed
+            io::KEY1 if self.cgb_mode() => {
+                0x7E | (self.double_speed as u8) << 7 | self.io[addr as usize] & 0x01
             }
-            
-            0x4000..=0x5FFF => {
-                // RAM bank select (or upper bits of ROM bank for MBC1)
-                match self.mbc_type {
-                    MbcType::Mbc1 => {
-                        // MBC1: 2-bit register, affects ROM or RAM based on mode
-                        self.rom_bank_high = value & 0x03;
-                        self.ram_bank = value & 0x03;
-                    }
-                    MbcType::Mbc3 => {
-                        self.ram_bank = value & 0x0F;
-                    }
-                    MbcType::Mbc5 => {
-                        self.ram_bank = value & 0x0F;
-                    }
-                    _ => {}
-                }
+
+            // RP: bit 1 reads 0 while the enabled sensor sees light
+            io::RP if self.cgb_mode() => {
+                let rp = self.io[addr as usize];
+                let receiving = rp & 0xC0 == 0xC0 && self.ir_light;
+                rp | 0x3C | (!receiving as u8) << 1
             }
-            
-            0x6000..=0x7FFF => {
-                // Banking mode select (MBC1 only)
-                if self.mbc_type == MbcType::Mbc1 {
-                    self.banking_mode = value & 0x01;
+
+            // Undocumented CGB registers: FF72/FF73 plain storage, FF74
+            // only in CGB mode, FF75 bits 4-6, and FF76/FF77 the PCM
+            // amplitudes the APU mirrors in
+            0xFF72 | 0xFF73 | 0xFF76 | 0xFF77 if self.model.is_cgb() => self.io[addr as usize],
+            0xFF74 if self.cgb_mode() => self.io[addr as usize],
+            0xFF75 if self.model.is_cgb() => self.io[addr as usize] | 0x8F,
+
+            // Wave RAM - while channel 3 plays, only the byte it is
+            // fetching can be read, and on the DMG only during the fetch
+            0xFF30..=0xFF3F => match self.wave_playing_byte {
+                Some(index) if self.wave_fetch_now || self.model.is_cgb() => {
+                    self.io[0xFF30 + index as usize]
                 }
-            }
+                Some(_) => 0xFF,
+                None => self.io[addr as usize],
+            },
+
+            // Everything else reads back as stored
+            _ => self.io[addr as usize] | IO_READ_MASK[addr as usize - 0xFF00],
+        }
+    }
+
+    /// Read from 0xFEA0-0xFEFF, which no memory answers
+    ///
+    /// While the PPU or OAM DMA has the OAM bus it reads 0xFF. Otherwise
+    /// the DMG-family chips read 0x00 and the CGB repeats the high nibble
+    /// of the address's low byte (0xFEAx reads 0xAA), as CGB revision E
+    /// does.
+    fn read_unusable(&self, addr: u16) -> u8 {
+        let lcd_on = self.io[io::LCDC as usize] & 0x80 != 0;
+        let oam_busy = lcd_on && self.io[io::STAT as usize] & 0x02 != 0;
+        if oam_busy || self.dma_active || !self.accuracy.open_bus {
+            0xFF
+        } else if self.model.is_cgb() {
+            (addr as u8 & 0xF0) | (addr as u8 >> 4)
+        } else {
+            0x00
+        }
+    }
+
+    /// What the CPU reads at `addr` while OAM DMA has the bus, if the DMA
+    /// is in the way
+    ///
+    /// OAM reads 0xFF, and the bus the DMA is reading from returns the
+    /// byte it last copied. HRAM and the I/O registers stay reachable.
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        if (0xFE00..=0xFEFF).contains(&addr) {
+            return Some(0xFF);
+        }
+        let bus = self.dma_bus(addr)?;
+        (Some(bus) == self.dma_bus(self.dma_source)).then(|| {
+            self.dma_offset.checked_sub(1).map_or(0xFF, |last| self.oam[0xFE00 + last as usize])
+        })
+    }
+
+    /// Which bus `addr` is on: the cartridge's (0), VRAM's (1), or on
+    /// the CGB work RAM's own (2)
+    fn dma_bus(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0x9FFF => Some(1),
+            0xC000..=0xFDFF if self.model.is_cgb() => Some(2),
+            0x0000..=0xFDFF => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Corrupt OAM as the DMG does when the CPU puts `addr` on the bus
+    /// during OAM scan, by writing it or incrementing or decrementing a
+    /// 16-bit register holding it
+    ///
+    /// The row the PPU is reading gets a mix of itself and the row
+    /// before in its first word, and the rest of the row before copied
+    /// over the rest. The first row is never hit.
+    pub fn oam_bug(&mut self, addr: u16) {
+        if !self.accuracy.oam_bug || self.model.is_cgb() || !(0xFE00..=0xFEFF).contains(&addr) {
+            return;
+        }
+        let Some(row) = self.oam_scan_row.filter(|&row| row > 0) else { return };
+        let base = 0xFE00 + row as usize * 8;
+        let word = |at: usize| u16::from_le_bytes([self.oam[at], self.oam[at + 1]]);
+        let (a, b, c) = (word(base), word(base - 8), word(base - 4));
+        let [lo, hi] = (((a ^ c) & (b ^ c)) ^ c).to_le_bytes();
+        self.oam[base] = lo;
+        self.oam[base + 1] = hi;
+        for i in 2..8 {
+            self.oam[base + i] = self.oam[base - 8 + i];
+        }
+    }
+
+    /// Writes a byte to the given address.
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if let Some(flat) = &mut self.flat {
+            flat.ram[addr as usize] = value;
+            flat.log.get_mut().push(BusAccess { addr, value, write: true });
+            return;
+        }
+        match addr {
+            // ROM area - MBC register writes
+            0x0000..=0x7FFF => self.write_mbc(addr, value),
             
             // VRAM
             0x8000..=0x9FFF => {
-                self.data[addr as usize] = value;
+                self.vram[addr as usize] = value;
+                self.vram_version = self.vram_version.wrapping_add(1);
             }
             
             // External RAM
             0xA000..=0xBFFF => {
-                if self.ram_enabled {
-                    let bank = match self.mbc_type {
-                        MbcType::Mbc1 => self.mbc1_ram_bank(),
-                        _ => self.ram_bank as usize,
-                    };
-                    let offset = (bank * 0x2000) + ((addr as usize) - 0xA000);
-                    if offset < self.eram.len() {
+                if self.ram_enabled && self.rtc_selected() {
+                    self.rtc.write(self.ram_bank, value);
+                    self.eram_dirty = true;
+                } else if self.ram_enabled {
+                    let offset = self.eram_offset(self.ram_bank(), addr);
+                    let value = if self.mbc_type == MbcType::Mbc2 { value & 0x0F } else { value };
+                    if offset < self.eram.len() && self.eram[offset] != value {
                         self.eram[offset] = value;
+                        self.eram_dirty = true;
                     }
+                } else {
+                    log_trace!("gb3000::memory", "write {:02X} to disabled cartridge RAM at {:04X}", value, addr);
                 }
             }
             
             // Work RAM
             0xC000..=0xDFFF => {
-                self.data[addr as usize] = value;
+                self.wram[addr as usize] = value;
             }
             
             // Echo RAM
             0xE000..=0xFDFF => {
-                self.data[(addr - 0x2000) as usize] = value;
+                self.wram[(addr - 0x2000) as usize] = value;
             }
             
             // OAM
             0xFE00..=0xFE9F => {
-                self.data[addr as usize] = value;
+                self.oam_bug(addr);
+                self.oam[addr as usize] = value;
             }
             
             // Not usable
-            0xFEA0..=0xFEFF => {}
+            0xFEA0..=0xFEFF => {
+                self.oam_bug(addr);
+                log_trace!("gb3000::memory", "write {:02X} to unusable area at {:04X}", value, addr);
+            }
             
             // I/O Registers
             0xFF00..=0xFF7F => {
@@ -577,101 +995,421 @@ impl Memory {
             
             // HRAM
             0xFF80..=0xFFFE => {
-                self.data[addr as usize] = value;
+                self.hram[addr as usize] = value;
             }
             
             // IE register
             0xFFFF => {
-                self.data[addr as usize] = value;
+                self.ie = value;
+            }
+        }
+    }
+
+    /// Reads a byte without the bus behavior a CPU read has
+    ///
+    /// Cartridge RAM is read through the current bank even when disabled,
+    /// and I/O registers return their stored value, without read masks,
+    /// joypad matrix mixing or wave RAM lockout.
+    pub fn peek(&self, addr: u16) -> u8 {
+        if let Some(flat) = &self.flat {
+            return flat.ram[addr as usize];
+        }
+        match addr {
+            0xA000..=0xBFFF if self.rtc_selected() => self.rtc.read(self.ram_bank),
+            0xA000..=0xBFFF => self.peek_banked(self.ram_bank(), addr),
+            0xFF00..=0xFF7F => self.io[addr as usize],
+            _ => self.read_mapped(addr),
+        }
+    }
+
+    /// Reads a byte from a specific ROM or external RAM bank
+    ///
+    /// For 0x0000-0x7FFF, `bank` is the ROM bank and `addr` is taken
+    /// modulo 0x4000; for 0xA000-0xBFFF it is the RAM bank. Other addresses
+    /// aren't banked and read as [`Memory::peek`]. Banks past the end of
+    /// the cartridge read 0xFF.
+    pub fn peek_banked(&self, bank: usize, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => {
+                let offset = bank * 0x4000 + (addr as usize & 0x3FFF);
+                self.rom.get(offset).copied().unwrap_or(0xFF)
+            }
+            0xA000..=0xBFFF => {
+                let offset = self.eram_offset(bank, addr);
+                self.eram.get(offset).copied().unwrap_or(0xFF)
+            }
+            _ => self.peek(addr),
+        }
+    }
+
+    /// Writes a byte without triggering any hardware side effects.
+    ///
+    /// Cartridge RAM is written through the current bank even when disabled,
+    /// I/O registers are stored as-is, and ROM writes are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        if let Some(flat) = &mut self.flat {
+            flat.ram[addr as usize] = value;
+            return;
+        }
+        match addr {
+            0x0000..=0x7FFF => {}
+            0xA000..=0xBFFF => self.poke_banked(self.ram_bank(), addr, value),
+            0x8000..=0x9FFF => {
+                self.vram[addr as usize] = value;
+                self.vram_version = self.vram_version.wrapping_add(1);
             }
+            0xC000..=0xDFFF => self.wram[addr as usize] = value,
+            0xE000..=0xFDFF => self.wram[(addr - 0x2000) as usize] = value,
+            0xFE00..=0xFE9F => self.oam[addr as usize] = value,
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.io[addr as usize] = value,
+            0xFF80..=0xFFFE => self.hram[addr as usize] = value,
+            0xFFFF => self.ie = value,
+        }
+    }
+
+    /// Writes a byte to a specific external RAM bank (0xA000-0xBFFF)
+    ///
+    /// Other addresses aren't banked and write as [`Memory::poke`]; banks
+    /// past the end of the cartridge RAM are ignored.
+    pub fn poke_banked(&mut self, bank: usize, addr: u16, value: u8) {
+        if !(0xA000..=0xBFFF).contains(&addr) {
+            return self.poke(addr, value);
         }
+        let offset = self.eram_offset(bank, addr);
+        let value = if self.mbc_type == MbcType::Mbc2 { value & 0x0F } else { value };
+        if offset < self.eram.len() && self.eram[offset] != value {
+            self.eram[offset] = value;
+            self.eram_dirty = true;
+        }
+    }
+
+    /// Number of 16 KB banks in the loaded ROM, counting a partial one
+    pub fn rom_bank_count(&self) -> usize {
+        self.rom.len().div_ceil(0x4000)
+    }
+
+    /// Number of 8 KB external RAM banks, counting a smaller RAM (such as
+    /// the MBC2's 512 bytes) as one
+    pub fn ram_bank_count(&self) -> usize {
+        self.eram.len().div_ceil(0x2000)
+    }
+
+    /// Whether 0xA000-0xBFFF maps a clock register instead of RAM
+    fn rtc_selected(&self) -> bool {
+        self.has_rtc && (0x08..=0x0C).contains(&self.ram_bank)
+    }
+
+    /// Advance the cartridge clock, if there is one
+    pub fn tick_rtc(&mut self, cycles: u32) {
+        if self.has_rtc {
+            self.rtc.tick(cycles);
+        }
+    }
+
+    /// Whether the cartridge has an MBC3 real-time clock
+    pub fn has_rtc(&self) -> bool {
+        self.has_rtc
+    }
+
+    /// Whether the cartridge has a rumble motor
+    pub fn has_rumble(&self) -> bool {
+        self.has_rumble
+    }
+
+    /// Whether the rumble motor is currently switched on
+    pub fn rumble_active(&self) -> bool {
+        self.rumble
+    }
+
+    /// The cartridge clock
+    pub fn rtc(&self) -> &Rtc {
+        &self.rtc
+    }
+
+    /// Mutable access to the cartridge clock
+    pub fn rtc_mut(&mut self) -> &mut Rtc {
+        &mut self.rtc
     }
 
     /// Reads the joypad register with proper button/direction selection
     fn read_joypad(&self) -> u8 {
-        let select = self.data[io::JOYP as usize];
+        let select = self.io[io::JOYP as usize];
         let mut result = select | 0x0F;
+        let player = if self.model.is_sgb() { self.sgb.current_player() as usize } else { 0 };
+        let joypad_state = self.joypad_states[player];
         
         // Buttons are active low
         if select & 0x20 == 0 {
             // Select button keys
-            result &= (self.joypad_state >> 4) | 0xF0;
+            result &= (joypad_state >> 4) | 0xF0;
         }
         if select & 0x10 == 0 {
             // Select direction keys
-            result &= (self.joypad_state & 0x0F) | 0xF0;
+            result &= (joypad_state & 0x0F) | 0xF0;
+        }
+        if select & 0x30 == 0x30 && self.model.is_sgb() {
+            // With nothing selected the SGB reports the controller index
+            result = (result & 0xF0) | self.sgb.joypad_id();
         }
         
         result | 0xC0 // Upper bits always 1
     }
 
+    /// Note a sound register or wave RAM write for the APU write hook
+    fn log_apu_write(&mut self, addr: u16, value: u8) {
+        if self.log_apu_writes {
+            self.apu_write_log.push((addr, value));
+        }
+    }
+
     /// Handles I/O register writes
     fn write_io(&mut self, addr: u16, value: u8) {
+        self.io_accessed.set(true);
+        if self.log_ppu_writes && (io::LCDC..=io::WX).contains(&addr) {
+            self.ppu_write_log.push((addr, value));
+        }
         match addr {
             io::JOYP => {
                 // Only bits 4-5 are writable
-                self.data[addr as usize] = (value & 0x30) | (self.data[addr as usize] & 0xCF);
+                self.io[addr as usize] = (value & 0x30) | (self.io[addr as usize] & 0xCF);
+                if self.model.is_sgb() {
+                    self.sgb.write_joypad(value);
+                }
+                // Selecting a line with a button held pulls it low
+                self.update_joypad_lines();
             }
             
             io::DIV => {
                 // Writing any value resets DIV to 0
                 // Timer will handle the edge detection
                 self.timer_div_written = true;
-                self.data[addr as usize] = 0;
+                self.io[addr as usize] = 0;
             }
             
             io::TAC => {
                 // Timer control - notify timer of change
                 self.timer_tac_written = true;
-                self.timer_tac_old_value = self.data[addr as usize];
-                self.data[addr as usize] = value;
+                self.timer_tac_old_value = self.io[addr as usize];
+                self.io[addr as usize] = value;
             }
             
             io::TIMA => {
                 // Timer counter - writing during overflow window cancels reload
                 self.timer_tima_written = true;
                 self.timer_tima_new_value = value;
-                self.data[addr as usize] = value;
+                self.io[addr as usize] = value;
             }
             
             io::TMA => {
                 // Timer modulo - notify timer of write (affects reload value)
                 self.timer_tma_written = true;
-                self.data[addr as usize] = value;
+                self.io[addr as usize] = value;
             }
             
             io::DMA => {
                 // Start DMA transfer
+                log_trace!("gb3000::dma", "OAM DMA from {:04X}", (value as u16) << 8);
                 self.dma_source = (value as u16) << 8;
                 self.dma_active = true;
                 self.dma_offset = 0;
                 self.dma_cycles = 0;
-                self.data[addr as usize] = value;
+                self.io[addr as usize] = value;
             }
             
             io::LY => {
                 // LY is read-only, writes are ignored
             }
             
+            io::LCDC => {
+                let old = self.io[addr as usize];
+                if (old ^ value) & 0x80 != 0 {
+                    log_debug!("gb3000::ppu", "LCD {}", if value & 0x80 != 0 { "on" } else { "off" });
+                }
+                log_trace!("gb3000::ppu", "LCDC {:02X} -> {:02X}", old, value);
+                self.io[addr as usize] = value;
+            }
+            
+            io::KEY1 => {
+                // Only the switch request bit is writable, and only in CGB mode
+                if self.cgb_mode() {
+                    self.io[addr as usize] = value & 0x01;
+                }
+            }
+            
+            io::RP => {
+                // Bit 0 drives the LED, bits 6-7 enable the sensor
+                if self.cgb_mode() {
+                    self.io[addr as usize] = value & 0xC1;
+                }
+            }
+            
             io::STAT => {
                 // Lower 3 bits are read-only
-                self.data[addr as usize] = (value & 0xF8) | (self.data[addr as usize] & 0x07);
+                self.io[addr as usize] = (value & 0xF8) | (self.io[addr as usize] & 0x07);
                 self.stat_written = true;
             }
             
             io::LYC => {
-                self.data[addr as usize] = value;
+                self.io[addr as usize] = value;
                 self.lyc_written = true;
             }
             
+            io::NR52 => {
+                self.log_apu_write(addr, value);
+                // Only the power bit is writable; the APU owns the status bits
+                self.io[addr as usize] = (value & 0x80) | (self.io[addr as usize] & 0x0F);
+                self.apu_written |= 1 << (addr - 0xFF10);
+            }
+
+            0xFF10..=0xFF2F => {
+                self.log_apu_write(addr, value);
+                // Sound registers - the APU picks these up on its next tick.
+                // While powered off they are read-only, except that the DMG
+                // still accepts length counter writes.
+                if self.io[io::NR52 as usize] & 0x80 == 0 {
+                    if self.model.is_cgb() {
+                        return;
+                    }
+                    match addr {
+                        io::NR11 | io::NR21 | io::NR41 => self.io[addr as usize] = value & 0x3F,
+                        io::NR31 => self.io[addr as usize] = value,
+                        _ => return,
+                    }
+                } else {
+                    self.io[addr as usize] = value;
+                }
+                self.apu_written |= 1 << (addr - 0xFF10);
+            }
+
+            0xFF30..=0xFF3F => {
+                self.log_apu_write(addr, value);
+                // Wave RAM - while channel 3 plays, writes land on the byte
+                // it is fetching, and on the DMG only during the fetch
+                match self.wave_playing_byte {
+                    Some(index) if self.wave_fetch_now || self.model.is_cgb() => {
+                        self.io[0xFF30 + index as usize] = value
+                    }
+                    Some(_) => {}
+                    None => self.io[addr as usize] = value,
+                }
+            }
+            
+            io::SC => {
+                self.io[addr as usize] = value;
+                self.serial_started |= value & 0x81 == 0x81;
+            }
+            
             io::IF => {
                 // Only bits 0-4 are writable
-                self.data[addr as usize] = value & 0x1F;
+                self.io[addr as usize] = value & 0x1F;
             }
             
+            0xFF72 | 0xFF73 if self.model.is_cgb() => self.io[addr as usize] = value,
+            0xFF74 if self.cgb_mode() => self.io[addr as usize] = value,
+            0xFF75 if self.model.is_cgb() => self.io[addr as usize] = value & 0x70,
+            // PCM12/PCM34 are read-only, and the rest doesn't exist on the DMG
+            0xFF72..=0xFF77 => {}
+            
             _ => {
-                self.data[addr as usize] = value;
+                self.io[addr as usize] = value;
+            }
+        }
+    }
+
+    /// Handles writes to the memory bank controller's registers
+    fn write_mbc(&mut self, addr: u16, value: u8) {
+        let before = (self.rom_bank(), self.ram_bank(), self.ram_enabled);
+        match addr {
+            // MBC2 decodes both of its registers across 0x0000-0x3FFF and
+            // tells them apart by address bit 8
+            0x0000..=0x3FFF if self.mbc_type == MbcType::Mbc2 => {
+                if addr & 0x0100 == 0 {
+                    self.ram_enabled = (value & 0x0F) == 0x0A;
+                } else {
+                    self.rom_bank = (value & 0x0F).max(1) as u16;
+                }
+            }
+
+            0x0000..=0x1FFF => {
+                // RAM enable
+                match self.mbc_type {
+                    MbcType::Mbc1 | MbcType::Mbc3 | MbcType::Mbc5 => {
+                        self.ram_enabled = (value & 0x0F) == 0x0A;
+                    }
+                    MbcType::Mbc2 | MbcType::None => {}
+                }
+            }
+
+            0x2000..=0x3FFF => {
+                // ROM bank select
+                match self.mbc_type {
+                    MbcType::Mbc1 => {
+                        // MBC1: 5-bit bank register (0→1 handled in mbc1_rom_bank)
+                        self.rom_bank_low = value & 0x1F;
+                    }
+                    MbcType::Mbc3 => {
+                        let bank = value & 0x7F;
+                        self.rom_bank = if bank == 0 { 1 } else { bank as u16 };
+                    }
+                    MbcType::Mbc5 => {
+                        if addr < 0x3000 {
+                            self.rom_bank = (self.rom_bank & 0x100) | (value as u16);
+                        } else {
+                            self.rom_bank = (self.rom_bank & 0xFF) | (((value & 1) as u16) << 8);
+                        }
+                    }
+                    MbcType::Mbc2 | MbcType::None => {}
+                }
+            }
+
+            0x4000..=0x5FFF => {
+                // RAM bank select (or upper bits of ROM bank for MBC1)
+                match self.mbc_type {
+                    MbcType::Mbc1 => {
+                        // MBC1: 2-bit register, affects ROM or RAM based on mode
+                        self.rom_bank_high = value & 0x03;
+                        self.ram_bank = value & 0x03;
+                    }
+                    MbcType::Mbc3 => {
+                        self.ram_bank = value & 0x0F;
+                    }
+                    MbcType::Mbc5 if self.has_rumble => {
+                        // Bit 3 drives the motor, so only 8 RAM banks remain
+                        self.rumble = value & 0x08 != 0;
+                        self.ram_bank = value & 0x07;
+                    }
+                    MbcType::Mbc5 => {
+                        self.ram_bank = value & 0x0F;
+                    }
+                    _ => {}
+                }
             }
+
+            0x6000..=0x7FFF => {
+                // Banking mode select (MBC1) or clock latch (MBC3)
+                match self.mbc_type {
+                    MbcType::Mbc1 => self.banking_mode = value & 0x01,
+                    MbcType::Mbc3 if self.has_rtc => self.rtc.write_latch(value),
+                    _ => {}
+                }
+            }
+
+            _ => {}
+        }
+        let (rom_bank, ram_bank, ram_enabled) = (self.rom_bank(), self.ram_bank(), self.ram_enabled);
+        if (rom_bank, ram_bank, ram_enabled) != before {
+            log_trace!(
+                "gb3000::mbc",
+                "{:04X} <- {:02X}: ROM bank {}, RAM bank {} ({})",
+                addr,
+                value,
+                rom_bank,
+                ram_bank,
+                if ram_enabled { "enabled" } else { "disabled" }
+            );
+        } else if self.mbc_type == MbcType::None {
+            log_trace!("gb3000::memory", "write {:02X} to ROM at {:04X} with no MBC", value, addr);
         }
     }
 
@@ -687,8 +1425,8 @@ impl Memory {
                 
                 let src = self.dma_source + self.dma_offset as u16;
                 let dst = 0xFE00 + self.dma_offset as u16;
-                let val = self.read_byte(src);
-                self.data[dst as usize] = val;
+                let val = self.read_mapped(src);
+                self.oam[dst as usize] = val;
                 
                 self.dma_offset += 1;
                 if self.dma_offset >= 160 {
@@ -705,36 +1443,63 @@ impl Memory {
 
     /// Request an interrupt
     pub fn request_interrupt(&mut self, interrupt: u8) {
-        self.data[io::IF as usize] |= interrupt;
+        self.io[io::IF as usize] |= interrupt;
     }
 
     /// Get pending interrupts (IF & IE)
     pub fn pending_interrupts(&self) -> u8 {
-        self.data[io::IF as usize] & self.data[io::IE as usize] & 0x1F
+        self.io[io::IF as usize] & self.ie & 0x1F
     }
 
     /// Clear an interrupt flag
     pub fn clear_interrupt(&mut self, interrupt: u8) {
-        self.data[io::IF as usize] &= !interrupt;
+        self.io[io::IF as usize] &= !interrupt;
     }
 
-    /// Set joypad button state (bit = 0 means pressed)
+    /// Set a controller's button state (bit = 0 means pressed)
     /// Bits: 7-4 = Start, Select, B, A | 3-0 = Down, Up, Left, Right
-    pub fn set_joypad(&mut self, state: u8) {
-        let old_state = self.joypad_state;
-        self.joypad_state = state;
-        
-        // Request joypad interrupt on any button press (high to low transition)
-        if (old_state & !state) != 0 {
+    pub fn set_joypad(&mut self, player: usize, state: u8) {
+        self.joypad_states[player] = state;
+        self.update_joypad_lines();
+    }
+
+    /// Level of the P10-P13 input lines (bit clear = pulled low by a
+    /// pressed button in a selected group)
+    pub fn joypad_lines(&self) -> u8 {
+        self.joypad_lines
+    }
+
+    /// Request the joypad interrupt on a falling edge of any input line
+    ///
+    /// Only buttons in a group selected through JOYP reach the lines, so
+    /// presses in the other group don't interrupt.
+    fn update_joypad_lines(&mut self) {
+        let lines = self.read_joypad() & 0x0F;
+        if self.joypad_lines & !lines != 0 {
             self.request_interrupt(interrupts::JOYPAD);
         }
+        self.joypad_lines = lines;
     }
 
     /// Check if the cartridge has battery-backed RAM
     pub fn has_battery(&self) -> bool {
         // Any MBC with RAM can potentially have battery backup
-        // We check if RAM exists (ram_bank_count > 0)
-        self.ram_bank_count > 0
+        // We check if RAM exists (ram_bank_count > 0); clock carts always
+        // have one
+        self.ram_bank_count > 0 || self.has_rtc
+    }
+
+    pub fn sgb(&self) -> &Sgb {
+        &self.sgb
+    }
+
+    pub fn sgb_mut(&mut self) -> &mut Sgb {
+        &mut self.sgb
+    }
+
+    /// Whether the cartridge uses MBC2 and its built-in half-byte RAM
+    pub fn is_mbc2(&self) -> bool {
+        self.mbc_type == MbcType::Mbc2
     }
 
     /// Get the external RAM contents
@@ -747,6 +1512,138 @@ impl Memory {
     pub fn set_eram(&mut self, data: &[u8]) {
         let size = data.len().min(self.eram.len());
         self.eram[..size].copy_from_slice(&data[..size]);
+        self.eram_dirty = false;
+    }
+
+    /// Changes whenever VRAM is written through the bus or restored
+    pub fn vram_version(&self) -> u32 {
+        self.vram_version
+    }
+
+    /// Whether external RAM or the clock changed since the flag was cleared
+    pub fn eram_dirty(&self) -> bool {
+        self.eram_dirty
+    }
+
+    /// Mark external RAM as saved
+    pub fn clear_eram_dirty(&mut self) {
+        self.eram_dirty = false;
+    }
+
+    /// Whether the CPU touched an I/O register since the last call
+    pub fn take_io_accessed(&self) -> bool {
+        self.io_accessed.replace(false)
+    }
+
+    /// FNV-1a hash of the loaded ROM, used to match save states to ROMs
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Get mutable access to the external RAM contents
+    pub fn eram_mut(&mut self) -> &mut [u8] {
+        let size = self.ram_bank_count as usize * 0x2000;
+        let len = size.min(self.eram.len());
+        &mut self.eram[..len]
+    }
+
+    /// Serialize memory contents and cartridge/DMA state
+    ///
+    /// ROM contents are not saved, only a hash used to reject states
+    /// made with a different ROM.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.rom_hash);
+        w.bytes(self.vram.as_slice());
+        w.bytes(self.wram.as_slice());
+        w.bytes(self.oam.as_slice());
+        w.bytes(self.io.as_slice());
+        w.bytes(self.hram.as_slice());
+        w.u8(self.ie);
+        w.slice(&self.eram);
+        w.u8(self.rom_bank_low);
+        w.u8(self.rom_bank_high);
+        w.u16(self.rom_bank);
+        w.u8(self.ram_bank);
+        w.bool(self.ram_enabled);
+        w.u8(self.banking_mode);
+        w.bytes(&self.joypad_states);
+        w.u8(self.joypad_lines);
+        w.bool(self.dma_active);
+        w.u16(self.dma_source);
+        w.u8(self.dma_offset);
+        w.u8(self.dma_cycles);
+        w.bool(self.timer_div_written);
+        w.bool(self.timer_tac_written);
+        w.u8(self.timer_tac_old_value);
+        w.bool(self.timer_tima_written);
+        w.u8(self.timer_tima_new_value);
+        w.bool(self.timer_tma_written);
+        w.bool(self.stat_written);
+        w.bool(self.lyc_written);
+        w.bool(self.serial_started);
+        w.u32(self.apu_written);
+        w.u8(self.wave_playing_byte.unwrap_or(0xFF));
+        w.bool(self.wave_fetch_now);
+        w.bool(self.double_speed);
+        w.bool(self.ir_light);
+        w.bool(self.rumble);
+        self.rtc.save_state(w);
+        self.sgb.save_state(w);
+    }
+
+    /// Restore memory contents and cartridge/DMA state
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        if r.u32()? != self.rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+        r.bytes_into(self.vram.as_mut_slice())?;
+        self.vram_version = self.vram_version.wrapping_add(1);
+        r.bytes_into(self.wram.as_mut_slice())?;
+        r.bytes_into(self.oam.as_mut_slice())?;
+        r.bytes_into(self.io.as_mut_slice())?;
+        r.bytes_into(self.hram.as_mut_slice())?;
+        self.ie = r.u8()?;
+        let eram = r.slice()?;
+        if eram.len() != self.eram.len() {
+            return Err(StateError::Invalid("external RAM size"));
+        }
+        if self.eram[..] != eram[..] {
+            self.eram.copy_from_slice(eram);
+            self.eram_dirty = true;
+        }
+        self.rom_bank_low = r.u8()?;
+        self.rom_bank_high = r.u8()?;
+        self.rom_bank = r.u16()?;
+        self.ram_bank = r.u8()?;
+        self.ram_enabled = r.bool()?;
+        self.banking_mode = r.u8()?;
+        r.bytes_into(&mut self.joypad_states)?;
+        self.joypad_lines = r.u8()? & 0x0F;
+        self.dma_active = r.bool()?;
+        self.dma_source = r.u16()?;
+        self.dma_offset = r.u8()?;
+        self.dma_cycles = r.u8()?;
+        self.timer_div_written = r.bool()?;
+        self.timer_tac_written = r.bool()?;
+        self.timer_tac_old_value = r.u8()?;
+        self.timer_tima_written = r.bool()?;
+        self.timer_tima_new_value = r.u8()?;
+        self.timer_tma_written = r.bool()?;
+        self.stat_written = r.bool()?;
+        self.lyc_written = r.bool()?;
+        self.serial_started = r.bool()?;
+        self.apu_written = r.u32()?;
+        self.wave_playing_byte = match r.u8()? {
+            0xFF => None,
+            index => Some(index & 0x0F),
+        };
+        self.wave_fetch_now = r.bool()?;
+        self.double_speed = r.bool()?;
+        self.ir_light = r.bool()?;
+        self.rumble = r.bool()?;
+        self.rtc.load_state(r)?;
+        self.sgb.load_state(r)?;
+        Ok(())
     }
 }
 
@@ -767,6 +1664,56 @@ mod tests {
         assert_eq!(mem.read_byte(0xC000), 0x42);
     }
 
+    #[test]
+    fn undocumented_cgb_registers() {
+        let mut mem = Memory::new();
+        mem.load_rom(&[0u8; 0x8000]);
+        for addr in 0xFF72..=0xFF75 {
+            mem.write_byte(addr, 0xFF);
+            assert_eq!(mem.read_byte(addr), 0xFF);
+        }
+        assert_eq!(mem.io[0xFF72], 0x00);
+
+        // FF74 needs a CGB cartridge, FF75 only keeps bits 4-6
+        mem.set_model(GbModel::Cgb);
+        for addr in 0xFF72..=0xFF75 {
+            mem.write_byte(addr, 0x5A);
+        }
+        assert_eq!(mem.read_byte(0xFF72), 0x5A);
+        assert_eq!(mem.read_byte(0xFF73), 0x5A);
+        assert_eq!(mem.read_byte(0xFF74), 0xFF);
+        assert_eq!(mem.read_byte(0xFF75), 0xDF);
+    }
+
+    #[test]
+    fn joypad_interrupt_needs_a_selected_line() {
+        let mut mem = Memory::new();
+        let a_pressed = 0xEF;
+        let pending = |mem: &mut Memory| {
+            let fired = mem.io[io::IF as usize] & interrupts::JOYPAD != 0;
+            mem.clear_interrupt(interrupts::JOYPAD);
+            fired
+        };
+
+        // Directions selected: pressing A doesn't reach the lines
+        mem.write_byte(io::JOYP, 0x20);
+        mem.set_joypad(0, a_pressed);
+        assert!(!pending(&mut mem));
+        assert_eq!(mem.joypad_lines(), 0x0F);
+
+        // Selecting the buttons with A held pulls P10 low
+        mem.write_byte(io::JOYP, 0x10);
+        assert!(pending(&mut mem));
+        assert_eq!(mem.joypad_lines(), 0x0E);
+
+        // Holding the button doesn't fire again; a new press does
+        mem.set_joypad(0, a_pressed);
+        assert!(!pending(&mut mem));
+        mem.set_joypad(0, 0xFF);
+        mem.set_joypad(0, a_pressed);
+        assert!(pending(&mut mem));
+    }
+
     #[test]
     fn load_rom_copies_bytes() {
         let rom = vec![0xAA, 0xBB, 0xCC];
@@ -777,6 +1724,360 @@ mod tests {
         assert_eq!(mem.read_byte(0x0002), 0xCC);
     }
 
+    #[test]
+    fn rom_is_only_read_through_the_banks() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x01; // MBC1
+        rom[0x0148] = 0x01; // 64KB
+        rom[0x4000] = 0x11;
+        rom[0x8000] = 0x22;
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        assert_eq!(mem.read_byte(0x4000), 0x11);
+        mem.write_byte(0x2000, 2);
+        assert_eq!(mem.read_byte(0x4000), 0x22);
+        assert_eq!(mem.peek(0x4000), 0x22);
+
+        // Nothing behind the ROM to poke, and VRAM is its own region
+        mem.poke(0x4000, 0x33);
+        assert_eq!(mem.read_byte(0x4000), 0x22);
+        mem.write_byte(0x8000, 0x44);
+        assert_eq!(mem.vram[0x8000], 0x44);
+        assert_eq!(mem.vram.as_slice()[0], 0x44);
+    }
+
+    #[test]
+    fn banked_pokes_reach_unmapped_ram_banks() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x0148] = 0x01; // 64KB
+        rom[0x0149] = 0x03; // 32KB RAM
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        assert_eq!((mem.rom_bank_count(), mem.ram_bank_count()), (4, 4));
+
+        mem.poke_banked(2, 0xA010, 0x5A);
+        assert_eq!(mem.peek_banked(2, 0xA010), 0x5A);
+        assert_eq!(mem.peek(0xA010), 0x00);
+        assert!(mem.eram_dirty());
+        // Past the end of RAM nothing happens; unbanked addresses are pokes
+        mem.poke_banked(4, 0xA000, 0x5A);
+        assert_eq!(mem.peek_banked(4, 0xA000), 0xFF);
+        mem.poke_banked(7, 0xC000, 0x77);
+        assert_eq!(mem.peek(0xC000), 0x77);
+    }
+
+    /// ROM with each 16 KB bank starting with its own bank number
+    fn numbered_rom(cart_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000 << rom_size];
+        for (bank, chunk) in rom.chunks_mut(0x4000).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        rom[0x0147] = cart_type;
+        rom[0x0148] = rom_size;
+        rom[0x0149] = ram_size;
+        rom
+    }
+
+    #[test]
+    fn mbc1_rom_banks_wrap_at_rom_size() {
+        // After Mooneye's mbc1/rom_256kb: 16 banks, so bank 0x12 is bank 2
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x01, 0x03, 0x00));
+        mem.write_byte(0x2000, 0x12);
+        assert_eq!(mem.read_byte(0x4000), 0x02);
+        // The upper bits select nothing on a cart this small
+        mem.write_byte(0x4000, 0x03);
+        mem.write_byte(0x6000, 0x01);
+        assert_eq!(mem.read_byte(0x4000), 0x02);
+        assert_eq!(mem.read_byte(0x0000), 0x00);
+
+        // mbc1/rom_1Mb: 64 banks, the upper bits reach 0x20-0x3F
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x01, 0x05, 0x00));
+        mem.write_byte(0x2000, 0x00);
+        mem.write_byte(0x4000, 0x01);
+        assert_eq!(mem.read_byte(0x4000), 0x21);
+        mem.write_byte(0x6000, 0x01);
+        assert_eq!(mem.read_byte(0x0000), 0x20);
+        mem.write_byte(0x4000, 0x03);
+        assert_eq!(mem.read_byte(0x4000), 0x21);
+    }
+
+    #[test]
+    fn mbc5_rom_banks_wrap_at_rom_size() {
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x19, 0x02, 0x00));
+        mem.write_byte(0x2000, 0x0B);
+        mem.write_byte(0x3000, 0x01);
+        assert_eq!(mem.read_byte(0x4000), 0x03);
+        // MBC5 maps bank 0 at 0x4000 too
+        mem.write_byte(0x2000, 0x08);
+        assert_eq!(mem.read_byte(0x4000), 0x00);
+    }
+
+    #[test]
+    fn ram_banks_wrap_at_ram_size() {
+        // After Mooneye's mbc1/ram_64kb: one 8 KB bank, whatever is selected
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x03, 0x01, 0x02));
+        mem.write_byte(0x0000, 0x0A);
+        mem.write_byte(0xA000, 0x11);
+        mem.write_byte(0x6000, 0x01);
+        mem.write_byte(0x4000, 0x02);
+        assert_eq!(mem.read_byte(0xA000), 0x11);
+
+        // mbc1/ram_256kb: four banks in mode 1
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x03, 0x01, 0x03));
+        mem.write_byte(0x0000, 0x0A);
+        mem.write_byte(0x6000, 0x01);
+        for bank in 0..4 {
+            mem.write_byte(0x4000, bank);
+            mem.write_byte(0xA000, 0x40 + bank);
+        }
+        mem.write_byte(0x4000, 0x02);
+        assert_eq!(mem.read_byte(0xA000), 0x42);
+        mem.write_byte(0x6000, 0x00);
+        assert_eq!(mem.read_byte(0xA000), 0x40);
+
+        // MBC5 with 32 KB: bank 5 is bank 1
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x1B, 0x01, 0x03));
+        mem.write_byte(0x0000, 0x0A);
+        mem.write_byte(0x4000, 0x01);
+        mem.write_byte(0xA123, 0x77);
+        mem.write_byte(0x4000, 0x05);
+        assert_eq!(mem.read_byte(0xA123), 0x77);
+        assert_eq!(mem.ram_bank(), 1);
+    }
+
+    #[test]
+    fn small_ram_repeats_across_the_window() {
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x03, 0x01, 0x01));
+        assert_eq!(mem.ram_bank_count(), 1);
+        mem.write_byte(0x0000, 0x0A);
+        mem.write_byte(0xA005, 0x5A);
+        for addr in [0xA805, 0xB005, 0xB805] {
+            assert_eq!(mem.read_byte(addr), 0x5A);
+        }
+        mem.write_byte(0xBFFF, 0x33);
+        assert_eq!(mem.read_byte(0xA7FF), 0x33);
+        assert_eq!(mem.get_eram().len(), 0x800);
+    }
+
+    #[test]
+    fn no_ram_reads_open_bus() {
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x01, 0x01, 0x00));
+        mem.write_byte(0x0000, 0x0A);
+        mem.write_byte(0xA000, 0x12);
+        assert_eq!(mem.read_byte(0xA000), 0xFF);
+        assert!(!mem.eram_dirty());
+    }
+
+    #[test]
+    fn mbc2_ram_is_half_bytes_echoed_every_512() {
+        let mut mem = Memory::new();
+        mem.load_rom(&numbered_rom(0x06, 0x02, 0x00));
+        assert_eq!(mem.ram_bank_count(), 1);
+        assert!(mem.has_battery());
+
+        // Bit 8 clear enables RAM even in 0x2000-0x3FFF
+        mem.write_byte(0x3000, 0x0A);
+        mem.write_byte(0xA000, 0x5C);
+        assert_eq!(mem.read_byte(0xA000), 0xFC);
+        assert_eq!(mem.read_byte(0xA200), 0xFC);
+        assert_eq!(mem.read_byte(0xBE00), 0xFC);
+        mem.write_byte(0xB1FF, 0x03);
+        assert_eq!(mem.read_byte(0xA1FF), 0xF3);
+        assert_eq!(mem.get_eram().len(), 512);
+        assert_eq!(mem.get_eram()[0], 0x0C);
+
+        // Bit 8 set selects the ROM bank even in 0x0000-0x1FFF
+        mem.write_byte(0x0100, 0x03);
+        assert_eq!(mem.read_byte(0x4000), 0x03);
+        mem.write_byte(0x0100, 0x00);
+        assert_eq!(mem.read_byte(0x4000), 0x01);
+        mem.write_byte(0x2000, 0x00);
+        assert_eq!(mem.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn truncated_and_overdumped_roms_wrap_at_the_banks_present() {
+        // Declares 256 KB but holds 128 KB: bank 9 mirrors bank 1
+        let mut rom = numbered_rom(0x19, 0x02, 0x00);
+        rom[0x0148] = 0x03;
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        mem.write_byte(0x2000, 0x09);
+        assert_eq!(mem.read_byte(0x4000), 0x01);
+
+        // Declares 64 KB but holds 128 KB: the extra banks are unreachable
+        rom[0x0148] = 0x01;
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        mem.write_byte(0x2000, 0x05);
+        assert_eq!(mem.read_byte(0x4000), 0x01);
+
+        // A bad size code on a stub image maps what there is
+        let mut mem = Memory::new();
+        mem.load_rom(&[0x19; 0x150]);
+        mem.write_byte(0x2000, 0x03);
+        assert_eq!(mem.read_byte(0x4000), 0x19);
+    }
+
+    #[test]
+    fn mbc5_rumble_bit_is_not_a_ram_bank_bit() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x1E; // MBC5+RUMBLE+RAM+BATTERY
+        rom[0x0149] = 0x03; // 32KB RAM
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        assert!(mem.has_rumble());
+
+        mem.write_byte(0x4000, 0x0A);
+        assert!(mem.rumble_active());
+        assert_eq!(mem.ram_bank(), 2);
+        mem.write_byte(0x4000, 0x02);
+        assert!(!mem.rumble_active());
+        assert_eq!(mem.ram_bank(), 2);
+
+        // Without a motor the bit is part of the bank number
+        rom[0x0147] = 0x1B;
+        rom[0x0149] = 0x04; // 128KB RAM
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        mem.write_byte(0x4000, 0x0A);
+        assert!(!mem.rumble_active());
+        assert_eq!(mem.ram_bank(), 0x0A);
+    }
+
+    #[test]
+    fn unmapped_io_and_unused_bits_read_high() {
+        let mut mem = Memory::new();
+        mem.load_rom(&[0u8; 0x8000]);
+        for addr in [0xFF03, 0xFF08, 0xFF15, 0xFF27, 0xFF4C, 0xFF50, 0xFF7F] {
+            mem.write_byte(addr, 0x00);
+            assert_eq!(mem.read_byte(addr), 0xFF, "{:04X}", addr);
+        }
+        mem.write_byte(io::TAC, 0x05);
+        assert_eq!(mem.read_byte(io::TAC), 0xFD);
+        mem.write_byte(io::IF, 0x01);
+        assert_eq!(mem.read_byte(io::IF), 0xE1);
+        assert_eq!(mem.read_byte(io::STAT) & 0x80, 0x80);
+        mem.write_byte(io::SB, 0x00);
+        assert_eq!(mem.read_byte(io::SB), 0x00);
+    }
+
+    #[test]
+    fn io_read_masks_match_hardware() {
+        // Writing 0x00 and reading back leaves just the bits tied high,
+        // after Mooneye's acceptance/bits tests
+        let dmg = [
+            (io::SC, 0x7E), (io::TAC, 0xF8), (io::IF, 0xE0), (io::NR10, 0x80),
+            (io::NR11, 0x3F), (io::NR13, 0xFF), (io::NR14, 0xBF), (io::NR30, 0x7F),
+            (io::NR32, 0x9F), (io::NR41, 0xFF), (io::NR44, 0xBF),
+            (io::KEY1, 0xFF), (io::RP, 0xFF), (0xFF4F, 0xFF), (0xFF70, 0xFF),
+        ];
+        let mut rom = vec![0u8; 0x8000];
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        mem.reset_io();
+        for (addr, mask) in dmg {
+            mem.write_byte(addr, 0x00);
+            assert_eq!(mem.read_byte(addr), mask, "{:04X}", addr);
+            mem.write_byte(addr, 0xFF);
+            assert_eq!(mem.read_byte(addr) & mask, mask, "{:04X}", addr);
+        }
+        assert_eq!(mem.read_byte(io::JOYP) & 0xC0, 0xC0);
+        // The mode bits are the PPU's
+        mem.write_byte(io::STAT, 0x00);
+        assert_eq!(mem.read_byte(io::STAT) & 0xF8, 0x80);
+
+        // A CGB game gets the serial clock speed bit
+        rom[0x0143] = 0x80;
+        let mut mem = Memory::new();
+        mem.load_rom(&rom);
+        mem.set_model(GbModel::Cgb);
+        mem.write_byte(io::SC, 0x02);
+        assert_eq!(mem.read_byte(io::SC), 0x7E);
+        mem.write_byte(io::SC, 0x00);
+        assert_eq!(mem.read_byte(io::SC), 0x7C);
+    }
+
+    #[test]
+    fn unusable_area_reads_by_model_and_ppu_mode() {
+        let mut mem = Memory::new();
+        mem.io[io::LCDC as usize] = 0x80;
+        mem.io[io::STAT as usize] = 0x80; // HBlank
+        assert_eq!(mem.read_byte(0xFEA5), 0x00);
+        mem.io[io::STAT as usize] = 0x83; // Drawing
+        assert_eq!(mem.read_byte(0xFEA5), 0xFF);
+
+        mem.set_model(GbModel::Cgb);
+        mem.io[io::STAT as usize] = 0x81; // VBlank
+        assert_eq!(mem.read_byte(0xFEA5), 0xAA);
+        assert_eq!(mem.read_byte(0xFEF0), 0xFF);
+        mem.io[io::LCDC as usize] = 0x00;
+        mem.io[io::STAT as usize] = 0x82;
+        assert_eq!(mem.read_byte(0xFEC0), 0xCC);
+    }
+
+    #[test]
+    fn accuracy_options_for_the_bus() {
+        let mut mem = Memory::new();
+        mem.wram[0xC000] = 0x11;
+        mem.wram[0xC001] = 0x22;
+        mem.vram[0x8000] = 0x33;
+        mem.hram[0xFF80] = 0x44;
+        mem.write_byte(io::DMA, 0xC0);
+        for _ in 0..4 {
+            mem.tick_dma();
+        }
+        // The DMA's bus shows the byte it copied; others stay reachable
+        assert_eq!(mem.read_byte(0xC001), 0x11);
+        assert_eq!(mem.read_byte(0x0100), 0x11);
+        assert_eq!(mem.read_byte(0xFE00), 0xFF);
+        assert_eq!(mem.read_byte(0x8000), 0x33);
+        assert_eq!(mem.read_byte(0xFF80), 0x44);
+        assert_eq!(mem.peek(0xC001), 0x22);
+
+        mem.set_accuracy(AccuracyOptions::FAST);
+        assert_eq!(mem.read_byte(0xC001), 0x22);
+        assert_eq!(mem.read_byte(0xFE00), 0x11);
+        mem.dma_active = false;
+        mem.io[io::STAT as usize] = 0x80; // HBlank
+        assert_eq!(mem.read_byte(0xFEA5), 0xFF);
+    }
+
+    #[test]
+    fn oam_bug_corrupts_the_scanned_row() {
+        let mut mem = Memory::new();
+        let row = |mem: &Memory, n: usize| (0..8).map(|i| mem.oam[0xFE00 + n * 8 + i]).collect::<Vec<_>>();
+        for i in 0..16 {
+            mem.oam[0xFE00 + i] = i as u8 * 0x11;
+        }
+        mem.oam_scan_row = Some(1);
+        mem.oam_bug(0xFE40);
+        // a = 0x9988, b = 0x1100, c = 0x5544
+        assert_eq!(row(&mem, 1), [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+
+        // Not outside OAM scan, on the CGB, or with the bug turned off
+        mem.oam[0xFE08] = 0xAB;
+        mem.oam_bug(0xD000);
+        mem.oam_scan_row = Some(0);
+        mem.oam_bug(0xFE40);
+        mem.oam_scan_row = Some(1);
+        mem.set_model(GbModel::Cgb);
+        mem.oam_bug(0xFE40);
+        mem.set_model(GbModel::DmgABC);
+        mem.set_accuracy(AccuracyOptions::FAST);
+        mem.oam_bug(0xFE40);
+        assert_eq!(mem.oam[0xFE08], 0xAB);
+    }
+
     #[test]
     fn echo_ram_mirrors_wram() {
         let mut mem = Memory::new();
@@ -787,7 +2088,7 @@ mod tests {
     #[test]
     fn div_reset_on_write() {
         let mut mem = Memory::new();
-        mem.data[io::DIV as usize] = 0xAB;
+        mem.io[io::DIV as usize] = 0xAB;
         mem.write_byte(io::DIV, 0x12);
         assert_eq!(mem.read_byte(io::DIV), 0x00);
     }
@@ -796,8 +2097,8 @@ mod tests {
     fn interrupt_request_and_clear() {
         let mut mem = Memory::new();
         mem.request_interrupt(interrupts::VBLANK);
-        assert_eq!(mem.data[io::IF as usize] & interrupts::VBLANK, interrupts::VBLANK);
+        assert_eq!(mem.io[io::IF as usize] & interrupts::VBLANK, interrupts::VBLANK);
         mem.clear_interrupt(interrupts::VBLANK);
-        assert_eq!(mem.data[io::IF as usize] & interrupts::VBLANK, 0);
+        assert_eq!(mem.io[io::IF as usize] & interrupts::VBLANK, 0);
     }
 }
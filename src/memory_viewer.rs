@@ -0,0 +1,374 @@
+//! Memory viewer window for the desktop UI
+//!
+//! F6 opens a hex dump of the whole 64 KB address space, read live
+//! through `peek` every frame. The mouse wheel, arrow keys and Page
+//! Up/Down scroll; G or the goto box jumps to a typed address. Clicking
+//! a byte selects it and typing two hex digits pokes a new value. The
+//! bank pickers show a ROM or cartridge RAM bank other than the mapped
+//! one at 0x4000-0x7FFF and 0xA000-0xBFFF.
+
+use crate::ui::{draw_rect, draw_text, fill_rect};
+use gb3000::Emulator;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+
+/// Window buffer size; the window shows it at 2x
+const WIDTH: usize = 584;
+const HEIGHT: usize = 324;
+
+/// Rows of 16 bytes shown, and where they start
+const ROWS: usize = 29;
+const ROWS_Y: usize = 28;
+const LINE_HEIGHT: usize = 10;
+/// Total rows in the address space
+const ADDRESS_ROWS: usize = 0x10000 / 16;
+
+const BYTES_X: usize = 48;
+const ASCII_X: usize = 456;
+
+/// Header widgets: the goto box and the two bank pickers
+const GOTO_X: usize = 8;
+const ROM_PICKER_X: usize = 136;
+const RAM_PICKER_X: usize = 296;
+
+const BACKGROUND: u32 = 0xFF1A1A2E;
+const TEXT: u32 = 0xFFE5E7EB;
+const DIM: u32 = 0xFF6B7280;
+const ACCENT: u32 = 0xFF0EA5E9;
+
+pub struct MemoryViewer {
+    window: Window,
+    buffer: Vec<u32>,
+    /// First row shown
+    top_row: usize,
+    /// Byte being edited
+    selected: Option<u16>,
+    /// First hex digit typed into the selected byte
+    high_nibble: Option<u8>,
+    /// Address typed into the goto box, while it has focus
+    goto: Option<String>,
+    /// Bank shown at 0x4000-0x7FFF, or None for the mapped one
+    rom_bank: Option<usize>,
+    /// Bank shown at 0xA000-0xBFFF, or None for the mapped one
+    ram_bank: Option<usize>,
+    /// Mouse button state last frame, to detect clicks
+    mouse_was_down: bool,
+}
+
+impl MemoryViewer {
+    pub fn open() -> Self {
+        let mut window = Window::new(
+            "GB3000 - Memory",
+            WIDTH,
+            HEIGHT,
+            WindowOptions { scale: Scale::X2, ..WindowOptions::default() },
+        )
+        .expect("Failed to create memory viewer window");
+        window.set_target_fps(0);
+        Self {
+            window,
+            buffer: vec![0; WIDTH * HEIGHT],
+            top_row: 0,
+            selected: None,
+            high_nibble: None,
+            goto: None,
+            rom_bank: None,
+            ram_bank: None,
+            mouse_was_down: false,
+        }
+    }
+
+    /// Handle input and redraw
+    ///
+    /// Returns false once the window was closed, with its close button or
+    /// F6.
+    pub fn update(&mut self, emulator: &mut Emulator) -> bool {
+        if !self.window.is_open() || self.window.is_key_pressed(Key::F6, KeyRepeat::No) {
+            return false;
+        }
+        let mouse = self.window.get_mouse_pos(MouseMode::Discard).map(|(x, y)| (x as usize, y as usize));
+        let down = self.window.get_mouse_down(MouseButton::Left);
+        let click = mouse.filter(|_| down && !self.mouse_was_down);
+        self.mouse_was_down = down;
+
+        if let Some((_, scroll)) = self.window.get_scroll_wheel() {
+            self.scroll(-(scroll.signum() as isize) * 3);
+        }
+        for key in self.window.get_keys_pressed(KeyRepeat::Yes) {
+            self.handle_key(emulator, key);
+        }
+
+        fill_rect(&mut self.buffer, WIDTH, 0, 0, WIDTH, HEIGHT, BACKGROUND);
+        self.draw_header(emulator, mouse, click);
+        self.draw_rows(emulator, click);
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .expect("Failed to update memory viewer window");
+        true
+    }
+
+    fn handle_key(&mut self, emulator: &mut Emulator, key: Key) {
+        if let Some(text) = self.goto.as_mut() {
+            match key {
+                Key::Enter => {
+                    if let Ok(addr) = u16::from_str_radix(text, 16) {
+                        self.select(addr);
+                    }
+                    self.goto = None;
+                }
+                Key::Escape => self.goto = None,
+                Key::Backspace => {
+                    text.pop();
+                }
+                _ => {
+                    if let Some(digit) = hex_digit(key).filter(|_| text.len() < 4) {
+                        text.push(char::from_digit(digit as u32, 16).unwrap().to_ascii_uppercase());
+                    }
+                }
+            }
+            return;
+        }
+
+        match key {
+            Key::G => self.goto = Some(String::new()),
+            Key::PageUp => self.scroll(-(ROWS as isize)),
+            Key::PageDown => self.scroll(ROWS as isize),
+            Key::Escape => self.selected = None,
+            _ => {}
+        }
+        let Some(addr) = self.selected else {
+            match key {
+                Key::Up => self.scroll(-1),
+                Key::Down => self.scroll(1),
+                _ => {}
+            }
+            return;
+        };
+        let moved = match key {
+            Key::Left => Some(addr.wrapping_sub(1)),
+            Key::Right => Some(addr.wrapping_add(1)),
+            Key::Up => Some(addr.wrapping_sub(16)),
+            Key::Down => Some(addr.wrapping_add(16)),
+            _ => None,
+        };
+        if let Some(addr) = moved {
+            self.select(addr);
+        } else if let Some(digit) = hex_digit(key) {
+            match self.high_nibble.take() {
+                None => self.high_nibble = Some(digit),
+                Some(high) => {
+                    write(emulator, addr, high << 4 | digit, self.ram_bank);
+                    self.select(addr.wrapping_add(1));
+                }
+            }
+        }
+    }
+
+    /// Select `addr`, scrolling it into view
+    fn select(&mut self, addr: u16) {
+        self.selected = Some(addr);
+        self.high_nibble = None;
+        let row = addr as usize / 16;
+        if row < self.top_row {
+            self.top_row = row;
+        } else if row >= self.top_row + ROWS {
+            self.top_row = row + 1 - ROWS;
+        }
+    }
+
+    fn scroll(&mut self, rows: isize) {
+        self.top_row = self.top_row.saturating_add_signed(rows).min(ADDRESS_ROWS - ROWS);
+    }
+
+    fn draw_header(&mut self, emulator: &Emulator, mouse: Option<(usize, usize)>, click: Option<(usize, usize)>) {
+        let buffer = &mut self.buffer;
+        let inside = |x: usize, w: usize| move |(mx, my): (usize, usize)| (x..x + w).contains(&mx) && (4..18).contains(&my);
+
+        // Goto box
+        draw_text(buffer, WIDTH, GOTO_X, 8, "Goto", DIM);
+        let (box_x, box_w) = (GOTO_X + 40, 48);
+        let focused = self.goto.is_some();
+        fill_rect(buffer, WIDTH, box_x, 4, box_w, 14, 0xFF1F2937);
+        draw_rect(buffer, WIDTH, box_x, 4, box_w, 14, if focused { ACCENT } else { 0xFF4B5563 });
+        let text = match &self.goto {
+            Some(text) => format!("{}_", text),
+            None => self.selected.map_or(String::new(), |addr| format!("{:04X}", addr)),
+        };
+        draw_text(buffer, WIDTH, box_x + 4, 8, &text, TEXT);
+        if click.is_some_and(inside(box_x, box_w)) {
+            self.goto = Some(String::new());
+        }
+
+        // Bank pickers: "-", the bank (or "auto" for the mapped one), "+"
+        let pickers = [
+            (ROM_PICKER_X, "ROM", &mut self.rom_bank, emulator.rom_bank_count()),
+            (RAM_PICKER_X, "RAM", &mut self.ram_bank, emulator.ram_bank_count()),
+        ];
+        for (x, name, bank, count) in pickers {
+            draw_text(buffer, WIDTH, x, 8, name, DIM);
+            let value = match (*bank, count) {
+                (_, 0) => "none".to_string(),
+                (None, _) => "auto".to_string(),
+                (Some(b), _) => format!("{:02X}", b),
+            };
+            for (dx, label, delta) in [(32, "-", -1), (96, "+", 1)] {
+                let hover = mouse.is_some_and(inside(x + dx, 16));
+                fill_rect(buffer, WIDTH, x + dx, 4, 16, 14, if hover { 0xFF374151 } else { 0xFF1F2937 });
+                draw_rect(buffer, WIDTH, x + dx, 4, 16, 14, 0xFF4B5563);
+                draw_text(buffer, WIDTH, x + dx + 4, 8, label, TEXT);
+                if click.is_some_and(inside(x + dx, 16)) {
+                    *bank = step_bank(*bank, delta, count);
+                }
+            }
+            draw_text(buffer, WIDTH, x + 56, 8, &value, TEXT);
+        }
+
+        if let Some(addr) = self.selected {
+            draw_text(buffer, WIDTH, 456, 8, region_name(addr), DIM);
+        }
+    }
+
+    fn draw_rows(&mut self, emulator: &Emulator, click: Option<(usize, usize)>) {
+        let buffer = &mut self.buffer;
+        let mut clicked = None;
+        for row in 0..ROWS {
+            let base = ((self.top_row + row) * 16) as u16;
+            let y = ROWS_Y + row * LINE_HEIGHT;
+            draw_text(buffer, WIDTH, 8, y, &format!("{:04X}", base), DIM);
+            let mut ascii = String::with_capacity(16);
+            for col in 0..16 {
+                let addr = base + col as u16;
+                let value = read(emulator, addr, self.rom_bank, self.ram_bank);
+                let x = byte_x(col);
+                let color = if self.selected == Some(addr) {
+                    fill_rect(buffer, WIDTH, x - 2, y - 1, 20, LINE_HEIGHT, ACCENT);
+                    0xFF000000
+                } else {
+                    TEXT
+                };
+                let text = match self.high_nibble.filter(|_| self.selected == Some(addr)) {
+                    Some(high) => format!("{:X}_", high),
+                    None => format!("{:02X}", value),
+                };
+                draw_text(buffer, WIDTH, x, y, &text, color);
+                let on_byte = |(mx, my): (usize, usize)| (x..x + 16).contains(&mx) && (y..y + LINE_HEIGHT).contains(&my);
+                if click.is_some_and(on_byte) {
+                    clicked = Some(addr);
+                }
+                ascii.push(if value.is_ascii_alphanumeric() || value == b' ' { value as char } else { '.' });
+            }
+            draw_text(buffer, WIDTH, ASCII_X, y, &ascii, DIM);
+        }
+        if let Some(addr) = clicked {
+            self.goto = None;
+            self.select(addr);
+        }
+    }
+}
+
+/// Left edge of byte `col` in a row, with a gap after the first eight
+fn byte_x(col: usize) -> usize {
+    BYTES_X + col * 24 + if col >= 8 { 8 } else { 0 }
+}
+
+/// Read `addr`, through the picked bank where one is picked
+fn read(emulator: &Emulator, addr: u16, rom_bank: Option<usize>, ram_bank: Option<usize>) -> u8 {
+    match (addr, rom_bank, ram_bank) {
+        (0x4000..=0x7FFF, Some(bank), _) | (0xA000..=0xBFFF, _, Some(bank)) => emulator.peek_banked(bank, addr),
+        _ => emulator.peek(addr),
+    }
+}
+
+/// Write `addr`, through the picked RAM bank where one is picked
+fn write(emulator: &mut Emulator, addr: u16, value: u8, ram_bank: Option<usize>) {
+    match ram_bank {
+        Some(bank) => emulator.poke_banked(bank, addr, value),
+        None => emulator.poke(addr, value),
+    }
+}
+
+/// Next bank choice in "auto", 0, 1, ... `count - 1` order, wrapping
+fn step_bank(bank: Option<usize>, delta: isize, count: usize) -> Option<usize> {
+    if count == 0 {
+        return None;
+    }
+    // Position 0 is "auto", then one per bank
+    let position = bank.map_or(0, |b| b + 1) as isize;
+    match (position + delta).rem_euclid(count as isize + 1) {
+        0 => None,
+        p => Some(p as usize - 1),
+    }
+}
+
+/// Hex value typed by `key`, from the number row, the keypad or A-F
+fn hex_digit(key: Key) -> Option<u8> {
+    const DIGITS: [(Key, Key); 10] = [
+        (Key::Key0, Key::NumPad0), (Key::Key1, Key::NumPad1), (Key::Key2, Key::NumPad2),
+        (Key::Key3, Key::NumPad3), (Key::Key4, Key::NumPad4), (Key::Key5, Key::NumPad5),
+        (Key::Key6, Key::NumPad6), (Key::Key7, Key::NumPad7), (Key::Key8, Key::NumPad8),
+        (Key::Key9, Key::NumPad9),
+    ];
+    const LETTERS: [Key; 6] = [Key::A, Key::B, Key::C, Key::D, Key::E, Key::F];
+    DIGITS
+        .iter()
+        .position(|&(row, pad)| key == row || key == pad)
+        .or_else(|| LETTERS.iter().position(|&k| k == key).map(|i| i + 10))
+        .map(|d| d as u8)
+}
+
+/// Name of the memory region `addr` is in
+fn region_name(addr: u16) -> &'static str {
+    match addr {
+        0x0000..=0x3FFF => "ROM bank 0",
+        0x4000..=0x7FFF => "ROM banked",
+        0x8000..=0x9FFF => "VRAM",
+        0xA000..=0xBFFF => "Cart RAM",
+        0xC000..=0xDFFF => "WRAM",
+        0xE000..=0xFDFF => "Echo RAM",
+        0xFE00..=0xFE9F => "OAM",
+        0xFEA0..=0xFEFF => "Unusable",
+        0xFF00..=0xFF7F => "I/O",
+        0xFF80..=0xFFFE => "HRAM",
+        0xFFFF => "IE",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_through_picked_banks() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x03; // MBC1+RAM+BATTERY
+        rom[0x0148] = 0x01; // 64KB
+        rom[0x0149] = 0x03; // 32KB RAM
+        rom[0x3 * 0x4000 + 0x10] = 0xAB;
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&rom);
+
+        assert_eq!(read(&emulator, 0x4010, None, None), 0x00);
+        assert_eq!(read(&emulator, 0x4010, Some(3), None), 0xAB);
+        write(&mut emulator, 0xA000, 0x42, Some(2));
+        assert_eq!(read(&emulator, 0xA000, None, Some(2)), 0x42);
+        assert_eq!(read(&emulator, 0xA000, None, Some(0)), 0x00);
+        write(&mut emulator, 0xC000, 0x99, Some(2));
+        assert_eq!(read(&emulator, 0xC000, Some(3), Some(2)), 0x99);
+    }
+
+    #[test]
+    fn bank_pickers_cycle_through_auto() {
+        assert_eq!(step_bank(None, 1, 4), Some(0));
+        assert_eq!(step_bank(Some(3), 1, 4), None);
+        assert_eq!(step_bank(None, -1, 4), Some(3));
+        assert_eq!(step_bank(Some(0), -1, 4), None);
+        assert_eq!(step_bank(None, 1, 0), None);
+    }
+
+    #[test]
+    fn hex_digits_come_from_any_digit_key() {
+        assert_eq!(hex_digit(Key::Key7), Some(7));
+        assert_eq!(hex_digit(Key::NumPad0), Some(0));
+        assert_eq!(hex_digit(Key::F), Some(15));
+        assert_eq!(hex_digit(Key::G), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Audio mixing for two consoles played side by side
+//!
+//! Each console's audio sink queues its samples here. The consoles run in
+//! lockstep, so they produce samples at the same pace; whatever both have
+//! produced so far is mixed with each console's volume and pan and handed
+//! to the output buffer.
+
+use crate::config::ConsoleMix;
+use crate::emu_thread::{AudioBuffer, AUDIO_BUFFER_SIZE};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Samples of both consoles on their way to the output buffer
+pub struct Mixer {
+    output: AudioBuffer,
+    /// Master volume as `f32` bits
+    volume: Arc<AtomicU32>,
+    /// Left and right gain of each console
+    gains: [[f32; 2]; 2],
+    /// Interleaved samples of each console the other hasn't caught up with
+    pending: [VecDeque<f32>; 2],
+}
+
+impl Mixer {
+    pub fn new(output: &AudioBuffer, volume: &Arc<AtomicU32>, levels: [ConsoleMix; 2]) -> Arc<Mutex<Self>> {
+        // Panning turns down the far side only, so centered is full volume
+        let gains = levels.map(|mix| [mix.volume * (1.0 - mix.pan).min(1.0), mix.volume * (1.0 + mix.pan).min(1.0)]);
+        Arc::new(Mutex::new(Self {
+            output: Arc::clone(output),
+            volume: Arc::clone(volume),
+            gains,
+            pending: [VecDeque::new(), VecDeque::new()],
+        }))
+    }
+
+    /// Queue samples from `console`, mixing out what both have produced
+    fn push(&mut self, console: usize, samples: &[f32]) {
+        let pending = &mut self.pending[console];
+        pending.extend(samples);
+        // Don't let a console that went quiet hold the other one back
+        let excess = pending.len().saturating_sub(AUDIO_BUFFER_SIZE);
+        pending.drain(..excess);
+
+        let ready = self.pending[0].len().min(self.pending[1].len());
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        let Ok(mut out) = self.output.lock() else { return };
+        let [first, second] = &mut self.pending;
+        for (i, (a, b)) in first.drain(..ready).zip(second.drain(..ready)).enumerate() {
+            let channel = i % 2;
+            out.push_back((a * self.gains[0][channel] + b * self.gains[1][channel]) * volume);
+        }
+        let excess = out.len().saturating_sub(AUDIO_BUFFER_SIZE);
+        out.drain(..excess);
+    }
+}
+
+/// Audio sink feeding `console`'s (0 or 1) side of the mixer
+pub fn sink(mixer: &Arc<Mutex<Mixer>>, console: usize) -> impl FnMut(&[f32]) + Send + 'static {
+    let mixer = Arc::clone(mixer);
+    move |samples| {
+        if let Ok(mut m) = mixer.lock() {
+            m.push(console, samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixes_what_both_consoles_produced() {
+        let output: AudioBuffer = Arc::new(Mutex::new(VecDeque::new()));
+        let volume = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+        let levels = [ConsoleMix { volume: 1.0, pan: -1.0 }, ConsoleMix { volume: 0.5, pan: 0.0 }];
+        let mixer = Mixer::new(&output, &volume, levels);
+        let (mut first, mut second) = (sink(&mixer, 0), sink(&mixer, 1));
+
+        first(&[0.5, 0.5, 0.25, 0.25]);
+        assert!(output.lock().unwrap().is_empty());
+        second(&[0.5, 0.5]);
+        // The first console is left only, the second centered at half volume
+        assert_eq!(output.lock().unwrap().drain(..).collect::<Vec<_>>(), [0.75, 0.25]);
+
+        second(&[1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(output.lock().unwrap().drain(..).collect::<Vec<_>>(), [0.75, 0.5]);
+        assert_eq!(mixer.lock().unwrap().pending[1].len(), 2);
+    }
+}
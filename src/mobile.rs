@@ -0,0 +1,603 @@
+//! Mobile Adapter GB
+//!
+//! The adapter sits in the link port and connects a game to a mobile
+//! phone, and through the phone to another player or to an ISP and
+//! Nintendo's servers (Mobile Trainer, Pokémon Crystal's Japanese Mobile
+//! Stadium and trade corner). Attach a [`MobileAdapter`] with
+//! [`Emulator::set_serial_device`](crate::Emulator::set_serial_device);
+//! what lies beyond the phone is a [`MobileBackend`], such as the offline
+//! [`MockBackend`].
+//!
+//! The game clocks packets both ways, with the adapter answering each
+//! byte on the next transfer:
+//! - 0x99 0x66 magic
+//! - command, 0x00, and the data length as a big-endian word
+//! - up to 254 bytes of data
+//! - the big-endian 16-bit sum of header and data
+//! - acknowledgement: the sender's device ID and 0x00, answered with the
+//!   receiver's device ID and the command XOR 0x80 (0xF0 for an unknown
+//!   command, 0xF1 for a bad checksum)
+//!
+//! Every accepted command gets a reply packet with bit 7 of the command
+//! set, or 0xEE holding the command and an error code. The adapter idles
+//! with 0xD2 and only speaks 8-bit mode.
+
+use crate::serial::SerialDevice;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Size of the adapter's settings memory
+pub const CONFIG_SIZE: usize = 0xC0;
+
+/// Byte the adapter sends while it has nothing to say
+const IDLE: u8 = 0xD2;
+
+/// Acknowledgement of a command the adapter doesn't know
+const UNKNOWN_COMMAND: u8 = 0xF0;
+
+/// Acknowledgement of a packet with a bad checksum
+const BAD_CHECKSUM: u8 = 0xF1;
+
+/// Longest packet data
+const MAX_DATA: usize = 254;
+
+/// Connection ID of the telephone call in transfer commands
+pub const CALL: u8 = 0xFF;
+
+/// Number of TCP and UDP connections open at once
+const CONNECTIONS: u8 = 2;
+
+mod command {
+    pub const BEGIN_SESSION: u8 = 0x10;
+    pub const END_SESSION: u8 = 0x11;
+    pub const DIAL: u8 = 0x12;
+    pub const HANG_UP: u8 = 0x13;
+    pub const WAIT_FOR_CALL: u8 = 0x14;
+    pub const TRANSFER: u8 = 0x15;
+    pub const RESET: u8 = 0x16;
+    pub const STATUS: u8 = 0x17;
+    pub const SIO32: u8 = 0x18;
+    pub const READ_CONFIG: u8 = 0x19;
+    pub const WRITE_CONFIG: u8 = 0x1A;
+    pub const TRANSFER_ENDED: u8 = 0x1F;
+    pub const ISP_LOGIN: u8 = 0x21;
+    pub const ISP_LOGOUT: u8 = 0x22;
+    pub const OPEN_TCP: u8 = 0x23;
+    pub const CLOSE_TCP: u8 = 0x24;
+    pub const OPEN_UDP: u8 = 0x25;
+    pub const CLOSE_UDP: u8 = 0x26;
+    pub const DNS_QUERY: u8 = 0x28;
+    pub const ERROR: u8 = 0x6E;
+}
+
+/// Adapter models, one per Japanese phone network; games read the
+/// model from the device ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdapterKind {
+    /// PDC phones
+    #[default]
+    Blue,
+    /// cdmaOne phones
+    Yellow,
+    /// PHS phones
+    Green,
+    /// DDI phones
+    Red,
+}
+
+impl AdapterKind {
+    fn device_id(self) -> u8 {
+        0x88 | self as u8
+    }
+}
+
+/// What the adapter's phone reaches
+///
+/// Connection IDs are picked by the adapter: [`CALL`] for the telephone
+/// call, otherwise one of two TCP or UDP connections.
+pub trait MobileBackend: Send {
+    /// Call `number`; true once the other end picks up
+    fn dial(&mut self, number: &str) -> bool;
+
+    /// End the call
+    fn hang_up(&mut self) {}
+
+    /// Whether someone is calling; answering is implied
+    fn incoming_call(&mut self) -> bool {
+        false
+    }
+
+    /// Resolve a host name to an IPv4 address
+    fn resolve(&mut self, host: &str) -> Option<[u8; 4]>;
+
+    /// Open connection `id` to `address`:`port`, over UDP when `udp`
+    fn connect(&mut self, id: u8, address: [u8; 4], port: u16, udp: bool) -> bool;
+
+    /// Close connection `id`
+    fn disconnect(&mut self, id: u8);
+
+    /// Send `data` over connection `id` and return what has arrived since
+    /// the last transfer, or None once the other end has closed it
+    fn transfer(&mut self, id: u8, data: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Where the adapter is in a packet exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for 0x99
+    Idle,
+    /// Got 0x99, waiting for 0x66
+    Magic,
+    /// Reading header, data and checksum
+    Packet,
+    /// Answering the game's device ID
+    Ack,
+    /// Answering the game's 0x00, after which the reply goes out
+    AckEnd,
+}
+
+/// A Mobile Adapter GB in the link port
+pub struct MobileAdapter<B> {
+    backend: B,
+    kind: AdapterKind,
+    config: [u8; CONFIG_SIZE],
+    /// Byte the game reads on its next transfer
+    next: u8,
+    phase: Phase,
+    /// Packet being received, from the command to the checksum
+    packet: Vec<u8>,
+    /// Reply still to go out, with its acknowledgement
+    reply: VecDeque<u8>,
+    /// Between begin and end session commands
+    in_session: bool,
+    in_call: bool,
+    /// Kind of each open connection: Some(true) for UDP
+    connections: [Option<bool>; CONNECTIONS as usize],
+}
+
+impl<B: MobileBackend> MobileAdapter<B> {
+    /// A blue adapter with blank settings
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            kind: AdapterKind::default(),
+            config: [0; CONFIG_SIZE],
+            next: IDLE,
+            phase: Phase::Idle,
+            packet: Vec::new(),
+            reply: VecDeque::new(),
+            in_session: false,
+            in_call: false,
+            connections: [None; CONNECTIONS as usize],
+        }
+    }
+
+    /// Use another adapter model
+    pub fn with_kind(mut self, kind: AdapterKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Start from settings saved with [`config`](Self::config)
+    pub fn with_config(mut self, config: [u8; CONFIG_SIZE]) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The settings memory, where games keep the user's ISP login and
+    /// email address
+    pub fn config(&self) -> &[u8; CONFIG_SIZE] {
+        &self.config
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Take in one byte from the game and pick the one it gets next
+    fn receive(&mut self, byte: u8) -> u8 {
+        if let Some(out) = self.reply.pop_front() {
+            // The game clocks our reply out; its bytes mean nothing here
+            return out;
+        }
+        match self.phase {
+            Phase::Idle | Phase::Magic if byte == 0x99 => self.phase = Phase::Magic,
+            Phase::Magic if byte == 0x66 => {
+                self.packet.clear();
+                self.phase = Phase::Packet;
+            }
+            Phase::Idle | Phase::Magic => self.phase = Phase::Idle,
+            Phase::Packet => {
+                self.packet.push(byte);
+                if self.packet.len() == 4 && (self.packet[2] != 0 || self.packet[3] as usize > MAX_DATA) {
+                    self.phase = Phase::Idle;
+                } else if self.packet.len() >= 4 && self.packet.len() == 6 + self.packet[3] as usize {
+                    self.phase = Phase::Ack;
+                    return self.kind.device_id();
+                }
+            }
+            Phase::Ack => {
+                self.phase = Phase::AckEnd;
+                let (body, sum) = self.packet.split_at(self.packet.len() - 2);
+                let checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+                return if checksum != u16::from_be_bytes([sum[0], sum[1]]) {
+                    self.phase = Phase::Idle;
+                    BAD_CHECKSUM
+                } else if !is_known(self.packet[0]) {
+                    self.phase = Phase::Idle;
+                    UNKNOWN_COMMAND
+                } else {
+                    self.packet[0] ^ 0x80
+                };
+            }
+            Phase::AckEnd => {
+                self.phase = Phase::Idle;
+                let command = self.packet[0];
+                let data = self.packet[4..self.packet.len() - 2].to_vec();
+                let (command, mut data) = match self.handle(command, &data) {
+                    Ok(reply) => reply,
+                    Err(code) => (command::ERROR, vec![command, code]),
+                };
+                data.truncate(MAX_DATA);
+                self.send(command, &data);
+                return self.reply.pop_front().unwrap_or(IDLE);
+            }
+        }
+        IDLE
+    }
+
+    /// Queue a reply packet and its acknowledgement
+    fn send(&mut self, command: u8, data: &[u8]) {
+        let header = [command | 0x80, 0x00, 0x00, data.len() as u8];
+        let checksum = header.iter().chain(data).fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        self.reply.extend([0x99, 0x66]);
+        self.reply.extend(header);
+        self.reply.extend(data);
+        self.reply.extend(checksum.to_be_bytes());
+        self.reply.extend([self.kind.device_id(), 0x00]);
+    }
+
+    /// Carry out a command, returning the reply's command and data or an
+    /// error code: 0 for no incoming call, 1 for the wrong moment, 2 for
+    /// bad data, 3 when the backend couldn't do it
+    fn handle(&mut self, command: u8, data: &[u8]) -> Result<(u8, Vec<u8>), u8> {
+        use command::*;
+        if command != BEGIN_SESSION && !self.in_session {
+            return Err(1);
+        }
+        let reply = |data: Vec<u8>| Ok((command, data));
+        match command {
+            BEGIN_SESSION if data == b"NINTENDO" && !self.in_session => {
+                self.in_session = true;
+                reply(data.to_vec())
+            }
+            BEGIN_SESSION => Err(1),
+            END_SESSION | RESET => {
+                self.end_call();
+                self.in_session = command == RESET;
+                reply(Vec::new())
+            }
+            DIAL if self.in_call => Err(1),
+            DIAL => {
+                // The first byte is a protocol flag games always clear
+                let number = String::from_utf8_lossy(data.get(1..).unwrap_or_default()).into_owned();
+                if !self.backend.dial(&number) {
+                    return Err(3);
+                }
+                self.in_call = true;
+                reply(Vec::new())
+            }
+            HANG_UP => {
+                self.end_call();
+                reply(Vec::new())
+            }
+            WAIT_FOR_CALL if !self.in_call && self.backend.incoming_call() => {
+                self.in_call = true;
+                reply(Vec::new())
+            }
+            WAIT_FOR_CALL => Err(0),
+            TRANSFER => {
+                let (&id, payload) = data.split_first().ok_or(2u8)?;
+                let open = if id == CALL { self.in_call } else { self.connection(id).is_some() };
+                if !open {
+                    return Err(1);
+                }
+                match self.backend.transfer(id, payload) {
+                    Some(received) => reply([&[id], &received[..]].concat()),
+                    None => {
+                        self.forget(id);
+                        Ok((TRANSFER_ENDED, vec![id]))
+                    }
+                }
+            }
+            STATUS => reply(vec![if self.in_call { 0x04 } else { 0x00 }, self.kind.device_id() & 0x7F, 0x00]),
+            // Only 8-bit transfers: the mode stays as it is
+            SIO32 => reply(Vec::new()),
+            READ_CONFIG => {
+                let &[offset, len] = data else { return Err(2) };
+                let range = offset as usize..offset as usize + len as usize;
+                let bytes = self.config.get(range).ok_or(2u8)?;
+                reply([&[offset], bytes].concat())
+            }
+            WRITE_CONFIG => {
+                let (&offset, bytes) = data.split_first().ok_or(2u8)?;
+                let range = offset as usize..offset as usize + bytes.len();
+                self.config.get_mut(range).ok_or(2u8)?.copy_from_slice(bytes);
+                reply(vec![offset, bytes.len() as u8])
+            }
+            ISP_LOGIN if !self.in_call => Err(1),
+            ISP_LOGIN => {
+                // Addresses for the game to show: ours, then the DNS servers it asked for
+                let dns = data.get(data.len().saturating_sub(8)..).unwrap_or_default();
+                reply([&[127, 0, 0, 1][..], dns].concat())
+            }
+            ISP_LOGOUT => {
+                self.close_all();
+                reply(Vec::new())
+            }
+            OPEN_TCP | OPEN_UDP => {
+                let &[a, b, c, d, port_hi, port_lo] = data else { return Err(2) };
+                let udp = command == OPEN_UDP;
+                let id = (0..CONNECTIONS).find(|&id| self.connection(id).is_none()).ok_or(1u8)?;
+                if !self.backend.connect(id, [a, b, c, d], u16::from_be_bytes([port_hi, port_lo]), udp) {
+                    return Err(3);
+                }
+                self.connections[id as usize] = Some(udp);
+                reply(vec![id])
+            }
+            CLOSE_TCP | CLOSE_UDP => {
+                let &[id] = data else { return Err(2) };
+                if self.connection(id) != Some(command == CLOSE_UDP) {
+                    return Err(1);
+                }
+                self.backend.disconnect(id);
+                self.forget(id);
+                reply(vec![id])
+            }
+            DNS_QUERY => {
+                let host = String::from_utf8_lossy(data);
+                let address = self.backend.resolve(host.trim_end_matches('\0')).ok_or(3u8)?;
+                reply(address.to_vec())
+            }
+            _ => Err(0),
+        }
+    }
+
+    fn connection(&self, id: u8) -> Option<bool> {
+        self.connections.get(id as usize).copied().flatten()
+    }
+
+    fn forget(&mut self, id: u8) {
+        if id == CALL {
+            self.in_call = false;
+            self.close_all();
+        } else if let Some(slot) = self.connections.get_mut(id as usize) {
+            *slot = None;
+        }
+    }
+
+    fn close_all(&mut self) {
+        for id in 0..CONNECTIONS {
+            if self.connection(id).is_some() {
+                self.backend.disconnect(id);
+                self.forget(id);
+            }
+        }
+    }
+
+    fn end_call(&mut self) {
+        self.close_all();
+        if self.in_call {
+            self.in_call = false;
+            self.backend.hang_up();
+        }
+    }
+}
+
+fn is_known(command: u8) -> bool {
+    use command::*;
+    matches!(
+        command,
+        BEGIN_SESSION
+            | END_SESSION
+            | DIAL
+            | HANG_UP
+            | WAIT_FOR_CALL
+            | TRANSFER
+            | RESET
+            | STATUS
+            | SIO32
+            | READ_CONFIG
+            | WRITE_CONFIG
+            | ISP_LOGIN
+            | ISP_LOGOUT
+            | OPEN_TCP
+            | CLOSE_TCP
+            | OPEN_UDP
+            | CLOSE_UDP
+            | DNS_QUERY
+    )
+}
+
+impl<B: MobileBackend> SerialDevice for MobileAdapter<B> {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        // The answer to this byte goes out on the next transfer
+        let out = self.next;
+        self.next = self.receive(byte);
+        out
+    }
+}
+
+/// An offline stand-in for the phone network and the servers behind it
+///
+/// Every number answers and echoes what it is sent. Host names resolve
+/// from a table filled with [`add_host`](Self::add_host); connections
+/// succeed to any address and get the responses queued for it with
+/// [`serve`](Self::serve), one per transfer. What the game sends is
+/// kept for inspection.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    hosts: BTreeMap<String, [u8; 4]>,
+    responses: BTreeMap<([u8; 4], u16), VecDeque<Vec<u8>>>,
+    /// Where each open connection goes
+    connections: BTreeMap<u8, ([u8; 4], u16)>,
+    sent: Vec<(u8, Vec<u8>)>,
+    dialed: Vec<String>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve `host` to `address`
+    pub fn add_host(&mut self, host: &str, address: [u8; 4]) {
+        self.hosts.insert(host.to_string(), address);
+    }
+
+    /// Queue `response` for the next transfer on a connection to
+    /// `address`:`port`
+    pub fn serve(&mut self, address: [u8; 4], port: u16, response: &[u8]) {
+        self.responses.entry((address, port)).or_default().push_back(response.to_vec());
+    }
+
+    /// Non-empty data the game sent, with the connection ID it went over
+    pub fn sent(&self) -> &[(u8, Vec<u8>)] {
+        &self.sent
+    }
+
+    /// Numbers the game called
+    pub fn dialed(&self) -> &[String] {
+        &self.dialed
+    }
+}
+
+impl MobileBackend for MockBackend {
+    fn dial(&mut self, number: &str) -> bool {
+        self.dialed.push(number.to_string());
+        true
+    }
+
+    fn resolve(&mut self, host: &str) -> Option<[u8; 4]> {
+        self.hosts.get(host).copied()
+    }
+
+    fn connect(&mut self, id: u8, address: [u8; 4], port: u16, _udp: bool) -> bool {
+        self.connections.insert(id, (address, port));
+        true
+    }
+
+    fn disconnect(&mut self, id: u8) {
+        self.connections.remove(&id);
+    }
+
+    fn transfer(&mut self, id: u8, data: &[u8]) -> Option<Vec<u8>> {
+        if !data.is_empty() {
+            self.sent.push((id, data.to_vec()));
+        }
+        if id == CALL {
+            return Some(data.to_vec());
+        }
+        let endpoint = self.connections.get(&id)?;
+        let queue = self.responses.get_mut(endpoint);
+        Some(queue.and_then(VecDeque::pop_front).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock a packet into the adapter the way a game does, returning the
+    /// reply's command and data
+    fn command(adapter: &mut MobileAdapter<MockBackend>, command: u8, data: &[u8]) -> (u8, Vec<u8>) {
+        let mut packet = vec![0x99, 0x66, command, 0x00, 0x00, data.len() as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        packet.extend_from_slice(&checksum.to_be_bytes());
+        for &byte in &packet {
+            assert_eq!(adapter.exchange(byte), IDLE);
+        }
+        assert_eq!(adapter.exchange(0x80), 0x88, "device ID");
+        assert_eq!(adapter.exchange(0x00), command ^ 0x80, "acknowledgement");
+
+        // Clock the reply out with idle bytes
+        let mut reply: Vec<u8> = (0..6).map(|_| adapter.exchange(0x4B)).collect();
+        assert_eq!(reply[..2], [0x99, 0x66]);
+        let len = reply[5] as usize;
+        reply.extend((0..len + 2).map(|_| adapter.exchange(0x4B)));
+        let checksum = reply[2..6 + len].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        assert_eq!(reply[6 + len..], checksum.to_be_bytes());
+        assert_eq!(adapter.exchange(0x80), 0x88);
+        adapter.exchange(reply[2] ^ 0x80);
+        assert_eq!(adapter.exchange(0x4B), IDLE);
+        (reply[2], reply[6..6 + len].to_vec())
+    }
+
+    #[test]
+    fn sessions_calls_and_settings() {
+        let mut adapter = MobileAdapter::new(MockBackend::new());
+        assert_eq!(command(&mut adapter, 0x17, &[]), (0xEE, vec![0x17, 1]), "no session yet");
+        assert_eq!(command(&mut adapter, 0x10, b"NINTENDO"), (0x90, b"NINTENDO".to_vec()));
+
+        assert_eq!(command(&mut adapter, 0x1A, &[0x10, 1, 2, 3]), (0x9A, vec![0x10, 3]));
+        assert_eq!(command(&mut adapter, 0x19, &[0x0F, 3]), (0x99, vec![0x0F, 0, 1, 2]));
+        assert_eq!(command(&mut adapter, 0x19, &[0xBF, 2]).0, 0xEE, "past the end");
+        assert_eq!(adapter.config()[0x12], 3);
+
+        assert_eq!(command(&mut adapter, 0x12, b"\x000755551234"), (0x92, vec![]));
+        assert_eq!(adapter.backend().dialed(), ["0755551234"]);
+        assert_eq!(command(&mut adapter, 0x17, &[]).1[0], 0x04);
+        assert_eq!(command(&mut adapter, 0x15, b"\xFFhello"), (0x95, b"\xFFhello".to_vec()));
+        assert_eq!(command(&mut adapter, 0x13, &[]), (0x93, vec![]));
+        assert_eq!(command(&mut adapter, 0x15, b"\xFFhello"), (0xEE, vec![0x15, 1]));
+        assert_eq!(command(&mut adapter, 0x11, &[]), (0x91, vec![]));
+    }
+
+    #[test]
+    fn isp_connections_reach_the_mock_server() {
+        let mut backend = MockBackend::new();
+        backend.add_host("gameboy.datacenter.ne.jp", [10, 0, 0, 2]);
+        backend.serve([10, 0, 0, 2], 80, b"HTTP/1.0 200 OK\r\n\r\n");
+        let mut adapter = MobileAdapter::new(backend);
+        command(&mut adapter, 0x10, b"NINTENDO");
+        command(&mut adapter, 0x12, b"\x00#9677");
+        let login = command(&mut adapter, 0x21, &[2, b'i', b'd', 2, b'p', b'w', 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(login, (0xA1, vec![127, 0, 0, 1, 1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let (_, address) = command(&mut adapter, 0x28, b"gameboy.datacenter.ne.jp");
+        assert_eq!(address, [10, 0, 0, 2]);
+        assert_eq!(command(&mut adapter, 0x28, b"nowhere").0, 0xEE);
+        let mut open = address;
+        open.extend_from_slice(&80u16.to_be_bytes());
+        assert_eq!(command(&mut adapter, 0x23, &open), (0xA3, vec![0]));
+        let reply = command(&mut adapter, 0x15, b"\x00GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(reply, (0x95, b"\x00HTTP/1.0 200 OK\r\n\r\n".to_vec()));
+        assert_eq!(command(&mut adapter, 0x15, b"\x00"), (0x95, vec![0]));
+        assert_eq!(adapter.backend().sent(), [(0, b"GET / HTTP/1.0\r\n\r\n".to_vec())]);
+        assert_eq!(command(&mut adapter, 0x26, &[0]).0, 0xEE, "not a UDP connection");
+        assert_eq!(command(&mut adapter, 0x24, &[0]), (0xA4, vec![0]));
+        assert_eq!(command(&mut adapter, 0x15, b"\x00"), (0xEE, vec![0x15, 1]));
+    }
+
+    #[test]
+    fn rejects_bad_packets_in_the_acknowledgement() {
+        let mut adapter = MobileAdapter::new(MockBackend::new());
+        for byte in [0x99, 0x66, 0x10, 0x00, 0x00, 0x00, 0x00, 0x11] {
+            adapter.exchange(byte);
+        }
+        assert_eq!(adapter.exchange(0x80), 0x88);
+        assert_eq!(adapter.exchange(0x00), BAD_CHECKSUM);
+        assert_eq!(adapter.exchange(0x4B), IDLE);
+
+        for byte in [0x99, 0x66, 0x7F, 0x00, 0x00, 0x00, 0x00, 0x7F] {
+            adapter.exchange(byte);
+        }
+        assert_eq!(adapter.exchange(0x80), 0x88);
+        assert_eq!(adapter.exchange(0x00), UNKNOWN_COMMAND);
+        assert_eq!(adapter.exchange(0x4B), IDLE);
+    }
+}
@@ -0,0 +1,152 @@
+//! Network backend for the Mobile Adapter GB
+//!
+//! Connections a game opens through its ISP go out over the host's own
+//! network. Host names resolve through the system, except those the
+//! settings file maps to an address: the original servers are long gone,
+//! so games need pointing at a replacement. Dialing an ISP access number
+//! (they start with `#`) connects at once; calling another player would
+//! need a relay server, which isn't supported, so those calls fail.
+
+use gb3000::mobile::MobileBackend;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// How long opening a connection may hold up emulation
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most data handed back per transfer, leaving room for the connection
+/// ID in a packet
+const MAX_RECEIVE: usize = 253;
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// Mobile Adapter backend on real sockets
+pub struct NetBackend {
+    /// Host names resolved without asking the system
+    hosts: BTreeMap<String, [u8; 4]>,
+    connections: BTreeMap<u8, Connection>,
+}
+
+impl NetBackend {
+    pub fn new(hosts: BTreeMap<String, [u8; 4]>) -> Self {
+        Self { hosts, connections: BTreeMap::new() }
+    }
+}
+
+impl MobileBackend for NetBackend {
+    fn dial(&mut self, number: &str) -> bool {
+        number.starts_with('#')
+    }
+
+    fn resolve(&mut self, host: &str) -> Option<[u8; 4]> {
+        if let Some(&address) = self.hosts.get(host) {
+            return Some(address);
+        }
+        (host, 0).to_socket_addrs().ok()?.find_map(|address| match address {
+            SocketAddr::V4(v4) => Some(v4.ip().octets()),
+            SocketAddr::V6(_) => None,
+        })
+    }
+
+    fn connect(&mut self, id: u8, address: [u8; 4], port: u16, udp: bool) -> bool {
+        let target = SocketAddr::from((address, port));
+        let connection = if udp {
+            UdpSocket::bind(("0.0.0.0", 0))
+                .and_then(|socket| socket.connect(target).map(|()| socket))
+                .and_then(|socket| socket.set_nonblocking(true).map(|()| Connection::Udp(socket)))
+        } else {
+            TcpStream::connect_timeout(&target, CONNECT_TIMEOUT)
+                .and_then(|stream| stream.set_nonblocking(true).map(|()| Connection::Tcp(stream)))
+        };
+        match connection {
+            Ok(connection) => {
+                self.connections.insert(id, connection);
+                true
+            }
+            Err(e) => {
+                eprintln!("Mobile adapter: can't reach {}: {}", target, e);
+                false
+            }
+        }
+    }
+
+    fn disconnect(&mut self, id: u8) {
+        self.connections.remove(&id);
+    }
+
+    fn transfer(&mut self, id: u8, data: &[u8]) -> Option<Vec<u8>> {
+        let mut received = vec![0u8; MAX_RECEIVE];
+        let len = match self.connections.get_mut(&id)? {
+            Connection::Tcp(stream) => {
+                // Writes block briefly rather than splitting the game's data
+                stream.set_nonblocking(false).ok()?;
+                stream.write_all(data).ok()?;
+                stream.set_nonblocking(true).ok()?;
+                match stream.read(&mut received) {
+                    Ok(0) => return None,
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => 0,
+                    Err(_) => return None,
+                }
+            }
+            Connection::Udp(socket) => {
+                if !data.is_empty() {
+                    socket.send(data).ok()?;
+                }
+                match socket.recv(&mut received) {
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => 0,
+                    Err(_) => return None,
+                }
+            }
+        };
+        received.truncate(len);
+        Some(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    #[test]
+    fn talks_tcp_to_a_local_server() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"pong").unwrap();
+            request
+        });
+
+        let mut backend = NetBackend::new(BTreeMap::from([("server".to_string(), [127, 0, 0, 1])]));
+        assert!(backend.dial("#9677"));
+        assert!(!backend.dial("0755551234"));
+        let address = backend.resolve("server").unwrap();
+        assert!(backend.connect(0, address, port, false));
+
+        let mut reply = backend.transfer(0, b"ping").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reply.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+            reply = backend.transfer(0, &[]).unwrap();
+        }
+        assert_eq!(reply, b"pong");
+        assert_eq!(&server.join().unwrap(), b"ping");
+        // The server hung up
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backend.transfer(0, &[]).is_some() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(backend.transfer(0, &[]).is_none());
+    }
+}
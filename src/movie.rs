@@ -0,0 +1,220 @@
+//! Input movies for deterministic recording and playback
+//!
+//! A movie is a starting save state plus the buttons held on every frame
+//! after it. Emulation is deterministic, so replaying the inputs from the
+//! same state reproduces the run exactly (tool-assisted speedruns, bug
+//! reports, regression tests).
+//!
+//! File layout (little-endian):
+//! - 4-byte magic ("GB3M") and a u32 format version
+//! - u32 hash of the ROM the movie was recorded on
+//! - u32 length and the starting save state
+//! - u32 frame count and one [`InputFrame`] byte per frame
+
+use crate::{Emulator, InputFrame, StateError};
+
+/// Magic bytes at the start of every movie file
+const MAGIC: &[u8; 4] = b"GB3M";
+
+/// Current movie format version
+pub const MOVIE_VERSION: u32 = 1;
+
+/// Errors that can occur while loading or playing a movie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// Data does not start with the movie magic
+    BadMagic,
+    /// Movie was written by an incompatible format version
+    UnsupportedVersion(u32),
+    /// Movie was recorded with a different ROM
+    RomMismatch,
+    /// Data ended before all fields were read
+    Truncated,
+    /// The starting save state could not be loaded
+    State(StateError),
+}
+
+impl std::fmt::Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a GB3000 movie"),
+            MovieError::UnsupportedVersion(v) => write!(f, "unsupported movie version {}", v),
+            MovieError::RomMismatch => write!(f, "movie was recorded with a different ROM"),
+            MovieError::Truncated => write!(f, "movie data is truncated"),
+            MovieError::State(e) => write!(f, "movie start state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        MovieError::State(e)
+    }
+}
+
+/// A recorded sequence of per-frame inputs and the state it starts from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    rom_hash: u32,
+    start_state: Vec<u8>,
+    inputs: Vec<InputFrame>,
+}
+
+impl Movie {
+    /// Start recording from the emulator's current state
+    pub fn start(emulator: &Emulator) -> Self {
+        Self {
+            rom_hash: emulator.rom_hash(),
+            start_state: emulator.save_state(),
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Run one frame with `input` and append it to the movie
+    pub fn record_frame(&mut self, emulator: &mut Emulator, input: InputFrame) {
+        emulator.run_frame_with_input(input.0);
+        self.inputs.push(input);
+    }
+
+    /// Number of recorded frames
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Whether no frames have been recorded
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Hash of the ROM the movie was recorded on
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Recorded inputs, one per frame
+    pub fn inputs(&self) -> &[InputFrame] {
+        &self.inputs
+    }
+
+    /// Load the movie's starting state into the emulator
+    ///
+    /// Use this with [`Movie::inputs`] to play back frame by frame.
+    pub fn restart(&self, emulator: &mut Emulator) -> Result<(), MovieError> {
+        if emulator.rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        emulator.load_state(&self.start_state)?;
+        Ok(())
+    }
+
+    /// Restart from the starting state and replay every recorded frame
+    pub fn play(&self, emulator: &mut Emulator) -> Result<(), MovieError> {
+        self.restart(emulator)?;
+        emulator.run_frames_with_input(&self.inputs);
+        Ok(())
+    }
+
+    /// Serialize the movie
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(20 + self.start_state.len() + self.inputs.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.extend_from_slice(&(self.start_state.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.start_state);
+        out.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        out.extend(self.inputs.iter().map(|input| input.0));
+        out
+    }
+
+    /// Deserialize a movie written by [`Movie::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut pos = 0;
+        if take(data, &mut pos, 4).map_err(|_| MovieError::BadMagic)? != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = read_u32(data, &mut pos)?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = read_u32(data, &mut pos)?;
+        let state_len = read_u32(data, &mut pos)? as usize;
+        let start_state = take(data, &mut pos, state_len)?.to_vec();
+        let frames = read_u32(data, &mut pos)? as usize;
+        let inputs = take(data, &mut pos, frames)?
+            .iter()
+            .map(|&b| InputFrame(b))
+            .collect();
+        Ok(Self {
+            rom_hash,
+            start_state,
+            inputs,
+        })
+    }
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], MovieError> {
+    let end = pos.checked_add(len).ok_or(MovieError::Truncated)?;
+    let out = data.get(*pos..end).ok_or(MovieError::Truncated)?;
+    *pos = end;
+    Ok(out)
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> Result<u32, MovieError> {
+    let b = take(data, pos, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Button;
+
+    fn test_emulator(fill: u8) -> Emulator {
+        let mut emulator = Emulator::new();
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x8000 - 1] = fill;
+        emulator.load_rom(&rom);
+        emulator
+    }
+
+    #[test]
+    fn playback_reproduces_recording() {
+        let mut emulator = test_emulator(0);
+        emulator.run_frame();
+        let mut movie = Movie::start(&emulator);
+        for i in 0..30 {
+            let input = if i % 3 == 0 {
+                InputFrame::from_buttons(&[Button::A, Button::Right])
+            } else {
+                InputFrame::NONE
+            };
+            movie.record_frame(&mut emulator, input);
+        }
+        let recorded = emulator.save_state();
+        emulator.run_frames_with_input(&[InputFrame::NONE.with(Button::Start); 10]);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.len(), 30);
+        movie.play(&mut emulator).unwrap();
+        assert_eq!(emulator.save_state(), recorded);
+    }
+
+    #[test]
+    fn rejects_bad_data_and_other_roms() {
+        let mut emulator = test_emulator(0);
+        let movie = Movie::start(&emulator);
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(b"GB3S"), Err(MovieError::BadMagic));
+        assert_eq!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Truncated)
+        );
+
+        let mut other = test_emulator(1);
+        assert_eq!(movie.play(&mut other), Err(MovieError::RomMismatch));
+        assert!(movie.play(&mut emulator).is_ok());
+    }
+}
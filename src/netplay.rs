@@ -0,0 +1,499 @@
+//! Rollback netplay for two players
+//!
+//! Each peer runs the full simulation locally. Local input is scheduled
+//! `input_delay` frames ahead and sent to the other peer; the remote
+//! player's input is predicted (repeat the last known buttons) until it
+//! arrives. When a late input differs from its prediction, the session
+//! loads the snapshot taken before that frame and re-simulates up to the
+//! present, at most `max_rollback` frames.
+//!
+//! The session doesn't do any networking itself: the frontend sends the
+//! [`InputPacket`]s returned by [`NetplaySession::add_local_input`] over
+//! a reliable channel and passes received ones to
+//! [`NetplaySession::add_remote_input`].
+
+use crate::{Emulator, InputFrame, StateError};
+use std::collections::VecDeque;
+
+/// Number of players in a session
+pub const PLAYERS: usize = 2;
+
+/// Something that can be snapshotted and advanced with both players' input
+pub trait RollbackGame {
+    /// Serialize the current state into `out`, reusing its allocation
+    fn save_snapshot(&self, out: &mut Vec<u8>);
+    /// Restore a state written by [`RollbackGame::save_snapshot`]
+    fn load_snapshot(&mut self, data: &[u8]) -> Result<(), StateError>;
+    /// Run one frame; `resimulating` is set while replaying after a rollback
+    fn advance(&mut self, inputs: [InputFrame; PLAYERS], resimulating: bool);
+}
+
+/// A single Game Boy driven by player 1 (player 2's input is ignored)
+impl RollbackGame for Emulator {
+    fn save_snapshot(&self, out: &mut Vec<u8>) {
+        self.save_state_into(out);
+    }
+
+    fn load_snapshot(&mut self, data: &[u8]) -> Result<(), StateError> {
+        self.load_state(data)
+    }
+
+    fn advance(&mut self, inputs: [InputFrame; PLAYERS], resimulating: bool) {
+        if resimulating {
+            // The audio for these frames has already been played
+            let sink = self.audio_sink.take();
+            self.run_frame_with_input(inputs[0].0);
+            self.apu.clear_buffer();
+            self.audio_sink = sink;
+        } else {
+            self.run_frame_with_input(inputs[0].0);
+        }
+    }
+}
+
+/// One Game Boy per player, for link games
+impl RollbackGame for [Emulator; PLAYERS] {
+    fn save_snapshot(&self, out: &mut Vec<u8>) {
+        let mut second = Vec::new();
+        self[1].save_state_into(&mut second);
+        self[0].save_state_into(out);
+        let first_len = out.len() as u32;
+        out.extend_from_slice(&second);
+        out.extend_from_slice(&first_len.to_le_bytes());
+    }
+
+    fn load_snapshot(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (states, len) = data
+            .split_last_chunk::<4>()
+            .ok_or(StateError::Truncated)?;
+        let first_len = u32::from_le_bytes(*len) as usize;
+        if first_len > states.len() {
+            return Err(StateError::Truncated);
+        }
+        let (first, second) = states.split_at(first_len);
+        self[0].load_state(first)?;
+        self[1].load_state(second)
+    }
+
+    fn advance(&mut self, inputs: [InputFrame; PLAYERS], resimulating: bool) {
+        for (emulator, input) in self.iter_mut().zip(inputs) {
+            emulator.advance([input, InputFrame::NONE], resimulating);
+        }
+    }
+}
+
+/// Session tuning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetplayConfig {
+    /// Frames between pressing a button and it taking effect
+    ///
+    /// Higher values hide more latency without rolling back, at the cost
+    /// of input lag.
+    pub input_delay: u32,
+    /// Most frames the session may run ahead of the remote player's input
+    pub max_rollback: u32,
+}
+
+impl Default for NetplayConfig {
+    fn default() -> Self {
+        Self {
+            input_delay: 2,
+            max_rollback: 8,
+        }
+    }
+}
+
+/// Errors reported by a netplay session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetplayError {
+    /// The remote player is more than `max_rollback` frames behind;
+    /// wait for their input before advancing
+    WaitingForRemote,
+    /// Local input was added twice for the same frame
+    LocalInputAlreadyAdded,
+    /// A remote input is for a frame that was already confirmed or
+    /// dropped from the rollback window
+    StaleInput(u32),
+    /// A remote input is for a frame further ahead than the remote
+    /// player can get, from a broken or malicious peer
+    FutureInput(u32),
+    /// A snapshot could not be restored
+    State(StateError),
+}
+
+impl std::fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetplayError::WaitingForRemote => write!(f, "waiting for remote input"),
+            NetplayError::LocalInputAlreadyAdded => write!(f, "local input already added for this frame"),
+            NetplayError::StaleInput(frame) => write!(f, "input for frame {} arrived too late", frame),
+            NetplayError::FutureInput(frame) => write!(f, "input for frame {} is too far ahead", frame),
+            NetplayError::State(e) => write!(f, "rollback failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+impl From<StateError> for NetplayError {
+    fn from(e: StateError) -> Self {
+        NetplayError::State(e)
+    }
+}
+
+/// A player's input for one frame, as sent between peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputPacket {
+    /// Frame the input applies to
+    pub frame: u32,
+    /// Buttons held on that frame
+    pub input: InputFrame,
+}
+
+impl InputPacket {
+    /// Size of an encoded packet in bytes
+    pub const SIZE: usize = 5;
+
+    /// Encode as a little-endian frame number followed by the buttons
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let f = self.frame.to_le_bytes();
+        [f[0], f[1], f[2], f[3], self.input.0]
+    }
+
+    /// Decode a packet written by [`InputPacket::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data: &[u8; Self::SIZE] = data.try_into().ok()?;
+        Some(Self {
+            frame: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            input: InputFrame(data[4]),
+        })
+    }
+}
+
+/// Inputs for one frame inside the rollback window
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameInputs {
+    local: Option<InputFrame>,
+    remote: Option<InputFrame>,
+    /// Remote input the frame was last simulated with
+    predicted: InputFrame,
+}
+
+/// A two-player rollback session
+#[derive(Debug)]
+pub struct NetplaySession<G: RollbackGame> {
+    game: G,
+    config: NetplayConfig,
+    local_player: usize,
+    /// Next frame to simulate
+    frame: u32,
+    /// First frame whose remote input hasn't arrived
+    confirmed: u32,
+    /// Inputs from `base_frame` onward
+    inputs: VecDeque<FrameInputs>,
+    base_frame: u32,
+    /// Remote input used for prediction
+    last_remote: InputFrame,
+    /// Snapshot taken before each frame, indexed by frame modulo length
+    snapshots: Vec<Vec<u8>>,
+    /// Earliest frame simulated with a wrong prediction
+    rollback_from: Option<u32>,
+}
+
+impl<G: RollbackGame> NetplaySession<G> {
+    /// Start a session at frame 0
+    ///
+    /// Both peers must start from the same state, with `local_player`
+    /// set to 0 on one side and 1 on the other.
+    pub fn new(game: G, local_player: usize, config: NetplayConfig) -> Self {
+        assert!(local_player < PLAYERS, "local_player must be 0 or 1");
+        let mut session = Self {
+            game,
+            config,
+            local_player,
+            frame: 0,
+            confirmed: 0,
+            inputs: VecDeque::new(),
+            base_frame: 0,
+            last_remote: InputFrame::NONE,
+            snapshots: vec![Vec::new(); config.max_rollback as usize + 1],
+            rollback_from: None,
+        };
+        // Nobody can press anything during the delay at the start
+        for frame in 0..config.input_delay {
+            let slot = session.slot_mut(frame);
+            slot.local = Some(InputFrame::NONE);
+            slot.remote = Some(InputFrame::NONE);
+        }
+        session.advance_confirmed();
+        session
+    }
+
+    /// The simulated game
+    pub fn game(&self) -> &G {
+        &self.game
+    }
+
+    /// Mutable access to the game, e.g. to configure audio
+    ///
+    /// Changing emulation state here desyncs the peers.
+    pub fn game_mut(&mut self) -> &mut G {
+        &mut self.game
+    }
+
+    /// Session configuration
+    pub fn config(&self) -> NetplayConfig {
+        self.config
+    }
+
+    /// Next frame to be simulated
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Frames before this one have both players' input
+    pub fn confirmed_frame(&self) -> u32 {
+        self.confirmed
+    }
+
+    /// Add this frame's local input, returning the packet to send
+    ///
+    /// The input takes effect `input_delay` frames from now.
+    pub fn add_local_input(&mut self, input: InputFrame) -> Result<InputPacket, NetplayError> {
+        let frame = self.frame + self.config.input_delay;
+        let slot = self.slot_mut(frame);
+        if slot.local.is_some() {
+            return Err(NetplayError::LocalInputAlreadyAdded);
+        }
+        slot.local = Some(input);
+        Ok(InputPacket { frame, input })
+    }
+
+    /// Add an input received from the remote player
+    ///
+    /// Duplicates of already confirmed inputs are ignored, so packets can
+    /// be resent freely. The remote player can't run more than
+    /// `max_rollback` frames past the last input it has from us, which is
+    /// `input_delay` frames ahead of ours, and schedules its own input
+    /// `input_delay` further; packets beyond that are rejected, rather
+    /// than growing the input window to whatever frame they name.
+    pub fn add_remote_input(&mut self, packet: InputPacket) -> Result<(), NetplayError> {
+        if packet.frame < self.base_frame {
+            return Err(NetplayError::StaleInput(packet.frame));
+        }
+        if packet.frame < self.confirmed {
+            return Ok(());
+        }
+        let latest = self
+            .frame
+            .saturating_add(self.config.max_rollback)
+            .saturating_add(self.config.input_delay.saturating_mul(2))
+            .saturating_add(1);
+        if packet.frame > latest {
+            return Err(NetplayError::FutureInput(packet.frame));
+        }
+        let slot = self.slot_mut(packet.frame);
+        if slot.remote.is_some() {
+            return Ok(());
+        }
+        slot.remote = Some(packet.input);
+        let mispredicted = slot.predicted != packet.input;
+        if packet.frame < self.frame && mispredicted {
+            let from = self.rollback_from.map_or(packet.frame, |f| f.min(packet.frame));
+            self.rollback_from = Some(from);
+        }
+        self.advance_confirmed();
+        Ok(())
+    }
+
+    /// Roll back if needed and simulate the next frame
+    ///
+    /// Returns how many frames were re-simulated. Call
+    /// [`NetplaySession::add_local_input`] once before each call.
+    pub fn advance_frame(&mut self) -> Result<u32, NetplayError> {
+        if self.frame.saturating_sub(self.confirmed) >= self.config.max_rollback {
+            return Err(NetplayError::WaitingForRemote);
+        }
+        if self.slot(self.frame).and_then(|s| s.local).is_none() {
+            // Local input missing: treat as nothing held
+            self.slot_mut(self.frame).local = Some(InputFrame::NONE);
+        }
+
+        let mut resimulated = 0;
+        if let Some(from) = self.rollback_from.take() {
+            let snapshot = &self.snapshots[self.snapshot_index(from)];
+            self.game.load_snapshot(snapshot)?;
+            for frame in from..self.frame {
+                self.simulate(frame, true);
+            }
+            resimulated = self.frame - from;
+        }
+        self.simulate(self.frame, false);
+        self.frame += 1;
+        self.prune();
+        Ok(resimulated)
+    }
+
+    /// Save a snapshot and run `frame` with the best known inputs
+    fn simulate(&mut self, frame: u32, resimulating: bool) {
+        let index = self.snapshot_index(frame);
+        self.game.save_snapshot(&mut self.snapshots[index]);
+
+        let slot = self.slot(frame).copied().unwrap_or_default();
+        let remote = slot.remote.unwrap_or_else(|| self.predict(frame));
+        self.slot_mut(frame).predicted = remote;
+
+        let mut inputs = [InputFrame::NONE; PLAYERS];
+        inputs[self.local_player] = slot.local.unwrap_or(InputFrame::NONE);
+        inputs[1 - self.local_player] = remote;
+        self.game.advance(inputs, resimulating);
+    }
+
+    /// Remote input to assume for a frame that hasn't arrived yet
+    fn predict(&self, frame: u32) -> InputFrame {
+        (self.base_frame..frame)
+            .rev()
+            .find_map(|f| self.slot(f).and_then(|s| s.remote))
+            .unwrap_or(self.last_remote)
+    }
+
+    fn advance_confirmed(&mut self) {
+        while let Some(remote) = self.slot(self.confirmed).and_then(|s| s.remote) {
+            self.last_remote = remote;
+            self.confirmed += 1;
+        }
+    }
+
+    /// Drop inputs that can no longer be rolled back to
+    fn prune(&mut self) {
+        let keep_from = self.confirmed.min(self.frame);
+        while self.base_frame < keep_from && !self.inputs.is_empty() {
+            self.inputs.pop_front();
+            self.base_frame += 1;
+        }
+    }
+
+    fn snapshot_index(&self, frame: u32) -> usize {
+        frame as usize % self.snapshots.len()
+    }
+
+    fn slot(&self, frame: u32) -> Option<&FrameInputs> {
+        self.inputs.get(frame.checked_sub(self.base_frame)? as usize)
+    }
+
+    fn slot_mut(&mut self, frame: u32) -> &mut FrameInputs {
+        let index = (frame - self.base_frame) as usize;
+        if index >= self.inputs.len() {
+            self.inputs.resize(index + 1, FrameInputs::default());
+        }
+        &mut self.inputs[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Button;
+
+    fn test_emulator() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&vec![0u8; 0x8000]);
+        emulator.set_audio_enabled(false);
+        emulator
+    }
+
+    fn input_for(player: usize, frame: u32) -> InputFrame {
+        let button = if player == 0 { Button::A } else { Button::B };
+        if (frame / 3 + player as u32).is_multiple_of(2) {
+            InputFrame::NONE.with(button)
+        } else {
+            InputFrame::NONE
+        }
+    }
+
+    #[test]
+    fn peers_stay_in_sync_with_latency() {
+        let config = NetplayConfig {
+            input_delay: 1,
+            max_rollback: 8,
+        };
+        let mut peers = [
+            NetplaySession::new([test_emulator(), test_emulator()], 0, config),
+            NetplaySession::new([test_emulator(), test_emulator()], 1, config),
+        ];
+        // Packets in flight, delivered three frames after sending
+        let mut in_flight: [VecDeque<(u32, InputPacket)>; 2] = Default::default();
+
+        let mut rollbacks = 0;
+        for tick in 0..60 {
+            for (player, peer) in peers.iter_mut().enumerate() {
+                let packet = peer.add_local_input(input_for(player, peer.frame() + 1)).unwrap();
+                in_flight[1 - player].push_back((tick + 3, packet));
+                while in_flight[player].front().is_some_and(|&(at, _)| at <= tick) {
+                    let (_, packet) = in_flight[player].pop_front().unwrap();
+                    peer.add_remote_input(packet).unwrap();
+                }
+                rollbacks += peer.advance_frame().unwrap();
+            }
+        }
+        assert!(rollbacks > 0);
+
+        // Deliver everything, then one more frame resolves the last rollback
+        for (player, peer) in peers.iter_mut().enumerate() {
+            for (_, packet) in in_flight[player].drain(..) {
+                peer.add_remote_input(packet).unwrap();
+            }
+            peer.add_local_input(InputFrame::NONE).unwrap();
+            peer.advance_frame().unwrap();
+        }
+        let [a, b] = &peers;
+        assert_eq!(a.confirmed_frame(), b.confirmed_frame());
+        for i in 0..PLAYERS {
+            assert_eq!(a.game()[i].save_state(), b.game()[i].save_state());
+        }
+    }
+
+    #[test]
+    fn waits_when_remote_falls_behind() {
+        let config = NetplayConfig {
+            input_delay: 0,
+            max_rollback: 2,
+        };
+        let mut session = NetplaySession::new(test_emulator(), 0, config);
+        for _ in 0..2 {
+            session.add_local_input(InputFrame::NONE).unwrap();
+            session.advance_frame().unwrap();
+        }
+        assert_eq!(session.advance_frame(), Err(NetplayError::WaitingForRemote));
+
+        session
+            .add_remote_input(InputPacket { frame: 0, input: InputFrame::NONE })
+            .unwrap();
+        assert_eq!(session.advance_frame(), Ok(0));
+        assert_eq!(session.frame(), 3);
+
+        let packet = InputPacket { frame: 7, input: InputFrame::NONE.with(Button::Up) };
+        assert_eq!(InputPacket::from_bytes(&packet.to_bytes()), Some(packet));
+    }
+
+    #[test]
+    fn rejects_remote_input_too_far_ahead() {
+        let config = NetplayConfig {
+            input_delay: 2,
+            max_rollback: 8,
+        };
+        let mut session = NetplaySession::new(test_emulator(), 0, config);
+        let far = InputPacket { frame: u32::MAX, input: InputFrame::NONE };
+        assert_eq!(session.add_remote_input(far), Err(NetplayError::FutureInput(u32::MAX)));
+        let beyond = InputPacket { frame: 14, input: InputFrame::NONE };
+        assert_eq!(session.add_remote_input(beyond), Err(NetplayError::FutureInput(14)));
+        assert!(session.inputs.len() <= 2);
+
+        // The furthest a peer can get is still accepted, and a resent
+        // confirmed input is ignored
+        let furthest = InputPacket { frame: 13, input: InputFrame::NONE };
+        assert_eq!(session.add_remote_input(furthest), Ok(()));
+        let resent = InputPacket { frame: 0, input: InputFrame::NONE.with(Button::A) };
+        assert_eq!(session.add_remote_input(resent), Ok(()));
+    }
+}
@@ -0,0 +1,138 @@
+//! Emulator options that change how the hardware is modeled.
+//!
+//! Options are passed to [`Emulator::with_options`](crate::Emulator::with_options)
+//! or [`Emulator::set_options`](crate::Emulator::set_options) and take
+//! effect at the next reset, like powering the console off and on. The
+//! [`AudioOptions`] are the exception: they only shape the sound on its
+//! way to the host, so they apply straight away.
+//!
+//! The [`AccuracyOptions`] trade faithfulness for speed or for
+//! forgiveness of game bugs; the defaults model the hardware.
+
+use crate::cpu::GbModel;
+
+/// Options for an [`Emulator`](crate::Emulator)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EmulatorOptions {
+    /// What work RAM and high RAM hold at power-up
+    pub initial_ram: InitialRam,
+    /// How the sound channels are mixed for the host
+    pub audio: AudioOptions,
+    /// Which hardware quirks are modeled
+    pub accuracy: AccuracyOptions,
+}
+
+/// Hardware behaviors that can be turned off
+///
+/// Each one is modeled by default. Turning one off makes emulation a
+/// little cheaper or hides a quirk some game trips over by accident,
+/// at the cost of matching the hardware less closely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccuracyOptions {
+    /// Work out how long each line takes to draw from its sprites, fine
+    /// scroll and window, as the pixel FIFO does; off, every line draws
+    /// in the base 172 dots, like a plain scanline renderer
+    pub dot_timing: bool,
+    /// DMG OAM bug: during OAM scan, 16-bit increments and decrements of
+    /// a register pointing at 0xFE00-0xFEFF, and writes there, corrupt
+    /// the OAM row the PPU is reading. The CGB doesn't have it
+    pub oam_bug: bool,
+    /// While OAM DMA runs, the CPU reads 0xFF from OAM and the byte being
+    /// copied from anywhere on the same bus as the DMA source
+    pub dma_bus_conflicts: bool,
+    /// Draw at most 10 sprites per line; off, all of a line's sprites
+    /// are drawn, which removes the flicker of games that multiplex them
+    pub sprite_limit: bool,
+    /// Reads of 0xFEA0-0xFEFF return what that model's bus leaves there;
+    /// off, they read 0xFF
+    pub open_bus: bool,
+}
+
+impl AccuracyOptions {
+    /// Every quirk off
+    pub const FAST: Self = Self {
+        dot_timing: false,
+        oam_bug: false,
+        dma_bus_conflicts: false,
+        sprite_limit: false,
+        open_bus: false,
+    };
+}
+
+impl Default for AccuracyOptions {
+    fn default() -> Self {
+        Self { dot_timing: true, oam_bug: true, dma_bus_conflicts: true, sprite_limit: true, open_bus: true }
+    }
+}
+
+/// Mixing of the APU's output for the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioOptions {
+    /// How much of the left/right separation to keep, in percent: 100
+    /// plays NR51's hard panning as the hardware does, 0 folds both sides
+    /// into mono, and values in between narrow the stereo image, which is
+    /// easier on headphones
+    pub stereo_separation: u8,
+    /// Filter the output like the console's output capacitor, which
+    /// removes the DC offset of the channel DACs; the CGB's drains faster
+    /// than the DMG's, so the cutoff follows the model
+    pub high_pass: bool,
+}
+
+impl AudioOptions {
+    /// Both sides mixed into one
+    pub const MONO: Self = Self { stereo_separation: 0, high_pass: true };
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self { stereo_separation: 100, high_pass: true }
+    }
+}
+
+/// Power-up contents of work RAM and high RAM
+///
+/// Real consoles power up with whatever the RAM cells settle to. A few
+/// games read RAM before writing it, some of them to detect emulators,
+/// and behave differently depending on what they find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InitialRam {
+    /// All zeros
+    #[default]
+    Zeros,
+    /// The same pattern on every reset, resembling the model's power-up
+    /// RAM: noisy on the DMG-family chips, alternating runs of 0x00 and
+    /// 0xFF on the CGB
+    ModelPattern,
+    /// Pseudo-random bytes from a seed, reproducible across runs
+    Random(u64),
+}
+
+impl InitialRam {
+    /// Fill `ram` for a console of the given model
+    pub fn fill(self, model: GbModel, ram: &mut [u8]) {
+        match self {
+            InitialRam::Zeros => ram.fill(0),
+            InitialRam::ModelPattern if model.is_cgb() => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 0x08 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            InitialRam::ModelPattern => fill_random(ram, 0x4447_3330_0000 | model as u64),
+            InitialRam::Random(seed) => fill_random(ram, seed),
+        }
+    }
+}
+
+/// Fill `ram` from a SplitMix64 generator
+fn fill_random(ram: &mut [u8], seed: u64) {
+    let mut state = seed;
+    for chunk in ram.chunks_mut(8) {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+    }
+}
@@ -1,35 +1,62 @@
-/// Picture Processing Unit (PPU) for the Game Boy emulator.
-///
-/// The PPU handles all graphics rendering including:
-/// - Background layer
-/// - Window layer
-/// - Sprites (OBJ)
-///
-/// The Game Boy screen is 160x144 pixels with 4 shades of gray.
-/// The PPU operates in cycles matching the LCD refresh:
-/// - Mode 2 (OAM Scan): 80 dots
-/// - Mode 3 (Drawing): 172-289 dots (variable based on sprites/scroll/window)
-/// - Mode 0 (HBlank): remaining dots to complete 456 per line
-/// - Mode 1 (VBlank): 10 lines (4560 dots total)
-///
-/// Cycle-exact timing features:
-/// - Variable Mode 3 length based on sprite count and positions
-/// - SCX fine scroll penalty (SCX % 8 extra cycles)
-/// - Window trigger penalty
-/// - Proper STAT interrupt timing with blocking
-/// - OAM/VRAM access blocking during appropriate modes
-
+//! Picture Processing Unit (PPU) for the Game Boy emulator.
+//!
+//! The PPU handles all graphics rendering including:
+//! - Background layer
+//! - Window layer
+//! - Sprites (OBJ)
+//!
+//! The Game Boy screen is 160x144 pixels with 4 shades of gray.
+//! The PPU operates in cycles matching the LCD refresh:
+//! - Mode 2 (OAM Scan): 80 dots
+//! - Mode 3 (Drawing): 172-289 dots (variable based on sprites/scroll/window)
+//! - Mode 0 (HBlank): remaining dots to complete 456 per line
+//! - Mode 1 (VBlank): 10 lines (4560 dots total)
+//!
+//! Cycle-exact timing features:
+//! - Variable Mode 3 length based on sprite count and positions
+//! - SCX fine scroll penalty (SCX % 8 extra cycles)
+//! - Window trigger penalty
+//! - Proper STAT interrupt timing with blocking
+//! - OAM/VRAM access blocking during appropriate modes
+//!
+//! The variable Mode 3 length and the 10-sprite limit can be turned off
+//! through [`AccuracyOptions`].
+//!
+//! Ticking jumps straight from one mode change to the next rather than
+//! stepping each dot; the CPU's register writes land between ticks, so
+//! they still take effect on the exact M-cycle.
+//!
+//! A line is only drawn again when something it depends on changed since
+//! the last frame: VRAM, the LCDC/scroll/window/palette registers, the
+//! window line or the sprites found on it. With video turned off nothing
+//! is drawn at all, while timing and interrupts stay the same.
+//!
+//! For debuggers, the tile sheet, tile map and OAM views at the end read
+//! VRAM and OAM directly, apart from the scanline renderer.
+
+use crate::cpu::GbModel;
 use crate::memory::{io, interrupts, Memory};
+use crate::options::AccuracyOptions;
+use crate::state::{StateError, StateReader, StateWriter};
 
 /// Dots per scanline (constant)
 const DOTS_PER_LINE: u32 = 456;
 
+/// Dots per frame, 154 lines; also the cadence of frames while the LCD is off
+const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * 154;
+
 /// Mode 2 (OAM Scan) duration
 const MODE_2_DOTS: u32 = 80;
 
 /// Base Mode 3 duration (minimum, before penalties)
 const MODE_3_BASE_DOTS: u32 = 172;
 
+/// Sprites in OAM
+const OAM_SPRITES: usize = 40;
+
+/// Sprites the hardware draws per line
+const LINE_SPRITE_LIMIT: usize = 10;
+
 /// Screen dimensions
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
@@ -43,8 +70,29 @@ pub enum Mode {
     Drawing = 3, // Mode 3
 }
 
+/// A write to a PPU register, with when it landed in the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuWrite {
+    /// total_cycles at the start of the M-cycle doing the write
+    pub cycle: u64,
+    /// LY when the write landed
+    pub line: u8,
+    /// Dot within the line, 0-455
+    pub dot: u16,
+    /// 0xFF40-0xFF4B, LCDC to WX
+    pub register: u16,
+    pub value: u8,
+}
+
+/// Register writes of the frame being drawn and of the last complete one
+#[derive(Debug, Clone, Default)]
+struct WriteTimeline {
+    current: Vec<PpuWrite>,
+    last: Vec<PpuWrite>,
+}
+
 /// Sprite attributes
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 struct Sprite {
     y: u8,
     x: u8,
@@ -70,7 +118,18 @@ impl Sprite {
     }
 }
 
-#[derive(Debug)]
+/// Everything a drawn scanline depends on
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LineKey {
+    vram_version: u32,
+    /// LCDC, SCY, SCX, WY, WX, BGP, OBP0 and OBP1
+    registers: [u8; 8],
+    window_line: u8,
+    sprite_count: u8,
+    sprites: [Sprite; OAM_SPRITES],
+}
+
+#[derive(Debug, Clone)]
 pub struct Ppu {
     /// Current mode
     mode: Mode,
@@ -80,8 +139,10 @@ pub struct Ppu {
     pub framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
     /// Flag indicating a new frame is ready
     pub frame_ready: bool,
-    /// Sprites on current scanline (max 10)
-    scanline_sprites: Vec<Sprite>,
+    /// Sprites on current scanline, the first `scanline_sprite_count`
+    /// of them
+    scanline_sprites: [Sprite; OAM_SPRITES],
+    scanline_sprite_count: u8,
     /// Window line counter (internal)
     window_line: u8,
     /// Window was triggered this frame
@@ -92,6 +153,9 @@ pub struct Ppu {
     stat_interrupt_line: bool,
     /// Previous STAT interrupt conditions (for edge detection)
     prev_stat_conditions: bool,
+    /// What the conditions were last worked out from; while it stays the
+    /// same, checking again can't change anything
+    stat_inputs: Option<[u8; 4]>,
     /// Current pixel X position during Mode 3 rendering
     render_x: u8,
     /// Pixel FIFO for background
@@ -100,6 +164,21 @@ pub struct Ppu {
     sprite_fifo: u16,
     /// FIFO pixel count
     fifo_count: u8,
+    /// Hardware model, for revision-specific quirks
+    model: GbModel,
+    /// Which quirks are modeled
+    accuracy: AccuracyOptions,
+    /// Frames completed (VBlank entries) since power-on
+    frame_count: u64,
+    /// Whether scanlines are drawn into the framebuffer
+    video_enabled: bool,
+    /// What each framebuffer line was last drawn from, if still valid
+    line_keys: Vec<Option<LineKey>>,
+    /// Dots since the LCD was turned off or the last frame synthesized
+    /// for it; None while the LCD is on
+    lcd_off_dots: Option<u32>,
+    /// Register writes placed in the frame, while they're being recorded
+    write_timeline: Option<Box<WriteTimeline>>,
 }
 
 impl Ppu {
@@ -109,16 +188,123 @@ impl Ppu {
             dots: 0,
             framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
             frame_ready: false,
-            scanline_sprites: Vec::with_capacity(10),
+            scanline_sprites: [Sprite::default(); OAM_SPRITES],
+            scanline_sprite_count: 0,
             window_line: 0,
             window_triggered: false,
             mode_3_length: MODE_3_BASE_DOTS,
             stat_interrupt_line: false,
             prev_stat_conditions: false,
+            stat_inputs: None,
             render_x: 0,
             bg_fifo: 0,
             sprite_fifo: 0,
             fifo_count: 0,
+            model: GbModel::DmgABC,
+            accuracy: AccuracyOptions::default(),
+            frame_count: 0,
+            video_enabled: true,
+            line_keys: vec![None; SCREEN_HEIGHT],
+            lcd_off_dots: None,
+            write_timeline: None,
+        }
+    }
+
+    /// Frames completed (VBlank entries) since the last reset
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Whether the LCD is off, showing a blank screen
+    pub fn lcd_off(&self) -> bool {
+        self.lcd_off_dots.is_some()
+    }
+
+    /// Sprites found on the current scanline, in drawing priority order
+    fn sprites(&self) -> &[Sprite] {
+        &self.scanline_sprites[..self.scanline_sprite_count as usize]
+    }
+
+    /// Current PPU mode
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Turn drawing on or off; timing and interrupts are unaffected
+    ///
+    /// While off the framebuffer keeps whatever it last showed.
+    pub fn set_video_enabled(&mut self, enabled: bool) {
+        self.video_enabled = enabled;
+    }
+
+    /// Whether scanlines are drawn into the framebuffer
+    pub fn video_enabled(&self) -> bool {
+        self.video_enabled
+    }
+
+    /// Blank the screen, e.g. while the clock is stopped
+    pub fn clear_screen(&mut self) {
+        self.framebuffer.fill(0);
+        self.line_keys.fill(None);
+    }
+
+    /// Set the hardware model, for revision-specific quirks
+    pub fn set_model(&mut self, model: GbModel) {
+        self.model = model;
+    }
+
+    /// Choose which quirks are modeled
+    pub fn set_accuracy(&mut self, accuracy: AccuracyOptions) {
+        self.accuracy = accuracy;
+    }
+
+    /// Start or stop placing register writes in the frame; starting
+    /// begins an empty timeline
+    ///
+    /// The writes themselves are collected by [`Memory`] into
+    /// `ppu_write_log` and handed over with [`Ppu::record_writes`].
+    pub fn set_write_timeline(&mut self, enabled: bool) {
+        self.write_timeline = enabled.then(Box::default);
+    }
+
+    /// Register writes of the last complete frame, oldest first; empty
+    /// while not recording
+    pub fn write_timeline(&self) -> &[PpuWrite] {
+        self.write_timeline.as_ref().map_or(&[], |timeline| &timeline.last)
+    }
+
+    /// Move the writes logged in `memory` onto the timeline, as landing
+    /// now, at `cycle`; call before ticking for the M-cycle that did them
+    pub fn record_writes(&mut self, memory: &mut Memory, cycle: u64) {
+        let line = memory.io[io::LY as usize];
+        let dot = self.line_dot() as u16;
+        let Some(timeline) = self.write_timeline.as_mut() else {
+            memory.ppu_write_log.clear();
+            return;
+        };
+        timeline.current.extend(memory.ppu_write_log.drain(..).map(|(register, value)| PpuWrite {
+            cycle,
+            line,
+            dot,
+            register,
+            value,
+        }));
+    }
+
+    /// Dots since the current line began
+    fn line_dot(&self) -> u32 {
+        match self.mode {
+            Mode::OamScan | Mode::VBlank => self.dots,
+            Mode::Drawing => MODE_2_DOTS + self.dots,
+            Mode::HBlank => MODE_2_DOTS + self.mode_3_length + self.dots,
+        }
+    }
+
+    /// A frame finished: its writes become the last frame's
+    fn end_timeline_frame(&mut self) {
+        if let Some(timeline) = self.write_timeline.as_mut() {
+            std::mem::swap(&mut timeline.current, &mut timeline.last);
+            timeline.current.clear();
         }
     }
 
@@ -127,34 +313,162 @@ impl Ppu {
         self.dots = 0;
         self.framebuffer = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
         self.frame_ready = false;
-        self.scanline_sprites.clear();
+        self.scanline_sprite_count = 0;
         self.window_line = 0;
         self.window_triggered = false;
         self.mode_3_length = MODE_3_BASE_DOTS;
         self.stat_interrupt_line = false;
         self.prev_stat_conditions = false;
+        self.stat_inputs = None;
         self.render_x = 0;
         self.bg_fifo = 0;
         self.sprite_fifo = 0;
         self.fifo_count = 0;
+        self.frame_count = 0;
+        self.line_keys.fill(None);
+        self.lcd_off_dots = None;
+        if self.write_timeline.is_some() {
+            self.set_write_timeline(true);
+        }
+    }
+
+    /// Serialize PPU timing state, framebuffer, and sprite buffer
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.mode as u8);
+        w.u32(self.dots);
+        w.bytes(&self.framebuffer);
+        w.bool(self.frame_ready);
+        w.u8(self.scanline_sprite_count);
+        for sprite in self.sprites() {
+            w.bytes(&[sprite.y, sprite.x, sprite.tile, sprite.flags]);
+        }
+        w.u8(self.window_line);
+        w.bool(self.window_triggered);
+        w.u32(self.mode_3_length);
+        w.bool(self.stat_interrupt_line);
+        w.bool(self.prev_stat_conditions);
+        w.u8(self.render_x);
+        w.u16(self.bg_fifo);
+        w.u16(self.sprite_fifo);
+        w.u8(self.fifo_count);
+        w.u64(self.frame_count);
+        w.bool(self.lcd_off_dots.is_some());
+        w.u32(self.lcd_off_dots.unwrap_or(0));
+    }
+
+    /// Restore PPU timing state, framebuffer, and sprite buffer
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mode = match r.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(StateError::Invalid("PPU mode")),
+        };
+        self.dots = r.u32()?;
+        r.bytes_into(&mut self.framebuffer)?;
+        self.frame_ready = r.bool()?;
+        let sprite_count = r.u8()?;
+        if sprite_count as usize > OAM_SPRITES {
+            return Err(StateError::Invalid("scanline sprite count"));
+        }
+        for sprite in &mut self.scanline_sprites[..sprite_count as usize] {
+            let b = r.bytes(4)?;
+            *sprite = Sprite { y: b[0], x: b[1], tile: b[2], flags: b[3] };
+        }
+        self.scanline_sprite_count = sprite_count;
+        self.window_line = r.u8()?;
+        self.window_triggered = r.bool()?;
+        self.mode_3_length = r.u32()?;
+        if self.mode_3_length > DOTS_PER_LINE - MODE_2_DOTS {
+            return Err(StateError::Invalid("mode 3 length"));
+        }
+        self.stat_interrupt_line = r.bool()?;
+        self.prev_stat_conditions = r.bool()?;
+        self.stat_inputs = None;
+        self.render_x = r.u8()?;
+        self.bg_fifo = r.u16()?;
+        self.sprite_fifo = r.u16()?;
+        self.fifo_count = r.u8()?;
+        self.frame_count = r.u64()?;
+        self.line_keys.fill(None);
+        let lcd_off = r.bool()?;
+        let off_dots = r.u32()?;
+        if off_dots >= DOTS_PER_FRAME {
+            return Err(StateError::Invalid("LCD off dots"));
+        }
+        self.lcd_off_dots = lcd_off.then_some(off_dots);
+        Ok(())
     }
 
     /// Advance the PPU by the given number of T-cycles.
     pub fn tick(&mut self, memory: &mut Memory, cycles: u32) {
-        let lcdc = memory.data[io::LCDC as usize];
+        let lcdc = memory.io[io::LCDC as usize];
 
-        // If LCD is disabled, do nothing
+        // If LCD is disabled, only keep the frame cadence
         if lcdc & 0x80 == 0 {
             self.mode = Mode::HBlank;
             self.dots = 0;
-            memory.data[io::LY as usize] = 0;
+            self.stat_inputs = None;
+            memory.io[io::LY as usize] = 0;
             // Clear mode bits in STAT
-            memory.data[io::STAT as usize] &= 0xFC;
+            memory.io[io::STAT as usize] &= 0xFC;
+            memory.oam_scan_row = None;
+            self.tick_lcd_off(cycles);
             return;
         }
-
-        for _ in 0..cycles {
+        self.lcd_off_dots = None;
+
+        // Nothing observable happens between mode changes, so jump from
+        // one to the next instead of stepping every dot. The registers
+        // can't change under us: the CPU only writes between calls.
+        let mut cycles = cycles;
+        while cycles > 0 {
+            let until_event = self.mode_length().saturating_sub(self.dots).max(1);
+            if cycles < until_event {
+                self.dots += cycles;
+                self.refresh_stat_interrupt(memory);
+                break;
+            }
+            // Every dot before the event sees the same STAT conditions,
+            // so checking them once stands in for checking each
+            if until_event > 1 {
+                self.dots += until_event - 1;
+                self.refresh_stat_interrupt(memory);
+            }
             self.tick_single(memory);
+            cycles -= until_event;
+        }
+        // The OAM scan reads a row of two sprites every M-cycle
+        memory.oam_scan_row = (self.mode == Mode::OamScan).then_some((self.dots / 4) as u8);
+    }
+
+    /// Blank the screen as the LCD turns off, then complete a white frame
+    /// every [`DOTS_PER_FRAME`] so frontends keep presenting frames
+    fn tick_lcd_off(&mut self, cycles: u32) {
+        let mut off_dots = match self.lcd_off_dots {
+            Some(dots) => dots + cycles,
+            None => {
+                self.clear_screen();
+                cycles
+            }
+        };
+        if off_dots >= DOTS_PER_FRAME {
+            off_dots -= DOTS_PER_FRAME;
+            self.frame_count += 1;
+            self.end_timeline_frame();
+            self.frame_ready = true;
+        }
+        self.lcd_off_dots = Some(off_dots);
+    }
+
+    /// Dots the current mode lasts on this line
+    fn mode_length(&self) -> u32 {
+        match self.mode {
+            Mode::OamScan => MODE_2_DOTS,
+            Mode::Drawing => self.mode_3_length,
+            Mode::HBlank => DOTS_PER_LINE - MODE_2_DOTS - self.mode_3_length,
+            Mode::VBlank => DOTS_PER_LINE,
         }
     }
 
@@ -162,7 +476,7 @@ impl Ppu {
     fn tick_single(&mut self, memory: &mut Memory) {
         self.dots += 1;
 
-        let ly = memory.data[io::LY as usize];
+        let ly = memory.io[io::LY as usize];
 
         match self.mode {
             Mode::OamScan => {
@@ -195,20 +509,19 @@ impl Ppu {
 
             Mode::HBlank => {
                 // Mode 0: HBlank (remaining dots to complete 456 per line)
-                // HBlank length = 456 - 80 - mode_3_length
-                let hblank_length = DOTS_PER_LINE - MODE_2_DOTS - self.mode_3_length;
-                
-                if self.dots >= hblank_length {
+                if self.dots >= self.mode_length() {
                     self.dots = 0;
 
                     // Move to next line
                     let new_ly = ly.wrapping_add(1);
-                    memory.data[io::LY as usize] = new_ly;
+                    memory.io[io::LY as usize] = new_ly;
 
                     if new_ly >= 144 {
                         // Enter VBlank
                         self.mode = Mode::VBlank;
                         self.frame_ready = true;
+                        self.frame_count += 1;
+                        self.end_timeline_frame();
                         self.window_line = 0;
                         self.window_triggered = false;
 
@@ -235,12 +548,12 @@ impl Ppu {
 
                     if new_ly >= 154 {
                         // Start new frame
-                        memory.data[io::LY as usize] = 0;
+                        memory.io[io::LY as usize] = 0;
                         self.mode = Mode::OamScan;
                         self.update_stat(memory);
                         self.check_lyc(memory, 0);
                     } else {
-                        memory.data[io::LY as usize] = new_ly;
+                        memory.io[io::LY as usize] = new_ly;
                         self.check_lyc(memory, new_ly);
                     }
                 }
@@ -253,12 +566,15 @@ impl Ppu {
     
     /// Calculate Mode 3 length based on sprites, scroll, and window
     fn calculate_mode_3_length(&self, memory: &Memory, ly: u8) -> u32 {
-        let lcdc = memory.data[io::LCDC as usize];
-        let scx = memory.data[io::SCX as usize];
-        let wy = memory.data[io::WY as usize];
-        let wx = memory.data[io::WX as usize];
+        let lcdc = memory.io[io::LCDC as usize];
+        let scx = memory.io[io::SCX as usize];
+        let wy = memory.io[io::WY as usize];
+        let wx = memory.io[io::WX as usize];
         
         let mut length = MODE_3_BASE_DOTS;
+        if !self.accuracy.dot_timing {
+            return length;
+        }
         
         // SCX fine scroll penalty: (SCX % 8) extra dots at the start
         // Actually, this is handled by discarding pixels, adding ~0-7 cycles
@@ -266,7 +582,7 @@ impl Ppu {
         
         // Sprite penalty: each sprite adds 6-11 cycles depending on position
         // Simplified: each sprite adds ~6 cycles on average
-        let sprite_count = self.scanline_sprites.len() as u32;
+        let sprite_count = self.scanline_sprite_count as u32;
         length += sprite_count * 6;
         
         // Window penalty: if window is visible on this line, adds ~6 cycles
@@ -280,9 +596,10 @@ impl Ppu {
     
     /// Handle STAT interrupt with rising edge detection
     fn handle_stat_interrupt(&mut self, memory: &mut Memory) {
-        let stat = memory.data[io::STAT as usize];
-        let ly = memory.data[io::LY as usize];
-        let lyc = memory.data[io::LYC as usize];
+        let stat = memory.io[io::STAT as usize];
+        let ly = memory.io[io::LY as usize];
+        let lyc = memory.io[io::LYC as usize];
+        self.stat_inputs = Some(self.stat_inputs(memory));
         
         // Calculate if any STAT interrupt condition is true
         let mode_0_condition = (stat & 0x08 != 0) && self.mode == Mode::HBlank;
@@ -299,11 +616,9 @@ impl Ppu {
         let current_conditions = mode_0_condition || mode_1_condition || mode_2_condition || lyc_condition;
         
         // STAT interrupt on rising edge (low to high transition)
-        if current_conditions && !self.prev_stat_conditions {
-            if !self.stat_interrupt_line {
-                memory.request_interrupt(interrupts::LCD_STAT);
-                self.stat_interrupt_line = true;
-            }
+        if current_conditions && !self.prev_stat_conditions && !self.stat_interrupt_line {
+            memory.request_interrupt(interrupts::LCD_STAT);
+            self.stat_interrupt_line = true;
         }
         
         if !current_conditions {
@@ -313,12 +628,29 @@ impl Ppu {
         self.prev_stat_conditions = current_conditions;
     }
 
+    /// Everything [`Ppu::handle_stat_interrupt`] looks at
+    fn stat_inputs(&self, memory: &Memory) -> [u8; 4] {
+        [
+            memory.io[io::STAT as usize] & 0x78,
+            memory.io[io::LY as usize],
+            memory.io[io::LYC as usize],
+            self.mode as u8 | ((self.dots == 0) as u8) << 2,
+        ]
+    }
+
+    /// Handle STAT interrupts, skipping the work when nothing changed
+    fn refresh_stat_interrupt(&mut self, memory: &mut Memory) {
+        if self.stat_inputs != Some(self.stat_inputs(memory)) {
+            self.handle_stat_interrupt(memory);
+        }
+    }
+
     /// Update STAT register with current mode and LYC flag
     fn update_stat(&self, memory: &mut Memory) {
-        let ly = memory.data[io::LY as usize];
-        let lyc = memory.data[io::LYC as usize];
+        let ly = memory.io[io::LY as usize];
+        let lyc = memory.io[io::LYC as usize];
         
-        let mut stat = memory.data[io::STAT as usize] & 0xF8;
+        let mut stat = memory.io[io::STAT as usize] & 0xF8;
         stat |= self.mode as u8;
         
         // Update LY=LYC coincidence flag
@@ -326,19 +658,19 @@ impl Ppu {
             stat |= 0x04;
         }
         
-        memory.data[io::STAT as usize] = stat;
+        memory.io[io::STAT as usize] = stat;
     }
 
     /// Check LY == LYC coincidence and update flag
     fn check_lyc(&mut self, memory: &mut Memory, ly: u8) {
-        let lyc = memory.data[io::LYC as usize];
+        let lyc = memory.io[io::LYC as usize];
 
         if ly == lyc {
             // Set coincidence flag
-            memory.data[io::STAT as usize] |= 0x04;
+            memory.io[io::STAT as usize] |= 0x04;
         } else {
             // Clear coincidence flag
-            memory.data[io::STAT as usize] &= !0x04;
+            memory.io[io::STAT as usize] &= !0x04;
         }
         // Note: STAT interrupt is handled by handle_stat_interrupt()
     }
@@ -361,6 +693,18 @@ impl Ppu {
     /// Called when STAT register is written to
     /// This can trigger an immediate STAT interrupt if write enables a currently true condition
     pub fn on_stat_write(&mut self, memory: &mut Memory) {
+        // DMG STAT write bug: for one cycle the write enables every source,
+        // so writing during HBlank, VBlank or LY=LYC raises an interrupt
+        // (Road Rash and Zerd no Densetsu depend on it). Fixed on the CGB.
+        let lcd_on = memory.io[io::LCDC as usize] & 0x80 != 0;
+        if !self.model.is_cgb() && lcd_on && !self.stat_interrupt_line {
+            let ly = memory.io[io::LY as usize];
+            let lyc = memory.io[io::LYC as usize];
+            if matches!(self.mode, Mode::HBlank | Mode::VBlank) || ly == lyc {
+                memory.request_interrupt(interrupts::LCD_STAT);
+                self.stat_interrupt_line = true;
+            }
+        }
         // Re-evaluate STAT conditions after write
         self.handle_stat_interrupt(memory);
     }
@@ -369,13 +713,13 @@ impl Ppu {
     /// This can trigger an immediate STAT interrupt if LY == new LYC and LYC interrupt is enabled
     pub fn on_lyc_write(&mut self, memory: &mut Memory) {
         // Update the LY=LYC flag in STAT
-        let ly = memory.data[io::LY as usize];
-        let lyc = memory.data[io::LYC as usize];
+        let ly = memory.io[io::LY as usize];
+        let lyc = memory.io[io::LYC as usize];
         
         if ly == lyc {
-            memory.data[io::STAT as usize] |= 0x04;
+            memory.io[io::STAT as usize] |= 0x04;
         } else {
-            memory.data[io::STAT as usize] &= !0x04;
+            memory.io[io::STAT as usize] &= !0x04;
         }
         
         // Check for STAT interrupt
@@ -384,51 +728,82 @@ impl Ppu {
 
     /// Scan OAM for sprites on the given scanline
     fn scan_oam(&mut self, memory: &Memory, ly: u8) {
-        self.scanline_sprites.clear();
+        let mut count = 0;
 
-        let lcdc = memory.data[io::LCDC as usize];
+        let lcdc = memory.io[io::LCDC as usize];
         let sprite_height = if lcdc & 0x04 != 0 { 16 } else { 8 };
 
         // Scan all 40 sprites in OAM
         for i in 0..40 {
             let addr = 0xFE00 + (i * 4);
-            let y = memory.data[addr as usize];
-            let x = memory.data[(addr + 1) as usize];
-            let tile = memory.data[(addr + 2) as usize];
-            let flags = memory.data[(addr + 3) as usize];
+            let y = memory.oam[addr as usize];
+            let x = memory.oam[(addr + 1) as usize];
+            let tile = memory.oam[(addr + 2) as usize];
+            let flags = memory.oam[(addr + 3) as usize];
 
             // Check if sprite is on this scanline
             let sprite_y = y.wrapping_sub(16);
             let line = ly;
 
             if line >= sprite_y && line < sprite_y.wrapping_add(sprite_height) {
-                self.scanline_sprites.push(Sprite { y, x, tile, flags });
+                self.scanline_sprites[count] = Sprite { y, x, tile, flags };
+                count += 1;
 
                 // Max 10 sprites per scanline
-                if self.scanline_sprites.len() >= 10 {
+                if self.accuracy.sprite_limit && count >= LINE_SPRITE_LIMIT {
                     break;
                 }
             }
         }
+        self.scanline_sprite_count = count as u8;
 
-        // Sort by X coordinate (lower X = higher priority)
-        self.scanline_sprites.sort_by(|a, b| a.x.cmp(&b.x));
+        // Sort by X coordinate (lower X = higher priority); a stable sort
+        // this short doesn't allocate
+        self.scanline_sprites[..count].sort_by_key(|a| a.x);
     }
 
-    /// Render a single scanline
+    /// Render a single scanline, unless it would come out the same as
+    /// last frame
     fn render_scanline(&mut self, memory: &Memory, ly: u8) {
-        let lcdc = memory.data[io::LCDC as usize];
+        let lcdc = memory.io[io::LCDC as usize];
 
         // Get palettes
-        let bgp = memory.data[io::BGP as usize];
-        let obp0 = memory.data[io::OBP0 as usize];
-        let obp1 = memory.data[io::OBP1 as usize];
+        let bgp = memory.io[io::BGP as usize];
+        let obp0 = memory.io[io::OBP0 as usize];
+        let obp1 = memory.io[io::OBP1 as usize];
 
         let line_offset = (ly as usize) * SCREEN_WIDTH;
 
         // Background enable (on DMG, this also affects window)
         let bg_enable = lcdc & 0x01 != 0;
 
+        let wy = memory.io[io::WY as usize];
+        let wx = memory.io[io::WX as usize];
+        let window_visible = bg_enable && lcdc & 0x20 != 0 && ly >= wy && wx <= 166;
+
+        let mut key = LineKey {
+            vram_version: memory.vram_version(),
+            registers: [lcdc, memory.io[io::SCY as usize], memory.io[io::SCX as usize], wy, wx, bgp, obp0, obp1],
+            window_line: self.window_line,
+            sprite_count: self.scanline_sprite_count,
+            sprites: [Sprite::default(); OAM_SPRITES],
+        };
+        key.sprites[..self.sprites().len()].copy_from_slice(self.sprites());
+
+        let cached = &mut self.line_keys[ly as usize];
+        let skip = if self.video_enabled {
+            let unchanged = *cached == Some(key);
+            *cached = Some(key);
+            unchanged
+        } else {
+            *cached = None;
+            true
+        };
+        if skip {
+            self.advance_window_line(window_visible);
+            return;
+        }
+
         // Render background
         if bg_enable {
             self.render_background(memory, ly, lcdc, bgp, line_offset);
@@ -440,9 +815,10 @@ impl Ppu {
         }
 
         // Render window
-        if bg_enable && (lcdc & 0x20 != 0) {
-            self.render_window(memory, ly, lcdc, bgp, line_offset);
+        if window_visible {
+            self.render_window(memory, lcdc, bgp, wx, line_offset);
         }
+        self.advance_window_line(window_visible);
 
         // Render sprites
         if lcdc & 0x02 != 0 {
@@ -450,6 +826,14 @@ impl Ppu {
         }
     }
 
+    /// The window line counter only moves on lines that show the window
+    fn advance_window_line(&mut self, window_visible: bool) {
+        if window_visible {
+            self.window_line += 1;
+            self.window_triggered = true;
+        }
+    }
+
     /// Render background for a scanline
     fn render_background(
         &mut self,
@@ -459,8 +843,8 @@ impl Ppu {
         bgp: u8,
         line_offset: usize,
     ) {
-        let scy = memory.data[io::SCY as usize];
-        let scx = memory.data[io::SCX as usize];
+        let scy = memory.io[io::SCY as usize];
+        let scx = memory.io[io::SCX as usize];
 
         // Background tile map address
         let tile_map = if lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
@@ -478,7 +862,7 @@ impl Ppu {
 
             // Get tile index from tile map
             let map_addr = tile_map + (tile_row * 32) + tile_col;
-            let tile_idx = memory.data[map_addr as usize];
+            let tile_idx = memory.vram[map_addr as usize];
 
             // Calculate tile data address
             let tile_addr = if signed_addressing {
@@ -494,8 +878,8 @@ impl Ppu {
 
             // Read tile data (2 bytes per row)
             let addr = tile_addr + (tile_y * 2);
-            let low = memory.data[addr as usize];
-            let high = memory.data[(addr + 1) as usize];
+            let low = memory.vram[addr as usize];
+            let high = memory.vram[(addr + 1) as usize];
 
             // Get color index
             let color_bit = 1 << tile_x;
@@ -511,19 +895,11 @@ impl Ppu {
     fn render_window(
         &mut self,
         memory: &Memory,
-        ly: u8,
         lcdc: u8,
         bgp: u8,
+        wx: u8,
         line_offset: usize,
     ) {
-        let wy = memory.data[io::WY as usize];
-        let wx = memory.data[io::WX as usize];
-
-        // Window not visible yet
-        if ly < wy || wx > 166 {
-            return;
-        }
-
         // Window tile map address
         let tile_map = if lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
 
@@ -540,7 +916,7 @@ impl Ppu {
 
             // Get tile index from tile map
             let map_addr = tile_map + (tile_row * 32) + tile_col;
-            let tile_idx = memory.data[map_addr as usize];
+            let tile_idx = memory.vram[map_addr as usize];
 
             // Calculate tile data address
             let tile_addr = if signed_addressing {
@@ -556,8 +932,8 @@ impl Ppu {
 
             // Read tile data
             let addr = tile_addr + (tile_y * 2);
-            let low = memory.data[addr as usize];
-            let high = memory.data[(addr + 1) as usize];
+            let low = memory.vram[addr as usize];
+            let high = memory.vram[(addr + 1) as usize];
 
             // Get color index
             let color_bit = 1 << tile_x;
@@ -567,9 +943,6 @@ impl Ppu {
             let color = (bgp >> (color_idx * 2)) & 0x03;
             self.framebuffer[line_offset + screen_x] = color;
         }
-
-        self.window_line += 1;
-        self.window_triggered = true;
     }
 
     /// Render sprites for a scanline
@@ -585,7 +958,8 @@ impl Ppu {
         let sprite_height = if lcdc & 0x04 != 0 { 16 } else { 8 };
 
         // Render sprites in reverse order (lower index = higher priority when same X)
-        for sprite in self.scanline_sprites.iter().rev() {
+        let count = self.scanline_sprite_count as usize;
+        for sprite in self.scanline_sprites[..count].iter().rev() {
             let palette = if sprite.palette() { obp1 } else { obp0 };
 
             // Calculate sprite position
@@ -607,8 +981,8 @@ impl Ppu {
 
             // Calculate tile address
             let tile_addr = 0x8000 + (tile as u16 * 16) + ((tile_y as u16) * 2);
-            let low = memory.data[tile_addr as usize];
-            let high = memory.data[(tile_addr + 1) as usize];
+            let low = memory.vram[tile_addr as usize];
+            let high = memory.vram[(tile_addr + 1) as usize];
 
             // Render each pixel of the sprite
             for tile_x in 0..8 {
@@ -648,17 +1022,151 @@ impl Default for Ppu {
     }
 }
 
+/// Tile sheet size: the 384 tiles at 0x8000-0x97FF, 16 to a row
+pub const TILE_SHEET_WIDTH: usize = 128;
+pub const TILE_SHEET_HEIGHT: usize = 192;
+
+/// Width and height of a whole tile map (32x32 tiles)
+pub const TILE_MAP_SIZE: usize = 256;
+
+/// One of the two background/window tile maps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileMap {
+    /// The map at 0x9800
+    Low,
+    /// The map at 0x9C00
+    High,
+}
+
+impl TileMap {
+    /// Address of the map's first entry
+    pub fn base(self) -> u16 {
+        match self {
+            TileMap::Low => 0x9800,
+            TileMap::High => 0x9C00,
+        }
+    }
+
+    /// The map LCDC selects for the background
+    pub fn background(lcdc: u8) -> Self {
+        if lcdc & 0x08 != 0 { TileMap::High } else { TileMap::Low }
+    }
+
+    /// The map LCDC selects for the window
+    pub fn window(lcdc: u8) -> Self {
+        if lcdc & 0x40 != 0 { TileMap::High } else { TileMap::Low }
+    }
+}
+
+/// A sprite's four bytes of OAM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OamEntry {
+    /// Y position plus 16
+    pub y: u8,
+    /// X position plus 8
+    pub x: u8,
+    pub tile: u8,
+    /// Bit 7 behind the background, 6 Y flip, 5 X flip, 4 OBP1
+    pub flags: u8,
+}
+
+/// Color index (0-3) of pixel (`x`, `y`) in the tile at `addr`
+fn tile_pixel(memory: &Memory, addr: u16, x: usize, y: usize) -> u8 {
+    let row = addr as usize + y * 2;
+    let bit = 7 - x;
+    let low = (memory.vram[row] >> bit) & 1;
+    let high = (memory.vram[row + 1] >> bit) & 1;
+    high << 1 | low
+}
+
+/// Draw every tile in VRAM as raw color indices, without a palette
+///
+/// `out` holds `TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT` pixels; tile `n`
+/// (at 0x8000 + 16n) is at column `n % 16`, row `n / 16`.
+pub fn draw_tile_sheet(memory: &Memory, out: &mut [u8]) {
+    for (i, pixel) in out.iter_mut().enumerate().take(TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT) {
+        let (x, y) = (i % TILE_SHEET_WIDTH, i / TILE_SHEET_WIDTH);
+        let tile = (y / 8) * 16 + x / 8;
+        *pixel = tile_pixel(memory, 0x8000 + tile as u16 * 16, x % 8, y % 8);
+    }
+}
+
+/// Draw a whole tile map as the background would show it: through BGP,
+/// with the tile data LCDC selects
+///
+/// `out` holds `TILE_MAP_SIZE * TILE_MAP_SIZE` shades.
+pub fn draw_tile_map(memory: &Memory, map: TileMap, out: &mut [u8]) {
+    let lcdc = memory.io[io::LCDC as usize];
+    let bgp = memory.io[io::BGP as usize];
+    for (i, pixel) in out.iter_mut().enumerate().take(TILE_MAP_SIZE * TILE_MAP_SIZE) {
+        let (x, y) = (i % TILE_MAP_SIZE, i / TILE_MAP_SIZE);
+        let index = memory.vram[(map.base() as usize) + (y / 8) * 32 + x / 8];
+        let tile_addr = if lcdc & 0x10 != 0 {
+            0x8000 + index as u16 * 16
+        } else {
+            (0x9000 + (index as i8 as i32) * 16) as u16
+        };
+        let color = tile_pixel(memory, tile_addr, x % 8, y % 8);
+        *pixel = (bgp >> (color * 2)) & 0x03;
+    }
+}
+
+/// All 40 sprites in OAM order
+pub fn oam_entries(memory: &Memory) -> [OamEntry; 40] {
+    std::array::from_fn(|i| {
+        let addr = 0xFE00 + i * 4;
+        OamEntry {
+            y: memory.oam[addr],
+            x: memory.oam[addr + 1],
+            tile: memory.oam[addr + 2],
+            flags: memory.oam[addr + 3],
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn debug_views_decode_vram() {
+        let mut memory = Memory::new();
+        // Tile 1: top row colors 3,2,1,0,0,0,0,0
+        memory.vram[0x8010] = 0b1010_0000;
+        memory.vram[0x8011] = 0b1100_0000;
+        // Tile 2 in signed addressing (0x9020, not 0x8020) is the same shape
+        memory.vram[0x9020] = 0b1010_0000;
+        memory.vram[0x9021] = 0b1100_0000;
+        memory.vram[0x9C21] = 0x02; // High map, row 1, column 1
+        memory.oam[0xFE04..0xFE08].copy_from_slice(&[0x20, 0x18, 0x01, 0x30]);
+
+        let mut sheet = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+        draw_tile_sheet(&memory, &mut sheet);
+        assert_eq!(&sheet[8..12], [3, 2, 1, 0]);
+        assert_eq!(sheet[TILE_SHEET_WIDTH + 8], 0);
+
+        memory.io[io::LCDC as usize] = 0x81; // signed tile data
+        memory.io[io::BGP as usize] = 0x1B; // reversed shades
+        let mut map = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+        draw_tile_map(&memory, TileMap::High, &mut map);
+        let row = 8 * TILE_MAP_SIZE;
+        assert_eq!(&map[row + 8..row + 12], [0, 1, 2, 3]);
+        assert_eq!(map[0], 3);
+        assert_eq!(TileMap::background(0x81), TileMap::Low);
+        assert_eq!(TileMap::window(0xC1), TileMap::High);
+
+        let oam = oam_entries(&memory);
+        assert_eq!(oam[1], OamEntry { y: 0x20, x: 0x18, tile: 0x01, flags: 0x30 });
+        assert_eq!(oam[0], OamEntry::default());
+    }
+
     #[test]
     fn ppu_modes_cycle() {
         let mut ppu = Ppu::new();
         let mut memory = Memory::new();
 
         // Enable LCD
-        memory.data[io::LCDC as usize] = 0x91;
+        memory.io[io::LCDC as usize] = 0x91;
 
         // Start in OAM scan
         assert_eq!(ppu.mode, Mode::OamScan);
@@ -676,7 +1184,7 @@ mod tests {
         let hblank_len = DOTS_PER_LINE - MODE_2_DOTS - mode_3_len;
         ppu.tick(&mut memory, hblank_len);
         assert_eq!(ppu.mode, Mode::OamScan);
-        assert_eq!(memory.data[io::LY as usize], 1);
+        assert_eq!(memory.io[io::LY as usize], 1);
     }
 
     #[test]
@@ -685,7 +1193,7 @@ mod tests {
         let mut memory = Memory::new();
 
         // Enable LCD
-        memory.data[io::LCDC as usize] = 0x91;
+        memory.io[io::LCDC as usize] = 0x91;
 
         // Run through 144 lines (456 dots each)
         for _ in 0..144 {
@@ -697,7 +1205,7 @@ mod tests {
         assert!(ppu.frame_ready);
 
         // VBlank interrupt should be requested
-        assert!(memory.data[io::IF as usize] & interrupts::VBLANK != 0);
+        assert!(memory.io[io::IF as usize] & interrupts::VBLANK != 0);
     }
     
     #[test]
@@ -706,21 +1214,40 @@ mod tests {
         let mut memory = Memory::new();
         
         // Enable LCD and sprites
-        memory.data[io::LCDC as usize] = 0x93;
+        memory.io[io::LCDC as usize] = 0x93;
         
         // Base mode 3 length (no sprites)
         let base_length = ppu.calculate_mode_3_length(&memory, 0);
         assert_eq!(base_length, MODE_3_BASE_DOTS);
         
         // Add a sprite on line 0
-        memory.data[0xFE00] = 16; // Y = 16 means visible on line 0
-        memory.data[0xFE01] = 8;  // X = 8
+        memory.oam[0xFE00] = 16; // Y = 16 means visible on line 0
+        memory.oam[0xFE01] = 8;  // X = 8
         ppu.scan_oam(&memory, 0);
         
         let with_sprite = ppu.calculate_mode_3_length(&memory, 0);
         assert!(with_sprite > base_length);
     }
-    
+
+    #[test]
+    fn accuracy_options_lift_sprite_limit_and_fix_mode_3() {
+        let mut memory = Memory::new();
+        memory.io[io::LCDC as usize] = 0x93;
+        for i in 0..12 {
+            memory.oam[0xFE00 + i * 4] = 16;
+            memory.oam[0xFE01 + i * 4] = 8 + i as u8 * 8;
+        }
+
+        let mut ppu = Ppu::new();
+        ppu.scan_oam(&memory, 0);
+        assert_eq!(ppu.sprites().len(), 10);
+
+        ppu.set_accuracy(AccuracyOptions::FAST);
+        ppu.scan_oam(&memory, 0);
+        assert_eq!(ppu.sprites().len(), 12);
+        assert_eq!(ppu.calculate_mode_3_length(&memory, 0), MODE_3_BASE_DOTS);
+    }
+
     #[test]
     fn oam_vram_access_timing() {
         let ppu_oam = Ppu { mode: Mode::OamScan, ..Ppu::new() };
@@ -740,5 +1267,91 @@ mod tests {
         assert!(ppu_hblank.vram_accessible());
         assert!(ppu_vblank.vram_accessible());
     }
+
+    #[test]
+    fn batched_ticks_match_single_dots() {
+        let setup = || {
+            let mut memory = Memory::new();
+            memory.io[io::LCDC as usize] = 0x93;
+            // Every STAT source, with LYC on a visible line
+            memory.io[io::STAT as usize] = 0x78;
+            memory.io[io::LYC as usize] = 20;
+            for (i, sprite) in memory.oam.as_mut_slice().chunks_mut(4).take(12).enumerate() {
+                sprite.copy_from_slice(&[16 + i as u8 * 3, 8 + i as u8 * 13, 0, 0]);
+            }
+            memory
+        };
+        let (mut dots_memory, mut batch_memory) = (setup(), setup());
+        let (mut dots, mut batch) = (Ppu::new(), Ppu::new());
+
+        let mut seed = 1u32;
+        let mut stat_irqs = (0, 0);
+        for _ in 0..1500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let cycles = 1 + (seed >> 16) % 100;
+            for _ in 0..cycles {
+                dots.tick(&mut dots_memory, 1);
+            }
+            batch.tick(&mut batch_memory, cycles);
+            for (memory, count) in [(&mut dots_memory, &mut stat_irqs.0), (&mut batch_memory, &mut stat_irqs.1)] {
+                *count += (memory.io[io::IF as usize] & interrupts::LCD_STAT != 0) as u32;
+                memory.io[io::IF as usize] = 0;
+            }
+            assert_eq!(stat_irqs.0, stat_irqs.1);
+            assert_eq!(dots_memory.io.as_slice(), batch_memory.io.as_slice());
+        }
+        assert!(stat_irqs.0 > 0);
+        let (mut a, mut b) = (StateWriter::new(), StateWriter::new());
+        dots.save_state(&mut a);
+        batch.save_state(&mut b);
+        assert_eq!(a.finish(), b.finish());
+    }
+
+    #[test]
+    fn unchanged_lines_are_not_redrawn() {
+        let mut ppu = Ppu::new();
+        let mut memory = Memory::new();
+        memory.io[io::LCDC as usize] = 0x91;
+        memory.io[io::BGP as usize] = 0xE4;
+        memory.poke(0x8000, 0xFF);
+        ppu.render_scanline(&memory, 0);
+        assert_eq!(ppu.framebuffer[0], 1);
+
+        // Same inputs: the line is kept as it is
+        ppu.framebuffer[0] = 3;
+        ppu.render_scanline(&memory, 0);
+        assert_eq!(ppu.framebuffer[0], 3);
+
+        // A palette or VRAM change redraws it
+        memory.io[io::BGP as usize] = 0xE0;
+        ppu.render_scanline(&memory, 0);
+        assert_eq!(ppu.framebuffer[0], 0);
+        memory.poke(0x8001, 0xFF);
+        ppu.render_scanline(&memory, 0);
+        assert_eq!(ppu.framebuffer[0], 3);
+
+        // With video off the window line still counts
+        memory.io[io::LCDC as usize] = 0xB1;
+        ppu.set_video_enabled(false);
+        ppu.framebuffer[0] = 2;
+        ppu.render_scanline(&memory, 0);
+        assert_eq!(ppu.framebuffer[0], 2);
+        assert_eq!(ppu.window_line, 1);
+    }
+
+    #[test]
+    fn stat_write_bug_is_dmg_only() {
+        for (model, fires) in [(GbModel::DmgABC, true), (GbModel::Cgb, false)] {
+            let mut ppu = Ppu { mode: Mode::HBlank, ..Ppu::new() };
+            ppu.set_model(model);
+            let mut memory = Memory::new();
+            memory.io[io::LY as usize] = 10;
+            memory.io[io::IF as usize] = 0;
+            // No sources enabled, yet the DMG sees them all for a cycle
+            memory.io[io::STAT as usize] = 0x00;
+            ppu.on_stat_write(&mut memory);
+            assert_eq!(memory.io[io::IF as usize] & interrupts::LCD_STAT != 0, fires);
+        }
+    }
 }
 
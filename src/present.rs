@@ -0,0 +1,94 @@
+//! Fitting the UI into a resizable window
+//!
+//! The UI draws into a fixed 640x576 buffer. Presenting scales that to
+//! the largest whole multiple of the Game Boy's 160x144 screen that fits
+//! the window, so every Game Boy pixel stays the same size, and centers it
+//! with black bars around it.
+
+use gb3000::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Where the scaled UI lands in the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Viewport {
+    /// Largest integer-scaled, centered area of a `width` x `height`
+    /// window, never below 1x
+    pub fn fit(width: usize, height: usize) -> Self {
+        let scale = (width / SCREEN_WIDTH).min(height / SCREEN_HEIGHT).max(1);
+        let (w, h) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+        Self {
+            x: width.saturating_sub(w) / 2,
+            y: height.saturating_sub(h) / 2,
+            width: w,
+            height: h,
+        }
+    }
+
+    /// Map a window position to the UI buffer's coordinates
+    pub fn to_ui(self, x: f32, y: f32, ui_width: usize, ui_height: usize) -> (f32, f32) {
+        let ux = (x - self.x as f32) * ui_width as f32 / self.width as f32;
+        let uy = (y - self.y as f32) * ui_height as f32 / self.height as f32;
+        (ux.clamp(0.0, ui_width as f32 - 1.0), uy.clamp(0.0, ui_height as f32 - 1.0))
+    }
+}
+
+/// Scale the `ui_width` x `ui_height` buffer `src` into `dst`, sized to a
+/// `width` x `height` window, with nearest-neighbor sampling
+///
+/// Returns the viewport used. Parts of the viewport outside a window
+/// smaller than 1x are cut off.
+pub fn present(src: &[u32], ui_width: usize, ui_height: usize, dst: &mut Vec<u32>, width: usize, height: usize) -> Viewport {
+    let viewport = Viewport::fit(width, height);
+    dst.clear();
+    dst.resize(width * height, 0);
+    for y in 0..viewport.height.min(height - viewport.y.min(height)) {
+        let src_row = &src[y * ui_height / viewport.height * ui_width..][..ui_width];
+        let dst_row = &mut dst[(viewport.y + y) * width + viewport.x..][..viewport.width.min(width - viewport.x)];
+        for (x, pixel) in dst_row.iter_mut().enumerate() {
+            *pixel = src_row[x * ui_width / viewport.width];
+        }
+    }
+    viewport
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_to_whole_game_boy_pixels() {
+        assert_eq!(Viewport::fit(640, 576), Viewport { x: 0, y: 0, width: 640, height: 576 });
+        // 5x fits across but only 4x down: letterboxed at the sides
+        assert_eq!(Viewport::fit(800, 600), Viewport { x: 80, y: 12, width: 640, height: 576 });
+        assert_eq!(Viewport::fit(1920, 1080), Viewport { x: 400, y: 36, width: 1120, height: 1008 });
+        assert_eq!(Viewport::fit(100, 100).width, SCREEN_WIDTH);
+    }
+
+    #[test]
+    fn presents_letterboxed_and_maps_the_mouse() {
+        let (ui_w, ui_h) = (640, 576);
+        let mut src = vec![0xFF00_0000; ui_w * ui_h];
+        src[ui_w * ui_h - 1] = 0xFFFF_FFFF;
+        let mut dst = Vec::new();
+        let viewport = present(&src, ui_w, ui_h, &mut dst, 800, 600);
+        assert_eq!(dst.len(), 800 * 600);
+        // Black bars left and right, the image in between
+        assert_eq!(dst[12 * 800], 0);
+        assert_eq!(dst[12 * 800 + 80], 0xFF00_0000);
+        assert_eq!(dst[(12 + 575) * 800 + 80 + 639], 0xFFFF_FFFF);
+
+        assert_eq!(viewport.to_ui(80.0, 12.0, ui_w, ui_h), (0.0, 0.0));
+        assert_eq!(viewport.to_ui(400.0, 300.0, ui_w, ui_h), (320.0, 288.0));
+        assert_eq!(viewport.to_ui(0.0, 599.0, ui_w, ui_h), (0.0, 575.0));
+
+        // A window smaller than 1x shows the top-left of the image
+        present(&src, ui_w, ui_h, &mut dst, 100, 100);
+        assert_eq!(dst.len(), 100 * 100);
+    }
+}
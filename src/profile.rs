@@ -0,0 +1,172 @@
+//! Sampling profiler for the emulator's subsystems
+//!
+//! With profiling on, every [`SAMPLE_INTERVAL`]th step is timed piece by
+//! piece: the CPU between memory accesses, then each subsystem as the
+//! M-cycle catches it up. Timing only a fraction of the steps keeps the
+//! clock reads from swamping the work being measured, so the totals show
+//! how time splits between subsystems rather than how much was spent.
+//! Turn it on with [`Emulator::set_profiling`](crate::Emulator::set_profiling).
+//!
+//! Separately, [`Emulator::set_opcode_counting`](crate::Emulator::set_opcode_counting)
+//! counts every instruction executed by opcode, showing which instructions
+//! a test ROM covers or a game leans on.
+
+use std::time::{Duration, Instant};
+
+/// Steps between timed steps
+pub const SAMPLE_INTERVAL: u32 = 16;
+
+/// Time spent in each subsystem over the sampled steps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Instruction execution and interrupt dispatch
+    pub cpu: Duration,
+    pub ppu: Duration,
+    pub apu: Duration,
+    pub timer: Duration,
+    /// Serial port, OAM DMA, the cartridge clock and per-step bookkeeping
+    pub other: Duration,
+    /// Number of steps timed
+    pub samples: u64,
+}
+
+impl Profile {
+    /// Time over all subsystems
+    pub fn total(&self) -> Duration {
+        self.cpu + self.ppu + self.apu + self.timer + self.other
+    }
+}
+
+/// Executions of each opcode
+///
+/// Opcodes are reported as `u16`: base opcodes as they are, CB-prefixed
+/// ones as `0xCB00 | opcode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeHistogram {
+    /// Executions of each base opcode; CB-prefixed instructions also
+    /// count under 0xCB
+    pub base: [u64; 256],
+    /// Executions of each CB-prefixed opcode
+    pub cb: [u64; 256],
+}
+
+impl Default for OpcodeHistogram {
+    fn default() -> Self {
+        Self { base: [0; 256], cb: [0; 256] }
+    }
+}
+
+impl OpcodeHistogram {
+    /// Instructions counted
+    pub fn total(&self) -> u64 {
+        self.base.iter().sum()
+    }
+
+    /// Every instruction with its count, base opcodes first; the CB
+    /// prefix only appears through the CB-prefixed opcodes
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        let base = self.base.iter().enumerate().filter(|&(op, _)| op != 0xCB).map(|(op, &n)| (op as u16, n));
+        let cb = self.cb.iter().enumerate().map(|(op, &n)| (0xCB00 | op as u16, n));
+        base.chain(cb)
+    }
+
+    /// The `n` opcodes that ran most, most first
+    pub fn most_executed(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut counts: Vec<_> = self.iter().filter(|&(_, count)| count > 0).collect();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts.truncate(n);
+        counts
+    }
+
+    /// Opcodes that never ran, including the 11 base opcodes the CPU
+    /// doesn't implement
+    pub fn unexecuted(&self) -> Vec<u16> {
+        self.iter().filter(|&(_, count)| count == 0).map(|(op, _)| op).collect()
+    }
+
+    /// Count one instruction; `next` is the byte after the opcode, read
+    /// for the CB prefix
+    pub(crate) fn count(&mut self, opcode: u8, next: u8) {
+        self.base[opcode as usize] += 1;
+        if opcode == 0xCB {
+            self.cb[next as usize] += 1;
+        }
+    }
+}
+
+/// Subsystem a stretch of time is charged to
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Part {
+    Cpu,
+    Ppu,
+    Apu,
+    Timer,
+    Other,
+}
+
+/// Timing of the step in progress
+pub(crate) struct Sample {
+    last: Instant,
+    times: Profile,
+}
+
+impl Sample {
+    /// Charge the time since the previous lap to `part`
+    fn lap(&mut self, part: Part) {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        let times = &mut self.times;
+        *match part {
+            Part::Cpu => &mut times.cpu,
+            Part::Ppu => &mut times.ppu,
+            Part::Apu => &mut times.apu,
+            Part::Timer => &mut times.timer,
+            Part::Other => &mut times.other,
+        } += elapsed;
+    }
+}
+
+/// Charge the time since the previous lap to `part`, if this step is timed
+#[inline]
+pub(crate) fn lap(sample: &mut Option<Sample>, part: Part) {
+    if let Some(sample) = sample {
+        sample.lap(part);
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    profile: Profile,
+    /// Steps until the next timed one
+    countdown: u32,
+}
+
+impl Profiler {
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Start timing the current step if it's due
+    pub fn begin(&mut self) -> Option<Sample> {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return None;
+        }
+        self.countdown = SAMPLE_INTERVAL - 1;
+        Some(Sample { last: Instant::now(), times: Profile::default() })
+    }
+
+    /// Add a finished step's times to the profile, charging the rest of
+    /// the step to [`Part::Other`]
+    pub fn finish(&mut self, mut sample: Sample) {
+        sample.lap(Part::Other);
+        let (profile, times) = (&mut self.profile, sample.times);
+        profile.cpu += times.cpu;
+        profile.ppu += times.ppu;
+        profile.apu += times.apu;
+        profile.timer += times.timer;
+        profile.other += times.other;
+        profile.samples += 1;
+    }
+}
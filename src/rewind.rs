@@ -0,0 +1,201 @@
+//! Rewind buffer for save states
+//!
+//! Keeps the most recent state in full and every older one as an
+//! XOR delta against its successor, run-length encoded. Consecutive states
+//! differ in a few KB at most, so holding about a minute of history costs
+//! a few MB instead of the ~80 KB per state a raw ring buffer would use.
+
+use std::collections::VecDeque;
+
+/// Ring buffer of delta-compressed save states, newest last
+#[derive(Debug, Clone, Default)]
+pub struct RewindBuffer {
+    /// Newest state, uncompressed
+    current: Vec<u8>,
+    /// Deltas stepping back from each state to the one before it
+    deltas: VecDeque<Vec<u8>>,
+    /// Total size of `deltas` in bytes
+    delta_bytes: usize,
+    /// Memory budget for `deltas`
+    max_bytes: usize,
+    /// Scratch space for XOR-ing states
+    scratch: Vec<u8>,
+}
+
+impl RewindBuffer {
+    /// Create a buffer that keeps at most `max_bytes` of compressed history
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            ..Self::default()
+        }
+    }
+
+    /// Number of states that can be rewound to
+    pub fn len(&self) -> usize {
+        if self.current.is_empty() {
+            0
+        } else {
+            self.deltas.len() + 1
+        }
+    }
+
+    /// Whether the buffer holds no states
+    pub fn is_empty(&self) -> bool {
+        self.current.is_empty()
+    }
+
+    /// Compressed size of the history, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.current.len() + self.delta_bytes
+    }
+
+    /// Drop all stored states
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Record a new state, evicting the oldest ones if over budget
+    ///
+    /// A state of a different size (another ROM or save RAM layout) starts
+    /// the history over.
+    pub fn push(&mut self, state: &[u8]) {
+        if self.current.len() != state.len() {
+            self.clear();
+        }
+        if !self.current.is_empty() {
+            self.scratch.clear();
+            self.scratch
+                .extend(self.current.iter().zip(state).map(|(old, new)| old ^ new));
+            let delta = encode_delta(&self.scratch);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.current.clear();
+        self.current.extend_from_slice(state);
+
+        while self.delta_bytes > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.delta_bytes -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Remove and return the newest state
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        if self.current.is_empty() {
+            return None;
+        }
+        let newest = self.current.clone();
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.delta_bytes -= delta.len();
+                apply_delta(&mut self.current, &delta);
+            }
+            None => self.current.clear(),
+        }
+        Some(newest)
+    }
+}
+
+/// Run-length encode an XOR delta
+///
+/// The output is a sequence of `(zero run, literal length, literals)`
+/// records with LEB128 lengths.
+fn encode_delta(xor: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < xor.len() {
+        let zeros = xor[i..].iter().take_while(|&&b| b == 0).count();
+        i += zeros;
+        let literals = xor[i..].iter().take_while(|&&b| b != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&xor[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+/// XOR an encoded delta into `state`
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+    while i < delta.len() {
+        pos += read_varint(delta, &mut i);
+        let literals = read_varint(delta, &mut i);
+        for (dst, src) in state[pos..pos + literals].iter_mut().zip(&delta[i..i + literals]) {
+            *dst ^= src;
+        }
+        pos += literals;
+        i += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_states_newest_first() {
+        let mut states = Vec::new();
+        let mut state = vec![0u8; 4096];
+        for i in 0..20usize {
+            state[i * 100] = i as u8 + 1;
+            state[4000 + i] ^= 0xFF;
+            states.push(state.clone());
+        }
+
+        let mut buffer = RewindBuffer::new(1 << 20);
+        for s in &states {
+            buffer.push(s);
+        }
+        assert_eq!(buffer.len(), 20);
+        assert!(buffer.memory_usage() < 4096 + 20 * 16);
+
+        for expected in states.iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(expected));
+        }
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn evicts_oldest_over_budget() {
+        let mut buffer = RewindBuffer::new(64);
+        for i in 0..100u8 {
+            buffer.push(&[i; 32]);
+        }
+        assert!(buffer.len() < 100);
+        assert_eq!(buffer.pop(), Some(vec![99; 32]));
+        assert_eq!(buffer.pop(), Some(vec![98; 32]));
+
+        // A differently sized state restarts the history
+        buffer.push(&[1; 8]);
+        assert_eq!(buffer.len(), 1);
+    }
+}
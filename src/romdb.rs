@@ -0,0 +1,207 @@
+//! ROM database lookup (`romdb` feature)
+//!
+//! Matches ROMs by CRC32 against a No-Intro style DAT file (the Logiqx XML
+//! format No-Intro and Redump publish). A database is embedded at build
+//! time from the file the `GB3000_ROMDB` environment variable names, or
+//! `data/romdb.dat`, which has no entries of its own:
+//!
+//! ```sh
+//! GB3000_ROMDB=~/dats/"Nintendo - Game Boy.dat" cargo build --features romdb
+//! ```
+//!
+//! Frontends can also parse a DAT the user downloaded with
+//! [`RomDatabase::parse`].
+//!
+//! ```rust,no_run
+//! use gb3000::RomInfo;
+//!
+//! let rom = std::fs::read("game.gb").unwrap();
+//! if let Some(m) = RomInfo::lookup(&rom) {
+//!     println!("{} ({})", m.entry.name, m.entry.region);
+//! }
+//! ```
+
+use crate::RomInfo;
+use std::sync::OnceLock;
+
+/// The embedded database in Logiqx XML format, picked by build.rs
+const BUILTIN_DAT: &str = include_str!(env!("GB3000_ROMDB_DAT"));
+
+/// One known dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomEntry {
+    /// Full No-Intro name, e.g. "Tetris (World) (Rev 1)"
+    pub name: String,
+    /// First parenthesized tag of the name, e.g. "World"
+    pub region: String,
+    /// Size of the known-good dump in bytes
+    pub size: usize,
+    /// CRC32 of the known-good dump
+    pub crc32: u32,
+    /// No-Intro marks known bad dumps with "[b]"
+    pub bad_dump: bool,
+}
+
+/// A database hit for a ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomMatch<'a> {
+    pub entry: &'a RomEntry,
+    /// The ROM only matches once trimmed to the entry's size
+    pub overdump: bool,
+}
+
+/// A set of known dumps
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    entries: Vec<RomEntry>,
+}
+
+impl RomDatabase {
+    /// Parse a Logiqx XML DAT file
+    ///
+    /// Games without a `<rom>` carrying `size` and `crc` are skipped.
+    pub fn parse(dat: &str) -> Self {
+        let mut entries = Vec::new();
+        for game in dat.split("<game ").skip(1) {
+            let game = game.split("</game>").next().unwrap_or(game);
+            let Some(name) = attribute(game, "name") else { continue };
+            let Some(rom) = game.split("<rom ").nth(1) else { continue };
+            let size = attribute(rom, "size").and_then(|s| s.parse().ok());
+            let crc32 = attribute(rom, "crc").and_then(|s| u32::from_str_radix(&s, 16).ok());
+            let (Some(size), Some(crc32)) = (size, crc32) else { continue };
+            let region = name
+                .split_once('(')
+                .and_then(|(_, rest)| rest.split_once(')'))
+                .map_or_else(String::new, |(region, _)| region.to_string());
+            entries.push(RomEntry {
+                bad_dump: name.contains("[b]"),
+                name,
+                region,
+                size,
+                crc32,
+            });
+        }
+        Self { entries }
+    }
+
+    /// The embedded database
+    pub fn builtin() -> &'static Self {
+        static DATABASE: OnceLock<RomDatabase> = OnceLock::new();
+        DATABASE.get_or_init(|| Self::parse(BUILTIN_DAT))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Find `rom`, also trying it trimmed to its header-declared size in
+    /// case it is an overdump
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomMatch<'_>> {
+        let find = |data: &[u8]| {
+            let crc = crc32(data);
+            self.entries.iter().find(|e| e.size == data.len() && e.crc32 == crc)
+        };
+        if let Some(entry) = find(rom) {
+            return Some(RomMatch { entry, overdump: false });
+        }
+        let declared = 0x8000usize << rom.get(0x0148).copied().filter(|&c| c <= 8)?;
+        if rom.len() > declared {
+            return find(&rom[..declared]).map(|entry| RomMatch { entry, overdump: true });
+        }
+        None
+    }
+}
+
+impl RomInfo {
+    /// Look `rom` up in the embedded database
+    pub fn lookup(rom: &[u8]) -> Option<RomMatch<'static>> {
+        RomDatabase::builtin().lookup(rom)
+    }
+}
+
+/// Value of `key="..."` in an XML tag, with entities decoded
+fn attribute(tag: &str, key: &str) -> Option<String> {
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    let pattern = format!("{}=\"", key);
+    let start = tag
+        .match_indices(&pattern)
+        .find(|&(i, _)| i == 0 || tag.as_bytes()[i - 1].is_ascii_whitespace())?
+        .0
+        + pattern.len();
+    let end = start + tag[start..].find('"')?;
+    Some(
+        tag[start..end]
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// CRC-32 (IEEE), as used by DAT files
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_dumps_and_overdumps() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let rom = vec![0u8; 0x8000];
+        let dat = format!(
+            r#"<?xml version="1.0"?>
+<datafile>
+	<header><name>Test</name></header>
+	<game name="Blank &amp; Empty (Europe) (En,Fr)">
+		<description>Blank</description>
+		<rom name="Blank.gb" size="32768" crc="{:08X}"/>
+	</game>
+	<game name="Broken (USA)"><description>x</description></game>
+</datafile>"#,
+            crc32(&rom)
+        );
+        let db = RomDatabase::parse(&dat);
+        assert_eq!(db.len(), 1);
+
+        let m = db.lookup(&rom).unwrap();
+        assert_eq!(m.entry.name, "Blank & Empty (Europe) (En,Fr)");
+        assert_eq!(m.entry.region, "Europe");
+        assert!(!m.overdump && !m.entry.bad_dump);
+
+        let mut overdump = rom.clone();
+        overdump.resize(0x10000, 0xFF);
+        assert!(db.lookup(&overdump).unwrap().overdump);
+        overdump[0] = 1;
+        assert!(db.lookup(&overdump).is_none());
+    }
+
+    #[test]
+    fn looks_up_a_known_crc() {
+        // A blank 32 KB image, as a No-Intro DAT lists it
+        let db = RomDatabase::parse(
+            r#"<game name="Blank (World)"><rom name="Blank (World).gb" size="32768" crc="011ffca6" md5="x"/></game>"#,
+        );
+        let m = db.lookup(&[0u8; 0x8000]).unwrap();
+        assert_eq!((m.entry.crc32, m.entry.name.as_str()), (0x011F_FCA6, "Blank (World)"));
+        assert!(db.lookup(&[1u8; 0x8000]).is_none());
+
+        // Whatever DAT was embedded parses
+        assert_eq!(RomDatabase::builtin().len(), RomDatabase::parse(BUILTIN_DAT).len());
+    }
+}
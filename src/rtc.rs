@@ -0,0 +1,291 @@
+//! MBC3 real-time clock
+//!
+//! The clock counts seconds, minutes, hours and a 9-bit day counter from a
+//! 32.768 kHz crystal. Games read a latched copy: writing 0x00 then 0x01 to
+//! 0x6000-0x7FFF copies the live registers into the latch. Registers are
+//! selected by writing 0x08-0x0C to the RAM bank register.
+//!
+//! Battery saves append the clock to the RAM dump in the 48-byte footer
+//! used by BGB and VBA-M (or the 44-byte one from older VBA builds), so
+//! time keeps passing while the emulator is closed.
+
+use crate::savefile::RtcFooter;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// T-cycles per RTC second
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// Size of the BGB/VBA-M save file footer
+pub const FOOTER_SIZE: usize = 48;
+
+/// Size of the older VBA footer, which has a 32-bit timestamp
+pub const VBA_FOOTER_SIZE: usize = 44;
+
+/// Day counter high bit, halt flag and day carry in the DH register
+const DH_DAY_BIT8: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+/// Clock registers in RAM bank order (0x08-0x0C)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days_low: u8,
+    /// Bit 0: day bit 8, bit 6: halt, bit 7: day carry
+    days_high: u8,
+}
+
+impl Registers {
+    fn get(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days_low,
+            0x0C => self.days_high,
+            _ => 0xFF,
+        }
+    }
+
+    fn to_array(self) -> [u8; 5] {
+        [self.seconds, self.minutes, self.hours, self.days_low, self.days_high]
+    }
+
+    fn from_array(r: [u8; 5]) -> Self {
+        Self {
+            seconds: r[0] & 0x3F,
+            minutes: r[1] & 0x3F,
+            hours: r[2] & 0x1F,
+            days_low: r[3],
+            days_high: r[4] & (DH_DAY_BIT8 | DH_HALT | DH_CARRY),
+        }
+    }
+
+    fn days(&self) -> u16 {
+        self.days_low as u16 | ((self.days_high & DH_DAY_BIT8) as u16) << 8
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & !DH_DAY_BIT8) | ((days >> 8) as u8 & DH_DAY_BIT8);
+    }
+
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    /// Count one second
+    ///
+    /// Out-of-range values written by the game wrap at the register width
+    /// without carrying, like on hardware.
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.add_days(1);
+    }
+
+    fn add_days(&mut self, days: u64) {
+        let total = self.days() as u64 + days;
+        if total > 0x1FF {
+            self.days_high |= DH_CARRY;
+        }
+        self.set_days((total & 0x1FF) as u16);
+    }
+
+    /// Count `seconds` seconds at once
+    fn advance(&mut self, mut seconds: u64) {
+        // Step out-of-range values one at a time until they wrap
+        while seconds > 0 && !self.in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        let time = self.seconds as u64 + 60 * (self.minutes as u64 + 60 * self.hours as u64) + seconds;
+        self.seconds = (time % 60) as u8;
+        self.minutes = (time / 60 % 60) as u8;
+        self.hours = (time / 3600 % 24) as u8;
+        self.add_days(time / 86400);
+    }
+}
+
+/// MBC3 clock state
+#[derive(Debug, Clone, Default)]
+pub struct Rtc {
+    live: Registers,
+    latched: Registers,
+    /// Last value written to the latch register
+    latch_write: u8,
+    /// T-cycles since the last second
+    cycles: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance the clock by `cycles` T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        if self.live.days_high & DH_HALT != 0 {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.live.tick_second();
+        }
+    }
+
+    /// Read a latched register (0x08-0x0C)
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched.get(reg)
+    }
+
+    /// Write a live register (0x08-0x0C)
+    pub fn write(&mut self, reg: u8, value: u8) {
+        let r = &mut self.live;
+        match reg {
+            0x08 => {
+                r.seconds = value & 0x3F;
+                // Writing seconds resets the sub-second divider
+                self.cycles = 0;
+            }
+            0x09 => r.minutes = value & 0x3F,
+            0x0A => r.hours = value & 0x1F,
+            0x0B => r.days_low = value,
+            0x0C => r.days_high = value & (DH_DAY_BIT8 | DH_HALT | DH_CARRY),
+            _ => {}
+        }
+    }
+
+    /// Handle a write to 0x6000-0x7FFF: 0x00 then 0x01 latches the clock
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_write == 0x00 && value == 0x01 {
+            self.latched = self.live;
+        }
+        self.latch_write = value;
+    }
+
+    /// Advance the clock by whole seconds, e.g. time spent with the
+    /// emulator closed
+    pub fn advance_seconds(&mut self, seconds: u64) {
+        if self.live.days_high & DH_HALT == 0 {
+            self.live.advance(seconds);
+        }
+    }
+
+    /// Encode the clock as a save footer
+    ///
+    /// Layout: live then latched registers as five little-endian u32 each,
+    /// followed by the UNIX time of saving as a u64 (BGB) or u32 (VBA).
+    pub fn footer(&self, format: RtcFooter, unix_time: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(format.size());
+        let regs = self.live.to_array().into_iter().chain(self.latched.to_array());
+        for value in regs {
+            out.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        match format {
+            RtcFooter::Bgb => out.extend_from_slice(&unix_time.to_le_bytes()),
+            RtcFooter::Vba => out.extend_from_slice(&(unix_time as u32).to_le_bytes()),
+        }
+        out
+    }
+
+    /// Restore the clock from a save footer in either layout, catching up
+    /// on the time between `footer`'s timestamp and `unix_time`
+    ///
+    /// Returns false if `footer` isn't a footer.
+    pub fn load_footer(&mut self, footer: &[u8], unix_time: u64) -> bool {
+        let saved_at = match footer.len() {
+            FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            VBA_FOOTER_SIZE => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            _ => return false,
+        };
+        let word = |i: usize| footer[i * 4];
+        self.live = Registers::from_array([word(0), word(1), word(2), word(3), word(4)]);
+        self.latched = Registers::from_array([word(5), word(6), word(7), word(8), word(9)]);
+        self.advance_seconds(unix_time.saturating_sub(saved_at));
+        true
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.live.to_array());
+        w.bytes(&self.latched.to_array());
+        w.u8(self.latch_write);
+        w.u32(self.cycles);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut regs = [0u8; 5];
+        r.bytes_into(&mut regs)?;
+        self.live = Registers::from_array(regs);
+        r.bytes_into(&mut regs)?;
+        self.latched = Registers::from_array(regs);
+        self.latch_write = r.u8()?;
+        self.cycles = r.u32()?.min(CYCLES_PER_SECOND - 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latched(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|reg| rtc.read(reg))
+    }
+
+    #[test]
+    fn counts_and_latches_time() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0A, 23);
+        rtc.write(0x09, 59);
+        rtc.write(0x08, 59);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.tick(CYCLES_PER_SECOND);
+        // Day 511 rolls over to 0 and sets the carry
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0, DH_CARRY]);
+
+        // The latch only updates on a 0 -> 1 write
+        rtc.tick(CYCLES_PER_SECOND * 5);
+        rtc.write_latch(1);
+        assert_eq!(rtc.read(0x08), 0);
+
+        rtc.write(0x0C, DH_HALT);
+        rtc.tick(CYCLES_PER_SECOND * 5);
+        assert_eq!(latched(&mut rtc)[0], 5);
+    }
+
+    #[test]
+    fn footer_catches_up_elapsed_time() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 30);
+        let elapsed = 2 * 86400 + 3600 + 45;
+
+        for format in [RtcFooter::Bgb, RtcFooter::Vba] {
+            let footer = rtc.footer(format, 1_000_000);
+            assert_eq!(footer.len(), format.size());
+            let mut loaded = Rtc::new();
+            assert!(loaded.load_footer(&footer, 1_000_000 + elapsed));
+            assert_eq!(latched(&mut loaded), [15, 1, 1, 2, 0]);
+        }
+        assert!(!Rtc::new().load_footer(&[0; 40], 0));
+    }
+}
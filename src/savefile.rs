@@ -0,0 +1,100 @@
+//! Battery save file layouts used by other emulators
+//!
+//! A `.sav` file is the cartridge RAM, followed on MBC3 clock cartridges by
+//! an RTC footer. Emulators disagree on two details:
+//!
+//! - **RTC footer**: BGB, VBA-M, SameBoy and mGBA append 48 bytes ending in
+//!   a 64-bit timestamp; older VBA builds append 44 bytes with a 32-bit one.
+//! - **MBC2 RAM**: the chip stores 512 half-bytes. Most emulators write one
+//!   nibble per byte (512 bytes), some pack two nibbles per byte (256 bytes).
+//!
+//! Loading accepts every variant; [`SaveFormat`] picks what is written.
+
+/// RTC footer layout appended to clock cartridge saves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RtcFooter {
+    /// 48 bytes with a 64-bit timestamp (BGB, VBA-M, SameBoy, mGBA)
+    #[default]
+    Bgb,
+    /// 44 bytes with a 32-bit timestamp (older VBA builds)
+    Vba,
+}
+
+impl RtcFooter {
+    /// Footer length in bytes
+    pub fn size(self) -> usize {
+        match self {
+            RtcFooter::Bgb => crate::rtc::FOOTER_SIZE,
+            RtcFooter::Vba => crate::rtc::VBA_FOOTER_SIZE,
+        }
+    }
+}
+
+/// How MBC2's 512 half-bytes of RAM are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mbc2Layout {
+    /// One nibble per byte with the upper nibble set, as the CPU reads it
+    /// (512 bytes; BGB, SameBoy, mGBA)
+    #[default]
+    Nibbles,
+    /// Two nibbles per byte, low address in the low nibble (256 bytes)
+    Packed,
+}
+
+/// Layout used when writing a battery save
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SaveFormat {
+    pub rtc_footer: RtcFooter,
+    pub mbc2_layout: Mbc2Layout,
+}
+
+/// Size of MBC2's built-in RAM in half-bytes
+pub const MBC2_RAM_SIZE: usize = 512;
+
+/// Store MBC2 RAM (one nibble per byte) in `layout`
+pub fn encode_mbc2(ram: &[u8], layout: Mbc2Layout) -> Vec<u8> {
+    match layout {
+        Mbc2Layout::Nibbles => ram.iter().map(|b| b | 0xF0).collect(),
+        Mbc2Layout::Packed => ram
+            .chunks(2)
+            .map(|pair| (pair[0] & 0x0F) | pair.get(1).map_or(0, |b| (b & 0x0F) << 4))
+            .collect(),
+    }
+}
+
+/// Read MBC2 RAM from a save in either layout, telling them apart by size
+///
+/// Returns one nibble per byte with the upper nibble cleared.
+pub fn decode_mbc2(data: &[u8]) -> Vec<u8> {
+    if data.len() == MBC2_RAM_SIZE / 2 {
+        data.iter().flat_map(|b| [b & 0x0F, b >> 4]).collect()
+    } else {
+        // Some emulators pad to 8 KB; only the first 512 bytes are real
+        data.iter().take(MBC2_RAM_SIZE).map(|b| b & 0x0F).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mbc2_layouts_round_trip() {
+        let ram: Vec<u8> = (0..MBC2_RAM_SIZE).map(|i| (i * 7) as u8 & 0x0F).collect();
+
+        let nibbles = encode_mbc2(&ram, Mbc2Layout::Nibbles);
+        assert_eq!(nibbles.len(), 512);
+        assert!(nibbles.iter().all(|b| b & 0xF0 == 0xF0));
+        assert_eq!(decode_mbc2(&nibbles), ram);
+
+        let packed = encode_mbc2(&ram, Mbc2Layout::Packed);
+        assert_eq!(packed.len(), 256);
+        assert_eq!(packed[0], ram[0] | ram[1] << 4);
+        assert_eq!(decode_mbc2(&packed), ram);
+
+        // 8 KB saves from emulators that treat MBC2 like any other RAM
+        let mut padded = nibbles.clone();
+        padded.resize(0x2000, 0xFF);
+        assert_eq!(decode_mbc2(&padded), ram);
+    }
+}
@@ -0,0 +1,104 @@
+//! Numbered save state slots for the desktop UI
+//!
+//! Each slot is a file next to the ROM (`game.ss0` to `game.ss9`) holding
+//! a thumbnail of the screen followed by the core's save state.
+
+use crate::capture;
+use gb3000::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Number of save state slots per ROM
+pub const SLOT_COUNT: u8 = 10;
+
+/// Magic bytes at the start of a slot file
+const SLOT_MAGIC: &[u8; 4] = b"GB3T";
+
+/// Size of the thumbnail (2-bit color indices of the screen)
+const THUMBNAIL_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+/// A filled save state slot, for the state browser
+#[derive(Debug, Clone)]
+pub struct SlotInfo {
+    /// Screen at the time of saving (160x144 color indices)
+    pub thumbnail: Box<[u8]>,
+    /// When the slot was written, formatted for display
+    pub saved_at: String,
+}
+
+/// Path of a slot file for a ROM
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("ss{}", slot))
+}
+
+/// Save the emulator state with a thumbnail into a slot
+pub fn save_slot(emulator: &Emulator, rom_path: &Path, slot: u8) -> Result<(), String> {
+    let mut data = Vec::new();
+    data.extend_from_slice(SLOT_MAGIC);
+    data.extend_from_slice(emulator.framebuffer());
+    data.extend_from_slice(&emulator.save_state());
+
+    let path = slot_path(rom_path, slot);
+    fs::write(&path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Restore the emulator from a slot
+pub fn load_slot(emulator: &mut Emulator, rom_path: &Path, slot: u8) -> Result<(), String> {
+    let path = slot_path(rom_path, slot);
+    let data = fs::read(&path).map_err(|_| format!("Slot {} is empty", slot))?;
+    let state = data
+        .strip_prefix(SLOT_MAGIC)
+        .and_then(|rest| rest.get(THUMBNAIL_SIZE..))
+        .ok_or_else(|| format!("Slot {} is not a save state", slot))?;
+    emulator
+        .load_state(state)
+        .map_err(|e| format!("Slot {}: {}", slot, e))
+}
+
+/// Read the thumbnail and timestamp of every slot
+pub fn list_slots(rom_path: &Path) -> Vec<Option<SlotInfo>> {
+    (0..SLOT_COUNT).map(|slot| read_slot_info(&slot_path(rom_path, slot))).collect()
+}
+
+fn read_slot_info(path: &Path) -> Option<SlotInfo> {
+    let data = fs::read(path).ok()?;
+    let thumbnail = data.strip_prefix(SLOT_MAGIC)?.get(..THUMBNAIL_SIZE)?;
+    let saved_at = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| capture::format_datetime(d.as_secs()))
+        .unwrap_or_default();
+    Some(SlotInfo {
+        thumbnail: thumbnail.into(),
+        saved_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_roundtrip_with_thumbnail() {
+        let rom_path = std::env::temp_dir().join(format!("gb3000-slot-test-{}.gb", std::process::id()));
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&vec![0u8; 0x8000]);
+        emulator.run_frame();
+
+        save_slot(&emulator, &rom_path, 3).unwrap();
+        let saved = emulator.save_state();
+        emulator.run_frame();
+        load_slot(&mut emulator, &rom_path, 3).unwrap();
+        assert_eq!(emulator.save_state(), saved);
+
+        let slots = list_slots(&rom_path);
+        fs::remove_file(slot_path(&rom_path, 3)).unwrap();
+        assert_eq!(slots.len(), SLOT_COUNT as usize);
+        let info = slots[3].as_ref().unwrap();
+        assert_eq!(&info.thumbnail[..], &emulator.framebuffer()[..]);
+        assert!(slots[4].is_none());
+        assert!(load_slot(&mut emulator, &rom_path, 4).is_err());
+    }
+}
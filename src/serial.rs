@@ -0,0 +1,373 @@
+//! Serial port (link cable)
+//!
+//! Writing SC with bits 7 and 0 set starts a transfer on the internal
+//! clock: SB shifts out one bit at a time, MSB first, at 8192 Hz (262144 Hz
+//! with the CGB fast clock), while the other side's bits shift in. After
+//! eight bits SC bit 7 clears and the serial interrupt fires.
+//!
+//! With nothing plugged in the incoming line stays high, so every
+//! transfer receives 0xFF, and transfers on the external clock never
+//! finish, as on hardware with no cable. A [`SerialDevice`], attached with
+//! [`Emulator::set_serial_device`](crate::Emulator::set_serial_device),
+//! answers instead: [`LinkCable::pair`] connects two emulators, which the
+//! frontend then runs in small interleaved slices like an IR link. Each
+//! byte sent on the internal clock is kept for
+//! [`Emulator::take_serial_output`](crate::Emulator::take_serial_output),
+//! which is how Blargg's test ROMs report results.
+
+use crate::memory::{interrupts, io, Memory};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// CPU cycles per bit on the normal clock (8192 Hz)
+const NORMAL_BIT_CYCLES: u32 = 512;
+/// CPU cycles per bit on the CGB fast clock (262144 Hz)
+const FAST_BIT_CYCLES: u32 = 16;
+/// Sent bytes kept until taken; older ones are dropped
+const OUTPUT_LIMIT: usize = 0x10000;
+
+/// Something plugged into the link port
+pub trait SerialDevice: Send {
+    /// The game started clocking out `byte` on its internal clock; return
+    /// the byte the device shifts back over the same eight bits
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    /// The game is waiting on the external clock with `byte` in SB;
+    /// return the byte shifted in once the device has clocked a transfer
+    fn external_clock(&mut self, byte: u8) -> Option<u8> {
+        let _ = byte;
+        None
+    }
+
+    /// The game stopped waiting on the external clock, by clearing SC
+    /// bit 7 or switching to its internal clock, before a transfer came
+    fn cancel(&mut self) {}
+}
+
+/// One end of a link cable between two emulators
+///
+/// The end whose game clocks a transfer swaps bytes with the other end,
+/// if that game is waiting on the external clock; otherwise it reads
+/// 0xFF, as with the cable unplugged. The waiting side's transfer
+/// completes on its next step; a game that stops waiting first, to clock
+/// transfers itself, takes its byte back.
+#[derive(Debug, Clone)]
+pub struct LinkCable {
+    own: Arc<CablePort>,
+    other: Arc<CablePort>,
+}
+
+/// What one end of the cable shows the other; 0 for nothing, otherwise
+/// 0x100 with the byte
+#[derive(Debug, Default)]
+struct CablePort {
+    /// SB of a game waiting on the external clock
+    waiting: AtomicU16,
+    /// Byte clocked in by the other end, not yet picked up
+    delivered: AtomicU16,
+}
+
+impl LinkCable {
+    /// Both ends of a cable
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(CablePort::default());
+        let b = Arc::new(CablePort::default());
+        (
+            Self { own: a.clone(), other: b.clone() },
+            Self { own: b, other: a },
+        )
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        match self.other.waiting.swap(0, Ordering::AcqRel) {
+            0 => 0xFF,
+            theirs => {
+                self.other.delivered.store(0x100 | byte as u16, Ordering::Release);
+                theirs as u8
+            }
+        }
+    }
+
+    fn external_clock(&mut self, byte: u8) -> Option<u8> {
+        match self.own.delivered.swap(0, Ordering::AcqRel) {
+            0 => {
+                self.own.waiting.store(0x100 | byte as u16, Ordering::Release);
+                None
+            }
+            delivered => Some(delivered as u8),
+        }
+    }
+
+    fn cancel(&mut self) {
+        // A byte the other end clocked in meanwhile belonged to the
+        // abandoned transfer
+        self.own.waiting.store(0, Ordering::Release);
+        self.own.delivered.store(0, Ordering::Release);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Serial {
+    /// Bits left in the current transfer, 0 when idle
+    bits_left: u8,
+    /// CPU cycles until the next bit shifts
+    counter: u32,
+    /// Byte shifting in, MSB first
+    incoming: u8,
+    /// Bytes sent since the output was last taken
+    output: Vec<u8>,
+    /// Whether the device was told the game waits on the external clock
+    waiting: bool,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self { bits_left: 0, counter: 0, incoming: 0xFF, output: Vec::new(), waiting: false }
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.bits_left = 0;
+        self.counter = 0;
+        self.incoming = 0xFF;
+        self.output.clear();
+        self.waiting = false;
+    }
+
+    /// Advance the port by the given number of CPU cycles, talking to
+    /// `device` if one is plugged in
+    pub fn tick(&mut self, memory: &mut Memory, cycles: u32, mut device: Option<&mut dyn SerialDevice>) {
+        let sc = memory.io[io::SC as usize];
+        let external = sc & 0x81 == 0x80;
+        if self.waiting && !external {
+            self.waiting = false;
+            if let Some(device) = device.as_deref_mut() {
+                device.cancel();
+            }
+        }
+        if sc & 0x80 == 0 {
+            self.bits_left = 0;
+            return;
+        }
+        if external {
+            // External clock: only the other end can finish the transfer
+            self.bits_left = 0;
+            let Some(device) = device else {
+                return;
+            };
+            match device.external_clock(memory.io[io::SB as usize]) {
+                Some(byte) => {
+                    self.waiting = false;
+                    memory.io[io::SB as usize] = byte;
+                    memory.io[io::SC as usize] &= 0x7F;
+                    memory.request_interrupt(interrupts::SERIAL);
+                }
+                None => self.waiting = true,
+            }
+            return;
+        }
+        let period = if sc & 0x02 != 0 && memory.cgb_mode() {
+            FAST_BIT_CYCLES
+        } else {
+            NORMAL_BIT_CYCLES
+        };
+        if self.bits_left == 0 {
+            self.bits_left = 8;
+            self.counter = period;
+            if self.output.len() >= OUTPUT_LIMIT {
+                self.output.drain(..OUTPUT_LIMIT / 2);
+            }
+            let byte = memory.io[io::SB as usize];
+            log_debug!("gb3000::serial", "sent {:02X} {:?}", byte, byte as char);
+            self.output.push(byte);
+            self.incoming = device.map_or(0xFF, |d| d.exchange(byte));
+        }
+
+        let mut cycles = cycles;
+        while cycles >= self.counter {
+            cycles -= self.counter;
+            self.counter = period;
+            let sb = &mut memory.io[io::SB as usize];
+            *sb = (*sb << 1) | (self.incoming >> 7);
+            self.incoming <<= 1;
+            self.bits_left -= 1;
+            if self.bits_left == 0 {
+                memory.io[io::SC as usize] &= 0x7F;
+                memory.request_interrupt(interrupts::SERIAL);
+                return;
+            }
+        }
+        self.counter -= cycles;
+    }
+
+    /// Take the bytes sent since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Bytes sent since the output was last taken
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Save the transfer in progress, or the wait for the other end's
+    /// clock; unread output is not saved
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.bits_left);
+        w.u32(self.counter);
+        w.u8(self.incoming);
+        w.bool(self.waiting);
+    }
+
+    /// Restore the transfer in progress
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bits_left = r.u8()?;
+        self.counter = r.u32()?;
+        self.incoming = r.u8()?;
+        self.waiting = r.bool()?;
+        if self.bits_left > 8 {
+            return Err(StateError::Invalid("serial transfer"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_shifts_in_ones_and_interrupts() {
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        memory.io[io::SB as usize] = 0x42;
+        memory.write_byte(io::SC, 0x81);
+
+        serial.tick(&mut memory, 4, None);
+        serial.tick(&mut memory, 7 * NORMAL_BIT_CYCLES, None);
+        assert_eq!(memory.io[io::SB as usize], 0x7F);
+        assert_eq!(memory.io[io::IF as usize] & interrupts::SERIAL, 0);
+
+        serial.tick(&mut memory, NORMAL_BIT_CYCLES, None);
+        assert_eq!(memory.io[io::SB as usize], 0xFF);
+        assert_eq!(memory.io[io::SC as usize] & 0x80, 0);
+        assert_ne!(memory.io[io::IF as usize] & interrupts::SERIAL, 0);
+        assert_eq!(serial.take_output(), [0x42]);
+        assert!(serial.take_output().is_empty());
+
+        // Nothing drives the external clock
+        memory.write_byte(io::SC, 0x80);
+        serial.tick(&mut memory, 16 * NORMAL_BIT_CYCLES, None);
+        assert_eq!(memory.io[io::SC as usize], 0x80);
+        assert!(serial.take_output().is_empty());
+    }
+
+    #[test]
+    fn link_cable_swaps_bytes_with_a_waiting_game() {
+        let (a, b) = LinkCable::pair();
+        let mut sides = [(Memory::new(), Serial::new(), a), (Memory::new(), Serial::new(), b)];
+
+        // Nobody waits on the other end yet
+        let (memory, serial, cable) = &mut sides[0];
+        memory.io[io::SB as usize] = 0x12;
+        memory.write_byte(io::SC, 0x81);
+        serial.tick(memory, 8 * NORMAL_BIT_CYCLES, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0xFF);
+
+        // The other side waits on the external clock
+        let (memory, serial, cable) = &mut sides[1];
+        memory.io[io::SB as usize] = 0x34;
+        memory.write_byte(io::SC, 0x80);
+        serial.tick(memory, 4, Some(cable));
+        assert_eq!(memory.io[io::SC as usize] & 0x80, 0x80);
+
+        let (memory, serial, cable) = &mut sides[0];
+        memory.io[io::SB as usize] = 0x56;
+        memory.write_byte(io::SC, 0x81);
+        serial.tick(memory, 4, Some(cable));
+        serial.tick(memory, 4 * NORMAL_BIT_CYCLES, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0x63, "half of 0x34 shifted in");
+        serial.tick(memory, 4 * NORMAL_BIT_CYCLES, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0x34);
+
+        let (memory, serial, cable) = &mut sides[1];
+        serial.tick(memory, 4, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0x56);
+        assert_eq!(memory.io[io::SC as usize] & 0x80, 0);
+        assert_ne!(memory.io[io::IF as usize] & interrupts::SERIAL, 0);
+    }
+
+    #[test]
+    fn link_cable_forgets_a_cancelled_wait() {
+        let (a, b) = LinkCable::pair();
+        let mut sides = [(Memory::new(), Serial::new(), a), (Memory::new(), Serial::new(), b)];
+
+        // The first side waits on the external clock, then takes over
+        // the clock itself, as games do when trading roles
+        let (memory, serial, cable) = &mut sides[0];
+        memory.io[io::SB as usize] = 0x11;
+        memory.write_byte(io::SC, 0x80);
+        serial.tick(memory, 4, Some(cable));
+        memory.write_byte(io::SC, 0x01);
+        serial.tick(memory, 4, Some(cable));
+
+        // so the second side's transfer finds nobody waiting
+        let (memory, serial, cable) = &mut sides[1];
+        memory.io[io::SB as usize] = 0x22;
+        memory.write_byte(io::SC, 0x81);
+        serial.tick(memory, 8 * NORMAL_BIT_CYCLES, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0xFF);
+
+        // The first side now clocks and the second waits
+        let (memory, serial, cable) = &mut sides[1];
+        memory.io[io::SB as usize] = 0x33;
+        memory.write_byte(io::SC, 0x80);
+        serial.tick(memory, 4, Some(cable));
+        assert_eq!(memory.io[io::SC as usize] & 0x80, 0x80, "nothing stale delivered");
+
+        let (memory, serial, cable) = &mut sides[0];
+        memory.io[io::SB as usize] = 0x44;
+        memory.write_byte(io::SC, 0x81);
+        serial.tick(memory, 8 * NORMAL_BIT_CYCLES, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0x33);
+
+        let (memory, serial, cable) = &mut sides[1];
+        serial.tick(memory, 4, Some(cable));
+        assert_eq!(memory.io[io::SB as usize], 0x44);
+        assert_eq!(memory.io[io::SC as usize] & 0x80, 0);
+    }
+
+    #[test]
+    fn waiting_survives_a_state_round_trip() {
+        let (mut a, mut b) = LinkCable::pair();
+        let mut memory = Memory::new();
+        let mut serial = Serial::new();
+        memory.io[io::SB as usize] = 0x11;
+        memory.write_byte(io::SC, 0x80);
+        serial.tick(&mut memory, 4, Some(&mut a));
+
+        let mut w = StateWriter::new();
+        serial.save_state(&mut w);
+        let data = w.finish();
+        let mut loaded = Serial::new();
+        loaded.load_state(&mut StateReader::new(&data).unwrap()).unwrap();
+
+        // The loaded port still knows to withdraw the wait when the game
+        // takes over the clock
+        memory.write_byte(io::SC, 0x01);
+        loaded.tick(&mut memory, 4, Some(&mut a));
+        let mut other = Memory::new();
+        other.io[io::SB as usize] = 0x22;
+        other.write_byte(io::SC, 0x81);
+        Serial::new().tick(&mut other, 8 * NORMAL_BIT_CYCLES, Some(&mut b));
+        assert_eq!(other.io[io::SB as usize], 0xFF);
+    }
+}
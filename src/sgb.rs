@@ -0,0 +1,548 @@
+//! Super Game Boy command packets, palettes and borders
+//!
+//! The SGB talks to the game through the joypad register. Pulling both
+//! select lines low starts a packet, then each of its 128 bits is sent LSB
+//! first as a pulse on P14 (0) or P15 (1), followed by a 0 stop bit. The
+//! first byte of a command holds its code (bits 3-7) and how many 16-byte
+//! packets it spans (bits 0-2).
+//!
+//! Bulk data is sent by putting it on screen: after a `*_TRN` command the
+//! SGB reads the next frame as 256 tiles (4 KB).
+//!
+//! Supported commands are PAL01-PAL12, ATTR_BLK, PAL_SET, PAL_TRN, MLT_REQ,
+//! CHR_TRN, PCT_TRN and MASK_EN; the rest are ignored.
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Width of the SGB picture, border included
+pub const SGB_WIDTH: usize = 256;
+/// Height of the SGB picture, border included
+pub const SGB_HEIGHT: usize = 224;
+
+/// Controllers the SGB can multiplex with MLT_REQ
+pub const MAX_PLAYERS: usize = 4;
+
+/// Top-left corner of the Game Boy screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+const PACKET_SIZE: usize = 16;
+/// Bytes read from the screen by a `*_TRN` command
+const TRANSFER_SIZE: usize = 4096;
+/// Palette attribute cells (one per tile)
+const ATTR_COLUMNS: usize = SCREEN_WIDTH / 8;
+const ATTR_ROWS: usize = SCREEN_HEIGHT / 8;
+/// Border tilemap plus its four 16-color palettes
+const BORDER_MAP_SIZE: usize = 0x880;
+
+mod command {
+    pub const PAL01: u8 = 0x00;
+    pub const PAL23: u8 = 0x01;
+    pub const PAL03: u8 = 0x02;
+    pub const PAL12: u8 = 0x03;
+    pub const ATTR_BLK: u8 = 0x04;
+    pub const PAL_SET: u8 = 0x0A;
+    pub const PAL_TRN: u8 = 0x0B;
+    pub const MLT_REQ: u8 = 0x11;
+    pub const CHR_TRN: u8 = 0x13;
+    pub const PCT_TRN: u8 = 0x14;
+    pub const MASK_EN: u8 = 0x17;
+}
+
+/// MASK_EN modes
+const MASK_NONE: u8 = 0;
+const MASK_FREEZE: u8 = 1;
+const MASK_BLACK: u8 = 2;
+const MASK_COLOR0: u8 = 3;
+
+/// Data the SGB reads from the next frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    SystemPalettes,
+    /// Border tiles 0x00-0x7F, or 0x80-0xFF if set
+    BorderTiles(bool),
+    BorderMap,
+}
+
+/// Super Game Boy state
+#[derive(Debug, Clone)]
+pub struct Sgb {
+    /// Last P14/P15 select lines written
+    select: u8,
+    /// Bits received of the current packet; None outside a packet
+    bit: Option<u8>,
+    /// The select lines went high since the last bit
+    ready: bool,
+    packet: [u8; PACKET_SIZE],
+    /// Packets of the command received so far
+    command: Vec<u8>,
+    /// Number of controllers enabled by MLT_REQ (1, 2 or 4)
+    players: u8,
+    /// Controller the game is reading
+    player: u8,
+    /// Active palettes, BGR555
+    palettes: [[u16; 4]; 4],
+    /// Palettes loaded by PAL_TRN, picked from by PAL_SET
+    system_palettes: Box<[[u16; 4]; 512]>,
+    /// Palette of each 8x8 cell of the screen
+    attributes: [u8; ATTR_COLUMNS * ATTR_ROWS],
+    mask: u8,
+    transfer: Option<Transfer>,
+    /// 256 SNES 4bpp tiles
+    border_tiles: Box<[u8; 0x2000]>,
+    /// 32x32 tilemap followed by border palettes 4-7
+    border_map: Box<[u8; BORDER_MAP_SIZE]>,
+    /// Composed picture, 0xAARRGGBB
+    output: Box<[u32; SGB_WIDTH * SGB_HEIGHT]>,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        let default = crate::palettes::SGB.map(argb_to_bgr555);
+        Self {
+            select: 0x30,
+            bit: None,
+            ready: false,
+            packet: [0; PACKET_SIZE],
+            command: Vec::with_capacity(PACKET_SIZE * 7),
+            players: 1,
+            player: 0,
+            palettes: [default; 4],
+            system_palettes: Box::new([[0; 4]; 512]),
+            attributes: [0; ATTR_COLUMNS * ATTR_ROWS],
+            mask: MASK_NONE,
+            transfer: None,
+            border_tiles: Box::new([0; 0x2000]),
+            border_map: Box::new([0; BORDER_MAP_SIZE]),
+            output: Box::new([0; SGB_WIDTH * SGB_HEIGHT]),
+        }
+    }
+
+    /// Handle a write to the joypad register
+    pub fn write_joypad(&mut self, value: u8) {
+        let select = value & 0x30;
+        let old = std::mem::replace(&mut self.select, select);
+
+        // With several controllers, releasing P15 moves to the next one
+        if old & 0x20 == 0 && select & 0x20 != 0 && self.players > 1 {
+            self.player = (self.player + 1) & (self.players - 1);
+        }
+
+        match select {
+            0x00 => {
+                // Reset pulse: start a new packet
+                self.bit = Some(0);
+                self.packet = [0; PACKET_SIZE];
+                self.ready = false;
+            }
+            0x30 => self.ready = true,
+            _ if self.ready => {
+                self.ready = false;
+                // P15 low sends a 1, P14 low a 0
+                self.receive_bit(select == 0x10);
+            }
+            _ => {}
+        }
+    }
+
+    /// Low nibble of the joypad register with both select lines high:
+    /// 0xF minus the controller being read
+    pub fn joypad_id(&self) -> u8 {
+        0x0F - self.player
+    }
+
+    /// Index of the controller the game is reading
+    pub fn current_player(&self) -> u8 {
+        self.player
+    }
+
+    /// Number of controllers enabled by MLT_REQ
+    pub fn players(&self) -> u8 {
+        self.players
+    }
+
+    /// The composed 256x224 picture, 0xAARRGGBB
+    pub fn output(&self) -> &[u32] {
+        &self.output[..]
+    }
+
+    fn receive_bit(&mut self, one: bool) {
+        let Some(bit) = self.bit else { return };
+        if bit < 128 {
+            if one {
+                self.packet[bit as usize / 8] |= 1 << (bit % 8);
+            }
+            self.bit = Some(bit + 1);
+            return;
+        }
+        // Stop bit; a 1 here means the packet was garbled
+        self.bit = None;
+        if !one {
+            self.receive_packet();
+        }
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+            self.command = command;
+            self.command.clear();
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            command::PAL01 => self.set_palette_pair(0, 1, data),
+            command::PAL23 => self.set_palette_pair(2, 3, data),
+            command::PAL03 => self.set_palette_pair(0, 3, data),
+            command::PAL12 => self.set_palette_pair(1, 2, data),
+            command::ATTR_BLK => self.attr_blk(data),
+            command::PAL_SET => {
+                for p in 0..4 {
+                    let id = color(data, p) & 0x1FF;
+                    self.palettes[p] = self.system_palettes[id as usize];
+                }
+                self.share_color0(self.palettes[0][0]);
+                if data[9] & 0x40 != 0 {
+                    self.mask = MASK_NONE;
+                }
+            }
+            command::PAL_TRN => self.transfer = Some(Transfer::SystemPalettes),
+            command::MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            command::CHR_TRN => self.transfer = Some(Transfer::BorderTiles(data[1] & 0x01 != 0)),
+            command::PCT_TRN => self.transfer = Some(Transfer::BorderMap),
+            command::MASK_EN => self.mask = data[1] & 0x03,
+            _ => {}
+        }
+    }
+
+    /// PAL01/PAL23/PAL03/PAL12: color 0, then colors 1-3 of each palette
+    fn set_palette_pair(&mut self, a: usize, b: usize, data: &[u8]) {
+        for i in 0..3 {
+            self.palettes[a][i + 1] = color(data, i + 1);
+            self.palettes[b][i + 1] = color(data, i + 4);
+        }
+        self.share_color0(color(data, 0));
+    }
+
+    /// Color 0 is shared by all palettes
+    fn share_color0(&mut self, color0: u16) {
+        for palette in &mut self.palettes {
+            palette[0] = color0;
+        }
+    }
+
+    /// ATTR_BLK: color rectangles inside, on the edge of, or outside
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+            // With only the inside or outside changed, the edge goes with it
+            let edge = match control {
+                0x01 => Some(inside),
+                0x04 => Some(outside),
+                c if c & 0x02 != 0 => Some((set[1] >> 2) & 0x03),
+                _ => None,
+            };
+            let [x1, y1, x2, y2] = [set[2], set[3], set[4], set[5]].map(|v| (v & 0x1F) as usize);
+            for y in 0..ATTR_ROWS {
+                for x in 0..ATTR_COLUMNS {
+                    let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        (control & 0x01 != 0).then_some(inside)
+                    } else if x < x1 || x > x2 || y < y1 || y > y2 {
+                        (control & 0x04 != 0).then_some(outside)
+                    } else {
+                        edge
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTR_COLUMNS + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Called once per frame with the finished Game Boy screen: performs a
+    /// pending transfer and composes the SGB picture
+    pub fn on_frame(&mut self, screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT]) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = read_transfer(screen);
+            match transfer {
+                Transfer::SystemPalettes => {
+                    for (palette, bytes) in self.system_palettes.iter_mut().zip(data.chunks_exact(8)) {
+                        *palette = [0, 1, 2, 3].map(|i| u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]));
+                    }
+                }
+                Transfer::BorderTiles(high) => {
+                    let start = if high { TRANSFER_SIZE } else { 0 };
+                    self.border_tiles[start..start + TRANSFER_SIZE].copy_from_slice(&data);
+                }
+                Transfer::BorderMap => self.border_map.copy_from_slice(&data[..BORDER_MAP_SIZE]),
+            }
+        }
+        if self.mask != MASK_FREEZE {
+            self.render(screen);
+        }
+    }
+
+    fn render(&mut self, screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT]) {
+        let backdrop = bgr555_to_argb(self.palettes[0][0]);
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    MASK_BLACK => 0xFF00_0000,
+                    MASK_COLOR0 => backdrop,
+                    _ => {
+                        let palette = self.attributes[(y / 8) * ATTR_COLUMNS + x / 8] as usize;
+                        let shade = (screen[y * SCREEN_WIDTH + x] & 0x03) as usize;
+                        bgr555_to_argb(self.palettes[palette][shade])
+                    }
+                };
+                self.output[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X] = color;
+            }
+        }
+
+        // The border goes on top; its color 0 shows the screen or backdrop
+        for ty in 0..SGB_HEIGHT / 8 {
+            for tx in 0..SGB_WIDTH / 8 {
+                let i = (ty * 32 + tx) * 2;
+                let entry = u16::from_le_bytes([self.border_map[i], self.border_map[i + 1]]);
+                let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..][..32];
+                let palette = 0x800 + ((entry >> 10) & 0x03) as usize * 32;
+                for row in 0..8 {
+                    let r = if entry & 0x8000 != 0 { 7 - row } else { row };
+                    for col in 0..8 {
+                        let bit = if entry & 0x4000 != 0 { col } else { 7 - col };
+                        let index = (tile[r * 2] >> bit & 1)
+                            | (tile[r * 2 + 1] >> bit & 1) << 1
+                            | (tile[16 + r * 2] >> bit & 1) << 2
+                            | (tile[17 + r * 2] >> bit & 1) << 3;
+                        let (x, y) = (tx * 8 + col, ty * 8 + row);
+                        let in_screen = (SCREEN_X..SCREEN_X + SCREEN_WIDTH).contains(&x)
+                            && (SCREEN_Y..SCREEN_Y + SCREEN_HEIGHT).contains(&y);
+                        let pixel = &mut self.output[y * SGB_WIDTH + x];
+                        if index != 0 {
+                            let c = palette + index as usize * 2;
+                            *pixel = bgr555_to_argb(u16::from_le_bytes([self.border_map[c], self.border_map[c + 1]]));
+                        } else if !in_screen {
+                            *pixel = backdrop;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.select);
+        w.u8(self.bit.map_or(0xFF, |b| b));
+        w.bool(self.ready);
+        w.bytes(&self.packet);
+        w.slice(&self.command);
+        w.u8(self.players);
+        w.u8(self.player);
+        for color in self.palettes.iter().chain(self.system_palettes.iter()).flatten() {
+            w.u16(*color);
+        }
+        w.bytes(&self.attributes);
+        w.u8(self.mask);
+        w.u8(match self.transfer {
+            None => 0,
+            Some(Transfer::SystemPalettes) => 1,
+            Some(Transfer::BorderTiles(false)) => 2,
+            Some(Transfer::BorderTiles(true)) => 3,
+            Some(Transfer::BorderMap) => 4,
+        });
+        w.bytes(&self.border_tiles[..]);
+        w.bytes(&self.border_map[..]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.select = r.u8()? & 0x30;
+        self.bit = match r.u8()? {
+            0xFF => None,
+            b @ 0..=128 => Some(b),
+            _ => return Err(StateError::Invalid("SGB packet bit")),
+        };
+        self.ready = r.bool()?;
+        r.bytes_into(&mut self.packet)?;
+        let command = r.slice()?;
+        if command.len() >= PACKET_SIZE * 7 || command.len() % PACKET_SIZE != 0 {
+            return Err(StateError::Invalid("SGB command length"));
+        }
+        self.command.clear();
+        self.command.extend_from_slice(command);
+        self.players = match r.u8()? {
+            p @ (1 | 2 | 4) => p,
+            _ => return Err(StateError::Invalid("SGB player count")),
+        };
+        self.player = r.u8()? & (self.players - 1);
+        for color in self.palettes.iter_mut().chain(self.system_palettes.iter_mut()).flatten() {
+            *color = r.u16()?;
+        }
+        r.bytes_into(&mut self.attributes)?;
+        for palette in &mut self.attributes {
+            *palette &= 0x03;
+        }
+        self.mask = r.u8()? & 0x03;
+        self.transfer = match r.u8()? {
+            0 => None,
+            1 => Some(Transfer::SystemPalettes),
+            2 => Some(Transfer::BorderTiles(false)),
+            3 => Some(Transfer::BorderTiles(true)),
+            4 => Some(Transfer::BorderMap),
+            _ => return Err(StateError::Invalid("SGB transfer")),
+        };
+        r.bytes_into(&mut self.border_tiles[..])?;
+        r.bytes_into(&mut self.border_map[..])?;
+        Ok(())
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Color `i` of a palette command (little-endian BGR555 from byte 1)
+fn color(data: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]])
+}
+
+/// Read 256 tiles back from the screen, left to right, top to bottom
+fn read_transfer(screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT]) -> Vec<u8> {
+    let mut data = vec![0u8; TRANSFER_SIZE];
+    for (i, byte) in data.iter_mut().enumerate() {
+        let tile = i / 16;
+        let row = (i % 16) / 2;
+        let plane = i % 2;
+        let x = (tile % ATTR_COLUMNS) * 8;
+        let y = (tile / ATTR_COLUMNS) * 8 + row;
+        for col in 0..8 {
+            let shade = screen[y * SCREEN_WIDTH + x + col];
+            *byte |= ((shade >> plane) & 1) << (7 - col);
+        }
+    }
+    data
+}
+
+fn bgr555_to_argb(color: u16) -> u32 {
+    let expand = |v: u16| {
+        let v = (v & 0x1F) as u32;
+        (v << 3) | (v >> 2)
+    };
+    0xFF00_0000 | expand(color) << 16 | expand(color >> 5) << 8 | expand(color >> 10)
+}
+
+fn argb_to_bgr555(argb: u32) -> u16 {
+    let channel = |shift: u32| ((argb >> shift) & 0xFF) as u16 >> 3;
+    channel(16) | channel(8) << 5 | channel(0) << 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(sgb: &mut Sgb, bytes: &[u8]) {
+        for packet in bytes.chunks(PACKET_SIZE) {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            for i in 0..PACKET_SIZE * 8 {
+                let byte = packet.get(i / 8).copied().unwrap_or(0);
+                sgb.write_joypad(if byte >> (i % 8) & 1 != 0 { 0x10 } else { 0x20 });
+                sgb.write_joypad(0x30);
+            }
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+    }
+
+    #[test]
+    fn palettes_and_attributes_color_the_screen() {
+        let mut sgb = Sgb::new();
+        // PAL01: color 0 red, palette 1 color 3 blue
+        let mut pal01 = [0u8; 16];
+        pal01[0] = command::PAL01 << 3 | 1;
+        pal01[1..3].copy_from_slice(&0x001Fu16.to_le_bytes());
+        pal01[13..15].copy_from_slice(&0x7C00u16.to_le_bytes());
+        send(&mut sgb, &pal01);
+        // ATTR_BLK: everything inside (0,0)-(2,2) uses palette 1
+        send(&mut sgb, &[command::ATTR_BLK << 3 | 1, 1, 0x01, 0x01, 0, 0, 2, 2]);
+
+        let mut screen = [0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        screen[8 * SCREEN_WIDTH + 8] = 3;
+        screen[8 * SCREEN_WIDTH + 9] = 0;
+        sgb.on_frame(&screen);
+        let pixel = |x: usize, y: usize| sgb.output()[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X];
+        assert_eq!(pixel(8, 8), 0xFF0000FF);
+        assert_eq!(pixel(9, 8), 0xFFFF0000);
+        // The transparent border shows color 0 as the backdrop
+        assert_eq!(sgb.output()[0], 0xFFFF0000);
+    }
+
+    #[test]
+    fn mlt_req_switches_controllers() {
+        let mut sgb = Sgb::new();
+        assert_eq!(sgb.joypad_id(), 0x0F);
+        send(&mut sgb, &[command::MLT_REQ << 3 | 1, 0x01]);
+        assert_eq!((sgb.players(), sgb.current_player()), (2, 0));
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.joypad_id(), 0x0E);
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.joypad_id(), 0x0F);
+    }
+
+    /// A screen that transfers `data`
+    fn transfer_screen(data: &[u8]) -> [u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        let mut screen = [0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for (i, pair) in data.chunks(2).enumerate() {
+            let (tile, row) = (i / 8, i % 8);
+            let y = (tile / ATTR_COLUMNS) * 8 + row;
+            for col in 0..8 {
+                let bit = |b: u8| (b >> (7 - col)) & 1;
+                let shade = bit(pair[0]) | bit(pair.get(1).copied().unwrap_or(0)) << 1;
+                screen[y * SCREEN_WIDTH + (tile % ATTR_COLUMNS) * 8 + col] = shade;
+            }
+        }
+        screen
+    }
+
+    #[test]
+    fn border_transfer() {
+        let mut sgb = Sgb::new();
+        // Tile 1 is solid color 1
+        let mut tiles = [0u8; TRANSFER_SIZE];
+        for row in 0..8 {
+            tiles[32 + row * 2] = 0xFF;
+        }
+        send(&mut sgb, &[command::CHR_TRN << 3 | 1, 0]);
+        sgb.on_frame(&transfer_screen(&tiles));
+
+        // The top-left map entry uses tile 1 with palette 4, whose color 1
+        // is green
+        let mut map = [0u8; TRANSFER_SIZE];
+        map[0..2].copy_from_slice(&0x1001u16.to_le_bytes());
+        map[0x802..0x804].copy_from_slice(&0x03E0u16.to_le_bytes());
+        send(&mut sgb, &[command::PCT_TRN << 3 | 1]);
+        sgb.on_frame(&transfer_screen(&map));
+
+        assert_eq!(sgb.output()[0], 0xFF00FF00);
+        assert_eq!(sgb.output()[8], bgr555_to_argb(sgb.palettes[0][0]));
+    }
+}
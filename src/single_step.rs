@@ -0,0 +1,406 @@
+//! SingleStepTests CPU harness
+//!
+//! Runs the per-opcode JSON vectors of the SingleStepTests/sm83 project
+//! (<https://github.com/SingleStepTests/sm83>): each file, such as
+//! `00.json` or `cb 7e.json`, holds a thousand random register and memory
+//! states, the state after one instruction, and the bus activity of every
+//! M-cycle in between. The CPU runs on flat 64 KB memory, so the vectors
+//! define everything it sees.
+
+use gb3000::cpu::Cpu;
+use gb3000::memory::{BusAccess, Memory};
+
+/// Result of one opcode's test file
+#[derive(Debug)]
+pub struct OpcodeResult {
+    pub name: String,
+    pub passed: usize,
+    /// One line per failing test
+    pub failures: Vec<String>,
+}
+
+/// Run every test in one JSON file
+pub fn run_file(path: &std::path::Path) -> Result<OpcodeResult, String> {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let tests = Json::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let tests = tests.as_array().ok_or("expected an array of tests")?;
+
+    let mut result = OpcodeResult { name, passed: 0, failures: Vec::new() };
+    for test in tests {
+        match run_case(test) {
+            Ok(()) => result.passed += 1,
+            Err(e) => {
+                let name = test.get("name").and_then(Json::as_str).unwrap_or("?");
+                result.failures.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Run every JSON file in a directory, in file name order
+pub fn run_all(dir: &str) -> Vec<Result<OpcodeResult, String>> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|ext| ext == "json").unwrap_or(false))
+        .collect();
+    paths.sort();
+    paths.iter().map(|p| run_file(p)).collect()
+}
+
+/// Run one test vector
+fn run_case(test: &Json) -> Result<(), String> {
+    let initial = test.get("initial").ok_or("missing initial state")?;
+    let expected = test.get("final").ok_or("missing final state")?;
+
+    let mut cpu = Cpu::new();
+    let mut memory = Memory::new();
+    memory.set_flat(true);
+    cpu.a = reg(initial, "a")? as u8;
+    cpu.f = reg(initial, "f")? as u8;
+    cpu.b = reg(initial, "b")? as u8;
+    cpu.c = reg(initial, "c")? as u8;
+    cpu.d = reg(initial, "d")? as u8;
+    cpu.e = reg(initial, "e")? as u8;
+    cpu.h = reg(initial, "h")? as u8;
+    cpu.l = reg(initial, "l")? as u8;
+    cpu.sp = reg(initial, "sp")? as u16;
+    cpu.pc = reg(initial, "pc")? as u16;
+    cpu.ime = reg(initial, "ime")? != 0;
+    for (addr, value) in ram(initial)? {
+        memory.poke(addr, value);
+    }
+
+    let mut cycles: Vec<Vec<BusAccess>> = Vec::new();
+    cpu.step_mcycle(&mut memory, |memory, _| cycles.push(memory.take_bus_log()));
+    let rest = memory.take_bus_log();
+    if !rest.is_empty() {
+        cycles.push(rest);
+    }
+
+    let registers = [
+        ("a", cpu.a as u64),
+        ("f", cpu.f as u64),
+        ("b", cpu.b as u64),
+        ("c", cpu.c as u64),
+        ("d", cpu.d as u64),
+        ("e", cpu.e as u64),
+        ("h", cpu.h as u64),
+        ("l", cpu.l as u64),
+        ("sp", cpu.sp as u64),
+        ("pc", cpu.pc as u64),
+        ("ime", cpu.ime as u64),
+    ];
+    for (name, actual) in registers {
+        let want = reg(expected, name)?;
+        if actual != want {
+            return Err(format!("{} is {:#X}, expected {:#X}", name, actual, want));
+        }
+    }
+    for (addr, want) in ram(expected)? {
+        let actual = memory.peek(addr);
+        if actual != want {
+            return Err(format!("[{:04X}] is {:#04X}, expected {:#04X}", addr, actual, want));
+        }
+    }
+
+    let want_cycles = test.get("cycles").and_then(Json::as_array).ok_or("missing cycles")?;
+    if cycles.len() != want_cycles.len() {
+        return Err(format!("took {} M-cycles, expected {}", cycles.len(), want_cycles.len()));
+    }
+    for (i, (actual, want)) in cycles.iter().zip(want_cycles).enumerate() {
+        let want = want.as_array().ok_or("bad cycle entry")?;
+        let kind = want.get(2).and_then(Json::as_str).ok_or("bad cycle entry")?;
+        let access = match (kind.starts_with('r'), kind.get(1..2) == Some("w")) {
+            (false, false) => None,
+            (read, _) => Some(BusAccess {
+                addr: want[0].as_u64().ok_or("bad cycle address")? as u16,
+                value: want[1].as_u64().ok_or("bad cycle value")? as u8,
+                write: !read,
+            }),
+        };
+        if actual.as_slice() != access.as_slice() {
+            return Err(format!("M-cycle {}: bus {:?}, expected {:?}", i, actual, access));
+        }
+    }
+    Ok(())
+}
+
+/// A register of a test state
+fn reg(state: &Json, name: &str) -> Result<u64, String> {
+    state
+        .get(name)
+        .and_then(Json::as_u64)
+        .ok_or_else(|| format!("missing register {}", name))
+}
+
+/// The `[address, value]` pairs of a test state
+fn ram(state: &Json) -> Result<Vec<(u16, u8)>, String> {
+    let entries = state.get("ram").and_then(Json::as_array).ok_or("missing ram")?;
+    entries
+        .iter()
+        .map(|entry| {
+            let pair = entry.as_array().filter(|p| p.len() == 2);
+            let pair = pair.and_then(|p| Some((p[0].as_u64()? as u16, p[1].as_u64()? as u8)));
+            pair.ok_or_else(|| "bad ram entry".to_string())
+        })
+        .collect()
+}
+
+/// Just enough JSON for the test vectors
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> String {
+        format!("{} at byte {}", what, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.bytes.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected token"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        if !self.eat(b':') {
+                            return Err(self.error("expected ':'"));
+                        }
+                        fields.push((key, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.error("expected ',' or '}'"));
+                        }
+                    }
+                }
+                Ok(Json::Object(fields))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                    }
+                }
+                Ok(Json::Array(items))
+            }
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => {
+                let start = self.pos;
+                while self
+                    .bytes
+                    .get(self.pos)
+                    .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("");
+                number.parse().map(Json::Number).map_err(|_| self.error("bad number"))
+            }
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.bytes.get(self.pos) != Some(&b'"') {
+            return Err(self.error("expected string"));
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self.bytes.get(self.pos).is_some_and(|&b| b != b'"' && b != b'\\') {
+                self.pos += 1;
+            }
+            out.push_str(&String::from_utf8_lossy(&self.bytes[start..self.pos]));
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    let escaped = self.bytes.get(self.pos + 1).copied();
+                    self.pos += 2;
+                    out.push(match escaped {
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'u') => {
+                            let hex = self.bytes.get(self.pos..self.pos + 4).unwrap_or(b"");
+                            self.pos += 4;
+                            std::str::from_utf8(hex)
+                                .ok()
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .and_then(char::from_u32)
+                                .unwrap_or('?')
+                        }
+                        Some(c) => c as char,
+                        None => return Err(self.error("unterminated string")),
+                    });
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_vectors_with_bus_activity() {
+        // From "77.json" (LD (HL), A) and "00.json", plus a broken copy
+        let tests = Json::parse(
+            r#"[
+  {"name": "77 0000", "initial": {"pc": 4660, "sp": 65534, "a": 66, "b": 0, "c": 0,
+    "d": 0, "e": 0, "f": 176, "h": 192, "l": 16, "ime": 0, "ie": 0,
+    "ram": [[4660, 119], [49168, 0]]},
+   "final": {"a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 176, "h": 192, "l": 16,
+    "pc": 4661, "sp": 65534, "ime": 0, "ram": [[4660, 119], [49168, 66]]},
+   "cycles": [[4660, 119, "r-m"], [49168, 66, "-wm"]]},
+  {"name": "00 0000", "initial": {"pc": 256, "sp": 0, "a": 1, "b": 2, "c": 3, "d": 4,
+    "e": 5, "f": 0, "h": 6, "l": 7, "ime": 1, "ram": [[256, 0]]},
+   "final": {"pc": 257, "sp": 0, "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 0,
+    "h": 6, "l": 7, "ime": 1, "ram": [[256, 0]]},
+   "cycles": [[256, 0, "r-m"]]},
+  {"name": "00 0001", "initial": {"pc": 256, "sp": 0, "a": 1, "b": 2, "c": 3, "d": 4,
+    "e": 5, "f": 0, "h": 6, "l": 7, "ime": 1, "ram": [[256, 0]]},
+   "final": {"pc": 257, "sp": 0, "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 0,
+    "h": 6, "l": 7, "ime": 1, "ram": [[256, 0]]},
+   "cycles": [[256, 0, "r-m"], [null, null, "---"]]}
+]"#,
+        )
+        .unwrap();
+        let tests = tests.as_array().unwrap();
+        assert_eq!(run_case(&tests[0]), Ok(()));
+        assert_eq!(run_case(&tests[1]), Ok(()));
+        assert_eq!(run_case(&tests[2]), Err("took 1 M-cycles, expected 2".to_string()));
+    }
+
+    #[test]
+    fn parses_json() {
+        let json = Json::parse(r#" {"a": [1, -2.5e1, true, null], "b\"A": "x\ny"} "#).unwrap();
+        assert_eq!(
+            json.get("a").and_then(Json::as_array),
+            Some(&[Json::Number(1.0), Json::Number(-25.0), Json::Bool(true), Json::Null][..])
+        );
+        assert_eq!(json.get("b\"A").and_then(Json::as_str), Some("x\ny"));
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("{} x").is_err());
+    }
+
+    #[test]
+    #[ignore] // Needs the vectors in test_roms/sm83/v1; run with: cargo test -- --ignored
+    fn sm83_single_step_tests() {
+        let results = run_all("test_roms/sm83/v1");
+        assert!(!results.is_empty(), "no test vectors found");
+        let mut failed = 0;
+        for result in results {
+            let result = result.unwrap();
+            if let Some(first) = result.failures.first() {
+                println!("{}: {} failed, e.g. {}", result.name, result.failures.len(), first);
+                failed += 1;
+            }
+        }
+        assert_eq!(failed, 0, "{} opcodes failed", failed);
+    }
+}
@@ -0,0 +1,223 @@
+//! Sound viewer window for the desktop UI
+//!
+//! F4 opens a window with a row per sound channel: an oscilloscope of its
+//! recent output, the note and frequency it plays, its envelope volume,
+//! and buttons to mute it or hear it alone. Closing the window unmutes
+//! every channel again.
+
+use crate::ui::{draw_rect, draw_text, fill_rect};
+use gb3000::Emulator;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+
+/// Window buffer size; the window shows it at 2x
+const WIDTH: usize = 400;
+const HEIGHT: usize = 296;
+
+/// Height of each channel's row
+const ROW_HEIGHT: usize = 72;
+/// Oscilloscope size and position within a row
+const SCOPE_X: usize = 8;
+const SCOPE_WIDTH: usize = 240;
+const SCOPE_HEIGHT: usize = 60;
+/// Samples the scope shows across its width (~11 ms)
+const SCOPE_SAMPLES: usize = SCOPE_WIDTH * 2;
+
+const INFO_X: usize = SCOPE_X + SCOPE_WIDTH + 12;
+
+const BACKGROUND: u32 = 0xFF1A1A2E;
+const TEXT: u32 = 0xFFE5E7EB;
+const DIM: u32 = 0xFF6B7280;
+const ACCENT: u32 = 0xFF0EA5E9;
+/// Trace color of each channel
+const TRACE: [u32; 4] = [0xFF4ADE80, 0xFFFACC15, 0xFF38BDF8, 0xFFF472B6];
+
+const NAMES: [&str; 4] = ["1 Pulse", "2 Pulse", "3 Wave", "4 Noise"];
+
+pub struct SoundViewer {
+    window: Window,
+    buffer: Vec<u32>,
+    /// Scratch space for channel history
+    history: Vec<f32>,
+    /// Mouse button state last frame, to detect clicks
+    mouse_was_down: bool,
+}
+
+impl SoundViewer {
+    pub fn open() -> Self {
+        let mut window = Window::new(
+            "GB3000 - Sound",
+            WIDTH,
+            HEIGHT,
+            WindowOptions { scale: Scale::X2, ..WindowOptions::default() },
+        )
+        .expect("Failed to create sound viewer window");
+        window.set_target_fps(0);
+        Self {
+            window,
+            buffer: vec![0; WIDTH * HEIGHT],
+            history: vec![0.0; SCOPE_SAMPLES * 2],
+            mouse_was_down: false,
+        }
+    }
+
+    /// Handle mute/solo clicks and redraw
+    ///
+    /// Returns false once the window was closed, with its close button or
+    /// F4.
+    pub fn update(&mut self, emulator: &mut Emulator) -> bool {
+        if !self.window.is_open() || self.window.is_key_pressed(Key::F4, KeyRepeat::No) {
+            return false;
+        }
+        let mouse = self.window.get_mouse_pos(MouseMode::Discard).map(|(x, y)| (x as usize, y as usize));
+        let down = self.window.get_mouse_down(MouseButton::Left);
+        let click = mouse.filter(|_| down && !self.mouse_was_down);
+        self.mouse_was_down = down;
+
+        fill_rect(&mut self.buffer, WIDTH, 0, 0, WIDTH, HEIGHT, BACKGROUND);
+        let outputs = emulator.channel_outputs();
+        for (channel, output) in outputs.iter().enumerate() {
+            let y = 4 + channel * ROW_HEIGHT;
+            emulator.channel_history(channel, &mut self.history);
+            self.draw_scope(channel, y);
+
+            let buffer = &mut self.buffer;
+            draw_text(buffer, WIDTH, INFO_X, y + 2, NAMES[channel], TRACE[channel]);
+            if output.active {
+                let note = if channel == 3 { "--".to_string() } else { note_name(output.frequency) };
+                draw_text(buffer, WIDTH, INFO_X, y + 16, &note, TEXT);
+                draw_text(buffer, WIDTH, INFO_X + 40, y + 16, &format!("{:.0} Hz", output.frequency), DIM);
+            } else {
+                draw_text(buffer, WIDTH, INFO_X, y + 16, "off", DIM);
+            }
+
+            // Envelope volume
+            let bar_w = 80;
+            fill_rect(buffer, WIDTH, INFO_X, y + 30, bar_w, 6, 0xFF1F2937);
+            fill_rect(buffer, WIDTH, INFO_X, y + 30, bar_w * output.volume as usize / 15, 6, TRACE[channel]);
+            draw_text(buffer, WIDTH, INFO_X + bar_w + 6, y + 29, &format!("{:2}", output.volume), DIM);
+
+            // Mute and solo
+            let muted = emulator.channel_muted(channel);
+            let solo = is_solo(emulator, channel);
+            for (i, (label, lit)) in [("Mute", muted), ("Solo", solo)].into_iter().enumerate() {
+                let (bx, by, w, h) = (INFO_X + i * 56, y + 44, 48, 16);
+                let inside = |(mx, my): (usize, usize)| (bx..bx + w).contains(&mx) && (by..by + h).contains(&my);
+                let color = if lit {
+                    ACCENT
+                } else if mouse.is_some_and(inside) {
+                    0xFF374151
+                } else {
+                    0xFF1F2937
+                };
+                fill_rect(buffer, WIDTH, bx, by, w, h, color);
+                draw_rect(buffer, WIDTH, bx, by, w, h, 0xFF4B5563);
+                draw_text(buffer, WIDTH, bx + 8, by + 4, label, TEXT);
+                if click.is_some_and(inside) {
+                    match i {
+                        0 => emulator.set_channel_muted(channel, !muted),
+                        _ => set_solo(emulator, channel, !solo),
+                    }
+                }
+            }
+        }
+
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .expect("Failed to update sound viewer window");
+        true
+    }
+
+    /// Draw `channel`'s recent output, lined up on a rising edge so a
+    /// steady tone holds still
+    fn draw_scope(&mut self, channel: usize, y: usize) {
+        let buffer = &mut self.buffer;
+        fill_rect(buffer, WIDTH, SCOPE_X, y, SCOPE_WIDTH, SCOPE_HEIGHT, 0xFF111122);
+        draw_rect(buffer, WIDTH, SCOPE_X, y, SCOPE_WIDTH, SCOPE_HEIGHT, 0xFF374151);
+
+        let start = trigger(&self.history, SCOPE_SAMPLES);
+        let samples = &self.history[start..start + SCOPE_SAMPLES];
+        let level_y = |level: f32| y + SCOPE_HEIGHT - 2 - (level.clamp(0.0, 1.0) * (SCOPE_HEIGHT - 4) as f32) as usize;
+        let mut last = level_y(samples[0]);
+        for x in 0..SCOPE_WIDTH {
+            let py = level_y(samples[x * SCOPE_SAMPLES / SCOPE_WIDTH]);
+            // Join steps with a vertical line
+            for line_y in py.min(last)..=py.max(last) {
+                buffer[line_y * WIDTH + SCOPE_X + x] = TRACE[channel];
+            }
+            last = py;
+        }
+    }
+}
+
+/// Where to start showing `window` of `samples`: the first rising edge
+/// through the middle of their range that leaves enough after it, or
+/// the latest samples if there is none
+fn trigger(samples: &[f32], window: usize) -> usize {
+    let latest = samples.len() - window;
+    let (min, max) = samples.iter().fold((f32::MAX, f32::MIN), |(lo, hi), &s| (lo.min(s), hi.max(s)));
+    if max - min < 1e-3 {
+        return latest;
+    }
+    let mid = (min + max) / 2.0;
+    (1..=latest)
+        .find(|&i| samples[i - 1] < mid && samples[i] >= mid)
+        .unwrap_or(latest)
+}
+
+/// Nearest note to `frequency`, e.g. "A4" or "C#5"
+fn note_name(frequency: f32) -> String {
+    const NOTES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    if frequency <= 0.0 {
+        return "--".to_string();
+    }
+    let midi = (69.0 + 12.0 * (frequency / 440.0).log2()).round() as i32;
+    format!("{}{}", NOTES[midi.rem_euclid(12) as usize], midi.div_euclid(12) - 1)
+}
+
+/// Whether `channel` is the only one not muted
+fn is_solo(emulator: &Emulator, channel: usize) -> bool {
+    (0..4).all(|c| emulator.channel_muted(c) == (c != channel))
+}
+
+/// Hear only `channel`, or everything again
+fn set_solo(emulator: &mut Emulator, channel: usize, solo: bool) {
+    for c in 0..4 {
+        emulator.set_channel_muted(c, solo && c != channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_notes() {
+        assert_eq!(note_name(440.0), "A4");
+        assert_eq!(note_name(261.63), "C4");
+        assert_eq!(note_name(131072.0 / 298.0), "A4");
+        assert_eq!(note_name(277.18), "C#4");
+        assert_eq!(note_name(0.0), "--");
+    }
+
+    #[test]
+    fn scope_triggers_on_a_rising_edge() {
+        // A square wave rising at 5, 25, 45, ...
+        let samples: Vec<f32> = (0..100).map(|i| if (i + 15) % 20 < 10 { 1.0 } else { 0.0 }).collect();
+        assert_eq!(trigger(&samples, 50), 5);
+        // Silence or no edge early enough shows the latest samples
+        assert_eq!(trigger(&[0.5; 100], 50), 50);
+        assert_eq!(trigger(&samples, 96), 4);
+    }
+
+    #[test]
+    fn solo_mutes_the_others() {
+        let mut emulator = Emulator::new();
+        set_solo(&mut emulator, 2, true);
+        assert!(is_solo(&emulator, 2));
+        assert!(emulator.channel_muted(0) && !emulator.channel_muted(2));
+        emulator.set_channel_muted(1, false);
+        assert!(!is_solo(&emulator, 2));
+        set_solo(&mut emulator, 2, false);
+        assert!((0..4).all(|c| !emulator.channel_muted(c)));
+    }
+}
@@ -0,0 +1,217 @@
+//! Save state serialization for the Game Boy emulator.
+//!
+//! A save state is a flat little-endian byte stream:
+//! - 4-byte magic ("GB3S") and a u32 format version
+//! - each component's fields, written in a fixed order by its `save_state`
+//!
+//! The ROM itself is not included; a state can only be loaded into an
+//! emulator that has the same ROM loaded (checked via a ROM hash).
+
+/// Magic bytes at the start of every save state
+const MAGIC: &[u8; 4] = b"GB3S";
+
+/// Current save state format version
+pub const STATE_VERSION: u32 = 2;
+
+/// Errors that can occur while loading a save state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// Data does not start with the save state magic
+    BadMagic,
+    /// Save state was written by an incompatible format version
+    UnsupportedVersion(u32),
+    /// Save state was made with a different ROM
+    RomMismatch,
+    /// Data ended before all fields were read
+    Truncated,
+    /// A field contained a value that is out of range
+    Invalid(&'static str),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a GB3000 save state"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported save state version {}", v),
+            StateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "save state data is truncated"),
+            StateError::Invalid(field) => write!(f, "invalid value for {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Little-endian writer for save state data
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Create a writer and emit the state header
+    pub fn new() -> Self {
+        Self::with_buffer(Vec::with_capacity(0x14000))
+    }
+
+    /// Create a writer that reuses `buf`'s allocation
+    pub fn with_buffer(mut buf: Vec<u8>) -> Self {
+        buf.clear();
+        let mut w = Self { buf };
+        w.bytes(MAGIC);
+        w.u32(STATE_VERSION);
+        w
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.buf.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.u32(v.to_bits());
+    }
+
+    /// Write raw bytes (fixed length, known to the reader)
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    /// Write a length-prefixed byte slice
+    pub fn slice(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.bytes(v);
+    }
+
+    /// Finish writing and return the serialized state
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Little-endian reader for save state data
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Create a reader and validate the state header
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut r = Self { data, pos: 0 };
+        if r.bytes(4).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u32()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(r)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let b = self.bytes(8)?;
+        let mut arr = [0u8; 8];
+        arr.copy_from_slice(b);
+        Ok(u64::from_le_bytes(arr))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /// Read `len` raw bytes
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        if end > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let out = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    /// Read raw bytes into a fixed-size buffer
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        let src = self.bytes(out.len())?;
+        out.copy_from_slice(src);
+        Ok(())
+    }
+
+    /// Read a length-prefixed byte slice
+    pub fn slice(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_primitives() {
+        let mut w = StateWriter::new();
+        w.u8(0x12);
+        w.bool(true);
+        w.u16(0xBEEF);
+        w.u32(0xDEADBEEF);
+        w.f32(1.5);
+        w.slice(&[1, 2, 3]);
+        let data = w.finish();
+
+        let mut r = StateReader::new(&data).unwrap();
+        assert_eq!(r.u8().unwrap(), 0x12);
+        assert!(r.bool().unwrap());
+        assert_eq!(r.u16().unwrap(), 0xBEEF);
+        assert_eq!(r.u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(r.f32().unwrap(), 1.5);
+        assert_eq!(r.slice().unwrap(), &[1, 2, 3]);
+        assert_eq!(r.u8(), Err(StateError::Truncated));
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        assert_eq!(StateReader::new(b"nope").unwrap_err(), StateError::BadMagic);
+        let mut data = StateWriter::new().finish();
+        data[4] = 0xFF;
+        assert!(matches!(
+            StateReader::new(&data).unwrap_err(),
+            StateError::UnsupportedVersion(_)
+        ));
+    }
+}
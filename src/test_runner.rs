@@ -1,24 +1,49 @@
-/// Automated test runner for Game Boy test ROMs
-///
-/// Supports multiple test ROM formats:
-/// 1. Blargg tests - output via serial port, "Passed"/"Failed" in output
-/// 2. Mooneye tests - execute LD B,B when done, Fibonacci registers on success
-
-use gb3000::cpu::{Cpu, GbModel};
-use gb3000::memory::Memory;
-use gb3000::ppu::Ppu;
-use gb3000::timer::Timer;
+//! Automated test runner for Game Boy test ROMs
+//!
+//! Supports multiple test ROM formats:
+//! 1. Blargg tests - output via serial port, "Passed"/"Failed" in output
+//! 2. Mooneye tests - execute LD B,B when done, with Fibonacci numbers in
+//!    B-L on success or 0x42 in each on failure
+//! 3. Screenshot tests (Mealybug Tearoom, dmg-acid2, cgb-acid2) - compared
+//!    against a reference PNG once they execute LD B,B or the picture stops
+//!    changing
+
+use crate::capture;
+use gb3000::{CpuState, Emulator, GbModel, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::path::{Path, PathBuf};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Maximum cycles to run a test before timing out
 const MAX_CYCLES: u64 = 500_000_000; // ~120 seconds of emulated time
 
-/// Mooneye Fibonacci success signature
-const MOONEYE_B: u8 = 3;
-const MOONEYE_C: u8 = 5;
-const MOONEYE_D: u8 = 8;
-const MOONEYE_E: u8 = 13;
-const MOONEYE_H: u8 = 21;
-const MOONEYE_L: u8 = 34;
+/// Idle cycles after which a test that has stopped reporting counts as hung
+const HANG_CYCLES: u64 = 4_194_304 * 10; // 10 seconds of emulated time
+
+/// Frames to run a screenshot test before comparing anyway
+const MAX_SCREENSHOT_FRAMES: u32 = 600;
+
+/// Identical frames in a row after which a screenshot test counts as done
+const STABLE_FRAMES: u32 = 30;
+
+/// Mooneye Fibonacci success signature in B, C, D, E, H and L
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// Mooneye failure signature, 0x42 in all six registers
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+/// Mooneye's verdict from the registers at an LD B,B: `None` unless they
+/// hold one of its signatures, as Blargg's tests and games run LD B,B as
+/// an ordinary instruction
+fn mooneye_result(cpu: &CpuState) -> Option<bool> {
+    match [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l] {
+        MOONEYE_PASS => Some(true),
+        MOONEYE_FAIL => Some(false),
+        _ => None,
+    }
+}
 
 /// Result of running a test
 #[derive(Debug)]
@@ -28,14 +53,23 @@ pub struct TestResult {
     pub output: String,
     pub cycles: u64,
     pub error: Option<String>,
+    /// Wall-clock time the test took
+    pub duration: Duration,
+    /// Directory of the ROM relative to the tested path, "" at the top
+    pub suite: String,
 }
 
-/// Run a single test ROM and return the result
-pub fn run_test(rom_path: &str) -> TestResult {
-    let name = std::path::Path::new(rom_path)
+/// File name of a test ROM
+fn test_name(rom_path: &str) -> String {
+    Path::new(rom_path)
         .file_name()
         .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| rom_path.to_string());
+        .unwrap_or_else(|| rom_path.to_string())
+}
+
+/// Run a single test ROM and return the result
+pub fn run_test(rom_path: &str) -> TestResult {
+    let name = test_name(rom_path);
 
     // Load ROM
     let rom = match std::fs::read(rom_path) {
@@ -46,296 +80,770 @@ pub fn run_test(rom_path: &str) -> TestResult {
                 passed: false,
                 output: String::new(),
                 cycles: 0,
+                duration: Duration::ZERO,
+                suite: String::new(),
                 error: Some(format!("Failed to load ROM: {}", e)),
             };
         }
     };
 
     // Detect hardware model from filename
-    let model = GbModel::from_filename(rom_path);
-    let model_str = model.to_string();
-
-    // Initialize emulator components
-    let mut cpu = Cpu::new();
-    let mut memory = Memory::new();
-    let mut ppu = Ppu::new();
-    let mut timer = Timer::new();
+    run_rom(name, &rom, GbModel::from_filename(rom_path))
+}
 
-    memory.load_rom(&rom);
-    cpu.reset_for_model(model);
-    timer.reset_for_model(&model_str);
+/// Run a test ROM on the library emulator until it reports a result
+fn run_rom(name: String, rom: &[u8], model: GbModel) -> TestResult {
+    let mut emu = Emulator::new();
+    emu.load_rom(rom);
+    emu.reset_for_model(model);
+    emu.set_audio_enabled(false);
+    emu.set_hang_detection(Some(HANG_CYCLES));
+    // Mooneye tests execute LD B, B when done, with the result in B-L
+    let (mooneye_tx, mooneye_rx) = std::sync::mpsc::channel();
+    emu.set_debug_opcode_hook(0x40, move |_, cpu| {
+        if let Some(passed) = mooneye_result(cpu) {
+            let _ = mooneye_tx.send((passed, *cpu));
+        }
+    });
 
-    // Serial output buffer
     let mut serial_output = String::new();
-    let mut total_cycles: u64 = 0;
-    
-    // Track previous PC for Mooneye LD B,B detection
-    let mut prev_pc: u16 = 0;
+    let finish = |emu: &Emulator, output: String, passed: bool, error: Option<String>| TestResult {
+        name: name.clone(),
+        passed,
+        output,
+        cycles: emu.total_cycles(),
+        duration: Duration::ZERO,
+        suite: String::new(),
+        error,
+    };
 
     // Run the test
     loop {
         // Check for timeout
-        if total_cycles >= MAX_CYCLES {
-            return TestResult {
-                name,
-                passed: false,
-                output: serial_output,
-                cycles: total_cycles,
-                error: Some("Test timed out".to_string()),
-            };
+        if emu.total_cycles() >= MAX_CYCLES {
+            return finish(&emu, serial_output, false, Some("Test timed out".to_string()));
         }
 
-        // Handle interrupts (returns cycles consumed, 20 if interrupt was serviced)
-        let intr_cycles = handle_interrupts(&mut cpu, &mut memory);
-        if intr_cycles > 0 {
-            total_cycles += intr_cycles as u64;
-            // Tick components during interrupt dispatch in 4-cycle chunks
-            for _ in 0..(intr_cycles / 4) {
-                timer.tick(&mut memory, 4);
-                ppu.tick(&mut memory, 4);
-                for _ in 0..4 {
-                    memory.tick_dma();
-                }
-            }
+        if let Err(e) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| emu.step())) {
+            let msg = if let Some(s) = e.downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = e.downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "Unknown panic".to_string()
+            };
+            return finish(&emu, serial_output, false, Some(format!("CPU panic: {}", msg)));
         }
 
-        // Save PC before execution
-        prev_pc = cpu.pc;
-
-        // Execute one instruction with M-cycle accurate timing
-        // The closure is called after each M-cycle (4 T-cycles)
-        let cycles = {
-            let timer_ref = &mut timer;
-            let ppu_ref = &mut ppu;
-            
-            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                cpu.step_mcycle(&mut memory, |mem, tcycles| {
-                    // Handle PPU register writes
-                    if mem.stat_written {
-                        mem.stat_written = false;
-                        ppu_ref.on_stat_write(mem);
-                    }
-                    if mem.lyc_written {
-                        mem.lyc_written = false;
-                        ppu_ref.on_lyc_write(mem);
-                    }
-                    timer_ref.tick(mem, tcycles);
-                    ppu_ref.tick(mem, tcycles);
-                    for _ in 0..tcycles {
-                        mem.tick_dma();
-                    }
-                })
-            })) {
-                Ok(c) => c,
-                Err(e) => {
-                    let msg = if let Some(s) = e.downcast_ref::<&str>() {
-                        s.to_string()
-                    } else if let Some(s) = e.downcast_ref::<String>() {
-                        s.clone()
-                    } else {
-                        "Unknown panic".to_string()
-                    };
-                    return TestResult {
-                        name,
-                        passed: false,
-                        output: serial_output,
-                        cycles: total_cycles,
-                        error: Some(format!("CPU panic: {}", msg)),
-                    };
-                }
-            }
-        };
-
-        total_cycles += cycles as u64;
-
-        // Check for Mooneye test completion (LD B, B = 0x40 in an infinite loop)
-        // Mooneye tests end with: LD B, B followed by JR -2 (infinite loop)
-        // So we check if the current instruction is LD B,B and the next is JR -2
-        let executed_opcode = memory.read_byte(prev_pc);
-        if executed_opcode == 0x40 {
-            // Check if followed by JR -2 (0x18 0xFE) which is the Mooneye termination pattern
-            let next_opcode = memory.read_byte(prev_pc.wrapping_add(1));
-            let jr_offset = memory.read_byte(prev_pc.wrapping_add(2));
-            
-            if next_opcode == 0x18 && jr_offset == 0xFE {
-                // This is the Mooneye termination pattern
-                let is_fibonacci = cpu.b == MOONEYE_B
-                    && cpu.c == MOONEYE_C
-                    && cpu.d == MOONEYE_D
-                    && cpu.e == MOONEYE_E
-                    && cpu.h == MOONEYE_H
-                    && cpu.l == MOONEYE_L;
-                
-                return TestResult {
-                    name,
-                    passed: is_fibonacci,
-                    output: serial_output,
-                    cycles: total_cycles,
-                    error: if !is_fibonacci {
-                        Some(format!(
-                            "Mooneye: B={} C={} D={} E={} H={} L={}",
-                            cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l
-                        ))
-                    } else {
-                        None
-                    },
-                };
-            }
+        // Check for Mooneye test completion
+        if let Ok((passed, cpu)) = mooneye_rx.try_recv() {
+            let error = (!passed).then(|| {
+                format!(
+                    "Mooneye: B={} C={} D={} E={} H={} L={}",
+                    cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l
+                )
+            });
+            return finish(&emu, serial_output, passed, error);
         }
 
-        // Timer, PPU, and DMA are now ticked inside step_mcycle for M-cycle accuracy
-
         // Check serial output
-        // When SC (0xFF02) has bit 7 set and bit 0 set, a byte is being sent
-        let sc = memory.data[0xFF02];
-        if sc == 0x81 {
-            let sb = memory.data[0xFF01];
-            serial_output.push(sb as char);
-            memory.data[0xFF02] = 0; // Clear transfer flag
+        let sent = emu.take_serial_output();
+        if !sent.is_empty() {
+            serial_output.extend(sent.iter().map(|&b| b as char));
 
             // Check for test completion
             if serial_output.contains("Passed") {
-                return TestResult {
-                    name,
-                    passed: true,
-                    output: serial_output,
-                    cycles: total_cycles,
-                    error: None,
-                };
+                return finish(&emu, serial_output, true, None);
             }
             if serial_output.contains("Failed") {
-                return TestResult {
-                    name,
-                    passed: false,
-                    output: serial_output,
-                    cycles: total_cycles,
-                    error: None,
-                };
+                return finish(&emu, serial_output, false, None);
             }
         }
 
         // Also check memory signature for test completion
         // Blargg tests write 0 to 0xA000 on success, non-zero on failure
         // And they set specific patterns when done (signature DE B0 61 at 0xA001-0xA003)
-        // Must read through read_byte to properly access external RAM
-        if memory.read_byte(0xA001) == 0xDE
-            && memory.read_byte(0xA002) == 0xB0
-            && memory.read_byte(0xA003) == 0x61
-        {
-            let status = memory.read_byte(0xA000);
-            return TestResult {
-                name,
-                passed: status == 0,
-                output: serial_output,
-                cycles: total_cycles,
-                error: if status != 0 {
-                    Some(format!("Test failed with status: {}", status))
-                } else {
-                    None
-                },
-            };
+        if emu.peek(0xA001) == 0xDE && emu.peek(0xA002) == 0xB0 && emu.peek(0xA003) == 0x61 {
+            let status = emu.peek(0xA000);
+            let error = (status != 0).then(|| format!("Test failed with status: {}", status));
+            return finish(&emu, serial_output, status == 0, error);
+        }
+
+        // A crashed test spins without touching I/O; no need to wait it out
+        if emu.hang_detected() {
+            let error = format!("Test hung at PC {:04X}", emu.cpu_state().pc);
+            return finish(&emu, serial_output, false, Some(error));
         }
     }
 }
 
-/// Handle pending interrupts. Returns cycles consumed (20 if interrupt was serviced, 0 otherwise)
-fn handle_interrupts(cpu: &mut Cpu, memory: &mut Memory) -> u32 {
-    // Wake from HALT if any interrupt is pending
-    if memory.pending_interrupts() != 0 {
-        cpu.halted = false;
-    }
-
-    if !cpu.ime {
-        return 0;
-    }
-
-    let pending = memory.pending_interrupts();
-    if pending == 0 {
-        return 0;
-    }
-
-    cpu.ime = false;
-
-    // Push PC onto stack
-    let pc = cpu.pc;
-    cpu.sp = cpu.sp.wrapping_sub(1);
-    memory.data[cpu.sp as usize] = (pc >> 8) as u8;
-    cpu.sp = cpu.sp.wrapping_sub(1);
-    memory.data[cpu.sp as usize] = pc as u8;
-
-    // Jump to interrupt handler (priority order)
-    use gb3000::memory::interrupts;
-    if pending & interrupts::VBLANK != 0 {
-        memory.clear_interrupt(interrupts::VBLANK);
-        cpu.pc = 0x0040;
-    } else if pending & interrupts::LCD_STAT != 0 {
-        memory.clear_interrupt(interrupts::LCD_STAT);
-        cpu.pc = 0x0048;
-    } else if pending & interrupts::TIMER != 0 {
-        memory.clear_interrupt(interrupts::TIMER);
-        cpu.pc = 0x0050;
-    } else if pending & interrupts::SERIAL != 0 {
-        memory.clear_interrupt(interrupts::SERIAL);
-        cpu.pc = 0x0058;
-    } else if pending & interrupts::JOYPAD != 0 {
-        memory.clear_interrupt(interrupts::JOYPAD);
-        cpu.pc = 0x0060;
-    }
-    
-    // Interrupt dispatch takes 5 M-cycles = 20 T-cycles
-    20
+/// Run a screenshot test ROM and compare its final frame with `reference`
+///
+/// The ROM runs until it executes LD B,B, the Mealybug Tearoom "done"
+/// signal, and then one more whole frame; ROMs without the signal run
+/// until the picture has been still for [`STABLE_FRAMES`] frames. Shades
+/// are compared, so the reference may use any grayscale palette.
+pub fn run_screenshot_test(rom_path: &str, reference: &Path) -> TestResult {
+    let name = test_name(rom_path);
+    let fail = |cycles, error: String| TestResult {
+        name: name.clone(),
+        passed: false,
+        output: String::new(),
+        cycles,
+        error: Some(error),
+        duration: Duration::ZERO,
+        suite: String::new(),
+    };
+
+    let rom = match std::fs::read(rom_path) {
+        Ok(data) => data,
+        Err(e) => return fail(0, format!("Failed to load ROM: {}", e)),
+    };
+    let expected = match capture::read_png(reference) {
+        Ok((rgba, w, h)) if (w as usize, h as usize) == (SCREEN_WIDTH, SCREEN_HEIGHT) => rgba,
+        Ok((_, w, h)) => return fail(0, format!("Reference image is {}x{}, not 160x144", w, h)),
+        Err(e) => return fail(0, e),
+    };
+
+    let mut emu = Emulator::new();
+    emu.load_rom(&rom);
+    emu.reset_for_model(GbModel::from_filename(rom_path));
+    emu.set_audio_enabled(false);
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    // Screenshot ROMs use any LD B,B as their done signal
+    emu.set_debug_opcode_hook(0x40, move |_, _| flag.store(true, Ordering::Relaxed));
+
+    let mut last_frame = *emu.framebuffer();
+    let mut stable = 0;
+    for _ in 0..MAX_SCREENSHOT_FRAMES {
+        emu.run_frame();
+        if done.load(Ordering::Relaxed) {
+            // Let the frame the signal came in be replaced by a whole one
+            emu.run_frame();
+            break;
+        }
+        if *emu.framebuffer() == last_frame {
+            stable += 1;
+            if stable >= STABLE_FRAMES {
+                break;
+            }
+        } else {
+            stable = 0;
+            last_frame = *emu.framebuffer();
+        }
+    }
+
+    let mut actual = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+    emu.framebuffer_gray8(&mut actual);
+    match frame_mismatch(&actual, &expected) {
+        None => TestResult {
+            name,
+            passed: true,
+            output: String::new(),
+            cycles: emu.total_cycles(),
+            duration: Duration::ZERO,
+            suite: String::new(),
+            error: None,
+        },
+        Some(error) => fail(emu.total_cycles(), error),
+    }
 }
 
-/// Run all tests in a directory or a single test file
-pub fn run_all_tests(test_path: &str) -> Vec<TestResult> {
-    let mut results = Vec::new();
+/// Describe how a grayscale frame differs from an RGBA reference, if it
+/// does
+fn frame_mismatch(actual: &[u8], expected_rgba: &[u8]) -> Option<String> {
+    // Darkest shade 3 through lightest shade 0
+    let shade = |gray: u32| 3 - ((gray * 3 + 127) / 255) as u8;
+    let mut mismatches = actual.iter().zip(expected_rgba.chunks(4)).enumerate().filter_map(|(i, (&a, p))| {
+        let want = shade((p[0] as u32 + p[1] as u32 + p[2] as u32) / 3);
+        let got = shade(a as u32);
+        (got != want).then_some((i, got, want))
+    });
+    let (first, got, want) = mismatches.next()?;
+    Some(format!(
+        "{} pixels differ, first at ({}, {}): shade {}, expected {}",
+        mismatches.count() + 1,
+        first % SCREEN_WIDTH,
+        first / SCREEN_WIDTH,
+        got,
+        want
+    ))
+}
 
-    let path = std::path::Path::new(test_path);
-    
-    // If it's a single .gb file, run just that test
-    if path.is_file() && path.extension().map(|e| e == "gb").unwrap_or(false) {
-        let result = run_test(test_path);
+/// Reference image for a screenshot test ROM: `<rom name>.png` in
+/// `reference_dir`, or next to the ROM
+fn reference_image(rom: &Path, reference_dir: Option<&Path>) -> PathBuf {
+    let file = rom.with_extension("png");
+    match (reference_dir, file.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => file,
+    }
+}
+
+/// Run screenshot tests for a ROM or every ROM in a directory
+pub fn run_all_screenshot_tests(test_path: &str, reference_dir: Option<&str>) -> Vec<TestResult> {
+    let reference_dir = reference_dir.map(Path::new);
+    let mut results = Vec::new();
+    for rom in test_roms(Path::new(test_path)) {
+        let start = Instant::now();
+        let mut result = run_screenshot_test(&rom.to_string_lossy(), &reference_image(&rom, reference_dir));
+        result.duration = start.elapsed();
+        print_result(&result);
         results.push(result);
-        return results;
-    }
-
-    let paths: Vec<_> = std::fs::read_dir(test_path)
-        .into_iter()
-        .flatten()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.path()
-                .extension()
-                .map(|ext| ext == "gb")
-                .unwrap_or(false)
-        })
-        .collect();
-
-    for entry in paths {
-        let path = entry.path();
-        println!("Running test: {}", path.display());
-        let result = run_test(path.to_str().unwrap());
-        println!(
-            "  {} - {} cycles",
-            if result.passed { "PASSED ✓" } else { "FAILED ✗" },
-            result.cycles
-        );
-        if let Some(ref err) = result.error {
-            println!("  Error: {}", err);
+    }
+    results
+}
+
+/// Reference image the acid2 repositories name for a ROM
+fn acid2_reference(rom: &Path) -> &'static str {
+    let stem = rom.file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if stem.starts_with("cgb") {
+        "reference-cgb.png"
+    } else {
+        "reference-dmg.png"
+    }
+}
+
+/// Whether a ROM is one of the dmg-acid2/cgb-acid2 tests
+fn is_acid2(rom: &Path) -> bool {
+    rom.file_stem().is_some_and(|s| s.to_string_lossy().to_lowercase().contains("acid2"))
+}
+
+/// Run a test ROM the way its suite reports results
+///
+/// ROMs with a `<rom name>.png` next to them, and the acid2 tests, are
+/// screenshot tests. acid2 also finds the reference under the name its
+/// repository uses (`reference-dmg.png` or `reference-cgb.png`, next to
+/// the ROM or in `img/`). Everything else reports through serial output
+/// or registers.
+pub fn run_suite_test(rom: &Path) -> TestResult {
+    let rom_path = rom.to_string_lossy();
+    let own = reference_image(rom, None);
+    if own.is_file() {
+        return run_screenshot_test(&rom_path, &own);
+    }
+    if is_acid2(rom) {
+        let dir = rom.parent().unwrap_or(Path::new("."));
+        let name = acid2_reference(rom);
+        let found = [dir.join(name), dir.join("img").join(name)].into_iter().find(|p| p.is_file());
+        return match found {
+            Some(reference) => run_screenshot_test(&rom_path, &reference),
+            None => TestResult {
+                name: test_name(&rom_path),
+                passed: false,
+                output: String::new(),
+                cycles: 0,
+                duration: Duration::ZERO,
+                suite: String::new(),
+                error: Some(format!("No reference image: put {} next to the ROM", name)),
+            },
+        };
+    }
+    run_test(&rom_path)
+}
+
+/// Test ROMs (.gb and .gbc) at a path: the file itself, or every ROM in
+/// the directory tree in path order
+pub fn test_roms(path: &Path) -> Vec<PathBuf> {
+    let is_rom = |p: &Path| p.extension().map(|ext| ext == "gb" || ext == "gbc").unwrap_or(false);
+    if path.is_file() {
+        return if is_rom(path) { vec![path.to_path_buf()] } else { Vec::new() };
+    }
+    let mut roms = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_rom(&path) {
+                roms.push(path);
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+/// Which discovered test ROMs to run
+#[derive(Debug, Clone, Default)]
+pub struct TestFilter {
+    /// Globs a ROM must match one of, if any are given
+    pub include: Vec<String>,
+    /// Globs that skip a ROM
+    pub exclude: Vec<String>,
+    /// Only run ROMs meant for this model
+    pub model: Option<GbModel>,
+}
+
+impl TestFilter {
+    /// Whether to run the ROM at `relative`, its path below the tested
+    /// directory with `/` separators
+    ///
+    /// Globs containing a `/` match the whole path, others just the file
+    /// name. ROMs meant only for hardware GB3000 doesn't emulate (the GBA
+    /// and the first CGB revision) are always skipped.
+    pub fn matches(&self, relative: &str) -> bool {
+        let file = relative.rsplit('/').next().unwrap_or(relative);
+        let hit = |pattern: &String| {
+            let text = if pattern.contains('/') { relative } else { file };
+            glob_match(pattern.as_bytes(), text.as_bytes())
+        };
+        if !self.include.is_empty() && !self.include.iter().any(hit) {
+            return false;
         }
-        if !result.output.is_empty() {
-            println!("  Output: {}", result.output.trim());
+        if self.exclude.iter().any(hit) {
+            return false;
+        }
+        let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+        match target_models(stem) {
+            Some(models) => self.model.map_or(!models.is_empty(), |m| models.contains(&m)),
+            None => true,
         }
-        results.push(result);
     }
+}
 
-    results
+/// Match `*` (within a path component), `**` (across components) and `?`
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let end = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=end).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => text.first().is_some_and(|&c| c != b'/') && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Models a ROM is meant for, from a Mooneye-style name suffix:
+/// `-dmgABCmgb` lists models, `-GS` hardware groups (G: DMG/MGB, S: SGB,
+/// C: CGB, A: AGB). `None` when the name has no such suffix.
+fn target_models(stem: &str) -> Option<Vec<GbModel>> {
+    let (_, suffix) = stem.rsplit_once('-')?;
+    if suffix.bytes().all(|c| b"GSCA".contains(&c)) {
+        let mut models = Vec::new();
+        for group in suffix.chars() {
+            models.extend_from_slice(match group {
+                'G' => &[GbModel::Dmg0, GbModel::DmgABC, GbModel::Mgb][..],
+                'S' => &[GbModel::Sgb, GbModel::Sgb2],
+                'C' => &[GbModel::Cgb],
+                _ => &[],
+            });
+        }
+        return Some(models);
+    }
+    const NAMES: [(&str, Option<GbModel>); 9] = [
+        ("dmg0", Some(GbModel::Dmg0)),
+        ("dmgABC", Some(GbModel::DmgABC)),
+        ("mgb", Some(GbModel::Mgb)),
+        ("sgb2", Some(GbModel::Sgb2)),
+        ("sgb", Some(GbModel::Sgb)),
+        ("cgb0", None),
+        ("cgb", Some(GbModel::Cgb)),
+        ("agb", None),
+        ("ags", None),
+    ];
+    let mut models = Vec::new();
+    let mut rest = suffix;
+    while !rest.is_empty() {
+        let (name, model) = NAMES.iter().find(|(name, _)| rest.starts_with(name))?;
+        models.extend(model);
+        rest = &rest[name.len()..];
+    }
+    Some(models)
+}
+
+/// Results of [`run_all_tests`]
+#[derive(Debug, Default)]
+pub struct TestRun {
+    /// In ROM path order
+    pub results: Vec<TestResult>,
+    /// ROMs the filter skipped
+    pub skipped: usize,
+}
+
+impl TestRun {
+    /// Passed and total tests of each suite, sorted by suite
+    pub fn suites(&self) -> Vec<(&str, usize, usize)> {
+        let mut suites: Vec<(&str, usize, usize)> = Vec::new();
+        for result in &self.results {
+            match suites.iter_mut().find(|(suite, _, _)| *suite == result.suite) {
+                Some((_, passed, total)) => {
+                    *passed += result.passed as usize;
+                    *total += 1;
+                }
+                None => suites.push((&result.suite, result.passed as usize, 1)),
+            }
+        }
+        suites.sort();
+        suites
+    }
+}
+
+/// Print a test's result as it finishes
+pub fn print_result(result: &TestResult) {
+    println!(
+        "{} {} - {} cycles, {:.2}s",
+        if result.passed { "PASSED ✓" } else { "FAILED ✗" },
+        result.name,
+        result.cycles,
+        result.duration.as_secs_f64()
+    );
+    if let Some(ref err) = result.error {
+        println!("  Error: {}", err);
+    }
+    if !result.output.is_empty() {
+        println!("  Output: {}", result.output.trim());
+    }
+}
+
+/// Run the tests `filter` selects in a directory tree or a single test
+/// file, on `jobs` threads
+///
+/// `on_result` sees each result as soon as its test finishes, in whatever
+/// order they finish.
+pub fn run_all_tests(
+    test_path: &str,
+    filter: &TestFilter,
+    jobs: usize,
+    on_result: impl Fn(&TestResult) + Sync,
+) -> TestRun {
+    let root = Path::new(test_path);
+    let relative = |rom: &Path| {
+        let path = rom.strip_prefix(root).unwrap_or(rom);
+        path.to_string_lossy().replace('\\', "/")
+    };
+    let all = test_roms(root);
+    let roms: Vec<_> = all.iter().filter(|rom| filter.matches(&relative(rom))).collect();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..roms.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, roms.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(rom) = roms.get(i) else { break };
+                let start = Instant::now();
+                let mut result = run_suite_test(rom);
+                result.duration = start.elapsed();
+                let path = relative(rom);
+                result.suite = path.rsplit_once('/').map_or("", |(dir, _)| dir).to_string();
+                on_result(&result);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    TestRun {
+        results: results.into_inner().unwrap().into_iter().flatten().collect(),
+        skipped: all.len() - roms.len(),
+    }
+}
+
+/// Machine-readable output of [`run_all_tests`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Junit,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            "junit" => Some(OutputFormat::Junit),
+            _ => None,
+        }
+    }
+}
+
+/// Results as a JSON document
+pub fn format_json(results: &[TestResult]) -> String {
+    fn string(s: &str) -> String {
+        let mut out = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                c if (c as u32) < 0x20 => {
+                    let _ = write!(out, "\\u{:04x}", c as u32);
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    let passed = results.iter().filter(|r| r.passed).count();
+    let mut out = format!(
+        "{{\n  \"passed\": {},\n  \"failed\": {},\n  \"tests\": [",
+        passed,
+        results.len() - passed
+    );
+    for (i, r) in results.iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}\n    {{\"suite\": {}, \"name\": {}, \"passed\": {}, \"cycles\": {}, \"seconds\": {:.3}, \"error\": {}, \"output\": {}}}",
+            if i == 0 { "" } else { "," },
+            string(&r.suite),
+            string(&r.name),
+            r.passed,
+            r.cycles,
+            r.duration.as_secs_f64(),
+            r.error.as_deref().map_or_else(|| "null".to_string(), string),
+            string(&r.output)
+        );
+    }
+    out.push_str("\n  ]\n}\n");
+    out
+}
+
+/// Results as a JUnit XML report, the format CI systems import
+pub fn format_junit(results: &[TestResult]) -> String {
+    fn escape(s: &str) -> String {
+        s.chars()
+            .filter(|&c| c >= ' ' || matches!(c, '\t' | '\n' | '\r'))
+            .map(|c| match c {
+                '&' => "&amp;".to_string(),
+                '<' => "&lt;".to_string(),
+                '>' => "&gt;".to_string(),
+                '"' => "&quot;".to_string(),
+                c => c.to_string(),
+            })
+            .collect()
+    }
+
+    let failures = results.iter().filter(|r| !r.passed).count();
+    let total: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuite name=\"gb3000\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        failures,
+        total
+    );
+    for r in results {
+        let _ = writeln!(
+            out,
+            "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">",
+            if r.suite.is_empty() { "gb3000".to_string() } else { escape(&r.suite) },
+            escape(&r.name),
+            r.duration.as_secs_f64()
+        );
+        if !r.passed {
+            let message = r.error.as_deref().unwrap_or("Failed");
+            let _ = writeln!(out, "    <failure message=\"{}\"/>", escape(message));
+        }
+        if !r.output.is_empty() {
+            let _ = writeln!(out, "    <system-out>{}</system-out>", escape(&r.output));
+        }
+        out.push_str("  </testcase>\n");
+    }
+    out.push_str("</testsuite>\n");
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A 32 KB ROM running `program` from the entry point
+    fn test_rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn serial_output_reports_blargg_result() {
+        let mut program = Vec::new();
+        for byte in b"Passed" {
+            // LD A, byte; LDH (SB), A; LD A, $81; LDH (SC), A
+            program.extend_from_slice(&[0x3E, *byte, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+            // Wait for the transfer: LDH A, (SC); BIT 7, A; JR NZ, -6
+            program.extend_from_slice(&[0xF0, 0x02, 0xCB, 0x7F, 0x20, 0xFA]);
+        }
+        program.extend_from_slice(&[0x18, 0xFE]);
+        let result = run_rom("serial".into(), &test_rom(&program), GbModel::DmgABC);
+        assert!(result.passed, "{:?}", result);
+        assert_eq!(result.output, "Passed");
+    }
+
+    /// LD B-L with 0x42 each, then LD B, B; JR -2
+    const MOONEYE_FAIL_PROGRAM: [u8; 15] =
+        [0x06, 0x42, 0x0E, 0x42, 0x16, 0x42, 0x1E, 0x42, 0x26, 0x42, 0x2E, 0x42, 0x40, 0x18, 0xFE];
+
+    #[test]
+    fn mooneye_registers_decide_result() {
+        // LD B, 3; LD C, 5; LD D, 8; LD E, 13; LD H, 21; LD L, 34; LD B, B; JR -2
+        let mut program = vec![
+            0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE,
+        ];
+        let result = run_rom("mooneye".into(), &test_rom(&program), GbModel::DmgABC);
+        assert!(result.passed, "{:?}", result);
+
+        let result = run_rom("mooneye".into(), &test_rom(&MOONEYE_FAIL_PROGRAM), GbModel::DmgABC);
+        assert!(!result.passed);
+        assert_eq!(result.error.as_deref(), Some("Mooneye: B=66 C=66 D=66 E=66 H=66 L=66"));
+
+        // An LD B,B without either signature is just an instruction
+        program.insert(0, 0x40);
+        let result = run_rom("mooneye".into(), &test_rom(&program), GbModel::DmgABC);
+        assert!(result.passed, "{:?}", result);
+    }
+
+    #[test]
+    fn screenshot_test_compares_final_frame() {
+        // LD B, B; JR -2 with the LCD showing a blank background
+        let rom = test_rom(&[0x40, 0x18, 0xFE]);
+        let dir = std::env::temp_dir();
+        let rom_path = dir.join("gb3000-screenshot-test.gb");
+        let reference = dir.join("gb3000-screenshot-test.png");
+        std::fs::write(&rom_path, rom).unwrap();
+        let mut white = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        capture::write_png(&reference, &white, 160, 144).unwrap();
+        let passed = run_screenshot_test(rom_path.to_str().unwrap(), &reference);
+
+        white[(2 * SCREEN_WIDTH + 5) * 4..][..3].fill(0);
+        capture::write_png(&reference, &white, 160, 144).unwrap();
+        let failed = run_screenshot_test(rom_path.to_str().unwrap(), &reference);
+        std::fs::remove_file(&rom_path).unwrap();
+        std::fs::remove_file(&reference).unwrap();
+
+        assert!(passed.passed, "{:?}", passed);
+        assert_eq!(failed.error.as_deref(), Some("1 pixels differ, first at (5, 2): shade 0, expected 3"));
+    }
+
+    #[test]
+    fn acid2_needs_its_reference() {
+        let dir = std::env::temp_dir().join("gb3000-acid2-test");
+        std::fs::create_dir_all(dir.join("img")).unwrap();
+        let rom = dir.join("dmg-acid2.gb");
+        std::fs::write(&rom, test_rom(&[0x40, 0x18, 0xFE])).unwrap();
+        let missing = run_suite_test(&rom);
+
+        let white = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        capture::write_png(&dir.join("img/reference-dmg.png"), &white, 160, 144).unwrap();
+        let found = run_suite_test(&rom);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            missing.error.as_deref(),
+            Some("No reference image: put reference-dmg.png next to the ROM")
+        );
+        assert!(found.passed, "{:?}", found);
+        assert_eq!(acid2_reference(Path::new("cgb-acid2.gbc")), "reference-cgb.png");
+    }
+
+    #[test]
+    fn runs_directory_trees_in_parallel() {
+        let dir = std::env::temp_dir().join("gb3000-parallel-test");
+        std::fs::create_dir_all(dir.join("timer/deep")).unwrap();
+        // The failure signature fails, the Fibonacci registers pass
+        let fail = test_rom(&MOONEYE_FAIL_PROGRAM);
+        let fib = test_rom(&[0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE]);
+        std::fs::write(dir.join("a.gb"), &fail).unwrap();
+        std::fs::write(dir.join("timer/b.gb"), &fib).unwrap();
+        std::fs::write(dir.join("timer/deep/c-GS.gb"), &fib).unwrap();
+        std::fs::write(dir.join("timer/deep/d-A.gb"), &fib).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let seen = AtomicUsize::new(0);
+        let run = run_all_tests(dir.to_str().unwrap(), &TestFilter::default(), 4, |_| {
+            seen.fetch_add(1, Ordering::Relaxed);
+        });
+        let filter = TestFilter { exclude: vec!["timer/**".into()], ..Default::default() };
+        let filtered = run_all_tests(dir.to_str().unwrap(), &filter, 1, |_| {});
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(seen.into_inner(), 3);
+        let summary: Vec<_> = run.results.iter().map(|r| (r.name.as_str(), r.passed)).collect();
+        assert_eq!(summary, [("a.gb", false), ("b.gb", true), ("c-GS.gb", true)]);
+        assert_eq!(run.skipped, 1);
+        assert_eq!(run.suites(), [("", 0, 1), ("timer", 1, 1), ("timer/deep", 1, 1)]);
+        assert_eq!((filtered.results.len(), filtered.skipped), (1, 3));
+    }
+
+    #[test]
+    fn filters_by_glob_and_model() {
+        let dmg = TestFilter { model: Some(GbModel::DmgABC), ..Default::default() };
+        assert!(dmg.matches("acceptance/timer/div_write.gb"));
+        assert!(dmg.matches("acceptance/boot_regs-dmgABC.gb"));
+        assert!(dmg.matches("acceptance/di_timing-GS.gb"));
+        assert!(!dmg.matches("acceptance/boot_regs-sgb.gb"));
+        assert!(!dmg.matches("acceptance/boot_div-S.gb"));
+        assert!(!TestFilter::default().matches("misc/boot_regs-A.gb"));
+        assert!(TestFilter::default().matches("misc/boot_regs-cgb.gb"));
+        assert_eq!(target_models("boot_hwio-dmgABCmgb"), Some(vec![GbModel::DmgABC, GbModel::Mgb]));
+        assert_eq!(target_models("rapid-toggle"), None);
+
+        let timers = TestFilter {
+            include: vec!["acceptance/timer/*".into()],
+            exclude: vec!["*reload*".into()],
+            model: None,
+        };
+        assert!(timers.matches("acceptance/timer/div_write.gb"));
+        assert!(!timers.matches("acceptance/timer/tima_reload.gb"));
+        assert!(!timers.matches("acceptance/timer/deep/div_write.gb"));
+        assert!(!timers.matches("acceptance/ei_timing.gb"));
+        assert!(glob_match(b"**/ei_?iming.gb", b"a/b/ei_timing.gb"));
+    }
+
+    #[test]
+    fn structured_output() {
+        let results = [
+            TestResult {
+                name: "ok.gb".into(),
+                passed: true,
+                output: "Passed\n".into(),
+                cycles: 10,
+                error: None,
+                duration: Duration::from_millis(1500),
+                suite: String::new(),
+            },
+            TestResult {
+                name: "bad \"<&>\".gb".into(),
+                passed: false,
+                output: String::new(),
+                cycles: 20,
+                error: Some("Test timed out".into()),
+                duration: Duration::ZERO,
+                suite: "timer".into(),
+            },
+        ];
+        assert_eq!(
+            format_json(&results),
+            r#"{
+  "passed": 1,
+  "failed": 1,
+  "tests": [
+    {"suite": "", "name": "ok.gb", "passed": true, "cycles": 10, "seconds": 1.500, "error": null, "output": "Passed\n"},
+    {"suite": "timer", "name": "bad \"<&>\".gb", "passed": false, "cycles": 20, "seconds": 0.000, "error": "Test timed out", "output": ""}
+  ]
+}
+"#
+        );
+        assert_eq!(
+            format_junit(&results),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="gb3000" tests="2" failures="1" time="1.500">
+  <testcase classname="gb3000" name="ok.gb" time="1.500">
+    <system-out>Passed
+</system-out>
+  </testcase>
+  <testcase classname="timer" name="bad &quot;&lt;&amp;&gt;&quot;.gb" time="0.000">
+    <failure message="Test timed out"/>
+  </testcase>
+</testsuite>
+"#
+        );
+        assert_eq!(OutputFormat::parse("junit"), Some(OutputFormat::Junit));
+        assert_eq!(OutputFormat::parse("xml"), None);
+    }
+
     #[test]
     #[ignore] // Run with: cargo test -- --ignored
     fn test_blargg_cpu_instrs_01() {
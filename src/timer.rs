@@ -1,26 +1,44 @@
-/// Timer subsystem for the Game Boy emulator.
-///
-/// The Game Boy has a precise timer with the following registers:
-/// - DIV (0xFF04): Divider register, upper 8 bits of a 16-bit counter
-/// - TIMA (0xFF05): Timer counter, increments based on TAC
-/// - TMA (0xFF06): Timer modulo, loaded into TIMA on overflow
-/// - TAC (0xFF07): Timer control
-///
-/// The timer uses falling edge detection on a specific bit of the internal
-/// counter (selected by TAC) ANDed with the timer enable bit.
-
+//! Timer subsystem for the Game Boy emulator.
+//!
+//! The Game Boy has a precise timer with the following registers:
+//! - DIV (0xFF04): Divider register, upper 8 bits of a 16-bit counter
+//! - TIMA (0xFF05): Timer counter, increments based on TAC
+//! - TMA (0xFF06): Timer modulo, loaded into TIMA on overflow
+//! - TAC (0xFF07): Timer control
+//!
+//! The timer uses falling edge detection on a specific bit of the internal
+//! counter (selected by TAC) ANDed with the timer enable bit.
+//!
+//! Between falling edges nothing but the counter changes, so ticking
+//! skips straight to the next edge or pending reload. Register writes are
+//! handled at the start of each tick, on the M-cycle they happened.
+//!
+//! An overflow leaves TIMA at 0 for one M-cycle, then loads TMA and
+//! requests the interrupt. Writing TIMA during the first M-cycle cancels
+//! the reload. During the M-cycle of the reload TIMA writes are lost, and
+//! TMA writes go straight through to TIMA as well.
+//!
+//! The CPU reads DIV and TIMA from the I/O registers, which every tick
+//! brings up to date. The emulator ticks the timer after each M-cycle of
+//! an instruction, so a read partway through one sees the counter as of
+//! that M-cycle rather than as of the instruction's start.
+
+use crate::cpu::GbModel;
 use crate::memory::{io, interrupts, Memory};
+use crate::state::{StateError, StateReader, StateWriter};
 
 /// Timer state for accurate emulation
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum OverflowState {
     /// Normal operation
     None,
-    /// TIMA overflowed, waiting for reload (cycles remaining, cached TMA value)
-    Pending(u8, u8),
+    /// TIMA overflowed and reads 0, waiting for reload (cycles remaining)
+    Pending(u8),
+    /// TIMA was just loaded from TMA (cycles remaining in the reload M-cycle)
+    Reloaded(u8),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Timer {
     /// Internal 16-bit counter (upper 8 bits = DIV register)
     div_counter: u16,
@@ -48,19 +66,48 @@ impl Timer {
     }
 
     /// Reset for a specific Game Boy model with accurate post-boot DIV value
-    pub fn reset_for_model(&mut self, model: &str) {
+    pub fn reset_for_model(&mut self, model: GbModel) {
         self.overflow_state = OverflowState::None;
         // These values are the internal counter values after the boot ROM finishes
         // DIV register = div_counter >> 8
         // Values from Mooneye tests and hardware analysis
         self.div_counter = match model {
-            "dmg0" => 0x267C, // DMG-0: DIV = 0x26 (early boot ROM)
-            "dmgABC" | "dmg" => 0xABCC, // DMG-ABC: DIV = 0xAB  
-            "mgb" => 0xABCC, // MGB: DIV = 0xAB (same as DMG-ABC)
-            "sgb" => 0xD294, // SGB: DIV = 0xD2
-            "sgb2" => 0xD294, // SGB2: Same as SGB
-            _ => 0xABCC, // Default to DMG-ABC
+            GbModel::Dmg0 => 0x267C, // DMG-0: DIV = 0x26 (early boot ROM)
+            GbModel::DmgABC => 0xABCC, // DMG-ABC: DIV = 0xAB
+            GbModel::Mgb => 0xABCC, // MGB: DIV = 0xAB (same as DMG-ABC)
+            GbModel::Sgb => 0xD294, // SGB: DIV = 0xD2
+            GbModel::Sgb2 => 0xD294, // SGB2: Same as SGB
+            GbModel::Cgb => 0xABCC, // CGB boot ROM not modelled yet
+        };
+    }
+
+    /// DIV register value (upper 8 bits of the internal counter)
+    pub fn div(&self) -> u8 {
+        (self.div_counter >> 8) as u8
+    }
+
+    /// Serialize the internal counter and pending reload
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.div_counter);
+        // 1-4 is a pending reload, 5-8 the reload M-cycle
+        w.u8(match self.overflow_state {
+            OverflowState::None => 0,
+            OverflowState::Pending(cycles) => cycles,
+            OverflowState::Reloaded(cycles) => cycles + 4,
+        });
+    }
+
+    /// Restore the internal counter and pending reload
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.div_counter = r.u16()?;
+        let cycles = r.u8()?;
+        self.overflow_state = match cycles {
+            0 => OverflowState::None,
+            1..=4 => OverflowState::Pending(cycles),
+            5..=8 => OverflowState::Reloaded(cycles - 4),
+            _ => return Err(StateError::Invalid("timer overflow state")),
         };
+        Ok(())
     }
 
     /// Get the bit position to check for the given TAC frequency
@@ -88,10 +135,37 @@ impl Timer {
     pub fn tick(&mut self, memory: &mut Memory, cycles: u32) {
         // Process any pending timer register writes
         self.process_writes(memory);
-        
-        for _ in 0..cycles {
+
+        let tac = memory.io[io::TAC as usize];
+        let mut cycles = cycles;
+        while cycles > 0 {
+            // A reload is a few cycles off at most; step through it
+            if self.overflow_state != OverflowState::None {
+                self.tick_single(memory);
+                cycles -= 1;
+                continue;
+            }
+            let until_edge = self.cycles_until_edge(tac);
+            if cycles < until_edge {
+                self.div_counter = self.div_counter.wrapping_add(cycles as u16);
+                memory.io[io::DIV as usize] = self.div();
+                return;
+            }
+            // Jump to just before the edge and take it as usual
+            self.div_counter = self.div_counter.wrapping_add((until_edge - 1) as u16);
             self.tick_single(memory);
+            cycles -= until_edge;
+        }
+    }
+
+    /// T-cycles until the selected counter bit next falls, `u32::MAX`
+    /// while the timer is disabled
+    fn cycles_until_edge(&self, tac: u8) -> u32 {
+        if tac & 0x04 == 0 {
+            return u32::MAX;
         }
+        let period = 2u32 << Self::get_bit_position(tac);
+        period - (self.div_counter as u32 & (period - 1))
     }
     
     /// Process timer register writes from memory
@@ -104,43 +178,57 @@ impl Timer {
         if memory.timer_tac_written {
             memory.timer_tac_written = false;
             let old_tac = memory.timer_tac_old_value;
-            let new_tac = memory.data[io::TAC as usize];
+            let new_tac = memory.io[io::TAC as usize];
             self.write_tac(memory, old_tac, new_tac);
         }
         
         if memory.timer_tima_written {
             memory.timer_tima_written = false;
-            // Writing to TIMA during overflow window cancels the reload
-            if self.in_overflow_window() {
-                self.overflow_state = OverflowState::None;
+            match self.overflow_state {
+                // Writing TIMA while it reads 0 cancels the reload and interrupt
+                OverflowState::Pending(_) => self.overflow_state = OverflowState::None,
+                // On the reload M-cycle the write loses to TMA
+                OverflowState::Reloaded(_) => {
+                    memory.io[io::TIMA as usize] = memory.io[io::TMA as usize];
+                }
+                OverflowState::None => {}
+            }
+        }
+
+        if memory.timer_tma_written {
+            memory.timer_tma_written = false;
+            // On the reload M-cycle the new TMA is loaded into TIMA too
+            if let OverflowState::Reloaded(_) = self.overflow_state {
+                memory.io[io::TIMA as usize] = memory.io[io::TMA as usize];
             }
         }
-        
-        // TMA writes don't need special handling - we already cached TMA at overflow start
-        memory.timer_tma_written = false;
     }
 
     /// Advance the timer by a single T-cycle.
     fn tick_single(&mut self, memory: &mut Memory) {
-        let tac = memory.data[io::TAC as usize];
+        let tac = memory.io[io::TAC as usize];
         let old_clock = self.timer_clock_high(tac);
 
         // Increment the internal counter
         self.div_counter = self.div_counter.wrapping_add(1);
         
         // Update DIV register
-        memory.data[io::DIV as usize] = (self.div_counter >> 8) as u8;
+        memory.io[io::DIV as usize] = (self.div_counter >> 8) as u8;
 
         // Handle overflow state
         match self.overflow_state {
-            OverflowState::Pending(1, tma_value) => {
-                // Reload TIMA with cached TMA value and request interrupt
-                memory.data[io::TIMA as usize] = tma_value;
+            OverflowState::Pending(1) => {
+                // Reload TIMA with TMA as it is now and request interrupt
+                memory.io[io::TIMA as usize] = memory.io[io::TMA as usize];
                 memory.request_interrupt(interrupts::TIMER);
-                self.overflow_state = OverflowState::None;
+                self.overflow_state = OverflowState::Reloaded(4);
+            }
+            OverflowState::Pending(n) => {
+                self.overflow_state = OverflowState::Pending(n - 1);
             }
-            OverflowState::Pending(n, tma) => {
-                self.overflow_state = OverflowState::Pending(n - 1, tma);
+            OverflowState::Reloaded(1) => self.overflow_state = OverflowState::None,
+            OverflowState::Reloaded(n) => {
+                self.overflow_state = OverflowState::Reloaded(n - 1);
             }
             OverflowState::None => {}
         }
@@ -154,29 +242,27 @@ impl Timer {
 
     /// Increment TIMA and handle overflow
     fn increment_tima(&mut self, memory: &mut Memory) {
-        let tima = memory.data[io::TIMA as usize];
+        let tima = memory.io[io::TIMA as usize];
         let (new_tima, overflow) = tima.overflowing_add(1);
         
         if overflow {
             // TIMA becomes 0, and after 4 cycles it will be reloaded with TMA
-            // Cache the TMA value now - writes to TMA during the window won't affect reload
-            let tma = memory.data[io::TMA as usize];
-            memory.data[io::TIMA as usize] = 0;
-            self.overflow_state = OverflowState::Pending(4, tma);
+            memory.io[io::TIMA as usize] = 0;
+            self.overflow_state = OverflowState::Pending(4);
         } else {
-            memory.data[io::TIMA as usize] = new_tima;
+            memory.io[io::TIMA as usize] = new_tima;
         }
     }
 
     /// Called when DIV is written to.
     /// This resets the internal counter and may trigger a TIMA increment.
     pub fn write_div(&mut self, memory: &mut Memory) {
-        let tac = memory.data[io::TAC as usize];
+        let tac = memory.io[io::TAC as usize];
         let old_clock = self.timer_clock_high(tac);
         
         // Reset the counter
         self.div_counter = 0;
-        memory.data[io::DIV as usize] = 0;
+        memory.io[io::DIV as usize] = 0;
         
         // If the clock was high and is now low, increment TIMA
         if old_clock {
@@ -210,14 +296,14 @@ impl Timer {
     /// Called when TIMA is written to during the overflow period.
     /// Writing to TIMA during the 4-cycle window cancels the TMA reload.
     pub fn write_tima(&mut self, memory: &mut Memory, value: u8) {
-        memory.data[io::TIMA as usize] = value;
+        memory.io[io::TIMA as usize] = value;
         // Cancel any pending overflow
         self.overflow_state = OverflowState::None;
     }
 
     /// Check if we're in the overflow window (for detecting writes)
     pub fn in_overflow_window(&self) -> bool {
-        matches!(self.overflow_state, OverflowState::Pending(_, _))
+        matches!(self.overflow_state, OverflowState::Pending(_))
     }
 }
 
@@ -237,15 +323,45 @@ mod tests {
         let mut memory = Memory::new();
 
         // DIV should be 0 initially
-        assert_eq!(memory.data[io::DIV as usize], 0);
+        assert_eq!(memory.io[io::DIV as usize], 0);
 
         // After 256 cycles, DIV should be 1
         timer.tick(&mut memory, 256);
-        assert_eq!(memory.data[io::DIV as usize], 1);
+        assert_eq!(memory.io[io::DIV as usize], 1);
 
         // After another 256 cycles, DIV should be 2
         timer.tick(&mut memory, 256);
-        assert_eq!(memory.data[io::DIV as usize], 2);
+        assert_eq!(memory.io[io::DIV as usize], 2);
+    }
+
+    #[test]
+    fn skipping_to_edges_matches_single_cycles() {
+        let mut stepped = (Timer::new(), Memory::new());
+        let mut skipped = (Timer::new(), Memory::new());
+        let mut seed = 7u32;
+        for round in 0..3000u32 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let cycles = 1 + (seed >> 16) % 700;
+            for memory in [&mut stepped.1, &mut skipped.1] {
+                // Change the frequency, DIV and TIMA now and then
+                match round % 97 {
+                    0 => memory.write_byte(io::TAC, 0x04 | (round / 97) as u8 & 0x03),
+                    40 => memory.write_byte(io::DIV, 0),
+                    60 => memory.write_byte(io::TIMA, 0xF0),
+                    80 => memory.write_byte(io::TAC, 0x00),
+                    _ => {}
+                }
+                memory.io[io::TMA as usize] = round as u8;
+            }
+            for _ in 0..cycles {
+                stepped.0.tick(&mut stepped.1, 1);
+            }
+            skipped.0.tick(&mut skipped.1, cycles);
+            assert_eq!(stepped.0.div_counter, skipped.0.div_counter);
+            assert_eq!(stepped.0.overflow_state, skipped.0.overflow_state);
+            assert_eq!(stepped.1.io.as_slice(), skipped.1.io.as_slice(), "round {}", round);
+        }
+        assert_ne!(skipped.1.io[io::IF as usize] & interrupts::TIMER, 0);
     }
 
     #[test]
@@ -254,14 +370,14 @@ mod tests {
         let mut memory = Memory::new();
 
         // Enable timer with fastest frequency (16 cycles per increment)
-        memory.data[io::TAC as usize] = 0x05; // Enabled, freq = 01
+        memory.io[io::TAC as usize] = 0x05; // Enabled, freq = 01
 
         // Set TIMA to 0xFF so it will overflow soon
-        memory.data[io::TIMA as usize] = 0xFF;
-        memory.data[io::TMA as usize] = 0x42;
+        memory.io[io::TIMA as usize] = 0xFF;
+        memory.io[io::TMA as usize] = 0x42;
 
         // Clear interrupt flags
-        memory.data[io::IF as usize] = 0;
+        memory.io[io::IF as usize] = 0;
 
         // Tick until overflow (need 16 cycles for one increment)
         timer.tick(&mut memory, 16);
@@ -271,10 +387,89 @@ mod tests {
         timer.tick(&mut memory, 4);
         
         // TIMA should have been reloaded with TMA
-        assert_eq!(memory.data[io::TIMA as usize], 0x42);
+        assert_eq!(memory.io[io::TIMA as usize], 0x42);
 
         // Timer interrupt should be requested
-        assert!(memory.data[io::IF as usize] & interrupts::TIMER != 0);
+        assert!(memory.io[io::IF as usize] & interrupts::TIMER != 0);
     }
-}
 
+    /// A timer that has just overflowed, TMA 0x42, one M-cycle from reload
+    fn overflowed() -> (Timer, Memory) {
+        let mut timer = Timer::new();
+        let mut memory = Memory::new();
+        memory.io[io::TAC as usize] = 0x05;
+        memory.io[io::TIMA as usize] = 0xFF;
+        memory.io[io::TMA as usize] = 0x42;
+        memory.io[io::IF as usize] = 0;
+        for _ in 0..4 {
+            timer.tick(&mut memory, 4);
+        }
+        assert_eq!(memory.io[io::TIMA as usize], 0);
+        (timer, memory)
+    }
+
+    #[test]
+    fn writes_around_the_reload() {
+        let tima = |memory: &Memory| memory.io[io::TIMA as usize];
+        let interrupted = |memory: &Memory| memory.io[io::IF as usize] & interrupts::TIMER != 0;
+
+        // TMA written while TIMA reads 0 is the value reloaded
+        let (mut timer, mut memory) = overflowed();
+        memory.write_byte(io::TMA, 0x55);
+        timer.tick(&mut memory, 4);
+        assert_eq!(tima(&memory), 0x55);
+        assert!(interrupted(&memory));
+
+        // TIMA written while it reads 0 cancels the reload and interrupt
+        let (mut timer, mut memory) = overflowed();
+        memory.write_byte(io::TIMA, 0x33);
+        timer.tick(&mut memory, 4);
+        timer.tick(&mut memory, 4);
+        assert_eq!(tima(&memory), 0x33);
+        assert!(!interrupted(&memory));
+
+        // TIMA written on the reload M-cycle is lost
+        let (mut timer, mut memory) = overflowed();
+        timer.tick(&mut memory, 4);
+        memory.write_byte(io::TIMA, 0x33);
+        timer.tick(&mut memory, 4);
+        assert_eq!(tima(&memory), 0x42);
+        assert!(interrupted(&memory));
+
+        // TMA written on the reload M-cycle goes through to TIMA
+        let (mut timer, mut memory) = overflowed();
+        timer.tick(&mut memory, 4);
+        memory.write_byte(io::TMA, 0x77);
+        timer.tick(&mut memory, 4);
+        assert_eq!(tima(&memory), 0x77);
+
+        // One M-cycle later both registers are independent again
+        memory.write_byte(io::TIMA, 0x10);
+        memory.write_byte(io::TMA, 0x20);
+        timer.tick(&mut memory, 4);
+        assert_eq!(tima(&memory), 0x10);
+    }
+
+    #[test]
+    fn rapid_tac_toggles_clock_tima() {
+        let mut timer = Timer::new();
+        let mut memory = Memory::new();
+        memory.io[io::TAC as usize] = 0x05;
+        for _ in 0..5 {
+            // With bit 3 of the counter high, disabling the timer is a
+            // falling edge of its clock and bumps TIMA
+            timer.set_div_counter(0x0008);
+            memory.write_byte(io::TAC, 0x01);
+            timer.tick(&mut memory, 4);
+            memory.write_byte(io::TAC, 0x05);
+            timer.tick(&mut memory, 0);
+        }
+        assert_eq!(memory.io[io::TIMA as usize], 5);
+
+        // Switching to a frequency whose bit is low is an edge too
+        timer.set_div_counter(0x0008);
+        memory.write_byte(io::TAC, 0x06);
+        timer.tick(&mut memory, 0);
+        assert_eq!(memory.io[io::TIMA as usize], 6);
+    }
+}
@@ -2,8 +2,13 @@
 //!
 //! Uses software rendering with a built-in bitmap font.
 
+use crate::config::{self, Config, BUTTONS, MAX_RECENT_ROMS, PALETTES, TURBO_BUTTONS};
+use crate::filters::Filter;
+use crate::savestates::{SlotInfo, SLOT_COUNT};
+use gb3000::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use rfd::FileDialog;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 /// UI state
 #[derive(Debug, Clone, PartialEq)]
@@ -11,6 +16,12 @@ pub enum EmulatorState {
     StartScreen,
     Running,
     Paused,
+    /// Save state browser, opened from the pause menu
+    StateBrowser,
+    /// Settings screen, opened from the start screen or pause menu
+    Settings,
+    /// Header details of the loaded ROM, opened from the pause menu
+    RomInfo,
 }
 
 /// Recent ROM entry
@@ -24,20 +35,55 @@ pub struct RecentRom {
 #[derive(Debug, Clone)]
 pub struct RomInfo {
     pub title: String,
-    pub cart_type: String,
-    pub rom_size: String,
-    pub ram_size: String,
+    /// Label and value rows for the ROM info screen
+    pub details: Vec<(&'static str, String)>,
 }
 
+/// Selectable emulation speeds, slowest first
+pub const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+/// Speed while the fast-forward key is held
+pub const FAST_FORWARD_SPEED: f64 = 4.0;
+
+/// How long on-screen messages stay visible
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
 /// Main UI controller
 pub struct Ui {
     pub state: EmulatorState,
-    pub recent_roms: Vec<RecentRom>,
+    /// Persistent settings, including the recent ROM list
+    pub config: Config,
+    /// Index into the key bindings waiting for a key press on the settings
+    /// screen
+    pub rebinding: Option<usize>,
     pub current_rom: Option<PathBuf>,
+    /// Header hash of the loaded game, for per-game settings
+    pub current_game: Option<u32>,
     pub rom_info: Option<RomInfo>,
     pub show_fps: bool,
     pub fps: f64,
     pub error_message: Option<String>,
+    /// Active video filter
+    pub video_filter: Filter,
+    /// Pace frames off the audio buffer instead of a fixed timer
+    pub audio_sync: bool,
+    /// Emulation speed picked in the pause menu (1.0 = normal)
+    pub speed: f64,
+    /// Whether the fast-forward key is held
+    pub fast_forward: bool,
+    /// Whether the rewind key is held
+    pub rewinding: bool,
+    /// Paused by the frame-advance key, showing the game instead of the
+    /// pause menu
+    pub frame_advance: bool,
+    /// Selected save state slot
+    pub state_slot: u8,
+    /// Contents of each slot, refreshed when the state browser opens
+    pub slots: Vec<Option<SlotInfo>>,
+    /// Audio output devices, listed when the settings screen opens
+    pub audio_devices: Vec<String>,
+    /// On-screen message and when it was posted
+    message: Option<(String, Instant)>,
     /// Mouse position
     mouse_x: f32,
     mouse_y: f32,
@@ -54,19 +100,54 @@ pub enum UiAction {
     LoadRom(PathBuf),
     Resume,
     Reset,
+    CycleFilter,
+    ToggleAudioSync,
+    SetSpeed(f64),
+    OpenStates,
+    SelectSlot(u8),
+    SaveState,
+    LoadState,
+    CloseStates,
+    OpenRomInfo,
+    CloseRomInfo,
+    OpenSettings,
+    CyclePalette,
+    ChangeVolume(f32),
+    CycleAudioDevice,
+    CycleAudioBuffer,
+    CycleStereoWidth,
+    ToggleHighPass,
+    CycleWindowScale,
+    CycleModel,
+    CycleTurboRate,
+    ToggleResetCombo,
+    RebindButton(usize),
+    CloseSettings,
     Quit,
 }
 
 impl Ui {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
         Self {
             state: EmulatorState::StartScreen,
-            recent_roms: Vec::new(),
+            config,
+            rebinding: None,
             current_rom: None,
+            current_game: None,
             rom_info: None,
             show_fps: true,
             fps: 0.0,
             error_message: None,
+            video_filter: Filter::default(),
+            audio_sync: true,
+            speed: 1.0,
+            fast_forward: false,
+            rewinding: false,
+            frame_advance: false,
+            state_slot: 0,
+            slots: Vec::new(),
+            audio_devices: Vec::new(),
+            message: None,
             mouse_x: 0.0,
             mouse_y: 0.0,
             mouse_down: false,
@@ -87,6 +168,7 @@ impl Ui {
     pub fn open_file_dialog() -> Option<PathBuf> {
         FileDialog::new()
             .add_filter("Game Boy ROMs", &["gb", "gbc", "GB", "GBC"])
+            .add_filter("GBS sound files", &["gbs", "GBS"])
             .add_filter("All files", &["*"])
             .set_title("Select a Game Boy ROM")
             .pick_file()
@@ -94,25 +176,26 @@ impl Ui {
 
     /// Add ROM to recent list
     pub fn add_recent_rom(&mut self, path: PathBuf, title: String) {
-        self.recent_roms.retain(|r| r.path != path);
-        self.recent_roms.insert(0, RecentRom { path, title });
-        self.recent_roms.truncate(5);
+        let recent = &mut self.config.recent_roms;
+        recent.retain(|r| r.path != path);
+        recent.insert(0, RecentRom { path, title });
+        recent.truncate(MAX_RECENT_ROMS);
     }
 
     /// Render start screen and return action
     pub fn render_start_screen(&mut self, buffer: &mut [u32], width: usize, height: usize) -> UiAction {
         // Fill background
-        fill_rect(buffer, width, 0, 0, width, height, 0xFF1a1a2e);
+        fill_rect(buffer, width, 0, 0, width, height, 0xFF1A1A2E);
 
         // Title
         let title = "GB3000";
         let title_x = (width - title.len() * 24) / 2;
-        draw_text_large(buffer, width, title_x, 80, title, 0xFF4ade80);
+        draw_text_large(buffer, width, title_x, 80, title, 0xFF4ADE80);
 
         // Subtitle
         let subtitle = "Game Boy Emulator";
         let sub_x = (width - subtitle.len() * 8) / 2;
-        draw_text(buffer, width, sub_x, 140, subtitle, 0xFF9ca3af);
+        draw_text(buffer, width, sub_x, 140, subtitle, 0xFF9CA3AF);
 
         // Open ROM button
         let btn_w = 200;
@@ -121,31 +204,44 @@ impl Ui {
         let btn_y = 200;
         
         let btn_hover = self.is_mouse_in_rect(btn_x, btn_y, btn_w, btn_h);
-        let btn_color = if btn_hover { 0xFF22c55e } else { 0xFF16a34a };
+        let btn_color = if btn_hover { 0xFF22C55E } else { 0xFF16A34A };
         
         fill_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, btn_color);
-        draw_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, 0xFF4ade80);
+        draw_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, 0xFF4ADE80);
         
         let text = "Open ROM";
         let text_x = btn_x + (btn_w - text.len() * 8) / 2;
         let text_y = btn_y + (btn_h - 8) / 2;
-        draw_text(buffer, width, text_x, text_y, text, 0xFFffffff);
+        draw_text(buffer, width, text_x, text_y, text, 0xFFFFFFFF);
 
         if btn_hover && self.mouse_clicked {
             return UiAction::OpenFile;
         }
 
+        // Settings button
+        let set_w = 120;
+        let set_h = 26;
+        let set_x = (width - set_w) / 2;
+        let set_y = btn_y + btn_h + 12;
+        let set_hover = self.is_mouse_in_rect(set_x, set_y, set_w, set_h);
+        fill_rect(buffer, width, set_x, set_y, set_w, set_h, if set_hover { 0xFF374151 } else { 0xFF1F2937 });
+        draw_rect(buffer, width, set_x, set_y, set_w, set_h, 0xFF4B5563);
+        draw_text(buffer, width, set_x + (set_w - 8 * 8) / 2, set_y + (set_h - 8) / 2, "Settings", 0xFFD1D5DB);
+        if set_hover && self.mouse_clicked {
+            return UiAction::OpenSettings;
+        }
+
         // Recent ROMs
-        if !self.recent_roms.is_empty() {
-            draw_text(buffer, width, (width - 11 * 8) / 2, 280, "Recent ROMs", 0xFF6b7280);
+        if !self.config.recent_roms.is_empty() {
+            draw_text(buffer, width, (width - 11 * 8) / 2, 310, "Recent ROMs", 0xFF6B7280);
             
-            for (i, recent) in self.recent_roms.iter().enumerate() {
-                let y = 310 + i * 35;
+            for (i, recent) in self.config.recent_roms.iter().enumerate() {
+                let y = 335 + i * 35;
                 let item_w = 300;
                 let item_x = (width - item_w) / 2;
                 
                 let hover = self.is_mouse_in_rect(item_x, y, item_w, 30);
-                let bg_color = if hover { 0xFF374151 } else { 0xFF1f2937 };
+                let bg_color = if hover { 0xFF374151 } else { 0xFF1F2937 };
                 
                 fill_rect(buffer, width, item_x, y, item_w, 30, bg_color);
                 
@@ -156,7 +252,7 @@ impl Ui {
                 };
                 let tx = item_x + 10;
                 let ty = y + 11;
-                draw_text(buffer, width, tx, ty, &display_title, 0xFFd1d5db);
+                draw_text(buffer, width, tx, ty, &display_title, 0xFFD1D5DB);
                 
                 if hover && self.mouse_clicked {
                     return UiAction::LoadRom(recent.path.clone());
@@ -165,14 +261,21 @@ impl Ui {
         }
 
         // Controls hint
-        let controls = "Arrow Keys = D-Pad | Z = A | X = B | Enter = Start | Space = Select | Esc = Menu";
+        let key = |i: usize| config::key_name(self.config.keys.0[i]);
+        let controls = format!(
+            "{}/{}/{}/{} = D-Pad | {} = A | {} = B | {} = Start | {} = Select | {}/{} = Turbo | Esc = Menu",
+            key(0), key(1), key(2), key(3), key(4), key(5), key(6), key(7), key(8), key(9)
+        );
         let cx = (width.saturating_sub(controls.len() * 6)) / 2;
-        draw_text_small(buffer, width, cx, height - 40, controls, 0xFF4b5563);
+        draw_text_small(buffer, width, cx, height - 52, &controls, 0xFF4B5563);
+        let hotkeys = "Tab = Fast Forward | Backspace = Rewind | F10 = Record | F12 = Screenshot";
+        let hx = (width.saturating_sub(hotkeys.len() * 6)) / 2;
+        draw_text_small(buffer, width, hx, height - 40, hotkeys, 0xFF4B5563);
 
         // Error message
         if let Some(ref error) = self.error_message {
             let ex = (width.saturating_sub(error.len() * 8)) / 2;
-            draw_text(buffer, width, ex, height - 80, error, 0xFFef4444);
+            draw_text(buffer, width, ex, height - 80, error, 0xFFEF4444);
         }
 
         UiAction::None
@@ -191,23 +294,30 @@ impl Ui {
         // Title
         let title = "PAUSED";
         let tx = (width - title.len() * 16) / 2;
-        draw_text_large(buffer, width, tx, 100, title, 0xFFffffff);
+        draw_text_large(buffer, width, tx, 90, title, 0xFFFFFFFF);
 
         // Buttons
+        let filter_label = format!("Filter: {}", self.video_filter.name());
+        let sync_label = if self.audio_sync { "Sync: Audio" } else { "Sync: Video" };
         let buttons = [
-            ("Resume", UiAction::Resume, 0xFF22c55e),
-            ("Reset", UiAction::Reset, 0xFF3b82f6),
-            ("Open ROM", UiAction::OpenFile, 0xFF6366f1),
-            ("Quit", UiAction::Quit, 0xFFef4444),
+            ("Resume", UiAction::Resume, 0xFF22C55E),
+            ("Reset", UiAction::Reset, 0xFF3B82F6),
+            (filter_label.as_str(), UiAction::CycleFilter, 0xFF8B5CF6),
+            (sync_label, UiAction::ToggleAudioSync, 0xFF0EA5E9),
+            ("Save States", UiAction::OpenStates, 0xFFF59E0B),
+            ("Settings", UiAction::OpenSettings, 0xFF64748B),
+            ("Open ROM", UiAction::OpenFile, 0xFF6366F1),
+            ("Quit", UiAction::Quit, 0xFFEF4444),
         ];
 
         let btn_w = 180;
-        let btn_h = 45;
+        let btn_h = 38;
         let btn_x = (width - btn_w) / 2;
-        let start_y = 180;
+        let start_y = 135;
+        let spacing = 42;
 
         for (i, (text, action, color)) in buttons.iter().enumerate() {
-            let btn_y = start_y + i * 55;
+            let btn_y = start_y + i * spacing;
             
             let hover = self.is_mouse_in_rect(btn_x, btn_y, btn_w, btn_h);
             let bg = if hover { lighten_color(*color) } else { *color };
@@ -217,32 +327,433 @@ impl Ui {
             
             let text_x = btn_x + (btn_w - text.len() * 8) / 2;
             let text_y = btn_y + (btn_h - 8) / 2;
-            draw_text(buffer, width, text_x, text_y, text, 0xFFffffff);
+            draw_text(buffer, width, text_x, text_y, text, 0xFFFFFFFF);
             
             if hover && self.mouse_clicked {
                 return action.clone();
             }
         }
 
-        // ROM info
+        if let Some(speed) = self.render_speed_slider(buffer, width, start_y + buttons.len() * spacing + 6) {
+            return UiAction::SetSpeed(speed);
+        }
+
+        // ROM info, clicked through to the details
         if let Some(ref info) = self.rom_info {
             let info_text = format!("Playing: {}", info.title);
+            let box_w = info_text.len() * 6 + 16;
+            let box_x = width.saturating_sub(box_w) / 2;
+            let hover = self.is_mouse_in_rect(box_x, height - 56, box_w, 20);
+            if hover {
+                fill_rect(buffer, width, box_x, height - 56, box_w, 20, 0xFF1F2937);
+            }
+
             let ix = (width.saturating_sub(info_text.len() * 6)) / 2;
-            draw_text_small(buffer, width, ix, height - 50, &info_text, 0xFF9ca3af);
+            draw_text_small(buffer, width, ix, height - 50, &info_text, 0xFF9CA3AF);
+
+            if hover && self.mouse_clicked {
+                return UiAction::OpenRomInfo;
+            }
         }
 
         UiAction::None
     }
 
+    /// Render the ROM info screen: every decoded header field
+    pub fn render_rom_info(&mut self, buffer: &mut [u32], width: usize, height: usize) -> UiAction {
+        buffer.fill(0xFF111827);
+
+        let title = "ROM INFO";
+        let tx = (width - title.len() * 24) / 2;
+        draw_text_large(buffer, width, tx, 30, title, 0xFFFFFFFF);
+
+        let row_w = 400;
+        let row_x = (width - row_w) / 2;
+        let value_x = row_x + 140;
+        let mut y = 96;
+        if let Some(ref info) = self.rom_info {
+            draw_text(buffer, width, row_x, y, &ellipsize(&info.title, row_w / 8), 0xFFFFFFFF);
+            y += 30;
+            for (label, value) in &info.details {
+                draw_text(buffer, width, row_x, y, label, 0xFF9CA3AF);
+                draw_text(buffer, width, value_x, y, &ellipsize(value, (row_w - 140) / 8), 0xFFD1D5DB);
+                y += 22;
+            }
+        }
+
+        let mut action = UiAction::None;
+        let btn_w = 120;
+        let btn_h = 36;
+        let btn_x = (width - btn_w) / 2;
+        let btn_y = y + 20;
+        let hover = self.is_mouse_in_rect(btn_x, btn_y, btn_w, btn_h);
+        let color = 0xFF6B7280;
+        fill_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, if hover { lighten_color(color) } else { color });
+        draw_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, lighten_color(color));
+        draw_text(buffer, width, btn_x + (btn_w - 4 * 8) / 2, btn_y + (btn_h - 8) / 2, "Back", 0xFFFFFFFF);
+        if hover && self.mouse_clicked {
+            action = UiAction::CloseRomInfo;
+        }
+
+        let hint = "Esc = Back";
+        let hx = (width.saturating_sub(hint.len() * 6)) / 2;
+        draw_text_small(buffer, width, hx, height - 40, hint, 0xFF4B5563);
+
+        action
+    }
+
+    /// Render the save state browser: a grid of slots with thumbnails
+    pub fn render_state_browser(
+        &mut self,
+        buffer: &mut [u32],
+        width: usize,
+        height: usize,
+        palette: &[u32; 4],
+    ) -> UiAction {
+        buffer.fill(0xFF111827);
+
+        let title = "SAVE STATES";
+        let tx = (width - title.len() * 24) / 2;
+        draw_text_large(buffer, width, tx, 30, title, 0xFFFFFFFF);
+
+        // 5x2 grid of half-size thumbnails
+        let thumb_w = SCREEN_WIDTH / 2;
+        let thumb_h = SCREEN_HEIGHT / 2;
+        let cell_w = thumb_w + 36;
+        let cell_h = thumb_h + 50;
+        let columns = 5;
+        let grid_x = (width - cell_w * columns) / 2;
+        let grid_y = 90;
+
+        let mut action = UiAction::None;
+        for slot in 0..SLOT_COUNT {
+            let col = slot as usize % columns;
+            let row = slot as usize / columns;
+            let x = grid_x + col * cell_w + 8;
+            let y = grid_y + row * cell_h;
+            let w = cell_w - 16;
+            let h = cell_h - 10;
+
+            let selected = slot == self.state_slot;
+            let hover = self.is_mouse_in_rect(x, y, w, h);
+            let bg = if hover { 0xFF374151 } else { 0xFF1F2937 };
+            fill_rect(buffer, width, x, y, w, h, bg);
+            let border = if selected { 0xFFF59E0B } else { 0xFF4B5563 };
+            draw_rect(buffer, width, x, y, w, h, border);
+
+            let tx = x + (w - thumb_w) / 2;
+            let ty = y + 16;
+            draw_text_small(buffer, width, x + 4, y + 4, &format!("Slot {}", slot), 0xFFD1D5DB);
+            match self.slots.get(slot as usize).and_then(|s| s.as_ref()) {
+                Some(info) => {
+                    draw_thumbnail(buffer, width, tx, ty, &info.thumbnail, palette);
+                    let date = info.saved_at.get(5..).unwrap_or(&info.saved_at);
+                    draw_text_small(buffer, width, x + 4, ty + thumb_h + 6, date, 0xFF9CA3AF);
+                }
+                None => {
+                    fill_rect(buffer, width, tx, ty, thumb_w, thumb_h, 0xFF111827);
+                    draw_text_small(buffer, width, tx + (thumb_w - 30) / 2, ty + thumb_h / 2 - 3, "Empty", 0xFF6B7280);
+                }
+            }
+
+            if hover && self.mouse_clicked {
+                action = UiAction::SelectSlot(slot);
+            }
+        }
+
+        // Actions for the selected slot
+        let filled = matches!(self.slots.get(self.state_slot as usize), Some(Some(_)));
+        let buttons = [
+            ("Save", UiAction::SaveState, 0xFF22C55E, true),
+            ("Load", UiAction::LoadState, 0xFF3B82F6, filled),
+            ("Back", UiAction::CloseStates, 0xFF6B7280, true),
+        ];
+        let btn_w = 120;
+        let btn_h = 36;
+        let gap = 20;
+        let total_w = buttons.len() * btn_w + (buttons.len() - 1) * gap;
+        let btn_y = grid_y + 2 * cell_h + 20;
+        for (i, (text, button_action, color, enabled)) in buttons.iter().enumerate() {
+            let btn_x = (width - total_w) / 2 + i * (btn_w + gap);
+            let color = if *enabled { *color } else { 0xFF374151 };
+            let hover = *enabled && self.is_mouse_in_rect(btn_x, btn_y, btn_w, btn_h);
+            let bg = if hover { lighten_color(color) } else { color };
+            fill_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, bg);
+            draw_rect(buffer, width, btn_x, btn_y, btn_w, btn_h, lighten_color(color));
+            let text_x = btn_x + (btn_w - text.len() * 8) / 2;
+            draw_text(buffer, width, text_x, btn_y + (btn_h - 8) / 2, text, 0xFFFFFFFF);
+            if hover && self.mouse_clicked {
+                action = button_action.clone();
+            }
+        }
+
+        let hint = "F5 = Quick Save | F8 = Quick Load | 0-9 = Select Slot | Esc = Back";
+        let hx = (width.saturating_sub(hint.len() * 6)) / 2;
+        draw_text_small(buffer, width, hx, height - 40, hint, 0xFF4B5563);
+
+        action
+    }
+
+    /// Render the settings screen
+    pub fn render_settings(&mut self, buffer: &mut [u32], width: usize, height: usize) -> UiAction {
+        buffer.fill(0xFF111827);
+
+        let title = "SETTINGS";
+        let tx = (width - title.len() * 24) / 2;
+        draw_text_large(buffer, width, tx, 30, title, 0xFFFFFFFF);
+
+        let row_w = 400;
+        let row_x = (width - row_w) / 2;
+        let value_w = 200;
+        let value_x = row_x + row_w - value_w;
+        let mut action = UiAction::None;
+
+        // One row per setting: the label, and its value button 10 pixels up
+        let row_y = |row: usize| 86 + row * 28;
+
+        // Palette, with a swatch of its four shades
+        draw_text(buffer, width, row_x, row_y(0), "Palette", 0xFFD1D5DB);
+        let (palette_name, palette) = PALETTES[self.palette_index()];
+        if self.value_button(buffer, width, value_x, row_y(0) - 10, value_w, palette_name) {
+            action = UiAction::CyclePalette;
+        }
+        for (i, &color) in palette.iter().enumerate() {
+            fill_rect(buffer, width, value_x - 60 + i * 12, row_y(0) - 8, 10, 20, color);
+        }
+
+        // Volume
+        draw_text(buffer, width, row_x, row_y(1), "Volume", 0xFFD1D5DB);
+        let volume = if self.config.muted {
+            "Muted".to_string()
+        } else {
+            format!("{}%", (self.config.volume * 100.0).round())
+        };
+        let vx = value_x + (value_w - volume.len() * 8) / 2;
+        draw_text(buffer, width, vx, row_y(1), &volume, 0xFFFFFFFF);
+        if self.value_button(buffer, width, value_x, row_y(1) - 10, 40, "-") {
+            action = UiAction::ChangeVolume(-0.1);
+        }
+        if self.value_button(buffer, width, value_x + value_w - 40, row_y(1) - 10, 40, "+") {
+            action = UiAction::ChangeVolume(0.1);
+        }
+
+        // Audio output device and buffer size
+        draw_text(buffer, width, row_x, row_y(2), "Audio Device", 0xFFD1D5DB);
+        let device = ellipsize(self.config.audio_device.as_deref().unwrap_or("Default"), value_w / 8 - 1);
+        if self.value_button(buffer, width, value_x, row_y(2) - 10, value_w, &device) {
+            action = UiAction::CycleAudioDevice;
+        }
+        draw_text(buffer, width, row_x, row_y(3), "Audio Buffer", 0xFFD1D5DB);
+        let audio_buffer = match self.config.audio_buffer {
+            0 => "Auto".to_string(),
+            frames => format!("{} frames", frames),
+        };
+        if self.value_button(buffer, width, value_x, row_y(3) - 10, value_w, &audio_buffer) {
+            action = UiAction::CycleAudioBuffer;
+        }
+
+        // Stereo width and the output filter
+        draw_text(buffer, width, row_x, row_y(4), "Stereo Width", 0xFFD1D5DB);
+        let stereo = match self.config.stereo_separation {
+            0 => "Mono".to_string(),
+            percent => format!("{}%", percent),
+        };
+        if self.value_button(buffer, width, value_x, row_y(4) - 10, value_w, &stereo) {
+            action = UiAction::CycleStereoWidth;
+        }
+        draw_text(buffer, width, row_x, row_y(5), "High-Pass Filter", 0xFFD1D5DB);
+        let high_pass = if self.config.high_pass { "On" } else { "Off" };
+        if self.value_button(buffer, width, value_x, row_y(5) - 10, value_w, high_pass) {
+            action = UiAction::ToggleHighPass;
+        }
+
+        // Window scale (the window is created at startup)
+        draw_text(buffer, width, row_x, row_y(6), "Window Scale", 0xFFD1D5DB);
+        let scale = format!("{}x (on restart)", self.config.window_scale);
+        if self.value_button(buffer, width, value_x, row_y(6) - 10, value_w, &scale) {
+            action = UiAction::CycleWindowScale;
+        }
+
+        // Hardware model override for the loaded game
+        if let Some(hash) = self.current_game {
+            draw_text(buffer, width, row_x, row_y(7), "Model (this game)", 0xFFD1D5DB);
+            let model = match self.config.games.get(&hash).and_then(|g| g.model) {
+                Some(model) => format!("{} (on reset)", config::model_name(model)),
+                None => "Auto".to_string(),
+            };
+            if self.value_button(buffer, width, value_x, row_y(7) - 10, value_w, &model) {
+                action = UiAction::CycleModel;
+            }
+        }
+
+        // Turbo rate
+        draw_text(buffer, width, row_x, row_y(8), "Turbo Rate", 0xFFD1D5DB);
+        let rate = format!("{} Hz", self.config.turbo_rate);
+        if self.value_button(buffer, width, value_x, row_y(8) - 10, value_w, &rate) {
+            action = UiAction::CycleTurboRate;
+        }
+
+        // Soft reset on A+B+Start+Select
+        draw_text(buffer, width, row_x, row_y(9), "Reset Combo", 0xFFD1D5DB);
+        let reset_combo = if self.config.reset_combo { "A+B+Start+Select" } else { "Off" };
+        if self.value_button(buffer, width, value_x, row_y(9) - 10, value_w, reset_combo) {
+            action = UiAction::ToggleResetCombo;
+        }
+
+        // Key bindings, in two columns
+        draw_text(buffer, width, row_x, row_y(10), "Controls", 0xFF6B7280);
+        let labels = BUTTONS
+            .iter()
+            .map(|(button, _)| format!("{:?}", button))
+            .chain(TURBO_BUTTONS.iter().map(|(button, _)| format!("Turbo {:?}", button)));
+        let rows = self.config.keys.0.len().div_ceil(2);
+        let (column_w, key_w) = (row_w / 2 + 4, 120);
+        let keys_y = row_y(10) + 16;
+        for (i, label) in labels.enumerate() {
+            let x = row_x + i / rows * column_w;
+            let y = keys_y + i % rows * 26;
+            draw_text(buffer, width, x, y + 8, &label, 0xFFD1D5DB);
+            let key_x = x + column_w - 12 - key_w;
+            let key = if self.rebinding == Some(i) {
+                "Press a key...".to_string()
+            } else {
+                config::key_name(self.config.keys.0[i])
+            };
+            if self.value_button(buffer, width, key_x, y, key_w, &key) {
+                action = UiAction::RebindButton(i);
+            }
+            if self.rebinding == Some(i) {
+                draw_rect(buffer, width, key_x, y, key_w, 24, 0xFFF59E0B);
+            }
+        }
+
+        let back_w = 120;
+        let back_x = (width - back_w) / 2;
+        let back_y = keys_y + rows * 26 + 8;
+        let hover = self.is_mouse_in_rect(back_x, back_y, back_w, 36);
+        let color = 0xFF6B7280;
+        fill_rect(buffer, width, back_x, back_y, back_w, 36, if hover { lighten_color(color) } else { color });
+        draw_text(buffer, width, back_x + (back_w - 4 * 8) / 2, back_y + 14, "Back", 0xFFFFFFFF);
+        if hover && self.mouse_clicked {
+            action = UiAction::CloseSettings;
+        }
+
+        let hint = "Click a control, then press a key | Esc = Back";
+        let hx = (width.saturating_sub(hint.len() * 6)) / 2;
+        draw_text_small(buffer, width, hx, height - 20, hint, 0xFF4B5563);
+
+        action
+    }
+
+    /// Draw a clickable settings value, returning true when clicked
+    fn value_button(&self, buffer: &mut [u32], width: usize, x: usize, y: usize, w: usize, text: &str) -> bool {
+        let h = 24;
+        let hover = self.is_mouse_in_rect(x, y, w, h);
+        fill_rect(buffer, width, x, y, w, h, if hover { 0xFF374151 } else { 0xFF1F2937 });
+        draw_rect(buffer, width, x, y, w, h, 0xFF4B5563);
+        let text_x = x + w.saturating_sub(text.len() * 8) / 2;
+        draw_text(buffer, width, text_x, y + (h - 8) / 2, text, 0xFFFFFFFF);
+        hover && self.mouse_clicked
+    }
+
+    /// Show a short message at the top of the screen
+    pub fn show_message(&mut self, text: impl Into<String>) {
+        self.message = Some((text.into(), Instant::now()));
+    }
+
+    /// Render the current message, if it hasn't expired
+    pub fn render_message(&mut self, buffer: &mut [u32], width: usize) {
+        let Some((text, posted)) = &self.message else { return };
+        if posted.elapsed() > MESSAGE_DURATION {
+            self.message = None;
+            return;
+        }
+        let w = text.len() * 8 + 16;
+        let x = width.saturating_sub(w) / 2;
+        let y = 28;
+        fill_rect(buffer, width, x, y, w, 20, 0xC0000000);
+        draw_text(buffer, width, x + 8, y + 6, text, 0xFFFFFFFF);
+    }
+
+    /// Speed slider with a stop per entry in [`SPEEDS`]
+    ///
+    /// Returns the speed under the cursor when clicked.
+    fn render_speed_slider(&self, buffer: &mut [u32], width: usize, y: usize) -> Option<f64> {
+        let track_w = 300;
+        let track_x = (width - track_w) / 2;
+        let step = track_w / (SPEEDS.len() - 1);
+        let track_y = y + 14;
+
+        draw_text_small(buffer, width, track_x - 48, track_y - 3, "Speed", 0xFFD1D5DB);
+        fill_rect(buffer, width, track_x, track_y, track_w, 2, 0xFF6B7280);
+
+        let mut clicked = None;
+        for (i, &speed) in SPEEDS.iter().enumerate() {
+            let stop_x = track_x + i * step;
+            let selected = speed == self.speed;
+            let hover = self.is_mouse_in_rect(stop_x.saturating_sub(step / 2), y, step, 34);
+            let color = if selected {
+                0xFF0EA5E9
+            } else if hover {
+                0xFFD1D5DB
+            } else {
+                0xFF9CA3AF
+            };
+            fill_rect(buffer, width, stop_x - 4, track_y - 4, 9, 10, color);
+
+            let label = speed_label(speed);
+            let lx = stop_x.saturating_sub(label.len() * 3);
+            draw_text_small(buffer, width, lx, track_y + 10, &label, color);
+
+            if hover && self.mouse_clicked {
+                clicked = Some(speed);
+            }
+        }
+        clicked
+    }
+
+    /// Palette in use: the loaded game's choice, else the global one
+    pub fn palette_index(&self) -> usize {
+        self.current_game
+            .and_then(|hash| self.config.games.get(&hash))
+            .and_then(|game| game.palette)
+            .unwrap_or(self.config.palette)
+            % PALETTES.len()
+    }
+
+    /// Speed emulation should run at this frame
+    pub fn effective_speed(&self) -> f64 {
+        if self.fast_forward {
+            self.speed.max(FAST_FORWARD_SPEED)
+        } else {
+            self.speed
+        }
+    }
+
     /// Render FPS overlay
     pub fn render_fps(&self, buffer: &mut [u32], width: usize) {
         if !self.show_fps {
             return;
         }
-        let fps_text = format!("FPS: {:.0}", self.fps);
+        let speed = self.effective_speed();
+        let fps_text = if self.rewinding {
+            format!("FPS: {:.0} | Rewind", self.fps)
+        } else if speed != 1.0 {
+            format!("FPS: {:.0} | {}", self.fps, speed_label(speed))
+        } else {
+            format!("FPS: {:.0}", self.fps)
+        };
         // Background
         fill_rect(buffer, width, 5, 5, fps_text.len() * 6 + 8, 14, 0x80000000);
-        draw_text_small(buffer, width, 9, 8, &fps_text, 0xFF4ade80);
+        draw_text_small(buffer, width, 9, 8, &fps_text, 0xFF4ADE80);
+    }
+
+    /// Render the recording indicator in the top-right corner
+    pub fn render_recording(&self, buffer: &mut [u32], width: usize) {
+        let x = width - 40;
+        fill_rect(buffer, width, x - 4, 5, 38, 14, 0x80000000);
+        fill_rect(buffer, width, x, 9, 6, 6, 0xFFEF4444);
+        draw_text_small(buffer, width, x + 9, 8, "REC", 0xFFEF4444);
     }
 
     fn is_mouse_in_rect(&self, x: usize, y: usize, w: usize, h: usize) -> bool {
@@ -254,7 +765,7 @@ impl Ui {
 
 impl Default for Ui {
     fn default() -> Self {
-        Self::new()
+        Self::new(Config::default())
     }
 }
 
@@ -262,7 +773,7 @@ impl Default for Ui {
 // Drawing primitives
 // ============================================================================
 
-fn fill_rect(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, w: usize, h: usize, color: u32) {
+pub fn fill_rect(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, w: usize, h: usize, color: u32) {
     for dy in 0..h {
         for dx in 0..w {
             let px = x + dx;
@@ -277,7 +788,7 @@ fn fill_rect(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, w: usize,
     }
 }
 
-fn draw_rect(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, w: usize, h: usize, color: u32) {
+pub fn draw_rect(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, w: usize, h: usize, color: u32) {
     // Top and bottom
     for dx in 0..w {
         set_pixel(buffer, buf_width, x + dx, y, color);
@@ -323,6 +834,102 @@ fn blend(dst: u32, src: u32) -> u32 {
     0xFF000000 | (r << 16) | (g << 8) | b
 }
 
+/// Draw a half-size screen thumbnail from 2-bit color indices
+fn draw_thumbnail(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, pixels: &[u8], palette: &[u32; 4]) {
+    for ty in 0..SCREEN_HEIGHT / 2 {
+        for tx in 0..SCREEN_WIDTH / 2 {
+            let idx = pixels[(ty * 2) * SCREEN_WIDTH + tx * 2] & 0x03;
+            set_pixel(buffer, buf_width, x + tx, y + ty, palette[idx as usize]);
+        }
+    }
+}
+
+/// Draw what a Lua script drew over a screen whose top left corner is
+/// at `origin`, each Game Boy pixel `scale` pixels wide
+///
+/// Shapes are clipped to the screen and blended by their alpha.
+#[cfg(feature = "lua")]
+pub fn draw_overlay(
+    buffer: &mut [u32],
+    buf_width: usize,
+    commands: &[gb3000::lua::DrawCommand],
+    origin: (usize, usize),
+    scale: usize,
+) {
+    use gb3000::lua::DrawCommand;
+    let mut plot = |x: i32, y: i32, color: u32| {
+        if (0..SCREEN_WIDTH as i32).contains(&x) && (0..SCREEN_HEIGHT as i32).contains(&y) {
+            let (px, py) = (origin.0 + x as usize * scale, origin.1 + y as usize * scale);
+            fill_rect(buffer, buf_width, px, py, scale, scale, color);
+        }
+    };
+    for command in commands {
+        match *command {
+            DrawCommand::Pixel { x, y, color } => plot(x, y, color),
+            DrawCommand::Line { x1, y1, x2, y2, color } => {
+                // Bresenham, with every point on the line plotted once
+                let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+                let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+                let (mut x, mut y, mut err) = (x1, y1, dx + dy);
+                loop {
+                    plot(x, y, color);
+                    if x == x2 && y == y2 {
+                        break;
+                    }
+                    if 2 * err >= dy {
+                        err += dy;
+                        x += sx;
+                    }
+                    if 2 * err <= dx {
+                        err += dx;
+                        y += sy;
+                    }
+                }
+            }
+            DrawCommand::Rect { x, y, width, height, color, fill } => {
+                // Only the part on screen, so huge rectangles stay cheap
+                let (left, right) = (x.max(0), (x + width).min(SCREEN_WIDTH as i32));
+                let (top, bottom) = (y.max(0), (y + height).min(SCREEN_HEIGHT as i32));
+                for py in top..bottom {
+                    for px in left..right {
+                        let edge = px == x || py == y || px == x + width - 1 || py == y + height - 1;
+                        if fill || edge {
+                            plot(px, py, color);
+                        }
+                    }
+                }
+            }
+            DrawCommand::Text { x, y, ref text, color } => {
+                for (i, ch) in text.chars().enumerate() {
+                    let left = x.saturating_add(i as i32 * 6);
+                    for (row, bits) in get_char_bitmap(ch).into_iter().enumerate() {
+                        for col in 0..5 {
+                            if (bits >> (4 - col)) & 1 == 1 {
+                                plot(left.saturating_add(col), y + row as i32, color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shorten text to `max` characters, marking the cut with ".."
+fn ellipsize(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(max.saturating_sub(2)).collect();
+    short.push_str("..");
+    short
+}
+
+/// Format a speed multiplier, e.g. "0.25x" or "4x"
+fn speed_label(speed: f64) -> String {
+    format!("{}x", speed)
+}
+
 fn lighten_color(color: u32) -> u32 {
     let r = ((color >> 16) & 0xFF).min(200) + 40;
     let g = ((color >> 8) & 0xFF).min(200) + 40;
@@ -335,7 +942,7 @@ fn lighten_color(color: u32) -> u32 {
 // ============================================================================
 
 /// Draw text with 8x8 character size
-fn draw_text(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, text: &str, color: u32) {
+pub fn draw_text(buffer: &mut [u32], buf_width: usize, x: usize, y: usize, text: &str, color: u32) {
     for (i, ch) in text.chars().enumerate() {
         draw_char(buffer, buf_width, x + i * 8, y, ch, color, 1);
     }
@@ -466,6 +1073,9 @@ fn get_char_bitmap(ch: char) -> [u8; 7] {
         ']' => [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110],
         '<' => [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010],
         '>' => [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000],
+        '#' => [0b01010, 0b11111, 0b01010, 0b01010, 0b11111, 0b01010, 0b00000],
+        '$' => [0b00100, 0b01111, 0b10100, 0b01110, 0b00101, 0b11110, 0b00100],
+        '%' => [0b11001, 0b11010, 0b00010, 0b00100, 0b01000, 0b01011, 0b10011],
         '|' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
         '_' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111],
         '\'' => [0b00100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000],
@@ -0,0 +1,200 @@
+//! VGM music logging
+//!
+//! A [`VgmLog`] collects writes to the sound registers and wave RAM with
+//! their timing and turns them into a VGM file, the format chiptune
+//! players and trackers use for register-level captures. Start one with
+//! [`Emulator::start_vgm_log`](crate::Emulator::start_vgm_log); it opens
+//! with the sound registers as they were at that point, so logging can
+//! start mid-song.
+
+use crate::memory::{io, Memory};
+
+/// CPU clock, which is also the DMG sound chip clock VGM players expect
+const GB_CLOCK: u64 = 4_194_304;
+
+/// VGM's fixed sample rate, in which waits are counted
+const VGM_RATE: u64 = 44_100;
+
+/// Size of the header; commands start right after it
+const HEADER_SIZE: usize = 0x100;
+
+/// A sound register or wave RAM write, as seen by the APU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApuWrite {
+    /// [`total_cycles`](crate::Emulator::total_cycles) when the
+    /// instruction doing the write finished
+    pub cycle: u64,
+    /// 0xFF10-0xFF26 or 0xFF30-0xFF3F
+    pub address: u16,
+    pub value: u8,
+}
+
+/// Sound register writes on their way to a VGM file
+#[derive(Debug, Clone)]
+pub struct VgmLog {
+    /// Emulator cycle the current stretch of the log started on
+    start_cycle: u64,
+    /// Cycles logged before that, across resets and state loads
+    earlier_cycles: u64,
+    /// Commands after the header, without the end marker
+    commands: Vec<u8>,
+    /// Samples waited for so far
+    samples: u64,
+}
+
+impl VgmLog {
+    /// Start logging at `cycle`, recreating the sound state in `memory`
+    pub(crate) fn new(cycle: u64, memory: &Memory) -> Self {
+        let mut log = Self { start_cycle: cycle, earlier_cycles: 0, commands: Vec::new(), samples: 0 };
+        log.snapshot(memory);
+        log
+    }
+
+    /// Write the sound registers and wave RAM in `memory` as they are
+    pub(crate) fn snapshot(&mut self, memory: &Memory) {
+        let reg = |addr: u16| memory.io[addr as usize];
+        let nr52 = reg(io::NR52);
+        self.command(io::NR52, nr52 & 0x80);
+        if nr52 & 0x80 == 0 {
+            return;
+        }
+        self.command(io::NR50, reg(io::NR50));
+        self.command(io::NR51, reg(io::NR51));
+
+        // Wave RAM is only reachable with channel 3's DAC off
+        self.command(io::NR30, 0x00);
+        for addr in 0xFF30..=0xFF3F {
+            self.command(addr, reg(addr));
+        }
+
+        // Each channel's registers, retriggering those playing now
+        for (channel, first) in [io::NR10, io::NR21 - 1, io::NR30, io::NR41 - 1].into_iter().enumerate() {
+            for addr in first..first + 4 {
+                if addr != io::NR21 - 1 && addr != io::NR41 - 1 {
+                    self.command(addr, reg(addr));
+                }
+            }
+            let playing = nr52 & (1 << channel) != 0;
+            self.command(first + 4, reg(first + 4) & 0x7F | if playing { 0x80 } else { 0x00 });
+        }
+    }
+
+    /// Add a write, waiting from the previous one as needed
+    pub fn record(&mut self, write: ApuWrite) {
+        self.wait_until(write.cycle);
+        self.command(write.address, write.value);
+    }
+
+    /// Keep time running on when the emulator's cycle count jumps from
+    /// `from` to `to`, as it does on reset and state loads
+    pub(crate) fn clock_jumped(&mut self, from: u64, to: u64) {
+        self.earlier_cycles += from.saturating_sub(self.start_cycle);
+        self.start_cycle = to;
+    }
+
+    /// Length of the log so far, in 44100 Hz samples
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// The finished VGM file, ending at `cycle`
+    pub fn to_vgm(&self, cycle: u64) -> Vec<u8> {
+        let mut log = self.clone();
+        log.wait_until(cycle);
+        log.commands.push(0x66);
+
+        let mut file = vec![0u8; HEADER_SIZE];
+        let mut put = |offset: usize, value: u32| file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        put(0x00, u32::from_le_bytes(*b"Vgm "));
+        put(0x04, (HEADER_SIZE + log.commands.len() - 4) as u32);
+        put(0x08, 0x0000_0171);
+        put(0x18, log.samples.min(u32::MAX as u64) as u32);
+        put(0x34, (HEADER_SIZE - 0x34) as u32);
+        put(0x80, GB_CLOCK as u32);
+        file.extend_from_slice(&log.commands);
+        file
+    }
+
+    /// Write command 0xB3: a DMG register, as an offset from 0xFF10
+    fn command(&mut self, address: u16, value: u8) {
+        self.commands.extend_from_slice(&[0xB3, (address - io::NR10) as u8, value]);
+    }
+
+    /// Wait up to the sample `cycle` falls in
+    fn wait_until(&mut self, cycle: u64) {
+        let elapsed = self.earlier_cycles + cycle.saturating_sub(self.start_cycle);
+        self.wait((elapsed * VGM_RATE / GB_CLOCK).saturating_sub(self.samples));
+    }
+
+    /// Wait commands covering `samples`
+    fn wait(&mut self, mut samples: u64) {
+        self.samples += samples;
+        while samples > 0 {
+            match samples {
+                735 => self.commands.push(0x62),
+                882 => self.commands.push(0x63),
+                1..=16 => self.commands.push(0x70 + (samples - 1) as u8),
+                _ => {
+                    let n = samples.min(0xFFFF);
+                    self.commands.push(0x61);
+                    self.commands.extend_from_slice(&(n as u16).to_le_bytes());
+                    samples -= n;
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_waits_and_commands() {
+        let mut memory = Memory::new();
+        memory.io[io::NR52 as usize] = 0x00;
+        let mut log = VgmLog::new(1000, &memory);
+        // One frame later (70224 cycles = 738.4 samples), then 5 samples more
+        log.record(ApuWrite { cycle: 1000 + 70224, address: io::NR52, value: 0x80 });
+        log.record(ApuWrite { cycle: 1000 + 70224 + 480, address: io::NR50, value: 0x77 });
+        let vgm = log.to_vgm(1000 + 4_194_304);
+
+        let u32_at = |offset: usize| u32::from_le_bytes(vgm[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&vgm[..4], b"Vgm ");
+        assert_eq!(u32_at(0x04) as usize, vgm.len() - 4);
+        assert_eq!(u32_at(0x18), 44_100);
+        assert_eq!(u32_at(0x34) as usize + 0x34, HEADER_SIZE);
+        assert_eq!(u32_at(0x80), 4_194_304);
+        assert_eq!(
+            vgm[HEADER_SIZE..],
+            [
+                0xB3, 0x16, 0x00, // NR52 off, from the snapshot
+                0x61, 0xE2, 0x02, // wait 738
+                0xB3, 0x16, 0x80,
+                0x74, // wait 5
+                0xB3, 0x14, 0x77,
+                0x61, 0x5D, 0xA9, // wait the rest of the second, 43357
+                0x66,
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_retriggers_playing_channels() {
+        let mut memory = Memory::new();
+        memory.io[io::NR52 as usize] = 0x82;
+        memory.io[io::NR24 as usize] = 0x47;
+        let vgm = VgmLog::new(0, &memory).to_vgm(0);
+        let writes: Vec<_> = vgm[HEADER_SIZE..vgm.len() - 1].chunks(3).map(|c| (c[1], c[2])).collect();
+        assert_eq!(writes[0], (0x16, 0x80));
+        // NR14 without its trigger bit, NR24 with it
+        assert!(writes.contains(&(0x04, memory.io[io::NR14 as usize] & 0x7F)));
+        assert!(writes.contains(&(0x09, 0xC7)));
+        // Wave RAM is written with channel 3's DAC off
+        let wave = writes.iter().position(|&(reg, _)| reg == 0x20).unwrap();
+        assert!(writes[..wave].contains(&(0x0A, 0x00)));
+        assert_eq!(writes.len(), 3 + 16 + 19);
+    }
+}
@@ -0,0 +1,328 @@
+//! Video memory viewer window for the desktop UI
+//!
+//! F7 opens a window with four tabs, all redrawn every frame from VRAM
+//! and OAM: the tile sheet, each of the two tile maps (with the
+//! background viewport, and the window's visible part, outlined) and the
+//! sprite list with a preview of each sprite. Hovering a tile shows where
+//! it lives.
+
+use crate::ui::{draw_rect, draw_text, fill_rect};
+use gb3000::{Emulator, OamEntry, TileMap, TILE_MAP_SIZE, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, Window, WindowOptions};
+
+/// Window buffer size; the window shows it at 2x
+const WIDTH: usize = 400;
+const HEIGHT: usize = 416;
+
+/// Top-left corner of the tab contents
+const VIEW_X: usize = 8;
+const VIEW_Y: usize = 28;
+/// Left edge of the info panel next to the tile sheet and maps
+const INFO_X: usize = 276;
+
+/// The tile sheet is drawn at 2x
+const SHEET_SCALE: usize = 2;
+
+/// Sprites per column of the OAM list, and the height of each row
+const OAM_ROWS: usize = 20;
+const OAM_ROW_HEIGHT: usize = 18;
+
+const BACKGROUND: u32 = 0xFF1A1A2E;
+const TEXT: u32 = 0xFFE5E7EB;
+const DIM: u32 = 0xFF6B7280;
+const ACCENT: u32 = 0xFF0EA5E9;
+/// Behind transparent sprite pixels
+const TRANSPARENT: u32 = 0xFF2A2A3E;
+/// Outline of the background viewport and of the window
+const VIEWPORT: u32 = 0xFFEF4444;
+const WINDOW_AREA: u32 = 0xFF4ADE80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Tiles,
+    Map(TileMap),
+    Oam,
+}
+
+const TABS: [(Tab, &str); 4] = [
+    (Tab::Tiles, "Tiles"),
+    (Tab::Map(TileMap::Low), "9800"),
+    (Tab::Map(TileMap::High), "9C00"),
+    (Tab::Oam, "OAM"),
+];
+
+pub struct VramViewer {
+    window: Window,
+    buffer: Vec<u32>,
+    tab: Tab,
+    /// Scratch space for the core's tile sheet and map views
+    sheet: Vec<u8>,
+    map: Vec<u8>,
+    /// Mouse button state last frame, to detect clicks
+    mouse_was_down: bool,
+}
+
+impl VramViewer {
+    pub fn open() -> Self {
+        let mut window = Window::new(
+            "GB3000 - Video",
+            WIDTH,
+            HEIGHT,
+            WindowOptions { scale: Scale::X2, ..WindowOptions::default() },
+        )
+        .expect("Failed to create video viewer window");
+        window.set_target_fps(0);
+        Self {
+            window,
+            buffer: vec![0; WIDTH * HEIGHT],
+            tab: Tab::Tiles,
+            sheet: vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT],
+            map: vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE],
+            mouse_was_down: false,
+        }
+    }
+
+    /// Redraw from the emulator's video memory, with shades drawn in
+    /// `palette`
+    ///
+    /// Returns false once the window was closed, with its close button or
+    /// F7.
+    pub fn update(&mut self, emulator: &Emulator, palette: &[u32; 4]) -> bool {
+        if !self.window.is_open() || self.window.is_key_pressed(Key::F7, KeyRepeat::No) {
+            return false;
+        }
+        let mouse = self.window.get_mouse_pos(MouseMode::Discard).map(|(x, y)| (x as usize, y as usize));
+        let down = self.window.get_mouse_down(MouseButton::Left);
+        let click = mouse.filter(|_| down && !self.mouse_was_down);
+        self.mouse_was_down = down;
+
+        fill_rect(&mut self.buffer, WIDTH, 0, 0, WIDTH, HEIGHT, BACKGROUND);
+        self.draw_tabs(mouse, click);
+        emulator.tile_sheet(&mut self.sheet);
+        match self.tab {
+            Tab::Tiles => self.draw_tiles(palette, mouse),
+            Tab::Map(map) => self.draw_map(emulator, map, palette, mouse),
+            Tab::Oam => self.draw_oam(emulator, palette),
+        }
+
+        self.window
+            .update_with_buffer(&self.buffer, WIDTH, HEIGHT)
+            .expect("Failed to update video viewer window");
+        true
+    }
+
+    fn draw_tabs(&mut self, mouse: Option<(usize, usize)>, click: Option<(usize, usize)>) {
+        for (i, &(tab, label)) in TABS.iter().enumerate() {
+            let (x, w, h) = (VIEW_X + i * 70, 64, 16);
+            let inside = |(mx, my): (usize, usize)| (x..x + w).contains(&mx) && (4..4 + h).contains(&my);
+            let color = if self.tab == tab {
+                ACCENT
+            } else if mouse.is_some_and(inside) {
+                0xFF374151
+            } else {
+                0xFF1F2937
+            };
+            fill_rect(&mut self.buffer, WIDTH, x, 4, w, h, color);
+            draw_rect(&mut self.buffer, WIDTH, x, 4, w, h, 0xFF4B5563);
+            draw_text(&mut self.buffer, WIDTH, x + (w - label.len() * 8) / 2, 8, label, TEXT);
+            if click.is_some_and(inside) {
+                self.tab = tab;
+            }
+        }
+    }
+
+    fn draw_tiles(&mut self, palette: &[u32; 4], mouse: Option<(usize, usize)>) {
+        let buffer = &mut self.buffer;
+        for (i, &color) in self.sheet.iter().enumerate() {
+            let (x, y) = (i % TILE_SHEET_WIDTH, i / TILE_SHEET_WIDTH);
+            let (px, py) = (VIEW_X + x * SHEET_SCALE, VIEW_Y + y * SHEET_SCALE);
+            fill_rect(buffer, WIDTH, px, py, SHEET_SCALE, SHEET_SCALE, palette[color as usize]);
+        }
+
+        let Some(tile) = mouse.and_then(|(mx, my)| {
+            let (x, y) = (mx.checked_sub(VIEW_X)? / SHEET_SCALE, my.checked_sub(VIEW_Y)? / SHEET_SCALE);
+            (x < TILE_SHEET_WIDTH && y < TILE_SHEET_HEIGHT).then_some(y / 8 * 16 + x / 8)
+        }) else {
+            draw_text(buffer, WIDTH, INFO_X, VIEW_Y, "Hover a tile", DIM);
+            return;
+        };
+        let (tx, ty) = (VIEW_X + tile % 16 * 8 * SHEET_SCALE, VIEW_Y + tile / 16 * 8 * SHEET_SCALE);
+        draw_rect(buffer, WIDTH, tx, ty, 8 * SHEET_SCALE, 8 * SHEET_SCALE, ACCENT);
+
+        draw_text(buffer, WIDTH, INFO_X, VIEW_Y, &format!("Tile {:03X}", tile), TEXT);
+        draw_text(buffer, WIDTH, INFO_X, VIEW_Y + 12, &format!("Addr {:04X}", 0x8000 + tile * 16), TEXT);
+        // The number a map or sprite uses for it, in each addressing mode
+        let unsigned = if tile < 256 { format!("{:02X}", tile) } else { "--".to_string() };
+        let signed = if tile >= 128 { format!("{:02X}", (tile - 128) as u8 ^ 0x80) } else { "--".to_string() };
+        draw_text(buffer, WIDTH, INFO_X, VIEW_Y + 28, &format!("8000 #{}", unsigned), DIM);
+        draw_text(buffer, WIDTH, INFO_X, VIEW_Y + 40, &format!("8800 #{}", signed), DIM);
+
+        // Zoomed preview
+        let zoom = 12;
+        for y in 0..8 {
+            for x in 0..8 {
+                let color = tile_color(&self.sheet, tile, x, y);
+                let (px, py) = (INFO_X + x * zoom, VIEW_Y + 60 + y * zoom);
+                fill_rect(buffer, WIDTH, px, py, zoom, zoom, palette[color as usize]);
+            }
+        }
+    }
+
+    fn draw_map(&mut self, emulator: &Emulator, map: TileMap, palette: &[u32; 4], mouse: Option<(usize, usize)>) {
+        emulator.tile_map(map, &mut self.map);
+        let buffer = &mut self.buffer;
+        for (i, &shade) in self.map.iter().enumerate() {
+            let (x, y) = (VIEW_X + i % TILE_MAP_SIZE, VIEW_Y + i / TILE_MAP_SIZE);
+            buffer[y * WIDTH + x] = palette[shade as usize];
+        }
+
+        let io = |reg: u16| emulator.peek(0xFF00 | reg);
+        let (lcdc, scy, scx, wy, wx) = (io(0x40), io(0x42), io(0x43), io(0x4A), io(0x4B));
+        let mut uses = Vec::new();
+        if TileMap::background(lcdc) == map {
+            uses.push("Background");
+            for (x, y) in viewport_outline(scx, scy) {
+                buffer[(VIEW_Y + y) * WIDTH + VIEW_X + x] = VIEWPORT;
+            }
+        }
+        let window_on = lcdc & 0x21 == 0x21 && wy < 144 && wx < 167;
+        if TileMap::window(lcdc) == map && window_on {
+            uses.push("Window");
+            // The window shows the map's top-left corner from (WX-7, WY) on
+            let w = 160 - wx.saturating_sub(7) as usize;
+            let h = 144 - wy as usize;
+            draw_rect(buffer, WIDTH, VIEW_X, VIEW_Y, w, h, WINDOW_AREA);
+        }
+
+        let mut y = VIEW_Y;
+        for (i, line) in uses.iter().enumerate() {
+            draw_text(buffer, WIDTH, INFO_X, y, line, [VIEWPORT, WINDOW_AREA][i.min(1)]);
+            y += 12;
+        }
+        if uses.is_empty() {
+            draw_text(buffer, WIDTH, INFO_X, y, "Not shown", DIM);
+            y += 12;
+        }
+        draw_text(buffer, WIDTH, INFO_X, y + 4, &format!("SCX {:02X} SCY {:02X}", scx, scy), DIM);
+        draw_text(buffer, WIDTH, INFO_X, y + 16, &format!("WX {:02X}  WY {:02X}", wx, wy), DIM);
+
+        if let Some((x, y)) = mouse
+            .and_then(|(mx, my)| Some((mx.checked_sub(VIEW_X)?, my.checked_sub(VIEW_Y)?)))
+            .filter(|&(x, y)| x < TILE_MAP_SIZE && y < TILE_MAP_SIZE)
+        {
+            let (col, row) = (x / 8, y / 8);
+            let addr = map.base() + (row * 32 + col) as u16;
+            let info_y = VIEW_Y + 72;
+            draw_text(buffer, WIDTH, INFO_X, info_y, &format!("Col {:02} Row {:02}", col, row), TEXT);
+            draw_text(buffer, WIDTH, INFO_X, info_y + 12, &format!("Addr {:04X}", addr), TEXT);
+            draw_text(buffer, WIDTH, INFO_X, info_y + 24, &format!("Tile {:02X}", emulator.peek(addr)), TEXT);
+        }
+    }
+
+    fn draw_oam(&mut self, emulator: &Emulator, palette: &[u32; 4]) {
+        let lcdc = emulator.peek(0xFF40);
+        let tall = lcdc & 0x04 != 0;
+        let palettes = [emulator.peek(0xFF48), emulator.peek(0xFF49)];
+        let buffer = &mut self.buffer;
+        for column in 0..2 {
+            let x = VIEW_X + column * 196;
+            draw_text(buffer, WIDTH, x + 36, VIEW_Y, "Y  X  T  F", DIM);
+        }
+        for (i, entry) in emulator.oam_entries().iter().enumerate() {
+            let x = VIEW_X + i / OAM_ROWS * 196;
+            let y = VIEW_Y + 14 + i % OAM_ROWS * OAM_ROW_HEIGHT;
+            let height = if tall { 16 } else { 8 };
+            fill_rect(buffer, WIDTH, x, y, 8, height, TRANSPARENT);
+            let obp = palettes[(entry.flags >> 4 & 1) as usize];
+            for (py, row) in sprite_pixels(&self.sheet, entry, tall).iter().enumerate() {
+                for (px, &color) in row.iter().enumerate() {
+                    if color != 0 {
+                        let shade = (obp >> (color * 2)) & 0x03;
+                        buffer[(y + py) * WIDTH + x + px] = palette[shade as usize];
+                    }
+                }
+            }
+            let color = if on_screen(entry, tall) { TEXT } else { DIM };
+            let text = format!("{:02} {:02X} {:02X} {:02X} {:02X}", i, entry.y, entry.x, entry.tile, entry.flags);
+            draw_text(buffer, WIDTH, x + 12, y + 4, &text, color);
+        }
+    }
+}
+
+/// Color index of pixel (`x`, `y`) of sheet tile `tile`
+fn tile_color(sheet: &[u8], tile: usize, x: usize, y: usize) -> u8 {
+    let (sx, sy) = (tile % 16 * 8 + x, tile / 16 * 8 + y);
+    sheet[sy * TILE_SHEET_WIDTH + sx]
+}
+
+/// A sprite's color indices as drawn, flips applied, 8 or 16 rows
+fn sprite_pixels(sheet: &[u8], entry: &OamEntry, tall: bool) -> Vec<[u8; 8]> {
+    let height = if tall { 16 } else { 8 };
+    let tile = if tall { entry.tile & 0xFE } else { entry.tile } as usize;
+    (0..height)
+        .map(|y| {
+            let sy = if entry.flags & 0x40 != 0 { height - 1 - y } else { y };
+            std::array::from_fn(|x| {
+                let sx = if entry.flags & 0x20 != 0 { 7 - x } else { x };
+                tile_color(sheet, tile + sy / 8, sx, sy % 8)
+            })
+        })
+        .collect()
+}
+
+/// Whether any of the sprite lands on the screen
+fn on_screen(entry: &OamEntry, tall: bool) -> bool {
+    let height = if tall { 16 } else { 8 };
+    entry.x > 0 && entry.x < 168 && entry.y as u16 + height > 16 && entry.y < 160
+}
+
+/// Map pixels on the edge of the 160x144 area the background scroll
+/// shows, wrapping around the map's edges
+fn viewport_outline(scx: u8, scy: u8) -> impl Iterator<Item = (usize, usize)> {
+    let at = move |dx: usize, dy: usize| ((scx as usize + dx) % TILE_MAP_SIZE, (scy as usize + dy) % TILE_MAP_SIZE);
+    let horizontal = (0..160).flat_map(move |dx| [at(dx, 0), at(dx, 143)]);
+    let vertical = (0..144).flat_map(move |dy| [at(0, dy), at(159, dy)]);
+    horizontal.chain(vertical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprites_flip_and_stack_tall_tiles() {
+        let mut sheet = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+        // Tile 2: a dot in the top-left corner; tile 3: one in the bottom-right
+        sheet[2 * 8] = 1;
+        sheet[7 * TILE_SHEET_WIDTH + 3 * 8 + 7] = 2;
+
+        let entry = OamEntry { y: 16, x: 8, tile: 2, flags: 0x00 };
+        let pixels = sprite_pixels(&sheet, &entry, false);
+        assert_eq!(pixels.len(), 8);
+        assert_eq!(pixels[0][0], 1);
+
+        let flipped = OamEntry { flags: 0x60, ..entry };
+        assert_eq!(sprite_pixels(&sheet, &flipped, false)[7][7], 1);
+
+        // 8x16 ignores the tile's low bit and stacks tiles 2 and 3
+        let tall = sprite_pixels(&sheet, &OamEntry { tile: 3, ..entry }, true);
+        assert_eq!((tall.len(), tall[0][0], tall[15][7]), (16, 1, 2));
+        let tall_flipped = sprite_pixels(&sheet, &OamEntry { tile: 3, flags: 0x40, ..entry }, true);
+        assert_eq!((tall_flipped[15][0], tall_flipped[0][7]), (1, 2));
+
+        assert!(on_screen(&entry, false));
+        assert!(!on_screen(&OamEntry { y: 8, ..entry }, false));
+        assert!(on_screen(&OamEntry { y: 8, ..entry }, true));
+        assert!(!on_screen(&OamEntry { x: 0, ..entry }, false));
+    }
+
+    #[test]
+    fn viewport_wraps_around_the_map() {
+        let outline: Vec<_> = viewport_outline(200, 250).collect();
+        assert!(outline.contains(&(200, 250)));
+        // The right edge wraps to x = (200 + 159) % 256, the bottom to y = (250 + 143) % 256
+        assert!(outline.contains(&(103, 250)));
+        assert!(outline.contains(&(200, 137)));
+        assert!(outline.iter().all(|&(x, y)| x < TILE_MAP_SIZE && y < TILE_MAP_SIZE));
+    }
+}